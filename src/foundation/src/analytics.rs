@@ -0,0 +1,312 @@
+/* Asynchronous tee of streamed inference output to an analytics sink.
+
+Streaming handlers hand each chunk they already sent to the client to an
+`AnalyticsTee`. The tee only performs a non-blocking channel send, so the client
+stream never waits on the sink: records are written by a dedicated background
+thread, and when the sink falls behind and the channel is full, records are
+dropped and counted instead of applying backpressure.
+
+Sinks are a JSON lines file, or a Kafka topic reached through a Kafka REST
+proxy (`kafka://host:port/topic`), keyed by request id so that the chunks of a
+stream stay in order.
+*/
+
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+use crate::kafka::{KafkaProducer, KeyedMessage};
+
+/// One chunk of streamed output as seen by the client.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalyticsRecord {
+    pub model_name: String,
+    pub model_version: Option<String>,
+    pub request_id: String,
+    /// Position of the chunk within its stream, starting at 0.
+    pub sequence: u64,
+    pub timestamp_ms: u128,
+    pub payload: serde_json::Value,
+}
+
+impl AnalyticsRecord {
+    pub fn new(
+        model_name: impl Into<String>,
+        model_version: Option<String>,
+        request_id: impl Into<String>,
+        sequence: u64,
+        payload: serde_json::Value,
+    ) -> Self {
+        Self {
+            model_name: model_name.into(),
+            model_version,
+            request_id: request_id.into(),
+            sequence,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
+            payload,
+        }
+    }
+}
+
+/// Destination for analytics records (file, message broker, ...).
+///
+/// Writes happen on the tee's background thread, so implementations may block.
+pub trait AnalyticsSink: Send + Sync {
+    fn write(&self, record: &AnalyticsRecord) -> Result<()>;
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Appends records as JSON lines to a local file.
+pub struct FileAnalyticsSink {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FileAnalyticsSink {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+impl AnalyticsSink for FileAnalyticsSink {
+    fn write(&self, record: &AnalyticsRecord) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.writer.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
+
+/// Produces records to a Kafka topic, one JSON record per message keyed by its request id,
+/// in one produce request per flush.
+pub struct KafkaAnalyticsSink {
+    producer: KafkaProducer,
+    pending: Mutex<Vec<KeyedMessage>>,
+    runtime: Handle,
+}
+
+impl KafkaAnalyticsSink {
+    /// Must be called from within a tokio runtime, which sends the records.
+    pub fn new(producer: KafkaProducer) -> Self {
+        Self {
+            producer,
+            pending: Mutex::new(Vec::new()),
+            runtime: Handle::current(),
+        }
+    }
+}
+
+impl AnalyticsSink for KafkaAnalyticsSink {
+    fn write(&self, record: &AnalyticsRecord) -> Result<()> {
+        let value = serde_json::to_vec(record)?;
+        self.pending
+            .lock()
+            .unwrap()
+            .push((Some(record.request_id.clone().into_bytes()), value));
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let messages = std::mem::take(&mut *self.pending.lock().unwrap());
+        self.runtime.block_on(self.producer.produce(&messages))
+    }
+}
+
+/// Opens the sink described by `spec`: `kafka://host:port/topic` or a file path. Must be
+/// called from within a tokio runtime.
+pub fn open_sink(spec: &str) -> Result<Arc<dyn AnalyticsSink>> {
+    match KafkaProducer::for_sink(spec)? {
+        Some(producer) => Ok(Arc::new(KafkaAnalyticsSink::new(producer))),
+        None => Ok(Arc::new(FileAnalyticsSink::open(spec)?)),
+    }
+}
+
+/// Cheaply cloneable handle feeding records to a sink without blocking the caller.
+#[derive(Clone)]
+pub struct AnalyticsTee {
+    sender: mpsc::Sender<AnalyticsRecord>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AnalyticsTee {
+    /// Starts the background writer. Must be called from within a tokio runtime.
+    pub fn spawn(sink: Arc<dyn AnalyticsSink>, capacity: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<AnalyticsRecord>(capacity);
+
+        tokio::task::spawn_blocking(move || {
+            while let Some(record) = receiver.blocking_recv() {
+                if let Err(e) = sink.write(&record) {
+                    eprintln!("Failed to write analytics record: {}", e);
+                }
+                // Flush whenever the channel is drained so records are visible promptly.
+                if receiver.is_empty()
+                    && let Err(e) = sink.flush()
+                {
+                    eprintln!("Failed to flush analytics sink: {}", e);
+                }
+            }
+            let _ = sink.flush();
+        });
+
+        Self {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Queues a record for the sink, dropping it if the sink is lagging behind.
+    pub fn tee(&self, record: AnalyticsRecord) {
+        if self.sender.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of records dropped because the sink could not keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for AnalyticsTee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnalyticsTee")
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct MemorySink {
+        records: Mutex<Vec<AnalyticsRecord>>,
+    }
+
+    impl AnalyticsSink for MemorySink {
+        fn write(&self, record: &AnalyticsRecord) -> Result<()> {
+            self.records.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    fn record(sequence: u64) -> AnalyticsRecord {
+        AnalyticsRecord::new(
+            "model",
+            None,
+            "req",
+            sequence,
+            serde_json::json!({"n": sequence}),
+        )
+    }
+
+    async fn wait_for<F: Fn() -> bool>(condition: F) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not reached in time");
+    }
+
+    #[tokio::test]
+    async fn test_tee_delivers_records_in_order() {
+        let sink = Arc::new(MemorySink::default());
+        let tee = AnalyticsTee::spawn(sink.clone(), 16);
+
+        tee.tee(record(0));
+        tee.tee(record(1));
+
+        wait_for(|| sink.records.lock().unwrap().len() == 2).await;
+        let records = sink.records.lock().unwrap();
+        assert_eq!(records[0].sequence, 0);
+        assert_eq!(records[1].sequence, 1);
+        assert_eq!(tee.dropped(), 0);
+    }
+
+    #[test]
+    fn test_file_sink_writes_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("galemind-analytics-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let sink = FileAnalyticsSink::open(&path).unwrap();
+        sink.write(&record(0)).unwrap();
+        sink.write(&record(1)).unwrap();
+        sink.flush().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let parsed: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(parsed["sequence"], 1);
+        assert_eq!(parsed["payload"]["n"], 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kafka_sink_produces_records_keyed_by_request() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let spec = format!("kafka://{}/stream", listener.local_addr().unwrap());
+        let tee = AnalyticsTee::spawn(open_sink(&spec).unwrap(), 16);
+        tee.tee(record(0));
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&request).ends_with("]}") {
+            let read = socket.read(&mut buffer).await.unwrap();
+            assert!(read > 0);
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let body = r#"{"offsets":[{"partition":0,"offset":1}]}"#;
+        socket
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /topics/stream"));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        // "req", the request id, in base64.
+        assert_eq!(body["records"][0]["key"], "cmVx");
+        assert_eq!(tee.dropped(), 0);
+
+        assert!(open_sink("kafka://proxy:8082").is_err());
+    }
+}
@@ -0,0 +1,509 @@
+/* Output tensor casting.
+
+Runtimes produce FP64 outputs. A client may ask for an output in another
+datatype with the `datatype` parameter of the requested output, e.g. FP16
+outputs of a large embedding to halve the bytes sent. The servers cast the
+values while converting the output to their wire format:
+
+- Float casts round to nearest, overflowing to infinity.
+- Integer casts truncate toward zero and saturate at the bounds of the type;
+  NaN becomes 0.
+- BOOL is false for 0 and true otherwise.
+
+FP16 and BF16 have no typed contents field in the gRPC protocol and are sent
+as little-endian `raw_output_contents`; REST returns their values, rounded to
+the requested precision, as JSON numbers.
+
+Raw contents are read back with `CastTensor::from_raw_bytes`, for tensors sent
+as binary data.
+*/
+
+use anyhow::anyhow;
+use std::fmt;
+use std::str::FromStr;
+
+/// Parameter of a requested output naming the datatype to return it in.
+pub const DATATYPE_PARAMETER: &str = "datatype";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputDatatype {
+    Fp64,
+    Fp32,
+    Fp16,
+    Bf16,
+    Int64,
+    Int32,
+    Int16,
+    Int8,
+    Uint64,
+    Uint32,
+    Uint16,
+    Uint8,
+    Bool,
+}
+
+impl OutputDatatype {
+    /// Bytes per element in raw contents.
+    pub fn element_size(self) -> usize {
+        match self {
+            Self::Fp64 | Self::Int64 | Self::Uint64 => 8,
+            Self::Fp32 | Self::Int32 | Self::Uint32 => 4,
+            Self::Fp16 | Self::Bf16 | Self::Int16 | Self::Uint16 => 2,
+            Self::Int8 | Self::Uint8 | Self::Bool => 1,
+        }
+    }
+
+    /// Whether the gRPC protocol can only carry the datatype as raw contents.
+    pub fn requires_raw(self) -> bool {
+        matches!(self, Self::Fp16 | Self::Bf16)
+    }
+}
+
+impl FromStr for OutputDatatype {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "FP64" => Ok(Self::Fp64),
+            "FP32" => Ok(Self::Fp32),
+            "FP16" => Ok(Self::Fp16),
+            "BF16" => Ok(Self::Bf16),
+            "INT64" => Ok(Self::Int64),
+            "INT32" => Ok(Self::Int32),
+            "INT16" => Ok(Self::Int16),
+            "INT8" => Ok(Self::Int8),
+            "UINT64" => Ok(Self::Uint64),
+            "UINT32" => Ok(Self::Uint32),
+            "UINT16" => Ok(Self::Uint16),
+            "UINT8" => Ok(Self::Uint8),
+            "BOOL" => Ok(Self::Bool),
+            _ => Err(anyhow!("Unsupported output datatype '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputDatatype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Fp64 => "FP64",
+            Self::Fp32 => "FP32",
+            Self::Fp16 => "FP16",
+            Self::Bf16 => "BF16",
+            Self::Int64 => "INT64",
+            Self::Int32 => "INT32",
+            Self::Int16 => "INT16",
+            Self::Int8 => "INT8",
+            Self::Uint64 => "UINT64",
+            Self::Uint32 => "UINT32",
+            Self::Uint16 => "UINT16",
+            Self::Uint8 => "UINT8",
+            Self::Bool => "BOOL",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Cast values, in the widest container of their datatype's family, as the typed
+/// contents of the protocols hold them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CastValues {
+    Fp64(Vec<f64>),
+    Fp32(Vec<f32>),
+    /// FP16 or BF16 bit patterns.
+    Half(Vec<u16>),
+    Int64(Vec<i64>),
+    /// INT32, INT16 and INT8.
+    Int32(Vec<i32>),
+    Uint64(Vec<u64>),
+    /// UINT32, UINT16 and UINT8.
+    Uint32(Vec<u32>),
+    Bool(Vec<bool>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastTensor {
+    pub datatype: OutputDatatype,
+    pub values: CastValues,
+}
+
+impl CastTensor {
+    pub fn new(values: &[f64], datatype: OutputDatatype) -> Self {
+        let values = match datatype {
+            OutputDatatype::Fp64 => CastValues::Fp64(values.to_vec()),
+            OutputDatatype::Fp32 => CastValues::Fp32(values.iter().map(|v| *v as f32).collect()),
+            OutputDatatype::Fp16 => {
+                CastValues::Half(values.iter().map(|v| f32_to_f16(*v as f32)).collect())
+            }
+            OutputDatatype::Bf16 => {
+                CastValues::Half(values.iter().map(|v| f32_to_bf16(*v as f32)).collect())
+            }
+            OutputDatatype::Int64 => CastValues::Int64(values.iter().map(|v| *v as i64).collect()),
+            OutputDatatype::Int32 => CastValues::Int32(values.iter().map(|v| *v as i32).collect()),
+            OutputDatatype::Int16 => {
+                CastValues::Int32(values.iter().map(|v| *v as i16 as i32).collect())
+            }
+            OutputDatatype::Int8 => {
+                CastValues::Int32(values.iter().map(|v| *v as i8 as i32).collect())
+            }
+            OutputDatatype::Uint64 => {
+                CastValues::Uint64(values.iter().map(|v| *v as u64).collect())
+            }
+            OutputDatatype::Uint32 => {
+                CastValues::Uint32(values.iter().map(|v| *v as u32).collect())
+            }
+            OutputDatatype::Uint16 => {
+                CastValues::Uint32(values.iter().map(|v| *v as u16 as u32).collect())
+            }
+            OutputDatatype::Uint8 => {
+                CastValues::Uint32(values.iter().map(|v| *v as u8 as u32).collect())
+            }
+            OutputDatatype::Bool => CastValues::Bool(values.iter().map(|v| *v != 0.0).collect()),
+        };
+        Self { datatype, values }
+    }
+
+    /// `values` cast to `datatype` without going through FP64, which holds integers of at
+    /// most 53 bits exactly. Like the casts of `new`, values out of range saturate.
+    pub fn from_i64(values: &[i64], datatype: OutputDatatype) -> Self {
+        Self::from_integers(values.iter().map(|v| *v as i128), datatype)
+    }
+
+    /// `values` cast to `datatype`, see `from_i64`.
+    pub fn from_u64(values: &[u64], datatype: OutputDatatype) -> Self {
+        Self::from_integers(values.iter().map(|v| *v as i128), datatype)
+    }
+
+    fn from_integers(values: impl Iterator<Item = i128>, datatype: OutputDatatype) -> Self {
+        fn saturate<T: TryFrom<i128> + Bounded>(value: i128) -> T {
+            T::try_from(value).unwrap_or(if value < 0 { T::MIN } else { T::MAX })
+        }
+        let values = match datatype {
+            OutputDatatype::Int64 => CastValues::Int64(values.map(saturate).collect()),
+            OutputDatatype::Int32 => CastValues::Int32(values.map(saturate).collect()),
+            OutputDatatype::Int16 => {
+                CastValues::Int32(values.map(|v| saturate::<i16>(v) as i32).collect())
+            }
+            OutputDatatype::Int8 => {
+                CastValues::Int32(values.map(|v| saturate::<i8>(v) as i32).collect())
+            }
+            OutputDatatype::Uint64 => CastValues::Uint64(values.map(saturate).collect()),
+            OutputDatatype::Uint32 => CastValues::Uint32(values.map(saturate).collect()),
+            OutputDatatype::Uint16 => {
+                CastValues::Uint32(values.map(|v| saturate::<u16>(v) as u32).collect())
+            }
+            OutputDatatype::Uint8 => {
+                CastValues::Uint32(values.map(|v| saturate::<u8>(v) as u32).collect())
+            }
+            OutputDatatype::Bool => CastValues::Bool(values.map(|v| v != 0).collect()),
+            OutputDatatype::Fp64
+            | OutputDatatype::Fp32
+            | OutputDatatype::Fp16
+            | OutputDatatype::Bf16 => {
+                let values: Vec<f64> = values.map(|v| v as f64).collect();
+                return Self::new(&values, datatype);
+            }
+        };
+        Self { datatype, values }
+    }
+
+    /// FP16 and BF16 values widened to `f32`, for protocols without half precision.
+    pub fn half_as_f32(&self) -> Option<Vec<f32>> {
+        let CastValues::Half(bits) = &self.values else {
+            return None;
+        };
+        let widen = match self.datatype {
+            OutputDatatype::Bf16 => bf16_to_f32,
+            _ => f16_to_f32,
+        };
+        Some(bits.iter().map(|bits| widen(*bits)).collect())
+    }
+
+    /// The values widened to FP64, as runtimes take them.
+    pub fn to_f64(&self) -> Vec<f64> {
+        match &self.values {
+            CastValues::Fp64(values) => values.clone(),
+            CastValues::Fp32(values) => values.iter().map(|v| *v as f64).collect(),
+            CastValues::Half(_) => self
+                .half_as_f32()
+                .unwrap_or_default()
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+            CastValues::Int64(values) => values.iter().map(|v| *v as f64).collect(),
+            CastValues::Int32(values) => values.iter().map(|v| *v as f64).collect(),
+            CastValues::Uint64(values) => values.iter().map(|v| *v as f64).collect(),
+            CastValues::Uint32(values) => values.iter().map(|v| *v as f64).collect(),
+            CastValues::Bool(values) => values.iter().map(|v| *v as u8 as f64).collect(),
+        }
+    }
+
+    /// The values as little-endian raw contents.
+    pub fn raw_bytes(&self) -> Vec<u8> {
+        let size = self.datatype.element_size();
+        let mut bytes = Vec::new();
+        match &self.values {
+            CastValues::Fp64(values) => values.iter().for_each(|v| bytes.extend(v.to_le_bytes())),
+            CastValues::Fp32(values) => values.iter().for_each(|v| bytes.extend(v.to_le_bytes())),
+            CastValues::Half(values) => values.iter().for_each(|v| bytes.extend(v.to_le_bytes())),
+            CastValues::Int64(values) => values.iter().for_each(|v| bytes.extend(v.to_le_bytes())),
+            CastValues::Uint64(values) => values.iter().for_each(|v| bytes.extend(v.to_le_bytes())),
+            // Narrower types keep the low bytes of their container.
+            CastValues::Int32(values) => values
+                .iter()
+                .for_each(|v| bytes.extend(&v.to_le_bytes()[..size])),
+            CastValues::Uint32(values) => values
+                .iter()
+                .for_each(|v| bytes.extend(&v.to_le_bytes()[..size])),
+            CastValues::Bool(values) => bytes.extend(values.iter().map(|v| *v as u8)),
+        }
+        bytes
+    }
+}
+
+impl CastTensor {
+    /// Values of little-endian raw contents of `datatype`, the inverse of `raw_bytes`.
+    pub fn from_raw_bytes(bytes: &[u8], datatype: OutputDatatype) -> anyhow::Result<Self> {
+        let size = datatype.element_size();
+        if !bytes.len().is_multiple_of(size) {
+            return Err(anyhow!(
+                "{} bytes of {} data are not a whole number of {}-byte elements",
+                bytes.len(),
+                datatype,
+                size
+            ));
+        }
+        let elements = bytes.chunks_exact(size);
+        // Every chunk has the size of the element, the conversions cannot fail.
+        fn array<const N: usize>(chunk: &[u8]) -> [u8; N] {
+            chunk.try_into().unwrap_or([0; N])
+        }
+        let values = match datatype {
+            OutputDatatype::Fp64 => {
+                CastValues::Fp64(elements.map(|e| f64::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Fp32 => {
+                CastValues::Fp32(elements.map(|e| f32::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Fp16 | OutputDatatype::Bf16 => {
+                CastValues::Half(elements.map(|e| u16::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Int64 => {
+                CastValues::Int64(elements.map(|e| i64::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Int32 => {
+                CastValues::Int32(elements.map(|e| i32::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Int16 => CastValues::Int32(
+                elements
+                    .map(|e| i16::from_le_bytes(array(e)) as i32)
+                    .collect(),
+            ),
+            OutputDatatype::Int8 => {
+                CastValues::Int32(elements.map(|e| e[0] as i8 as i32).collect())
+            }
+            OutputDatatype::Uint64 => {
+                CastValues::Uint64(elements.map(|e| u64::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Uint32 => {
+                CastValues::Uint32(elements.map(|e| u32::from_le_bytes(array(e))).collect())
+            }
+            OutputDatatype::Uint16 => CastValues::Uint32(
+                elements
+                    .map(|e| u16::from_le_bytes(array(e)) as u32)
+                    .collect(),
+            ),
+            OutputDatatype::Uint8 => CastValues::Uint32(elements.map(|e| e[0] as u32).collect()),
+            OutputDatatype::Bool => CastValues::Bool(elements.map(|e| e[0] != 0).collect()),
+        };
+        Ok(Self { datatype, values })
+    }
+}
+
+/// Integer types with bounds, the targets of saturating casts.
+trait Bounded {
+    const MIN: Self;
+    const MAX: Self;
+}
+
+macro_rules! bounded {
+    ($($t:ty),*) => {
+        $(impl Bounded for $t {
+            const MIN: Self = <$t>::MIN;
+            const MAX: Self = <$t>::MAX;
+        })*
+    };
+}
+
+bounded!(i64, i32, i16, i8, u64, u32, u16, u8);
+
+/// IEEE 754 half precision bits of `value`, rounded to nearest even.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (half, remainder, halfway) = if exponent <= 0 {
+        // Subnormal: the implicit leading bit becomes explicit.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        (
+            mantissa >> shift,
+            mantissa & ((1 << shift) - 1),
+            1 << (shift - 1),
+        )
+    } else {
+        (
+            ((exponent as u32) << 10) | (mantissa >> 13),
+            mantissa & 0x1fff,
+            0x1000,
+        )
+    };
+    // A carry out of the mantissa correctly moves to the next exponent (or infinity).
+    let rounded = if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+        half + 1
+    } else {
+        half
+    };
+    sign | rounded as u16
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x03ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// bfloat16 bits of `value`, rounded to nearest even.
+fn f32_to_bf16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    if value.is_nan() {
+        return ((bits >> 16) as u16) | 0x0040;
+    }
+    let rounding = 0x7fff + ((bits >> 16) & 1);
+    (bits.wrapping_add(rounding) >> 16) as u16
+}
+
+fn bf16_to_f32(bits: u16) -> f32 {
+    f32::from_bits((bits as u32) << 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_precision_rounding() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(5.960_464_5e-8), 0x0001);
+        assert_eq!(f32_to_f16(1e-9), 0x0000);
+        // 1 + 2^-11 lies halfway between 1 and the next half; it rounds to even.
+        assert_eq!(f32_to_f16(1.000_488_3), 0x3c00);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+
+        assert_eq!(f32_to_bf16(1.0), 0x3f80);
+        assert_eq!(bf16_to_f32(f32_to_bf16(3.140_625)), 3.140_625);
+        assert!(bf16_to_f32(f32_to_bf16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_cast_values_and_raw_bytes() {
+        let values = [1.7, -1.7, 300.0, f64::NAN];
+        assert_eq!(
+            CastTensor::new(&values, OutputDatatype::Int8).values,
+            CastValues::Int32(vec![1, -1, 127, 0])
+        );
+        assert_eq!(
+            CastTensor::new(&values, OutputDatatype::Uint8).values,
+            CastValues::Uint32(vec![1, 0, 255, 0])
+        );
+
+        let tensor = CastTensor::new(&[1.0, -2.0], OutputDatatype::Fp16);
+        assert_eq!(tensor.raw_bytes(), vec![0x00, 0x3c, 0x00, 0xc0]);
+        assert_eq!(tensor.half_as_f32(), Some(vec![1.0, -2.0]));
+        let tensor = CastTensor::new(&[-1.0, 258.0], OutputDatatype::Int16);
+        assert_eq!(tensor.raw_bytes(), vec![0xff, 0xff, 0x02, 0x01]);
+
+        for datatype in [
+            OutputDatatype::Fp64,
+            OutputDatatype::Fp32,
+            OutputDatatype::Bf16,
+            OutputDatatype::Int64,
+            OutputDatatype::Int16,
+            OutputDatatype::Int8,
+            OutputDatatype::Uint32,
+            OutputDatatype::Uint8,
+            OutputDatatype::Bool,
+        ] {
+            let tensor = CastTensor::new(&[1.0, -2.0, 3.5], datatype);
+            let decoded = CastTensor::from_raw_bytes(&tensor.raw_bytes(), datatype).unwrap();
+            assert_eq!(decoded, tensor);
+        }
+        assert!(CastTensor::from_raw_bytes(&[0; 6], OutputDatatype::Fp32).is_err());
+        let tensor = CastTensor::from_raw_bytes(&[0x00, 0x3c, 0xff, 0xff], OutputDatatype::Int16);
+        assert_eq!(tensor.unwrap().to_f64(), vec![15360.0, -1.0]);
+        let tensor = CastTensor::from_raw_bytes(&[0x00, 0x3c, 0x00, 0xc0], OutputDatatype::Fp16);
+        assert_eq!(tensor.unwrap().to_f64(), vec![1.0, -2.0]);
+
+        assert_eq!(
+            "fp16".parse::<OutputDatatype>().unwrap(),
+            OutputDatatype::Fp16
+        );
+        assert!("FP8".parse::<OutputDatatype>().is_err());
+    }
+
+    #[test]
+    fn test_integer_casts_keep_64_bit_values() {
+        let tensor = CastTensor::from_i64(&[i64::MIN, i64::MAX, -1], OutputDatatype::Int64);
+        assert_eq!(
+            tensor.values,
+            CastValues::Int64(vec![i64::MIN, i64::MAX, -1])
+        );
+        let tensor = CastTensor::from_u64(&[u64::MAX, (1 << 53) + 1], OutputDatatype::Uint64);
+        assert_eq!(
+            tensor.values,
+            CastValues::Uint64(vec![u64::MAX, (1 << 53) + 1])
+        );
+        let decoded = CastTensor::from_raw_bytes(&tensor.raw_bytes(), OutputDatatype::Uint64);
+        assert_eq!(decoded.unwrap(), tensor);
+
+        // Out of range values saturate, as the casts of floating point values do.
+        assert_eq!(
+            CastTensor::from_i64(&[300, -300, -1], OutputDatatype::Uint8).values,
+            CastValues::Uint32(vec![255, 0, 0])
+        );
+        assert_eq!(
+            CastTensor::from_u64(&[u64::MAX], OutputDatatype::Int64).values,
+            CastValues::Int64(vec![i64::MAX])
+        );
+        assert_eq!(
+            CastTensor::from_i64(&[0, 2], OutputDatatype::Bool).values,
+            CastValues::Bool(vec![false, true])
+        );
+        assert_eq!(
+            CastTensor::from_i64(&[3], OutputDatatype::Fp32).values,
+            CastValues::Fp32(vec![3.0])
+        );
+    }
+}
@@ -0,0 +1,301 @@
+/* Wire format of inference requests and responses.
+
+Persistent queues, dead letter queues, traffic recorders and the message queue
+ingestion path store or forward `InferenceRequest`s and `InferenceResponse`s.
+They encode them with a `Codec`, so a request written by one can be read by
+any other. `PostcardCodec` is the default: a compact binary encoding with
+variable-length integers and no field names. `JsonCodec` trades size for
+payloads that can be read by hand.
+
+Every encoded message starts with two header bytes, the tag of its codec and
+the format version, so readers refuse payloads they do not understand instead
+of misreading them; `detect` picks the codec of a message from its header.
+
+The debug timeline of a request is not encoded. Its deadline is encoded as a
+wall-clock time (milliseconds since the Unix epoch) and mapped back onto the
+monotonic clock when decoded, so a request keeps its remaining time across
+processes: one that expired in the meantime decodes as expired.
+*/
+
+use anyhow::{Result, anyhow};
+use std::sync::Arc;
+
+use super::inference::{InferenceRequest, InferenceResponse};
+
+/// Version of the encoding of the inference types, bumped on incompatible changes.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Encodes inference requests and responses to bytes and back.
+pub trait Codec: Send + Sync {
+    /// Name of the codec in configurations, e.g. `postcard`.
+    fn name(&self) -> &'static str;
+
+    /// First header byte of the messages of this codec.
+    fn tag(&self) -> u8;
+
+    fn encode_request(&self, request: &InferenceRequest) -> Result<Vec<u8>>;
+
+    fn decode_request(&self, bytes: &[u8]) -> Result<InferenceRequest>;
+
+    fn encode_response(&self, response: &InferenceResponse) -> Result<Vec<u8>>;
+
+    fn decode_response(&self, bytes: &[u8]) -> Result<InferenceResponse>;
+}
+
+fn header(codec: &dyn Codec) -> Vec<u8> {
+    vec![codec.tag(), FORMAT_VERSION]
+}
+
+/// The encoded value following the header of a message of `codec`.
+fn body<'a>(codec: &dyn Codec, bytes: &'a [u8]) -> Result<&'a [u8]> {
+    match bytes {
+        [tag, version, body @ ..] if *tag == codec.tag() => {
+            if *version != FORMAT_VERSION {
+                return Err(anyhow!(
+                    "Unsupported {} format version {} (expected {})",
+                    codec.name(),
+                    version,
+                    FORMAT_VERSION
+                ));
+            }
+            Ok(body)
+        }
+        _ => Err(anyhow!("Not a {} encoded message", codec.name())),
+    }
+}
+
+/// Compact binary encoding, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostcardCodec;
+
+impl Codec for PostcardCodec {
+    fn name(&self) -> &'static str {
+        "postcard"
+    }
+
+    fn tag(&self) -> u8 {
+        b'P'
+    }
+
+    fn encode_request(&self, request: &InferenceRequest) -> Result<Vec<u8>> {
+        Ok(postcard::to_extend(request, header(self))?)
+    }
+
+    fn decode_request(&self, bytes: &[u8]) -> Result<InferenceRequest> {
+        Ok(postcard::from_bytes(body(self, bytes)?)?)
+    }
+
+    fn encode_response(&self, response: &InferenceResponse) -> Result<Vec<u8>> {
+        Ok(postcard::to_extend(response, header(self))?)
+    }
+
+    fn decode_response(&self, bytes: &[u8]) -> Result<InferenceResponse> {
+        Ok(postcard::from_bytes(body(self, bytes)?)?)
+    }
+}
+
+/// JSON encoding, for payloads read by hand.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn tag(&self) -> u8 {
+        b'J'
+    }
+
+    fn encode_request(&self, request: &InferenceRequest) -> Result<Vec<u8>> {
+        let mut bytes = header(self);
+        serde_json::to_writer(&mut bytes, request)?;
+        Ok(bytes)
+    }
+
+    fn decode_request(&self, bytes: &[u8]) -> Result<InferenceRequest> {
+        Ok(serde_json::from_slice(body(self, bytes)?)?)
+    }
+
+    fn encode_response(&self, response: &InferenceResponse) -> Result<Vec<u8>> {
+        let mut bytes = header(self);
+        serde_json::to_writer(&mut bytes, response)?;
+        Ok(bytes)
+    }
+
+    fn decode_response(&self, bytes: &[u8]) -> Result<InferenceResponse> {
+        Ok(serde_json::from_slice(body(self, bytes)?)?)
+    }
+}
+
+/// Every built-in codec, the default first.
+pub fn codecs() -> Vec<Arc<dyn Codec>> {
+    vec![Arc::new(PostcardCodec), Arc::new(JsonCodec)]
+}
+
+/// The built-in codec called `name`.
+pub fn codec(name: &str) -> Result<Arc<dyn Codec>> {
+    codecs()
+        .into_iter()
+        .find(|codec| codec.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("Unknown codec '{}', expected postcard or json", name))
+}
+
+/// The built-in codec that encoded `bytes`, read from their header.
+pub fn detect(bytes: &[u8]) -> Result<Arc<dyn Codec>> {
+    let tag = bytes
+        .first()
+        .ok_or_else(|| anyhow!("Empty message has no codec header"))?;
+    codecs()
+        .into_iter()
+        .find(|codec| codec.tag() == *tag)
+        .ok_or_else(|| anyhow!("Unknown codec tag {:#04x}", tag))
+}
+
+/// Serde adapter encoding an `Option<Instant>` deadline as Unix epoch milliseconds.
+pub(crate) mod wall_clock {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(
+        deadline: &Option<Instant>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        deadline.map(to_unix_ms).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Instant>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(from_unix_ms))
+    }
+
+    fn to_unix_ms(deadline: Instant) -> u64 {
+        let (now, wall_now) = (Instant::now(), SystemTime::now());
+        let wall = if deadline >= now {
+            wall_now + (deadline - now)
+        } else {
+            wall_now - (now - deadline)
+        };
+        let millis = wall
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        u64::try_from(millis).unwrap_or(u64::MAX)
+    }
+
+    fn from_unix_ms(millis: u64) -> Instant {
+        let wall = UNIX_EPOCH + Duration::from_millis(millis);
+        let (now, wall_now) = (Instant::now(), SystemTime::now());
+        match wall.duration_since(wall_now) {
+            Ok(remaining) => now + remaining,
+            // Already past: any instant up to now is expired.
+            Err(past) => now.checked_sub(past.duration()).unwrap_or(now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::{InferParameter, InferenceError, InferenceOutput};
+    use crate::api::sampling::SamplingOptions;
+    use crate::api::tensor::{Data, DataType};
+    use crate::deadline::is_expired;
+    use crate::model::priority::Priority;
+    use crate::timeline::Timeline;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn request(deadline: Option<Instant>) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: Some("2".to_string()),
+            id: "r".to_string(),
+            parameters: Some(HashMap::from([
+                ("top_k".to_string(), InferParameter::Int64(5)),
+                ("text".to_string(), InferParameter::String("hi".to_string())),
+            ])),
+            outputs: None,
+            timeline: Some(Arc::new(Timeline::new())),
+            priority: Priority::High,
+            deadline,
+            tenant: Some("acme".to_string()),
+            sampling: SamplingOptions {
+                temperature: Some(0.7),
+                stop: vec!["END".to_string()],
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_requests_round_trip_with_their_remaining_time() {
+        for codec in codecs() {
+            let deadline = Instant::now() + Duration::from_secs(60);
+            let bytes = codec.encode_request(&request(Some(deadline))).unwrap();
+            assert_eq!(bytes[..2], [codec.tag(), FORMAT_VERSION]);
+            assert_eq!(detect(&bytes).unwrap().name(), codec.name());
+
+            let decoded = codec.decode_request(&bytes).unwrap();
+            assert_eq!(decoded.model_version.as_deref(), Some("2"));
+            assert_eq!(decoded.priority, Priority::High);
+            assert_eq!(decoded.tenant.as_deref(), Some("acme"));
+            assert_eq!(decoded.sampling.temperature, Some(0.7));
+            assert_eq!(decoded.sampling.stop, vec!["END".to_string()]);
+            assert!(matches!(
+                decoded.parameters.unwrap()["top_k"],
+                InferParameter::Int64(5)
+            ));
+            assert!(decoded.timeline.is_none());
+            let drift = decoded.deadline.unwrap().duration_since(deadline)
+                + deadline.duration_since(decoded.deadline.unwrap());
+            assert!(drift < Duration::from_millis(5));
+
+            let expired = request(Some(Instant::now() - Duration::from_millis(10)));
+            let decoded = codec
+                .decode_request(&codec.encode_request(&expired).unwrap())
+                .unwrap();
+            assert!(is_expired(decoded.deadline, Instant::now()));
+        }
+    }
+
+    #[test]
+    fn test_responses_round_trip_and_foreign_messages_are_refused() {
+        let response = InferenceResponse::Ok(InferenceOutput {
+            name: "output_1".to_string(),
+            shape: vec![1, 3],
+            datatype: DataType::VFLOAT,
+            parameters: None,
+            data: Data::VFLOAT(vec![0.1, 0.5, 0.4]),
+        });
+        let postcard = PostcardCodec.encode_response(&response).unwrap();
+        let json = JsonCodec.encode_response(&response).unwrap();
+        assert!(postcard.len() < json.len());
+        match PostcardCodec.decode_response(&postcard).unwrap() {
+            InferenceResponse::Ok(output) => {
+                let Data::VFLOAT(values) = output.data else {
+                    panic!("expected numbers");
+                };
+                assert_eq!((output.shape, values), (vec![1, 3], vec![0.1, 0.5, 0.4]));
+            }
+            _ => panic!("expected an output"),
+        }
+        let error = InferenceResponse::DeadlineExceeded(InferenceError {
+            error: "late".to_string(),
+        });
+        let bytes = JsonCodec.encode_response(&error).unwrap();
+        assert!(matches!(
+            JsonCodec.decode_response(&bytes).unwrap(),
+            InferenceResponse::DeadlineExceeded(e) if e.error == "late"
+        ));
+
+        assert!(PostcardCodec.decode_response(&json).is_err());
+        let mut future = postcard.clone();
+        future[1] = FORMAT_VERSION + 1;
+        assert!(PostcardCodec.decode_response(&future).is_err());
+        assert!(detect(&[]).is_err());
+        assert_eq!(codec("JSON").unwrap().name(), "json");
+        assert!(codec("bincode").is_err());
+    }
+}
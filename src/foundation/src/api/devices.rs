@@ -0,0 +1,521 @@
+/* Device placement of model runtimes.
+
+A model configuration may pin the model to a device (`device: cuda:1`). The
+runtime factory loads the artifact onto that device, and every call to the
+runtime then takes one of the device's slots from the `DeviceScheduler`.
+Models sharing a GPU therefore share its slots: once `gpu_slots` calls are in
+flight on a device, further calls wait for one to complete instead of
+oversubscribing its memory and compute, whichever model they are for. Models
+left on the CPU are not scheduled here; their request buffers bound them.
+
+Runtimes staging their batches (see `api::transfer`) only hold the slot while
+the batch executes: its inputs are uploaded before, and its outputs downloaded
+after, through the pinned host buffers of the device.
+*/
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::inference::{InferenceError, InferenceRequest, InferenceResponse};
+use super::inference_runtime::InferenceRuntime;
+use super::model_metadata::ModelSignature;
+use super::transfer::{
+    DeviceStaging, Direction, PinnedBufferPool, TransferMetrics, stage, staged_len,
+};
+use crate::model::model_discovery_service::ModelVersionId;
+
+/// Calls in flight at once on a GPU unless configured otherwise.
+pub const DEFAULT_GPU_SLOTS: usize = 4;
+
+/// Device a runtime is loaded onto, written `cpu` or `cuda:<index>`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub enum Device {
+    #[default]
+    Cpu,
+    Cuda(u32),
+}
+
+impl Device {
+    pub fn is_gpu(&self) -> bool {
+        matches!(self, Device::Cuda(_))
+    }
+}
+
+impl FromStr for Device {
+    type Err = anyhow::Error;
+
+    /// Accepts `cpu`, `cuda:<index>` and `gpu:<index>`; a bare `cuda` or `gpu` is device 0.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        if s == "cpu" {
+            return Ok(Device::Cpu);
+        }
+        let (kind, index) = s.split_once(':').unwrap_or((&s, "0"));
+        if kind != "cuda" && kind != "gpu" {
+            return Err(anyhow!(
+                "Unknown device '{}', expected cpu or cuda:<index>",
+                s
+            ));
+        }
+        index
+            .parse()
+            .map(Device::Cuda)
+            .map_err(|_| anyhow!("Invalid GPU index in device '{}'", s))
+    }
+}
+
+impl TryFrom<String> for Device {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Device> for String {
+    fn from(device: Device) -> Self {
+        device.to_string()
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Device::Cpu => write!(f, "cpu"),
+            Device::Cuda(index) => write!(f, "cuda:{}", index),
+        }
+    }
+}
+
+/// Load of one device, as reported by `DeviceScheduler::loads`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceLoad {
+    pub device: Device,
+    pub slots: usize,
+    pub in_flight: usize,
+    /// Calls waiting for a slot.
+    pub waiting: usize,
+    /// Model versions placed on the device.
+    pub versions: Vec<String>,
+}
+
+struct DeviceSlots {
+    permits: Arc<Semaphore>,
+    in_flight: AtomicUsize,
+    waiting: AtomicUsize,
+    versions: Mutex<BTreeSet<String>>,
+    buffers: PinnedBufferPool,
+}
+
+/// A slot of a device, released when the call holding it completes or is cancelled.
+pub struct DeviceLease {
+    _permit: OwnedSemaphorePermit,
+    slots: Arc<DeviceSlots>,
+}
+
+impl Drop for DeviceLease {
+    fn drop(&mut self) {
+        self.slots.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::AcqRel);
+        Self(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Shares the slots of each GPU among the model versions placed on it.
+pub struct DeviceScheduler {
+    gpu_slots: usize,
+    devices: DashMap<Device, Arc<DeviceSlots>>,
+    transfers: TransferMetrics,
+}
+
+impl Default for DeviceScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_GPU_SLOTS)
+    }
+}
+
+impl DeviceScheduler {
+    /// Allows `gpu_slots` (at least one) calls in flight at once per GPU.
+    pub fn new(gpu_slots: usize) -> Self {
+        Self {
+            gpu_slots: gpu_slots.max(1),
+            devices: DashMap::new(),
+            transfers: TransferMetrics::default(),
+        }
+    }
+
+    pub fn gpu_slots(&self) -> usize {
+        self.gpu_slots
+    }
+
+    fn slots(&self, device: Device) -> Arc<DeviceSlots> {
+        self.devices
+            .entry(device)
+            .or_insert_with(|| {
+                Arc::new(DeviceSlots {
+                    permits: Arc::new(Semaphore::new(self.gpu_slots)),
+                    in_flight: AtomicUsize::new(0),
+                    waiting: AtomicUsize::new(0),
+                    versions: Mutex::new(BTreeSet::new()),
+                    buffers: PinnedBufferPool::default(),
+                })
+            })
+            .clone()
+    }
+
+    /// Records `version_id` as placed on `device`, moving it off any other device.
+    pub fn place(&self, version_id: &ModelVersionId, device: Device) {
+        self.remove(version_id);
+        self.slots(device)
+            .versions
+            .lock()
+            .unwrap()
+            .insert(version_id.to_string());
+    }
+
+    pub fn remove(&self, version_id: &ModelVersionId) {
+        let version = version_id.to_string();
+        for slots in self.devices.iter() {
+            slots.versions.lock().unwrap().remove(&version);
+        }
+    }
+
+    /// Waits for a free slot of `device`.
+    pub async fn acquire(&self, device: Device) -> DeviceLease {
+        let slots = self.slots(device);
+        let permit = {
+            // Counted until the slot is granted, or the call is cancelled while waiting.
+            let _waiting = Waiting::new(&slots.waiting);
+            slots.permits.clone().acquire_owned().await
+        };
+        slots.in_flight.fetch_add(1, Ordering::AcqRel);
+        DeviceLease {
+            _permit: permit.expect("device semaphores are never closed"),
+            slots,
+        }
+    }
+
+    /// Time and bytes of the host/device transfers of staged batches.
+    pub fn transfers(&self) -> &TransferMetrics {
+        &self.transfers
+    }
+
+    /// Load of every device seen so far, in device order.
+    pub fn loads(&self) -> Vec<DeviceLoad> {
+        let mut loads: Vec<DeviceLoad> = self
+            .devices
+            .iter()
+            .map(|entry| DeviceLoad {
+                device: *entry.key(),
+                slots: self.gpu_slots,
+                in_flight: entry.in_flight.load(Ordering::Acquire),
+                waiting: entry.waiting.load(Ordering::Acquire),
+                versions: entry.versions.lock().unwrap().iter().cloned().collect(),
+            })
+            .collect();
+        loads.sort_by_key(|load| load.device);
+        loads
+    }
+}
+
+/// A runtime placed on a GPU, running each call in one of the device's slots.
+pub struct PlacedRuntime {
+    runtime: Arc<dyn InferenceRuntime>,
+    device: Device,
+    scheduler: Arc<DeviceScheduler>,
+}
+
+impl PlacedRuntime {
+    pub fn new(
+        runtime: Arc<dyn InferenceRuntime>,
+        device: Device,
+        scheduler: Arc<DeviceScheduler>,
+    ) -> Self {
+        Self {
+            runtime,
+            device,
+            scheduler,
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Runs `requests` with `staging`, transferring outside of the device slot.
+    async fn process_staged(
+        &self,
+        staging: &dyn DeviceStaging,
+        requests: Vec<InferenceRequest>,
+    ) -> Result<Vec<InferenceResponse>> {
+        let transfers = &self.scheduler.transfers;
+        let mut host = self
+            .scheduler
+            .slots(self.device)
+            .buffers
+            .take(staged_len(&requests), staging)?;
+        let spans = stage(&requests, &mut host);
+
+        let started = Instant::now();
+        let mut batch = staging.upload(requests, host.as_slice(), &spans).await?;
+        transfers.record(
+            self.device,
+            Direction::HostToDevice,
+            host.len(),
+            started.elapsed(),
+        );
+        {
+            let _lease = self.scheduler.acquire(self.device).await;
+            staging.execute(&mut batch).await?;
+        }
+        let started = Instant::now();
+        let (responses, bytes) = staging.download(batch, &mut host).await?;
+        transfers.record(
+            self.device,
+            Direction::DeviceToHost,
+            bytes,
+            started.elapsed(),
+        );
+        Ok(responses)
+    }
+}
+
+#[async_trait]
+impl InferenceRuntime for PlacedRuntime {
+    fn model_id(&self) -> &str {
+        self.runtime.model_id()
+    }
+
+    fn platform(&self) -> Option<&str> {
+        self.runtime.platform()
+    }
+
+    fn signature(&self) -> Option<ModelSignature> {
+        self.runtime.signature()
+    }
+
+    async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+        let _lease = self.scheduler.acquire(self.device).await;
+        self.runtime.process_single(request).await
+    }
+
+    fn staging(&self) -> Option<&dyn DeviceStaging> {
+        self.runtime.staging()
+    }
+
+    async fn process_batch(&self, requests: Vec<InferenceRequest>) -> Vec<InferenceResponse> {
+        let Some(staging) = self.runtime.staging() else {
+            let _lease = self.scheduler.acquire(self.device).await;
+            return self.runtime.process_batch(requests).await;
+        };
+        let count = requests.len();
+        match self.process_staged(staging, requests).await {
+            Ok(responses) => responses,
+            Err(error) => (0..count)
+                .map(|_| {
+                    InferenceResponse::Error(InferenceError {
+                        error: format!("Batch failed on {}: {}", self.device, error),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::InferenceOutput;
+    use crate::api::tensor::{Data, DataType};
+    use crate::api::transfer::{DeviceBatch, PinnedBuffer, TensorSpan};
+    use crate::model::priority::Priority;
+    use std::time::Duration;
+
+    /// Echoes its inputs, counting the batches uploaded.
+    #[derive(Default)]
+    struct EchoStaging {
+        uploads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl DeviceStaging for EchoStaging {
+        fn pin(&self, _host: &mut [u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn unpin(&self, _host: &mut [u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn upload(
+            &self,
+            requests: Vec<InferenceRequest>,
+            _host: &[u8],
+            _spans: &[TensorSpan],
+        ) -> Result<DeviceBatch> {
+            self.uploads.fetch_add(1, Ordering::AcqRel);
+            Ok(DeviceBatch {
+                requests,
+                state: Box::new(()),
+            })
+        }
+
+        async fn execute(&self, _batch: &mut DeviceBatch) -> Result<()> {
+            Ok(())
+        }
+
+        async fn download(
+            &self,
+            batch: DeviceBatch,
+            host: &mut PinnedBuffer,
+        ) -> Result<(Vec<InferenceResponse>, usize)> {
+            let responses = batch
+                .requests
+                .into_iter()
+                .map(|request| InferenceResponse::Ok(request.outputs.unwrap().remove(0)))
+                .collect();
+            Ok((responses, host.len()))
+        }
+    }
+
+    #[async_trait]
+    impl InferenceRuntime for EchoStaging {
+        fn model_id(&self) -> &str {
+            "echo"
+        }
+
+        fn staging(&self) -> Option<&dyn DeviceStaging> {
+            Some(self)
+        }
+
+        async fn process_single(&self, _request: InferenceRequest) -> InferenceResponse {
+            unreachable!("batches are staged")
+        }
+    }
+
+    fn request(values: Vec<f64>) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "echo".to_string(),
+            model_version: None,
+            id: "r".to_string(),
+            parameters: None,
+            outputs: Some(vec![InferenceOutput {
+                name: "input_1".to_string(),
+                shape: vec![values.len()],
+                datatype: DataType::VFLOAT,
+                parameters: None,
+                data: Data::VFLOAT(values),
+            }]),
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_device_parsing() {
+        assert_eq!("cpu".parse::<Device>().unwrap(), Device::Cpu);
+        assert_eq!("cuda:1".parse::<Device>().unwrap(), Device::Cuda(1));
+        assert_eq!("GPU".parse::<Device>().unwrap(), Device::Cuda(0));
+        assert!("cuda:x".parse::<Device>().is_err());
+        assert!("tpu:0".parse::<Device>().is_err());
+        assert_eq!(Device::Cuda(2).to_string(), "cuda:2");
+    }
+
+    #[tokio::test]
+    async fn test_models_sharing_a_gpu_share_its_slots() {
+        let scheduler = Arc::new(DeviceScheduler::new(1));
+        scheduler.place(&ModelVersionId::new("a", "1"), Device::Cuda(0));
+        scheduler.place(&ModelVersionId::new("b", "1"), Device::Cuda(0));
+        scheduler.place(&ModelVersionId::new("b", "1"), Device::Cuda(0));
+
+        let lease = scheduler.acquire(Device::Cuda(0)).await;
+        let waiter = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move { scheduler.acquire(Device::Cuda(0)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let loads = scheduler.loads();
+        assert_eq!(loads.len(), 1);
+        assert_eq!((loads[0].in_flight, loads[0].waiting), (1, 1));
+        assert_eq!(loads[0].versions, vec!["a:1", "b:1"]);
+
+        // Another GPU is not held up by the first one.
+        drop(scheduler.acquire(Device::Cuda(1)).await);
+
+        drop(lease);
+        let second = tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scheduler.loads()[0].in_flight, 1);
+        drop(second);
+        assert_eq!(scheduler.loads()[0].in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_staged_batches_upload_while_the_device_is_busy() {
+        let scheduler = Arc::new(DeviceScheduler::new(1));
+        let staging = Arc::new(EchoStaging::default());
+        let placed = Arc::new(PlacedRuntime::new(
+            staging.clone(),
+            Device::Cuda(0),
+            scheduler.clone(),
+        ));
+
+        let busy = scheduler.acquire(Device::Cuda(0)).await;
+        let batch = {
+            let placed = placed.clone();
+            tokio::spawn(async move {
+                placed
+                    .process_batch(vec![request(vec![1.0, 2.0]), request(vec![3.0])])
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Uploaded while another call holds the only slot, executed once it is released.
+        assert_eq!(staging.uploads.load(Ordering::Acquire), 1);
+        assert_eq!(scheduler.loads()[0].waiting, 1);
+        drop(busy);
+
+        let responses = batch.await.unwrap();
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(&responses[1], InferenceResponse::Ok(output) if output.shape == [1]));
+        let transfers = scheduler.transfers();
+        assert_eq!(
+            transfers.totals(Device::Cuda(0), Direction::HostToDevice),
+            (1, 24)
+        );
+        assert_eq!(
+            transfers.totals(Device::Cuda(0), Direction::DeviceToHost),
+            (1, 24)
+        );
+    }
+}
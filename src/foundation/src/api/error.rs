@@ -0,0 +1,479 @@
+/* The error model shared by the REST and gRPC servers.
+
+A refused or failed request is described by an `ApiError`, the same for both
+protocols: a canonical code, a message for people, and details for programs,
+after `google.rpc.Status`. The code is one of the `google.rpc.Code` values,
+which the REST server answers with their HTTP status and the gRPC server with
+the gRPC code of the same name. Details are the `google.rpc` messages:
+
+- `ErrorInfo` names the reason, e.g. `invalid_tensors`, to match on;
+- `BadRequest` lists each invalid field of the request and why;
+- `RetryInfo` tells how long to wait before retrying.
+
+REST clients get them as JSON, tagged with their `@type` as in the JSON
+mapping of `google.protobuf.Any`,
+
+```json
+{
+  "error": "Inputs do not match the signature of model 'resnet': ...",
+  "code": "invalid_tensors",
+  "status": "INVALID_ARGUMENT",
+  "details": [
+    {"@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "invalid_tensors", "domain": "galemind", "metadata": {"model": "resnet"}},
+    {"@type": "type.googleapis.com/google.rpc.BadRequest", "fieldViolations": [{"field": "inputs[input].datatype", "description": "expected FP32, got INT64"}]}
+  ],
+  "requestId": "01J9ZQ..."
+}
+```
+
+and gRPC clients as the binary `grpc-status-details-bin` trailer, which the
+rich error APIs of gRPC libraries decode.
+*/
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use super::sampling::SamplingRefusal;
+use super::validation::TensorRefusal;
+use crate::model::capabilities::CapabilityRefusal;
+use crate::model::circuit::CircuitOpen;
+use crate::model::context_window::ContextRefusal;
+use crate::model::images::ImageRefusal;
+use crate::model::model_discovery_service::BufferRefusal;
+use crate::model::pause::ModelPaused;
+
+/// Domain of the `ErrorInfo` reasons of the server.
+pub const ERROR_DOMAIN: &str = "galemind";
+
+/// Canonical error codes, those of `google.rpc.Code` but `OK`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    Cancelled,
+    Unknown,
+    InvalidArgument,
+    DeadlineExceeded,
+    NotFound,
+    AlreadyExists,
+    PermissionDenied,
+    ResourceExhausted,
+    FailedPrecondition,
+    Aborted,
+    OutOfRange,
+    Unimplemented,
+    Internal,
+    Unavailable,
+    DataLoss,
+    Unauthenticated,
+}
+
+impl ErrorCode {
+    /// Number of the code in `google.rpc.Code`, which gRPC status codes share.
+    pub fn rpc_code(self) -> i32 {
+        match self {
+            Self::Cancelled => 1,
+            Self::Unknown => 2,
+            Self::InvalidArgument => 3,
+            Self::DeadlineExceeded => 4,
+            Self::NotFound => 5,
+            Self::AlreadyExists => 6,
+            Self::PermissionDenied => 7,
+            Self::ResourceExhausted => 8,
+            Self::FailedPrecondition => 9,
+            Self::Aborted => 10,
+            Self::OutOfRange => 11,
+            Self::Unimplemented => 12,
+            Self::Internal => 13,
+            Self::Unavailable => 14,
+            Self::DataLoss => 15,
+            Self::Unauthenticated => 16,
+        }
+    }
+
+    /// HTTP status of the code, as `google.rpc.Code` documents it.
+    pub fn http_status(self) -> u16 {
+        match self {
+            Self::InvalidArgument | Self::FailedPrecondition | Self::OutOfRange => 400,
+            Self::Unauthenticated => 401,
+            Self::PermissionDenied => 403,
+            Self::NotFound => 404,
+            Self::AlreadyExists | Self::Aborted => 409,
+            Self::ResourceExhausted => 429,
+            Self::Cancelled => 499,
+            Self::Unknown | Self::Internal | Self::DataLoss => 500,
+            Self::Unimplemented => 501,
+            Self::Unavailable => 503,
+            Self::DeadlineExceeded => 504,
+        }
+    }
+
+    /// Code of an HTTP error status, for errors answered with a status of their own.
+    pub fn from_http_status(status: u16) -> Self {
+        match status {
+            401 => Self::Unauthenticated,
+            403 => Self::PermissionDenied,
+            404 => Self::NotFound,
+            409 => Self::Aborted,
+            413 => Self::OutOfRange,
+            429 => Self::ResourceExhausted,
+            499 => Self::Cancelled,
+            501 => Self::Unimplemented,
+            503 => Self::Unavailable,
+            504 => Self::DeadlineExceeded,
+            400..=499 => Self::InvalidArgument,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = serde_json::to_value(self).unwrap_or_default();
+        write!(f, "{}", name.as_str().unwrap_or_default())
+    }
+}
+
+/// One invalid field of a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FieldViolation {
+    /// Path to the field, e.g. `parameters.temperature` or `inputs[image].shape[1]`.
+    pub field: String,
+    pub description: String,
+}
+
+/// A `google.rpc` error detail message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "@type")]
+pub enum ErrorDetail {
+    #[serde(rename = "type.googleapis.com/google.rpc.ErrorInfo")]
+    ErrorInfo {
+        reason: String,
+        domain: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        metadata: BTreeMap<String, String>,
+    },
+    #[serde(
+        rename = "type.googleapis.com/google.rpc.BadRequest",
+        rename_all = "camelCase"
+    )]
+    BadRequest {
+        field_violations: Vec<FieldViolation>,
+    },
+    #[serde(
+        rename = "type.googleapis.com/google.rpc.RetryInfo",
+        rename_all = "camelCase"
+    )]
+    RetryInfo {
+        /// Seconds, written as the JSON mapping of `google.protobuf.Duration` does: `1.5s`.
+        #[serde(with = "duration_seconds")]
+        #[schemars(with = "String")]
+        retry_delay: Duration,
+    },
+}
+
+/// A refused or failed request, as answered over any protocol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<ErrorDetail>,
+    /// Id of the request, to quote when reporting the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            details: Vec::new(),
+            request_id: None,
+        }
+    }
+
+    pub fn invalid_argument(message: impl ToString) -> Self {
+        Self::new(ErrorCode::InvalidArgument, message)
+    }
+
+    pub fn not_found(message: impl ToString) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn resource_exhausted(message: impl ToString) -> Self {
+        Self::new(ErrorCode::ResourceExhausted, message)
+    }
+
+    pub fn unavailable(message: impl ToString) -> Self {
+        Self::new(ErrorCode::Unavailable, message)
+    }
+
+    pub fn internal(message: impl ToString) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    /// `self` with the `ErrorInfo` naming `reason`, e.g. `invalid_tensors`.
+    pub fn with_reason(self, reason: impl ToString) -> Self {
+        self.with_reason_metadata(reason, BTreeMap::new())
+    }
+
+    /// `self` with the `ErrorInfo` naming `reason`, and `metadata` about it.
+    pub fn with_reason_metadata(
+        mut self,
+        reason: impl ToString,
+        metadata: BTreeMap<String, String>,
+    ) -> Self {
+        self.details
+            .retain(|detail| !matches!(detail, ErrorDetail::ErrorInfo { .. }));
+        self.details.push(ErrorDetail::ErrorInfo {
+            reason: reason.to_string(),
+            domain: ERROR_DOMAIN.to_string(),
+            metadata,
+        });
+        self
+    }
+
+    /// `self` with `field` listed as invalid in its `BadRequest`.
+    pub fn with_field_violation(
+        mut self,
+        field: impl ToString,
+        description: impl ToString,
+    ) -> Self {
+        let violation = FieldViolation {
+            field: field.to_string(),
+            description: description.to_string(),
+        };
+        match self.details.iter_mut().find_map(|detail| match detail {
+            ErrorDetail::BadRequest { field_violations } => Some(field_violations),
+            _ => None,
+        }) {
+            Some(field_violations) => field_violations.push(violation),
+            None => self.details.push(ErrorDetail::BadRequest {
+                field_violations: vec![violation],
+            }),
+        }
+        self
+    }
+
+    /// `self` advising to retry after `delay`.
+    pub fn with_retry_delay(mut self, delay: Duration) -> Self {
+        self.details
+            .retain(|detail| !matches!(detail, ErrorDetail::RetryInfo { .. }));
+        self.details
+            .push(ErrorDetail::RetryInfo { retry_delay: delay });
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: impl ToString) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+
+    /// Reason of the `ErrorInfo` detail, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.details.iter().find_map(|detail| match detail {
+            ErrorDetail::ErrorInfo { reason, .. } => Some(reason.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn model_metadata(model: &str) -> BTreeMap<String, String> {
+    BTreeMap::from([("model".to_string(), model.to_string())])
+}
+
+impl From<TensorRefusal> for ApiError {
+    fn from(refusal: TensorRefusal) -> Self {
+        let error = ApiError::invalid_argument(&refusal)
+            .with_reason_metadata(refusal.code(), model_metadata(&refusal.model));
+        refusal.mismatches.iter().fold(error, |error, mismatch| {
+            error.with_field_violation(
+                format!("inputs[{}].{}", mismatch.input, mismatch.field),
+                format!("expected {}, got {}", mismatch.expected, mismatch.actual),
+            )
+        })
+    }
+}
+
+impl From<SamplingRefusal> for ApiError {
+    fn from(refusal: SamplingRefusal) -> Self {
+        ApiError::invalid_argument(&refusal)
+            .with_reason(refusal.code())
+            .with_field_violation(format!("parameters.{}", refusal.parameter), &refusal.reason)
+    }
+}
+
+impl From<CapabilityRefusal> for ApiError {
+    fn from(refusal: CapabilityRefusal) -> Self {
+        let error = ApiError::invalid_argument(&refusal)
+            .with_reason_metadata(refusal.code(), model_metadata(&refusal.model));
+        refusal.unsupported.iter().fold(error, |error, capability| {
+            error.with_field_violation(
+                format!("capabilities.{}", capability),
+                format!("not supported by model '{}'", refusal.model),
+            )
+        })
+    }
+}
+
+impl From<ContextRefusal> for ApiError {
+    fn from(refusal: ContextRefusal) -> Self {
+        ApiError::invalid_argument(&refusal)
+            .with_reason(refusal.code())
+            .with_field_violation(refusal.param(), &refusal)
+    }
+}
+
+impl From<ModelPaused> for ApiError {
+    fn from(refusal: ModelPaused) -> Self {
+        ApiError::unavailable(&refusal)
+            .with_reason_metadata(refusal.code(), model_metadata(&refusal.model))
+            .with_retry_delay(refusal.retry_after)
+    }
+}
+
+impl From<CircuitOpen> for ApiError {
+    fn from(refusal: CircuitOpen) -> Self {
+        ApiError::unavailable(&refusal)
+            .with_reason_metadata(refusal.code(), model_metadata(&refusal.model))
+            .with_retry_delay(refusal.retry_after)
+    }
+}
+
+impl From<BufferRefusal> for ApiError {
+    fn from(refusal: BufferRefusal) -> Self {
+        let error = match refusal {
+            BufferRefusal::NotFound { .. } => ApiError::not_found(&refusal),
+            BufferRefusal::Unloaded { .. } => ApiError::unavailable(&refusal),
+            BufferRefusal::Full { .. } => ApiError::resource_exhausted(&refusal),
+        };
+        error.with_reason_metadata(refusal.code(), model_metadata(refusal.model()))
+    }
+}
+
+impl From<ImageRefusal> for ApiError {
+    fn from(refusal: ImageRefusal) -> Self {
+        ApiError::invalid_argument(&refusal).with_reason(refusal.code())
+    }
+}
+
+mod duration_seconds {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(delay: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}s", delay.as_secs_f64()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let delay = String::deserialize(deserializer)?;
+        delay
+            .strip_suffix('s')
+            .and_then(|seconds| seconds.parse::<f64>().ok())
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(|| D::Error::custom(format!("invalid duration '{}'", delay)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::validation::TensorMismatch;
+    use serde_json::json;
+
+    #[test]
+    fn test_codes_map_to_http_and_grpc() {
+        assert_eq!(ErrorCode::InvalidArgument.http_status(), 400);
+        assert_eq!(ErrorCode::InvalidArgument.rpc_code(), 3);
+        assert_eq!(ErrorCode::ResourceExhausted.http_status(), 429);
+        assert_eq!(ErrorCode::Unauthenticated.rpc_code(), 16);
+        assert_eq!(ErrorCode::from_http_status(404), ErrorCode::NotFound);
+        assert_eq!(ErrorCode::from_http_status(406), ErrorCode::InvalidArgument);
+        assert_eq!(ErrorCode::from_http_status(502), ErrorCode::Internal);
+        assert_eq!(ErrorCode::DeadlineExceeded.to_string(), "DEADLINE_EXCEEDED");
+    }
+
+    #[test]
+    fn test_tensor_refusals_list_each_invalid_input() {
+        let refusal = TensorRefusal {
+            model: "resnet".to_string(),
+            mismatches: vec![TensorMismatch {
+                input: "image".to_string(),
+                field: "datatype".to_string(),
+                expected: "FP32".to_string(),
+                actual: "INT64".to_string(),
+            }],
+        };
+        let error = ApiError::from(refusal).with_request_id("42");
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert_eq!(error.reason(), Some("invalid_tensors"));
+        assert_eq!(
+            serde_json::to_value(&error.details).unwrap(),
+            json!([
+                {
+                    "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                    "reason": "invalid_tensors",
+                    "domain": "galemind",
+                    "metadata": {"model": "resnet"}
+                },
+                {
+                    "@type": "type.googleapis.com/google.rpc.BadRequest",
+                    "fieldViolations": [
+                        {"field": "inputs[image].datatype", "description": "expected FP32, got INT64"}
+                    ]
+                }
+            ])
+        );
+        assert_eq!(error.request_id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn test_buffer_refusals_keep_their_cause() {
+        let model = || "m".to_string();
+        let refused = |refusal: BufferRefusal| {
+            let error = ApiError::from(refusal);
+            (error.code, error.reason().unwrap().to_string())
+        };
+        assert_eq!(
+            refused(BufferRefusal::NotFound { model: model() }),
+            (ErrorCode::NotFound, "model_not_found".to_string())
+        );
+        assert_eq!(
+            refused(BufferRefusal::Unloaded { model: model() }),
+            (ErrorCode::Unavailable, "model_unloaded".to_string())
+        );
+        assert_eq!(
+            refused(BufferRefusal::Full {
+                model: model(),
+                waited: None
+            }),
+            (ErrorCode::ResourceExhausted, "buffer_full".to_string())
+        );
+    }
+
+    #[test]
+    fn test_details_round_trip_through_json() {
+        let error = ApiError::resource_exhausted("Too many requests")
+            .with_reason("rate_limited")
+            .with_reason("throttled")
+            .with_retry_delay(Duration::from_millis(1500));
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["code"], "RESOURCE_EXHAUSTED");
+        assert_eq!(json["details"][1]["retryDelay"], "1.5s");
+        let parsed: ApiError = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, error);
+        assert_eq!(parsed.reason(), Some("throttled"));
+    }
+}
@@ -1,9 +1,16 @@
+use super::devices::Device;
 use super::inference::{
     InferParameter, InferenceError, InferenceOutput, InferenceProcessor, InferenceRequest,
     InferenceResponse,
 };
+use super::inference_runtime::{InferenceRuntime, ProcessorRuntime};
+use super::model_metadata::{ModelSignature, TensorMetadata};
+use super::runtime_registry::RuntimeFactory;
 use super::tensor::{Data, DataType};
+use crate::model::model_discovery_service::ModelVersionId;
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
 
 /// A dummy implementation of the `InferenceProcessor` trait used for testing or development.
 pub struct FakeInferenceProcessor;
@@ -30,9 +37,49 @@ impl InferenceProcessor for FakeInferenceProcessor {
         InferenceResponse::Ok(output)
     }
 }
+
+/// Serves any artifact with a `FakeInferenceProcessor`, under the "fake" backend.
+pub struct FakeRuntimeFactory;
+
+impl RuntimeFactory for FakeRuntimeFactory {
+    fn backend(&self) -> &str {
+        "fake"
+    }
+
+    fn load(
+        &self,
+        version_id: &ModelVersionId,
+        _artifact_dir: &Path,
+    ) -> anyhow::Result<Arc<dyn InferenceRuntime>> {
+        let signature = ModelSignature {
+            inputs: Vec::new(),
+            outputs: vec![TensorMetadata {
+                name: "output_1".to_string(),
+                datatype: "FP32".to_string(),
+                shape: vec![1, 3],
+            }],
+        };
+        Ok(Arc::new(
+            ProcessorRuntime::new(version_id.model.0.clone(), FakeInferenceProcessor)
+                .with_platform(self.backend())
+                .with_signature(signature),
+        ))
+    }
+
+    /// Fake runtimes run anywhere; the device is ignored.
+    fn load_on(
+        &self,
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+        _device: Device,
+    ) -> anyhow::Result<Arc<dyn InferenceRuntime>> {
+        self.load(version_id, artifact_dir)
+    }
+}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::priority::Priority;
 
     #[test]
     fn fake_inference_processor_returns_expected_response() {
@@ -47,6 +94,11 @@ mod tests {
                 ("top_p".to_string(), InferParameter::Double(0.9)),
             ])),
             outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
         };
 
         let response = processor.process(dummy_request);
@@ -57,6 +109,7 @@ mod tests {
                 assert_eq!(output.shape, vec![1, 3]);
                 match output.data {
                     Data::VFLOAT(values) => assert_eq!(values, vec![0.1, 0.5, 0.4]),
+                    _ => panic!("Expected numbers"),
                 }
             }
             _ => panic!("Expected InferenceResponse::Ok variant"),
@@ -73,6 +126,11 @@ mod tests {
             id: "req_002".to_string(),
             parameters: None,
             outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
         };
 
         let response = processor.process(request);
@@ -1,5 +1,12 @@
+use super::sampling::SamplingOptions;
 use super::tensor::{Data, DataShape, DataType};
+use crate::model::priority::Priority;
+use crate::timeline::Timeline;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InferParameter {
     Bool(bool),
     Int64(i64),
@@ -7,19 +14,38 @@ pub enum InferParameter {
     String(String),
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum InferenceResponse {
     Ok(InferenceOutput),
     Error(InferenceError),
+    /// The deadline of the request passed before it was answered.
+    DeadlineExceeded(InferenceError),
 }
 
+/// Encoded with `api::codec`; the debug timeline is not part of the encoding.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct InferenceRequest {
     pub model_name: String,
     pub model_version: Option<String>,
     pub id: String,
     pub parameters: Option<HashMap<String, InferParameter>>,
     pub outputs: Option<Vec<InferenceOutput>>,
+    /// Debug timeline, for requests that opted into collecting one.
+    #[serde(skip)]
+    pub timeline: Option<Arc<Timeline>>,
+    /// Class deciding the order in which buffered requests are dispatched.
+    pub priority: Priority,
+    /// Past this instant the request is answered with `DeadlineExceeded` instead of run.
+    #[serde(with = "super::codec::wall_clock")]
+    pub deadline: Option<Instant>,
+    /// Tenant whose error budget the outcome counts against, see `tenants`.
+    pub tenant: Option<String>,
+    /// Generation settings read from `parameters`, see `api::sampling`.
+    #[serde(default)]
+    pub sampling: SamplingOptions,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InferenceOutput {
     pub name: String,
     pub shape: DataShape,
@@ -28,6 +54,7 @@ pub struct InferenceOutput {
     pub data: Data,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct InferenceError {
     pub error: String,
 }
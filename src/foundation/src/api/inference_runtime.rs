@@ -0,0 +1,142 @@
+use super::inference::{InferenceProcessor, InferenceRequest, InferenceResponse};
+use super::model_metadata::ModelSignature;
+use super::transfer::DeviceStaging;
+use async_trait::async_trait;
+use std::any::Any;
+
+/// A loaded model artifact able to execute inference requests.
+///
+/// One runtime is registered per model version, so two versions of the same
+/// model can be backed by different artifacts (or different backends).
+#[async_trait]
+pub trait InferenceRuntime: Send + Sync {
+    /// Name of the model served by this runtime.
+    fn model_id(&self) -> &str;
+
+    /// Backend executing the model, e.g. "onnx".
+    fn platform(&self) -> Option<&str> {
+        None
+    }
+
+    /// Input and output tensors read from the loaded artifact, if the backend exposes them.
+    fn signature(&self) -> Option<ModelSignature> {
+        None
+    }
+
+    /// Split execution of batches around host/device transfers, for backends running on a
+    /// GPU, see `api::transfer`.
+    fn staging(&self) -> Option<&dyn DeviceStaging> {
+        None
+    }
+
+    async fn process_single(&self, request: InferenceRequest) -> InferenceResponse;
+
+    /// Processes a batch of requests, returning one response per request in the same order.
+    async fn process_batch(&self, requests: Vec<InferenceRequest>) -> Vec<InferenceResponse> {
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in requests {
+            responses.push(self.process_single(request).await);
+        }
+        responses
+    }
+}
+
+/// Text of a panic caught while a runtime was running.
+pub fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Adapts a synchronous `InferenceProcessor` into an `InferenceRuntime`.
+pub struct ProcessorRuntime<P> {
+    model_id: String,
+    processor: P,
+    platform: Option<String>,
+    signature: Option<ModelSignature>,
+}
+
+impl<P: InferenceProcessor + Send + Sync> ProcessorRuntime<P> {
+    pub fn new(model_id: impl Into<String>, processor: P) -> Self {
+        Self {
+            model_id: model_id.into(),
+            processor,
+            platform: None,
+            signature: None,
+        }
+    }
+
+    pub fn with_platform(mut self, platform: impl Into<String>) -> Self {
+        self.platform = Some(platform.into());
+        self
+    }
+
+    pub fn with_signature(mut self, signature: ModelSignature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+}
+
+#[async_trait]
+impl<P: InferenceProcessor + Send + Sync> InferenceRuntime for ProcessorRuntime<P> {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn platform(&self) -> Option<&str> {
+        self.platform.as_deref()
+    }
+
+    fn signature(&self) -> Option<ModelSignature> {
+        self.signature.clone()
+    }
+
+    async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+        self.processor.process(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeInferenceProcessor;
+    use crate::api::inference::InferParameter;
+    use crate::model::priority::Priority;
+    use std::collections::HashMap;
+
+    fn request(id: &str, with_parameters: bool) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "test_model".to_string(),
+            model_version: None,
+            id: id.to_string(),
+            parameters: with_parameters
+                .then(|| HashMap::from([("k".to_string(), InferParameter::Bool(true))])),
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processor_runtime_reports_model_id() {
+        let runtime = ProcessorRuntime::new("test_model", FakeInferenceProcessor);
+        assert_eq!(runtime.model_id(), "test_model");
+    }
+
+    #[tokio::test]
+    async fn test_process_batch_preserves_request_order() {
+        let runtime = ProcessorRuntime::new("test_model", FakeInferenceProcessor);
+        let responses = runtime
+            .process_batch(vec![request("a", true), request("b", false)])
+            .await;
+
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(responses[0], InferenceResponse::Ok(_)));
+        assert!(matches!(responses[1], InferenceResponse::Error(_)));
+    }
+}
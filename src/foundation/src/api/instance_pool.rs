@@ -0,0 +1,426 @@
+/* Pools of runtime instances.
+
+A model configured with several instances (`instance_count`, or the counts of
+its `instance_group` entries) loads its artifact once per instance, e.g. one
+ONNX session per group of cores. The pool is registered as the runtime of the
+model version and hands every call to the instance with the fewest calls in
+flight, so the batches and requests the scheduler runs concurrently (up to
+one per instance) execute on separate instances instead of contending for one.
+
+A single instance can be restarted, e.g. after a CUDA error left it unusable,
+without touching the others: it stops receiving calls, the calls running on it
+are given time to complete, its runtime is loaded again and it rejoins the
+pool. Meanwhile calls go to the other instances, or wait for the instance to
+rejoin when it is the only one.
+*/
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use super::inference::{InferenceError, InferenceRequest, InferenceResponse};
+use super::inference_runtime::InferenceRuntime;
+use super::model_metadata::ModelSignature;
+
+/// Loads one more runtime of the pooled model, to replace a restarted instance.
+pub type InstanceLoader = Arc<dyn Fn() -> Result<Arc<dyn InferenceRuntime>> + Send + Sync>;
+
+struct Instance {
+    runtime: RwLock<Arc<dyn InferenceRuntime>>,
+    in_flight: AtomicUsize,
+    /// Set while the instance is restarted; it receives no calls meanwhile.
+    restarting: AtomicBool,
+    restarts: AtomicU64,
+}
+
+/// Releases an instance when the call running on it completes or is cancelled.
+struct Checkout<'a> {
+    instance: &'a Instance,
+    released: &'a Notify,
+}
+
+impl Drop for Checkout<'_> {
+    fn drop(&mut self) {
+        self.instance.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.released.notify_waiters();
+    }
+}
+
+impl Checkout<'_> {
+    fn runtime(&self) -> Arc<dyn InferenceRuntime> {
+        self.instance.runtime.read().unwrap().clone()
+    }
+}
+
+/// State of an instance, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstanceStatus {
+    pub instance: usize,
+    pub in_flight: usize,
+    pub restarting: bool,
+    /// Successful restarts since the version was loaded.
+    pub restarts: u64,
+}
+
+/// Outcome of an instance restart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceRestart {
+    pub instance: usize,
+    /// False when calls were still running on the instance at the drain timeout; they
+    /// complete on the previous runtime.
+    pub drained: bool,
+    pub duration_ms: f64,
+}
+
+pub struct InstancePool {
+    /// Model and platform of the instances, which reloading leaves unchanged.
+    model_id: String,
+    platform: Option<String>,
+    instances: Vec<Instance>,
+    loader: Option<InstanceLoader>,
+    /// Notified whenever a call completes.
+    released: Notify,
+    /// Notified whenever a restarted instance rejoins the pool.
+    rejoined: Notify,
+}
+
+impl InstancePool {
+    /// Pools `runtimes`, which must all serve the same model.
+    pub fn new(runtimes: Vec<Arc<dyn InferenceRuntime>>) -> Result<Self> {
+        let Some(first) = runtimes.first() else {
+            return Err(anyhow!("An instance pool needs at least one runtime"));
+        };
+        if let Some(other) = runtimes
+            .iter()
+            .find(|runtime| runtime.model_id() != first.model_id())
+        {
+            return Err(anyhow!(
+                "Instances of model '{}' include a runtime of model '{}'",
+                first.model_id(),
+                other.model_id()
+            ));
+        }
+        Ok(Self {
+            model_id: first.model_id().to_string(),
+            platform: first.platform().map(String::from),
+            instances: runtimes
+                .into_iter()
+                .map(|runtime| Instance {
+                    runtime: RwLock::new(runtime),
+                    in_flight: AtomicUsize::new(0),
+                    restarting: AtomicBool::new(false),
+                    restarts: AtomicU64::new(0),
+                })
+                .collect(),
+            loader: None,
+            released: Notify::new(),
+            rejoined: Notify::new(),
+        })
+    }
+
+    /// Lets instances be restarted, loading their new runtime with `loader`.
+    pub fn with_loader(mut self, loader: InstanceLoader) -> Self {
+        self.loader = Some(loader);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Calls in flight per instance.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.instances
+            .iter()
+            .map(|instance| instance.in_flight.load(Ordering::Acquire))
+            .collect()
+    }
+
+    pub fn status(&self) -> Vec<InstanceStatus> {
+        self.instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| InstanceStatus {
+                instance: index,
+                in_flight: instance.in_flight.load(Ordering::Acquire),
+                restarting: instance.restarting.load(Ordering::Acquire),
+                restarts: instance.restarts.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// The least busy instance that is not restarting, the first one on ties. Waits for an
+    /// instance to rejoin when every instance is restarting.
+    async fn checkout(&self) -> Checkout<'_> {
+        loop {
+            // Registered before looking, so an instance rejoining in between is not missed.
+            let mut rejoined = std::pin::pin!(self.rejoined.notified());
+            rejoined.as_mut().enable();
+            if let Some(instance) = self
+                .instances
+                .iter()
+                .filter(|instance| !instance.restarting.load(Ordering::Acquire))
+                .min_by_key(|instance| instance.in_flight.load(Ordering::Acquire))
+            {
+                instance.in_flight.fetch_add(1, Ordering::AcqRel);
+                let checkout = Checkout {
+                    instance,
+                    released: &self.released,
+                };
+                // A restart starting in between waits for this call otherwise.
+                if !instance.restarting.load(Ordering::Acquire) {
+                    return checkout;
+                }
+                continue;
+            }
+            rejoined.await;
+        }
+    }
+
+    /// Instance `index`, once it is not restarting.
+    async fn checkout_instance(&self, index: usize) -> Option<Checkout<'_>> {
+        let instance = self.instances.get(index)?;
+        loop {
+            let mut rejoined = std::pin::pin!(self.rejoined.notified());
+            rejoined.as_mut().enable();
+            if !instance.restarting.load(Ordering::Acquire) {
+                instance.in_flight.fetch_add(1, Ordering::AcqRel);
+                let checkout = Checkout {
+                    instance,
+                    released: &self.released,
+                };
+                // As in `checkout`, a restart may have started in between.
+                if !instance.restarting.load(Ordering::Acquire) {
+                    return Some(checkout);
+                }
+                continue;
+            }
+            rejoined.await;
+        }
+    }
+
+    /// Runs `request` on instance `index` rather than the least busy one, waiting for the
+    /// instance to rejoin the pool if it is restarting.
+    pub async fn process_on(&self, index: usize, request: InferenceRequest) -> InferenceResponse {
+        match self.checkout_instance(index).await {
+            Some(checkout) => checkout.runtime().process_single(request).await,
+            None => InferenceResponse::Error(InferenceError {
+                error: format!("Model '{}' has no instance {}", self.model_id, index),
+            }),
+        }
+    }
+
+    /// Restarts instance `index`: it stops receiving calls, the calls running on it get up
+    /// to `drain_timeout` to complete, then its runtime is loaded again and it rejoins the
+    /// pool. When loading fails the instance rejoins with its previous runtime.
+    pub async fn restart(&self, index: usize, drain_timeout: Duration) -> Result<InstanceRestart> {
+        let loader = self
+            .loader
+            .clone()
+            .ok_or_else(|| anyhow!("Instances of model '{}' cannot be reloaded", self.model_id))?;
+        let instance = self.instances.get(index).ok_or_else(|| {
+            anyhow!(
+                "Model '{}' has no instance {} (it has {})",
+                self.model_id,
+                index,
+                self.len()
+            )
+        })?;
+        if instance.restarting.swap(true, Ordering::AcqRel) {
+            return Err(anyhow!("Instance {} is already restarting", index));
+        }
+        let started = Instant::now();
+        let drained = tokio::time::timeout(drain_timeout, async {
+            loop {
+                let mut released = std::pin::pin!(self.released.notified());
+                released.as_mut().enable();
+                if instance.in_flight.load(Ordering::Acquire) == 0 {
+                    break;
+                }
+                released.await;
+            }
+        })
+        .await
+        .is_ok();
+
+        let loaded = tokio::task::spawn_blocking(move || loader())
+            .await
+            .map_err(|e| anyhow!("Reloading instance {} panicked: {}", index, e))
+            .and_then(|loaded| loaded);
+        if let Ok(runtime) = &loaded {
+            *instance.runtime.write().unwrap() = runtime.clone();
+            instance.restarts.fetch_add(1, Ordering::Relaxed);
+        }
+        instance.restarting.store(false, Ordering::Release);
+        self.rejoined.notify_waiters();
+        loaded?;
+        Ok(InstanceRestart {
+            instance: index,
+            drained,
+            duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        })
+    }
+
+    fn first(&self) -> Arc<dyn InferenceRuntime> {
+        self.instances[0].runtime.read().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl InferenceRuntime for InstancePool {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn platform(&self) -> Option<&str> {
+        self.platform.as_deref()
+    }
+
+    fn signature(&self) -> Option<ModelSignature> {
+        self.first().signature()
+    }
+
+    async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+        let checkout = self.checkout().await;
+        checkout.runtime().process_single(request).await
+    }
+
+    async fn process_batch(&self, requests: Vec<InferenceRequest>) -> Vec<InferenceResponse> {
+        let checkout = self.checkout().await;
+        checkout.runtime().process_batch(requests).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::InferenceOutput;
+    use crate::api::tensor::{Data, DataType};
+    use crate::model::priority::Priority;
+    use std::time::Duration;
+    use tokio::sync::Mutex;
+
+    /// Answers with its own number after holding the call for a while.
+    struct NumberedRuntime(usize);
+
+    #[async_trait]
+    impl InferenceRuntime for NumberedRuntime {
+        fn model_id(&self) -> &str {
+            "m"
+        }
+
+        async fn process_single(&self, _request: InferenceRequest) -> InferenceResponse {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            InferenceResponse::Ok(InferenceOutput {
+                name: self.0.to_string(),
+                shape: vec![1],
+                datatype: DataType::VFLOAT,
+                parameters: None,
+                data: Data::VFLOAT(vec![0.0]),
+            })
+        }
+    }
+
+    fn request() -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: None,
+            id: "r".to_string(),
+            parameters: None,
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_calls_run_on_separate_instances() {
+        let runtimes: Vec<Arc<dyn InferenceRuntime>> = (0..3)
+            .map(|n| Arc::new(NumberedRuntime(n)) as Arc<dyn InferenceRuntime>)
+            .collect();
+        let pool = Arc::new(InstancePool::new(runtimes).unwrap());
+        let served = Arc::new(Mutex::new(Vec::new()));
+
+        let calls: Vec<_> = (0..3)
+            .map(|_| {
+                let (pool, served) = (pool.clone(), served.clone());
+                tokio::spawn(async move {
+                    if let InferenceResponse::Ok(output) = pool.process_single(request()).await {
+                        served.lock().await.push(output.name);
+                    }
+                })
+            })
+            .collect();
+        for call in calls {
+            call.await.unwrap();
+        }
+
+        let mut served = served.lock().await.clone();
+        served.sort();
+        assert_eq!(served, vec!["0", "1", "2"]);
+        assert_eq!(pool.in_flight(), vec![0, 0, 0]);
+        assert!(InstancePool::new(Vec::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restarted_instance_is_drained_and_reloaded() {
+        let loads = Arc::new(AtomicUsize::new(10));
+        let loader: InstanceLoader = {
+            let loads = loads.clone();
+            Arc::new(move || {
+                Ok(
+                    Arc::new(NumberedRuntime(loads.fetch_add(1, Ordering::Relaxed)))
+                        as Arc<dyn InferenceRuntime>,
+                )
+            })
+        };
+        let pool = Arc::new(
+            InstancePool::new(vec![loader().unwrap()])
+                .unwrap()
+                .with_loader(loader),
+        );
+        assert!(pool.restart(1, Duration::ZERO).await.is_err());
+
+        // The running call completes before the instance reloads; the next one waits for it.
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.process_single(request()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let restart = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.restart(0, Duration::from_secs(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(pool.status()[0].restarting);
+        let waiting = pool.process_single(request()).await;
+
+        let name = |response: InferenceResponse| match response {
+            InferenceResponse::Ok(output) => output.name,
+            _ => String::new(),
+        };
+        assert_eq!(name(running.await.unwrap()), "10");
+        assert_eq!(name(waiting), "11");
+        assert!(restart.await.unwrap().unwrap().drained);
+        let status = &pool.status()[0];
+        assert_eq!((status.restarting, status.restarts), (false, 1));
+        assert!(
+            InstancePool::new(vec![Arc::new(NumberedRuntime(0))])
+                .unwrap()
+                .restart(0, Duration::ZERO)
+                .await
+                .is_err()
+        );
+    }
+}
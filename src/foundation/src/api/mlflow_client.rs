@@ -3,6 +3,10 @@ use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::model::object_store::{ObjectEntry, ObjectStoreClient};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MLFlowModel {
@@ -41,11 +45,30 @@ struct GetModelVersionsResponse {
     next_page_token: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MLFlowArtifact {
+    pub path: String,
+    #[serde(default)]
+    pub is_dir: bool,
+    pub file_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ListArtifactsResponse {
+    #[serde(default)]
+    files: Vec<MLFlowArtifact>,
+}
+
 #[async_trait]
 pub trait MLFlowClientTrait: Send + Sync {
     async fn list_models(&self) -> Result<Vec<MLFlowModel>>;
     async fn get_model_versions(&self, model_name: &str) -> Result<Vec<MLFlowModelVersion>>;
     async fn get_model(&self, name: &str) -> Result<Option<MLFlowModel>>;
+    /// Resolves the storage URI holding the artifacts of a model version.
+    async fn get_download_uri(&self, model_name: &str, version: &str) -> Result<String>;
+    /// Lists the direct children of `path` in the artifact proxy (`mlflow-artifacts:/`).
+    async fn list_artifacts(&self, path: &str) -> Result<Vec<MLFlowArtifact>>;
+    async fn download_artifact(&self, path: &str) -> Result<Vec<u8>>;
 }
 
 #[derive(Debug, Clone)]
@@ -65,12 +88,23 @@ impl MLFlowClient {
     }
 
     fn build_request(&self, endpoint: &str) -> reqwest::RequestBuilder {
-        let url = format!(
+        self.get(&format!(
             "{}/api/2.0/mlflow/{}",
             self.base_url.trim_end_matches('/'),
             endpoint
-        );
-        let mut request = self.client.get(&url);
+        ))
+    }
+
+    fn build_artifacts_request(&self, endpoint: &str) -> reqwest::RequestBuilder {
+        self.get(&format!(
+            "{}/api/2.0/mlflow-artifacts/{}",
+            self.base_url.trim_end_matches('/'),
+            endpoint
+        ))
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        let mut request = self.client.get(url);
 
         if let Some(token) = &self.api_token {
             request = request.header("Authorization", format!("Bearer {}", token));
@@ -163,8 +197,134 @@ impl MLFlowClientTrait for MLFlowClient {
             ))
         }
     }
+
+    async fn get_download_uri(&self, model_name: &str, version: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct GetDownloadUriResponse {
+            artifact_uri: String,
+        }
+
+        let endpoint = format!(
+            "model-versions/get-download-uri?name={}&version={}",
+            urlencoding::encode(model_name),
+            urlencoding::encode(version)
+        );
+        let response = check_status(self.build_request(&endpoint).send().await?).await?;
+        let response_data: GetDownloadUriResponse = response.json().await?;
+        Ok(response_data.artifact_uri)
+    }
+
+    async fn list_artifacts(&self, path: &str) -> Result<Vec<MLFlowArtifact>> {
+        let endpoint = format!("artifacts?path={}", urlencoding::encode(path));
+        let response = check_status(self.build_artifacts_request(&endpoint).send().await?).await?;
+        let response_data: ListArtifactsResponse = response.json().await?;
+        Ok(response_data.files)
+    }
+
+    async fn download_artifact(&self, path: &str) -> Result<Vec<u8>> {
+        let encoded_path = path
+            .split('/')
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        let endpoint = format!("artifacts/{}", encoded_path);
+        let response = check_status(self.build_artifacts_request(&endpoint).send().await?).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(anyhow!(
+            "MLFlow API request failed with status: {}, body: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        ))
+    }
+}
+
+/// Exposes the MLflow artifact proxy as an object store, so artifacts logged to
+/// `mlflow-artifacts:/` can be mirrored like any other remote model.
+pub struct MLFlowArtifactStore {
+    client: Arc<dyn MLFlowClientTrait>,
+}
+
+impl MLFlowArtifactStore {
+    pub fn new(client: Arc<dyn MLFlowClientTrait>) -> Self {
+        Self { client }
+    }
 }
 
+#[async_trait]
+impl ObjectStoreClient for MLFlowArtifactStore {
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectEntry>> {
+        let mut entries = Vec::new();
+        let mut pending = vec![prefix.trim_end_matches('/').to_string()];
+
+        while let Some(directory) = pending.pop() {
+            for artifact in self.client.list_artifacts(&directory).await? {
+                // The proxy returns names relative to the listed directory.
+                let name = artifact.path.rsplit('/').next().unwrap_or(&artifact.path);
+                let key = if directory.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}/{}", directory, name)
+                };
+                if artifact.is_dir {
+                    pending.push(key);
+                } else {
+                    entries.push(ObjectEntry {
+                        key,
+                        size: artifact.file_size.unwrap_or_default(),
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(entries)
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Vec<u8>> {
+        self.client.download_artifact(key).await
+    }
+}
+
+/// The `MLmodel` descriptor stored at the root of every MLflow model artifact.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MLModel {
+    #[serde(default)]
+    pub flavors: serde_yaml::Mapping,
+}
+
+impl MLModel {
+    pub fn from_dir(artifact_dir: &Path) -> Result<Self> {
+        let path = artifact_dir.join("MLmodel");
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(contents)?)
+    }
+
+    /// Flavor names in preference order: native flavors as declared, `python_function` last.
+    pub fn flavors(&self) -> Vec<String> {
+        let (mut native, generic): (Vec<String>, Vec<String>) = self
+            .flavors
+            .keys()
+            .filter_map(|key| key.as_str().map(str::to_string))
+            .partition(|flavor| flavor != PYTHON_FUNCTION_FLAVOR);
+        native.extend(generic);
+        native
+    }
+}
+
+const PYTHON_FUNCTION_FLAVOR: &str = "python_function";
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,6 +332,7 @@ mod tests {
     struct MockMLFlowClient {
         models: Vec<MLFlowModel>,
         model_versions: HashMap<String, Vec<MLFlowModelVersion>>,
+        artifacts: HashMap<String, Vec<u8>>,
     }
 
     impl MockMLFlowClient {
@@ -204,6 +365,16 @@ mod tests {
                     tags: Some(HashMap::new()),
                 }],
                 model_versions,
+                artifacts: HashMap::from([
+                    (
+                        "1/run123/artifacts/model/MLmodel".to_string(),
+                        b"flavors: {}".to_vec(),
+                    ),
+                    (
+                        "1/run123/artifacts/model/data/model.onnx".to_string(),
+                        b"onnx".to_vec(),
+                    ),
+                ]),
             }
         }
     }
@@ -225,6 +396,43 @@ mod tests {
         async fn get_model(&self, name: &str) -> Result<Option<MLFlowModel>> {
             Ok(self.models.iter().find(|m| m.name == name).cloned())
         }
+
+        async fn get_download_uri(&self, model_name: &str, version: &str) -> Result<String> {
+            self.model_versions
+                .get(model_name)
+                .and_then(|versions| versions.iter().find(|v| v.version == version))
+                .and_then(|v| v.source.clone())
+                .ok_or_else(|| anyhow!("unknown model version"))
+        }
+
+        async fn list_artifacts(&self, path: &str) -> Result<Vec<MLFlowArtifact>> {
+            let prefix = format!("{}/", path);
+            let mut children: Vec<MLFlowArtifact> = Vec::new();
+            for (key, value) in &self.artifacts {
+                let Some(relative) = key.strip_prefix(&prefix) else {
+                    continue;
+                };
+                let (name, is_dir) = match relative.split_once('/') {
+                    Some((dir, _)) => (dir, true),
+                    None => (relative, false),
+                };
+                if children.iter().all(|child| child.path != name) {
+                    children.push(MLFlowArtifact {
+                        path: name.to_string(),
+                        is_dir,
+                        file_size: (!is_dir).then_some(value.len() as u64),
+                    });
+                }
+            }
+            Ok(children)
+        }
+
+        async fn download_artifact(&self, path: &str) -> Result<Vec<u8>> {
+            self.artifacts
+                .get(path)
+                .cloned()
+                .ok_or_else(|| anyhow!("missing artifact {}", path))
+        }
     }
 
     #[tokio::test]
@@ -274,6 +482,54 @@ mod tests {
         assert!(versions.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_mlflow_artifact_store_lists_recursively() {
+        let store = MLFlowArtifactStore::new(Arc::new(MockMLFlowClient::new()));
+        let entries = store
+            .list_objects("1/run123/artifacts/model/")
+            .await
+            .unwrap();
+
+        let keys: Vec<&str> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "1/run123/artifacts/model/MLmodel",
+                "1/run123/artifacts/model/data/model.onnx"
+            ]
+        );
+        assert_eq!(entries[1].size, 4);
+        assert_eq!(
+            store.get_object(&entries[1].key).await.unwrap(),
+            b"onnx".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_mlmodel_flavors_prefer_native() {
+        let mlmodel = MLModel::parse(
+            r#"
+artifact_path: model
+flavors:
+  python_function:
+    loader_module: mlflow.onnx
+    python_version: 3.11.0
+  onnx:
+    data: model.onnx
+    onnx_version: 1.15.0
+run_id: run123
+"#,
+        )
+        .unwrap();
+        assert_eq!(mlmodel.flavors(), vec!["onnx", "python_function"]);
+    }
+
+    #[test]
+    fn test_mlmodel_without_flavors() {
+        let mlmodel = MLModel::parse("artifact_path: model").unwrap();
+        assert!(mlmodel.flavors().is_empty());
+    }
+
     #[test]
     fn test_mlflow_client_creation() {
         let client = MLFlowClient::new(
@@ -1,4 +1,18 @@
+pub mod cast;
+pub mod codec;
+pub mod devices;
+pub mod error;
 pub mod fake;
 pub mod inference;
+pub mod inference_runtime;
+pub mod instance_pool;
 pub mod mlflow_client;
+pub mod model_metadata;
+pub mod runtime_registry;
+pub mod sampling;
+pub mod schema;
+pub mod shared_memory;
 pub mod tensor;
+pub mod tensor_view;
+pub mod transfer;
+pub mod validation;
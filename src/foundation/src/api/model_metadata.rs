@@ -0,0 +1,63 @@
+/* Metadata describing a served model to clients.
+
+The tensors of a model come from its configuration file when it declares
+them, otherwise from the signature reported by the loaded runtime (e.g. the
+graph inputs of an ONNX model). Shapes are the shapes clients send and
+receive, including the batch dimension when the model batches.
+*/
+
+use serde::Serialize;
+
+use crate::model::capabilities::Capabilities;
+use crate::model::model_config::{ModelConfig, TensorSpec};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TensorMetadata {
+    pub name: String,
+    pub datatype: String,
+    /// Dimensions as seen by clients; -1 marks a variable size.
+    pub shape: Vec<i64>,
+}
+
+/// Input and output tensors of a model.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModelSignature {
+    pub inputs: Vec<TensorMetadata>,
+    pub outputs: Vec<TensorMetadata>,
+}
+
+impl ModelSignature {
+    pub fn from_config(config: &ModelConfig) -> Self {
+        let tensors = |specs: &[TensorSpec]| {
+            specs
+                .iter()
+                .map(|spec| TensorMetadata {
+                    name: spec.name.clone(),
+                    datatype: spec.datatype.clone(),
+                    shape: config.client_shape(spec),
+                })
+                .collect()
+        };
+        Self {
+            inputs: tensors(&config.inputs),
+            outputs: tensors(&config.outputs),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty() && self.outputs.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelMetadata {
+    pub name: String,
+    /// Served versions, oldest first.
+    pub versions: Vec<String>,
+    /// Backend serving the model, empty when unknown.
+    pub platform: String,
+    pub inputs: Vec<TensorMetadata>,
+    pub outputs: Vec<TensorMetadata>,
+    /// Capabilities the model declares, see `model::capabilities`.
+    pub capabilities: Option<Capabilities>,
+}
@@ -0,0 +1,218 @@
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::devices::Device;
+use super::inference_runtime::InferenceRuntime;
+use super::instance_pool::{InstanceLoader, InstancePool};
+use crate::model::model_discovery_service::ModelVersionId;
+
+/// Creates runtimes for one backend (e.g. "onnx", "pytorch") from local artifacts.
+pub trait RuntimeFactory: Send + Sync {
+    /// Backend name, matched against MLflow flavors and model configuration backends.
+    fn backend(&self) -> &str;
+
+    fn load(
+        &self,
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+    ) -> Result<Arc<dyn InferenceRuntime>>;
+
+    /// Loads the artifact onto `device`. Backends that cannot choose a device only load
+    /// onto the CPU.
+    fn load_on(
+        &self,
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+        device: Device,
+    ) -> Result<Arc<dyn InferenceRuntime>> {
+        match device {
+            Device::Cpu => self.load(version_id, artifact_dir),
+            device => Err(anyhow!(
+                "Runtime backend '{}' cannot place {} on {}",
+                self.backend(),
+                version_id,
+                device
+            )),
+        }
+    }
+}
+
+/// Available runtime backends, keyed by backend name.
+#[derive(Default)]
+pub struct RuntimeRegistry {
+    factories: DashMap<String, Arc<dyn RuntimeFactory>>,
+}
+
+impl RuntimeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, factory: Arc<dyn RuntimeFactory>) {
+        self.factories
+            .insert(factory.backend().to_string(), factory);
+    }
+
+    pub fn get(&self, backend: &str) -> Option<Arc<dyn RuntimeFactory>> {
+        self.factories.get(backend).map(|factory| factory.clone())
+    }
+
+    pub fn backends(&self) -> Vec<String> {
+        let mut backends: Vec<String> = self
+            .factories
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        backends.sort();
+        backends
+    }
+
+    /// Loads the artifact with the first of `backends` (in preference order) that is available.
+    pub fn load(
+        &self,
+        backends: &[String],
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+    ) -> Result<Arc<dyn InferenceRuntime>> {
+        self.load_on(backends, version_id, artifact_dir, Device::Cpu)
+    }
+
+    /// Loads the artifact onto `device` with the first of `backends` that is available.
+    pub fn load_on(
+        &self,
+        backends: &[String],
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+        device: Device,
+    ) -> Result<Arc<dyn InferenceRuntime>> {
+        self.factory(backends, version_id)?
+            .load_on(version_id, artifact_dir, device)
+    }
+
+    /// Loads the artifact `instances` times onto `device` into a pool that can reload any of
+    /// its instances later with the same backend.
+    pub fn load_instances(
+        &self,
+        backends: &[String],
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+        instances: usize,
+        device: Device,
+    ) -> Result<Arc<InstancePool>> {
+        let factory = self.factory(backends, version_id)?;
+        load_pool(factory, version_id, artifact_dir, instances, device)
+    }
+
+    /// Defers `load_instances` until the returned loader is called, e.g. once memory is
+    /// available for the pool. Fails now when none of `backends` is available; the name
+    /// of the backend that will load the artifact is returned with the loader.
+    pub fn deferred_instances(
+        &self,
+        backends: &[String],
+        version_id: &ModelVersionId,
+        artifact_dir: &Path,
+        instances: usize,
+        device: Device,
+    ) -> Result<(String, InstanceLoader)> {
+        let factory = self.factory(backends, version_id)?;
+        let backend = factory.backend().to_string();
+        let (version_id, artifact_dir) = (version_id.clone(), artifact_dir.to_path_buf());
+        let loader: InstanceLoader = Arc::new(move || {
+            load_pool(
+                factory.clone(),
+                &version_id,
+                &artifact_dir,
+                instances,
+                device,
+            )
+            .map(|pool| pool as Arc<dyn InferenceRuntime>)
+        });
+        Ok((backend, loader))
+    }
+
+    /// The first of `backends` that is available.
+    fn factory(
+        &self,
+        backends: &[String],
+        version_id: &ModelVersionId,
+    ) -> Result<Arc<dyn RuntimeFactory>> {
+        backends
+            .iter()
+            .find_map(|backend| self.get(backend))
+            .ok_or_else(|| {
+                anyhow!(
+                    "No runtime backend available for {} (artifact backends: {:?}, available: {:?})",
+                    version_id,
+                    backends,
+                    self.backends()
+                )
+            })
+    }
+}
+
+/// Loads the artifact `instances` times onto `device` with `factory` into a pool.
+fn load_pool(
+    factory: Arc<dyn RuntimeFactory>,
+    version_id: &ModelVersionId,
+    artifact_dir: &Path,
+    instances: usize,
+    device: Device,
+) -> Result<Arc<InstancePool>> {
+    let (version_id, artifact_dir) = (version_id.clone(), artifact_dir.to_path_buf());
+    let loader: InstanceLoader =
+        Arc::new(move || factory.load_on(&version_id, &artifact_dir, device));
+    let runtimes = (0..instances.max(1))
+        .map(|_| loader())
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(InstancePool::new(runtimes)?.with_loader(loader)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeRuntimeFactory;
+
+    #[test]
+    fn test_load_uses_first_available_backend() {
+        let registry = RuntimeRegistry::new();
+        registry.register(Arc::new(FakeRuntimeFactory));
+
+        let version_id = ModelVersionId::new("m", "1");
+        let runtime = registry
+            .load(
+                &["onnx".to_string(), "fake".to_string()],
+                &version_id,
+                Path::new("/models/m/1"),
+            )
+            .unwrap();
+        assert_eq!(runtime.model_id(), "m");
+
+        let pooled = registry
+            .load_instances(
+                &["fake".to_string()],
+                &version_id,
+                Path::new("/models/m/1"),
+                4,
+                Device::Cuda(0),
+            )
+            .unwrap();
+        assert_eq!(pooled.platform(), Some("fake"));
+        assert_eq!(pooled.len(), 4);
+    }
+
+    #[test]
+    fn test_load_without_matching_backend() {
+        let registry = RuntimeRegistry::new();
+        registry.register(Arc::new(FakeRuntimeFactory));
+
+        let version_id = ModelVersionId::new("m", "1");
+        assert!(
+            registry
+                .load(&["onnx".to_string()], &version_id, Path::new("/models/m/1"))
+                .is_err()
+        );
+        assert_eq!(registry.backends(), vec!["fake"]);
+    }
+}
@@ -0,0 +1,272 @@
+/* Sampling options of generation requests.
+
+Language models read their generation settings from the request parameters,
+named as in the OpenAI API. They are read once, when the request is received,
+into the `sampling` of the request, so runtimes use typed and checked values
+rather than each parsing the parameters:
+
+| Parameter            | Range                      |
+|----------------------|----------------------------|
+| `max_tokens`         | at least 1                 |
+| `temperature`        | 0 to 2                     |
+| `top_p`              | 0 to 1                     |
+| `top_k`              | at least 1                 |
+| `presence_penalty`   | -2 to 2                    |
+| `frequency_penalty`  | -2 to 2                    |
+| `repetition_penalty` | above 0                    |
+| `stop`               | 1 to 4 non-empty sequences |
+| `seed`               | any integer                |
+
+`stop` is one sequence, or the JSON array of up to four of them. A parameter
+out of its range, or of the wrong type, refuses the request with an
+`invalid_value` error naming it. Absent parameters are left to the runtime.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use super::inference::InferParameter;
+
+/// Most stop sequences a request may give, as OpenAI allows.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Generation settings of a request, None for those it leaves to the runtime.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingOptions {
+    pub max_tokens: Option<u64>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<u64>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    pub repetition_penalty: Option<f64>,
+    /// Sequences ending the generation, not included in the text.
+    pub stop: Vec<String>,
+    pub seed: Option<i64>,
+}
+
+/// A request refused for a sampling parameter out of its range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingRefusal {
+    pub parameter: &'static str,
+    pub reason: String,
+}
+
+impl SamplingRefusal {
+    /// OpenAI error code of the refusal.
+    pub fn code(&self) -> &'static str {
+        "invalid_value"
+    }
+
+    /// Request parameter to change.
+    pub fn param(&self) -> &'static str {
+        self.parameter
+    }
+}
+
+impl fmt::Display for SamplingRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid value for {}: {}", self.parameter, self.reason)
+    }
+}
+
+impl std::error::Error for SamplingRefusal {}
+
+fn refusal(parameter: &'static str, reason: impl ToString) -> SamplingRefusal {
+    SamplingRefusal {
+        parameter,
+        reason: reason.to_string(),
+    }
+}
+
+fn number(
+    parameters: &HashMap<String, InferParameter>,
+    parameter: &'static str,
+    valid: impl Fn(f64) -> bool,
+    range: &str,
+) -> Result<Option<f64>, SamplingRefusal> {
+    let value = match parameters.get(parameter) {
+        None => return Ok(None),
+        Some(InferParameter::Double(value)) => *value,
+        Some(InferParameter::Int64(value)) => *value as f64,
+        Some(_) => return Err(refusal(parameter, "expected a number")),
+    };
+    if !valid(value) {
+        return Err(refusal(parameter, format!("{} is not {}", value, range)));
+    }
+    Ok(Some(value))
+}
+
+fn count(
+    parameters: &HashMap<String, InferParameter>,
+    parameter: &'static str,
+) -> Result<Option<u64>, SamplingRefusal> {
+    match parameters.get(parameter) {
+        None => Ok(None),
+        Some(InferParameter::Int64(value)) if *value >= 1 => Ok(Some(*value as u64)),
+        Some(InferParameter::Int64(value)) => {
+            Err(refusal(parameter, format!("{} is not at least 1", value)))
+        }
+        Some(_) => Err(refusal(parameter, "expected an integer")),
+    }
+}
+
+fn stop_sequences(
+    parameters: &HashMap<String, InferParameter>,
+) -> Result<Vec<String>, SamplingRefusal> {
+    let stop = match parameters.get("stop") {
+        None => return Ok(Vec::new()),
+        Some(InferParameter::String(stop)) => stop,
+        Some(_) => return Err(refusal("stop", "expected a string or a list of strings")),
+    };
+    let sequences = if stop.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<String>>(stop)
+            .map_err(|_| refusal("stop", "expected a string or a list of strings"))?
+    } else {
+        vec![stop.clone()]
+    };
+    if sequences.len() > MAX_STOP_SEQUENCES {
+        return Err(refusal(
+            "stop",
+            format!(
+                "{} sequences given, at most {} are allowed",
+                sequences.len(),
+                MAX_STOP_SEQUENCES
+            ),
+        ));
+    }
+    if sequences.iter().any(String::is_empty) {
+        return Err(refusal("stop", "sequences must not be empty"));
+    }
+    Ok(sequences)
+}
+
+impl SamplingOptions {
+    /// Reads and checks the sampling options of a request's parameters.
+    pub fn from_parameters(
+        parameters: Option<&HashMap<String, InferParameter>>,
+    ) -> Result<Self, SamplingRefusal> {
+        let Some(parameters) = parameters else {
+            return Ok(Self::default());
+        };
+        let penalty = |value: f64| (-2.0..=2.0).contains(&value);
+        Ok(Self {
+            max_tokens: count(parameters, "max_tokens")?,
+            temperature: number(
+                parameters,
+                "temperature",
+                |value| (0.0..=2.0).contains(&value),
+                "between 0 and 2",
+            )?,
+            top_p: number(
+                parameters,
+                "top_p",
+                |value| (0.0..=1.0).contains(&value),
+                "between 0 and 1",
+            )?,
+            top_k: count(parameters, "top_k")?,
+            presence_penalty: number(parameters, "presence_penalty", penalty, "between -2 and 2")?,
+            frequency_penalty: number(
+                parameters,
+                "frequency_penalty",
+                penalty,
+                "between -2 and 2",
+            )?,
+            repetition_penalty: number(
+                parameters,
+                "repetition_penalty",
+                |value| value > 0.0,
+                "above 0",
+            )?,
+            stop: stop_sequences(parameters)?,
+            seed: match parameters.get("seed") {
+                None => None,
+                Some(InferParameter::Int64(seed)) => Some(*seed),
+                Some(_) => return Err(refusal("seed", "expected an integer")),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(values: &[(&str, InferParameter)]) -> HashMap<String, InferParameter> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sampling_options_are_read_and_checked() {
+        let options = SamplingOptions::from_parameters(Some(&parameters(&[
+            ("prompt", InferParameter::String("Once".to_string())),
+            ("max_tokens", InferParameter::Int64(32)),
+            ("temperature", InferParameter::Int64(1)),
+            ("top_p", InferParameter::Double(0.9)),
+            ("presence_penalty", InferParameter::Double(-0.5)),
+            (
+                "stop",
+                InferParameter::String("[\"\\n\", \"END\"]".to_string()),
+            ),
+            ("seed", InferParameter::Int64(7)),
+        ])))
+        .unwrap();
+        assert_eq!(options.max_tokens, Some(32));
+        assert_eq!(options.temperature, Some(1.0));
+        assert_eq!(options.top_p, Some(0.9));
+        assert_eq!(options.presence_penalty, Some(-0.5));
+        assert_eq!(options.frequency_penalty, None);
+        assert_eq!(options.stop, vec!["\n".to_string(), "END".to_string()]);
+        assert_eq!(options.seed, Some(7));
+
+        let options = SamplingOptions::from_parameters(Some(&parameters(&[(
+            "stop",
+            InferParameter::String("###".to_string()),
+        )])))
+        .unwrap();
+        assert_eq!(options.stop, vec!["###".to_string()]);
+        assert_eq!(
+            SamplingOptions::from_parameters(None).unwrap(),
+            SamplingOptions::default()
+        );
+
+        let refused = |values: &[(&str, InferParameter)]| {
+            SamplingOptions::from_parameters(Some(&parameters(values))).unwrap_err()
+        };
+        let refusal = refused(&[("temperature", InferParameter::Double(2.5))]);
+        assert_eq!(refusal.param(), "temperature");
+        assert_eq!(refusal.code(), "invalid_value");
+        assert_eq!(
+            refusal.to_string(),
+            "Invalid value for temperature: 2.5 is not between 0 and 2"
+        );
+        assert_eq!(
+            refused(&[("top_p", InferParameter::String("high".to_string()))]).param(),
+            "top_p"
+        );
+        assert_eq!(
+            refused(&[("top_k", InferParameter::Int64(0))]).param(),
+            "top_k"
+        );
+        assert_eq!(
+            refused(&[("frequency_penalty", InferParameter::Int64(-3))]).param(),
+            "frequency_penalty"
+        );
+        assert_eq!(
+            refused(&[(
+                "stop",
+                InferParameter::String("[\"a\", \"b\", \"c\", \"d\", \"e\"]".to_string())
+            )])
+            .param(),
+            "stop"
+        );
+        assert_eq!(
+            refused(&[("stop", InferParameter::String(String::new()))]).param(),
+            "stop"
+        );
+    }
+}
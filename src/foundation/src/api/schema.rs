@@ -0,0 +1,344 @@
+/* Request schema versioning.
+
+A model may declare the request schema versions it supports, one of which is
+its current version. Clients pick the version they speak with the
+`x-galemind-schema-version` header (or gRPC metadata entry) or the
+`schema_version` request parameter; without either they get the current one.
+When the client speaks an older version, the registered converters on the
+path from that version to the current one upgrade the request step by step,
+and the response is downgraded through the same steps in reverse, so old
+clients keep working after a model's inputs or outputs change.
+
+Converters work on KServe v2 JSON documents (`{"parameters", "inputs",
+"outputs", ...}`). Besides converters registered in code, simple ones can be
+declared in the model configuration as a `MappingConverter`:
+
+```yaml
+schema:
+  current: "2"
+  supported: ["1", "2"]
+  converters:
+    - from: "1"
+      to: "2"
+      rename_inputs: { text: prompt }
+      rename_outputs: { label: class }
+      default_parameters: { top_k: 1 }
+```
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+pub const SCHEMA_VERSION_HEADER: &str = "x-galemind-schema-version";
+pub const SCHEMA_VERSION_PARAMETER: &str = "schema_version";
+
+/// Converts between two adjacent schema versions of a model.
+pub trait SchemaConverter: Send + Sync {
+    /// Older version, spoken by the client.
+    fn older_version(&self) -> &str;
+    /// Newer version, closer to the model's current one.
+    fn newer_version(&self) -> &str;
+    fn upgrade_request(&self, request: &mut Value) -> Result<()>;
+    fn downgrade_response(&self, response: &mut Value) -> Result<()>;
+}
+
+/// Schema versions declared by a model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaVersions {
+    pub current: String,
+    /// Versions clients may use; the current version is always supported.
+    #[serde(default)]
+    pub supported: Vec<String>,
+    #[serde(default)]
+    pub converters: Vec<MappingConverter>,
+}
+
+impl SchemaVersions {
+    pub fn supports(&self, version: &str) -> bool {
+        version == self.current || self.supported.iter().any(|v| v == version)
+    }
+}
+
+/// Converter renaming tensors and parameters, declared in a model configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MappingConverter {
+    pub from: String,
+    pub to: String,
+    /// Input names of the old version mapped to the new ones.
+    #[serde(default)]
+    pub rename_inputs: HashMap<String, String>,
+    /// Output names of the old version mapped to the new ones.
+    #[serde(default)]
+    pub rename_outputs: HashMap<String, String>,
+    /// Parameter names of the old version mapped to the new ones.
+    #[serde(default)]
+    pub rename_parameters: HashMap<String, String>,
+    /// Parameters added to upgraded requests that do not set them.
+    #[serde(default)]
+    pub default_parameters: Map<String, Value>,
+}
+
+impl SchemaConverter for MappingConverter {
+    fn older_version(&self) -> &str {
+        &self.from
+    }
+
+    fn newer_version(&self) -> &str {
+        &self.to
+    }
+
+    fn upgrade_request(&self, request: &mut Value) -> Result<()> {
+        let request = request
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Request must be a JSON object"))?;
+
+        rename_tensors(request.get_mut("inputs"), &self.rename_inputs);
+        // Requested outputs use the names of the client's version too.
+        rename_tensors(request.get_mut("outputs"), &self.rename_outputs);
+
+        if !self.rename_parameters.is_empty() || !self.default_parameters.is_empty() {
+            let parameters = request
+                .entry("parameters")
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Some(parameters) = parameters.as_object_mut() {
+                for (old, new) in &self.rename_parameters {
+                    if let Some(value) = parameters.remove(old) {
+                        parameters.insert(new.clone(), value);
+                    }
+                }
+                for (name, value) in &self.default_parameters {
+                    parameters
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn downgrade_response(&self, response: &mut Value) -> Result<()> {
+        let reversed: HashMap<String, String> = self
+            .rename_outputs
+            .iter()
+            .map(|(old, new)| (new.clone(), old.clone()))
+            .collect();
+        if let Some(response) = response.as_object_mut() {
+            rename_tensors(response.get_mut("outputs"), &reversed);
+        }
+        Ok(())
+    }
+}
+
+fn rename_tensors(tensors: Option<&mut Value>, renames: &HashMap<String, String>) {
+    let Some(Value::Array(tensors)) = tensors else {
+        return;
+    };
+    for tensor in tensors {
+        if let Some(name) = tensor.get("name").and_then(Value::as_str)
+            && let Some(renamed) = renames.get(name)
+        {
+            tensor["name"] = Value::String(renamed.clone());
+        }
+    }
+}
+
+/// Converters chosen for a request, from the client's schema version to the model's.
+#[derive(Clone, Default)]
+pub struct SchemaPlan {
+    /// Version spoken by the client, `None` when the model is not versioned.
+    pub client_version: Option<String>,
+    steps: Vec<Arc<dyn SchemaConverter>>,
+}
+
+impl SchemaPlan {
+    pub fn is_identity(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn upgrade_request(&self, request: &mut Value) -> Result<()> {
+        for step in &self.steps {
+            step.upgrade_request(request)?;
+        }
+        Ok(())
+    }
+
+    pub fn downgrade_response(&self, response: &mut Value) -> Result<()> {
+        for step in self.steps.iter().rev() {
+            step.downgrade_response(response)?;
+        }
+        Ok(())
+    }
+}
+
+/// Schema converters registered per model.
+#[derive(Default)]
+pub struct SchemaRegistry {
+    converters: DashMap<String, Vec<Arc<dyn SchemaConverter>>>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, model: &str, converter: Arc<dyn SchemaConverter>) {
+        let mut converters = self.converters.entry(model.to_string()).or_default();
+        // A later registration for the same versions replaces the earlier one.
+        converters.retain(|existing| {
+            existing.older_version() != converter.older_version()
+                || existing.newer_version() != converter.newer_version()
+        });
+        converters.push(converter);
+    }
+
+    /// Chooses the converters, registered or declared in `versions`, taking a request
+    /// of the `requested` version (or the current one) to the current version of the model.
+    pub fn plan(
+        &self,
+        model: &str,
+        versions: &SchemaVersions,
+        requested: Option<&str>,
+    ) -> Result<SchemaPlan> {
+        let client_version = requested.unwrap_or(&versions.current);
+        if !versions.supports(client_version) {
+            let mut supported = versions.supported.clone();
+            if !supported.contains(&versions.current) {
+                supported.push(versions.current.clone());
+            }
+            return Err(anyhow!(
+                "Schema version '{}' is not supported by model '{}', supported versions: {}",
+                client_version,
+                model,
+                supported.join(", ")
+            ));
+        }
+
+        let mut converters = self
+            .converters
+            .get(model)
+            .map(|converters| converters.clone())
+            .unwrap_or_default();
+        converters.extend(
+            versions
+                .converters
+                .iter()
+                .map(|converter| Arc::new(converter.clone()) as Arc<dyn SchemaConverter>),
+        );
+        let steps =
+            shortest_path(&converters, client_version, &versions.current).ok_or_else(|| {
+                anyhow!(
+                    "No schema converters from version '{}' to '{}' for model '{}'",
+                    client_version,
+                    versions.current,
+                    model
+                )
+            })?;
+
+        Ok(SchemaPlan {
+            client_version: Some(client_version.to_string()),
+            steps,
+        })
+    }
+}
+
+/// Breadth-first search for the fewest converters leading from `from` to `to`.
+fn shortest_path(
+    converters: &[Arc<dyn SchemaConverter>],
+    from: &str,
+    to: &str,
+) -> Option<Vec<Arc<dyn SchemaConverter>>> {
+    let mut previous: HashMap<String, usize> = HashMap::new();
+    let mut queue = VecDeque::from([from.to_string()]);
+
+    while let Some(version) = queue.pop_front() {
+        if version == to {
+            let mut steps = Vec::new();
+            let mut current = version;
+            while current != from {
+                let step = previous[&current];
+                current = converters[step].older_version().to_string();
+                steps.push(converters[step].clone());
+            }
+            steps.reverse();
+            return Some(steps);
+        }
+        for (index, converter) in converters.iter().enumerate() {
+            let next = converter.newer_version();
+            if converter.older_version() == version && next != from && !previous.contains_key(next)
+            {
+                previous.insert(next.to_string(), index);
+                queue.push_back(next.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn versions() -> SchemaVersions {
+        SchemaVersions {
+            current: "3".to_string(),
+            supported: vec!["1".to_string(), "2".to_string()],
+            converters: Vec::new(),
+        }
+    }
+
+    fn converter(
+        from: &str,
+        to: &str,
+        input: (&str, &str),
+        output: (&str, &str),
+    ) -> Arc<dyn SchemaConverter> {
+        Arc::new(MappingConverter {
+            from: from.to_string(),
+            to: to.to_string(),
+            rename_inputs: HashMap::from([(input.0.to_string(), input.1.to_string())]),
+            rename_outputs: HashMap::from([(output.0.to_string(), output.1.to_string())]),
+            rename_parameters: HashMap::new(),
+            default_parameters: Map::from_iter([("top_k".to_string(), json!(1))]),
+        })
+    }
+
+    #[test]
+    fn test_plan_chains_converters_both_ways() {
+        let registry = SchemaRegistry::new();
+        registry.register(
+            "m",
+            converter("2", "3", ("text", "prompt"), ("class", "label")),
+        );
+        registry.register(
+            "m",
+            converter("1", "2", ("input", "text"), ("score", "class")),
+        );
+
+        let plan = registry.plan("m", &versions(), Some("1")).unwrap();
+        assert_eq!(plan.client_version.as_deref(), Some("1"));
+
+        let mut request = json!({ "inputs": [{ "name": "input", "data": [1] }] });
+        plan.upgrade_request(&mut request).unwrap();
+        assert_eq!(request["inputs"][0]["name"], "prompt");
+        assert_eq!(request["parameters"]["top_k"], 1);
+
+        let mut response = json!({ "outputs": [{ "name": "label" }] });
+        plan.downgrade_response(&mut response).unwrap();
+        assert_eq!(response["outputs"][0]["name"], "score");
+
+        assert!(registry.plan("m", &versions(), None).unwrap().is_identity());
+    }
+
+    #[test]
+    fn test_plan_rejects_unsupported_or_unreachable_versions() {
+        let registry = SchemaRegistry::new();
+        assert!(registry.plan("m", &versions(), Some("4")).is_err());
+        // "2" is supported but no converter leads to "3".
+        assert!(registry.plan("m", &versions(), Some("2")).is_err());
+    }
+}
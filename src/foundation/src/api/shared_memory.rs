@@ -0,0 +1,414 @@
+/* Shared memory tensor transport, the KServe / Triton shared memory extension.
+
+Clients on the same host as the server can pass tensors through shared memory
+instead of over the socket. A client creates a POSIX shared memory object
+(`shm_open("/input", ...)`, a file of `/dev/shm` on Linux) and registers a
+region of it under a name:
+
+```json
+POST /v2/systemsharedmemory/region/input/register
+{"key": "/input", "offset": 0, "byte_size": 4194304}
+```
+
+An input then names the region in its parameters instead of carrying data:
+`shared_memory_region`, `shared_memory_byte_size` and optionally
+`shared_memory_offset` within the region. The runtime receives a view of the
+mapped memory, which is never copied. A requested output with the same
+parameters is written to its region, and returned without data.
+
+Regions stay mapped until they are unregistered and no request in flight reads
+them. The client must not modify an input region before the response arrives.
+
+CUDA shared memory is not supported. Its regions (CUDA IPC handles) need a
+runtime opening the handles on its device, and no runtime of this server does,
+so neither API serves the CUDA shared memory calls of the extension.
+*/
+
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use memmap2::{MmapOptions, MmapRaw};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use super::inference::InferParameter;
+
+/// Parameter of a tensor naming the shared memory region holding it.
+pub const SHARED_MEMORY_REGION_PARAMETER: &str = "shared_memory_region";
+/// Parameter of a tensor in shared memory, its number of bytes.
+pub const SHARED_MEMORY_BYTE_SIZE_PARAMETER: &str = "shared_memory_byte_size";
+/// Parameter of a tensor in shared memory, where it starts in its region.
+pub const SHARED_MEMORY_OFFSET_PARAMETER: &str = "shared_memory_offset";
+
+/// Directory POSIX shared memory objects are files of, on Linux.
+pub const SHARED_MEMORY_DIR: &str = "/dev/shm";
+
+/// A registered system shared memory region.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemRegionStatus {
+    pub name: String,
+    /// Name of the shared memory object, e.g. `/input`.
+    pub key: String,
+    /// Start of the region in the object.
+    pub offset: u64,
+    pub byte_size: u64,
+}
+
+/// Where a tensor is in shared memory, read from its parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedMemoryRef {
+    pub region: String,
+    pub offset: usize,
+    pub byte_size: usize,
+}
+
+impl SharedMemoryRef {
+    /// Removes the shared memory parameters from `parameters`. `None` when they name no
+    /// region; fails when the region has no byte size or the numbers are not sizes.
+    pub fn take(parameters: &mut HashMap<String, InferParameter>) -> Result<Option<Self>> {
+        let region = parameters.remove(SHARED_MEMORY_REGION_PARAMETER);
+        let byte_size = parameters.remove(SHARED_MEMORY_BYTE_SIZE_PARAMETER);
+        let offset = parameters.remove(SHARED_MEMORY_OFFSET_PARAMETER);
+        let Some(region) = region else {
+            return Ok(None);
+        };
+        let InferParameter::String(region) = region else {
+            return Err(anyhow!(
+                "{} must be a region name",
+                SHARED_MEMORY_REGION_PARAMETER
+            ));
+        };
+        let size = |parameter: Option<InferParameter>, name: &str| match parameter {
+            Some(InferParameter::Int64(size)) => usize::try_from(size)
+                .map(Some)
+                .map_err(|_| anyhow!("{} must be a number of bytes", name)),
+            None => Ok(None),
+            Some(_) => Err(anyhow!("{} must be a number of bytes", name)),
+        };
+        let byte_size = size(byte_size, SHARED_MEMORY_BYTE_SIZE_PARAMETER)?.ok_or_else(|| {
+            anyhow!(
+                "{} is required with {}",
+                SHARED_MEMORY_BYTE_SIZE_PARAMETER,
+                SHARED_MEMORY_REGION_PARAMETER
+            )
+        })?;
+        let offset = size(offset, SHARED_MEMORY_OFFSET_PARAMETER)?.unwrap_or(0);
+        Ok(Some(Self {
+            region,
+            offset,
+            byte_size,
+        }))
+    }
+
+    /// The parameters of a tensor at this place.
+    pub fn parameters(&self) -> HashMap<String, InferParameter> {
+        HashMap::from([
+            (
+                SHARED_MEMORY_REGION_PARAMETER.to_string(),
+                InferParameter::String(self.region.clone()),
+            ),
+            (
+                SHARED_MEMORY_BYTE_SIZE_PARAMETER.to_string(),
+                InferParameter::Int64(self.byte_size as i64),
+            ),
+            (
+                SHARED_MEMORY_OFFSET_PARAMETER.to_string(),
+                InferParameter::Int64(self.offset as i64),
+            ),
+        ])
+    }
+}
+
+/// A mapped region, unmapped once the registry and every view of it let it go.
+#[derive(Debug)]
+struct Mapping(MmapRaw);
+
+impl Mapping {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// A mapping as the owner of the `Bytes` viewing it.
+struct Owner(Arc<Mapping>);
+
+impl AsRef<[u8]> for Owner {
+    fn as_ref(&self) -> &[u8] {
+        // SAFETY: the mapping lives as long as the owner. Other processes may write to
+        // it, as with any shared memory: clients must leave inputs alone until answered.
+        unsafe { std::slice::from_raw_parts(self.0.0.as_ptr(), self.0.len()) }
+    }
+}
+
+#[derive(Debug)]
+struct SystemRegion {
+    key: String,
+    offset: u64,
+    mapping: Arc<Mapping>,
+}
+
+/// Shared memory regions registered by clients, shared by the REST and gRPC servers.
+#[derive(Debug)]
+pub struct SharedMemoryRegistry {
+    directory: PathBuf,
+    system: Mutex<BTreeMap<String, SystemRegion>>,
+}
+
+impl Default for SharedMemoryRegistry {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from(SHARED_MEMORY_DIR),
+            system: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl SharedMemoryRegistry {
+    /// Finds shared memory objects in `directory` rather than `/dev/shm`.
+    pub fn with_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = directory.into();
+        self
+    }
+
+    /// Maps `byte_size` bytes of the shared memory object `key`, from `offset`, as the
+    /// region `name`.
+    pub fn register_system(
+        &self,
+        name: &str,
+        key: &str,
+        offset: u64,
+        byte_size: u64,
+    ) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow!("A shared memory region needs a name"));
+        }
+        if self.system.lock().unwrap().contains_key(name) {
+            return Err(anyhow!(
+                "Shared memory region '{}' is already registered",
+                name
+            ));
+        }
+        let object = key.strip_prefix('/').unwrap_or(key);
+        if object.is_empty() || object.contains('/') || object == "." || object == ".." {
+            return Err(anyhow!(
+                "'{}' is not the name of a shared memory object, such as /input",
+                key
+            ));
+        }
+        if byte_size == 0 {
+            return Err(anyhow!("Shared memory region '{}' is empty", name));
+        }
+        let path = self.directory.join(object);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open the shared memory object '{}'", key))?;
+        let length = file.metadata()?.len();
+        if offset.checked_add(byte_size).is_none_or(|end| end > length) {
+            return Err(anyhow!(
+                "The shared memory object '{}' has {} bytes, the region ends past them",
+                key,
+                length
+            ));
+        }
+        let len = usize::try_from(byte_size)?;
+        let mapping = MmapOptions::new()
+            .offset(offset)
+            .len(len)
+            .map_raw(&file)
+            .with_context(|| format!("Failed to map the shared memory object '{}'", key))?;
+
+        let mut system = self.system.lock().unwrap();
+        if system.contains_key(name) {
+            return Err(anyhow!(
+                "Shared memory region '{}' is already registered",
+                name
+            ));
+        }
+        system.insert(
+            name.to_string(),
+            SystemRegion {
+                key: key.to_string(),
+                offset,
+                mapping: Arc::new(Mapping(mapping)),
+            },
+        );
+        Ok(())
+    }
+
+    /// Unregisters the region `name`, or every region. Unknown regions are ignored.
+    pub fn unregister_system(&self, name: Option<&str>) {
+        let mut system = self.system.lock().unwrap();
+        match name {
+            Some(name) => {
+                system.remove(name);
+            }
+            None => system.clear(),
+        }
+    }
+
+    /// The region `name`, or every region, by name. Fails for an unknown region.
+    pub fn system_status(&self, name: Option<&str>) -> Result<Vec<SystemRegionStatus>> {
+        let system = self.system.lock().unwrap();
+        let status = |(name, region): (&String, &SystemRegion)| SystemRegionStatus {
+            name: name.clone(),
+            key: region.key.clone(),
+            offset: region.offset,
+            byte_size: region.mapping.len() as u64,
+        };
+        match name {
+            Some(name) => system
+                .get_key_value(name)
+                .map(|region| vec![status(region)])
+                .ok_or_else(|| unknown(name)),
+            None => Ok(system.iter().map(status).collect()),
+        }
+    }
+
+    /// The bytes at `location`, viewing the mapped memory.
+    pub fn read(&self, location: &SharedMemoryRef) -> Result<Bytes> {
+        let mapping = self.mapping(location)?;
+        Ok(Bytes::from_owner(Owner(mapping))
+            .slice(location.offset..location.offset + location.byte_size))
+    }
+
+    /// Writes `bytes` at `location`, whose byte size must hold them.
+    pub fn write(&self, location: &SharedMemoryRef, bytes: &[u8]) -> Result<()> {
+        if bytes.len() > location.byte_size {
+            return Err(anyhow!(
+                "The output has {} bytes, more than the {} bytes given in shared memory \
+                 region '{}'",
+                bytes.len(),
+                location.byte_size,
+                location.region
+            ));
+        }
+        let mapping = self.mapping(location)?;
+        // SAFETY: the range is within the mapping, checked by `mapping`.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                mapping.0.as_mut_ptr().add(location.offset),
+                bytes.len(),
+            );
+        }
+        Ok(())
+    }
+
+    /// The mapping of the region of `location`, provided the location is within it.
+    fn mapping(&self, location: &SharedMemoryRef) -> Result<Arc<Mapping>> {
+        let mapping = self
+            .system
+            .lock()
+            .unwrap()
+            .get(&location.region)
+            .map(|region| region.mapping.clone())
+            .ok_or_else(|| unknown(&location.region))?;
+        if location
+            .offset
+            .checked_add(location.byte_size)
+            .is_none_or(|end| end > mapping.len())
+        {
+            return Err(anyhow!(
+                "{} bytes at offset {} are past the {} bytes of shared memory region '{}'",
+                location.byte_size,
+                location.offset,
+                mapping.len(),
+                location.region
+            ));
+        }
+        Ok(mapping)
+    }
+}
+
+fn unknown(name: &str) -> anyhow::Error {
+    anyhow!("Unknown shared memory region '{}'", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shared memory object of `len` zero bytes in a directory of its own.
+    fn object(name: &str, len: u64) -> SharedMemoryRegistry {
+        let directory =
+            std::env::temp_dir().join(format!("galemind-shm-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::File::create(directory.join(name))
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+        SharedMemoryRegistry::default().with_directory(directory)
+    }
+
+    fn at(region: &str, offset: usize, byte_size: usize) -> SharedMemoryRef {
+        SharedMemoryRef {
+            region: region.to_string(),
+            offset,
+            byte_size,
+        }
+    }
+
+    #[test]
+    fn test_tensors_round_trip_through_a_region() {
+        let registry = object("tensors", 64);
+        registry.register_system("io", "/tensors", 16, 32).unwrap();
+        assert_eq!(
+            registry.system_status(None).unwrap(),
+            vec![SystemRegionStatus {
+                name: "io".to_string(),
+                key: "/tensors".to_string(),
+                offset: 16,
+                byte_size: 32,
+            }]
+        );
+
+        registry.write(&at("io", 4, 8), &[1, 2, 3, 4]).unwrap();
+        let bytes = registry.read(&at("io", 4, 4)).unwrap();
+        assert_eq!(&bytes[..], &[1, 2, 3, 4]);
+        // Views outlive the registration.
+        registry.unregister_system(Some("io"));
+        assert_eq!(&bytes[..], &[1, 2, 3, 4]);
+        assert!(registry.read(&at("io", 4, 4)).is_err());
+    }
+
+    #[test]
+    fn test_registrations_are_checked() {
+        let registry = object("checked", 16);
+        assert!(registry.register_system("a", "/missing", 0, 8).is_err());
+        assert!(registry.register_system("a", "/../checked", 0, 8).is_err());
+        assert!(registry.register_system("a", "/checked", 8, 16).is_err());
+        registry.register_system("a", "/checked", 0, 16).unwrap();
+        assert!(registry.register_system("a", "/checked", 0, 8).is_err());
+
+        assert!(registry.read(&at("a", 8, 16)).is_err());
+        assert!(registry.write(&at("a", 0, 2), &[0; 4]).is_err());
+        assert!(registry.system_status(Some("b")).is_err());
+    }
+
+    #[test]
+    fn test_locations_are_read_from_parameters() {
+        let mut parameters = HashMap::from([
+            (
+                SHARED_MEMORY_REGION_PARAMETER.to_string(),
+                InferParameter::String("io".to_string()),
+            ),
+            (
+                SHARED_MEMORY_BYTE_SIZE_PARAMETER.to_string(),
+                InferParameter::Int64(16),
+            ),
+            ("other".to_string(), InferParameter::Bool(true)),
+        ]);
+        let location = SharedMemoryRef::take(&mut parameters).unwrap().unwrap();
+        assert_eq!(location, at("io", 0, 16));
+        assert_eq!(parameters.len(), 1);
+        assert_eq!(SharedMemoryRef::take(&mut parameters).unwrap(), None);
+
+        let mut parameters = location.parameters();
+        parameters.remove(SHARED_MEMORY_BYTE_SIZE_PARAMETER);
+        assert!(SharedMemoryRef::take(&mut parameters).is_err());
+    }
+}
@@ -1,9 +1,166 @@
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use super::tensor_view::TensorView;
+
+/// Protocol datatype of string and bytes tensors.
+pub const BYTES_DATATYPE: &str = "BYTES";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Data {
     VFLOAT(Vec<f64>),
+    /// UTF-8 elements, such as the text given to NLP models or the labels of classifiers.
+    VSTRING(Vec<String>),
+    /// Opaque elements, such as encoded images.
+    VBYTES(Vec<Vec<u8>>),
+    /// Numbers as raw bytes, shared with the buffer they were received in.
+    VRAW(TensorView),
+}
+
+impl Data {
+    pub fn datatype(&self) -> DataType {
+        match self {
+            Data::VFLOAT(_) => DataType::VFLOAT,
+            Data::VSTRING(_) => DataType::VSTRING,
+            Data::VBYTES(_) => DataType::VBYTES,
+            Data::VRAW(_) => DataType::VRAW,
+        }
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        match self {
+            Data::VFLOAT(values) => values.len(),
+            Data::VSTRING(values) => values.len(),
+            Data::VBYTES(values) => values.len(),
+            Data::VRAW(view) => view.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// BYTES elements as strings when they are all UTF-8, as bytes otherwise.
+    pub fn from_byte_elements(elements: Vec<Vec<u8>>) -> Self {
+        if elements
+            .iter()
+            .all(|element| std::str::from_utf8(element).is_ok())
+        {
+            Data::VSTRING(
+                elements
+                    .into_iter()
+                    .map(|element| String::from_utf8(element).unwrap_or_default())
+                    .collect(),
+            )
+        } else {
+            Data::VBYTES(elements)
+        }
+    }
+
+    /// Raw contents of a tensor of `datatype`: BYTES elements are decoded, numbers are
+    /// viewed in `bytes`.
+    pub fn from_raw(datatype: &str, bytes: Bytes) -> Result<Self> {
+        if datatype == BYTES_DATATYPE {
+            Ok(Self::from_byte_elements(decode_byte_elements(&bytes)?))
+        } else {
+            Ok(Data::VRAW(TensorView::new(datatype.parse()?, bytes)?))
+        }
+    }
+
+    /// The elements of a numeric tensor, decoded from raw bytes if need be; None for
+    /// strings and bytes.
+    pub fn float_values(&self) -> Option<Cow<'_, [f64]>> {
+        match self {
+            Data::VFLOAT(values) => Some(Cow::Borrowed(values)),
+            Data::VRAW(view) => Some(Cow::Owned(view.to_f64())),
+            Data::VSTRING(_) | Data::VBYTES(_) => None,
+        }
+    }
+
+    /// The elements of a string or bytes tensor as bytes, None for numbers.
+    pub fn byte_elements(&self) -> Option<Vec<&[u8]>> {
+        match self {
+            Data::VFLOAT(_) | Data::VRAW(_) => None,
+            Data::VSTRING(values) => Some(values.iter().map(String::as_bytes).collect()),
+            Data::VBYTES(values) => Some(values.iter().map(Vec::as_slice).collect()),
+        }
+    }
 }
-#[derive(PartialEq)]
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     VFLOAT,
+    VSTRING,
+    VBYTES,
+    VRAW,
 }
 
 pub type DataShape = Vec<usize>;
+
+/// Raw contents of BYTES elements: each one is preceded by its length, as a
+/// little-endian `u32`.
+pub fn encode_byte_elements<'a>(elements: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for element in elements {
+        bytes.extend((element.len() as u32).to_le_bytes());
+        bytes.extend(element);
+    }
+    bytes
+}
+
+/// BYTES elements of raw contents, the inverse of `encode_byte_elements`.
+pub fn decode_byte_elements(mut bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut elements = Vec::new();
+    while !bytes.is_empty() {
+        let Some((length, rest)) = bytes.split_first_chunk::<4>() else {
+            return Err(anyhow!(
+                "BYTES element {} has a truncated length prefix",
+                elements.len()
+            ));
+        };
+        let length = u32::from_le_bytes(*length) as usize;
+        if length > rest.len() {
+            return Err(anyhow!(
+                "BYTES element {} announces {} bytes, only {} are left",
+                elements.len(),
+                length,
+                rest.len()
+            ));
+        }
+        let (element, rest) = rest.split_at(length);
+        elements.push(element.to_vec());
+        bytes = rest;
+    }
+    Ok(elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_elements_round_trip() {
+        let data = Data::VSTRING(vec!["a fine day".to_string(), String::new()]);
+        let bytes = encode_byte_elements(data.byte_elements().unwrap());
+        assert_eq!(&bytes[..4], &[10, 0, 0, 0]);
+        assert_eq!(
+            decode_byte_elements(&bytes).unwrap(),
+            vec![b"a fine day".to_vec(), Vec::new()]
+        );
+        assert!(Data::VFLOAT(vec![1.0]).byte_elements().is_none());
+        assert!(matches!(
+            Data::from_byte_elements(vec![b"label".to_vec()]),
+            Data::VSTRING(_)
+        ));
+        assert!(matches!(
+            Data::from_byte_elements(vec![b"label".to_vec(), vec![0xff, 0xd8]]),
+            Data::VBYTES(_)
+        ));
+
+        assert!(decode_byte_elements(&[1, 0]).is_err());
+        assert!(decode_byte_elements(&[5, 0, 0, 0, b'a']).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+/* Numeric tensors viewed in the buffer they were received in.
+
+Inputs sent as raw little-endian bytes (the binary data extension of the REST
+protocol, the `raw_input_contents` of gRPC) are not decoded into numbers when
+the request arrives. A `TensorView` holds a reference-counted slice of the
+received buffer, so multi-megabyte tensors such as image batches reach the
+runtime without being copied. Runtimes taking raw tensors (an ONNX session, a
+device upload) read `bytes` as they are; others decode the values with
+`to_f64` when they need them.
+
+Runtimes may return their outputs as views too. gRPC sends an output viewed in
+the datatype the client asked for (by default, its own) as raw output contents
+without copying it; any other datatype is cast from the decoded values.
+*/
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::cast::{CastTensor, OutputDatatype};
+
+/// Raw little-endian elements of a numeric tensor, shared with the buffer they came in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorView {
+    datatype: OutputDatatype,
+    bytes: Bytes,
+}
+
+impl TensorView {
+    /// Views `bytes` as elements of `datatype`. Fails unless they hold a whole number of
+    /// elements.
+    pub fn new(datatype: OutputDatatype, bytes: Bytes) -> Result<Self> {
+        let size = datatype.element_size();
+        if !bytes.len().is_multiple_of(size) {
+            return Err(anyhow!(
+                "{} bytes of {} data are not a whole number of {}-byte elements",
+                bytes.len(),
+                datatype,
+                size
+            ));
+        }
+        Ok(Self { datatype, bytes })
+    }
+
+    pub fn datatype(&self) -> OutputDatatype {
+        self.datatype
+    }
+
+    /// The raw contents, little-endian.
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.bytes.len() / self.datatype.element_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The elements, decoded.
+    pub fn to_f64(&self) -> Vec<f64> {
+        self.cast().to_f64()
+    }
+
+    /// The elements, decoded in their own datatype.
+    pub fn cast(&self) -> CastTensor {
+        // The length was checked when the view was made.
+        CastTensor::from_raw_bytes(&self.bytes, self.datatype)
+            .unwrap_or(CastTensor::new(&[], self.datatype))
+    }
+}
+
+/// Encoding of a view in the wire format of `api::codec`.
+#[derive(Serialize, Deserialize)]
+struct EncodedView {
+    datatype: String,
+    bytes: Bytes,
+}
+
+impl Serialize for TensorView {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        EncodedView {
+            datatype: self.datatype.to_string(),
+            bytes: self.bytes.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TensorView {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = EncodedView::deserialize(deserializer)?;
+        let datatype = encoded.datatype.parse().map_err(serde::de::Error::custom)?;
+        Self::new(datatype, encoded.bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_view_shares_the_buffer_it_was_made_from() {
+        let body = Bytes::from(
+            [1.5f32, -2.0, 3.25]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<u8>>(),
+        );
+        let view = TensorView::new(OutputDatatype::Fp32, body.slice(4..)).unwrap();
+        assert_eq!(view.bytes().as_ptr(), body[4..].as_ptr());
+        assert_eq!(view.len(), 2);
+        assert_eq!(view.to_f64(), vec![-2.0, 3.25]);
+
+        assert!(TensorView::new(OutputDatatype::Fp32, body.slice(1..)).is_err());
+        assert!(
+            TensorView::new(OutputDatatype::Uint8, Bytes::new())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_view_round_trips_through_json() {
+        let view =
+            TensorView::new(OutputDatatype::Int16, Bytes::from_static(&[1, 0, 255, 255])).unwrap();
+        let json = serde_json::to_string(&view).unwrap();
+        let decoded: TensorView = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, view);
+        assert_eq!(decoded.to_f64(), vec![1.0, -1.0]);
+
+        let truncated = r#"{"datatype":"INT16","bytes":[1,0,255]}"#;
+        assert!(serde_json::from_str::<TensorView>(truncated).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+/* Host/device transfers around batch execution on GPUs.
+
+Backends running on CUDA can split a batch into three steps by implementing
+`DeviceStaging`: the inputs are copied to the device (host to device), the
+batch is executed, and the outputs are copied back (device to host). A
+`PlacedRuntime` whose runtime does so stages the input tensors of a batch in a
+pinned host buffer and uploads them *before* taking one of the device's slots,
+and downloads the outputs *after* releasing it. Transfers of the next batch
+therefore overlap with the compute of the batch holding the slot, instead of
+running inside the slot and serializing with it.
+
+Pinning memory is expensive, so the host buffers come from a pool per device:
+each is pinned once (`DeviceStaging::pin`, e.g. `cudaHostRegister`) when first
+allocated and reused by the following batches. The pool keeps at most
+`MAX_POOLED_BUFFERS` idle buffers; a buffer released when it is full is
+unpinned (`DeviceStaging::unpin`, e.g. `cudaHostUnregister`) before it is
+freed.
+
+The time and bytes of every transfer are recorded per device and direction,
+and served on `/metrics` as `galemind_device_transfer_seconds` and
+`galemind_device_transfer_bytes_total`, to quantify the gain of the overlap.
+*/
+
+use anyhow::Result;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::any::Any;
+use std::fmt::Write;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::devices::Device;
+use super::inference::{InferenceRequest, InferenceResponse};
+use super::tensor::{Data, encode_byte_elements};
+use crate::metrics::{Histogram, LATENCY_BUCKETS};
+
+/// Idle host buffers kept per device.
+pub const MAX_POOLED_BUFFERS: usize = 8;
+
+/// Direction of a transfer between the host and a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    HostToDevice,
+    DeviceToHost,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::HostToDevice => "h2d",
+            Direction::DeviceToHost => "d2h",
+        }
+    }
+}
+
+/// A pinned host buffer of a `PinnedBufferPool`, returned to it when dropped, or unpinned
+/// with the staging it was taken with when the pool is full.
+pub struct PinnedBuffer<'a> {
+    bytes: Vec<u8>,
+    pool: Arc<Mutex<Vec<Vec<u8>>>>,
+    staging: &'a dyn DeviceStaging,
+}
+
+impl PinnedBuffer<'_> {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl Drop for PinnedBuffer<'_> {
+    fn drop(&mut self) {
+        let mut idle = self.pool.lock().unwrap();
+        if idle.len() < MAX_POOLED_BUFFERS {
+            idle.push(std::mem::take(&mut self.bytes));
+        } else if let Err(e) = self.staging.unpin(&mut self.bytes) {
+            eprintln!("Failed to unpin a host buffer: {}", e);
+        }
+    }
+}
+
+/// Reusable pinned host buffers of one device.
+#[derive(Default)]
+pub struct PinnedBufferPool {
+    idle: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// Buffers allocated and pinned so far.
+    allocated: AtomicU64,
+}
+
+impl PinnedBufferPool {
+    /// A buffer of `len` bytes, reusing an idle one large enough and pinning any new one
+    /// with `staging`.
+    pub fn take<'a>(&self, len: usize, staging: &'a dyn DeviceStaging) -> Result<PinnedBuffer<'a>> {
+        let reused = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.iter()
+                .position(|bytes| bytes.capacity() >= len)
+                .map(|index| idle.swap_remove(index))
+        };
+        let bytes = match reused {
+            Some(mut bytes) => {
+                bytes.resize(len, 0);
+                bytes
+            }
+            None => {
+                let mut bytes = vec![0; len];
+                staging.pin(&mut bytes)?;
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                bytes
+            }
+        };
+        Ok(PinnedBuffer {
+            bytes,
+            pool: self.idle.clone(),
+            staging,
+        })
+    }
+
+    pub fn allocated(&self) -> u64 {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+/// Bytes of the input tensor `name` of request `request` in a staged batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorSpan {
+    pub request: usize,
+    pub name: String,
+    pub range: Range<usize>,
+}
+
+/// Bytes the input tensors of `requests` take once staged.
+pub fn staged_len(requests: &[InferenceRequest]) -> usize {
+    requests
+        .iter()
+        .flat_map(|request| request.outputs.iter().flatten())
+        .map(|tensor| match &tensor.data {
+            Data::VFLOAT(values) => values.len() * size_of::<f64>(),
+            Data::VRAW(view) => view.bytes().len(),
+            data => encoded_elements_len(data),
+        })
+        .sum()
+}
+
+/// Bytes of string and bytes elements once staged, each with its length prefix.
+fn encoded_elements_len(data: &Data) -> usize {
+    data.byte_elements()
+        .unwrap_or_default()
+        .iter()
+        .map(|element| size_of::<u32>() + element.len())
+        .sum()
+}
+
+/// Copies the input tensors of `requests` into `host`, little-endian and back to back,
+/// returning where each one is. Numbers are FP64 unless they were received raw, in which
+/// case they keep their datatype; string and bytes elements are length-prefixed. `host`
+/// holds `staged_len(requests)` bytes.
+pub fn stage(requests: &[InferenceRequest], host: &mut PinnedBuffer) -> Vec<TensorSpan> {
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    for (index, request) in requests.iter().enumerate() {
+        for tensor in request.outputs.iter().flatten() {
+            let start = offset;
+            match &tensor.data {
+                Data::VFLOAT(values) => {
+                    for value in values {
+                        host.bytes[offset..offset + size_of::<f64>()]
+                            .copy_from_slice(&value.to_le_bytes());
+                        offset += size_of::<f64>();
+                    }
+                }
+                // Raw tensors are staged as they were received, in their own datatype.
+                Data::VRAW(view) => {
+                    host.bytes[offset..offset + view.bytes().len()].copy_from_slice(view.bytes());
+                    offset += view.bytes().len();
+                }
+                data => {
+                    let encoded = encode_byte_elements(data.byte_elements().unwrap_or_default());
+                    host.bytes[offset..offset + encoded.len()].copy_from_slice(&encoded);
+                    offset += encoded.len();
+                }
+            }
+            spans.push(TensorSpan {
+                request: index,
+                name: tensor.name.clone(),
+                range: start..offset,
+            });
+        }
+    }
+    spans
+}
+
+/// A batch whose inputs were uploaded to a device.
+pub struct DeviceBatch {
+    pub requests: Vec<InferenceRequest>,
+    /// Device memory and events of the batch, owned by the backend.
+    pub state: Box<dyn Any + Send + Sync>,
+}
+
+/// Batch execution split around host/device transfers, implemented by CUDA backends.
+#[async_trait]
+pub trait DeviceStaging: Send + Sync {
+    /// Pins a newly allocated host buffer so transfers from and to it can be asynchronous.
+    fn pin(&self, host: &mut [u8]) -> Result<()>;
+
+    /// Unpins a host buffer pinned by `pin` before it is freed.
+    fn unpin(&self, host: &mut [u8]) -> Result<()>;
+
+    /// Copies the inputs of `requests`, staged in `host` at `spans`, to the device.
+    async fn upload(
+        &self,
+        requests: Vec<InferenceRequest>,
+        host: &[u8],
+        spans: &[TensorSpan],
+    ) -> Result<DeviceBatch>;
+
+    /// Runs an uploaded batch, leaving its outputs on the device.
+    async fn execute(&self, batch: &mut DeviceBatch) -> Result<()>;
+
+    /// Copies the outputs of an executed batch back through `host`, one response per request
+    /// in order. Returns the responses and the bytes copied.
+    async fn download(
+        &self,
+        batch: DeviceBatch,
+        host: &mut PinnedBuffer,
+    ) -> Result<(Vec<InferenceResponse>, usize)>;
+}
+
+struct TransferStats {
+    seconds: Histogram,
+    bytes: AtomicU64,
+}
+
+/// Time and bytes of the transfers per device and direction.
+#[derive(Default)]
+pub struct TransferMetrics {
+    transfers: DashMap<(Device, Direction), TransferStats>,
+}
+
+impl TransferMetrics {
+    pub fn record(&self, device: Device, direction: Direction, bytes: usize, took: Duration) {
+        let stats = self
+            .transfers
+            .entry((device, direction))
+            .or_insert_with(|| TransferStats {
+                seconds: Histogram::new(&LATENCY_BUCKETS),
+                bytes: AtomicU64::new(0),
+            });
+        stats.seconds.observe(took.as_secs_f64());
+        stats.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Transfers and bytes recorded for `device` in `direction`.
+    pub fn totals(&self, device: Device, direction: Direction) -> (u64, u64) {
+        self.transfers
+            .get(&(device, direction))
+            .map_or((0, 0), |stats| {
+                (stats.seconds.count(), stats.bytes.load(Ordering::Relaxed))
+            })
+    }
+
+    /// The transfer series in the Prometheus text exposition format.
+    pub fn render(&self, out: &mut String) {
+        let mut transfers: Vec<_> = self.transfers.iter().collect();
+        transfers.sort_by_key(|entry| *entry.key());
+        let _ = writeln!(
+            out,
+            "# HELP galemind_device_transfer_seconds Duration of host/device transfers of batches.\n\
+             # TYPE galemind_device_transfer_seconds histogram"
+        );
+        for entry in &transfers {
+            let (device, direction) = entry.key();
+            let labels = format!("device=\"{}\",direction=\"{}\"", device, direction.as_str());
+            entry
+                .seconds
+                .render(out, "galemind_device_transfer_seconds", &labels);
+        }
+        let _ = writeln!(
+            out,
+            "# HELP galemind_device_transfer_bytes_total Bytes of host/device transfers of batches.\n\
+             # TYPE galemind_device_transfer_bytes_total counter"
+        );
+        for entry in &transfers {
+            let (device, direction) = entry.key();
+            let _ = writeln!(
+                out,
+                "galemind_device_transfer_bytes_total{{device=\"{}\",direction=\"{}\"}} {}",
+                device,
+                direction.as_str(),
+                entry.bytes.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::InferenceOutput;
+    use crate::api::tensor::DataType;
+    use crate::model::priority::Priority;
+
+    /// Stages nothing, counting the buffers pinned and unpinned.
+    #[derive(Default)]
+    struct Unpinned {
+        pins: AtomicU64,
+        unpins: AtomicU64,
+    }
+
+    #[async_trait]
+    impl DeviceStaging for Unpinned {
+        fn pin(&self, _host: &mut [u8]) -> Result<()> {
+            self.pins.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn unpin(&self, _host: &mut [u8]) -> Result<()> {
+            self.unpins.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        async fn upload(
+            &self,
+            requests: Vec<InferenceRequest>,
+            _host: &[u8],
+            _spans: &[TensorSpan],
+        ) -> Result<DeviceBatch> {
+            Ok(DeviceBatch {
+                requests,
+                state: Box::new(()),
+            })
+        }
+
+        async fn execute(&self, _batch: &mut DeviceBatch) -> Result<()> {
+            Ok(())
+        }
+
+        async fn download(
+            &self,
+            _batch: DeviceBatch,
+            _host: &mut PinnedBuffer,
+        ) -> Result<(Vec<InferenceResponse>, usize)> {
+            Ok((Vec::new(), 0))
+        }
+    }
+
+    fn request(values: Vec<f64>) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: None,
+            id: "r".to_string(),
+            parameters: None,
+            outputs: Some(vec![InferenceOutput {
+                name: "input_1".to_string(),
+                shape: vec![values.len()],
+                datatype: DataType::VFLOAT,
+                parameters: None,
+                data: Data::VFLOAT(values),
+            }]),
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_batches_are_staged_in_reused_buffers() {
+        let pool = PinnedBufferPool::default();
+        let staging = Unpinned::default();
+        let requests = vec![request(vec![1.0, 2.0]), request(vec![3.0])];
+        let len = staged_len(&requests);
+        assert_eq!(len, 24);
+
+        let mut host = pool.take(len, &staging).unwrap();
+        let spans = stage(&requests, &mut host);
+        assert_eq!(spans[1].request, 1);
+        assert_eq!(spans[1].range, 16..24);
+        assert_eq!(host.as_slice()[16..24], 3.0f64.to_le_bytes());
+        drop(host);
+
+        // The buffer is pinned once and reused by the next, smaller batch.
+        let host = pool.take(8, &staging).unwrap();
+        assert_eq!(host.len(), 8);
+        assert_eq!(pool.allocated(), 1);
+        assert_eq!(staging.unpins.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_buffers_past_the_pool_are_unpinned() {
+        let pool = PinnedBufferPool::default();
+        let staging = Unpinned::default();
+        let buffers: Vec<_> = (0..MAX_POOLED_BUFFERS + 2)
+            .map(|_| pool.take(8, &staging).unwrap())
+            .collect();
+        drop(buffers);
+        assert_eq!(
+            staging.pins.load(Ordering::Relaxed),
+            MAX_POOLED_BUFFERS as u64 + 2
+        );
+        assert_eq!(staging.unpins.load(Ordering::Relaxed), 2);
+
+        // The pooled buffers stay pinned for the next batches.
+        let buffers: Vec<_> = (0..MAX_POOLED_BUFFERS)
+            .map(|_| pool.take(8, &staging).unwrap())
+            .collect();
+        drop(buffers);
+        assert_eq!(pool.allocated(), MAX_POOLED_BUFFERS as u64 + 2);
+        assert_eq!(staging.unpins.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_transfers_are_rendered_per_device_and_direction() {
+        let metrics = TransferMetrics::default();
+        metrics.record(
+            Device::Cuda(0),
+            Direction::HostToDevice,
+            1024,
+            Duration::from_micros(500),
+        );
+        metrics.record(
+            Device::Cuda(0),
+            Direction::HostToDevice,
+            1024,
+            Duration::from_millis(3),
+        );
+        assert_eq!(
+            metrics.totals(Device::Cuda(0), Direction::HostToDevice),
+            (2, 2048)
+        );
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        assert!(out.contains(
+            "galemind_device_transfer_seconds_bucket{device=\"cuda:0\",direction=\"h2d\",le=\"0.001\"} 1\n"
+        ));
+        assert!(out.contains(
+            "galemind_device_transfer_bytes_total{device=\"cuda:0\",direction=\"h2d\"} 2048\n"
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+/* Validation of request tensors against the input signature of a model.
+
+Before a request is enqueued, its input tensors are compared with the inputs
+the model declares (see `model_metadata`): every declared input must be sent,
+no other, with the declared datatype, rank and dimensions. A declared
+dimension of -1 matches any size. Every mismatch is reported, so a client
+fixes its request in one go; the request is refused with an
+`invalid_tensors` error listing them.
+
+Models declaring no inputs are not checked, nor are requests without input
+tensors, such as those passing their prompt as a parameter.
+*/
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::model_metadata::{ModelSignature, TensorMetadata};
+
+pub const INVALID_TENSORS: &str = "invalid_tensors";
+
+/// One way an input tensor differs from the model's signature.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TensorMismatch {
+    /// Name of the input.
+    pub input: String,
+    /// What differs: `missing`, `unexpected`, `datatype`, `rank` or `shape[<index>]`.
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl TensorMismatch {
+    fn new(
+        input: &str,
+        field: impl Into<String>,
+        expected: impl ToString,
+        actual: impl ToString,
+    ) -> Self {
+        Self {
+            input: input.to_string(),
+            field: field.into(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TensorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "input '{}' {}: expected {}, got {}",
+            self.input, self.field, self.expected, self.actual
+        )
+    }
+}
+
+/// A request refused for input tensors not matching the signature of its model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorRefusal {
+    pub model: String,
+    pub mismatches: Vec<TensorMismatch>,
+}
+
+impl TensorRefusal {
+    pub fn code(&self) -> &'static str {
+        INVALID_TENSORS
+    }
+}
+
+impl fmt::Display for TensorRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mismatches: Vec<String> = self.mismatches.iter().map(ToString::to_string).collect();
+        write!(
+            f,
+            "Inputs do not match the signature of model '{}': {}",
+            self.model,
+            mismatches.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for TensorRefusal {}
+
+impl ModelSignature {
+    /// Compares the input tensors of a request to `model_name` with the declared inputs.
+    pub fn check_inputs(
+        &self,
+        model_name: &str,
+        inputs: &[TensorMetadata],
+    ) -> Result<(), TensorRefusal> {
+        if self.inputs.is_empty() || inputs.is_empty() {
+            return Ok(());
+        }
+        let mut mismatches = Vec::new();
+        for declared in &self.inputs {
+            let Some(input) = inputs.iter().find(|input| input.name == declared.name) else {
+                mismatches.push(TensorMismatch::new(
+                    &declared.name,
+                    "missing",
+                    "an input",
+                    "none",
+                ));
+                continue;
+            };
+            if !input.datatype.eq_ignore_ascii_case(&declared.datatype) {
+                mismatches.push(TensorMismatch::new(
+                    &declared.name,
+                    "datatype",
+                    &declared.datatype,
+                    &input.datatype,
+                ));
+            }
+            if input.shape.len() != declared.shape.len() {
+                mismatches.push(TensorMismatch::new(
+                    &declared.name,
+                    "rank",
+                    declared.shape.len(),
+                    input.shape.len(),
+                ));
+                continue;
+            }
+            for (index, (expected, actual)) in declared.shape.iter().zip(&input.shape).enumerate() {
+                if *expected != -1 && expected != actual {
+                    mismatches.push(TensorMismatch::new(
+                        &declared.name,
+                        format!("shape[{}]", index),
+                        expected,
+                        actual,
+                    ));
+                }
+            }
+        }
+        for input in inputs {
+            if !self
+                .inputs
+                .iter()
+                .any(|declared| declared.name == input.name)
+            {
+                mismatches.push(TensorMismatch::new(
+                    &input.name,
+                    "unexpected",
+                    "no such input",
+                    "an input",
+                ));
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(TensorRefusal {
+                model: model_name.to_string(),
+                mismatches,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tensor(name: &str, datatype: &str, shape: &[i64]) -> TensorMetadata {
+        TensorMetadata {
+            name: name.to_string(),
+            datatype: datatype.to_string(),
+            shape: shape.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_inputs_are_checked_against_the_signature() {
+        let signature = ModelSignature {
+            inputs: vec![
+                tensor("image", "FP32", &[-1, 3, 224, 224]),
+                tensor("scale", "FP32", &[1]),
+            ],
+            outputs: Vec::new(),
+        };
+        assert!(
+            signature
+                .check_inputs(
+                    "resnet",
+                    &[
+                        tensor("image", "fp32", &[8, 3, 224, 224]),
+                        tensor("scale", "FP32", &[1]),
+                    ]
+                )
+                .is_ok()
+        );
+
+        let refusal = signature
+            .check_inputs(
+                "resnet",
+                &[
+                    tensor("image", "INT8", &[8, 3, 224, 200]),
+                    tensor("mask", "BOOL", &[8]),
+                ],
+            )
+            .unwrap_err();
+        let fields: Vec<(&str, &str)> = refusal
+            .mismatches
+            .iter()
+            .map(|mismatch| (mismatch.input.as_str(), mismatch.field.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("image", "datatype"),
+                ("image", "shape[3]"),
+                ("scale", "missing"),
+                ("mask", "unexpected"),
+            ]
+        );
+        assert_eq!(refusal.code(), "invalid_tensors");
+        assert!(
+            refusal
+                .to_string()
+                .contains("input 'image' shape[3]: expected 224, got 200")
+        );
+
+        let refusal = signature
+            .check_inputs(
+                "resnet",
+                &[
+                    tensor("image", "FP32", &[3, 224, 224]),
+                    tensor("scale", "FP32", &[1]),
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(refusal.mismatches[0].field, "rank");
+
+        // Neither models without declared inputs nor requests without tensors are checked.
+        assert!(
+            ModelSignature::default()
+                .check_inputs("any", &[tensor("x", "FP32", &[1])])
+                .is_ok()
+        );
+        assert!(signature.check_inputs("resnet", &[]).is_ok());
+    }
+}
@@ -0,0 +1,459 @@
+/* Audit log of inference requests.
+
+When enabled with `--audit-log`, both servers record every inference request
+they answer, refused ones included: its id, protocol, model and version, the
+identity of the caller, the bytes received and sent, the latency and the status
+it was answered with. Records go to an `AuditSink`:
+
+- a JSON lines file, rotated once it reaches a size (`app.jsonl` becomes
+  `app.jsonl.1`, `app.jsonl.1` becomes `app.jsonl.2`, and so on, keeping a
+  configured number of rotated files);
+- `stdout`, one JSON line per record;
+- `kafka://host:port/topic`, a Kafka topic reached through a Kafka REST proxy
+  listening on `host:port`, one JSON record per message.
+
+Callers are identified by their tenant, else by a fingerprint of their API key
+(the key itself is never written), else by their IP address.
+
+Models may be sampled: a rate of 0.1 records about one request in ten. Sampling
+is decided from the request id, so the records of a request are kept or dropped
+together. Like the analytics tee, the logger never blocks a request: records are
+written by a background thread and dropped, and counted, when the sink falls
+behind.
+*/
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+use crate::kafka::{KafkaProducer, KeyedMessage};
+
+/// Records waiting for the sink before new ones are dropped.
+pub const AUDIT_QUEUE_CAPACITY: usize = 8192;
+
+/// One answered inference request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    pub timestamp_ms: u128,
+    pub request_id: String,
+    /// `rest` or `grpc`.
+    pub protocol: &'static str,
+    pub model: String,
+    pub version: Option<String>,
+    /// `tenant:<name>`, `key:<fingerprint>` or `ip:<address>`, see `caller_identity`.
+    pub caller: Option<String>,
+    pub input_bytes: u64,
+    /// None when the size of the response is not known up front, e.g. for streams.
+    pub output_bytes: Option<u64>,
+    pub latency_ms: f64,
+    /// The HTTP status code, or the gRPC code name.
+    pub status: String,
+}
+
+impl AuditRecord {
+    pub fn new(
+        protocol: &'static str,
+        model: impl Into<String>,
+        request_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
+            request_id: request_id.into(),
+            protocol,
+            model: model.into(),
+            version: None,
+            caller: None,
+            input_bytes: 0,
+            output_bytes: None,
+            latency_ms: 0.0,
+            status: String::new(),
+        }
+    }
+
+    pub fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version.filter(|version| !version.is_empty());
+        self
+    }
+
+    pub fn with_caller(mut self, caller: Option<String>) -> Self {
+        self.caller = caller;
+        self
+    }
+
+    pub fn with_bytes(mut self, input_bytes: u64, output_bytes: Option<u64>) -> Self {
+        self.input_bytes = input_bytes;
+        self.output_bytes = output_bytes;
+        self
+    }
+
+    pub fn with_outcome(mut self, status: impl Into<String>, latency: Duration) -> Self {
+        self.status = status.into();
+        self.latency_ms = latency.as_secs_f64() * 1000.0;
+        self
+    }
+}
+
+/// Identity of a caller for the audit log: its tenant, else a fingerprint of its API key,
+/// else its IP address.
+pub fn caller_identity(
+    tenant: Option<&str>,
+    api_key: Option<&str>,
+    addr: Option<IpAddr>,
+) -> Option<String> {
+    if let Some(tenant) = tenant.filter(|tenant| !tenant.is_empty()) {
+        return Some(format!("tenant:{}", tenant));
+    }
+    if let Some(key) = api_key.filter(|key| !key.is_empty()) {
+        let digest = hex::encode(Sha256::digest(key.as_bytes()));
+        return Some(format!("key:{}", &digest[..16]));
+    }
+    addr.map(|addr| format!("ip:{}", addr))
+}
+
+/// Destination of audit records.
+///
+/// Writes happen on the logger's background thread, so implementations may block.
+pub trait AuditSink: Send + Sync {
+    fn write(&self, record: &AuditRecord) -> Result<()>;
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Prints records as JSON lines on the standard output.
+#[derive(Debug, Default)]
+pub struct StdoutAuditSink;
+
+impl AuditSink for StdoutAuditSink {
+    fn write(&self, record: &AuditRecord) -> Result<()> {
+        println!("{}", serde_json::to_string(record)?);
+        Ok(())
+    }
+}
+
+struct OpenFile {
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+/// Appends records as JSON lines to a file, rotating it once it reaches `max_bytes`.
+pub struct RotatingFileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: Mutex<OpenFile>,
+}
+
+impl RotatingFileSink {
+    /// Opens `path` for appending. Rotated files are `path.1` (the newest) to `path.<keep>`.
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = Self::open_file(&path)?;
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn open_file(path: &Path) -> std::io::Result<OpenFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(OpenFile {
+            writer: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&self, file: &mut OpenFile) -> std::io::Result<()> {
+        file.writer.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        *file = Self::open_file(&self.path)?;
+        Ok(())
+    }
+}
+
+impl AuditSink for RotatingFileSink {
+    fn write(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        if file.size > 0 && file.size + line.len() as u64 > self.max_bytes {
+            self.rotate(&mut file)?;
+        }
+        file.writer.write_all(&line)?;
+        file.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.file.lock().unwrap().writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Produces records to a Kafka topic through a Kafka REST proxy, one JSON record per message,
+/// in one produce request per flush.
+pub struct KafkaRestSink {
+    producer: KafkaProducer,
+    pending: Mutex<Vec<KeyedMessage>>,
+    runtime: Handle,
+}
+
+impl KafkaRestSink {
+    /// Must be called from within a tokio runtime, which sends the records.
+    pub fn new(producer: KafkaProducer) -> Self {
+        Self {
+            producer,
+            pending: Mutex::new(Vec::new()),
+            runtime: Handle::current(),
+        }
+    }
+}
+
+impl AuditSink for KafkaRestSink {
+    fn write(&self, record: &AuditRecord) -> Result<()> {
+        let value = serde_json::to_vec(record)?;
+        self.pending.lock().unwrap().push((None, value));
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let messages = std::mem::take(&mut *self.pending.lock().unwrap());
+        self.runtime.block_on(self.producer.produce(&messages))
+    }
+}
+
+/// Opens the sink described by `spec`: `stdout`, `kafka://host:port/topic` or a file path.
+/// Files are rotated at `rotate_bytes`, keeping `keep` rotated files.
+pub fn open_sink(spec: &str, rotate_bytes: u64, keep: usize) -> Result<Arc<dyn AuditSink>> {
+    if spec == "stdout" || spec == "-" {
+        return Ok(Arc::new(StdoutAuditSink));
+    }
+    if let Some(producer) = KafkaProducer::for_sink(spec)? {
+        return Ok(Arc::new(KafkaRestSink::new(producer)));
+    }
+    if rotate_bytes == 0 {
+        return Err(anyhow!(
+            "Audit files must be rotated at a size of at least 1 byte"
+        ));
+    }
+    Ok(Arc::new(RotatingFileSink::open(spec, rotate_bytes, keep)?))
+}
+
+/// Share of the requests recorded per model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditSampling {
+    /// Rate of the models without their own, between 0 and 1.
+    pub rate: f64,
+    pub models: HashMap<String, f64>,
+}
+
+impl Default for AuditSampling {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            models: HashMap::new(),
+        }
+    }
+}
+
+impl AuditSampling {
+    /// Parses a sampling rate between 0 and 1.
+    pub fn parse_rate(s: &str) -> Result<f64> {
+        let rate: f64 = s
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid sampling rate '{}'", s))?;
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(anyhow!("Sampling rate '{}' must be between 0 and 1", s));
+        }
+        Ok(rate)
+    }
+
+    pub fn rate(&self, model: &str) -> f64 {
+        self.models.get(model).copied().unwrap_or(self.rate)
+    }
+
+    /// Whether the request `request_id` of `model` is recorded.
+    pub fn sampled(&self, model: &str, request_id: &str) -> bool {
+        let rate = self.rate(model);
+        if rate >= 1.0 {
+            return true;
+        }
+        if rate <= 0.0 {
+            return false;
+        }
+        let mut hasher = DefaultHasher::new();
+        request_id.hash(&mut hasher);
+        (hasher.finish() as f64 / u64::MAX as f64) < rate
+    }
+}
+
+/// Cheaply cloneable handle feeding sampled records to a sink without blocking the caller.
+#[derive(Clone)]
+pub struct AuditLogger {
+    sender: mpsc::Sender<AuditRecord>,
+    sampling: Arc<AuditSampling>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditLogger {
+    /// Starts the background writer. Must be called from within a tokio runtime.
+    pub fn spawn(sink: Arc<dyn AuditSink>, sampling: AuditSampling, capacity: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<AuditRecord>(capacity);
+
+        tokio::task::spawn_blocking(move || {
+            while let Some(record) = receiver.blocking_recv() {
+                if let Err(e) = sink.write(&record) {
+                    eprintln!("Failed to write audit record: {}", e);
+                }
+                if receiver.is_empty()
+                    && let Err(e) = sink.flush()
+                {
+                    eprintln!("Failed to flush audit sink: {}", e);
+                }
+            }
+            let _ = sink.flush();
+        });
+
+        Self {
+            sender,
+            sampling: Arc::new(sampling),
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Queues `record` for the sink if its model samples it, dropping it if the sink is
+    /// lagging behind.
+    pub fn log(&self, record: AuditRecord) {
+        if !self.sampling.sampled(&record.model, &record.request_id) {
+            return;
+        }
+        if self.sender.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of sampled records dropped because the sink could not keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for AuditLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLogger")
+            .field("sampling", &self.sampling)
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(request_id: &str) -> AuditRecord {
+        AuditRecord::new("rest", "m", request_id)
+            .with_caller(caller_identity(None, Some("secret"), None))
+            .with_bytes(120, Some(64))
+            .with_outcome("200", Duration::from_millis(5))
+    }
+
+    #[test]
+    fn test_files_rotate_and_keep_the_newest() {
+        let dir = std::env::temp_dir().join(format!("galemind-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+
+        let line = serde_json::to_vec(&record("r0")).unwrap().len() as u64 + 1;
+        let sink = RotatingFileSink::open(&path, line * 2, 2).unwrap();
+        for index in 0..7 {
+            sink.write(&record(&format!("r{}", index))).unwrap();
+        }
+        sink.flush().unwrap();
+
+        let ids = |path: &Path| -> Vec<String> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .map(|record| record["request_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(ids(&path), ["r6"]);
+        assert_eq!(ids(&sink.rotated(1)), ["r4", "r5"]);
+        assert_eq!(ids(&sink.rotated(2)), ["r2", "r3"]);
+        assert!(!sink.rotated(3).exists());
+
+        let written: serde_json::Value =
+            serde_json::from_str(fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!(written["caller"].as_str().unwrap().len(), "key:".len() + 16);
+        assert!(!written.to_string().contains("secret"));
+        assert_eq!(written["input_bytes"], 120);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sampling_is_per_model_and_stable_per_request() {
+        let sampling = AuditSampling {
+            rate: 1.0,
+            models: HashMap::from([("bulk".to_string(), 0.25), ("off".to_string(), 0.0)]),
+        };
+        assert!(sampling.sampled("m", "r"));
+        assert!(!sampling.sampled("off", "r"));
+        let kept = (0..4000)
+            .filter(|index| sampling.sampled("bulk", &format!("r{}", index)))
+            .count();
+        assert!((800..1200).contains(&kept), "kept {}", kept);
+        assert_eq!(
+            sampling.sampled("bulk", "r7"),
+            sampling.sampled("bulk", "r7")
+        );
+
+        assert_eq!(AuditSampling::parse_rate("0.1").unwrap(), 0.1);
+        assert!(AuditSampling::parse_rate("1.5").is_err());
+        assert_eq!(
+            caller_identity(Some("acme"), Some("k"), None).as_deref(),
+            Some("tenant:acme")
+        );
+        assert!(open_sink("kafka://proxy:8082", 1, 1).is_err());
+    }
+}
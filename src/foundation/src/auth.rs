@@ -0,0 +1,470 @@
+/* API key authentication.
+
+When a key store is configured, both servers refuse requests that do not carry
+one of its keys as `Authorization: Bearer <key>` (the `authorization` metadata
+entry on gRPC). Each key has a name, used in refusals, and may be scoped to a list
+of models: such a key can only reach those models, and not the endpoints that
+are not about a model (the admin API for instance). Keys without a list reach
+every model.
+
+Keys come from a YAML (or JSON) file:
+
+    keys:
+      - name: ci
+        key: 9f1c...           # the key itself
+        models: [resnet50]
+      - name: ops
+        sha256: 5e88...        # or its SHA-256 digest, in hex
+
+or from an environment variable holding `;`-separated `<name>=<key>` entries,
+each optionally followed by `:<model>,<model>`. The store only keeps digests of
+the keys, so requests are matched by digest rather than by comparing secrets.
+
+The servers check requests with an `Authenticator`, which also accepts JWTs
+when a `JwtValidator` is configured (see `jwt`). API keys hold every role.
+Refused requests are published on the event bus as `auth_failure` events.
+*/
+
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::events::{EventBus, ServerEvent};
+use crate::jwt::{JwtValidator, Role, is_jwt};
+
+/// Header (or gRPC metadata entry) carrying the API key, as `Bearer <key>`.
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// A key of the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub name: String,
+    /// Models the key may reach; every model when `None`.
+    pub models: Option<HashSet<String>>,
+}
+
+impl ApiKey {
+    pub fn allows(&self, model: &str) -> bool {
+        self.models
+            .as_ref()
+            .is_none_or(|models| models.contains(model))
+    }
+
+    pub fn is_scoped(&self) -> bool {
+        self.models.is_some()
+    }
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// No `Bearer` key in the request.
+    Missing,
+    /// The key is not in the store.
+    Invalid,
+    /// The key is valid but not scoped to this model.
+    ModelNotAllowed { key: String, model: String },
+    /// The key is scoped to models and the endpoint is not about one of them.
+    Scoped { key: String },
+    /// The JWT is malformed, expired, or not signed by a known key.
+    InvalidToken(String),
+    /// The JWT is valid but does not grant the role.
+    MissingRole { subject: String, role: Role },
+}
+
+impl AuthError {
+    /// True when the request carries no valid key at all, as opposed to a valid key
+    /// without the permission.
+    pub fn is_unauthenticated(&self) -> bool {
+        matches!(
+            self,
+            AuthError::Missing | AuthError::Invalid | AuthError::InvalidToken(_)
+        )
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "An API key is required, as 'Bearer <key>'"),
+            AuthError::Invalid => write!(f, "Invalid API key"),
+            AuthError::ModelNotAllowed { key, model } => {
+                write!(f, "API key '{}' may not use model '{}'", key, model)
+            }
+            AuthError::Scoped { key } => {
+                write!(f, "API key '{}' is restricted to its models", key)
+            }
+            AuthError::InvalidToken(reason) => write!(f, "Invalid token: {}", reason),
+            AuthError::MissingRole { subject, role } => {
+                write!(f, "'{}' does not have the {} role", subject, role)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+#[derive(Deserialize)]
+struct KeyFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    name: String,
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    sha256: Option<String>,
+    #[serde(default)]
+    models: Option<Vec<String>>,
+}
+
+/// The key out of an `authorization` value, `Bearer <key>`.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Keys accepted by the servers, by the SHA-256 digest of the key.
+#[derive(Debug, Clone, Default)]
+pub struct KeyStore {
+    keys: HashMap<[u8; 32], ApiKey>,
+}
+
+impl KeyStore {
+    /// Opens the store described by `spec`: `env:<VARIABLE>` or the path of a key file.
+    pub fn open(spec: &str) -> Result<Self> {
+        let store = match spec.strip_prefix("env:") {
+            Some(variable) => {
+                let value = std::env::var(variable)
+                    .with_context(|| format!("Environment variable {} is not set", variable))?;
+                Self::from_env_value(&value)?
+            }
+            None => Self::from_file(Path::new(spec))?,
+        };
+        if store.is_empty() {
+            return Err(anyhow!("No API key found in {}", spec));
+        }
+        Ok(store)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read API keys from {}", path.display()))?;
+        let file: KeyFile = serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid API key file {}", path.display()))?;
+        let mut store = Self::default();
+        for entry in file.keys {
+            let digest = match (entry.key, entry.sha256) {
+                (Some(key), None) => Self::digest(&key),
+                (None, Some(sha256)) => hex::decode(sha256.trim())
+                    .ok()
+                    .and_then(|digest| digest.try_into().ok())
+                    .ok_or_else(|| anyhow!("Key '{}' has an invalid SHA-256 digest", entry.name))?,
+                _ => {
+                    return Err(anyhow!(
+                        "Key '{}' needs exactly one of 'key' and 'sha256'",
+                        entry.name
+                    ));
+                }
+            };
+            store.insert_digest(digest, entry.name, entry.models)?;
+        }
+        Ok(store)
+    }
+
+    /// Parses `<name>=<key>[:<model>,<model>]` entries separated by `;`.
+    pub fn from_env_value(value: &str) -> Result<Self> {
+        let mut store = Self::default();
+        for entry in value.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, key) = entry.split_once('=').ok_or_else(|| {
+                anyhow!("Invalid API key entry, expected <name>=<key>[:<models>]")
+            })?;
+            let (key, models) = match key.split_once(':') {
+                Some((key, models)) => (
+                    key,
+                    Some(models.split(',').map(|m| m.trim().to_string()).collect()),
+                ),
+                None => (key, None),
+            };
+            if key.is_empty() {
+                return Err(anyhow!("API key '{}' is empty", name));
+            }
+            store.insert(name, key, models)?;
+        }
+        Ok(store)
+    }
+
+    /// Adds `key` under `name`, scoped to `models` when given.
+    pub fn insert(&mut self, name: &str, key: &str, models: Option<Vec<String>>) -> Result<()> {
+        self.insert_digest(Self::digest(key), name.to_string(), models)
+    }
+
+    fn insert_digest(
+        &mut self,
+        digest: [u8; 32],
+        name: String,
+        models: Option<Vec<String>>,
+    ) -> Result<()> {
+        if self.keys.values().any(|key| key.name == name) {
+            return Err(anyhow!("API key '{}' is defined twice", name));
+        }
+        let models = models.map(|models| models.into_iter().collect());
+        if self.keys.insert(digest, ApiKey { name, models }).is_some() {
+            return Err(anyhow!("The same API key is defined twice"));
+        }
+        Ok(())
+    }
+
+    fn digest(key: &str) -> [u8; 32] {
+        Sha256::digest(key.as_bytes()).into()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The key of a request from its `authorization` value.
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<&ApiKey, AuthError> {
+        let token = authorization
+            .and_then(bearer_token)
+            .ok_or(AuthError::Missing)?;
+        self.keys
+            .get(&Self::digest(token))
+            .ok_or(AuthError::Invalid)
+    }
+
+    /// Authenticates a request for `model`.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        model: &str,
+    ) -> Result<&ApiKey, AuthError> {
+        let key = self.authenticate(authorization)?;
+        if !key.allows(model) {
+            return Err(AuthError::ModelNotAllowed {
+                key: key.name.clone(),
+                model: model.to_string(),
+            });
+        }
+        Ok(key)
+    }
+
+    /// Authenticates a request for an endpoint that is not about a single model, which
+    /// scoped keys may not use.
+    pub fn authorize_unscoped(&self, authorization: Option<&str>) -> Result<&ApiKey, AuthError> {
+        let key = self.authenticate(authorization)?;
+        if key.is_scoped() {
+            return Err(AuthError::Scoped {
+                key: key.name.clone(),
+            });
+        }
+        Ok(key)
+    }
+}
+
+/// The account a request was authenticated as: the subject of its token or the name of
+/// its key. The servers attach it to the requests they authorized, for the limits and
+/// budgets keyed on the caller.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Authenticated(pub String);
+
+/// What a request reaches, which decides the API keys allowed to make it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// Nothing about a model in particular: server metadata, results of past requests.
+    Server,
+    /// A single model.
+    Model(&'a str),
+    /// Every model, like the admin API: scoped keys are refused.
+    AllModels,
+}
+
+/// Checks the `authorization` of requests against the API keys and tokens configured.
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    keys: Option<Arc<KeyStore>>,
+    jwt: Option<Arc<JwtValidator>>,
+    events: Option<Arc<EventBus>>,
+}
+
+impl Authenticator {
+    /// `None` when neither keys nor tokens are configured, and requests need no credentials.
+    pub fn new(keys: Option<Arc<KeyStore>>, jwt: Option<Arc<JwtValidator>>) -> Option<Self> {
+        (keys.is_some() || jwt.is_some()).then_some(Self {
+            keys,
+            jwt,
+            events: None,
+        })
+    }
+
+    /// Publishes every refused request on `events`.
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Authorizes a request needing `role` on `target`. Tokens are checked for the role,
+    /// API keys for their scope. Returns the account of the caller: the subject of the
+    /// token or the name of the key.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        role: Role,
+        target: Target<'_>,
+    ) -> Result<String, AuthError> {
+        let authorized = self.check(authorization, role, target);
+        if let (Err(error), Some(events)) = (&authorized, &self.events) {
+            events.publish(ServerEvent::AuthFailure {
+                role: role.to_string(),
+                model: match target {
+                    Target::Model(model) => Some(model.to_string()),
+                    Target::Server | Target::AllModels => None,
+                },
+                unauthenticated: error.is_unauthenticated(),
+                reason: error.to_string(),
+            });
+        }
+        authorized
+    }
+
+    fn check(
+        &self,
+        authorization: Option<&str>,
+        role: Role,
+        target: Target<'_>,
+    ) -> Result<String, AuthError> {
+        if let Some(jwt) = &self.jwt {
+            let token = authorization
+                .and_then(bearer_token)
+                .ok_or(AuthError::Missing)?;
+            if is_jwt(token) {
+                let principal = jwt.validate(token)?;
+                principal.require(role)?;
+                return Ok(principal.subject);
+            }
+        }
+        let Some(keys) = &self.keys else {
+            return Err(AuthError::Invalid);
+        };
+        let key = match target {
+            Target::Server => keys.authenticate(authorization),
+            Target::Model(model) => keys.authorize(authorization, model),
+            Target::AllModels => keys.authorize_unscoped(authorization),
+        }?;
+        Ok(key.name.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_matched_by_digest_and_scoped_to_models() {
+        let dir = std::env::temp_dir().join(format!("galemind-auth-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keys.yaml");
+        let ops_digest = hex::encode(Sha256::digest(b"ops-secret"));
+        std::fs::write(
+            &path,
+            format!(
+                "keys:\n  - name: ci\n    key: ci-secret\n    models: [resnet]\n  - name: ops\n    sha256: {}\n",
+                ops_digest
+            ),
+        )
+        .unwrap();
+        let store = KeyStore::from_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.authenticate(None), Err(AuthError::Missing));
+        assert_eq!(
+            store.authenticate(Some("Basic Y2k6Y2k=")),
+            Err(AuthError::Missing)
+        );
+        assert_eq!(
+            store.authenticate(Some("Bearer nope")),
+            Err(AuthError::Invalid)
+        );
+        let ci = store.authorize(Some("bearer ci-secret"), "resnet").unwrap();
+        assert_eq!(ci.name, "ci");
+        assert!(matches!(
+            store.authorize(Some("Bearer ci-secret"), "bert"),
+            Err(AuthError::ModelNotAllowed { .. })
+        ));
+        assert!(matches!(
+            store.authorize_unscoped(Some("Bearer ci-secret")),
+            Err(AuthError::Scoped { .. })
+        ));
+        assert_eq!(
+            store
+                .authorize_unscoped(Some("Bearer ops-secret"))
+                .unwrap()
+                .name,
+            "ops"
+        );
+
+        let store = KeyStore::from_env_value("a=k1:m1, m2; b=k2").unwrap();
+        assert!(store.authorize(Some("Bearer k1"), "m2").is_ok());
+        assert!(store.authorize(Some("Bearer k2"), "anything").is_ok());
+        assert!(KeyStore::from_env_value("a=k1;b=k1").is_err());
+        assert!(KeyStore::from_env_value("a").is_err());
+    }
+
+    #[test]
+    fn test_refused_requests_are_published() {
+        struct Recording(std::sync::Mutex<Vec<ServerEvent>>);
+        impl crate::events::EventObserver for Recording {
+            fn observe(&self, event: &ServerEvent) {
+                self.0.lock().unwrap().push(event.clone());
+            }
+        }
+        let events = Arc::new(EventBus::empty());
+        let recording = Arc::new(Recording(Default::default()));
+        events.subscribe(recording.clone());
+        let keys = Arc::new(KeyStore::from_env_value("ci=k1:resnet").unwrap());
+        let authenticator = Authenticator::new(Some(keys), None)
+            .unwrap()
+            .with_events(events);
+
+        assert!(
+            authenticator
+                .authorize(Some("Bearer k1"), Role::Infer, Target::Model("resnet"))
+                .is_ok()
+        );
+        assert!(
+            authenticator
+                .authorize(Some("Bearer k1"), Role::Infer, Target::Model("bert"))
+                .is_err()
+        );
+        assert!(
+            authenticator
+                .authorize(None, Role::Admin, Target::AllModels)
+                .is_err()
+        );
+
+        let published = recording.0.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert!(matches!(
+            &published[0],
+            ServerEvent::AuthFailure { model: Some(model), unauthenticated: false, .. }
+                if model == "bert"
+        ));
+        assert!(matches!(
+            &published[1],
+            ServerEvent::AuthFailure { role, model: None, unauthenticated: true, .. }
+                if role == "admin"
+        ));
+    }
+}
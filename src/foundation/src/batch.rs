@@ -0,0 +1,961 @@
+/* Batch prediction jobs over files.
+
+A job runs a model over every row of an input file in the background:
+`BatchJobs::submit` accepts it and answers at once with its status, which is
+then followed with `BatchJobs::get`.
+
+Inputs are read from a local path or an object store URI (`s3://`, `gs://`,
+`az://`, with the credentials of model sources, see `ModelSource`), in one of
+the `InputFormat`s, guessed from the extension of the file unless given:
+
+- JSONL: one JSON object per line.
+- CSV: a header naming the columns, then one row per line. Fields that parse
+  as numbers are numbers, the others strings; empty fields are missing.
+- Parquet: the columns of the file, compressed with any of its codecs. Nulls
+  are missing, lists are array values, and other types (dates, decimals) are
+  strings.
+
+Each column is the input of the model of the same name. Rows are sent in
+chunks of `batch_size`, one request per chunk, with inputs of shape `[rows]`,
+or `[rows, n]` for array values, and the output of a chunk is split back into
+rows. Requests are of low priority, so jobs give way to interactive traffic,
+and are counted under the `batch` route of the statistics.
+
+Results are appended to `<dir>/<id>/output.jsonl`, one line per row in input
+order: `{"row": 0, "outputs": {"output_1": [...]}}`, or `{"row": 0, "error":
+"..."}` for the rows of a chunk that failed. A failed chunk does not fail the
+job; an input that cannot be read or parsed does.
+
+After each chunk, the status of the job, with the rows done and the length of
+its output, is checkpointed to `<dir>/<id>/job.json`. Jobs the server was
+running when it stopped are resumed by `BatchJobs::resume` from their last
+checkpoint, their output first truncated to its checkpointed length.
+*/
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use dashmap::DashMap;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+use crate::api::inference::{InferenceOutput, InferenceRequest, InferenceResponse};
+use crate::api::tensor::{Data, DataType};
+use crate::ids::{IdProvider, IdScheme};
+use crate::log_info;
+use crate::model::model_discovery_service::{ModelDiscoveryService, ModelId, ModelSource};
+use crate::model::priority::Priority;
+
+/// Statistics route of the requests of batch jobs.
+const ROUTE: &str = "batch";
+/// Rows per request when the job does not say.
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+const CHECKPOINT_FILE: &str = "job.json";
+const OUTPUT_FILE: &str = "output.jsonl";
+
+/// Format of the input file of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+impl InputFormat {
+    /// The format of `input`, from its extension.
+    pub fn guess(input: &str) -> Result<Self> {
+        let extension = Path::new(input)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            "csv" => Ok(InputFormat::Csv),
+            "parquet" => Ok(InputFormat::Parquet),
+            _ => Err(anyhow!(
+                "Cannot tell the format of '{}' from its extension, expected jsonl, csv or \
+                 parquet",
+                input
+            )),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            "csv" => Ok(InputFormat::Csv),
+            "parquet" => Ok(InputFormat::Parquet),
+            _ => Err(anyhow!(
+                "Unknown input format '{}', expected jsonl, csv or parquet",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputFormat::Jsonl => f.write_str("jsonl"),
+            InputFormat::Csv => f.write_str("csv"),
+            InputFormat::Parquet => f.write_str("parquet"),
+        }
+    }
+}
+
+fn default_batch_size() -> usize {
+    DEFAULT_BATCH_SIZE
+}
+
+/// A job, as submitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchJobSpec {
+    pub model_name: String,
+    /// The version serving the model at submission when missing.
+    #[serde(default)]
+    pub model_version: Option<String>,
+    /// Local path or object store URI of the input file.
+    pub input: String,
+    /// `jsonl`, `csv` or `parquet`, guessed from the extension of `input` when missing.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Rows per request.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed)
+    }
+}
+
+/// Progress of a job, as reported and checkpointed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchJobStatus {
+    pub id: String,
+    pub state: JobState,
+    pub model_name: String,
+    pub model_version: String,
+    pub input: String,
+    pub format: InputFormat,
+    pub batch_size: usize,
+    /// Rows of the input, once it is read.
+    pub rows_total: Option<usize>,
+    /// Rows whose result is in the output, failed ones included.
+    pub rows_done: usize,
+    pub rows_failed: usize,
+    /// Path of the output file.
+    pub output: PathBuf,
+    /// Length of the output at the last checkpoint.
+    pub output_bytes: u64,
+    /// Why the job failed.
+    pub error: Option<String>,
+    /// Unix time of the submission, in seconds.
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Jobs submitted to the server, checkpointed under a directory.
+pub struct BatchJobs {
+    dir: PathBuf,
+    jobs: DashMap<String, BatchJobStatus>,
+    model_manager: Arc<ModelDiscoveryService>,
+    ids: Arc<dyn IdProvider>,
+}
+
+impl BatchJobs {
+    pub fn new(dir: impl Into<PathBuf>, model_manager: Arc<ModelDiscoveryService>) -> Self {
+        Self {
+            dir: dir.into(),
+            jobs: DashMap::new(),
+            model_manager,
+            ids: IdScheme::default().provider(),
+        }
+    }
+
+    /// Generates job ids with `ids` instead of the default UUIDv7 provider.
+    pub fn with_id_provider(mut self, ids: Arc<dyn IdProvider>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Accepts `spec` and starts running it in the background. Refuses specs naming no
+    /// served model, or of an unknown format.
+    pub fn submit(self: &Arc<Self>, spec: BatchJobSpec) -> Result<BatchJobStatus> {
+        if spec.batch_size == 0 {
+            return Err(anyhow!("The batch size must be at least 1"));
+        }
+        let format = match &spec.format {
+            Some(format) => format.parse()?,
+            None => InputFormat::guess(&spec.input)?,
+        };
+        let model_version = self
+            .model_manager
+            .resolve_version(
+                &ModelId(spec.model_name.clone()),
+                spec.model_version.as_deref(),
+            )?
+            .ok_or_else(|| anyhow!("Model '{}' has no loaded versions", spec.model_name))?
+            .version;
+
+        let id = self.ids.next_id();
+        let job_dir = self.dir.join(&id);
+        fs::create_dir_all(&job_dir)
+            .map_err(|e| anyhow!("Cannot create {}: {}", job_dir.display(), e))?;
+        let status = BatchJobStatus {
+            id: id.clone(),
+            state: JobState::Pending,
+            model_name: spec.model_name,
+            model_version,
+            input: spec.input,
+            format,
+            batch_size: spec.batch_size,
+            rows_total: None,
+            rows_done: 0,
+            rows_failed: 0,
+            output: job_dir.join(OUTPUT_FILE),
+            output_bytes: 0,
+            error: None,
+            created_at: now(),
+            finished_at: None,
+        };
+        checkpoint(&self.dir, &status)?;
+        self.jobs.insert(id.clone(), status.clone());
+        log_info!(
+            "Batch job {} running {} version {} over {}",
+            id,
+            status.model_name,
+            status.model_version,
+            status.input
+        );
+        tokio::spawn(self.clone().run(id));
+        Ok(status)
+    }
+
+    pub fn get(&self, id: &str) -> Option<BatchJobStatus> {
+        self.jobs.get(id).map(|status| status.clone())
+    }
+
+    /// Every job, oldest first.
+    pub fn list(&self) -> Vec<BatchJobStatus> {
+        let mut jobs: Vec<BatchJobStatus> = self.jobs.iter().map(|status| status.clone()).collect();
+        jobs.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        jobs
+    }
+
+    /// Loads the checkpointed jobs and resumes the unfinished ones. Returns how many were
+    /// resumed.
+    pub fn resume(self: &Arc<Self>) -> Result<usize> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(0);
+        };
+        let mut resumed = 0;
+        for entry in entries {
+            let path = entry?.path().join(CHECKPOINT_FILE);
+            if !path.is_file() {
+                continue;
+            }
+            let status: BatchJobStatus = match fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            {
+                Ok(status) => status,
+                Err(e) => {
+                    eprintln!("Skipping batch job checkpoint {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let id = status.id.clone();
+            let finished = status.state.is_finished();
+            self.jobs.insert(id.clone(), status);
+            if !finished {
+                tokio::spawn(self.clone().run(id));
+                resumed += 1;
+            }
+        }
+        if resumed > 0 {
+            log_info!("Resumed {} batch jobs from {}", resumed, self.dir.display());
+        }
+        Ok(resumed)
+    }
+
+    /// Applies `change` to the status of job `id` and checkpoints it.
+    fn update(&self, id: &str, change: impl FnOnce(&mut BatchJobStatus)) -> Result<BatchJobStatus> {
+        let status = {
+            let mut status = self
+                .jobs
+                .get_mut(id)
+                .ok_or_else(|| anyhow!("Batch job {} not found", id))?;
+            change(&mut status);
+            status.clone()
+        };
+        checkpoint(&self.dir, &status)?;
+        Ok(status)
+    }
+
+    async fn run(self: Arc<Self>, id: String) {
+        let outcome = match self.process(&id).await {
+            Ok(()) => self.update(&id, |status| {
+                status.state = JobState::Succeeded;
+                status.finished_at = Some(now());
+            }),
+            Err(e) => self.update(&id, |status| {
+                status.state = JobState::Failed;
+                status.error = Some(e.to_string());
+                status.finished_at = Some(now());
+            }),
+        };
+        match outcome {
+            Ok(status) => log_info!(
+                "Batch job {} {:?}: {} rows done, {} failed",
+                id,
+                status.state,
+                status.rows_done,
+                status.rows_failed
+            ),
+            Err(e) => eprintln!("Failed to checkpoint batch job {}: {}", id, e),
+        }
+    }
+
+    /// Runs the rows of job `id` not done yet.
+    async fn process(&self, id: &str) -> Result<()> {
+        let status = self
+            .get(id)
+            .ok_or_else(|| anyhow!("Batch job {} not found", id))?;
+        let rows = parse_rows(status.format, read_input(&status.input).await?)?;
+
+        let mut output = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&status.output)
+            .await?;
+        // Rows written after the last checkpoint are run again.
+        output.set_len(status.output_bytes).await?;
+        let status = self.update(id, |status| {
+            status.state = JobState::Running;
+            status.rows_total = Some(rows.len());
+        })?;
+
+        let mut first_row = status.rows_done;
+        for chunk in rows[first_row.min(rows.len())..].chunks(status.batch_size) {
+            let (lines, failed) = self.score(&status, first_row, chunk).await;
+            output.write_all(lines.as_bytes()).await?;
+            output.sync_data().await?;
+            first_row += chunk.len();
+            self.update(id, |status| {
+                status.rows_done = first_row;
+                status.rows_failed += failed;
+                status.output_bytes += lines.len() as u64;
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The output lines of `rows`, starting at `first_row`, and how many failed.
+    async fn score(
+        &self,
+        status: &BatchJobStatus,
+        first_row: usize,
+        rows: &[Map<String, Value>],
+    ) -> (String, usize) {
+        let request = chunk_request(status, first_row, rows);
+        let output = match request {
+            Ok(request) => self.infer(request).await,
+            Err(e) => Err(e),
+        };
+        match output.and_then(|output| split_output(output, rows.len())) {
+            Ok(results) => (
+                results
+                    .into_iter()
+                    .enumerate()
+                    .map(|(offset, result)| {
+                        format!(
+                            "{}\n",
+                            json!({ "row": first_row + offset, "outputs": result })
+                        )
+                    })
+                    .collect(),
+                0,
+            ),
+            Err(e) => (
+                (first_row..first_row + rows.len())
+                    .map(|row| format!("{}\n", json!({ "row": row, "error": e.to_string() })))
+                    .collect(),
+                rows.len(),
+            ),
+        }
+    }
+
+    async fn infer(&self, request: InferenceRequest) -> Result<InferenceOutput> {
+        let model_name = request.model_name.clone();
+        let version = request.model_version.clone().unwrap_or_default();
+        let started = Instant::now();
+        let response = self
+            .model_manager
+            .add_request(ModelId(model_name.clone()), request)
+            .await?
+            .await
+            .map_err(|_| {
+                anyhow!("Request was dropped from the full request buffer of the model")
+            })?;
+        self.model_manager.stats().record(
+            &model_name,
+            &version,
+            ROUTE,
+            started.elapsed(),
+            matches!(response, InferenceResponse::Ok(_)),
+        );
+        match response {
+            InferenceResponse::Ok(output) => Ok(output),
+            InferenceResponse::Error(e) | InferenceResponse::DeadlineExceeded(e) => {
+                Err(anyhow!(e.error))
+            }
+        }
+    }
+}
+
+/// Writes `status` to the checkpoint of its job, replacing the previous one at once.
+fn checkpoint(dir: &Path, status: &BatchJobStatus) -> Result<()> {
+    let path = dir.join(&status.id).join(CHECKPOINT_FILE);
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, serde_json::to_vec_pretty(status)?)?;
+    fs::rename(&partial, &path)?;
+    Ok(())
+}
+
+/// Contents of the input file at `uri`.
+async fn read_input(uri: &str) -> Result<Vec<u8>> {
+    let source = ModelSource::from_uri(uri)?.with_env_credentials();
+    if let ModelSource::Path(path) = &source {
+        return tokio::fs::read(path)
+            .await
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e));
+    }
+    let (client, _, key) = source.object_store().ok_or_else(|| {
+        anyhow!(
+            "Unsupported input '{}', expected a path or an s3://, gs:// or az:// URI",
+            uri
+        )
+    })?;
+    client.get_object(&key).await
+}
+
+/// The rows of an input file in `format`.
+fn parse_rows(format: InputFormat, bytes: Vec<u8>) -> Result<Vec<Map<String, Value>>> {
+    let text = || std::str::from_utf8(&bytes).map_err(|e| anyhow!("The input is not UTF-8: {}", e));
+    match format {
+        InputFormat::Jsonl => parse_jsonl(text()?),
+        InputFormat::Csv => parse_csv(text()?),
+        InputFormat::Parquet => parse_parquet(Bytes::from(bytes)),
+    }
+}
+
+fn parse_jsonl(text: &str) -> Result<Vec<Map<String, Value>>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow!("Line {} is not a JSON object: {}", index + 1, e))
+        })
+        .collect()
+}
+
+/// The rows of a Parquet file, written as JSON lines by the Arrow writer, which leaves
+/// nulls out.
+fn parse_parquet(bytes: Bytes) -> Result<Vec<Map<String, Value>>> {
+    let batches = ParquetRecordBatchReaderBuilder::try_new(bytes)
+        .and_then(|builder| builder.build())
+        .map_err(|e| anyhow!("The input is not a Parquet file: {}", e))?;
+    let mut writer = arrow_json::LineDelimitedWriter::new(Vec::new());
+    for batch in batches {
+        let batch = batch.map_err(|e| anyhow!("Cannot read the Parquet input: {}", e))?;
+        writer.write(&batch)?;
+    }
+    writer.finish()?;
+    parse_jsonl(std::str::from_utf8(&writer.into_inner())?)
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Map<String, Value>>> {
+    let mut records = csv_records(text)?.into_iter();
+    let Some(header) = records.next() else {
+        return Ok(Vec::new());
+    };
+    records
+        .enumerate()
+        .map(|(index, fields)| {
+            if fields.len() != header.len() {
+                return Err(anyhow!(
+                    "Row {} has {} fields, the header {}",
+                    index + 1,
+                    fields.len(),
+                    header.len()
+                ));
+            }
+            Ok(header
+                .iter()
+                .zip(fields)
+                .filter(|(_, field)| !field.is_empty())
+                .map(|(column, field)| {
+                    let value = match field.parse::<f64>() {
+                        Ok(number) => json!(number),
+                        Err(_) => Value::String(field),
+                    };
+                    (column.clone(), value)
+                })
+                .collect())
+        })
+        .collect()
+}
+
+/// The fields of each record of `text`, as RFC 4180 has them: fields may be quoted, with
+/// quotes doubled, and hold commas and line breaks. Blank lines are skipped.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                if !(record.len() == 1 && record[0].is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("The input ends inside a quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The request running `rows` of the job of `status`: one input per column.
+fn chunk_request(
+    status: &BatchJobStatus,
+    first_row: usize,
+    rows: &[Map<String, Value>],
+) -> Result<InferenceRequest> {
+    let columns: Vec<&String> = rows
+        .first()
+        .map(|row| row.keys().collect())
+        .unwrap_or_default();
+    let inputs = columns
+        .into_iter()
+        .map(|column| {
+            let values = rows
+                .iter()
+                .enumerate()
+                .map(|(offset, row)| {
+                    row.get(column).ok_or_else(|| {
+                        anyhow!("Row {} has no value for '{}'", first_row + offset, column)
+                    })
+                })
+                .collect::<Result<Vec<&Value>>>()?;
+            column_input(column, &values)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(InferenceRequest {
+        model_name: status.model_name.clone(),
+        model_version: Some(status.model_version.clone()),
+        id: format!("{}-{}", status.id, first_row),
+        parameters: Some(HashMap::new()),
+        outputs: (!inputs.is_empty()).then_some(inputs),
+        timeline: None,
+        priority: Priority::Low,
+        deadline: None,
+        tenant: None,
+        sampling: Default::default(),
+    })
+}
+
+/// The input `name` holding `values`, one per row: numbers or strings, or arrays of the
+/// same length of either.
+fn column_input(name: &str, values: &[&Value]) -> Result<InferenceOutput> {
+    let mixed = || anyhow!("Column '{}' mixes types or array lengths", name);
+    let (elements, width) = match values.first() {
+        Some(Value::Array(first)) => {
+            let mut elements = Vec::with_capacity(values.len() * first.len());
+            for value in values {
+                match value {
+                    Value::Array(array) if array.len() == first.len() => {
+                        elements.extend(array.iter())
+                    }
+                    _ => return Err(mixed()),
+                }
+            }
+            (elements, Some(first.len()))
+        }
+        _ => (values.to_vec(), None),
+    };
+    let data = if elements.iter().all(|element| element.is_number()) {
+        Data::VFLOAT(
+            elements
+                .iter()
+                .filter_map(|element| element.as_f64())
+                .collect(),
+        )
+    } else if elements.iter().all(|element| element.is_string()) {
+        Data::VSTRING(
+            elements
+                .iter()
+                .filter_map(|element| element.as_str().map(str::to_string))
+                .collect(),
+        )
+    } else {
+        return Err(mixed());
+    };
+    Ok(InferenceOutput {
+        name: name.to_string(),
+        shape: std::iter::once(values.len()).chain(width).collect(),
+        datatype: if matches!(data, Data::VSTRING(_)) {
+            DataType::VSTRING
+        } else {
+            DataType::VFLOAT
+        },
+        parameters: None,
+        data,
+    })
+}
+
+/// The outputs of each of `rows` rows, as JSON: the output of the chunk split in as many
+/// parts.
+fn split_output(output: InferenceOutput, rows: usize) -> Result<Vec<Value>> {
+    let elements: Vec<Value> = match output.data {
+        Data::VFLOAT(values) => values.into_iter().map(|value| json!(value)).collect(),
+        Data::VRAW(view) => view
+            .to_f64()
+            .into_iter()
+            .map(|value| json!(value))
+            .collect(),
+        Data::VSTRING(values) => values.into_iter().map(Value::String).collect(),
+        Data::VBYTES(values) => values
+            .iter()
+            .map(|value| Value::String(String::from_utf8_lossy(value).into_owned()))
+            .collect(),
+    };
+    if rows == 0 || !elements.len().is_multiple_of(rows) {
+        return Err(anyhow!(
+            "Output '{}' of {} elements cannot be split into {} rows",
+            output.name,
+            elements.len(),
+            rows
+        ));
+    }
+    Ok(elements
+        .chunks(elements.len() / rows)
+        .map(|row| json!({ output.name.clone(): row }))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::{InferenceError, InferenceProcessor};
+    use crate::api::inference_runtime::ProcessorRuntime;
+    use crate::model::model_discovery_service::ModelVersionId;
+    use std::time::Duration;
+
+    /// Doubles the numbers of input `x`.
+    struct Doubler;
+
+    impl InferenceProcessor for Doubler {
+        fn process(&self, request: InferenceRequest) -> InferenceResponse {
+            let input = request
+                .outputs
+                .unwrap_or_default()
+                .into_iter()
+                .find(|input| input.name == "x");
+            match input.as_ref().and_then(|input| input.data.float_values()) {
+                Some(values) => InferenceResponse::Ok(InferenceOutput {
+                    name: "y".to_string(),
+                    shape: input
+                        .as_ref()
+                        .map(|input| input.shape.clone())
+                        .unwrap_or_default(),
+                    datatype: DataType::VFLOAT,
+                    parameters: None,
+                    data: Data::VFLOAT(values.iter().map(|value| value * 2.0).collect()),
+                }),
+                None => InferenceResponse::Error(InferenceError {
+                    error: "No numeric input 'x'".to_string(),
+                }),
+            }
+        }
+    }
+
+    fn jobs(name: &str) -> (Arc<BatchJobs>, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("galemind-batch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", Doubler)),
+        );
+        (Arc::new(BatchJobs::new(dir.join("jobs"), service)), dir)
+    }
+
+    async fn finished(jobs: &BatchJobs, id: &str) -> BatchJobStatus {
+        for _ in 0..200 {
+            let status = jobs.get(id).unwrap();
+            if status.state.is_finished() {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("batch job {} did not finish", id);
+    }
+
+    fn output_lines(status: &BatchJobStatus) -> Vec<Value> {
+        fs::read_to_string(&status.output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_input_format() {
+        assert_eq!(
+            InputFormat::guess("s3://bucket/rows.JSONL").unwrap(),
+            InputFormat::Jsonl
+        );
+        assert_eq!(
+            InputFormat::guess("/data/rows.csv").unwrap(),
+            InputFormat::Csv
+        );
+        assert_eq!(
+            InputFormat::guess("/data/rows.parquet").unwrap(),
+            InputFormat::Parquet
+        );
+        assert!(InputFormat::guess("/data/rows").is_err());
+        assert_eq!("ndjson".parse::<InputFormat>().unwrap(), InputFormat::Jsonl);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let rows = parse_csv("x,label\n1.5,\"a, \"\"quoted\"\"\nlabel\"\r\n\n2,\n").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["x"], json!(1.5));
+        assert_eq!(rows[0]["label"], json!("a, \"quoted\"\nlabel"));
+        assert!(!rows[1].contains_key("label"));
+        assert!(parse_csv("x,y\n1\n").is_err());
+        assert!(parse_csv("x\n\"open\n").is_err());
+    }
+
+    /// A Parquet file of `batch`, compressed with Snappy.
+    fn parquet(batch: &arrow_array::RecordBatch) -> Vec<u8> {
+        use parquet::arrow::ArrowWriter;
+        use parquet::basic::Compression;
+        use parquet::file::properties::WriterProperties;
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let mut bytes = Vec::new();
+        let mut writer =
+            ArrowWriter::try_new(&mut bytes, batch.schema(), Some(properties)).unwrap();
+        writer.write(batch).unwrap();
+        writer.close().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_parse_parquet() {
+        use arrow_array::types::Int32Type;
+        use arrow_array::{ArrayRef, Float64Array, ListArray, RecordBatch, StringArray};
+
+        let columns: [(&str, ArrayRef); 3] = [
+            ("x", Arc::new(Float64Array::from(vec![Some(1.5), None]))),
+            ("label", Arc::new(StringArray::from(vec!["a", "b"]))),
+            (
+                "pair",
+                Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                    Some(vec![Some(1), Some(2)]),
+                    Some(vec![Some(3), Some(4)]),
+                ])),
+            ),
+        ];
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let rows = parse_rows(InputFormat::Parquet, parquet(&batch)).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["x"], json!(1.5));
+        assert_eq!(rows[0]["label"], json!("a"));
+        assert_eq!(rows[1]["pair"], json!([3, 4]));
+        assert!(!rows[1].contains_key("x"));
+
+        let error = parse_rows(InputFormat::Parquet, b"x,y\n1,2\n".to_vec()).unwrap_err();
+        assert!(error.to_string().contains("not a Parquet file"));
+    }
+
+    #[test]
+    fn test_column_inputs_and_split_outputs() {
+        let input = column_input("x", &[&json!([1, 2]), &json!([3, 4])]).unwrap();
+        assert_eq!(input.shape, vec![2, 2]);
+        assert!(column_input("x", &[&json!([1, 2]), &json!([3])]).is_err());
+        assert!(column_input("x", &[&json!(1), &json!("a")]).is_err());
+        let strings = column_input("s", &[&json!("a"), &json!("b")]).unwrap();
+        assert!(matches!(strings.datatype, DataType::VSTRING));
+
+        let rows = split_output(input, 2).unwrap();
+        assert_eq!(rows[1], json!({"x": [3.0, 4.0]}));
+        let odd = column_input("x", &[&json!(1), &json!(2), &json!(3)]).unwrap();
+        assert!(split_output(odd, 2).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_run_every_row_and_record_failed_chunks() {
+        let (jobs, dir) = jobs("run");
+        let input = dir.join("rows.jsonl");
+        fs::write(
+            &input,
+            "{\"x\": 1}\n{\"x\": 2}\n{\"x\": 3}\n{\"z\": 4}\n{\"x\": 5}\n",
+        )
+        .unwrap();
+        let status = jobs
+            .submit(BatchJobSpec {
+                model_name: "m".to_string(),
+                model_version: None,
+                input: input.display().to_string(),
+                format: None,
+                batch_size: 2,
+            })
+            .unwrap();
+        assert_eq!(status.model_version, "1");
+
+        let status = finished(&jobs, &status.id).await;
+        assert_eq!(status.state, JobState::Succeeded);
+        assert_eq!(status.rows_total, Some(5));
+        assert_eq!((status.rows_done, status.rows_failed), (5, 2));
+        let lines = output_lines(&status);
+        assert_eq!(lines[0], json!({"row": 0, "outputs": {"y": [2.0]}}));
+        assert!(lines[2]["error"].is_string() && lines[3]["error"].is_string());
+        assert_eq!(lines[4], json!({"row": 4, "outputs": {"y": [10.0]}}));
+
+        let input = dir.join("rows.parquet");
+        let x: arrow_array::ArrayRef = Arc::new(arrow_array::Int64Array::from(vec![1, 2, 3]));
+        let batch = arrow_array::RecordBatch::try_from_iter([("x", x)]).unwrap();
+        fs::write(&input, parquet(&batch)).unwrap();
+        let status = jobs
+            .submit(BatchJobSpec {
+                model_name: "m".to_string(),
+                model_version: None,
+                input: input.display().to_string(),
+                format: None,
+                batch_size: 2,
+            })
+            .unwrap();
+        assert_eq!(status.format, InputFormat::Parquet);
+        let status = finished(&jobs, &status.id).await;
+        assert_eq!((status.rows_done, status.rows_failed), (3, 0));
+        assert_eq!(
+            output_lines(&status)[2],
+            json!({"row": 2, "outputs": {"y": [6.0]}})
+        );
+
+        let missing = jobs.submit(BatchJobSpec {
+            model_name: "m".to_string(),
+            model_version: None,
+            input: dir.join("missing.csv").display().to_string(),
+            format: None,
+            batch_size: 2,
+        });
+        let failed = finished(&jobs, &missing.unwrap().id).await;
+        assert_eq!(failed.state, JobState::Failed);
+        assert!(failed.error.unwrap().contains("missing.csv"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jobs_resume_from_their_checkpoint() {
+        let (jobs, dir) = jobs("resume");
+        let input = dir.join("rows.csv");
+        fs::write(&input, "x\n1\n2\n3\n").unwrap();
+        let job_dir = dir.join("jobs").join("job-1");
+        fs::create_dir_all(&job_dir).unwrap();
+        let done = "{\"outputs\":{\"y\":[2.0]},\"row\":0}\n";
+        // The second row was written after the last checkpoint.
+        fs::write(job_dir.join(OUTPUT_FILE), format!("{}{{\"row\":1", done)).unwrap();
+        let status = BatchJobStatus {
+            id: "job-1".to_string(),
+            state: JobState::Running,
+            model_name: "m".to_string(),
+            model_version: "1".to_string(),
+            input: input.display().to_string(),
+            format: InputFormat::Csv,
+            batch_size: 1,
+            rows_total: Some(3),
+            rows_done: 1,
+            rows_failed: 0,
+            output: job_dir.join(OUTPUT_FILE),
+            output_bytes: done.len() as u64,
+            error: None,
+            created_at: now(),
+            finished_at: None,
+        };
+        checkpoint(&dir.join("jobs"), &status).unwrap();
+
+        assert_eq!(jobs.resume().unwrap(), 1);
+        let status = finished(&jobs, "job-1").await;
+        assert_eq!(status.state, JobState::Succeeded);
+        let rows: Vec<Value> = output_lines(&status)
+            .into_iter()
+            .map(|line| line["row"].clone())
+            .collect();
+        assert_eq!(rows, vec![json!(0), json!(1), json!(2)]);
+
+        // Finished jobs are listed but not run again.
+        assert_eq!(jobs.resume().unwrap(), 0);
+        assert_eq!(jobs.list().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+/* Concurrency limits with load shedding.
+
+Inference requests hold a permit from the `ConcurrencyLimiter` from the moment
+they reach a server until they are answered. A request arriving while the
+server already runs `global` requests, or its model runs its own limit, is shed
+right away instead of joining the request buffers: under overload, excess work
+is refused at the edge while the requests already admitted keep their latency.
+Limits can be replaced at runtime; requests already admitted keep their permit.
+Current counts, limits and shed totals are exported as Prometheus gauges and
+counters by `render_metrics`.
+*/
+
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::stats::escape_label;
+
+/// Limits from the server configuration; no limit applies where none is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConcurrencyLimits {
+    /// Requests in flight at once across all models.
+    pub global: Option<usize>,
+    /// Requests in flight at once per model.
+    pub per_model: Option<usize>,
+    /// Limits of specific models, replacing `per_model`.
+    pub models: HashMap<String, usize>,
+}
+
+impl ConcurrencyLimits {
+    fn model_limit(&self, model: &str) -> Option<usize> {
+        self.models.get(model).copied().or(self.per_model)
+    }
+}
+
+/// A request shed because a concurrency limit was reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shed {
+    /// `server` or `model '<name>'`.
+    pub scope: String,
+    pub limit: usize,
+}
+
+impl fmt::Display for Shed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The {} is running its limit of {} requests, retry later",
+            self.scope, self.limit
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    in_flight: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl Counter {
+    /// Counts one more request unless `limit` are already in flight.
+    fn try_enter(&self, limit: Option<usize>) -> bool {
+        let entered = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (limit.is_none_or(|limit| in_flight < limit)).then_some(in_flight + 1)
+            })
+            .is_ok();
+        if !entered {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+        }
+        entered
+    }
+
+    fn leave(&self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Counts a request as in flight until dropped.
+pub struct InFlightPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+    model: Option<Arc<Counter>>,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        if let Some(model) = &self.model {
+            model.leave();
+        }
+        self.limiter.global.leave();
+    }
+}
+
+/// In-flight count of one model, as reported by `ConcurrencyLimiter::models`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelConcurrency {
+    pub model: String,
+    pub in_flight: usize,
+    pub limit: Option<usize>,
+    pub shed: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ConcurrencyLimiter {
+    limits: RwLock<Arc<ConcurrencyLimits>>,
+    global: Counter,
+    models: DashMap<String, Arc<Counter>>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(limits: ConcurrencyLimits) -> Self {
+        Self {
+            limits: RwLock::new(Arc::new(limits)),
+            ..Self::default()
+        }
+    }
+
+    pub fn limits(&self) -> Arc<ConcurrencyLimits> {
+        self.limits.read().unwrap().clone()
+    }
+
+    /// Replaces the limits, applying to the following requests.
+    pub fn set_limits(&self, limits: ConcurrencyLimits) {
+        *self.limits.write().unwrap() = Arc::new(limits);
+    }
+
+    /// Admits a request for `model` (or for no model in particular), or sheds it when the
+    /// server or the model is at its limit.
+    pub fn try_acquire(self: &Arc<Self>, model: Option<&str>) -> Result<InFlightPermit, Shed> {
+        let limits = self.limits();
+        if !self.global.try_enter(limits.global) {
+            return Err(Shed {
+                scope: "server".to_string(),
+                limit: limits.global.unwrap_or_default(),
+            });
+        }
+        let mut permit = InFlightPermit {
+            limiter: self.clone(),
+            model: None,
+        };
+        if let Some(model) = model {
+            let counter = self.models.entry(model.to_string()).or_default().clone();
+            let limit = limits.model_limit(model);
+            if !counter.try_enter(limit) {
+                return Err(Shed {
+                    scope: format!("model '{}'", model),
+                    limit: limit.unwrap_or_default(),
+                });
+            }
+            permit.model = Some(counter);
+        }
+        Ok(permit)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.global.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Requests shed by the server-wide limit.
+    pub fn shed(&self) -> u64 {
+        self.global.shed.load(Ordering::Relaxed)
+    }
+
+    /// Every model seen so far, by name.
+    pub fn models(&self) -> Vec<ModelConcurrency> {
+        let limits = self.limits();
+        let mut models: Vec<ModelConcurrency> = self
+            .models
+            .iter()
+            .map(|entry| ModelConcurrency {
+                model: entry.key().clone(),
+                in_flight: entry.in_flight.load(Ordering::Acquire),
+                limit: limits.model_limit(entry.key()),
+                shed: entry.shed.load(Ordering::Relaxed),
+            })
+            .collect();
+        models.sort_by(|a, b| a.model.cmp(&b.model));
+        models
+    }
+
+    /// The counts in the Prometheus text exposition format.
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let models = self.models();
+        let _ = writeln!(
+            out,
+            "# HELP galemind_in_flight_requests Inference requests being served.\n\
+             # TYPE galemind_in_flight_requests gauge\n\
+             galemind_in_flight_requests {}",
+            self.in_flight()
+        );
+        for model in &models {
+            let _ = writeln!(
+                out,
+                "galemind_in_flight_requests{{model=\"{}\"}} {}",
+                escape_label(&model.model),
+                model.in_flight
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP galemind_max_in_flight_requests Concurrency limit of inference requests.\n\
+             # TYPE galemind_max_in_flight_requests gauge"
+        );
+        if let Some(limit) = self.limits().global {
+            let _ = writeln!(out, "galemind_max_in_flight_requests {}", limit);
+        }
+        for model in &models {
+            if let Some(limit) = model.limit {
+                let _ = writeln!(
+                    out,
+                    "galemind_max_in_flight_requests{{model=\"{}\"}} {}",
+                    escape_label(&model.model),
+                    limit
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP galemind_shed_requests_total Inference requests shed at a concurrency limit.\n\
+             # TYPE galemind_shed_requests_total counter\n\
+             galemind_shed_requests_total {}",
+            self.shed()
+        );
+        for model in &models {
+            let _ = writeln!(
+                out,
+                "galemind_shed_requests_total{{model=\"{}\"}} {}",
+                escape_label(&model.model),
+                model.shed
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excess_requests_are_shed() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(ConcurrencyLimits {
+            global: Some(3),
+            per_model: Some(2),
+            models: HashMap::from([("big".to_string(), 1)]),
+        }));
+
+        let first = limiter.try_acquire(Some("m")).unwrap();
+        let _second = limiter.try_acquire(Some("m")).unwrap();
+        let shed = limiter.try_acquire(Some("m")).err().unwrap();
+        assert_eq!(shed.scope, "model 'm'");
+        let _big = limiter.try_acquire(Some("big")).unwrap();
+        // The server is full, whatever the model.
+        assert_eq!(
+            limiter.try_acquire(Some("other")).err().unwrap().scope,
+            "server"
+        );
+        assert_eq!(limiter.in_flight(), 3);
+
+        drop(first);
+        let _third = limiter.try_acquire(Some("m")).unwrap();
+        assert_eq!(limiter.in_flight(), 3);
+
+        let metrics = limiter.render_metrics();
+        assert!(metrics.contains("galemind_in_flight_requests 3\n"));
+        assert!(metrics.contains("galemind_in_flight_requests{model=\"m\"} 2\n"));
+        assert!(metrics.contains("galemind_max_in_flight_requests{model=\"big\"} 1\n"));
+        assert!(metrics.contains("galemind_shed_requests_total 1\n"));
+        assert!(metrics.contains("galemind_shed_requests_total{model=\"m\"} 1\n"));
+    }
+}
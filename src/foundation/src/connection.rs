@@ -0,0 +1,198 @@
+/* Per-connection limits shared by the REST and gRPC servers.
+
+Every accepted socket is wrapped in `IdleTimeout`, which closes connections
+that stay silent for too long: a client must send its first bytes within
+`header_read_timeout` (slowloris-style clients opening sockets and never
+completing a request) and afterwards any traffic in either direction keeps
+the connection alive for another `idle_timeout`. Stream concurrency and
+header timeouts within a connection are applied by each server's protocol
+configuration.
+*/
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionLimits {
+    /// Maximum concurrent HTTP/2 streams (in-flight requests) per connection.
+    pub max_concurrent_streams: u32,
+    /// Time allowed for a client to send a complete request header, and its first bytes.
+    pub header_read_timeout: Duration,
+    /// Connections without any traffic for this long are closed.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: 128,
+            header_read_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// IO wrapper failing reads with `TimedOut` once the connection has been idle too long.
+pub struct IdleTimeout<T> {
+    inner: T,
+    idle_timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+    /// Whether the client has sent anything yet. Until then, our own writes (such as the
+    /// HTTP/2 server preface) do not extend the deadline.
+    established: bool,
+}
+
+impl<T> IdleTimeout<T> {
+    pub fn new(inner: T, limits: &ConnectionLimits) -> Self {
+        Self {
+            inner,
+            idle_timeout: limits.idle_timeout,
+            deadline: Box::pin(tokio::time::sleep(limits.header_read_timeout)),
+            established: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn record_activity(&mut self) {
+        let deadline = Instant::now() + self.idle_timeout;
+        self.deadline.as_mut().reset(deadline);
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for IdleTimeout<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                if result.is_ok() && buf.filled().len() > filled {
+                    self.established = true;
+                    self.record_activity();
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => match self.deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection idle timeout",
+                ))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result
+            && written > 0
+            && self.established
+        {
+            self.record_activity();
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result
+            && written > 0
+            && self.established
+        {
+            self.record_activity();
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn limits(header_read_timeout: u64, idle_timeout: u64) -> ConnectionLimits {
+        ConnectionLimits {
+            max_concurrent_streams: 1,
+            header_read_timeout: Duration::from_millis(header_read_timeout),
+            idle_timeout: Duration::from_millis(idle_timeout),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_times_out() {
+        let (_client, server) = tokio::io::duplex(64);
+        let mut connection = IdleTimeout::new(server, &limits(20, 1000));
+
+        let mut buf = [0u8; 8];
+        let error = connection.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_server_writes_do_not_extend_initial_deadline() {
+        let (_client, server) = tokio::io::duplex(64);
+        let mut connection = IdleTimeout::new(server, &limits(50, 1000));
+
+        connection.write_all(b"preface").await.unwrap();
+        let mut buf = [0u8; 8];
+        let started = Instant::now();
+        let error = connection.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_activity_keeps_connection_alive() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut connection = IdleTimeout::new(server, &limits(100, 100));
+
+        let writer = tokio::spawn(async move {
+            for _ in 0..4 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                client.write_all(b"x").await.unwrap();
+            }
+            client
+        });
+
+        let mut buf = [0u8; 1];
+        for _ in 0..4 {
+            connection.read_exact(&mut buf).await.unwrap();
+        }
+        // The client stays connected but silent from now on.
+        let _client = writer.await.unwrap();
+        let error = connection.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
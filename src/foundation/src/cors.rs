@@ -0,0 +1,154 @@
+/* Cross-origin access to the REST API.
+
+Browser dashboards and OpenAI client apps served from another origin can only
+call the REST API if it answers their CORS preflight requests. This is off by
+default; it is turned on by naming the origins allowed:
+
+```bash
+galemind start --rest-cors-origin https://dashboard.example.com \
+  --rest-cors-method GET --rest-cors-method POST \
+  --rest-cors-header x-custom-trace --rest-cors-credentials
+```
+
+`*` allows any origin. Allowed methods default to `GET`, `POST`, `PUT` and
+`DELETE`. The headers of the API (authorization, API key, priority, deadline,
+selector, schema version, correlation id, tenant, binary data length) are
+always allowed, and the configured ones are allowed besides. Responses expose
+the correlation id, schema version, rate limit and quota headers to scripts.
+
+Credentials (cookies, client certificates, authorization headers the browser
+manages) can only be allowed for listed origins: browsers refuse them from a
+server allowing any origin.
+*/
+
+use anyhow::{Result, bail};
+use std::time::Duration;
+
+/// Origin standing for any origin.
+pub const ANY_ORIGIN: &str = "*";
+const DEFAULT_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE"];
+/// How long browsers may cache a preflight response by default.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. `https://dashboard.example.com`, or `*`.
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    /// Request headers allowed besides those of the API.
+    pub headers: Vec<String>,
+    /// Whether browsers may send credentials along.
+    pub allow_credentials: bool,
+    pub max_age: Duration,
+}
+
+impl CorsConfig {
+    pub fn new(origins: Vec<String>) -> Self {
+        Self {
+            origins,
+            methods: DEFAULT_METHODS.iter().map(|m| m.to_string()).collect(),
+            headers: Vec::new(),
+            allow_credentials: false,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    pub fn with_methods(mut self, methods: Vec<String>) -> Self {
+        self.methods = methods
+            .into_iter()
+            .map(|method| method.to_ascii_uppercase())
+            .collect();
+        self
+    }
+
+    pub fn with_headers(mut self, headers: Vec<String>) -> Self {
+        self.headers = headers
+            .into_iter()
+            .map(|header| header.to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    pub fn with_credentials(mut self, allow_credentials: bool) -> Self {
+        self.allow_credentials = allow_credentials;
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.origins.iter().any(|origin| origin == ANY_ORIGIN)
+    }
+
+    /// Refuses configurations browsers would reject.
+    pub fn check(&self) -> Result<()> {
+        if self.origins.is_empty() {
+            bail!("CORS needs at least one allowed origin, or '*'");
+        }
+        if self.methods.is_empty() {
+            bail!("CORS needs at least one allowed method");
+        }
+        if self.allow_credentials && self.allows_any_origin() {
+            bail!("CORS credentials can only be allowed for listed origins, not '*'");
+        }
+        for origin in self.origins.iter().filter(|origin| *origin != ANY_ORIGIN) {
+            let valid = origin.split_once("://").is_some_and(|(scheme, host)| {
+                !scheme.is_empty() && !host.is_empty() && !host.contains('/')
+            });
+            if !valid {
+                bail!(
+                    "Invalid CORS origin '{}', expected <scheme>://<host>[:<port>]",
+                    origin
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origins(origins: &[&str]) -> Vec<String> {
+        origins.iter().map(|origin| origin.to_string()).collect()
+    }
+
+    #[test]
+    fn test_credentials_need_listed_origins() {
+        let listed = CorsConfig::new(origins(&["https://dashboard.example.com"]))
+            .with_credentials(true)
+            .with_methods(vec!["get".to_string()]);
+        assert!(listed.check().is_ok());
+        assert_eq!(listed.methods, vec!["GET"]);
+
+        let any = CorsConfig::new(origins(&[ANY_ORIGIN])).with_credentials(true);
+        assert!(any.allows_any_origin());
+        assert!(any.check().is_err());
+        assert!(any.with_credentials(false).check().is_ok());
+    }
+
+    #[test]
+    fn test_origins_must_be_scheme_and_host() {
+        for origin in [
+            "dashboard.example.com",
+            "https://example.com/app",
+            "https://",
+        ] {
+            assert!(
+                CorsConfig::new(origins(&[origin])).check().is_err(),
+                "{}",
+                origin
+            );
+        }
+        assert!(
+            CorsConfig::new(origins(&["http://localhost:3000"]))
+                .check()
+                .is_ok()
+        );
+        assert!(CorsConfig::new(Vec::new()).check().is_err());
+    }
+}
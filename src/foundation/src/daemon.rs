@@ -0,0 +1,157 @@
+/* Integration with service managers.
+
+The server stays in the foreground and lets the service manager supervise it.
+With `--pid-file` it writes its process id to a file when it starts and
+removes it when it exits; a file left by a process that is still running is
+not overwritten. Under a systemd `Type=notify` unit (`NOTIFY_SOCKET` set), it
+tells systemd it is ready only once every model has finished loading, gives a
+status line with the number of models served, and reports when it starts
+stopping. Outside of systemd, notifications are ignored.
+*/
+
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+
+/// Environment variable systemd sets to the socket notifications are sent to.
+pub const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// The process id of this process in a file, removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+/// Whether the process `pid` is running. Without `/proc`, every process is assumed gone.
+fn running(pid: u32) -> bool {
+    pid == std::process::id() || Path::new("/proc").join(pid.to_string()).exists()
+}
+
+impl PidFile {
+    /// Writes the process id to `path`, unless it names a process still running.
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Ok(previous) = std::fs::read_to_string(&path)
+            && let Ok(pid) = previous.trim().parse::<u32>()
+            && pid != std::process::id()
+            && running(pid)
+        {
+            return Err(anyhow!(
+                "{} names process {}, which is still running",
+                path.display(),
+                pid
+            ));
+        }
+        // Written aside then renamed, so the file is never seen half written.
+        let partial = path.with_extension("pid.tmp");
+        std::fs::write(&partial, format!("{}\n", std::process::id()))
+            .and_then(|_| std::fs::rename(&partial, &path))
+            .with_context(|| format!("Failed to write the pid file {}", path.display()))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Another process may have taken the file over.
+        let ours = std::fs::read_to_string(&self.path)
+            .is_ok_and(|pid| pid.trim() == std::process::id().to_string());
+        if ours {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Sends `state` (`READY=1`, `STATUS=...`, `STOPPING=1`, newline separated) to systemd.
+/// Returns whether it was sent, `false` when the process does not run under a
+/// `Type=notify` unit.
+pub fn sd_notify(state: &str) -> Result<bool> {
+    match std::env::var_os(NOTIFY_SOCKET_ENV) {
+        Some(socket) if !socket.is_empty() => {
+            notify_socket(&socket.to_string_lossy(), state)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Sends `state` to the datagram socket at `socket`, a path or, starting with `@`, an
+/// abstract socket name.
+#[cfg(unix)]
+fn notify_socket(socket: &str, state: &str) -> Result<()> {
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let addr = match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())?
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(anyhow!("Abstract sockets are only supported on Linux")),
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?
+        .send_to_addr(state.as_bytes(), &addr)
+        .with_context(|| format!("Failed to notify systemd at {}", socket))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn notify_socket(_socket: &str, _state: &str) -> Result<()> {
+    Err(anyhow!("systemd notifications are only supported on Unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("galemind-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_pid_file_lifecycle() {
+        let path = temp_path("lifecycle.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_pid_file_of_a_running_process_is_kept() {
+        let path = temp_path("running.pid");
+        // pid 1 is always running where /proc exists.
+        std::fs::write(&path, "1\n").unwrap();
+        if Path::new("/proc/1").exists() {
+            assert!(PidFile::create(&path).is_err());
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n");
+        }
+        // A stale file is replaced.
+        std::fs::write(&path, format!("{}\n", u32::MAX)).unwrap();
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(pid_file.path(), path);
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notifications_reach_the_socket() {
+        let path = temp_path("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        notify_socket(path.to_str().unwrap(), "READY=1\nSTATUS=Serving 2 models").unwrap();
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=Serving 2 models");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+/* Request deadlines.
+
+A client bounds how long it is willing to wait for a request with the
+`x-request-timeout-ms` header (REST or gRPC metadata) or the standard gRPC
+deadline, which clients send as the `grpc-timeout` metadata entry. The
+deadline travels with the `InferenceRequest`: a request still queued when it
+passes is answered with a timeout instead of being run late, and a runtime
+call still running is abandoned, which cancels runtimes that execute
+asynchronously.
+*/
+
+use anyhow::{Result, anyhow};
+use std::time::{Duration, Instant};
+
+use crate::api::inference::{InferenceError, InferenceResponse};
+
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout-ms";
+
+/// The metadata entry carrying the deadline of a gRPC call.
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Parses the value of the `x-request-timeout-ms` header.
+pub fn parse_timeout_ms(value: &str) -> Result<Duration> {
+    let millis: u64 = value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid request timeout '{}', expected milliseconds", value))?;
+    if millis == 0 {
+        return Err(anyhow!("Request timeout must be at least 1 millisecond"));
+    }
+    Ok(Duration::from_millis(millis))
+}
+
+/// Parses a `grpc-timeout` value: up to 8 digits followed by a unit, `H`, `M`, `S`, `m`
+/// (milliseconds), `u` (microseconds) or `n` (nanoseconds).
+pub fn parse_grpc_timeout(value: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid grpc-timeout '{}'", value);
+    let split = value.len().checked_sub(1).ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    match unit {
+        "H" => Ok(Duration::from_secs(amount * 3600)),
+        "M" => Ok(Duration::from_secs(amount * 60)),
+        "S" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_millis(amount)),
+        "u" => Ok(Duration::from_micros(amount)),
+        "n" => Ok(Duration::from_nanos(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Whether a request with `deadline` should no longer be run at `now`.
+pub fn is_expired(deadline: Option<Instant>, now: Instant) -> bool {
+    deadline.is_some_and(|deadline| now >= deadline)
+}
+
+/// Answer to a request whose deadline passed while it was `stage` (queued, running...).
+pub fn deadline_exceeded(id: &str, stage: &str) -> InferenceResponse {
+    InferenceResponse::DeadlineExceeded(InferenceError {
+        error: format!("Request '{}' exceeded its deadline while {}", id, stage),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeouts_are_parsed() {
+        assert_eq!(
+            parse_timeout_ms(" 250 ").unwrap(),
+            Duration::from_millis(250)
+        );
+        assert!(parse_timeout_ms("0").is_err());
+        assert!(parse_timeout_ms("1.5").is_err());
+
+        assert_eq!(parse_grpc_timeout("2S").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_grpc_timeout("1H").unwrap(), Duration::from_secs(3600));
+        assert_eq!(
+            parse_grpc_timeout("99m").unwrap(),
+            Duration::from_millis(99)
+        );
+        assert_eq!(parse_grpc_timeout("5n").unwrap(), Duration::from_nanos(5));
+        for invalid in ["", "S", "123456789m", "10x", "-1S"] {
+            assert!(parse_grpc_timeout(invalid).is_err(), "{}", invalid);
+        }
+
+        let now = Instant::now();
+        assert!(is_expired(Some(now), now));
+        assert!(!is_expired(Some(now + Duration::from_millis(1)), now));
+        assert!(!is_expired(None, now));
+    }
+}
@@ -0,0 +1,367 @@
+/* Server event bus.
+
+The scheduler, the registry and the authenticator publish what happens to the
+server as typed `ServerEvent`s instead of handling it themselves: models
+loaded, unloaded, paused and resumed, circuits opened and closed, versions
+loaded and unloaded, batches executed, requests rejected by a full buffer and
+requests refused for their credentials. Observers subscribe to the bus and do
+with the events what they need:
+
+- `LogObserver` prints the lifecycle of models and circuits and the failed
+  batches; every bus starts with it;
+- `MetricsRecorder` counts the events, `galemind_server_events_total`;
+- `WebhookObserver`, with `--event-webhook`, POSTs the events of the chosen
+  kinds to a URL as JSON, `{"event": "model_loaded", "model": "iris", ...}`.
+
+Observers are called on the publishing thread, in the order they subscribed,
+and must not block: slow work, like the webhook's, is queued for a task.
+*/
+
+use crate::log_info;
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Events waiting for the webhook before new ones are dropped.
+pub const EVENT_QUEUE_CAPACITY: usize = 1024;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened to the server.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// A model directory was loaded through the registry.
+    ModelLoaded { model: String, path: String },
+    /// A model was unloaded with its versions.
+    ModelUnloaded { model: String, versions: usize },
+    /// An operator paused a model, which refuses new requests until resumed.
+    ModelPaused {
+        model: String,
+        retry_after_secs: u64,
+    },
+    /// A paused model admits requests again.
+    ModelResumed { model: String },
+    /// A model's runtime kept failing and its requests are refused, see `model::circuit`.
+    CircuitOpened {
+        model: String,
+        requests: u64,
+        errors: u64,
+        error_rate: f64,
+    },
+    /// A probe of an open circuit succeeded and the model serves requests again.
+    CircuitClosed { model: String },
+    /// A runtime now serves a version.
+    VersionLoaded { model: String, version: String },
+    /// A version stopped being served.
+    VersionUnloaded { model: String, version: String },
+    /// The batcher ran a batch; `failure` tells why its requests were not all answered.
+    BatchExecuted {
+        model: String,
+        version: String,
+        batch_id: String,
+        size: usize,
+        latency_ms: f64,
+        failure: Option<String>,
+    },
+    /// A request was refused before running, as its model's buffer was full.
+    RequestRejected { model: String, reason: String },
+    /// A request was refused for its credentials.
+    AuthFailure {
+        role: String,
+        /// The model the request was about, if any.
+        model: Option<String>,
+        /// True when the request had no valid credentials at all.
+        unauthenticated: bool,
+        reason: String,
+    },
+}
+
+impl ServerEvent {
+    pub const KINDS: [&'static str; 11] = [
+        "model_loaded",
+        "model_unloaded",
+        "model_paused",
+        "model_resumed",
+        "circuit_opened",
+        "circuit_closed",
+        "version_loaded",
+        "version_unloaded",
+        "batch_executed",
+        "request_rejected",
+        "auth_failure",
+    ];
+
+    /// The name of the event, as in its JSON `event` field.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerEvent::ModelLoaded { .. } => "model_loaded",
+            ServerEvent::ModelUnloaded { .. } => "model_unloaded",
+            ServerEvent::ModelPaused { .. } => "model_paused",
+            ServerEvent::ModelResumed { .. } => "model_resumed",
+            ServerEvent::CircuitOpened { .. } => "circuit_opened",
+            ServerEvent::CircuitClosed { .. } => "circuit_closed",
+            ServerEvent::VersionLoaded { .. } => "version_loaded",
+            ServerEvent::VersionUnloaded { .. } => "version_unloaded",
+            ServerEvent::BatchExecuted { .. } => "batch_executed",
+            ServerEvent::RequestRejected { .. } => "request_rejected",
+            ServerEvent::AuthFailure { .. } => "auth_failure",
+        }
+    }
+
+    /// Parses a comma separated list of event kinds.
+    pub fn parse_kinds(s: &str) -> Result<HashSet<&'static str>> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|kind| !kind.is_empty())
+            .map(|kind| {
+                Self::KINDS
+                    .into_iter()
+                    .find(|known| *known == kind)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Unknown event '{}', expected one of {}",
+                            kind,
+                            Self::KINDS.join(", ")
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Receives every event published on the bus it subscribed to.
+pub trait EventObserver: Send + Sync {
+    /// Called on the publishing thread; must return quickly.
+    fn observe(&self, event: &ServerEvent);
+}
+
+/// Hands the events published by the server to its observers.
+pub struct EventBus {
+    observers: RwLock<Vec<Arc<dyn EventObserver>>>,
+}
+
+impl EventBus {
+    /// A bus without observers.
+    pub fn empty() -> Self {
+        Self {
+            observers: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn subscribe(&self, observer: Arc<dyn EventObserver>) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    pub fn publish(&self, event: ServerEvent) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.observe(&event);
+        }
+    }
+}
+
+impl Default for EventBus {
+    /// A bus logging the events, see `LogObserver`.
+    fn default() -> Self {
+        let bus = Self::empty();
+        bus.subscribe(Arc::new(LogObserver));
+        bus
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("observers", &self.observers.read().unwrap().len())
+            .finish()
+    }
+}
+
+/// Prints the lifecycle of models and the batches that failed.
+pub struct LogObserver;
+
+impl EventObserver for LogObserver {
+    fn observe(&self, event: &ServerEvent) {
+        match event {
+            ServerEvent::ModelLoaded { model, path } => {
+                log_info!("Loaded model {} from {}", model, path)
+            }
+            ServerEvent::ModelUnloaded { model, versions } => {
+                log_info!("Unloaded model {} ({} versions)", model, versions)
+            }
+            ServerEvent::ModelPaused { model, .. } => log_info!("Paused model {}", model),
+            ServerEvent::ModelResumed { model } => log_info!("Resumed model {}", model),
+            ServerEvent::CircuitOpened {
+                model, error_rate, ..
+            } => eprintln!(
+                "Opened the circuit of model {}: {:.0}% of its requests failed",
+                model,
+                error_rate * 100.0
+            ),
+            ServerEvent::CircuitClosed { model } => {
+                log_info!("Closed the circuit of model {}", model)
+            }
+            ServerEvent::BatchExecuted {
+                model,
+                version,
+                batch_id,
+                failure: Some(failure),
+                ..
+            } => eprintln!("Batch {} of {}:{}: {}", batch_id, model, version, failure),
+            _ => {}
+        }
+    }
+}
+
+/// POSTs the events of some kinds to a URL, from a background task. Events are dropped,
+/// and counted, when the URL cannot keep up.
+pub struct WebhookObserver {
+    kinds: HashSet<&'static str>,
+    sender: mpsc::Sender<ServerEvent>,
+    dropped: AtomicU64,
+}
+
+impl WebhookObserver {
+    /// Starts the task posting to `url`. Must be called from within a tokio runtime.
+    pub fn spawn(url: &str, kinds: HashSet<&'static str>, capacity: usize) -> Result<Self> {
+        let url = reqwest::Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| anyhow!("Invalid event webhook '{}', expected an http(s) URL", url))?;
+        let (sender, mut receiver) = mpsc::channel::<ServerEvent>(capacity);
+        tokio::spawn(async move {
+            let http = Client::new();
+            while let Some(event) = receiver.recv().await {
+                let result = http
+                    .post(url.clone())
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .json(&event)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    eprintln!("Failed to post {} event to webhook: {}", event.kind(), e);
+                }
+            }
+        });
+        Ok(Self {
+            kinds,
+            sender,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Number of events dropped because the webhook could not keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl EventObserver for WebhookObserver {
+    fn observe(&self, event: &ServerEvent) {
+        if !self.kinds.contains(event.kind()) {
+            return;
+        }
+        if self.sender.try_send(event.clone()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recording(Mutex<Vec<ServerEvent>>);
+
+    impl EventObserver for Recording {
+        fn observe(&self, event: &ServerEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_published_events_reach_every_observer() {
+        let bus = EventBus::default();
+        let first = Arc::new(Recording::default());
+        let second = Arc::new(Recording::default());
+        bus.subscribe(first.clone());
+        bus.subscribe(second.clone());
+
+        let event = ServerEvent::RequestRejected {
+            model: "m".to_string(),
+            reason: "full".to_string(),
+        };
+        bus.publish(event.clone());
+
+        assert_eq!(*first.0.lock().unwrap(), vec![event.clone()]);
+        assert_eq!(*second.0.lock().unwrap(), vec![event]);
+    }
+
+    #[test]
+    fn test_events_serialize_with_their_kind() {
+        let event = ServerEvent::VersionLoaded {
+            model: "iris".to_string(),
+            version: "2".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"event": "version_loaded", "model": "iris", "version": "2"})
+        );
+        assert!(ServerEvent::KINDS.contains(&event.kind()));
+    }
+
+    #[test]
+    fn test_kinds_are_parsed_from_a_list() {
+        let kinds = ServerEvent::parse_kinds("model_loaded, auth_failure").unwrap();
+        assert_eq!(kinds, HashSet::from(["model_loaded", "auth_failure"]));
+        assert!(ServerEvent::parse_kinds("model_exploded").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_posts_the_chosen_kinds() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let webhook = WebhookObserver::spawn(
+            &url,
+            ServerEvent::parse_kinds("model_unloaded").unwrap(),
+            EVENT_QUEUE_CAPACITY,
+        )
+        .unwrap();
+
+        webhook.observe(&ServerEvent::VersionUnloaded {
+            model: "m".to_string(),
+            version: "1".to_string(),
+        });
+        webhook.observe(&ServerEvent::ModelUnloaded {
+            model: "m".to_string(),
+            versions: 1,
+        });
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&request).ends_with('}') {
+            let read = socket.read(&mut buffer).await.unwrap();
+            assert!(read > 0);
+            request.extend_from_slice(&buffer[..read]);
+        }
+        socket
+            .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /events"));
+        assert!(request.ends_with(r#"{"event":"model_unloaded","model":"m","versions":1}"#));
+        assert_eq!(webhook.dropped(), 0);
+    }
+}
@@ -0,0 +1,282 @@
+/* Generation of request, batch and job ids.
+
+Ids are produced by a process-wide `IdProvider` chosen with `--id-scheme`.
+Every scheme starts with a millisecond timestamp and is monotonic within the
+process, so ids sort by creation time in downstream analytics stores:
+
+- `uuidv7`: RFC 9562 version 7 UUIDs, e.g. `01890a5d-ac96-774b-bcce-b302099a8057`.
+- `ulid`: 26 character Crockford base32 ULIDs, e.g. `01H455VB4PEX5VSKNK084SN02Q`.
+- `snowflake[:node]`: 64-bit integers made of milliseconds since 2024-01-01,
+  a 10-bit node id and a 12-bit sequence, printed in decimal.
+
+Callers may also supply their own correlation id (`x-correlation-id`,
+`x-request-id` or the trace id of a W3C `traceparent`), which is then used
+instead of a generated request id and echoed back in responses.
+*/
+
+use anyhow::{Result, anyhow};
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Header (or gRPC metadata entry) echoing the correlation id of a request.
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+/// Headers accepted as externally supplied correlation ids, in order of preference.
+const CORRELATION_HEADERS: [&str; 2] = [CORRELATION_ID_HEADER, "x-request-id"];
+const TRACEPARENT_HEADER: &str = "traceparent";
+const MAX_CORRELATION_ID_LEN: usize = 128;
+
+/// Start of the Snowflake timestamp, 2024-01-01T00:00:00Z in Unix milliseconds.
+const SNOWFLAKE_EPOCH_MS: u64 = 1_704_067_200_000;
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+const CROCKFORD_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+pub trait IdProvider: Send + Sync + Debug {
+    fn next_id(&self) -> String;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdScheme {
+    #[default]
+    UuidV7,
+    Ulid,
+    Snowflake {
+        node: u16,
+    },
+}
+
+impl IdScheme {
+    pub fn provider(self) -> Arc<dyn IdProvider> {
+        match self {
+            IdScheme::UuidV7 => Arc::new(UuidV7Provider),
+            IdScheme::Ulid => Arc::new(UlidProvider::default()),
+            IdScheme::Snowflake { node } => Arc::new(SnowflakeProvider::new(node)),
+        }
+    }
+}
+
+impl FromStr for IdScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (name, node) = match value.split_once(':') {
+            Some((name, node)) => (name, Some(node)),
+            None => (value, None),
+        };
+        match (name.to_ascii_lowercase().as_str(), node) {
+            ("uuidv7" | "uuid", None) => Ok(IdScheme::UuidV7),
+            ("ulid", None) => Ok(IdScheme::Ulid),
+            ("snowflake", node) => {
+                let node = match node {
+                    Some(node) => node
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|node| *node < 1 << SNOWFLAKE_NODE_BITS)
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Snowflake node id must be between 0 and 1023, got '{}'",
+                                node
+                            )
+                        })?,
+                    None => 0,
+                };
+                Ok(IdScheme::Snowflake { node })
+            }
+            _ => Err(anyhow!(
+                "Unknown id scheme '{}', expected uuidv7, ulid or snowflake[:node]",
+                value
+            )),
+        }
+    }
+}
+
+/// Version 7 UUIDs; the uuid crate keeps them ordered within the process.
+#[derive(Debug, Default)]
+pub struct UuidV7Provider;
+
+impl IdProvider for UuidV7Provider {
+    fn next_id(&self) -> String {
+        Uuid::now_v7().hyphenated().to_string()
+    }
+}
+
+/// ULIDs whose random part is incremented for ids created within the same millisecond.
+#[derive(Debug, Default)]
+pub struct UlidProvider {
+    /// Timestamp and random part of the last id.
+    last: Mutex<(u64, u128)>,
+}
+
+impl IdProvider for UlidProvider {
+    fn next_id(&self) -> String {
+        let mut last = self.last.lock().unwrap();
+        let now = unix_millis();
+        let (millis, random) = if now > last.0 {
+            (now, random_u128() & ((1 << 80) - 1))
+        } else if last.1 < (1 << 80) - 1 {
+            (last.0, last.1 + 1)
+        } else {
+            // The random part is exhausted: borrow the next millisecond.
+            (last.0 + 1, 0)
+        };
+        *last = (millis, random);
+        encode_ulid(((millis as u128) << 80) | random)
+    }
+}
+
+#[derive(Debug)]
+pub struct SnowflakeProvider {
+    node: u64,
+    /// Timestamp and sequence of the last id.
+    last: Mutex<(u64, u64)>,
+}
+
+impl SnowflakeProvider {
+    pub fn new(node: u16) -> Self {
+        Self {
+            node: node as u64 & ((1 << SNOWFLAKE_NODE_BITS) - 1),
+            last: Mutex::new((0, 0)),
+        }
+    }
+}
+
+impl IdProvider for SnowflakeProvider {
+    fn next_id(&self) -> String {
+        let mut last = self.last.lock().unwrap();
+        let now = unix_millis().saturating_sub(SNOWFLAKE_EPOCH_MS);
+        let (millis, sequence) = if now > last.0 {
+            (now, 0)
+        } else if last.1 < (1 << SNOWFLAKE_SEQUENCE_BITS) - 1 {
+            (last.0, last.1 + 1)
+        } else {
+            // More than 4096 ids within one millisecond: borrow the next one.
+            (last.0 + 1, 0)
+        };
+        *last = (millis, sequence);
+        let id = (millis << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS))
+            | (self.node << SNOWFLAKE_SEQUENCE_BITS)
+            | sequence;
+        id.to_string()
+    }
+}
+
+/// Externally supplied correlation id, looked up through `header`.
+///
+/// Ids that are empty, too long or not printable ASCII are ignored, so they can
+/// safely be echoed back and written to logs.
+pub fn correlation_id<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Option<String> {
+    CORRELATION_HEADERS
+        .iter()
+        .filter_map(|name| header(name))
+        .map(str::trim)
+        .find(|id| is_valid_correlation_id(id))
+        .map(String::from)
+        .or_else(|| header(TRACEPARENT_HEADER).and_then(trace_id))
+}
+
+fn is_valid_correlation_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_CORRELATION_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Trace id of a W3C `traceparent` header (`version-traceid-parentid-flags`).
+fn trace_id(traceparent: &str) -> Option<String> {
+    let trace_id = traceparent.trim().split('-').nth(1)?;
+    let valid = trace_id.len() == 32
+        && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+        && trace_id.bytes().any(|b| b != b'0');
+    valid.then(|| trace_id.to_ascii_lowercase())
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn random_u128() -> u128 {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("Failed to read random bytes");
+    u128::from_le_bytes(bytes)
+}
+
+fn encode_ulid(value: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD_ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_ids_are_sorted_and_well_formed() {
+        for (scheme, len) in [
+            ("uuidv7", Some(36)),
+            ("ulid", Some(26)),
+            ("snowflake:7", None),
+        ] {
+            let provider = scheme.parse::<IdScheme>().unwrap().provider();
+            let ids: Vec<String> = (0..5000).map(|_| provider.next_id()).collect();
+            if let Some(len) = len {
+                assert!(ids.iter().all(|id| id.len() == len), "{}", scheme);
+                assert!(ids.windows(2).all(|w| w[0] < w[1]), "{}", scheme);
+            } else {
+                let ids: Vec<u64> = ids.iter().map(|id| id.parse().unwrap()).collect();
+                assert!(ids.windows(2).all(|w| w[0] < w[1]));
+                assert_eq!((ids[0] >> SNOWFLAKE_SEQUENCE_BITS) & 0x3ff, 7);
+            }
+        }
+        assert_eq!(
+            Uuid::parse_str(&UuidV7Provider.next_id())
+                .unwrap()
+                .get_version_num(),
+            7
+        );
+        assert!("snowflake:1024".parse::<IdScheme>().is_err());
+        assert!("uuidv4".parse::<IdScheme>().is_err());
+    }
+
+    #[test]
+    fn test_correlation_id_from_headers() {
+        let lookup = |headers: HashMap<&'static str, &'static str>| {
+            correlation_id(|name| headers.get(name).copied())
+        };
+
+        assert_eq!(
+            lookup(HashMap::from([
+                ("x-request-id", "req-1"),
+                ("x-correlation-id", "corr-1"),
+            ])),
+            Some("corr-1".to_string())
+        );
+        assert_eq!(
+            lookup(HashMap::from([
+                ("x-correlation-id", "has spaces"),
+                ("x-request-id", "req-1"),
+            ])),
+            Some("req-1".to_string())
+        );
+        assert_eq!(
+            lookup(HashMap::from([(
+                "traceparent",
+                "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"
+            )])),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string())
+        );
+        assert_eq!(
+            lookup(HashMap::from([(
+                "traceparent",
+                "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+            )])),
+            None
+        );
+    }
+}
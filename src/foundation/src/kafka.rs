@@ -0,0 +1,432 @@
+/* Kafka topics reached through a Kafka REST proxy.
+
+As the audit log does (see `audit`), the server speaks to Kafka through a
+Confluent REST Proxy, with its v2 API, rather than linking a Kafka client.
+
+- `KafkaConsumer` is a consumer instance of a group, subscribed to a topic. It
+  reads messages in the binary embedded format, so their keys and values are
+  passed on as they are. Offsets are only committed when asked to, once the
+  messages are handled, so the messages a stopped server was handling are read
+  again by the group.
+- `KafkaProducer` produces keyed messages to a topic. It also backs the
+  `kafka://host:port/topic` sinks of the analytics tee and the audit log.
+
+`KafkaConfig` configures the consumer mode of the server (`--kafka-proxy`):
+inference requests are consumed from an input topic, and their results are
+produced to an output topic, in a `MessageFormat`.
+*/
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::{Client, Response};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::api::codec::{Codec, codec};
+
+/// Content type of the requests of the v2 API without records.
+const V2_JSON: &str = "application/vnd.kafka.v2+json";
+/// Content type of records in the binary embedded format, keys and values in base64.
+const BINARY_JSON: &str = "application/vnd.kafka.binary.v2+json";
+/// How long a poll waits for messages, in milliseconds.
+const POLL_TIMEOUT_MS: u64 = 1000;
+
+/// Encoding of the messages of the consumer mode.
+#[derive(Clone)]
+pub enum MessageFormat {
+    /// KServe V2 inference requests and responses in JSON, naming their model.
+    V2,
+    /// Domain requests and responses encoded with a codec, see `api::codec`.
+    Codec(Arc<dyn Codec>),
+}
+
+impl FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    /// `v2`, or the name of a codec.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "v2" => Ok(MessageFormat::V2),
+            name => codec(name).map(MessageFormat::Codec).map_err(|_| {
+                anyhow!(
+                    "Unknown message format '{}', expected v2, json or postcard",
+                    name
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for MessageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageFormat::V2 => f.write_str("v2"),
+            MessageFormat::Codec(codec) => f.write_str(codec.name()),
+        }
+    }
+}
+
+impl fmt::Debug for MessageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Topics and format of the consumer mode.
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    /// Base URL of the REST proxy, e.g. `http://localhost:8082`.
+    pub proxy_url: String,
+    /// Consumer group, whose members share the partitions of the input topic.
+    pub group: String,
+    pub input_topic: String,
+    pub output_topic: String,
+    pub format: MessageFormat,
+}
+
+/// A message read from a topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KafkaMessage {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    /// Empty for tombstones.
+    pub value: Vec<u8>,
+}
+
+/// A record as the proxy sends it in the binary embedded format.
+#[derive(Deserialize)]
+struct EncodedRecord {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    key: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreatedInstance {
+    base_uri: String,
+}
+
+fn decode_records(records: Vec<EncodedRecord>) -> Result<Vec<KafkaMessage>> {
+    records
+        .into_iter()
+        .map(|record| {
+            let decode = |field: Option<String>| -> Result<Option<Vec<u8>>> {
+                field
+                    .map(|encoded| STANDARD.decode(encoded))
+                    .transpose()
+                    .map_err(|e| {
+                        anyhow!(
+                            "Invalid base64 in message {} of partition {} of {}: {}",
+                            record.offset,
+                            record.partition,
+                            record.topic,
+                            e
+                        )
+                    })
+            };
+            Ok(KafkaMessage {
+                key: decode(record.key.clone())?,
+                value: decode(record.value.clone())?.unwrap_or_default(),
+                topic: record.topic,
+                partition: record.partition,
+                offset: record.offset,
+            })
+        })
+        .collect()
+}
+
+/// The body committing `messages`: the last offset of each of their partitions. The proxy
+/// commits the offset following it, which the group reads next.
+fn committed_offsets(messages: &[KafkaMessage]) -> serde_json::Value {
+    let mut last: BTreeMap<(&str, i32), i64> = BTreeMap::new();
+    for message in messages {
+        let offset = last
+            .entry((&message.topic, message.partition))
+            .or_insert(message.offset);
+        *offset = (*offset).max(message.offset);
+    }
+    let offsets: Vec<serde_json::Value> = last
+        .into_iter()
+        .map(|((topic, partition), offset)| {
+            serde_json::json!({ "topic": topic, "partition": partition, "offset": offset })
+        })
+        .collect();
+    serde_json::json!({ "offsets": offsets })
+}
+
+/// `response`, unless the proxy answered `action` with an error.
+async fn checked(response: Response, action: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(anyhow!(
+        "Kafka REST proxy answered {} to {}: {}",
+        status,
+        action,
+        body
+    ))
+}
+
+/// A consumer instance of the proxy, subscribed to one topic.
+pub struct KafkaConsumer {
+    client: Client,
+    /// URL of the instance, given by the proxy.
+    base_uri: String,
+}
+
+impl KafkaConsumer {
+    /// Joins `group` with a new consumer instance subscribed to `topic`. A group new to the
+    /// topic starts from its earliest messages.
+    pub async fn subscribe(proxy_url: &str, group: &str, topic: &str) -> Result<Self> {
+        let client = Client::new();
+        let response = client
+            .post(format!(
+                "{}/consumers/{}",
+                proxy_url.trim_end_matches('/'),
+                group
+            ))
+            .header("content-type", V2_JSON)
+            .json(&serde_json::json!({
+                "format": "binary",
+                "auto.offset.reset": "earliest",
+                "auto.commit.enable": "false",
+            }))
+            .send()
+            .await?;
+        let instance: CreatedInstance = checked(response, "the creation of a consumer")
+            .await?
+            .json()
+            .await?;
+        let consumer = Self {
+            client,
+            base_uri: instance.base_uri,
+        };
+        let response = consumer
+            .client
+            .post(format!("{}/subscription", consumer.base_uri))
+            .header("content-type", V2_JSON)
+            .json(&serde_json::json!({ "topics": [topic] }))
+            .send()
+            .await?;
+        if let Err(e) = checked(response, "a subscription").await {
+            let _ = consumer.close().await;
+            return Err(e);
+        }
+        Ok(consumer)
+    }
+
+    /// The next messages, none when there were none within the poll timeout.
+    pub async fn poll(&self) -> Result<Vec<KafkaMessage>> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/records?timeout={}",
+                self.base_uri, POLL_TIMEOUT_MS
+            ))
+            .header("accept", BINARY_JSON)
+            .send()
+            .await?;
+        let records: Vec<EncodedRecord> = checked(response, "a poll").await?.json().await?;
+        decode_records(records)
+    }
+
+    /// Commits `messages`, so the group reads on after them.
+    pub async fn commit(&self, messages: &[KafkaMessage]) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let response = self
+            .client
+            .post(format!("{}/offsets", self.base_uri))
+            .header("content-type", V2_JSON)
+            .json(&committed_offsets(messages))
+            .send()
+            .await?;
+        checked(response, "an offset commit").await?;
+        Ok(())
+    }
+
+    /// Deletes the instance, leaving the group.
+    pub async fn close(self) -> Result<()> {
+        let response = self
+            .client
+            .delete(&self.base_uri)
+            .header("content-type", V2_JSON)
+            .send()
+            .await?;
+        checked(response, "the deletion of a consumer").await?;
+        Ok(())
+    }
+}
+
+/// Key and value of a message to produce.
+pub type KeyedMessage = (Option<Vec<u8>>, Vec<u8>);
+
+/// Produces keyed messages to a topic.
+pub struct KafkaProducer {
+    client: Client,
+    /// `<proxy>/topics/<topic>`.
+    endpoint: String,
+}
+
+impl KafkaProducer {
+    pub fn new(proxy_url: &str, topic: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: format!("{}/topics/{}", proxy_url.trim_end_matches('/'), topic),
+        }
+    }
+
+    /// The producer of a sink given as `kafka://host:port/topic`, a topic reached through
+    /// a Kafka REST proxy listening on `host:port`. None for sinks of another kind.
+    pub fn for_sink(spec: &str) -> Result<Option<Self>> {
+        let Some(target) = spec.strip_prefix("kafka://") else {
+            return Ok(None);
+        };
+        let (proxy, topic) = target
+            .split_once('/')
+            .filter(|(proxy, topic)| !proxy.is_empty() && !topic.is_empty())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid Kafka sink '{}', expected kafka://host:port/topic",
+                    spec
+                )
+            })?;
+        Ok(Some(Self::new(&format!("http://{}", proxy), topic)))
+    }
+
+    /// Produces `messages`, keys and values, in one request. Fails unless all of them were.
+    pub async fn produce(&self, messages: &[KeyedMessage]) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<serde_json::Value> = messages
+            .iter()
+            .map(|(key, value)| {
+                serde_json::json!({
+                    "key": key.as_ref().map(|key| STANDARD.encode(key)),
+                    "value": STANDARD.encode(value),
+                })
+            })
+            .collect();
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .header("content-type", BINARY_JSON)
+            .json(&serde_json::json!({ "records": records }))
+            .send()
+            .await?;
+        let produced: ProduceResponse =
+            checked(response, "a produce request").await?.json().await?;
+        let failed: Vec<&str> = produced
+            .offsets
+            .iter()
+            .filter_map(|offset| offset.error.as_deref())
+            .collect();
+        if let Some(error) = failed.first() {
+            return Err(anyhow!(
+                "{} of {} messages were not produced: {}",
+                failed.len(),
+                messages.len(),
+                error
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ProduceResponse {
+    #[serde(default)]
+    offsets: Vec<ProducedOffset>,
+}
+
+#[derive(Deserialize)]
+struct ProducedOffset {
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(partition: i32, offset: i64) -> KafkaMessage {
+        KafkaMessage {
+            topic: "requests".to_string(),
+            partition,
+            offset,
+            key: None,
+            value: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_message_format_from_str() {
+        assert!(matches!(
+            "v2".parse::<MessageFormat>(),
+            Ok(MessageFormat::V2)
+        ));
+        let format = "json".parse::<MessageFormat>().unwrap();
+        assert_eq!(format.to_string(), "json");
+        assert!("avro".parse::<MessageFormat>().is_err());
+    }
+
+    #[test]
+    fn test_decode_records() {
+        let records: Vec<EncodedRecord> = serde_json::from_value(serde_json::json!([
+            {"topic": "requests", "partition": 1, "offset": 7, "key": "b3JkZXItMQ==", "value": "e30="},
+            {"topic": "requests", "partition": 0, "offset": 3, "key": null, "value": null},
+        ]))
+        .unwrap();
+        let messages = decode_records(records).unwrap();
+        assert_eq!(messages[0].key.as_deref(), Some(&b"order-1"[..]));
+        assert_eq!(messages[0].value, b"{}");
+        assert_eq!(messages[1].key, None);
+        assert!(messages[1].value.is_empty());
+
+        let invalid: Vec<EncodedRecord> = serde_json::from_value(serde_json::json!([
+            {"topic": "requests", "partition": 0, "offset": 3, "key": null, "value": "not base64!"},
+        ]))
+        .unwrap();
+        assert!(decode_records(invalid).is_err());
+    }
+
+    #[test]
+    fn test_producer_for_sink() {
+        let producer = KafkaProducer::for_sink("kafka://proxy:8082/stream").unwrap();
+        assert_eq!(
+            producer.unwrap().endpoint,
+            "http://proxy:8082/topics/stream"
+        );
+        assert!(
+            KafkaProducer::for_sink("/var/log/stream.jsonl")
+                .unwrap()
+                .is_none()
+        );
+        assert!(KafkaProducer::for_sink("kafka://proxy:8082").is_err());
+        assert!(KafkaProducer::for_sink("kafka:///stream").is_err());
+    }
+
+    #[test]
+    fn test_committed_offsets_are_the_last_of_each_partition() {
+        let body = committed_offsets(&[message(0, 4), message(1, 9), message(0, 6), message(0, 5)]);
+        assert_eq!(
+            body,
+            serde_json::json!({"offsets": [
+                {"topic": "requests", "partition": 0, "offset": 6},
+                {"topic": "requests", "partition": 1, "offset": 9},
+            ]})
+        );
+    }
+}
@@ -1,12 +1,166 @@
+pub mod analytics;
 pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod concurrency;
+pub mod connection;
+pub mod cors;
+pub mod daemon;
+pub mod deadline;
+pub mod events;
+pub mod ids;
+pub mod jwt;
+pub mod kafka;
+pub mod logging;
+pub mod metrics;
 pub mod model;
+pub mod overload;
+pub mod preflight;
+pub mod quota;
+pub mod rate_limit;
+pub mod readiness;
+pub mod redis;
+pub mod reload;
+pub mod settings;
+pub mod shared_port;
+pub mod shutdown;
+pub mod stats;
+pub mod tenants;
+pub mod timeline;
+pub mod timeseries;
+pub mod tls;
+pub mod traffic;
 
 use std::sync::Arc;
 
+pub use analytics::{
+    AnalyticsRecord, AnalyticsSink, AnalyticsTee, FileAnalyticsSink, KafkaAnalyticsSink,
+};
+pub use api::cast::{CastTensor, CastValues, DATATYPE_PARAMETER, OutputDatatype};
+pub use api::codec::{Codec, FORMAT_VERSION, JsonCodec, PostcardCodec};
+pub use api::devices::{
+    DEFAULT_GPU_SLOTS, Device, DeviceLease, DeviceLoad, DeviceScheduler, PlacedRuntime,
+};
+pub use api::error::{ApiError, ERROR_DOMAIN, ErrorCode, ErrorDetail, FieldViolation};
 pub use api::fake::FakeInferenceProcessor;
 pub use api::inference::{InferenceRequest, InferenceResponse};
-pub use api::mlflow_client::{MLFlowClient, MLFlowClientTrait, MLFlowModel, MLFlowModelVersion};
-pub use model::model_discovery_service::{ModelDiscoveryService, ModelId, ModelSource};
+pub use api::inference_runtime::{InferenceRuntime, ProcessorRuntime};
+pub use api::instance_pool::{InstanceLoader, InstancePool, InstanceRestart, InstanceStatus};
+pub use api::mlflow_client::{
+    MLFlowClient, MLFlowClientTrait, MLFlowModel, MLFlowModelVersion, MLModel,
+};
+pub use api::model_metadata::{ModelMetadata, ModelSignature, TensorMetadata};
+pub use api::runtime_registry::{RuntimeFactory, RuntimeRegistry};
+pub use api::sampling::{MAX_STOP_SEQUENCES, SamplingOptions, SamplingRefusal};
+pub use api::schema::{
+    MappingConverter, SCHEMA_VERSION_HEADER, SCHEMA_VERSION_PARAMETER, SchemaConverter, SchemaPlan,
+    SchemaRegistry, SchemaVersions,
+};
+pub use api::shared_memory::{
+    SHARED_MEMORY_BYTE_SIZE_PARAMETER, SHARED_MEMORY_OFFSET_PARAMETER,
+    SHARED_MEMORY_REGION_PARAMETER, SharedMemoryRef, SharedMemoryRegistry, SystemRegionStatus,
+};
+pub use api::tensor_view::TensorView;
+pub use api::transfer::{
+    DeviceBatch, DeviceStaging, Direction, PinnedBuffer, PinnedBufferPool, TensorSpan,
+    TransferMetrics,
+};
+pub use api::validation::{INVALID_TENSORS, TensorMismatch, TensorRefusal};
+pub use audit::{
+    AUDIT_QUEUE_CAPACITY, AuditLogger, AuditRecord, AuditSampling, AuditSink, KafkaRestSink,
+    RotatingFileSink, StdoutAuditSink, caller_identity,
+};
+pub use auth::{
+    AUTHORIZATION_HEADER, ApiKey, AuthError, Authenticated, Authenticator, KeyStore, Target,
+    bearer_token,
+};
+pub use batch::{BatchJobSpec, BatchJobStatus, BatchJobs, InputFormat, JobState};
+pub use concurrency::{
+    ConcurrencyLimiter, ConcurrencyLimits, InFlightPermit, ModelConcurrency, Shed,
+};
+pub use connection::{ConnectionLimits, IdleTimeout};
+pub use cors::{ANY_ORIGIN, CorsConfig};
+pub use daemon::{NOTIFY_SOCKET_ENV, PidFile, sd_notify};
+pub use deadline::{
+    GRPC_TIMEOUT_HEADER, REQUEST_TIMEOUT_HEADER, parse_grpc_timeout, parse_timeout_ms,
+};
+pub use events::{
+    EVENT_QUEUE_CAPACITY, EventBus, EventObserver, LogObserver, ServerEvent, WebhookObserver,
+};
+pub use ids::{CORRELATION_ID_HEADER, IdProvider, IdScheme, correlation_id};
+pub use jwt::{JwtConfig, JwtValidator, Principal, Role, is_jwt};
+pub use kafka::{
+    KafkaConfig, KafkaConsumer, KafkaMessage, KafkaProducer, KeyedMessage, MessageFormat,
+};
+pub use logging::{LogLevel, log_enabled, log_level, set_log_level};
+pub use metrics::{BATCH_SIZE_BUCKETS, Histogram, LATENCY_BUCKETS, MetricsRecorder};
+pub use model::buffer_tuning::{BufferSizing, BufferStats};
+pub use model::capabilities::{CAPABILITY_NOT_SUPPORTED, Capabilities, CapabilityRefusal};
+pub use model::circuit::{CircuitOpen, CircuitPolicy, CircuitState, CircuitStats};
+pub use model::circular_buffer::OverflowPolicy;
+pub use model::context_window::{ContextRefusal, ContextWindow, Tokenizer, Vocabulary};
+pub use model::hints::{
+    DEVICE_PARAMETER, DeviceClass, ExecutionTarget, HINTS_IGNORED_PARAMETER, Hint, HintPlan,
+    HintPolicy, INSTANCE_PARAMETER, IgnoredHint, NO_BATCHING_PARAMETER,
+};
+pub use model::images::{
+    DEFAULT_MAX_IMAGE_BYTES, IMAGE_TOO_LARGE, INVALID_IMAGE, ImageInfo, ImageLimits, ImageRefusal,
+};
+pub use model::labels::{LabelSelector, Labels, MODEL_SELECTOR_HEADER};
+pub use model::layout::{LegacyModel, LegacyVersion, ModelLayout};
+pub use model::memory::{
+    DeviceMemory, ManagedRuntime, MemoryBudget, MemoryManager, MemoryState, MemoryUsage,
+    VersionMemory,
+};
+pub use model::mlflow_watcher::{MLFlowStageWatcher, StageTransition, TransitionAction};
+pub use model::model_config::{InstanceGroup, ModelConfig, TensorSpec, WarmupInput, WarmupSample};
+pub use model::model_discovery_service::{
+    BufferRefusal, ModelDiscoveryService, ModelId, ModelSource, ModelSummary, ModelVersionId,
+    PendingInferenceRequest, SchedulingStats, VersionPolicy,
+};
+pub use model::model_store::LocalModelStore;
+pub use model::object_store::{ObjectStoreClient, S3Credentials};
+pub use model::pause::{DEFAULT_PAUSE_RETRY_AFTER, ModelPause, ModelPaused};
+pub use model::priority::{PRIORITY_HEADER, Priority};
+pub use model::response_cache::{ResponseCache, ResponseCacheConfig};
+pub use model::result_backend::{RedisResultBackend, ResultBackend};
+pub use model::result_store::{
+    ResultState, ResultStore, StreamChunks, StreamPacing, StreamStall, StreamStore,
+};
+pub use model::selftest::{CaseResult, Difference, GoldenCase, GoldenOutput, SelfTestReport};
+pub use model::shadow::ShadowStats;
+pub use model::watermark::{
+    Detection, WATERMARK_PARAMETER, WatermarkConfig, WatermarkStatus, Watermarker, Watermarking,
+    ZERO_WIDTH_SCHEME, ZeroWidthWatermark,
+};
+pub use overload::{FeatureGates, LoadGuard, OverloadController, OverloadPolicy};
+pub use preflight::{CheckResult, CheckStatus, Preflight, PreflightReport};
+pub use quota::{
+    QUOTA_LIMIT_REQUESTS_HEADER, QUOTA_LIMIT_TOKENS_HEADER, QUOTA_REMAINING_REQUESTS_HEADER,
+    QUOTA_REMAINING_TOKENS_HEADER, QUOTA_RESET_HEADER, Quota, QuotaLimits, QuotaRefusal,
+    QuotaTracker, QuotaUsage, parse_window,
+};
+pub use rate_limit::{
+    API_KEY_HEADER, RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER, RATE_LIMIT_RESET_HEADER,
+    RateDecision, RateLimit, RateLimited, RateLimiter, RateLimits, client_key,
+};
+pub use readiness::{MODELS_LOADING, Readiness, SHUTTING_DOWN};
+pub use reload::{ConfigReload, ReloadReport};
+pub use settings::{CONFIG_ENV, ENV_PREFIX, Settings};
+pub use shared_port::{Listener, PortRouting, Protocol, SharedPort};
+pub use shutdown::{Shutdown, ShutdownSignal, termination};
+pub use stats::{LatencySummary, RouteStats, SCHEDULER_ROUTE, StatsRegistry};
+pub use tenants::{
+    DEFAULT_TENANT, ErrorBudget, ErrorBudgetPolicy, Refusal, TENANT_HEADER, TenantState,
+    TenantStats, TenantTransition, request_tenant,
+};
+pub use timeline::{DEBUG_HEADER, Timeline, TimelineEvent, timeline_requested};
+pub use timeseries::{TimeSeries, TimeSeriesPoint, TimeSeriesRegistry};
+pub use tls::{ReloadableTls, TlsConfig};
+pub use traffic::{
+    TenantTraffic, TrafficAccounting, TrafficLimits, TrafficRefusal, parse_byte_size,
+};
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -17,6 +171,55 @@ pub struct InferenceServerConfig {
     pub rest_port: u16,
     pub grpc_hostname: String,
     pub grpc_port: u16,
+    /// When set, both servers are reached on this one port instead of their own.
+    pub shared_port: Option<Arc<SharedPort>>,
+    /// When set, streamed responses are teed to this analytics sink.
+    pub analytics: Option<AnalyticsTee>,
+    /// When set, every answered inference request is recorded in this audit log.
+    pub audit: Option<AuditLogger>,
+    /// Per-connection limits applied by both servers.
+    pub limits: ConnectionLimits,
+    /// Global load tracking shared by both servers, degrading service under overload.
+    pub overload: Arc<OverloadController>,
+    /// Generates request and job ids for requests that do not bring their own.
+    pub ids: Arc<dyn IdProvider>,
+    /// Browser origins allowed to make gRPC-Web calls; any origin when empty.
+    pub cors_origins: Vec<String>,
+    /// When set, browsers from its origins may call the REST API.
+    pub rest_cors: Option<CorsConfig>,
+    /// Inference rate limits per client and per model, shared by both servers.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Requests in flight at once, globally and per model, shared by both servers.
+    pub concurrency: Arc<ConcurrencyLimiter>,
+    /// Bytes received and sent per tenant, with the request size and daily limits.
+    pub traffic: Arc<TrafficAccounting>,
+    /// Requests and tokens per account and window, shared by both servers.
+    pub quotas: Arc<QuotaTracker>,
+    /// When set, the REST server also consumes inference requests from Kafka.
+    pub kafka: Option<KafkaConfig>,
+    /// Directory batch jobs are checkpointed in; the batch job API is off when unset.
+    pub batch_dir: Option<std::path::PathBuf>,
+    /// Where results of asynchronous inferences are shared, besides the memory of the server.
+    pub result_backend: Option<Arc<dyn ResultBackend>>,
+    /// Shared memory regions clients registered with either server.
+    pub shared_memory: Arc<SharedMemoryRegistry>,
+    /// Flow control of the response streams of both servers.
+    pub stream_pacing: StreamPacing,
+    /// When set, both servers only accept TLS connections.
+    pub tls: Option<TlsConfig>,
+    /// When set, both servers refuse requests without one of its API keys.
+    pub api_keys: Option<Arc<KeyStore>>,
+    /// When set, both servers accept JWTs verified by it, and enforce their roles.
+    pub jwt: Option<Arc<JwtValidator>>,
+    /// When set, the admin API can reload the configuration with it.
+    pub config_reload: Option<Arc<ConfigReload>>,
+    /// Host of the admin API listener, when it has its own port.
+    pub admin_hostname: String,
+    /// When set, the admin API is served on this port only, and no longer on the REST one.
+    pub admin_port: Option<u16>,
+    /// Whether the tenant header is set by a trusted proxy, rather than ignored in favor
+    /// of the authenticated account. See `tenants::request_tenant`.
+    pub trust_tenant_header: bool,
 }
 
 #[async_trait]
@@ -25,5 +228,9 @@ pub trait InferenceServerBuilder: Sized + Send + Sync {
         context: InferenceServerConfig,
         model_discovery_service: Arc<ModelDiscoveryService>,
     ) -> Self;
-    async fn start(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    /// Serves until `shutdown` fires, then drains the open connections until its deadline.
+    async fn start(
+        self,
+        shutdown: ShutdownSignal,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
 }
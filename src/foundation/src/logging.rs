@@ -0,0 +1,105 @@
+/* Verbosity of the server's messages.
+
+Warnings and errors are always written to standard error. Informational
+messages (models registered, listeners started, configuration reloaded) are
+written to standard output at the `info` level, the default, and the traces of
+individual requests at the `debug` level; `warn` leaves only the warnings. The
+level is set with `--log-level` and can be changed at runtime through the
+admin API or a configuration reload. Messages are written with `log_info!` and
+`log_debug!`, which format nothing when their level is off.
+*/
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 3] = [LogLevel::Warn, LogLevel::Info, LogLevel::Debug];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        Self::ALL
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow!("Invalid log level '{}', expected warn, info or debug", s))
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// The level messages are written at.
+pub fn log_level() -> LogLevel {
+    LogLevel::ALL[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of `level` are written.
+pub fn log_enabled(level: LogLevel) -> bool {
+    level <= log_level()
+}
+
+/// Writes an informational message to standard output, like `println!`.
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::logging::log_enabled($crate::logging::LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Writes the trace of a request to standard output, like `println!`.
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::log_enabled($crate::logging::LogLevel::Debug) {
+            println!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_parse_and_order() {
+        assert_eq!("DEBUG".parse::<LogLevel>().unwrap(), LogLevel::Debug);
+        assert_eq!(" warn".parse::<LogLevel>().unwrap(), LogLevel::Warn);
+        assert!("trace".parse::<LogLevel>().is_err());
+        assert!(LogLevel::Warn < LogLevel::Info && LogLevel::Info < LogLevel::Debug);
+        assert_eq!(LogLevel::default().to_string(), "info");
+    }
+}
@@ -0,0 +1,255 @@
+/* Prometheus metrics recorder.
+
+The servers record every inference they answer with its protocol (`rest` or
+`grpc`), model and status (the HTTP status code, or the gRPC code name), and
+the dynamic batcher records the size of every batch it dispatches. Both go to
+Prometheus histograms with fixed buckets. Requests for models that are not
+registered are recorded with an empty model label, so made-up names in request
+paths cannot grow the number of series. As an observer of the server's event
+bus, the recorder also counts the events by kind.
+
+`ModelDiscoveryService::render_metrics` combines the recorder with the gauges
+read from the request buffers (queue depth, capacity and fill) and with the
+`StatsRegistry` series.
+*/
+
+use dashmap::DashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::events::{EventObserver, ServerEvent};
+use crate::stats::escape_label;
+
+/// Upper bounds of the request latency buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 14] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+/// Upper bounds of the batch size buckets, in requests.
+pub const BATCH_SIZE_BUCKETS: [f64; 10] =
+    [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0];
+
+/// A Prometheus histogram over fixed buckets, updated without locks.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, the last one above every bound.
+    counts: Vec<AtomicU64>,
+    /// Bits of the f64 sum of the observations.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` lines of `name` with `labels`.
+    pub(crate) fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = self
+                .bounds
+                .get(index)
+                .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, le, cumulative
+            );
+        }
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum());
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, cumulative);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct RequestKey {
+    protocol: &'static str,
+    model: String,
+    status: String,
+}
+
+/// Request and batch histograms shared by the servers and the scheduler.
+#[derive(Debug, Default)]
+pub struct MetricsRecorder {
+    requests: DashMap<RequestKey, Histogram>,
+    /// Batch sizes per model and version.
+    batches: DashMap<(String, String), Histogram>,
+    /// Server events per kind.
+    events: DashMap<&'static str, AtomicU64>,
+}
+
+impl MetricsRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an inference answered on `protocol` with `status` after `latency`. `model` is
+    /// None for models that are not registered.
+    pub fn record_request(
+        &self,
+        protocol: &'static str,
+        model: Option<&str>,
+        status: &str,
+        latency: Duration,
+    ) {
+        let key = RequestKey {
+            protocol,
+            model: model.unwrap_or_default().to_string(),
+            status: status.to_string(),
+        };
+        self.requests
+            .entry(key)
+            .or_insert_with(|| Histogram::new(&LATENCY_BUCKETS))
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Records a batch of `size` requests dispatched to `model` `version`.
+    pub fn record_batch(&self, model: &str, version: &str, size: usize) {
+        self.batches
+            .entry((model.to_string(), version.to_string()))
+            .or_insert_with(|| Histogram::new(&BATCH_SIZE_BUCKETS))
+            .observe(size as f64);
+    }
+
+    /// Drops the batch sizes of a retired version.
+    pub fn remove_version(&self, model: &str, version: &str) {
+        self.batches
+            .remove(&(model.to_string(), version.to_string()));
+    }
+
+    /// The histograms in the Prometheus text exposition format.
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(
+            out,
+            "# HELP galemind_inference_duration_seconds Latency of answered inference requests.\n\
+             # TYPE galemind_inference_duration_seconds histogram"
+        );
+        let mut requests: Vec<_> = self.requests.iter().collect();
+        requests.sort_by(|a, b| a.key().cmp(b.key()));
+        for entry in requests {
+            let key = entry.key();
+            let labels = format!(
+                "protocol=\"{}\",model=\"{}\",status=\"{}\"",
+                key.protocol,
+                escape_label(&key.model),
+                escape_label(&key.status)
+            );
+            entry.render(out, "galemind_inference_duration_seconds", &labels);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP galemind_batch_size Requests per dispatched batch.\n\
+             # TYPE galemind_batch_size histogram"
+        );
+        let mut batches: Vec<_> = self.batches.iter().collect();
+        batches.sort_by(|a, b| a.key().cmp(b.key()));
+        for entry in batches {
+            let (model, version) = entry.key();
+            let labels = format!(
+                "model=\"{}\",version=\"{}\"",
+                escape_label(model),
+                escape_label(version)
+            );
+            entry.render(out, "galemind_batch_size", &labels);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP galemind_server_events_total Events published on the server event bus.\n\
+             # TYPE galemind_server_events_total counter"
+        );
+        let mut events: Vec<_> = self.events.iter().collect();
+        events.sort_by_key(|entry| *entry.key());
+        for entry in events {
+            let _ = writeln!(
+                out,
+                "galemind_server_events_total{{event=\"{}\"}} {}",
+                entry.key(),
+                entry.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+impl EventObserver for MetricsRecorder {
+    fn observe(&self, event: &ServerEvent) {
+        self.events
+            .entry(event.kind())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histograms_render_cumulative_buckets() {
+        let recorder = MetricsRecorder::new();
+        recorder.record_request("rest", Some("m"), "200", Duration::from_millis(3));
+        recorder.record_request("rest", Some("m"), "200", Duration::from_millis(40));
+        recorder.record_request("grpc", None, "NotFound", Duration::from_secs(60));
+        recorder.record_batch("m", "1", 3);
+        recorder.observe(&ServerEvent::RequestRejected {
+            model: "m".to_string(),
+            reason: "full".to_string(),
+        });
+
+        let mut out = String::new();
+        recorder.render(&mut out);
+        let labels = "protocol=\"rest\",model=\"m\",status=\"200\"";
+        assert!(out.contains(&format!(
+            "galemind_inference_duration_seconds_bucket{{{},le=\"0.0025\"}} 0\n",
+            labels
+        )));
+        assert!(out.contains(&format!(
+            "galemind_inference_duration_seconds_bucket{{{},le=\"0.005\"}} 1\n",
+            labels
+        )));
+        assert!(out.contains(&format!(
+            "galemind_inference_duration_seconds_count{{{}}} 2\n",
+            labels
+        )));
+        assert!(out.contains(
+            "galemind_inference_duration_seconds_bucket{protocol=\"grpc\",model=\"\",status=\"NotFound\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(out.contains("galemind_batch_size_bucket{model=\"m\",version=\"1\",le=\"4\"} 1\n"));
+        assert!(out.contains("galemind_batch_size_sum{model=\"m\",version=\"1\"} 3\n"));
+        assert!(out.contains("galemind_server_events_total{event=\"request_rejected\"} 1\n"));
+    }
+}
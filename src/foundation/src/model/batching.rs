@@ -0,0 +1,433 @@
+/* Dynamic batching of inference requests.
+
+Each model version with dynamic batching enabled gets a queue drained by its
+own task. The first request to arrive opens a batch; further requests join it
+until the batch holds `max_batch_size` requests or the first one has waited
+`max_queue_delay`, whichever comes first. The batch is then dispatched as a
+single `process_batch` call and every caller receives its own response.
+
+Like Triton's `preferred_batch_size`, a batch that has reached one of the
+preferred sizes is dispatched right away when no further request is queued,
+instead of waiting out the delay for a larger batch. Up to `instances`
+batches of a model version run concurrently.
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use futures::FutureExt;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::api::inference::{InferenceError, InferenceRequest, InferenceResponse};
+use crate::api::inference_runtime::{InferenceRuntime, panic_message};
+use crate::deadline::{deadline_exceeded, is_expired};
+use crate::events::{EventBus, ServerEvent};
+use crate::ids::IdProvider;
+use crate::metrics::MetricsRecorder;
+use crate::model::model_config::ModelConfig;
+use crate::model::model_discovery_service::ModelVersionId;
+
+/// Requests that may wait in a model version's queue, in multiples of `max_batch_size`.
+const QUEUED_BATCHES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchPolicy {
+    pub max_batch_size: usize,
+    pub max_queue_delay: Duration,
+    /// Sorted, deduplicated batch sizes dispatched without waiting for the delay.
+    pub preferred_batch_sizes: Vec<usize>,
+    /// Batches that may run concurrently.
+    pub instances: usize,
+}
+
+impl BatchPolicy {
+    /// Policy of a model configuration, `None` unless it enables dynamic batching.
+    pub fn from_config(config: &ModelConfig) -> Option<Self> {
+        let batching = config.dynamic_batching.as_ref()?;
+        if config.max_batch_size == 0 {
+            return None;
+        }
+        let mut preferred_batch_sizes: Vec<usize> = batching
+            .preferred_batch_sizes
+            .iter()
+            .map(|size| *size as usize)
+            .collect();
+        preferred_batch_sizes.sort_unstable();
+        preferred_batch_sizes.dedup();
+
+        Some(Self {
+            max_batch_size: config.max_batch_size as usize,
+            max_queue_delay: Duration::from_secs_f64(batching.max_queue_delay_ms.max(0.0) / 1000.0),
+            preferred_batch_sizes,
+            instances: config.instance_count.max(1) as usize,
+        })
+    }
+}
+
+struct PendingRequest {
+    request: InferenceRequest,
+    enqueued: Instant,
+    respond_to: oneshot::Sender<InferenceResponse>,
+}
+
+struct BatchQueue {
+    policy: BatchPolicy,
+    runtime: Arc<dyn InferenceRuntime>,
+    sender: mpsc::Sender<PendingRequest>,
+}
+
+/// Per model version batching queues, created on first use.
+pub struct DynamicBatcher {
+    queues: DashMap<ModelVersionId, BatchQueue>,
+    ids: Arc<dyn IdProvider>,
+    metrics: Arc<MetricsRecorder>,
+    events: Arc<EventBus>,
+}
+
+impl DynamicBatcher {
+    pub fn new(ids: Arc<dyn IdProvider>) -> Self {
+        Self {
+            queues: DashMap::new(),
+            ids,
+            metrics: Arc::new(MetricsRecorder::default()),
+            events: Arc::new(EventBus::default()),
+        }
+    }
+
+    /// Records the size of every dispatched batch in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<MetricsRecorder>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Publishes every executed batch on `events`.
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Queues `request` for the next batch of `version_id` and waits for its response.
+    pub async fn submit(
+        &self,
+        version_id: &ModelVersionId,
+        runtime: Arc<dyn InferenceRuntime>,
+        policy: &BatchPolicy,
+        request: InferenceRequest,
+    ) -> Result<InferenceResponse> {
+        let sender = self.queue(version_id, runtime, policy);
+        let (respond_to, response) = oneshot::channel();
+        if let Some(timeline) = &request.timeline {
+            timeline.mark(
+                "queue.enter",
+                Some(format!("batch queue of {}", version_id)),
+            );
+        }
+        sender
+            .send(PendingRequest {
+                request,
+                enqueued: Instant::now(),
+                respond_to,
+            })
+            .await
+            .map_err(|_| anyhow!("Batch queue of {} is closed", version_id))?;
+        response
+            .await
+            .map_err(|_| anyhow!("Batch of {} was dropped without a response", version_id))
+    }
+
+    /// Closes the queue of `version_id`; requests already queued are still served.
+    pub fn remove(&self, version_id: &ModelVersionId) {
+        self.queues.remove(version_id);
+    }
+
+    fn queue(
+        &self,
+        version_id: &ModelVersionId,
+        runtime: Arc<dyn InferenceRuntime>,
+        policy: &BatchPolicy,
+    ) -> mpsc::Sender<PendingRequest> {
+        let mut queue = self
+            .queues
+            .entry(version_id.clone())
+            .or_insert_with(|| self.spawn_queue(version_id, runtime.clone(), policy));
+        // A changed policy or runtime replaces the queue; the old task drains what it holds.
+        if queue.policy != *policy
+            || !Arc::ptr_eq(&queue.runtime, &runtime)
+            || queue.sender.is_closed()
+        {
+            *queue = self.spawn_queue(version_id, runtime, policy);
+        }
+        queue.sender.clone()
+    }
+
+    fn spawn_queue(
+        &self,
+        version_id: &ModelVersionId,
+        runtime: Arc<dyn InferenceRuntime>,
+        policy: &BatchPolicy,
+    ) -> BatchQueue {
+        let (sender, receiver) = mpsc::channel(policy.max_batch_size * QUEUED_BATCHES);
+        tokio::spawn(run_queue(
+            version_id.clone(),
+            receiver,
+            runtime.clone(),
+            policy.clone(),
+            self.ids.clone(),
+            self.metrics.clone(),
+            self.events.clone(),
+        ));
+        BatchQueue {
+            policy: policy.clone(),
+            runtime,
+            sender,
+        }
+    }
+}
+
+async fn run_queue(
+    version_id: ModelVersionId,
+    mut queue: mpsc::Receiver<PendingRequest>,
+    runtime: Arc<dyn InferenceRuntime>,
+    policy: BatchPolicy,
+    ids: Arc<dyn IdProvider>,
+    metrics: Arc<MetricsRecorder>,
+    events: Arc<EventBus>,
+) {
+    let instances = Arc::new(Semaphore::new(policy.instances));
+
+    while let Some(first) = queue.recv().await {
+        let deadline = Instant::now() + policy.max_queue_delay;
+        let mut batch = vec![first];
+
+        while batch.len() < policy.max_batch_size {
+            if policy.preferred_batch_sizes.contains(&batch.len()) {
+                match queue.try_recv() {
+                    Ok(pending) => {
+                        batch.push(pending);
+                        continue;
+                    }
+                    Err(_) => break,
+                }
+            }
+            match tokio::time::timeout_at(deadline, queue.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                // Delay expired or the queue was closed.
+                _ => break,
+            }
+        }
+
+        let Ok(instance) = instances.clone().acquire_owned().await else {
+            break;
+        };
+        let runtime = runtime.clone();
+        let batch_id = ids.next_id();
+        let version_id = version_id.clone();
+        let metrics = metrics.clone();
+        let events = events.clone();
+        tokio::spawn(async move {
+            let _instance = instance;
+            dispatch(
+                &version_id,
+                &batch_id,
+                runtime.as_ref(),
+                &metrics,
+                &events,
+                batch,
+            )
+            .await;
+        });
+    }
+}
+
+async fn dispatch(
+    version_id: &ModelVersionId,
+    batch_id: &str,
+    runtime: &dyn InferenceRuntime,
+    metrics: &MetricsRecorder,
+    events: &EventBus,
+    batch: Vec<PendingRequest>,
+) {
+    // Requests whose deadline passed while queued are answered without running.
+    let now = std::time::Instant::now();
+    let (expired, batch): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .partition(|pending| is_expired(pending.request.deadline, now));
+    for pending in expired {
+        let _ = pending
+            .respond_to
+            .send(deadline_exceeded(&pending.request.id, "queued"));
+    }
+    if batch.is_empty() {
+        return;
+    }
+    let size = batch.len();
+    metrics.record_batch(&version_id.model.0, &version_id.version, size);
+    let membership = format!("batch {} of {} requests", batch_id, size);
+    let mut timelines = Vec::new();
+    let (requests, callers): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|pending| {
+            if let Some(timeline) = &pending.request.timeline {
+                timeline.span(
+                    "queue",
+                    pending.enqueued.into_std(),
+                    Some(membership.clone()),
+                );
+                timelines.push(timeline.clone());
+            }
+            (pending.request, pending.respond_to)
+        })
+        .unzip();
+
+    let started = std::time::Instant::now();
+    // A panicking runtime fails the requests of the batch instead of the batching task.
+    let (mut responses, failure) = match AssertUnwindSafe(runtime.process_batch(requests))
+        .catch_unwind()
+        .await
+    {
+        Ok(responses) => (responses.into_iter(), None),
+        Err(panic) => (
+            Vec::new().into_iter(),
+            Some(format!(
+                "Runtime of {} panicked: {}",
+                version_id,
+                panic_message(panic.as_ref())
+            )),
+        ),
+    };
+    for timeline in timelines {
+        timeline.span("runtime.process_batch", started, Some(membership.clone()));
+    }
+    events.publish(ServerEvent::BatchExecuted {
+        model: version_id.model.0.clone(),
+        version: version_id.version.clone(),
+        batch_id: batch_id.to_string(),
+        size,
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        failure: failure.clone().or_else(|| {
+            (responses.len() != size).then(|| {
+                format!(
+                    "returned {} responses for {} requests",
+                    responses.len(),
+                    size
+                )
+            })
+        }),
+    });
+    for caller in callers {
+        let response = responses.next().unwrap_or_else(|| {
+            InferenceResponse::Error(InferenceError {
+                error: failure.clone().unwrap_or_else(|| {
+                    format!("Batch {} returned no response for this request", batch_id)
+                }),
+            })
+        });
+        // The caller may have given up waiting.
+        let _ = caller.send(response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeInferenceProcessor;
+    use crate::api::inference::InferenceProcessor;
+    use crate::ids::IdScheme;
+    use crate::model::priority::Priority;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// Records the size of every batch it processes.
+    #[derive(Default)]
+    struct RecordingRuntime {
+        batch_sizes: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl InferenceRuntime for RecordingRuntime {
+        fn model_id(&self) -> &str {
+            "m"
+        }
+
+        async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+            FakeInferenceProcessor.process(request)
+        }
+
+        async fn process_batch(&self, requests: Vec<InferenceRequest>) -> Vec<InferenceResponse> {
+            self.batch_sizes.lock().unwrap().push(requests.len());
+            requests
+                .into_iter()
+                .map(|request| FakeInferenceProcessor.process(request))
+                .collect()
+        }
+    }
+
+    fn request(id: usize) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: None,
+            id: id.to_string(),
+            parameters: None,
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    fn policy(max_queue_delay: Duration, preferred_batch_sizes: Vec<usize>) -> BatchPolicy {
+        BatchPolicy {
+            max_batch_size: 4,
+            max_queue_delay,
+            preferred_batch_sizes,
+            instances: 1,
+        }
+    }
+
+    /// Submits `count` requests at once and returns the batch sizes the runtime saw.
+    async fn submit_all(policy: BatchPolicy, count: usize) -> Vec<usize> {
+        let batcher = Arc::new(DynamicBatcher::new(IdScheme::default().provider()));
+        let runtime = Arc::new(RecordingRuntime::default());
+
+        let submissions: Vec<_> = (0..count)
+            .map(|id| {
+                let batcher = batcher.clone();
+                let runtime: Arc<dyn InferenceRuntime> = runtime.clone();
+                let policy = policy.clone();
+                tokio::spawn(async move {
+                    let version_id = ModelVersionId::new("m", "1");
+                    batcher
+                        .submit(&version_id, runtime, &policy, request(id))
+                        .await
+                })
+            })
+            .collect();
+        for submission in submissions {
+            assert!(submission.await.unwrap().is_ok());
+        }
+
+        runtime.batch_sizes.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_cut_at_max_batch_size() {
+        let sizes = submit_all(policy(Duration::from_millis(50), Vec::new()), 5).await;
+        assert_eq!(sizes, vec![4, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_size_is_dispatched_before_the_delay() {
+        let sizes = tokio::time::timeout(
+            Duration::from_secs(5),
+            submit_all(policy(Duration::from_secs(30), vec![2]), 2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sizes, vec![2]);
+    }
+}
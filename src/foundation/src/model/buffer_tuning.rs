@@ -0,0 +1,280 @@
+/* Adaptive sizing of the per-model request buffers.
+
+Each model buffer tracks an exponentially weighted moving average of the time
+between request arrivals and of the time it takes to serve a request. By
+Little's law the expected number of queued requests is the arrival rate times
+the service time; the buffer capacity is that product times `headroom`,
+clamped to the configured bounds. Capacity only changes once the
+recommendation leaves a band around the current value, so noise in the
+observations does not resize the buffer on every request.
+
+The storage is any `RequestBuffer`: a plain circular buffer, or the
+per-priority queues of `PriorityBuffer`.
+*/
+
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use crate::model::circular_buffer::{CircularBuffer, OverflowPolicy};
+use crate::model::priority::DEFAULT_MAX_WAIT;
+
+/// Relative change of the recommended capacity required before a buffer is resized.
+const RESIZE_TOLERANCE: f64 = 0.25;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferSizing {
+    /// Capacity of a new buffer, before anything has been observed.
+    pub initial_capacity: usize,
+    pub min_capacity: usize,
+    pub max_capacity: usize,
+    /// Multiple of the expected queue length to keep room for bursts.
+    pub headroom: f64,
+    /// Weight (0.0 - 1.0) of the newest observation in the moving averages.
+    pub smoothing: f64,
+    /// Wait after which a queued request is dispatched ahead of higher priority ones.
+    pub starvation_limit: Duration,
+}
+
+impl Default for BufferSizing {
+    fn default() -> Self {
+        Self {
+            initial_capacity: 32,
+            min_capacity: 8,
+            max_capacity: 4096,
+            headroom: 2.0,
+            smoothing: 0.2,
+            starvation_limit: DEFAULT_MAX_WAIT,
+        }
+    }
+}
+
+impl BufferSizing {
+    /// Default bounds around the given initial capacity.
+    pub fn with_initial_capacity(initial_capacity: usize) -> Self {
+        let sizing = Self::default();
+        Self {
+            initial_capacity,
+            min_capacity: sizing.min_capacity.min(initial_capacity).max(1),
+            max_capacity: sizing.max_capacity.max(initial_capacity),
+            ..sizing
+        }
+    }
+
+    fn clamp(&self, capacity: usize) -> usize {
+        let min = self.min_capacity.max(1);
+        capacity.clamp(min, self.max_capacity.max(min))
+    }
+}
+
+/// Capacity and observations of a model buffer, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BufferStats {
+    pub model: String,
+    pub capacity: usize,
+    pub queued: usize,
+    /// Smoothed requests per second, once two requests have arrived.
+    pub arrival_rate: Option<f64>,
+    /// Smoothed service time in milliseconds, once a request has been served.
+    pub service_time_ms: Option<f64>,
+}
+
+/// Bounded request storage that an `AdaptiveBuffer` resizes.
+pub trait RequestBuffer<T> {
+    fn with_capacity(capacity: usize) -> Self;
+    /// Stores `item`, returning the request evicted to make room for it. When the buffer
+    /// is full and `overflow` keeps what it holds, `item` is handed back instead.
+    fn push(&mut self, item: T, overflow: OverflowPolicy) -> Result<Option<T>, T>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn capacity(&self) -> usize;
+    fn resize(&mut self, capacity: usize);
+}
+
+impl<T> RequestBuffer<T> for CircularBuffer<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self::new(capacity)
+    }
+
+    fn push(&mut self, item: T, overflow: OverflowPolicy) -> Result<Option<T>, T> {
+        self.push_with(item, overflow)
+    }
+
+    fn len(&self) -> usize {
+        CircularBuffer::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        CircularBuffer::capacity(self)
+    }
+
+    fn resize(&mut self, capacity: usize) {
+        CircularBuffer::resize(self, capacity);
+    }
+}
+
+/// Request buffer resizing itself from observed load.
+#[derive(Debug)]
+pub struct AdaptiveBuffer<T, B = CircularBuffer<T>> {
+    buffer: B,
+    last_arrival: Option<Instant>,
+    /// Smoothed seconds between arrivals.
+    interarrival: Option<f64>,
+    /// Smoothed seconds per served request.
+    service_time: Option<f64>,
+    items: PhantomData<T>,
+}
+
+impl<T> AdaptiveBuffer<T> {
+    /// Takes every buffered request, oldest first.
+    pub fn drain(&mut self) -> Vec<T> {
+        self.buffer.drain()
+    }
+}
+
+impl<T, B: RequestBuffer<T>> AdaptiveBuffer<T, B> {
+    pub fn new(sizing: &BufferSizing) -> Self {
+        Self {
+            buffer: B::with_capacity(sizing.clamp(sizing.initial_capacity)),
+            last_arrival: None,
+            interarrival: None,
+            service_time: None,
+            items: PhantomData,
+        }
+    }
+
+    /// Stores a request arriving at `now` and retunes the capacity. Returns what
+    /// `RequestBuffer::push` does.
+    pub fn push(
+        &mut self,
+        item: T,
+        now: Instant,
+        sizing: &BufferSizing,
+        overflow: OverflowPolicy,
+    ) -> Result<Option<T>, T> {
+        if let Some(last) = self.last_arrival {
+            let interval = now.saturating_duration_since(last).as_secs_f64();
+            self.interarrival = Some(smooth(self.interarrival, interval, sizing.smoothing));
+        }
+        self.last_arrival = Some(now);
+        self.retune(sizing);
+        self.buffer.push(item, overflow)
+    }
+
+    pub fn record_service_time(&mut self, service_time: Duration, sizing: &BufferSizing) {
+        self.service_time = Some(smooth(
+            self.service_time,
+            service_time.as_secs_f64(),
+            sizing.smoothing,
+        ));
+        self.retune(sizing);
+    }
+
+    /// Capacity suggested by the observations so far, if there are enough of them.
+    pub fn recommended_capacity(&self, sizing: &BufferSizing) -> Option<usize> {
+        let rate = self.arrival_rate()?;
+        let service_time = self.service_time?;
+        let capacity = (rate * service_time * sizing.headroom).ceil();
+        Some(sizing.clamp(capacity.min(usize::MAX as f64) as usize))
+    }
+
+    pub fn arrival_rate(&self) -> Option<f64> {
+        // Requests arriving within the same instant are treated as one burst.
+        self.interarrival
+            .map(|interval| 1.0 / interval.max(f64::EPSILON))
+    }
+
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+
+    /// The storage, for taking requests out; pushes go through `push` to be observed.
+    pub fn buffer_mut(&mut self) -> &mut B {
+        &mut self.buffer
+    }
+
+    pub fn stats(&self, model: &str) -> BufferStats {
+        BufferStats {
+            model: model.to_string(),
+            capacity: self.buffer.capacity(),
+            queued: self.buffer.len(),
+            arrival_rate: self.arrival_rate(),
+            service_time_ms: self.service_time.map(|secs| secs * 1000.0),
+        }
+    }
+
+    fn retune(&mut self, sizing: &BufferSizing) {
+        let Some(recommended) = self.recommended_capacity(sizing) else {
+            return;
+        };
+        let current = self.buffer.capacity() as f64;
+        let change = (recommended as f64 - current).abs() / current.max(1.0);
+        if change > RESIZE_TOLERANCE {
+            self.buffer.resize(recommended);
+        }
+    }
+}
+
+fn smooth(average: Option<f64>, sample: f64, weight: f64) -> f64 {
+    match average {
+        Some(average) => average + weight * (sample - average),
+        None => sample,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizing() -> BufferSizing {
+        BufferSizing {
+            initial_capacity: 16,
+            min_capacity: 4,
+            max_capacity: 64,
+            headroom: 2.0,
+            smoothing: 1.0,
+            ..BufferSizing::default()
+        }
+    }
+
+    #[test]
+    fn test_capacity_follows_load_within_bounds() {
+        let sizing = sizing();
+        let mut buffer: AdaptiveBuffer<u8> = AdaptiveBuffer::new(&sizing);
+        let start = Instant::now();
+        assert_eq!(buffer.buffer().capacity(), 16);
+
+        // 100 req/s at 100ms each: 10 in flight, 20 with headroom.
+        let overflow = OverflowPolicy::default();
+        assert_eq!(buffer.push(0, start, &sizing, overflow), Ok(None));
+        assert_eq!(
+            buffer.push(1, start + Duration::from_millis(10), &sizing, overflow),
+            Ok(None)
+        );
+        assert_eq!(buffer.buffer().capacity(), 16);
+        buffer.record_service_time(Duration::from_millis(100), &sizing);
+        assert_eq!(buffer.recommended_capacity(&sizing), Some(20));
+        // Within the resize tolerance of 16: unchanged.
+        assert_eq!(buffer.buffer().capacity(), 16);
+
+        buffer.record_service_time(Duration::from_secs(1), &sizing);
+        assert_eq!(buffer.buffer().capacity(), 64);
+        assert_eq!(buffer.buffer().len(), 2);
+
+        buffer.record_service_time(Duration::from_millis(1), &sizing);
+        assert_eq!(buffer.buffer().capacity(), 4);
+
+        let stats = buffer.stats("m");
+        assert_eq!(stats.capacity, 4);
+        assert!((stats.service_time_ms.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bounds_include_initial_capacity() {
+        let sizing = BufferSizing::with_initial_capacity(2);
+        assert_eq!(sizing.min_capacity, 2);
+        assert_eq!(AdaptiveBuffer::<u8>::new(&sizing).buffer().capacity(), 2);
+    }
+}
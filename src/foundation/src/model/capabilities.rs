@@ -0,0 +1,184 @@
+/* Capabilities of models, negotiated with requests.
+
+A model config may declare what the model supports:
+
+```yaml
+capabilities:
+  streaming: true        # answers streamed requests
+  logprobs: false        # returns token log probabilities
+  tools: false           # calls tools
+  modalities: [text]     # input modalities
+```
+
+Requests list the capabilities they rely on in their `capabilities` field, the
+same in the REST body and the gRPC `ModelInferRequest`. Parameters imply
+capabilities too: `logprobs` and `top_logprobs` ask for log probabilities,
+`tools` and `tool_choice` for tool calls, and the streamed REST endpoint for
+streaming. A request asking for a capability its model does not declare is
+refused with a `capability_not_supported` error naming the unsupported ones,
+rather than run with the fields ignored.
+
+Models without a `capabilities` section are not checked, nor are the
+modalities of models that declare none.
+*/
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::api::inference::InferParameter;
+
+pub const CAPABILITY_NOT_SUPPORTED: &str = "capability_not_supported";
+
+/// Parameters asking for log probabilities.
+const LOGPROBS_PARAMETERS: &[&str] = &["logprobs", "top_logprobs"];
+/// Parameters asking for tool calls.
+const TOOLS_PARAMETERS: &[&str] = &["tools", "tool_choice"];
+
+/// Capabilities a model supports, or a request relies on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Capabilities {
+    #[serde(default)]
+    pub streaming: bool,
+    #[serde(default)]
+    pub logprobs: bool,
+    #[serde(default)]
+    pub tools: bool,
+    #[serde(default)]
+    pub modalities: Vec<String>,
+}
+
+/// Whether a parameter asks for what it names: any value but `false`, zero or null.
+fn asks_for(parameter: &InferParameter) -> bool {
+    match parameter {
+        InferParameter::Bool(value) => *value,
+        InferParameter::Int64(value) => *value != 0,
+        InferParameter::Double(value) => *value != 0.0,
+        InferParameter::String(value) => !value.is_empty() && value != "none",
+    }
+}
+
+impl Capabilities {
+    /// `self` with the capabilities implied by the `parameters` of a request.
+    pub fn with_parameters(mut self, parameters: Option<&HashMap<String, InferParameter>>) -> Self {
+        let implied = |names: &[&str]| {
+            parameters.is_some_and(|parameters| {
+                names
+                    .iter()
+                    .any(|name| parameters.get(*name).is_some_and(asks_for))
+            })
+        };
+        self.logprobs |= implied(LOGPROBS_PARAMETERS);
+        self.tools |= implied(TOOLS_PARAMETERS);
+        self
+    }
+
+    /// Checks the capabilities `requested` by a request to `model` against those of the model.
+    pub fn check(&self, model: &str, requested: &Capabilities) -> Result<(), CapabilityRefusal> {
+        let mut unsupported = Vec::new();
+        for (name, asked, supported) in [
+            ("streaming", requested.streaming, self.streaming),
+            ("logprobs", requested.logprobs, self.logprobs),
+            ("tools", requested.tools, self.tools),
+        ] {
+            if asked && !supported {
+                unsupported.push(name.to_string());
+            }
+        }
+        if !self.modalities.is_empty() {
+            unsupported.extend(
+                requested
+                    .modalities
+                    .iter()
+                    .filter(|modality| !self.modalities.contains(modality))
+                    .map(|modality| format!("modalities.{}", modality)),
+            );
+        }
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(CapabilityRefusal {
+                model: model.to_string(),
+                unsupported,
+            })
+        }
+    }
+}
+
+/// A request relying on capabilities its model does not support.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityRefusal {
+    pub model: String,
+    /// The unsupported capabilities, e.g. `logprobs` or `modalities.image`.
+    pub unsupported: Vec<String>,
+}
+
+impl CapabilityRefusal {
+    pub fn code(&self) -> &'static str {
+        CAPABILITY_NOT_SUPPORTED
+    }
+}
+
+impl fmt::Display for CapabilityRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Model '{}' does not support {}",
+            self.model,
+            self.unsupported.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_refused_capabilities_their_model_lacks() {
+        let model = Capabilities {
+            streaming: true,
+            modalities: vec!["text".to_string()],
+            ..Capabilities::default()
+        };
+        let parameters = HashMap::from([
+            ("logprobs".to_string(), InferParameter::Bool(true)),
+            ("tools".to_string(), InferParameter::String(String::new())),
+        ]);
+        let requested = Capabilities {
+            streaming: true,
+            modalities: vec!["text".to_string(), "image".to_string()],
+            ..Capabilities::default()
+        }
+        .with_parameters(Some(&parameters));
+        assert!(requested.logprobs);
+        assert!(!requested.tools);
+
+        let refusal = model.check("llm", &requested).unwrap_err();
+        assert_eq!(refusal.unsupported, vec!["logprobs", "modalities.image"]);
+        assert_eq!(refusal.code(), "capability_not_supported");
+        assert_eq!(
+            refusal.to_string(),
+            "Model 'llm' does not support logprobs, modalities.image"
+        );
+
+        assert!(
+            model
+                .check("llm", &Capabilities::default().with_parameters(None))
+                .is_ok()
+        );
+        // Models declaring no modalities accept any.
+        assert!(
+            Capabilities::default()
+                .check(
+                    "any",
+                    &Capabilities {
+                        modalities: vec!["audio".to_string()],
+                        ..Capabilities::default()
+                    }
+                )
+                .is_ok()
+        );
+    }
+}
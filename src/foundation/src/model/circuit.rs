@@ -0,0 +1,437 @@
+/* Circuit breakers of failing models.
+
+With `--circuit-error-rate`, the outcome of every request a model's runtime
+runs is counted over a sliding `window`: runtime errors, panics and deadlines
+expiring while running are failures. Once the model has run at least
+`min_requests` requests in the window and `open_at` of them or more failed, its
+circuit opens:
+
+- the model is reported not ready;
+- its requests fail fast as unavailable, with the reason `circuit_open` and
+  the delay until the next probe, instead of waiting on a slow failing backend;
+- every `probe_interval`, a single request is let through as a probe. Its
+  success closes the circuit with a fresh window; its failure keeps the circuit
+  open for another interval. Only the outcome of that request counts: requests
+  admitted before the circuit opened, or probes since replaced, may finish
+  while it runs and are ignored.
+
+Openings and closings are published on the event bus. Requests already
+buffered or running when a circuit opens still run.
+*/
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::model_discovery_service::ModelId;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitPolicy {
+    /// Span of the recent requests the error rate is computed over.
+    pub window: Duration,
+    /// Requests in the window before the error rate is acted upon.
+    pub min_requests: u64,
+    /// Error rate (0.0 - 1.0) at which the circuit opens.
+    pub open_at: f64,
+    /// Delay between two probes of an open circuit.
+    pub probe_interval: Duration,
+}
+
+impl Default for CircuitPolicy {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            min_requests: 10,
+            open_at: 0.5,
+            probe_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    /// A probe of an open circuit is running.
+    HalfOpen,
+}
+
+/// A request refused because the circuit of its model is open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitOpen {
+    pub model: String,
+    /// Time until the next probe.
+    pub retry_after: Duration,
+}
+
+impl CircuitOpen {
+    pub fn code(&self) -> &'static str {
+        "circuit_open"
+    }
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The circuit of model '{}' is open after repeated runtime failures, retry in {} s",
+            self.model,
+            self.retry_after.as_secs_f64().ceil()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+/// Admission of a request as the probe of an open circuit, to be handed back with its
+/// outcome to `CircuitBreakers::record`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitProbe(u64);
+
+/// The circuit of a model, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CircuitStats {
+    pub model: String,
+    pub state: CircuitState,
+    /// Requests run in the window.
+    pub requests: u64,
+    /// Failures in the window.
+    pub errors: u64,
+    pub error_rate: f64,
+}
+
+/// A circuit opening or closing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitTransition {
+    pub model: String,
+    pub to: CircuitState,
+    pub requests: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    /// Requests and failures per second of the window, oldest first.
+    buckets: VecDeque<(u64, u64, u64)>,
+    state: CircuitState,
+    /// When the next probe of an open circuit may run, or when a running probe is
+    /// considered lost and replaced.
+    next_probe: Option<Instant>,
+    /// The probe running while half open.
+    probe: Option<CircuitProbe>,
+}
+
+impl Circuit {
+    fn totals(&self) -> (u64, u64) {
+        self.buckets
+            .iter()
+            .fold((0, 0), |(requests, errors), (_, r, e)| {
+                (requests + r, errors + e)
+            })
+    }
+}
+
+fn error_rate(requests: u64, errors: u64) -> f64 {
+    if requests == 0 {
+        0.0
+    } else {
+        errors as f64 / requests as f64
+    }
+}
+
+/// The circuits of every model, none without a policy.
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    policy: Option<CircuitPolicy>,
+    circuits: DashMap<ModelId, Circuit>,
+    /// Origin of the per-second buckets.
+    epoch: Instant,
+    /// Last probe admitted, of any circuit.
+    probes: AtomicU64,
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl CircuitBreakers {
+    pub fn new(policy: Option<CircuitPolicy>) -> Self {
+        Self {
+            policy,
+            circuits: DashMap::new(),
+            epoch: Instant::now(),
+            probes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> Option<&CircuitPolicy> {
+        self.policy.as_ref()
+    }
+
+    /// Whether a request of `model_id` may be run now, and the probe it is if so.
+    pub fn admit(&self, model_id: &ModelId) -> Result<Option<CircuitProbe>, CircuitOpen> {
+        self.admit_at(model_id, Instant::now())
+    }
+
+    /// Admits requests of closed circuits, and one probe per interval of open ones.
+    pub fn admit_at(
+        &self,
+        model_id: &ModelId,
+        now: Instant,
+    ) -> Result<Option<CircuitProbe>, CircuitOpen> {
+        let Some(policy) = &self.policy else {
+            return Ok(None);
+        };
+        let Some(mut circuit) = self.circuits.get_mut(model_id) else {
+            return Ok(None);
+        };
+        if circuit.state == CircuitState::Closed {
+            return Ok(None);
+        }
+        match circuit.next_probe {
+            Some(next_probe) if next_probe > now => Err(CircuitOpen {
+                model: model_id.0.clone(),
+                retry_after: next_probe - now,
+            }),
+            _ => {
+                // A probe that never reported, dropped from a full buffer say, is replaced.
+                let probe = CircuitProbe(self.probes.fetch_add(1, Ordering::Relaxed) + 1);
+                circuit.state = CircuitState::HalfOpen;
+                circuit.next_probe = Some(now + policy.probe_interval);
+                circuit.probe = Some(probe);
+                Ok(Some(probe))
+            }
+        }
+    }
+
+    /// Whether the circuit of `model_id` lets requests through.
+    pub fn is_closed(&self, model_id: &ModelId) -> bool {
+        self.circuits
+            .get(model_id)
+            .is_none_or(|circuit| circuit.state == CircuitState::Closed)
+    }
+
+    /// Counts a request of `model_id` that its runtime ran, `failed` if it errored, panicked
+    /// or ran past its deadline, and `probe` if `admit` let it through as one. Returns the
+    /// opening or closing it caused, if any.
+    pub fn record(
+        &self,
+        model_id: &ModelId,
+        probe: Option<CircuitProbe>,
+        failed: bool,
+    ) -> Option<CircuitTransition> {
+        self.record_at(model_id, probe, failed, Instant::now())
+    }
+
+    pub fn record_at(
+        &self,
+        model_id: &ModelId,
+        probe: Option<CircuitProbe>,
+        failed: bool,
+        now: Instant,
+    ) -> Option<CircuitTransition> {
+        let policy = self.policy.as_ref()?;
+        let second = self.second(now);
+        let oldest = second.saturating_sub(policy.window.as_secs().max(1) - 1);
+
+        let mut circuit = self.circuits.entry(model_id.clone()).or_default();
+        match circuit.state {
+            // Requests admitted before the circuit opened do not count, nor do probes
+            // that were replaced.
+            CircuitState::Open => return None,
+            CircuitState::HalfOpen if probe.is_none() || probe != circuit.probe => return None,
+            CircuitState::HalfOpen if failed => {
+                circuit.state = CircuitState::Open;
+                circuit.next_probe = Some(now + policy.probe_interval);
+                circuit.probe = None;
+                return None;
+            }
+            CircuitState::HalfOpen => {
+                let (requests, errors) = circuit.totals();
+                *circuit = Circuit::default();
+                return Some(CircuitTransition {
+                    model: model_id.0.clone(),
+                    to: CircuitState::Closed,
+                    requests,
+                    errors,
+                    error_rate: error_rate(requests, errors),
+                });
+            }
+            CircuitState::Closed => {}
+        }
+        match circuit.buckets.back_mut() {
+            Some((bucket, requests, errors)) if *bucket == second => {
+                *requests += 1;
+                *errors += u64::from(failed);
+            }
+            _ => circuit.buckets.push_back((second, 1, u64::from(failed))),
+        }
+        while circuit
+            .buckets
+            .front()
+            .is_some_and(|(bucket, _, _)| *bucket < oldest)
+        {
+            circuit.buckets.pop_front();
+        }
+
+        let (requests, errors) = circuit.totals();
+        let rate = error_rate(requests, errors);
+        if requests < policy.min_requests || rate < policy.open_at {
+            return None;
+        }
+        circuit.state = CircuitState::Open;
+        circuit.next_probe = Some(now + policy.probe_interval);
+        Some(CircuitTransition {
+            model: model_id.0.clone(),
+            to: CircuitState::Open,
+            requests,
+            errors,
+            error_rate: rate,
+        })
+    }
+
+    /// Forgets the circuit of a model that is no longer served.
+    pub fn remove(&self, model_id: &ModelId) {
+        self.circuits.remove(model_id);
+    }
+
+    /// The circuit of every model that ran requests, sorted by model.
+    pub fn stats(&self) -> Vec<CircuitStats> {
+        let mut stats: Vec<CircuitStats> = self
+            .circuits
+            .iter()
+            .map(|entry| {
+                let (requests, errors) = entry.totals();
+                CircuitStats {
+                    model: entry.key().0.clone(),
+                    state: entry.state,
+                    requests,
+                    errors,
+                    error_rate: error_rate(requests, errors),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.model.cmp(&b.model));
+        stats
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breakers() -> CircuitBreakers {
+        CircuitBreakers::new(Some(CircuitPolicy {
+            window: Duration::from_secs(10),
+            min_requests: 4,
+            open_at: 0.5,
+            probe_interval: Duration::from_secs(5),
+        }))
+    }
+
+    #[test]
+    fn test_circuits_open_at_the_error_rate_and_close_after_a_successful_probe() {
+        let breakers = breakers();
+        let model = ModelId::from_string("m".to_string());
+        let start = Instant::now();
+
+        assert!(breakers.record_at(&model, None, true, start).is_none());
+        assert!(breakers.record_at(&model, None, false, start).is_none());
+        assert!(breakers.record_at(&model, None, true, start).is_none());
+        let opened = breakers.record_at(&model, None, false, start).unwrap();
+        assert_eq!(
+            (opened.to, opened.requests, opened.errors),
+            (CircuitState::Open, 4, 2)
+        );
+        assert!(!breakers.is_closed(&model));
+
+        let refused = breakers
+            .admit_at(&model, start + Duration::from_secs(2))
+            .unwrap_err();
+        assert_eq!(refused.retry_after, Duration::from_secs(3));
+        assert_eq!(refused.code(), "circuit_open");
+
+        // One probe per interval; its failure keeps the circuit open.
+        let at = start + Duration::from_secs(5);
+        let probe = breakers.admit_at(&model, at).unwrap();
+        assert!(probe.is_some());
+        assert!(breakers.admit_at(&model, at).is_err());
+        assert!(breakers.record_at(&model, probe, true, at).is_none());
+        assert!(breakers.admit_at(&model, at).is_err());
+
+        let at = at + Duration::from_secs(5);
+        let probe = breakers.admit_at(&model, at).unwrap();
+        let closed = breakers.record_at(&model, probe, false, at).unwrap();
+        assert_eq!(closed.to, CircuitState::Closed);
+        assert!(breakers.is_closed(&model));
+        assert_eq!(breakers.admit_at(&model, at), Ok(None));
+        assert_eq!(breakers.stats()[0].requests, 0);
+    }
+
+    #[test]
+    fn test_only_the_running_probe_closes_a_circuit() {
+        let breakers = breakers();
+        let model = ModelId::from_string("m".to_string());
+        let start = Instant::now();
+        for _ in 0..4 {
+            breakers.record_at(&model, None, true, start);
+        }
+        assert!(!breakers.is_closed(&model));
+
+        // A request admitted before the circuit opened succeeds while the probe runs.
+        let at = start + Duration::from_secs(5);
+        let lost = breakers.admit_at(&model, at).unwrap();
+        assert!(breakers.record_at(&model, None, false, at).is_none());
+        assert!(!breakers.is_closed(&model));
+
+        // Nor does a probe that was replaced, once lost, count.
+        let at = at + Duration::from_secs(5);
+        let probe = breakers.admit_at(&model, at).unwrap();
+        assert_ne!(probe, lost);
+        assert!(breakers.record_at(&model, lost, false, at).is_none());
+        assert!(breakers.record_at(&model, None, true, at).is_none());
+        assert!(!breakers.is_closed(&model));
+
+        let closed = breakers.record_at(&model, probe, false, at).unwrap();
+        assert_eq!(closed.to, CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_failures_outside_the_window_do_not_count() {
+        let breakers = breakers();
+        let model = ModelId::from_string("m".to_string());
+        let start = Instant::now();
+        for _ in 0..3 {
+            breakers.record_at(&model, None, true, start);
+        }
+        let later = start + Duration::from_secs(20);
+        for _ in 0..3 {
+            assert!(breakers.record_at(&model, None, false, later).is_none());
+        }
+        assert!(breakers.record_at(&model, None, true, later).is_none());
+        assert!(breakers.is_closed(&model));
+    }
+
+    #[test]
+    fn test_circuits_stay_closed_without_a_policy() {
+        let breakers = CircuitBreakers::default();
+        let model = ModelId::from_string("m".to_string());
+        for _ in 0..100 {
+            assert!(breakers.record(&model, None, true).is_none());
+        }
+        assert!(breakers.admit(&model).is_ok());
+        assert!(breakers.stats().is_empty());
+    }
+}
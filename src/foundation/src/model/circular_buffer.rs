@@ -6,13 +6,44 @@ When capacity is full, new items overwrite the oldest ones in a circular way.
 
 Key details:
 - `push` inserts a new element, overwriting the oldest when full.
+- `push_with` inserts a new element, handling a full buffer as the given
+  `OverflowPolicy` says.
 - `items` returns a slice of the current buffer contents in their stored order.
 - `capacity` returns available capacity
 - `len` returns current length
 - `is_empty` checks if buffer is empty
 - `is_full` checks if buffer is full
+- `resize` changes the capacity, keeping the most recent elements
+- `drain` removes every element, oldest first
 */
 
+use serde::{Deserialize, Serialize};
+
+/// What happens to a new element when the buffer is full.
+///
+/// `DropNewest` and `Reject` both turn the new element away; they differ in what its
+/// sender is told, as does `BlockWithTimeout`, which lets the sender wait for room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Overwrite the oldest element.
+    #[default]
+    DropOldest,
+    /// Silently discard the new element.
+    DropNewest,
+    /// Turn the new element away with an error.
+    Reject,
+    /// Wait up to `timeout_ms` for room, then turn the new element away with an error.
+    BlockWithTimeout { timeout_ms: u64 },
+}
+
+impl OverflowPolicy {
+    /// Whether a full buffer makes room by evicting what it holds.
+    pub fn evicts(&self) -> bool {
+        matches!(self, Self::DropOldest)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CircularBuffer<T> {
     buffer: Vec<T>,
@@ -38,6 +69,21 @@ impl<T> CircularBuffer<T> {
         self.index = (self.index + 1) % self.capacity;
     }
 
+    /// Inserts `item`, returning the element it overwrote. When the buffer is full and
+    /// `overflow` does not evict, `item` is handed back instead.
+    pub fn push_with(&mut self, item: T, overflow: OverflowPolicy) -> Result<Option<T>, T> {
+        if self.buffer.len() < self.capacity {
+            self.push(item);
+            return Ok(None);
+        }
+        if !overflow.evicts() || self.capacity == 0 {
+            return Err(item);
+        }
+        let evicted = std::mem::replace(&mut self.buffer[self.index], item);
+        self.index = (self.index + 1) % self.capacity;
+        Ok(Some(evicted))
+    }
+
     pub fn items(&self) -> &[T] {
         &self.buffer
     }
@@ -57,6 +103,36 @@ impl<T> CircularBuffer<T> {
     pub fn is_full(&self) -> bool {
         self.buffer.len() == self.capacity
     }
+
+    /// Removes and returns every element, oldest first.
+    pub fn drain(&mut self) -> Vec<T> {
+        if self.is_full() {
+            self.buffer.rotate_left(self.index);
+        }
+        self.index = 0;
+        std::mem::take(&mut self.buffer)
+    }
+
+    /// Changes the capacity; when shrinking, the oldest elements are dropped.
+    pub fn resize(&mut self, capacity: usize) {
+        if capacity == self.capacity {
+            return;
+        }
+        // Restore insertion order: once full, the oldest element sits at `index`.
+        if self.is_full() {
+            self.buffer.rotate_left(self.index);
+        }
+        if self.buffer.len() > capacity {
+            self.buffer.drain(..self.buffer.len() - capacity);
+        }
+        self.buffer.shrink_to(capacity);
+        self.capacity = capacity;
+        self.index = if capacity == 0 {
+            0
+        } else {
+            self.buffer.len() % capacity
+        };
+    }
 }
 
 #[cfg(test)]
@@ -131,4 +207,45 @@ mod tests {
         buf.push(6);
         assert_eq!(buf.items(), &[6]); // only the last survives
     }
+
+    #[test]
+    fn test_push_with_overflow_policy() {
+        let mut buf = CircularBuffer::new(2);
+        assert_eq!(buf.push_with(1, OverflowPolicy::Reject), Ok(None));
+        assert_eq!(buf.push_with(2, OverflowPolicy::Reject), Ok(None));
+        assert_eq!(buf.push_with(3, OverflowPolicy::Reject), Err(3));
+        assert_eq!(buf.push_with(3, OverflowPolicy::DropNewest), Err(3));
+        assert_eq!(buf.push_with(3, OverflowPolicy::DropOldest), Ok(Some(1)));
+        assert_eq!(buf.items(), &[3, 2]);
+    }
+
+    #[test]
+    fn test_resize_keeps_most_recent_items() {
+        let mut buf = CircularBuffer::new(3);
+        for i in 1..=4 {
+            buf.push(i);
+        }
+        buf.resize(2);
+        assert_eq!(buf.items(), &[3, 4]);
+        buf.push(5); // overwrites 3
+        assert_eq!(buf.items(), &[5, 4]);
+
+        buf.resize(4);
+        assert_eq!(buf.items(), &[4, 5]);
+        buf.push(6);
+        assert_eq!(buf.items(), &[4, 5, 6]);
+        assert_eq!(buf.capacity(), 4);
+    }
+
+    #[test]
+    fn test_drain_returns_oldest_first() {
+        let mut buf = CircularBuffer::new(3);
+        for i in 1..=4 {
+            buf.push(i);
+        }
+        assert_eq!(buf.drain(), vec![2, 3, 4]);
+        assert!(buf.is_empty());
+        buf.push(5);
+        assert_eq!(buf.items(), &[5]);
+    }
 }
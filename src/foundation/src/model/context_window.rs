@@ -0,0 +1,364 @@
+/* Context window and generation length limits of language models.
+
+A model config may declare the context window of the model and the largest
+generation it allows:
+
+```yaml
+context_window:
+  context_length: 4096   # prompt and generated tokens together
+  max_tokens: 1024       # largest `max_tokens` a request may ask for
+  tokenizer: words       # bytes, or the tokenizer.json of the model
+```
+
+Requests are checked before they are queued, so a prompt that does not fit is
+refused with an OpenAI style `context_length_exceeded` error instead of the
+backend failing on it. The prompt is the text of the `prompt` and `messages`
+parameters; `max_tokens` is the number of tokens the request asks the model to
+generate, none when absent.
+
+Prompts are counted with a tokenizer chosen per model. `words` counts runs of
+letters and digits and every other non-space character, a lower bound of the
+tokens of the usual subword vocabularies; `bytes` counts UTF-8 bytes, an upper
+bound of byte-level vocabularies. Any other value is the path of a Hugging
+Face `tokenizer.json`, relative to the model directory, which counts the tokens
+the model actually sees. It is loaded with the config; a file that cannot be
+read fails the model load.
+*/
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::api::inference::InferParameter;
+use crate::overload::MAX_TOKENS_PARAMETER;
+
+/// Parameters holding the prompt of a request.
+pub const PROMPT_PARAMETERS: &[&str] = &["prompt", "messages"];
+
+/// Counts the tokens of a prompt.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum Tokenizer {
+    #[default]
+    Words,
+    Bytes,
+    /// The vocabulary of the model, from its `tokenizer.json`.
+    Vocabulary(Vocabulary),
+}
+
+/// A Hugging Face tokenizer, loaded from its file by `Tokenizer::load`.
+#[derive(Clone)]
+pub struct Vocabulary {
+    pub file: PathBuf,
+    tokenizer: Option<Arc<tokenizers::Tokenizer>>,
+}
+
+impl fmt::Debug for Vocabulary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vocabulary")
+            .field("file", &self.file)
+            .field("loaded", &self.tokenizer.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for Vocabulary {
+    fn eq(&self, other: &Self) -> bool {
+        self.file == other.file
+    }
+}
+
+impl From<String> for Tokenizer {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "words" => Self::Words,
+            "bytes" => Self::Bytes,
+            _ => Self::Vocabulary(Vocabulary {
+                file: PathBuf::from(name),
+                tokenizer: None,
+            }),
+        }
+    }
+}
+
+impl From<Tokenizer> for String {
+    fn from(tokenizer: Tokenizer) -> Self {
+        match tokenizer {
+            Tokenizer::Words => "words".to_string(),
+            Tokenizer::Bytes => "bytes".to_string(),
+            Tokenizer::Vocabulary(vocabulary) => vocabulary.file.display().to_string(),
+        }
+    }
+}
+
+impl Tokenizer {
+    /// Tokens of the prompt in `parameters` plus the `max_tokens` they ask for, as charged
+    /// to quotas.
+    /// Loads the vocabulary file, relative to `model_dir`; words and bytes need nothing.
+    pub fn load(&mut self, model_dir: &Path) -> Result<()> {
+        if let Self::Vocabulary(vocabulary) = self {
+            let path = model_dir.join(&vocabulary.file);
+            let tokenizer = tokenizers::Tokenizer::from_file(&path)
+                .map_err(|e| anyhow!("Cannot load tokenizer {}: {}", path.display(), e))?;
+            vocabulary.tokenizer = Some(Arc::new(tokenizer));
+        }
+        Ok(())
+    }
+
+    pub fn request_tokens(&self, parameters: Option<&HashMap<String, InferParameter>>) -> u64 {
+        parameters.map_or(0, |parameters| {
+            self.prompt_tokens(parameters)
+                .saturating_add(max_tokens(parameters))
+        })
+    }
+
+    fn prompt_tokens(&self, parameters: &HashMap<String, InferParameter>) -> u64 {
+        PROMPT_PARAMETERS
+            .iter()
+            .filter_map(|name| match parameters.get(*name) {
+                Some(InferParameter::String(text)) => Some(self.count(text)),
+                _ => None,
+            })
+            .sum()
+    }
+
+    /// Tokens of `text`. A vocabulary that is not loaded counts words.
+    pub fn count(&self, text: &str) -> u64 {
+        match self {
+            Self::Vocabulary(Vocabulary {
+                tokenizer: Some(tokenizer),
+                ..
+            }) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len() as u64,
+                Err(_) => Self::Words.count(text),
+            },
+            Self::Words | Self::Vocabulary(_) => {
+                let mut tokens = 0;
+                let mut in_word = false;
+                for c in text.chars() {
+                    if c.is_alphanumeric() {
+                        if !in_word {
+                            tokens += 1;
+                        }
+                        in_word = true;
+                    } else {
+                        if !c.is_whitespace() {
+                            tokens += 1;
+                        }
+                        in_word = false;
+                    }
+                }
+                tokens
+            }
+            Self::Bytes => text.len() as u64,
+        }
+    }
+}
+
+fn max_tokens(parameters: &HashMap<String, InferParameter>) -> u64 {
+    match parameters.get(MAX_TOKENS_PARAMETER) {
+        Some(InferParameter::Int64(max_tokens)) => (*max_tokens).max(0) as u64,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextWindow {
+    /// Tokens of the prompt and of the generation together.
+    pub context_length: u64,
+    /// Largest generation a request may ask for.
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    #[serde(default)]
+    pub tokenizer: Tokenizer,
+}
+
+/// Why a request does not fit the context window of its model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextRefusal {
+    /// The prompt and the requested generation exceed the context length.
+    ContextLengthExceeded {
+        context_length: u64,
+        prompt_tokens: u64,
+        max_tokens: u64,
+    },
+    /// The request asks for more tokens than the model generates.
+    MaxTokensTooLarge { requested: u64, limit: u64 },
+}
+
+impl ContextRefusal {
+    /// OpenAI error code of the refusal.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ContextLengthExceeded { .. } => "context_length_exceeded",
+            Self::MaxTokensTooLarge { .. } => "invalid_value",
+        }
+    }
+
+    /// Request parameter to change.
+    pub fn param(&self) -> &'static str {
+        match self {
+            Self::ContextLengthExceeded { .. } => "messages",
+            Self::MaxTokensTooLarge { .. } => MAX_TOKENS_PARAMETER,
+        }
+    }
+}
+
+impl fmt::Display for ContextRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ContextLengthExceeded {
+                context_length,
+                prompt_tokens,
+                max_tokens,
+            } => write!(
+                f,
+                "This model's maximum context length is {} tokens. However, you requested {} \
+                 tokens ({} in the messages, {} in the completion). Please reduce the length of \
+                 the messages or completion.",
+                context_length,
+                prompt_tokens + max_tokens,
+                prompt_tokens,
+                max_tokens
+            ),
+            Self::MaxTokensTooLarge { requested, limit } => write!(
+                f,
+                "max_tokens is too large: {}. This model supports at most {} completion tokens, \
+                 whereas you provided {}.",
+                requested, limit, requested
+            ),
+        }
+    }
+}
+
+impl ContextWindow {
+    /// Checks the prompt and `max_tokens` of a request's parameters against the window.
+    pub fn check(
+        &self,
+        parameters: Option<&HashMap<String, InferParameter>>,
+    ) -> Result<(), ContextRefusal> {
+        let Some(parameters) = parameters else {
+            return Ok(());
+        };
+        let max_tokens = max_tokens(parameters);
+        if let Some(limit) = self.max_tokens
+            && max_tokens > limit
+        {
+            return Err(ContextRefusal::MaxTokensTooLarge {
+                requested: max_tokens,
+                limit,
+            });
+        }
+        let prompt_tokens = self.tokenizer.prompt_tokens(parameters);
+        if prompt_tokens.saturating_add(max_tokens) > self.context_length {
+            return Err(ContextRefusal::ContextLengthExceeded {
+                context_length: self.context_length,
+                prompt_tokens,
+                max_tokens,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(prompt: &str, max_tokens: Option<i64>) -> HashMap<String, InferParameter> {
+        let mut parameters = HashMap::from([(
+            "prompt".to_string(),
+            InferParameter::String(prompt.to_string()),
+        )]);
+        if let Some(max_tokens) = max_tokens {
+            parameters.insert("max_tokens".to_string(), InferParameter::Int64(max_tokens));
+        }
+        parameters
+    }
+
+    #[test]
+    fn test_prompts_and_generations_must_fit_the_window() {
+        assert_eq!(Tokenizer::Words.count("Hello, world! It's 2024."), 9);
+        assert_eq!(Tokenizer::Bytes.count("héllo"), 6);
+
+        let window = ContextWindow {
+            context_length: 10,
+            max_tokens: Some(6),
+            tokenizer: Tokenizer::Words,
+        };
+        assert!(window.check(None).is_ok());
+        assert!(
+            window
+                .check(Some(&parameters("one two three", Some(6))))
+                .is_ok()
+        );
+        assert!(
+            window
+                .check(Some(&parameters("one two three four five", None)))
+                .is_ok()
+        );
+
+        let refusal = window
+            .check(Some(&parameters("one two three four five", Some(6))))
+            .unwrap_err();
+        assert_eq!(refusal.code(), "context_length_exceeded");
+        assert!(refusal.to_string().starts_with(
+            "This model's maximum context length is 10 tokens. However, you requested 11 tokens \
+             (5 in the messages, 6 in the completion)."
+        ));
+        let refusal = window.check(Some(&parameters("", Some(7)))).unwrap_err();
+        assert_eq!(
+            refusal,
+            ContextRefusal::MaxTokensTooLarge {
+                requested: 7,
+                limit: 6
+            }
+        );
+        assert_eq!(refusal.param(), "max_tokens");
+    }
+
+    #[test]
+    fn test_vocabularies_count_the_tokens_of_the_model() {
+        let dir = std::env::temp_dir().join(format!("galemind-tokenizer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("tokenizer.json"),
+            r#"{
+                "version": "1.0",
+                "truncation": null,
+                "padding": null,
+                "added_tokens": [],
+                "normalizer": null,
+                "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": null,
+                "decoder": null,
+                "model": {
+                    "type": "WordLevel",
+                    "vocab": {"[UNK]": 0, "hello": 1, "world": 2, "!": 3},
+                    "unk_token": "[UNK]"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let window: ContextWindow =
+            serde_yaml::from_str("{ context_length: 8, tokenizer: tokenizer.json }").unwrap();
+        let mut tokenizer = window.tokenizer;
+        assert_eq!(String::from(tokenizer.clone()), "tokenizer.json");
+        // Not loaded yet: words are counted.
+        assert_eq!(tokenizer.count("hello, world"), 3);
+        tokenizer.load(&dir).unwrap();
+        assert_eq!(tokenizer.count("hello world !"), 3);
+        assert_eq!(tokenizer.count("hello unknown"), 2);
+
+        let mut missing = Tokenizer::from("missing.json".to_string());
+        assert!(missing.load(&dir).is_err());
+        let mut words = Tokenizer::from("words".to_string());
+        assert!(words.load(&dir).is_ok());
+        assert_eq!(words, Tokenizer::Words);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+/* Coalescing of identical requests in flight.
+
+Bursts of duplicate requests to a deterministic model need not each run on
+it. A model configured with
+
+```yaml
+deduplicate: true
+```
+
+runs an incoming request only if no identical request is in flight: one
+already buffered or running. Otherwise the request joins it and is answered
+with its response once it completes. Requests are identical when they have the
+same key as for the response cache (see `model::response_cache`): the same
+version, inputs and parameters.
+
+Joined requests share the outcome of the request they joined, including an
+error, an expired deadline or an eviction from a full buffer, and wait for it
+whatever their own priority. Coalesced requests are counted per model, as
+`galemind_coalesced_requests_total`.
+*/
+
+use dashmap::DashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::oneshot;
+
+use super::model_discovery_service::ModelId;
+use crate::api::inference::InferenceResponse;
+use crate::stats::escape_label;
+
+type Waiters = Vec<oneshot::Sender<InferenceResponse>>;
+
+/// Requests in flight by key, with the channels of the requests waiting for their response.
+#[derive(Default)]
+pub struct InFlightRequests {
+    waiting: Arc<DashMap<(ModelId, String), Waiters>>,
+    coalesced: DashMap<ModelId, AtomicU64>,
+}
+
+impl InFlightRequests {
+    /// Sends the response of the request of `key` in flight on `response_tx` too, and returns
+    /// None. When none is, returns the channel the response of the request must be sent on
+    /// to reach it and the requests joining it. Must be called within a Tokio runtime.
+    pub fn coalesce(
+        &self,
+        model_id: &ModelId,
+        key: String,
+        response_tx: oneshot::Sender<InferenceResponse>,
+    ) -> Option<oneshot::Sender<InferenceResponse>> {
+        let entry = (model_id.clone(), key);
+        match self.waiting.entry(entry.clone()) {
+            dashmap::Entry::Occupied(mut waiters) => {
+                waiters.get_mut().push(response_tx);
+                self.coalesced
+                    .entry(model_id.clone())
+                    .or_default()
+                    .fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            dashmap::Entry::Vacant(waiters) => {
+                waiters.insert(vec![response_tx]);
+                let (leader_tx, leader_rx) = oneshot::channel::<InferenceResponse>();
+                let waiting = self.waiting.clone();
+                tokio::spawn(async move {
+                    let response = leader_rx.await;
+                    let waiters = waiting
+                        .remove(&entry)
+                        .map(|(_, waiters)| waiters)
+                        .unwrap_or_default();
+                    // A request dropped without a response closes the channels of all.
+                    let Ok(response) = response else {
+                        return;
+                    };
+                    for waiter in waiters {
+                        let _ = waiter.send(response.clone());
+                    }
+                });
+                Some(leader_tx)
+            }
+        }
+    }
+
+    /// Requests of `model_id` answered with the response of an identical one.
+    pub fn coalesced(&self, model_id: &ModelId) -> u64 {
+        self.coalesced
+            .get(model_id)
+            .map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    /// Coalesced requests of every model in the Prometheus text exposition format.
+    pub fn render(&self, out: &mut String) {
+        let name = "galemind_coalesced_requests_total";
+        let help = "Requests answered with the response of an identical one in flight.";
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+        let mut models: Vec<ModelId> = self.coalesced.iter().map(|c| c.key().clone()).collect();
+        models.sort();
+        for model_id in models {
+            let _ = writeln!(
+                out,
+                "{}{{model=\"{}\"}} {}",
+                name,
+                escape_label(&model_id.0),
+                self.coalesced(&model_id)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::InferenceError;
+
+    fn error(message: &str) -> InferenceResponse {
+        InferenceResponse::Error(InferenceError {
+            error: message.to_string(),
+        })
+    }
+
+    fn message(response: InferenceResponse) -> String {
+        match response {
+            InferenceResponse::Error(e) => e.error,
+            _ => panic!("unexpected response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_share_one_response() {
+        let in_flight = InFlightRequests::default();
+        let model = ModelId("m".to_string());
+
+        let (first_tx, first_rx) = oneshot::channel();
+        let leader = in_flight
+            .coalesce(&model, "k".to_string(), first_tx)
+            .unwrap();
+        let (second_tx, second_rx) = oneshot::channel();
+        assert!(
+            in_flight
+                .coalesce(&model, "k".to_string(), second_tx)
+                .is_none()
+        );
+        let (other_tx, other_rx) = oneshot::channel();
+        let other = in_flight
+            .coalesce(&model, "other".to_string(), other_tx)
+            .unwrap();
+
+        leader.send(error("shared")).ok().unwrap();
+        assert_eq!(message(first_rx.await.unwrap()), "shared");
+        assert_eq!(message(second_rx.await.unwrap()), "shared");
+        assert_eq!(in_flight.coalesced(&model), 1);
+
+        // Once answered, the key runs again.
+        let (again_tx, _again_rx) = oneshot::channel();
+        assert!(
+            in_flight
+                .coalesce(&model, "k".to_string(), again_tx)
+                .is_some()
+        );
+
+        // Requests dropped without a response close the channels of those joining them.
+        drop(other);
+        assert!(other_rx.await.is_err());
+
+        let mut metrics = String::new();
+        in_flight.render(&mut metrics);
+        assert!(metrics.contains("galemind_coalesced_requests_total{model=\"m\"} 1"));
+    }
+}
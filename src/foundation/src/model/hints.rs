@@ -0,0 +1,318 @@
+/* Per-request execution hints.
+
+Advanced clients can pass advisory request parameters to steer how their
+request runs:
+
+- `no_batching: true` runs the request on its own even when its model batches
+  requests dynamically, e.g. to measure the latency of a single request;
+- `device: "cpu" | "gpu"` asks for a device class. A model version runs on one
+  device, so the hint is honored when the version serving the request runs on
+  that class;
+- `instance: <index>` runs the request on one instance of the version, e.g. to
+  debug an instance that returns wrong results. A request pinned to an
+  instance is not batched.
+
+Which hints are honored is a server policy (`--execution-hints`); by default
+`no_batching` and `device` are, `instance` is not. Hints are never an error:
+one that is not allowed, not valid or that cannot be honored is ignored, and
+the response says so in its `hints_ignored` parameter.
+*/
+
+use anyhow::anyhow;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::api::devices::Device;
+use crate::api::inference::InferParameter;
+
+/// Request parameter asking not to batch the request.
+pub const NO_BATCHING_PARAMETER: &str = "no_batching";
+/// Request parameter asking for a device class, `cpu` or `gpu`.
+pub const DEVICE_PARAMETER: &str = "device";
+/// Request parameter pinning the request to an instance of the model version.
+pub const INSTANCE_PARAMETER: &str = "instance";
+/// Response parameter listing the hints of the request that were ignored, and why.
+pub const HINTS_IGNORED_PARAMETER: &str = "hints_ignored";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hint {
+    NoBatching,
+    Device,
+    Instance,
+}
+
+impl FromStr for Hint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().replace('-', "_").as_str() {
+            NO_BATCHING_PARAMETER => Ok(Hint::NoBatching),
+            DEVICE_PARAMETER => Ok(Hint::Device),
+            INSTANCE_PARAMETER => Ok(Hint::Instance),
+            other => Err(anyhow!(
+                "Unknown execution hint '{}', expected no_batching, device or instance",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hint::NoBatching => f.write_str(NO_BATCHING_PARAMETER),
+            Hint::Device => f.write_str(DEVICE_PARAMETER),
+            Hint::Instance => f.write_str(INSTANCE_PARAMETER),
+        }
+    }
+}
+
+/// Hints the server honors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintPolicy {
+    allowed: BTreeSet<Hint>,
+}
+
+impl Default for HintPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: [Hint::NoBatching, Hint::Device].into(),
+        }
+    }
+}
+
+impl HintPolicy {
+    pub fn allows(&self, hint: Hint) -> bool {
+        self.allowed.contains(&hint)
+    }
+}
+
+impl FromStr for HintPolicy {
+    type Err = anyhow::Error;
+
+    /// A comma-separated list of hints, or `none`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.trim() == "none" {
+            return Ok(Self {
+                allowed: BTreeSet::new(),
+            });
+        }
+        Ok(Self {
+            allowed: s
+                .split(',')
+                .filter(|hint| !hint.trim().is_empty())
+                .map(str::parse)
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    Cpu,
+    Gpu,
+}
+
+impl DeviceClass {
+    pub fn of(device: Device) -> Self {
+        if device.is_gpu() {
+            DeviceClass::Gpu
+        } else {
+            DeviceClass::Cpu
+        }
+    }
+}
+
+impl FromStr for DeviceClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cpu" => Ok(DeviceClass::Cpu),
+            "gpu" | "cuda" => Ok(DeviceClass::Gpu),
+            other => Err(anyhow!("unknown device class '{}'", other)),
+        }
+    }
+}
+
+/// A hint of a request that was not honored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoredHint {
+    pub hint: Hint,
+    pub reason: String,
+}
+
+impl IgnoredHint {
+    fn new(hint: Hint, reason: impl Into<String>) -> Self {
+        Self {
+            hint,
+            reason: reason.into(),
+        }
+    }
+
+    /// Value of the `hints_ignored` response parameter.
+    pub fn describe(ignored: &[IgnoredHint]) -> String {
+        ignored
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl fmt::Display for IgnoredHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.hint, self.reason)
+    }
+}
+
+/// How the model version serving a request runs, as far as hints are concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionTarget {
+    pub batched: bool,
+    pub instances: usize,
+    pub device: Device,
+}
+
+/// Execution of a request once its hints are weighed against the policy and the version
+/// serving it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HintPlan {
+    /// Run the request on its own rather than in a batch.
+    pub unbatched: bool,
+    /// Instance to run the request on.
+    pub instance: Option<usize>,
+    pub ignored: Vec<IgnoredHint>,
+}
+
+impl HintPlan {
+    /// Plans the hints in `parameters` of a request for `target`.
+    pub fn new(
+        parameters: Option<&HashMap<String, InferParameter>>,
+        policy: &HintPolicy,
+        target: ExecutionTarget,
+    ) -> Self {
+        let mut plan = Self::default();
+        let Some(parameters) = parameters else {
+            return plan;
+        };
+        let mut requested = |hint: Hint| {
+            let value = parameters.get(&hint.to_string())?;
+            if policy.allows(hint) {
+                Some(value)
+            } else {
+                plan.ignored
+                    .push(IgnoredHint::new(hint, "not allowed by the server"));
+                None
+            }
+        };
+        let no_batching = requested(Hint::NoBatching);
+        let device = requested(Hint::Device);
+        let instance = requested(Hint::Instance);
+
+        match no_batching {
+            Some(InferParameter::Bool(no_batching)) => plan.unbatched = *no_batching,
+            Some(_) => plan
+                .ignored
+                .push(IgnoredHint::new(Hint::NoBatching, "expected a boolean")),
+            None => {}
+        }
+        match device {
+            Some(InferParameter::String(class)) => match class.parse::<DeviceClass>() {
+                Ok(class) if class == DeviceClass::of(target.device) => {}
+                Ok(_) => plan.ignored.push(IgnoredHint::new(
+                    Hint::Device,
+                    format!("the model runs on {}", target.device),
+                )),
+                Err(e) => plan
+                    .ignored
+                    .push(IgnoredHint::new(Hint::Device, e.to_string())),
+            },
+            Some(_) => plan
+                .ignored
+                .push(IgnoredHint::new(Hint::Device, "expected cpu or gpu")),
+            None => {}
+        }
+        match instance {
+            Some(InferParameter::Int64(index))
+                if usize::try_from(*index).is_ok_and(|index| index < target.instances) =>
+            {
+                plan.instance = Some(*index as usize);
+                plan.unbatched = true;
+            }
+            Some(InferParameter::Int64(_)) => plan.ignored.push(IgnoredHint::new(
+                Hint::Instance,
+                format!("the model has {} instances", target.instances),
+            )),
+            Some(_) => plan.ignored.push(IgnoredHint::new(
+                Hint::Instance,
+                "expected an instance index",
+            )),
+            None => {}
+        }
+        plan.unbatched &= target.batched;
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hints_are_honored_when_allowed_and_possible() {
+        let parameters: HashMap<String, InferParameter> = [
+            (
+                NO_BATCHING_PARAMETER.to_string(),
+                InferParameter::Bool(true),
+            ),
+            (
+                DEVICE_PARAMETER.to_string(),
+                InferParameter::String("gpu".to_string()),
+            ),
+            (INSTANCE_PARAMETER.to_string(), InferParameter::Int64(1)),
+        ]
+        .into();
+        let target = ExecutionTarget {
+            batched: true,
+            instances: 2,
+            device: Device::Cuda(0),
+        };
+
+        let plan = HintPlan::new(Some(&parameters), &HintPolicy::default(), target);
+        assert!(plan.unbatched);
+        assert_eq!(plan.instance, None);
+        assert_eq!(
+            IgnoredHint::describe(&plan.ignored),
+            "instance: not allowed by the server"
+        );
+
+        let policy: HintPolicy = "no-batching, device,instance".parse().unwrap();
+        let plan = HintPlan::new(Some(&parameters), &policy, target);
+        assert_eq!((plan.unbatched, plan.instance), (true, Some(1)));
+        assert!(plan.ignored.is_empty());
+
+        let cpu = ExecutionTarget {
+            batched: false,
+            instances: 1,
+            device: Device::Cpu,
+        };
+        let plan = HintPlan::new(Some(&parameters), &policy, cpu);
+        assert!(!plan.unbatched);
+        assert_eq!(
+            IgnoredHint::describe(&plan.ignored),
+            "device: the model runs on cpu; instance: the model has 1 instances"
+        );
+
+        let none: HintPolicy = "none".parse().unwrap();
+        assert_eq!(
+            HintPlan::new(Some(&parameters), &none, target)
+                .ignored
+                .len(),
+            3
+        );
+        assert_eq!(HintPlan::new(None, &none, target), HintPlan::default());
+        assert!("turbo".parse::<HintPolicy>().is_err());
+    }
+}
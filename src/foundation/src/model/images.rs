@@ -0,0 +1,294 @@
+/* Images sent to vision models.
+
+Images reach models as the bytes of their encoded file. Before a request is
+queued, each image is identified from its first bytes, and refused when it is
+not a PNG, JPEG, GIF or WebP file, or when it exceeds the limits of its model:
+
+```yaml
+images:
+  max_bytes: 10485760   # size of the encoded file, 20 MiB when absent
+  max_width: 2048       # pixels, no limit when absent
+  max_height: 2048
+```
+
+Dimensions are read from the file header, so images are checked without
+decoding them.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const INVALID_IMAGE: &str = "invalid_image";
+pub const IMAGE_TOO_LARGE: &str = "image_too_large";
+
+/// Largest encoded image accepted by models that do not set `max_bytes`, as OpenAI does.
+pub const DEFAULT_MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+fn default_max_bytes() -> u64 {
+    DEFAULT_MAX_IMAGE_BYTES
+}
+
+/// Limits of the images a model accepts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageLimits {
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    #[serde(default)]
+    pub max_height: Option<u32>,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_IMAGE_BYTES,
+            max_width: None,
+            max_height: None,
+        }
+    }
+}
+
+/// Format and dimensions of an image, read from its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub mime_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Why an image is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageRefusal {
+    /// Not a PNG, JPEG, GIF or WebP file, or a truncated one.
+    Unsupported,
+    TooManyBytes {
+        bytes: u64,
+        limit: u64,
+    },
+    TooLarge {
+        info: ImageInfo,
+        limits: ImageLimits,
+    },
+}
+
+impl ImageRefusal {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unsupported => INVALID_IMAGE,
+            Self::TooManyBytes { .. } | Self::TooLarge { .. } => IMAGE_TOO_LARGE,
+        }
+    }
+}
+
+impl fmt::Display for ImageRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "Images must be PNG, JPEG, GIF or WebP files"),
+            Self::TooManyBytes { bytes, limit } => write!(
+                f,
+                "The image has {} bytes, at most {} are accepted",
+                bytes, limit
+            ),
+            Self::TooLarge { info, limits } => {
+                let limit = |limit: Option<u32>| limit.map_or("any".to_string(), |l| l.to_string());
+                write!(
+                    f,
+                    "The image is {}x{} pixels, at most {}x{} are accepted",
+                    info.width,
+                    info.height,
+                    limit(limits.max_width),
+                    limit(limits.max_height)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageRefusal {}
+
+fn u16_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u24_le(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Dimensions of a JPEG, from its first start of frame segment.
+fn jpeg_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        while *bytes.get(at)? != 0xFF {
+            at += 1;
+        }
+        while *bytes.get(at)? == 0xFF {
+            at += 1;
+        }
+        let marker = *bytes.get(at)?;
+        at += 1;
+        // Markers without a segment.
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            continue;
+        }
+        let length = u16_be(bytes, at)? as usize;
+        let frame = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if frame {
+            return Some((u16_be(bytes, at + 5)?, u16_be(bytes, at + 3)?));
+        }
+        at += length;
+    }
+}
+
+/// Dimensions of a WebP, from its lossy, lossless or extended header.
+fn webp_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    match bytes.get(12..16)? {
+        b"VP8 " => Some((u16_le(bytes, 26)? & 0x3FFF, u16_le(bytes, 28)? & 0x3FFF)),
+        b"VP8L" => {
+            let b = bytes.get(21..25)?;
+            let width = 1 + (b[0] as u32 | ((b[1] as u32 & 0x3F) << 8));
+            let height =
+                1 + ((b[1] as u32 >> 6) | ((b[2] as u32) << 2) | ((b[3] as u32 & 0x0F) << 10));
+            Some((width, height))
+        }
+        b"VP8X" => Some((1 + u24_le(bytes, 24)?, 1 + u24_le(bytes, 27)?)),
+        _ => None,
+    }
+}
+
+/// Format and dimensions of an encoded image, None for unsupported or truncated files.
+pub fn probe(bytes: &[u8]) -> Option<ImageInfo> {
+    let (mime_type, (width, height)) = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        ("image/png", (u32_be(bytes, 16)?, u32_be(bytes, 20)?))
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        ("image/jpeg", jpeg_size(bytes)?)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        ("image/gif", (u16_le(bytes, 6)?, u16_le(bytes, 8)?))
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        ("image/webp", webp_size(bytes)?)
+    } else {
+        return None;
+    };
+    Some(ImageInfo {
+        mime_type,
+        width,
+        height,
+    })
+}
+
+impl ImageLimits {
+    /// Identifies an encoded image and checks it against the limits.
+    pub fn check(&self, bytes: &[u8]) -> Result<ImageInfo, ImageRefusal> {
+        if bytes.len() as u64 > self.max_bytes {
+            return Err(ImageRefusal::TooManyBytes {
+                bytes: bytes.len() as u64,
+                limit: self.max_bytes,
+            });
+        }
+        let info = probe(bytes).ok_or(ImageRefusal::Unsupported)?;
+        let over = |size: u32, limit: Option<u32>| limit.is_some_and(|limit| size > limit);
+        if over(info.width, self.max_width) || over(info.height, self.max_height) {
+            return Err(ImageRefusal::TooLarge {
+                info,
+                limits: self.clone(),
+            });
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        bytes.extend(width.to_be_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes.extend([8, 6, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_images_are_identified_from_their_header() {
+        assert_eq!(
+            probe(&png(640, 480)),
+            Some(ImageInfo {
+                mime_type: "image/png",
+                width: 640,
+                height: 480
+            })
+        );
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend([0x20, 0x03, 0x58, 0x02]);
+        assert_eq!(
+            probe(&gif).map(|info| (info.width, info.height)),
+            Some((800, 600))
+        );
+
+        // SOI, an APP0 segment, then a baseline frame of 1024x768.
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x03,
+            0x00, 0x04, 0x00,
+        ];
+        let info = probe(&jpeg).unwrap();
+        assert_eq!(
+            (info.mime_type, info.width, info.height),
+            ("image/jpeg", 1024, 768)
+        );
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X".to_vec();
+        webp.extend([0; 8]);
+        webp.extend([0x3F, 0x01, 0x00, 0xEF, 0x00, 0x00]);
+        assert_eq!(
+            probe(&webp).map(|info| (info.width, info.height)),
+            Some((320, 240))
+        );
+
+        assert_eq!(probe(b"%PDF-1.7"), None);
+        assert_eq!(probe(&png(640, 480)[..20]), None);
+    }
+
+    #[test]
+    fn test_images_are_checked_against_the_limits() {
+        let limits = ImageLimits {
+            max_width: Some(1024),
+            ..ImageLimits::default()
+        };
+        assert!(limits.check(&png(1024, 4000)).is_ok());
+
+        let refusal = limits.check(&png(2048, 10)).unwrap_err();
+        assert_eq!(refusal.code(), "image_too_large");
+        assert_eq!(
+            refusal.to_string(),
+            "The image is 2048x10 pixels, at most 1024xany are accepted"
+        );
+        let refusal = ImageLimits {
+            max_bytes: 16,
+            ..ImageLimits::default()
+        }
+        .check(&png(1, 1))
+        .unwrap_err();
+        assert_eq!(
+            refusal,
+            ImageRefusal::TooManyBytes {
+                bytes: 29,
+                limit: 16
+            }
+        );
+        assert_eq!(
+            limits.check(b"not an image").unwrap_err().code(),
+            "invalid_image"
+        );
+    }
+}
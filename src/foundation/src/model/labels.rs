@@ -0,0 +1,250 @@
+/* Model labels and label selectors.
+
+Models carry arbitrary `key: value` labels, declared under `labels` in their
+`model.yaml` or taken from the tags of a registered MLflow model. Selectors
+pick models by their labels with the Kubernetes syntax: comma-separated
+requirements that must all hold.
+
+- `key=value` (or `key==value`) and `key!=value`
+- `key in (a,b)` and `key notin (a,b)`
+- `key` (the label is set) and `!key` (the label is not set)
+
+As in Kubernetes, `!=` and `notin` also match models without the label.
+
+Inference requests sent with the `x-galemind-model-selector` header are routed
+to one of the models matching the selector instead of the model they name.
+*/
+
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+pub type Labels = BTreeMap<String, String>;
+
+/// Header (or gRPC metadata entry) routing an inference to any model matching a selector.
+pub const MODEL_SELECTOR_HEADER: &str = "x-galemind-model-selector";
+
+/// Longest label key or value accepted.
+const MAX_LABEL_LENGTH: usize = 253;
+
+/// Checks that `text` may be used as a label key (or value, when `allow_empty`).
+pub fn check_label(text: &str, allow_empty: bool) -> Result<()> {
+    if text.is_empty() && !allow_empty {
+        return Err(anyhow!("Label keys must not be empty"));
+    }
+    if text.len() > MAX_LABEL_LENGTH {
+        return Err(anyhow!(
+            "Label '{}' is longer than {} characters",
+            text,
+            MAX_LABEL_LENGTH
+        ));
+    }
+    if let Some(c) = text
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')))
+    {
+        return Err(anyhow!(
+            "Label '{}' contains invalid character '{}'",
+            text,
+            c
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, BTreeSet<String>),
+    NotIn(String, BTreeSet<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    pub fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Self::Equals(key, value) => labels.get(key) == Some(value),
+            Self::NotEquals(key, value) => labels.get(key) != Some(value),
+            Self::In(key, values) => labels.get(key).is_some_and(|v| values.contains(v)),
+            Self::NotIn(key, values) => !labels.get(key).is_some_and(|v| values.contains(v)),
+            Self::Exists(key) => labels.contains_key(key),
+            Self::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let checked_key = |key: &str| -> Result<String> {
+            let key = key.trim();
+            check_label(key, false)?;
+            Ok(key.to_string())
+        };
+        let checked_value = |value: &str| -> Result<String> {
+            let value = value.trim();
+            check_label(value, true)?;
+            Ok(value.to_string())
+        };
+
+        if let Some((key, value)) = s.split_once("!=") {
+            return Ok(Self::NotEquals(checked_key(key)?, checked_value(value)?));
+        }
+        if let Some((key, value)) = s.split_once("==").or_else(|| s.split_once('=')) {
+            return Ok(Self::Equals(checked_key(key)?, checked_value(value)?));
+        }
+        if let Some(key) = s.strip_prefix('!') {
+            return Ok(Self::NotExists(checked_key(key)?));
+        }
+        if let Some(open) = s.find('(') {
+            let Some(inner) = s[open + 1..].strip_suffix(')') else {
+                return Err(anyhow!("Unclosed value set in '{}'", s));
+            };
+            let mut words = s[..open].split_whitespace();
+            let (Some(key), Some(operator), None) = (words.next(), words.next(), words.next())
+            else {
+                return Err(anyhow!("Expected '<key> in (...)' in '{}'", s));
+            };
+            let values = inner
+                .split(',')
+                .map(checked_value)
+                .collect::<Result<BTreeSet<_>>>()?;
+            return match operator {
+                "in" => Ok(Self::In(checked_key(key)?, values)),
+                "notin" => Ok(Self::NotIn(checked_key(key)?, values)),
+                _ => Err(anyhow!("Unknown selector operator '{}'", operator)),
+            };
+        }
+        Ok(Self::Exists(checked_key(s)?))
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |values: &BTreeSet<String>| values.iter().cloned().collect::<Vec<_>>().join(",");
+        match self {
+            Self::Equals(key, value) => write!(f, "{}={}", key, value),
+            Self::NotEquals(key, value) => write!(f, "{}!={}", key, value),
+            Self::In(key, values) => write!(f, "{} in ({})", key, join(values)),
+            Self::NotIn(key, values) => write!(f, "{} notin ({})", key, join(values)),
+            Self::Exists(key) => write!(f, "{}", key),
+            Self::NotExists(key) => write!(f, "!{}", key),
+        }
+    }
+}
+
+/// Requirements that must all hold; the empty selector matches every model.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    pub requirements: Vec<Requirement>,
+}
+
+impl LabelSelector {
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(labels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Split on the commas separating requirements, not those inside value sets.
+        let mut requirements = Vec::new();
+        let (mut depth, mut start) = (0usize, 0);
+        for (i, c) in s.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    requirements.push(&s[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        requirements.push(&s[start..]);
+
+        let requirements = requirements
+            .into_iter()
+            .filter(|requirement| !requirement.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow!("Invalid label selector '{}': {}", s, e))?;
+        Ok(Self { requirements })
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requirements: Vec<String> = self.requirements.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", requirements.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_selector_matches_all_requirements() {
+        let sentiment_en = labels(&[("task", "sentiment"), ("lang", "en")]);
+        let sentiment_de = labels(&[("task", "sentiment"), ("lang", "de"), ("beta", "")]);
+
+        let selector: LabelSelector = "task=sentiment, lang==en".parse().unwrap();
+        assert!(selector.matches(&sentiment_en));
+        assert!(!selector.matches(&sentiment_de));
+
+        let selector: LabelSelector = "lang in (en, de),!beta".parse().unwrap();
+        assert!(selector.matches(&sentiment_en));
+        assert!(!selector.matches(&sentiment_de));
+
+        let selector: LabelSelector = "region!=eu,lang notin (de),task".parse().unwrap();
+        assert!(selector.matches(&sentiment_en));
+        assert!(!selector.matches(&sentiment_de));
+
+        assert!(LabelSelector::default().matches(&sentiment_de));
+        assert_eq!(
+            "lang in (en,de),!beta"
+                .parse::<LabelSelector>()
+                .unwrap()
+                .to_string(),
+            "lang in (de,en),!beta"
+        );
+    }
+
+    #[test]
+    fn test_invalid_selectors_are_rejected() {
+        for selector in [
+            "=en",
+            "lang in (en",
+            "lang within (en)",
+            "la ng",
+            "task=a b",
+        ] {
+            assert!(
+                selector.parse::<LabelSelector>().is_err(),
+                "{} should be invalid",
+                selector
+            );
+        }
+    }
+}
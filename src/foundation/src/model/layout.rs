@@ -0,0 +1,282 @@
+/* Model directory layouts of other inference servers.
+
+GaleMind's own layout is one directory per model in MODELS_DIR, named after the
+model with its artifact extension (`resnet.onnx/`). To ease migrations, two
+other layouts are recognized and mapped onto model names and versions:
+
+- Triton: `<model>/<version>/<artifact>`, with numeric version directories and
+  an optional `<model>/config.pbtxt`. The backend comes from the `backend` or
+  `platform` of the config, else from the artifact file names (`model.onnx`,
+  `model.pt`, `model.savedmodel`, ...).
+- MLServer: `<model>/model-settings.json`, a single version, or one
+  `model-settings.json` per version directory. The name and version come from
+  the settings (`name`, `parameters.version`), the backend from the runtime
+  `implementation` (`mlserver_sklearn.SKLearnModel` is `sklearn`).
+
+Directories without these markers are left to the native layout.
+*/
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::model::model_discovery_service::compare_versions;
+
+/// Settings file of an MLServer model.
+pub const MLSERVER_SETTINGS_FILE: &str = "model-settings.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelLayout {
+    Triton,
+    MLServer,
+}
+
+impl fmt::Display for ModelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelLayout::Triton => f.write_str("Triton"),
+            ModelLayout::MLServer => f.write_str("MLServer"),
+        }
+    }
+}
+
+/// A version of a `LegacyModel`: its artifact directory and the runtime backends that
+/// may load it, in preference order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyVersion {
+    pub version: String,
+    pub dir: PathBuf,
+    pub backends: Vec<String>,
+}
+
+/// A model directory in the layout of another server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyModel {
+    pub name: String,
+    pub layout: ModelLayout,
+    pub dir: PathBuf,
+    /// Oldest first.
+    pub versions: Vec<LegacyVersion>,
+}
+
+impl LegacyModel {
+    /// Recognizes the layout of the model directory `dir`, if it is a Triton or MLServer one.
+    pub fn detect(dir: &Path) -> Result<Option<Self>> {
+        let Some(dir_name) = dir.file_name().and_then(|name| name.to_str()) else {
+            return Ok(None);
+        };
+        let mut model = if dir.join(MLSERVER_SETTINGS_FILE).is_file() {
+            let (name, version) = mlserver_version(dir, "1")?;
+            Self {
+                name: name.unwrap_or_else(|| dir_name.to_string()),
+                layout: ModelLayout::MLServer,
+                dir: dir.to_path_buf(),
+                versions: vec![version],
+            }
+        } else {
+            let subdirs = subdirectories(dir)?;
+            if subdirs
+                .iter()
+                .any(|(_, subdir)| subdir.join(MLSERVER_SETTINGS_FILE).is_file())
+            {
+                let mut name = None;
+                let mut versions = Vec::new();
+                for (subdir_name, subdir) in subdirs
+                    .iter()
+                    .filter(|(_, subdir)| subdir.join(MLSERVER_SETTINGS_FILE).is_file())
+                {
+                    let (version_name, version) = mlserver_version(subdir, subdir_name)?;
+                    name = name.or(version_name);
+                    versions.push(version);
+                }
+                Self {
+                    name: name.unwrap_or_else(|| dir_name.to_string()),
+                    layout: ModelLayout::MLServer,
+                    dir: dir.to_path_buf(),
+                    versions,
+                }
+            } else {
+                let config_backend = triton_config_backend(dir)?;
+                let versions: Vec<LegacyVersion> = subdirs
+                    .into_iter()
+                    .filter(|(name, _)| name.parse::<u64>().is_ok())
+                    .map(|(version, subdir)| {
+                        let mut backends: Vec<String> = config_backend.iter().cloned().collect();
+                        for backend in artifact_backends(&subdir) {
+                            if !backends.contains(&backend) {
+                                backends.push(backend);
+                            }
+                        }
+                        LegacyVersion {
+                            version,
+                            dir: subdir,
+                            backends,
+                        }
+                    })
+                    .collect();
+                if versions.is_empty() {
+                    return Ok(None);
+                }
+                Self {
+                    name: dir_name.to_string(),
+                    layout: ModelLayout::Triton,
+                    dir: dir.to_path_buf(),
+                    versions,
+                }
+            }
+        };
+        model
+            .versions
+            .sort_by(|a, b| compare_versions(&a.version, &b.version));
+        Ok(Some(model))
+    }
+}
+
+/// Subdirectories of `dir` with their names.
+fn subdirectories(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir()
+            && let Some(name) = entry.file_name().to_str()
+        {
+            subdirs.push((name.to_string(), entry.path()));
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Our backend name for a Triton `backend` or `platform`.
+fn triton_backend(backend: &str) -> String {
+    match backend {
+        "onnxruntime" | "onnxruntime_onnx" => "onnx",
+        "pytorch" | "pytorch_libtorch" => "pytorch",
+        "tensorflow" | "tensorflow_savedmodel" | "tensorflow_graphdef" => "tensorflow",
+        "tensorrt" | "tensorrt_plan" => "tensorrt",
+        other => other,
+    }
+    .to_string()
+}
+
+fn triton_config_backend(dir: &Path) -> Result<Option<String>> {
+    let path = dir.join("config.pbtxt");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let config = crate::model::pbtxt::parse(&fs::read_to_string(&path)?)
+        .with_context(|| format!("Invalid model config {}", path.display()))?;
+    Ok(config
+        .get("backend")
+        .or_else(|| config.get("platform"))
+        .and_then(|backend| backend.as_str())
+        .map(triton_backend))
+}
+
+/// Backends of the artifacts Triton looks for in a version directory.
+fn artifact_backends(dir: &Path) -> Vec<String> {
+    const ARTIFACTS: &[(&str, &str)] = &[
+        ("model.onnx", "onnx"),
+        ("model.pt", "pytorch"),
+        ("model.savedmodel", "tensorflow"),
+        ("model.graphdef", "tensorflow"),
+        ("model.plan", "tensorrt"),
+        ("model.py", "python"),
+    ];
+    ARTIFACTS
+        .iter()
+        .filter(|(artifact, _)| dir.join(artifact).exists())
+        .map(|(_, backend)| backend.to_string())
+        .collect()
+}
+
+/// Reads the MLServer settings of a version directory: the model name they declare and the
+/// version, `default_version` when they do not declare one.
+fn mlserver_version(dir: &Path, default_version: &str) -> Result<(Option<String>, LegacyVersion)> {
+    let path = dir.join(MLSERVER_SETTINGS_FILE);
+    let settings: Value = serde_json::from_str(&fs::read_to_string(&path)?)
+        .with_context(|| format!("Invalid MLServer settings {}", path.display()))?;
+    let name = settings["name"].as_str().map(str::to_string);
+    let version = settings["parameters"]["version"]
+        .as_str()
+        .unwrap_or(default_version)
+        .to_string();
+    // `mlserver_sklearn.SKLearnModel` is served by the `sklearn` backend.
+    let backends = settings["implementation"]
+        .as_str()
+        .and_then(|implementation| implementation.split('.').next())
+        .map(
+            |module| match module.strip_prefix("mlserver_").unwrap_or(module) {
+                "mlflow" => "python_function".to_string(),
+                backend => backend.to_string(),
+            },
+        )
+        .into_iter()
+        .collect();
+    Ok((
+        name,
+        LegacyVersion {
+            version,
+            dir: dir.to_path_buf(),
+            backends,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triton_and_mlserver_layouts_are_detected() {
+        let root = std::env::temp_dir().join(format!("galemind-layouts-{}", std::process::id()));
+        let triton = root.join("densenet");
+        fs::create_dir_all(triton.join("10")).unwrap();
+        fs::create_dir_all(triton.join("2")).unwrap();
+        fs::write(triton.join("2/model.onnx"), b"").unwrap();
+        fs::write(triton.join("10/model.pt"), b"").unwrap();
+        fs::write(
+            triton.join("config.pbtxt"),
+            "platform: \"onnxruntime_onnx\"\n",
+        )
+        .unwrap();
+        let mlserver = root.join("iris");
+        fs::create_dir_all(mlserver.join("v1")).unwrap();
+        fs::write(
+            mlserver.join("v1").join(MLSERVER_SETTINGS_FILE),
+            r#"{"name": "iris-classifier", "implementation": "mlserver_sklearn.SKLearnModel",
+                "parameters": {"uri": "./model.joblib", "version": "0.3"}}"#,
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("plain")).unwrap();
+
+        let model = LegacyModel::detect(&triton).unwrap().unwrap();
+        assert_eq!(
+            (model.name.as_str(), model.layout),
+            ("densenet", ModelLayout::Triton)
+        );
+        let versions: Vec<(&str, Vec<String>)> = model
+            .versions
+            .iter()
+            .map(|v| (v.version.as_str(), v.backends.clone()))
+            .collect();
+        assert_eq!(
+            versions,
+            [
+                ("2", vec!["onnx".to_string()]),
+                ("10", vec!["onnx".to_string(), "pytorch".to_string()])
+            ]
+        );
+
+        let model = LegacyModel::detect(&mlserver).unwrap().unwrap();
+        assert_eq!(model.name, "iris-classifier");
+        assert_eq!(model.layout, ModelLayout::MLServer);
+        assert_eq!(model.versions[0].version, "0.3");
+        assert_eq!(model.versions[0].backends, ["sklearn"]);
+        assert_eq!(model.versions[0].dir, mlserver.join("v1"));
+
+        assert_eq!(LegacyModel::detect(&root.join("plain")).unwrap(), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
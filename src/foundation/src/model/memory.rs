@@ -0,0 +1,621 @@
+/* Memory budgets of the devices model versions are loaded onto.
+
+Without a budget every served version is loaded when it is registered and
+stays loaded. With a budget for its device (`--gpu-memory-budget`, which
+applies to each GPU, or `--cpu-memory-budget`), a version loaded from
+artifacts is registered unloaded and loaded on its first request, like
+Triton's explicit model control:
+
+- A version takes the `memory` of its model configuration, or the size of its
+  artifacts, once per instance.
+- When a version does not fit, the versions of the same device used least
+  recently are unloaded until it does. Requests running on an unloaded version
+  complete on it; its memory is released once they have.
+- A version that cannot fit even then fails its requests instead of loading.
+- Versions of a `pinned` model load at startup and are never unloaded.
+- With `--model-idle-unload`, versions that received no request for that long
+  are unloaded as well, freeing memory for the busy ones ahead of time.
+
+`GET /{version}/admin/memory` shows the budget, the memory in use and the
+versions of every device.
+*/
+
+use crate::log_info;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::api::devices::Device;
+use crate::api::inference::{InferenceError, InferenceRequest, InferenceResponse};
+use crate::api::inference_runtime::InferenceRuntime;
+use crate::api::instance_pool::InstanceLoader;
+use crate::api::model_metadata::ModelSignature;
+use crate::model::model_discovery_service::ModelVersionId;
+
+const MIB: u64 = 1 << 20;
+
+/// Bytes the versions loaded onto a device may take; unlimited where unset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryBudget {
+    /// Budget of each GPU.
+    pub gpu: Option<u64>,
+    pub cpu: Option<u64>,
+}
+
+impl MemoryBudget {
+    pub fn limit(&self, device: Device) -> Option<u64> {
+        if device.is_gpu() { self.gpu } else { self.cpu }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.gpu.is_some() || self.cpu.is_some()
+    }
+}
+
+/// Total size of the files under `dir`, the estimated memory of one loaded instance.
+pub fn artifact_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => artifact_size(&entry.path()),
+            Ok(_) => entry.metadata().map_or(0, |metadata| metadata.len()),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryState {
+    Unloaded,
+    /// Memory is reserved while the version loads.
+    Loading,
+    Loaded,
+}
+
+struct Entry {
+    device: Device,
+    bytes: u64,
+    pinned: bool,
+    state: MemoryState,
+    last_used: Instant,
+    runtime: Weak<ManagedRuntime>,
+}
+
+/// A version, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VersionMemory {
+    pub version: String,
+    pub bytes: u64,
+    pub state: MemoryState,
+    pub pinned: bool,
+    /// Seconds since the version last received a request, or was registered.
+    pub idle: f64,
+}
+
+/// A device, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceMemory {
+    pub device: Device,
+    pub budget: Option<u64>,
+    /// Bytes of the versions loaded or loading.
+    pub used: u64,
+    pub versions: Vec<VersionMemory>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MemoryUsage {
+    pub devices: Vec<DeviceMemory>,
+    /// Versions unloaded to make room for others, or for being idle, since startup.
+    pub evictions: u64,
+}
+
+/// Tracks the memory of the versions loaded on demand and chooses those to unload.
+#[derive(Default)]
+pub struct MemoryManager {
+    budget: MemoryBudget,
+    entries: Mutex<HashMap<ModelVersionId, Entry>>,
+    evictions: AtomicU64,
+}
+
+impl MemoryManager {
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self {
+            budget,
+            ..Self::default()
+        }
+    }
+
+    pub fn budget(&self) -> MemoryBudget {
+        self.budget
+    }
+
+    /// Whether versions loaded onto `device` are loaded on demand.
+    pub fn manages(&self, device: Device) -> bool {
+        self.budget.limit(device).is_some()
+    }
+
+    fn register(&self, runtime: &Arc<ManagedRuntime>) {
+        self.entries.lock().unwrap().insert(
+            runtime.version_id.clone(),
+            Entry {
+                device: runtime.device,
+                bytes: runtime.bytes,
+                pinned: runtime.pinned,
+                state: MemoryState::Unloaded,
+                last_used: Instant::now(),
+                runtime: Arc::downgrade(runtime),
+            },
+        );
+    }
+
+    /// Stops tracking `version_id`, whose runtime is dropped with its registration.
+    pub fn remove(&self, version_id: &ModelVersionId) {
+        self.entries.lock().unwrap().remove(version_id);
+    }
+
+    /// Reserves the memory of `version_id` before it loads, marking the versions used
+    /// least recently on its device as unloaded until it fits. Returns those versions,
+    /// for the caller to unload; fails when it cannot fit.
+    fn reserve(&self, version_id: &ModelVersionId) -> Result<Vec<Arc<ManagedRuntime>>> {
+        let mut entries = self.entries.lock().unwrap();
+        let (device, bytes) = match entries.get(version_id) {
+            Some(entry) if entry.state == MemoryState::Unloaded => (entry.device, entry.bytes),
+            Some(_) => return Ok(Vec::new()),
+            None => return Err(anyhow!("Model {} is no longer served", version_id)),
+        };
+        let mut victims = Vec::new();
+        if let Some(limit) = self.budget.limit(device) {
+            let mut used: u64 = entries
+                .values()
+                .filter(|entry| entry.device == device && entry.state != MemoryState::Unloaded)
+                .map(|entry| entry.bytes)
+                .sum();
+            let mut candidates: Vec<(&ModelVersionId, &Entry)> = entries
+                .iter()
+                .filter(|(_, entry)| {
+                    entry.device == device && entry.state == MemoryState::Loaded && !entry.pinned
+                })
+                .collect();
+            candidates.sort_by_key(|(_, entry)| entry.last_used);
+            let mut candidates = candidates.into_iter();
+            while used + bytes > limit {
+                let Some((victim, entry)) = candidates.next() else {
+                    return Err(anyhow!(
+                        "Not enough memory on {} to load {}: it needs {} MiB, {} MiB of the \
+                         {} MiB budget are held by pinned or loading versions",
+                        device,
+                        version_id,
+                        bytes.div_ceil(MIB),
+                        used.div_ceil(MIB),
+                        limit / MIB
+                    ));
+                };
+                used -= entry.bytes;
+                victims.push(victim.clone());
+            }
+        }
+        let victims = victims
+            .into_iter()
+            .filter_map(|victim| {
+                let entry = entries.get_mut(&victim)?;
+                entry.state = MemoryState::Unloaded;
+                entry.runtime.upgrade()
+            })
+            .collect::<Vec<_>>();
+        self.evictions
+            .fetch_add(victims.len() as u64, Ordering::Relaxed);
+        let entry = entries.get_mut(version_id).unwrap();
+        entry.state = MemoryState::Loading;
+        entry.last_used = Instant::now();
+        Ok(victims)
+    }
+
+    fn set_state(&self, version_id: &ModelVersionId, state: MemoryState) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(version_id) {
+            entry.state = state;
+        }
+    }
+
+    fn touch(&self, version_id: &ModelVersionId) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(version_id) {
+            entry.last_used = Instant::now();
+        }
+    }
+
+    /// Unloads the versions that are not pinned and received no request for `idle`.
+    /// Returns them.
+    pub fn unload_idle(&self, idle: Duration) -> Vec<ModelVersionId> {
+        let idle_runtimes: Vec<Arc<ManagedRuntime>> = {
+            let mut entries = self.entries.lock().unwrap();
+            entries
+                .values_mut()
+                .filter(|entry| {
+                    entry.state == MemoryState::Loaded
+                        && !entry.pinned
+                        && entry.last_used.elapsed() >= idle
+                })
+                .filter_map(|entry| {
+                    entry.state = MemoryState::Unloaded;
+                    entry.runtime.upgrade()
+                })
+                .collect()
+        };
+        self.evictions
+            .fetch_add(idle_runtimes.len() as u64, Ordering::Relaxed);
+        idle_runtimes
+            .iter()
+            .filter(|runtime| runtime.unload())
+            .map(|runtime| runtime.version_id.clone())
+            .collect()
+    }
+
+    /// Unloads the versions idle for `idle`, checking every tenth of it. Must be called
+    /// from within a tokio runtime.
+    pub fn spawn_idle_unload(self: &Arc<Self>, idle: Duration) -> JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval((idle / 10).max(Duration::from_secs(1)));
+            loop {
+                ticks.tick().await;
+                for version_id in manager.unload_idle(idle) {
+                    log_info!(
+                        "Unloaded {}, idle for more than {} s",
+                        version_id,
+                        idle.as_secs()
+                    );
+                }
+            }
+        })
+    }
+
+    /// Memory of every device holding versions.
+    pub fn usage(&self) -> MemoryUsage {
+        let entries = self.entries.lock().unwrap();
+        let mut devices: HashMap<Device, DeviceMemory> = HashMap::new();
+        for (version_id, entry) in entries.iter() {
+            let device = devices.entry(entry.device).or_insert_with(|| DeviceMemory {
+                device: entry.device,
+                budget: self.budget.limit(entry.device),
+                used: 0,
+                versions: Vec::new(),
+            });
+            if entry.state != MemoryState::Unloaded {
+                device.used += entry.bytes;
+            }
+            device.versions.push(VersionMemory {
+                version: version_id.to_string(),
+                bytes: entry.bytes,
+                state: entry.state,
+                pinned: entry.pinned,
+                idle: entry.last_used.elapsed().as_secs_f64(),
+            });
+        }
+        let mut devices: Vec<DeviceMemory> = devices.into_values().collect();
+        devices.sort_by_key(|device| device.device);
+        for device in &mut devices {
+            device.versions.sort_by(|a, b| a.version.cmp(&b.version));
+        }
+        MemoryUsage {
+            devices,
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Runtime of a version loaded on demand within the memory budget of its device. It is
+/// registered in place of the version's runtime, which it loads on the first request and
+/// drops when the memory manager unloads it.
+pub struct ManagedRuntime {
+    version_id: ModelVersionId,
+    device: Device,
+    bytes: u64,
+    pinned: bool,
+    platform: String,
+    loader: InstanceLoader,
+    loaded: Mutex<Option<Arc<dyn InferenceRuntime>>>,
+    /// Signature of the last runtime loaded, kept while the version is unloaded.
+    signature: Mutex<Option<ModelSignature>>,
+    /// Held while the runtime loads, so that concurrent first requests load it once.
+    loading: tokio::sync::Mutex<()>,
+    manager: Arc<MemoryManager>,
+}
+
+impl ManagedRuntime {
+    /// A runtime of `version_id` taking `bytes` on `device` once loaded with `loader`,
+    /// tracked by `manager`. It is not loaded yet.
+    pub fn new(
+        version_id: ModelVersionId,
+        device: Device,
+        bytes: u64,
+        pinned: bool,
+        platform: impl Into<String>,
+        loader: InstanceLoader,
+        manager: Arc<MemoryManager>,
+    ) -> Arc<Self> {
+        let runtime = Arc::new(Self {
+            version_id,
+            device,
+            bytes,
+            pinned,
+            platform: platform.into(),
+            loader,
+            loaded: Mutex::new(None),
+            signature: Mutex::new(None),
+            loading: tokio::sync::Mutex::new(()),
+            manager: manager.clone(),
+        });
+        manager.register(&runtime);
+        runtime
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.lock().unwrap().is_some()
+    }
+
+    fn current(&self) -> Option<Arc<dyn InferenceRuntime>> {
+        let runtime = self.loaded.lock().unwrap().clone();
+        if runtime.is_some() {
+            self.manager.touch(&self.version_id);
+        }
+        runtime
+    }
+
+    /// Drops the loaded runtime, returning whether there was one.
+    fn unload(&self) -> bool {
+        self.loaded.lock().unwrap().take().is_some()
+    }
+
+    /// Makes room for the version, unloading others if need be.
+    fn make_room(&self) -> Result<()> {
+        for victim in self.manager.reserve(&self.version_id)? {
+            if victim.unload() {
+                log_info!(
+                    "Unloaded {} from {} to make room for {}",
+                    victim.version_id,
+                    victim.device,
+                    self.version_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the outcome of a load, releasing the reserved memory when it failed.
+    fn loaded(
+        &self,
+        loaded: Result<Arc<dyn InferenceRuntime>>,
+        started: Instant,
+    ) -> Result<Arc<dyn InferenceRuntime>> {
+        match loaded {
+            Ok(runtime) => {
+                *self.signature.lock().unwrap() = runtime.signature();
+                *self.loaded.lock().unwrap() = Some(runtime.clone());
+                self.manager
+                    .set_state(&self.version_id, MemoryState::Loaded);
+                log_info!(
+                    "Loaded {} onto {} in {:.0} ms ({} MiB)",
+                    self.version_id,
+                    self.device,
+                    started.elapsed().as_secs_f64() * 1_000.0,
+                    self.bytes.div_ceil(MIB)
+                );
+                Ok(runtime)
+            }
+            Err(e) => {
+                self.manager
+                    .set_state(&self.version_id, MemoryState::Unloaded);
+                Err(anyhow!("Failed to load {}: {:#}", self.version_id, e))
+            }
+        }
+    }
+
+    /// Loads the version now, from outside of a tokio runtime or while nothing else
+    /// loads it, e.g. at startup.
+    pub fn load_blocking(&self) -> Result<()> {
+        if self.is_loaded() {
+            return Ok(());
+        }
+        let _loading = self
+            .loading
+            .try_lock()
+            .map_err(|_| anyhow!("Model {} is already loading", self.version_id))?;
+        self.make_room()?;
+        let started = Instant::now();
+        self.loaded((self.loader)(), started).map(|_| ())
+    }
+
+    /// The loaded runtime, loading it first if need be.
+    async fn runtime(&self) -> Result<Arc<dyn InferenceRuntime>> {
+        if let Some(runtime) = self.current() {
+            return Ok(runtime);
+        }
+        let _loading = self.loading.lock().await;
+        if let Some(runtime) = self.current() {
+            return Ok(runtime);
+        }
+        self.make_room()?;
+        let started = Instant::now();
+        let loader = self.loader.clone();
+        let loaded = tokio::task::spawn_blocking(move || loader())
+            .await
+            .unwrap_or_else(|e| Err(anyhow!("the loader panicked: {}", e)));
+        self.loaded(loaded, started)
+    }
+}
+
+#[async_trait]
+impl InferenceRuntime for ManagedRuntime {
+    fn model_id(&self) -> &str {
+        &self.version_id.model.0
+    }
+
+    fn platform(&self) -> Option<&str> {
+        Some(&self.platform)
+    }
+
+    fn signature(&self) -> Option<ModelSignature> {
+        match self.loaded.lock().unwrap().as_ref() {
+            Some(runtime) => runtime.signature(),
+            None => self.signature.lock().unwrap().clone(),
+        }
+    }
+
+    async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+        match self.runtime().await {
+            Ok(runtime) => runtime.process_single(request).await,
+            Err(e) => InferenceResponse::Error(InferenceError {
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    async fn process_batch(&self, requests: Vec<InferenceRequest>) -> Vec<InferenceResponse> {
+        match self.runtime().await {
+            Ok(runtime) => runtime.process_batch(requests).await,
+            Err(e) => requests
+                .iter()
+                .map(|_| {
+                    InferenceResponse::Error(InferenceError {
+                        error: e.to_string(),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeInferenceProcessor;
+    use crate::api::inference_runtime::ProcessorRuntime;
+    use crate::model::priority::Priority;
+    use std::sync::atomic::AtomicUsize;
+
+    fn manager(gpu: u64) -> Arc<MemoryManager> {
+        Arc::new(MemoryManager::new(MemoryBudget {
+            gpu: Some(gpu),
+            cpu: None,
+        }))
+    }
+
+    /// A runtime of `version` taking `bytes` on GPU 0, counting its loads.
+    fn managed(
+        manager: &Arc<MemoryManager>,
+        version: &str,
+        bytes: u64,
+        pinned: bool,
+        loads: &Arc<AtomicUsize>,
+    ) -> Arc<ManagedRuntime> {
+        let loads = loads.clone();
+        let loader: InstanceLoader = Arc::new(move || {
+            loads.fetch_add(1, Ordering::SeqCst);
+            Ok(Arc::new(ProcessorRuntime::new("m", FakeInferenceProcessor)) as _)
+        });
+        ManagedRuntime::new(
+            ModelVersionId::new("m", version),
+            Device::Cuda(0),
+            bytes,
+            pinned,
+            "fake",
+            loader,
+            manager.clone(),
+        )
+    }
+
+    fn request() -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: None,
+            id: "r".to_string(),
+            parameters: None,
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    fn states(manager: &MemoryManager) -> Vec<(String, MemoryState)> {
+        manager.usage().devices[0]
+            .versions
+            .iter()
+            .map(|version| (version.version.clone(), version.state))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_versions_load_on_demand_and_evict_least_recently_used() {
+        let manager = manager(100);
+        let loads = Arc::new(AtomicUsize::new(0));
+        let one = managed(&manager, "1", 40, false, &loads);
+        let two = managed(&manager, "2", 40, false, &loads);
+        let three = managed(&manager, "3", 40, false, &loads);
+        assert_eq!(loads.load(Ordering::SeqCst), 0);
+
+        one.process_single(request()).await;
+        two.process_single(request()).await;
+        one.process_single(request()).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+
+        // 2 was used least recently.
+        three.process_single(request()).await;
+        assert!(one.is_loaded() && !two.is_loaded() && three.is_loaded());
+        assert_eq!(
+            states(&manager),
+            vec![
+                ("m:1".to_string(), MemoryState::Loaded),
+                ("m:2".to_string(), MemoryState::Unloaded),
+                ("m:3".to_string(), MemoryState::Loaded),
+            ]
+        );
+        let usage = manager.usage();
+        assert_eq!(usage.devices[0].used, 80);
+        assert_eq!(usage.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_versions_stay_loaded() {
+        let manager = manager(100);
+        let loads = Arc::new(AtomicUsize::new(0));
+        let pinned = managed(&manager, "1", 60, true, &loads);
+        pinned.load_blocking().unwrap();
+        let other = managed(&manager, "2", 30, false, &loads);
+        let large = managed(&manager, "3", 50, false, &loads);
+
+        other.process_single(request()).await;
+        // Only the unpinned version can be unloaded, which is not enough.
+        let response = large.process_single(request()).await;
+        let InferenceResponse::Error(error) = response else {
+            panic!("expected an error");
+        };
+        assert!(error.error.contains("Not enough memory on cuda:0"));
+        assert!(pinned.is_loaded() && other.is_loaded() && !large.is_loaded());
+
+        assert!(manager.unload_idle(Duration::ZERO) == vec![ModelVersionId::new("m", "2")]);
+        assert!(pinned.is_loaded() && !other.is_loaded());
+    }
+
+    #[test]
+    fn test_artifact_size() {
+        let dir = std::env::temp_dir().join(format!("galemind-memory-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("variables")).unwrap();
+        std::fs::write(dir.join("model.onnx"), [0u8; 100]).unwrap();
+        std::fs::write(dir.join("variables").join("data"), [0u8; 20]).unwrap();
+        assert_eq!(artifact_size(&dir), 120);
+        assert_eq!(artifact_size(&dir.join("missing")), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+/* Stage-based auto-deployment from an MLflow model registry.
+
+The watcher periodically lists the versions of the watched models that sit in
+a given stage (e.g. "Production"). Versions entering the stage are downloaded
+and deployed, versions leaving it are retired, so the served set follows the
+registry without restarts. Every transition can be reported to a webhook as a
+JSON `StageTransition`.
+*/
+
+use crate::log_info;
+use anyhow::Result;
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::api::mlflow_client::MLFlowClientTrait;
+use crate::model::model_discovery_service::{
+    ModelDiscoveryService, ModelId, ModelVersionId, compare_versions, labels_from_tags,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionAction {
+    Deployed,
+    Retired,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageTransition {
+    pub model_name: String,
+    pub model_version: String,
+    pub stage: String,
+    pub action: TransitionAction,
+}
+
+pub struct MLFlowStageWatcher {
+    client: Arc<dyn MLFlowClientTrait>,
+    model_manager: Arc<ModelDiscoveryService>,
+    stage: String,
+    model_name: Option<String>,
+    poll_interval: Duration,
+    webhook: Option<String>,
+    http: Client,
+}
+
+impl MLFlowStageWatcher {
+    pub fn new(
+        client: Arc<dyn MLFlowClientTrait>,
+        model_manager: Arc<ModelDiscoveryService>,
+        stage: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            model_manager,
+            stage: stage.into(),
+            model_name: None,
+            poll_interval: Duration::from_secs(60),
+            webhook: None,
+            http: Client::new(),
+        }
+    }
+
+    /// Restricts the watcher to a single registered model instead of every model.
+    pub fn with_model_name(mut self, model_name: Option<String>) -> Self {
+        self.model_name = model_name;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// URL receiving a POST with each `StageTransition`.
+    pub fn with_webhook(mut self, webhook: Option<String>) -> Self {
+        self.webhook = webhook;
+        self
+    }
+
+    /// Polls immediately and then every `poll_interval` until the task is aborted.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match self.poll_once().await {
+                    Ok(transitions) => {
+                        for transition in &transitions {
+                            self.notify(transition).await;
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to poll MLflow registry: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Reconciles the served versions with the registry once, returning the transitions made.
+    /// Nothing is reconciled while the model registry is read-only.
+    pub async fn poll_once(&self) -> Result<Vec<StageTransition>> {
+        if self.model_manager.is_read_only() {
+            return Ok(Vec::new());
+        }
+        let model_names: Vec<String> = match &self.model_name {
+            Some(model_name) => vec![model_name.clone()],
+            None => {
+                let models = self.client.list_models().await?;
+                for model in &models {
+                    self.model_manager.add_labels(
+                        ModelId::from_string(model.name.clone()),
+                        labels_from_tags(model.tags.clone()),
+                        false,
+                    );
+                }
+                models.into_iter().map(|model| model.name).collect()
+            }
+        };
+
+        let mut transitions = Vec::new();
+        for model_name in model_names {
+            let model_id = ModelId::from_string(model_name);
+            transitions.extend(self.reconcile_model(&model_id).await?);
+        }
+        Ok(transitions)
+    }
+
+    async fn reconcile_model(&self, model_id: &ModelId) -> Result<Vec<StageTransition>> {
+        let mut staged: Vec<String> = self
+            .client
+            .get_model_versions(&model_id.0)
+            .await?
+            .into_iter()
+            .filter(|version| {
+                version
+                    .current_stage
+                    .as_deref()
+                    .is_some_and(|stage| stage.eq_ignore_ascii_case(&self.stage))
+                    && version.status.as_deref().is_none_or(|s| s == "READY")
+            })
+            .map(|version| version.version)
+            .collect();
+        staged.sort_by(|a, b| compare_versions(a, b));
+        let wanted: BTreeSet<String> = self
+            .model_manager
+            .version_policy(model_id)
+            .select(staged)
+            .into_iter()
+            .collect();
+        let served: BTreeSet<String> = self
+            .model_manager
+            .get_model_versions(model_id)
+            .into_iter()
+            .collect();
+
+        let mut transitions = Vec::new();
+        for version in wanted.difference(&served) {
+            let version_id = ModelVersionId::new(model_id.0.clone(), version.clone());
+            match self
+                .model_manager
+                .deploy_mlflow_version(&self.client, &version_id)
+                .await
+            {
+                Ok(()) => {
+                    transitions.push(self.transition(&version_id, TransitionAction::Deployed))
+                }
+                // Left undeployed, so the next poll retries it.
+                Err(e) => eprintln!("Failed to deploy MLflow model {}: {}", version_id, e),
+            }
+        }
+        // Retire only once a wanted version is served, so a failed deployment never
+        // leaves the model without any version.
+        let now_served = self.model_manager.get_model_versions(model_id);
+        if wanted.is_empty() || wanted.iter().any(|version| now_served.contains(version)) {
+            for version in served.difference(&wanted) {
+                let version_id = ModelVersionId::new(model_id.0.clone(), version.clone());
+                if self.model_manager.unregister_model_version(&version_id) {
+                    transitions.push(self.transition(&version_id, TransitionAction::Retired));
+                }
+            }
+        }
+
+        Ok(transitions)
+    }
+
+    fn transition(&self, version_id: &ModelVersionId, action: TransitionAction) -> StageTransition {
+        StageTransition {
+            model_name: version_id.model.0.clone(),
+            model_version: version_id.version.clone(),
+            stage: self.stage.clone(),
+            action,
+        }
+    }
+
+    async fn notify(&self, transition: &StageTransition) {
+        log_info!(
+            "MLflow {} {}:{} ({})",
+            match transition.action {
+                TransitionAction::Deployed => "deployed",
+                TransitionAction::Retired => "retired",
+            },
+            transition.model_name,
+            transition.model_version,
+            transition.stage
+        );
+
+        let Some(webhook) = &self.webhook else {
+            return;
+        };
+        let result = self
+            .http
+            .post(webhook)
+            .json(transition)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            eprintln!("Failed to notify stage transition webhook: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeRuntimeFactory;
+    use crate::api::mlflow_client::{MLFlowArtifact, MLFlowModel, MLFlowModelVersion};
+    use crate::api::runtime_registry::{RuntimeFactory, RuntimeRegistry};
+    use crate::model::model_discovery_service::VersionPolicy;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    /// Registry with one model whose version stages can be changed between polls.
+    struct StagedMLFlowClient {
+        artifact_root: PathBuf,
+        stages: Mutex<Vec<(String, String)>>,
+    }
+
+    impl StagedMLFlowClient {
+        fn set_stages(&self, stages: &[(&str, &str)]) {
+            *self.stages.lock().unwrap() = stages
+                .iter()
+                .map(|(version, stage)| (version.to_string(), stage.to_string()))
+                .collect();
+        }
+    }
+
+    #[async_trait]
+    impl MLFlowClientTrait for StagedMLFlowClient {
+        async fn list_models(&self) -> Result<Vec<MLFlowModel>> {
+            Ok(vec![MLFlowModel {
+                name: "ranker".to_string(),
+                version: None,
+                creation_timestamp: None,
+                last_updated_timestamp: None,
+                description: None,
+                tags: None,
+            }])
+        }
+
+        async fn get_model_versions(&self, model_name: &str) -> Result<Vec<MLFlowModelVersion>> {
+            Ok(self
+                .stages
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(version, stage)| MLFlowModelVersion {
+                    name: model_name.to_string(),
+                    version: version.clone(),
+                    creation_timestamp: None,
+                    last_updated_timestamp: None,
+                    description: None,
+                    user_id: None,
+                    current_stage: Some(stage.clone()),
+                    source: None,
+                    run_id: None,
+                    status: Some("READY".to_string()),
+                    tags: None,
+                })
+                .collect())
+        }
+
+        async fn get_model(&self, _name: &str) -> Result<Option<MLFlowModel>> {
+            Ok(self.list_models().await?.pop())
+        }
+
+        async fn get_download_uri(&self, _model_name: &str, version: &str) -> Result<String> {
+            Ok(format!(
+                "file://{}",
+                self.artifact_root.join(version).display()
+            ))
+        }
+
+        async fn list_artifacts(&self, _path: &str) -> Result<Vec<MLFlowArtifact>> {
+            Ok(Vec::new())
+        }
+
+        async fn download_artifact(&self, path: &str) -> Result<Vec<u8>> {
+            Err(anyhow!("missing artifact {}", path))
+        }
+    }
+
+    fn transitions(list: &[StageTransition]) -> Vec<(String, TransitionAction)> {
+        list.iter()
+            .map(|t| (t.model_version.clone(), t.action))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_poll_deploys_and_retires_with_stage() {
+        let artifact_root =
+            std::env::temp_dir().join(format!("galemind-mlflow-watcher-{}", std::process::id()));
+        for version in ["1", "2"] {
+            let dir = artifact_root.join(version);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("MLmodel"), "flavors:\n  fake: {}\n").unwrap();
+        }
+
+        let registry = Arc::new(RuntimeRegistry::new());
+        registry.register(Arc::new(FakeRuntimeFactory));
+        let service = Arc::new(
+            ModelDiscoveryService::new(10)
+                .with_version_policy(VersionPolicy::All)
+                .with_runtime_registry(registry),
+        );
+        let client = Arc::new(StagedMLFlowClient {
+            artifact_root: artifact_root.clone(),
+            stages: Mutex::new(Vec::new()),
+        });
+        let watcher = MLFlowStageWatcher::new(client.clone(), service.clone(), "Production");
+        let model = ModelId::from_string("ranker".to_string());
+
+        client.set_stages(&[("1", "Production"), ("2", "Staging")]);
+        let first = watcher.poll_once().await.unwrap();
+        assert_eq!(
+            transitions(&first),
+            vec![("1".to_string(), TransitionAction::Deployed)]
+        );
+        assert_eq!(service.get_model_versions(&model), vec!["1"]);
+
+        // Nothing changed: no transitions.
+        assert!(watcher.poll_once().await.unwrap().is_empty());
+
+        client.set_stages(&[("1", "Archived"), ("2", "production")]);
+        // A read-only registry is left untouched until unlocked.
+        service.set_read_only(true);
+        assert!(watcher.poll_once().await.unwrap().is_empty());
+        assert_eq!(service.get_model_versions(&model), vec!["1"]);
+        service.set_read_only(false);
+
+        let second = watcher.poll_once().await.unwrap();
+        assert_eq!(
+            transitions(&second),
+            vec![
+                ("2".to_string(), TransitionAction::Deployed),
+                ("1".to_string(), TransitionAction::Retired)
+            ]
+        );
+        assert_eq!(service.get_model_versions(&model), vec!["2"]);
+
+        std::fs::remove_dir_all(artifact_root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_deploy_keeps_serving_version() {
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        let served = ModelVersionId::new("ranker", "1");
+        service.register_model_version(
+            served.clone(),
+            FakeRuntimeFactory
+                .load(&served, std::path::Path::new("/unused"))
+                .unwrap(),
+        );
+        let client = Arc::new(StagedMLFlowClient {
+            artifact_root: PathBuf::from("/nonexistent"),
+            stages: Mutex::new(Vec::new()),
+        });
+        client.set_stages(&[("1", "Archived"), ("2", "Production")]);
+        let watcher = MLFlowStageWatcher::new(client, service.clone(), "Production")
+            .with_model_name(Some("ranker".to_string()));
+
+        assert!(watcher.poll_once().await.unwrap().is_empty());
+        assert_eq!(service.get_model_versions(&served.model), vec!["1"]);
+    }
+}
@@ -1,3 +1,27 @@
+pub mod batching;
+pub mod buffer_tuning;
+pub mod capabilities;
+pub mod circuit;
 pub mod circular_buffer;
+pub mod context_window;
+pub mod dedup;
+pub mod hints;
+pub mod images;
+pub mod labels;
+pub mod layout;
+pub mod memory;
+pub mod mlflow_watcher;
+pub mod model_config;
 pub mod model_discovery_service;
 pub mod model_manager;
+pub mod model_store;
+pub mod object_store;
+pub mod pause;
+pub mod pbtxt;
+pub mod priority;
+pub mod response_cache;
+pub mod result_backend;
+pub mod result_store;
+pub mod selftest;
+pub mod shadow;
+pub mod watermark;
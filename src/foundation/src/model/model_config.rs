@@ -0,0 +1,639 @@
+/* Per-model configuration read from the model directory.
+
+A model directory may contain either a `model.yaml` (or `model.yml`) in this
+crate's own schema or a Triton style `config.pbtxt`. Both describe the same
+`ModelConfig`: batching limit, input and output tensors, runtime backend,
+number of instances (`instance_count`, or Triton's `instance_group`), the
+device the model is pinned to (`device`, or the `gpus` of a Triton instance
+group) and the warmup samples to run before serving.
+
+```yaml
+backend: onnx
+max_batch_size: 8
+instance_group:
+  - { count: 2, kind: KIND_CPU }
+device: cpu
+overflow: { policy: block_with_timeout, timeout_ms: 50 }
+dynamic_batching:
+  max_queue_delay_ms: 5
+  preferred_batch_sizes: [4, 8]
+inputs:
+  - { name: input, datatype: FP32, shape: [3, 224, 224] }
+outputs:
+  - { name: probabilities, datatype: FP32, shape: [1000] }
+warmup:
+  - name: zeros
+    inputs:
+      - { name: input, datatype: FP32, shape: [3, 224, 224] }
+```
+
+A `golden` list of requests with their expected outputs is checked by
+`model::selftest`.
+*/
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::api::devices::Device;
+use crate::api::schema::SchemaVersions;
+use crate::model::capabilities::Capabilities;
+use crate::model::circular_buffer::OverflowPolicy;
+use crate::model::context_window::ContextWindow;
+use crate::model::images::ImageLimits;
+use crate::model::labels::{Labels, check_label};
+use crate::model::pbtxt;
+use crate::model::response_cache::ResponseCacheConfig;
+use crate::model::selftest::GoldenCase;
+use crate::model::watermark::WatermarkConfig;
+
+const YAML_CONFIG_FILES: &[&str] = &["model.yaml", "model.yml"];
+const PBTXT_CONFIG_FILE: &str = "config.pbtxt";
+
+/// Tensor datatypes of the KServe v2 protocol.
+const DATATYPES: &[&str] = &[
+    "BOOL", "UINT8", "UINT16", "UINT32", "UINT64", "INT8", "INT16", "INT32", "INT64", "FP16",
+    "FP32", "FP64", "BF16", "BYTES",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TensorSpec {
+    pub name: String,
+    pub datatype: String,
+    /// Dimensions excluding the batch dimension; -1 marks a variable size.
+    #[serde(default)]
+    pub shape: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WarmupInput {
+    pub name: String,
+    pub datatype: String,
+    #[serde(default)]
+    pub shape: Vec<i64>,
+    /// Fill with random values instead of zeros.
+    #[serde(default)]
+    pub random: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WarmupSample {
+    pub name: String,
+    #[serde(default = "default_one")]
+    pub batch_size: u32,
+    #[serde(default)]
+    pub inputs: Vec<WarmupInput>,
+}
+
+/// Runtime instances of a model, as in Triton's `instance_group`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstanceGroup {
+    #[serde(default = "default_one")]
+    pub count: u32,
+    /// Device kind, e.g. `KIND_CPU`; informational, instances run where the backend puts them.
+    #[serde(default)]
+    pub kind: Option<String>,
+}
+
+/// Dynamic batching of a model's requests, see `model::batching`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DynamicBatching {
+    /// Longest time the first request of a batch waits for others to join it.
+    #[serde(default)]
+    pub max_queue_delay_ms: f64,
+    /// Batch sizes dispatched as soon as they are reached and no more requests are queued.
+    #[serde(default)]
+    pub preferred_batch_sizes: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Runtime backend, matched against the runtime registry.
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Largest batch the model accepts; 0 disables batching.
+    #[serde(default)]
+    pub max_batch_size: u32,
+    /// Combine queued requests into batches of up to `max_batch_size`.
+    #[serde(default)]
+    pub dynamic_batching: Option<DynamicBatching>,
+    #[serde(default)]
+    pub inputs: Vec<TensorSpec>,
+    #[serde(default)]
+    pub outputs: Vec<TensorSpec>,
+    /// Runtime instances loaded per version, see `api::instance_pool`. Taken from the
+    /// sum of the `instance_group` counts when groups are declared.
+    #[serde(default = "default_one")]
+    pub instance_count: u32,
+    #[serde(default)]
+    pub instance_group: Vec<InstanceGroup>,
+    /// Device the model is pinned to, e.g. `cuda:0`, see `api::devices`. Unpinned models
+    /// run where their backend puts them.
+    #[serde(default)]
+    pub device: Option<Device>,
+    #[serde(default)]
+    pub warmup: Vec<WarmupSample>,
+    /// Request schema versions clients may use, see `api::schema`.
+    #[serde(default)]
+    pub schema: Option<SchemaVersions>,
+    /// Versions receiving a copy of the model's traffic without answering clients.
+    #[serde(default)]
+    pub shadow_versions: Vec<String>,
+    /// Arbitrary labels matched by label selectors, see `model::labels`.
+    #[serde(default)]
+    pub labels: Labels,
+    /// What happens to a request arriving while the model's request buffer is full.
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+    /// Context length and generation limits of a language model, see `model::context_window`.
+    #[serde(default)]
+    pub context_window: Option<ContextWindow>,
+    /// Requests with the outputs the model must answer them with, see `model::selftest`.
+    #[serde(default)]
+    pub golden: Vec<GoldenCase>,
+    /// Watermarking of the generated text of outputs, see `model::watermark`.
+    #[serde(default)]
+    pub watermark: Option<WatermarkConfig>,
+    /// What the model supports, checked against requests, see `model::capabilities`.
+    #[serde(default)]
+    pub capabilities: Option<Capabilities>,
+    /// Limits of the images sent to a vision model, see `model::images`.
+    #[serde(default)]
+    pub images: Option<ImageLimits>,
+    /// Caching of the responses of a deterministic model, see `model::response_cache`.
+    #[serde(default)]
+    pub response_cache: Option<ResponseCacheConfig>,
+    /// Whether identical requests in flight run once, see `model::dedup`.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// Memory a loaded instance takes on its device, e.g. `4GiB`, counted against the
+    /// memory budget of the device (see `model::memory`). Estimated from the size of the
+    /// artifacts when absent.
+    #[serde(default, deserialize_with = "byte_size")]
+    pub memory: Option<u64>,
+    /// Keeps the versions of the model loaded under a memory budget: they load at startup
+    /// and are never unloaded to make room for others.
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// A byte size written as a number of bytes or with a unit, e.g. `512MiB`.
+fn byte_size<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ByteSize {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<ByteSize>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(ByteSize::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(ByteSize::Text(text)) => crate::traffic::parse_byte_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+fn default_one() -> u32 {
+    1
+}
+
+impl ModelConfig {
+    /// Reads the configuration file of `model_dir`, if it has one.
+    pub fn load(model_dir: &Path) -> Result<Option<Self>> {
+        if !model_dir.is_dir() {
+            return Ok(None);
+        }
+
+        let mut config = if let Some(path) = YAML_CONFIG_FILES
+            .iter()
+            .map(|file| model_dir.join(file))
+            .find(|path| path.is_file())
+        {
+            Self::from_yaml(&std::fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("Invalid model config {}: {}", path.display(), e))?
+        } else if model_dir.join(PBTXT_CONFIG_FILE).is_file() {
+            let path = model_dir.join(PBTXT_CONFIG_FILE);
+            Self::from_pbtxt(&std::fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("Invalid model config {}: {}", path.display(), e))?
+        } else {
+            return Ok(None);
+        };
+        if let Some(window) = config.context_window.as_mut() {
+            window.tokenizer.load(model_dir)?;
+        }
+
+        Ok(Some(config))
+    }
+
+    pub fn from_yaml(contents: &str) -> Result<Self> {
+        let mut config: Self = serde_yaml::from_str(contents)?;
+        if !config.instance_group.is_empty() {
+            config.instance_count = config.instance_group.iter().map(|group| group.count).sum();
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parses a Triton model configuration.
+    pub fn from_pbtxt(contents: &str) -> Result<Self> {
+        let value = pbtxt::parse(contents)?;
+
+        let instance_group: Vec<InstanceGroup> = pbtxt::as_list(value.get("instance_group"))
+            .into_iter()
+            .map(|group| InstanceGroup {
+                count: group.get("count").and_then(Value::as_u64).unwrap_or(1) as u32,
+                kind: group.get("kind").and_then(Value::as_str).map(String::from),
+            })
+            .collect();
+        let instance_count = if instance_group.is_empty() {
+            1
+        } else {
+            instance_group.iter().map(|group| group.count).sum()
+        };
+        // Every instance is placed on one device: the first GPU any group lists.
+        let device = pbtxt::as_list(value.get("instance_group"))
+            .into_iter()
+            .flat_map(|group| pbtxt::as_list(group.get("gpus")))
+            .find_map(Value::as_u64)
+            .map(|gpu| Device::Cuda(gpu as u32));
+
+        let warmup = pbtxt::as_list(value.get("model_warmup"))
+            .into_iter()
+            .map(|sample| {
+                let inputs = pbtxt::as_list(sample.get("inputs"))
+                    .into_iter()
+                    .map(|entry| {
+                        let input = entry.get("value").unwrap_or(&Value::Null);
+                        Ok(WarmupInput {
+                            name: string_field(entry, "key")?,
+                            datatype: triton_datatype(&string_field(input, "data_type")?),
+                            shape: dims(input)?,
+                            random: input
+                                .get("random_data")
+                                .and_then(Value::as_bool)
+                                .unwrap_or(false),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(WarmupSample {
+                    name: string_field(sample, "name")?,
+                    batch_size: sample
+                        .get("batch_size")
+                        .and_then(Value::as_u64)
+                        .unwrap_or(1) as u32,
+                    inputs,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let dynamic_batching = value
+            .get("dynamic_batching")
+            .map(|batching| DynamicBatching {
+                max_queue_delay_ms: batching
+                    .get("max_queue_delay_microseconds")
+                    .and_then(Value::as_f64)
+                    .unwrap_or(0.0)
+                    / 1000.0,
+                preferred_batch_sizes: pbtxt::as_list(batching.get("preferred_batch_size"))
+                    .into_iter()
+                    .filter_map(Value::as_u64)
+                    .map(|size| size as u32)
+                    .collect(),
+            });
+
+        let config = Self {
+            name: value.get("name").and_then(Value::as_str).map(String::from),
+            backend: value
+                .get("backend")
+                .or_else(|| value.get("platform"))
+                .and_then(Value::as_str)
+                .map(String::from),
+            max_batch_size: value
+                .get("max_batch_size")
+                .and_then(Value::as_u64)
+                .unwrap_or(0) as u32,
+            dynamic_batching,
+            inputs: tensor_specs(value.get("input"))?,
+            outputs: tensor_specs(value.get("output"))?,
+            instance_count,
+            instance_group,
+            device,
+            warmup,
+            schema: None,
+            shadow_versions: Vec::new(),
+            labels: Labels::new(),
+            overflow: OverflowPolicy::default(),
+            context_window: None,
+            golden: Vec::new(),
+            watermark: None,
+            capabilities: None,
+            images: None,
+            response_cache: None,
+            deduplicate: false,
+            memory: None,
+            pinned: false,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Shape of `tensor` as seen by clients, with a leading variable batch dimension
+    /// when batching is enabled.
+    pub fn client_shape(&self, tensor: &TensorSpec) -> Vec<i64> {
+        if self.max_batch_size > 0 {
+            std::iter::once(-1)
+                .chain(tensor.shape.iter().copied())
+                .collect()
+        } else {
+            tensor.shape.clone()
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        for tensor in self.inputs.iter().chain(&self.outputs) {
+            if tensor.name.is_empty() {
+                return Err(anyhow!("Tensor names must not be empty"));
+            }
+            check_datatype(&tensor.name, &tensor.datatype)?;
+        }
+        if let Some(schema) = &self.schema {
+            if schema.current.is_empty() {
+                return Err(anyhow!("schema.current must not be empty"));
+            }
+            for converter in &schema.converters {
+                if !schema.supports(&converter.from) || !schema.supports(&converter.to) {
+                    return Err(anyhow!(
+                        "Schema converter {} -> {} uses an unsupported version",
+                        converter.from,
+                        converter.to
+                    ));
+                }
+            }
+        }
+        for (key, value) in &self.labels {
+            check_label(key, false)?;
+            check_label(value, true)?;
+        }
+        if let Some(batching) = &self.dynamic_batching {
+            if self.max_batch_size == 0 {
+                return Err(anyhow!("dynamic_batching requires max_batch_size"));
+            }
+            if !batching.max_queue_delay_ms.is_finite() || batching.max_queue_delay_ms < 0.0 {
+                return Err(anyhow!("max_queue_delay_ms must be a non-negative number"));
+            }
+            if let Some(size) = batching
+                .preferred_batch_sizes
+                .iter()
+                .find(|size| **size == 0 || **size > self.max_batch_size)
+            {
+                return Err(anyhow!(
+                    "Preferred batch size {} must be between 1 and max_batch_size {}",
+                    size,
+                    self.max_batch_size
+                ));
+            }
+        }
+        if self.instance_count == 0 || self.instance_group.iter().any(|group| group.count == 0) {
+            return Err(anyhow!("instance_count must be at least 1"));
+        }
+        if self.overflow == (OverflowPolicy::BlockWithTimeout { timeout_ms: 0 }) {
+            return Err(anyhow!("overflow timeout_ms must be at least 1"));
+        }
+        for sample in &self.warmup {
+            if self.max_batch_size > 0 && sample.batch_size > self.max_batch_size {
+                return Err(anyhow!(
+                    "Warmup sample '{}' exceeds max_batch_size {}",
+                    sample.name,
+                    self.max_batch_size
+                ));
+            }
+            for input in &sample.inputs {
+                check_datatype(&input.name, &input.datatype)?;
+                if !self.inputs.is_empty() && !self.inputs.iter().any(|i| i.name == input.name) {
+                    return Err(anyhow!(
+                        "Warmup sample '{}' uses undeclared input '{}'",
+                        sample.name,
+                        input.name
+                    ));
+                }
+            }
+        }
+        if let Some(window) = &self.context_window {
+            if window.context_length == 0 {
+                return Err(anyhow!("context_length must be at least 1"));
+            }
+            if window
+                .max_tokens
+                .is_some_and(|max_tokens| max_tokens > window.context_length)
+            {
+                return Err(anyhow!("max_tokens must not exceed context_length"));
+            }
+        }
+        for (index, case) in self.golden.iter().enumerate() {
+            case.validate()?;
+            if self.golden[..index]
+                .iter()
+                .any(|other| other.name == case.name)
+            {
+                return Err(anyhow!("Golden case '{}' is declared twice", case.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_datatype(tensor: &str, datatype: &str) -> Result<()> {
+    if DATATYPES.contains(&datatype) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Tensor '{}' has unknown datatype '{}'",
+            tensor,
+            datatype
+        ))
+    }
+}
+
+/// Converts Triton's `TYPE_FP32` style names to KServe datatypes.
+fn triton_datatype(data_type: &str) -> String {
+    match data_type.strip_prefix("TYPE_").unwrap_or(data_type) {
+        "STRING" => "BYTES".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn string_field(value: &Value, field: &str) -> Result<String> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .map(String::from)
+        .ok_or_else(|| anyhow!("Missing string field '{}'", field))
+}
+
+fn dims(value: &Value) -> Result<Vec<i64>> {
+    pbtxt::as_list(value.get("dims"))
+        .into_iter()
+        .map(|dim| {
+            dim.as_i64()
+                .ok_or_else(|| anyhow!("Invalid dimension {}", dim))
+        })
+        .collect()
+}
+
+fn tensor_specs(value: Option<&Value>) -> Result<Vec<TensorSpec>> {
+    pbtxt::as_list(value)
+        .into_iter()
+        .map(|tensor| {
+            Ok(TensorSpec {
+                name: string_field(tensor, "name")?,
+                datatype: triton_datatype(&string_field(tensor, "data_type")?),
+                shape: dims(tensor)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_yaml() {
+        let config = ModelConfig::from_yaml(
+            r#"
+backend: onnx
+max_batch_size: 8
+labels: { task: sentiment, lang: en }
+overflow: { policy: block_with_timeout, timeout_ms: 50 }
+memory: 2GiB
+pinned: true
+inputs:
+  - { name: input, datatype: FP32, shape: [3, 224, 224] }
+outputs:
+  - { name: probabilities, datatype: FP32, shape: [1000] }
+warmup:
+  - name: zeros
+    inputs:
+      - { name: input, datatype: FP32, shape: [3, 224, 224] }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.backend.as_deref(), Some("onnx"));
+        assert_eq!(config.instance_count, 1);
+        assert_eq!(config.labels["task"], "sentiment");
+        assert_eq!(
+            config.overflow,
+            OverflowPolicy::BlockWithTimeout { timeout_ms: 50 }
+        );
+        assert_eq!(config.warmup[0].batch_size, 1);
+        assert_eq!(config.memory, Some(2 << 30));
+        assert!(config.pinned);
+        assert_eq!(
+            config.client_shape(&config.inputs[0]),
+            vec![-1, 3, 224, 224]
+        );
+    }
+
+    #[test]
+    fn test_from_pbtxt() {
+        let config = ModelConfig::from_pbtxt(
+            r#"
+name: "resnet"
+platform: "onnxruntime_onnx"
+max_batch_size: 4
+input [ { name: "input" data_type: TYPE_FP32 dims: [ 3, 224, 224 ] } ]
+output [ { name: "label" data_type: TYPE_STRING dims: [ 1 ] } ]
+instance_group [ { count: 2 kind: KIND_GPU gpus: [ 1 ] }, { count: 1 kind: KIND_CPU } ]
+dynamic_batching { max_queue_delay_microseconds: 500 preferred_batch_size: [ 2, 4 ] }
+model_warmup [
+  {
+    name: "random"
+    batch_size: 2
+    inputs {
+      key: "input"
+      value { data_type: TYPE_FP32 dims: [ 3, 224, 224 ] random_data: true }
+    }
+  }
+]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.name.as_deref(), Some("resnet"));
+        assert_eq!(config.backend.as_deref(), Some("onnxruntime_onnx"));
+        assert_eq!(config.outputs[0].datatype, "BYTES");
+        assert_eq!(config.instance_count, 3);
+        assert_eq!(config.instance_group[0].kind.as_deref(), Some("KIND_GPU"));
+        assert_eq!(config.device, Some(Device::Cuda(1)));
+        let yaml = ModelConfig::from_yaml("instance_group: [{ count: 2 }, { kind: KIND_CPU }]");
+        assert_eq!(yaml.unwrap().instance_count, 3);
+        let yaml = ModelConfig::from_yaml("device: cuda:0").unwrap();
+        assert_eq!(yaml.device, Some(Device::Cuda(0)));
+        assert!(ModelConfig::from_yaml("device: tpu:0").is_err());
+        assert_eq!(config.warmup[0].batch_size, 2);
+        assert!(config.warmup[0].inputs[0].random);
+        assert_eq!(
+            config.dynamic_batching,
+            Some(DynamicBatching {
+                max_queue_delay_ms: 0.5,
+                preferred_batch_sizes: vec![2, 4],
+            })
+        );
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(ModelConfig::from_yaml("inputs: [{ name: x, datatype: FLOAT }]").is_err());
+        assert!(ModelConfig::from_yaml("instance_count: 0").is_err());
+        assert!(ModelConfig::from_yaml("instance_group: [{ count: 0 }]").is_err());
+        assert!(ModelConfig::from_yaml("labels: { \"task type\": sentiment }").is_err());
+        assert!(ModelConfig::from_yaml("dynamic_batching: {}").is_err());
+        assert!(
+            ModelConfig::from_yaml("context_window: { context_length: 8, max_tokens: 16 }")
+                .is_err()
+        );
+        assert!(ModelConfig::from_yaml("overflow: { policy: drop_all }").is_err());
+        assert!(
+            ModelConfig::from_yaml("overflow: { policy: block_with_timeout, timeout_ms: 0 }")
+                .is_err()
+        );
+        assert!(
+            ModelConfig::from_yaml(
+                "max_batch_size: 4\ndynamic_batching: { preferred_batch_sizes: [8] }"
+            )
+            .is_err()
+        );
+        assert!(
+            ModelConfig::from_yaml(
+                "schema: { current: \"2\", converters: [{ from: \"1\", to: \"2\" }] }"
+            )
+            .is_err()
+        );
+        assert!(
+            ModelConfig::from_yaml(
+                "golden: [{ name: g, expected: { data: [1] } }, { name: g, expected: { data: [2] } }]"
+            )
+            .is_err()
+        );
+        assert!(
+            ModelConfig::from_yaml(
+                "inputs: [{ name: x, datatype: FP32 }]\nwarmup: [{ name: w, inputs: [{ name: y, datatype: FP32 }] }]"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_load_without_config() {
+        assert!(
+            ModelConfig::load(Path::new("/nonexistent"))
+                .unwrap()
+                .is_none()
+        );
+    }
+}
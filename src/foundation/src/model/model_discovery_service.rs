@@ -1,13 +1,75 @@
+use crate::log_info;
+use anyhow::{Result, anyhow};
 use dashmap::DashMap;
+use futures::FutureExt;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Write;
 use std::fs;
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinSet;
 
-use crate::api::inference::InferenceRequest;
-use crate::api::mlflow_client::{MLFlowClient, MLFlowClientTrait};
-use crate::model::circular_buffer::CircularBuffer;
+use crate::api::devices::{Device, DeviceScheduler, PlacedRuntime};
+use crate::api::inference::{
+    InferParameter, InferenceError, InferenceOutput, InferenceRequest, InferenceResponse,
+};
+use crate::api::inference_runtime::{InferenceRuntime, panic_message};
+use crate::api::instance_pool::{InstancePool, InstanceRestart, InstanceStatus};
+use crate::api::mlflow_client::{MLFlowArtifactStore, MLFlowClient, MLFlowClientTrait, MLModel};
+use crate::api::model_metadata::{ModelMetadata, ModelSignature, TensorMetadata};
+use crate::api::runtime_registry::RuntimeRegistry;
+use crate::api::schema::{SchemaPlan, SchemaRegistry};
+use crate::api::validation::TensorRefusal;
+use crate::deadline::{deadline_exceeded, is_expired};
+use crate::events::{EventBus, ServerEvent};
+use crate::ids::{IdProvider, IdScheme};
+use crate::metrics::MetricsRecorder;
+use crate::model::batching::{BatchPolicy, DynamicBatcher};
+use crate::model::buffer_tuning::{AdaptiveBuffer, BufferSizing, BufferStats};
+use crate::model::capabilities::{Capabilities, CapabilityRefusal};
+use crate::model::circuit::{
+    CircuitBreakers, CircuitPolicy, CircuitProbe, CircuitState, CircuitStats,
+};
+use crate::model::circular_buffer::OverflowPolicy;
+use crate::model::context_window::{ContextRefusal, Tokenizer};
+use crate::model::dedup::InFlightRequests;
+use crate::model::hints::{ExecutionTarget, HintPlan, HintPolicy, IgnoredHint};
+use crate::model::images::{ImageInfo, ImageRefusal};
+use crate::model::labels::{LabelSelector, Labels, check_label};
+use crate::model::layout::LegacyModel;
+use crate::model::memory::{ManagedRuntime, MemoryBudget, MemoryManager, artifact_size};
+use crate::model::model_config::ModelConfig;
+use crate::model::model_store::LocalModelStore;
+use crate::model::object_store::{
+    AzureBlobClient, GcsClient, ObjectStoreClient, S3Client, S3Credentials,
+};
+use crate::model::pause::{ModelPause, ModelPaused, Pauses};
+use crate::model::priority::{Prioritized, Priority, PriorityBuffer};
+use crate::model::response_cache::{ResponseCache, cache_key};
+use crate::model::selftest::{SelfTestReport, run_self_test};
+use crate::model::shadow::{ShadowStats, ShadowTraffic};
+use crate::model::watermark::Watermarking;
+use crate::readiness::Readiness;
+use crate::stats::{SCHEDULER_ROUTE, StatsRegistry, escape_label};
+use crate::tenants::ErrorBudget;
 
-#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+/// Default location of the local cache for artifacts pulled from object storage.
+const DEFAULT_MODEL_STORE_DIR: &str = "galemind-model-store";
+/// Directory of the local model store holding MLflow artifacts, as `<model>/<version>`.
+const MLFLOW_STORE_KEY: &str = "mlflow";
+
+/// How often `drain` checks for requests still buffered or running.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct ModelId(pub String);
 
 impl ModelId {
@@ -29,12 +91,177 @@ impl ModelId {
     pub fn from_url(url: &str) -> Option<Self> {
         // Extract model name from URL path
         url.split('/')
-            .last()
+            .next_back()
             .filter(|s| !s.is_empty())
             .map(|s| ModelId(s.to_string()))
     }
 }
 
+impl fmt::Display for ModelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A request that did not reach the buffer of its model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferRefusal {
+    /// No model of the name is registered.
+    NotFound { model: String },
+    /// The model was unloaded before its buffer took the request.
+    Unloaded { model: String },
+    /// The buffer was full and its `OverflowPolicy` turned the request away, after waiting
+    /// `waited` for room when it blocks.
+    Full {
+        model: String,
+        waited: Option<Duration>,
+    },
+}
+
+impl BufferRefusal {
+    pub fn model(&self) -> &str {
+        match self {
+            BufferRefusal::NotFound { model }
+            | BufferRefusal::Unloaded { model }
+            | BufferRefusal::Full { model, .. } => model,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            BufferRefusal::NotFound { .. } => "model_not_found",
+            BufferRefusal::Unloaded { .. } => "model_unloaded",
+            BufferRefusal::Full { .. } => "buffer_full",
+        }
+    }
+}
+
+impl fmt::Display for BufferRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferRefusal::NotFound { model } => write!(f, "Model '{}' not found", model),
+            BufferRefusal::Unloaded { model } => write!(f, "Model '{}' was unloaded", model),
+            BufferRefusal::Full {
+                model,
+                waited: None,
+            } => write!(f, "Request buffer of model '{}' is full", model),
+            BufferRefusal::Full {
+                model,
+                waited: Some(waited),
+            } => write!(
+                f,
+                "Request buffer of model '{}' stayed full for {} ms",
+                model,
+                waited.as_millis()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BufferRefusal {}
+
+/// A model of the catalog, as model listings show it.
+#[derive(Debug, Clone)]
+pub struct ModelSummary {
+    pub name: String,
+    /// Served versions, oldest first.
+    pub versions: Vec<String>,
+    /// Backend serving the model, when known.
+    pub backend: Option<String>,
+    /// Whether the version answering unversioned requests is loaded.
+    pub ready: bool,
+    pub labels: Labels,
+    pub registered: SystemTime,
+}
+
+/// Identifies a single version of a model, each backed by its own runtime.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct ModelVersionId {
+    pub model: ModelId,
+    pub version: String,
+}
+
+impl ModelVersionId {
+    pub fn new(model: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            model: ModelId(model.into()),
+            version: version.into(),
+        }
+    }
+}
+
+impl fmt::Display for ModelVersionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.model, self.version)
+    }
+}
+
+/// Orders versions numerically when both are integers, lexicographically otherwise.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// Decides which registered versions of a model are served.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum VersionPolicy {
+    /// Only the newest registered version is served.
+    #[default]
+    Latest,
+    /// Only the listed versions are served.
+    Specific(Vec<String>),
+    /// Every registered version is served.
+    All,
+}
+
+impl VersionPolicy {
+    /// Filters `versions` (sorted oldest first) down to the ones this policy serves.
+    pub fn select(&self, versions: Vec<String>) -> Vec<String> {
+        match self {
+            VersionPolicy::Latest => versions.into_iter().last().into_iter().collect(),
+            VersionPolicy::Specific(allowed) => versions
+                .into_iter()
+                .filter(|version| allowed.contains(version))
+                .collect(),
+            VersionPolicy::All => versions,
+        }
+    }
+}
+
+impl FromStr for VersionPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `latest`, `all` or `specific:<v1>,<v2>,...`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "latest" => Ok(VersionPolicy::Latest),
+            "all" => Ok(VersionPolicy::All),
+            _ => match s.strip_prefix("specific:") {
+                Some(list) => {
+                    let versions: Vec<String> = list
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(String::from)
+                        .collect();
+                    if versions.is_empty() {
+                        return Err(anyhow!(
+                            "Version policy 'specific' requires at least one version"
+                        ));
+                    }
+                    Ok(VersionPolicy::Specific(versions))
+                }
+                None => Err(anyhow!(
+                    "Unknown version policy '{}', expected latest, all or specific:<versions>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ModelSource {
     Path(PathBuf),
@@ -45,21 +272,857 @@ pub enum ModelSource {
         api_token: Option<String>,
         model_name: Option<String>, // If None, discover all models
     },
+    /// Every `<prefix>/<model>/` directory of an S3 (or S3-compatible) bucket.
+    S3 {
+        bucket: String,
+        prefix: String,
+        region: String,
+        endpoint: Option<String>, // Custom endpoint for S3-compatible stores such as MinIO
+        credentials: Option<S3Credentials>, // If None, the bucket is accessed anonymously
+    },
+    /// Every `<prefix>/<model>/` directory of a Google Cloud Storage bucket.
+    Gcs {
+        bucket: String,
+        prefix: String,
+        access_token: Option<String>,
+    },
+    /// Every `<prefix>/<model>/` directory of an Azure Blob Storage container.
+    Azure {
+        account: String,
+        container: String,
+        prefix: String,
+        sas_token: Option<String>,
+    },
+}
+
+impl ModelSource {
+    /// Parses `s3://bucket/prefix`, `gs://bucket/prefix`, `az://account/container/prefix`
+    /// or a local path. Object store credentials are left empty, see `with_env_credentials`.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let Some((scheme, rest)) = uri.split_once("://") else {
+            return Ok(ModelSource::Path(PathBuf::from(uri)));
+        };
+        let (location, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if location.is_empty() {
+            return Err(anyhow!("Model source '{}' is missing a bucket", uri));
+        }
+
+        match scheme {
+            "s3" => Ok(ModelSource::S3 {
+                bucket: location.to_string(),
+                prefix: prefix.to_string(),
+                region: "us-east-1".to_string(),
+                endpoint: None,
+                credentials: None,
+            }),
+            "gs" => Ok(ModelSource::Gcs {
+                bucket: location.to_string(),
+                prefix: prefix.to_string(),
+                access_token: None,
+            }),
+            "az" => {
+                let (container, prefix) = prefix.split_once('/').unwrap_or((prefix, ""));
+                if container.is_empty() {
+                    return Err(anyhow!("Model source '{}' is missing a container", uri));
+                }
+                Ok(ModelSource::Azure {
+                    account: location.to_string(),
+                    container: container.to_string(),
+                    prefix: prefix.to_string(),
+                    sas_token: None,
+                })
+            }
+            "http" | "https" => Ok(ModelSource::Url(uri.to_string())),
+            _ => Err(anyhow!("Unsupported model source scheme '{}'", scheme)),
+        }
+    }
+
+    /// Fills object store settings from the environment: `AWS_REGION`, `AWS_ENDPOINT_URL`
+    /// and AWS credentials for S3, `GCS_ACCESS_TOKEN` for GCS and `AZURE_STORAGE_SAS_TOKEN`
+    /// for Azure.
+    pub fn with_env_credentials(self) -> Self {
+        match self {
+            ModelSource::S3 {
+                bucket,
+                prefix,
+                region,
+                endpoint,
+                credentials,
+            } => ModelSource::S3 {
+                bucket,
+                prefix,
+                region: std::env::var("AWS_REGION").unwrap_or(region),
+                endpoint: endpoint.or_else(|| std::env::var("AWS_ENDPOINT_URL").ok()),
+                credentials: credentials.or_else(S3Credentials::from_env),
+            },
+            ModelSource::Gcs {
+                bucket,
+                prefix,
+                access_token,
+            } => ModelSource::Gcs {
+                bucket,
+                prefix,
+                access_token: access_token.or_else(|| std::env::var("GCS_ACCESS_TOKEN").ok()),
+            },
+            ModelSource::Azure {
+                account,
+                container,
+                prefix,
+                sas_token,
+            } => ModelSource::Azure {
+                account,
+                container,
+                prefix,
+                sas_token: sas_token.or_else(|| std::env::var("AZURE_STORAGE_SAS_TOKEN").ok()),
+            },
+            other => other,
+        }
+    }
+
+    /// Client, cache key and model prefix for object store sources.
+    pub(crate) fn object_store(&self) -> Option<(Box<dyn ObjectStoreClient>, String, String)> {
+        match self {
+            ModelSource::S3 {
+                bucket,
+                prefix,
+                region,
+                endpoint,
+                credentials,
+            } => Some((
+                Box::new(S3Client::new(
+                    bucket.clone(),
+                    region.clone(),
+                    endpoint.clone(),
+                    credentials.clone(),
+                )),
+                format!("s3/{}", bucket),
+                prefix.clone(),
+            )),
+            ModelSource::Gcs {
+                bucket,
+                prefix,
+                access_token,
+            } => Some((
+                Box::new(GcsClient::new(bucket.clone(), access_token.clone())),
+                format!("gcs/{}", bucket),
+                prefix.clone(),
+            )),
+            ModelSource::Azure {
+                account,
+                container,
+                prefix,
+                sas_token,
+            } => Some((
+                Box::new(AzureBlobClient::new(
+                    account.clone(),
+                    container.clone(),
+                    sas_token.clone(),
+                )),
+                format!("azure/{}/{}", account, container),
+                prefix.clone(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A buffered request together with the channel its response is sent on.
+pub struct PendingInferenceRequest {
+    pub request: InferenceRequest,
+    pub response_tx: oneshot::Sender<InferenceResponse>,
+    /// Key the response is cached under, for models caching their responses.
+    pub cache_key: Option<String>,
+    /// Set when the request probes the open circuit of its model.
+    pub circuit_probe: Option<CircuitProbe>,
+}
+
+impl fmt::Debug for PendingInferenceRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingInferenceRequest")
+            .field("model_name", &self.request.model_name)
+            .field("id", &self.request.id)
+            .field("priority", &self.request.priority)
+            .finish()
+    }
+}
+
+impl Prioritized for PendingInferenceRequest {
+    fn priority(&self) -> Priority {
+        self.request.priority
+    }
+}
+
+type RequestBuffer =
+    AdaptiveBuffer<PendingInferenceRequest, PriorityBuffer<PendingInferenceRequest>>;
+
+/// Scheduling state of a model's requests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchedulingStats {
+    pub model: String,
+    /// Requests waiting in the model's buffer.
+    pub queued: usize,
+    /// Requests dispatched and not yet answered.
+    pub in_flight: usize,
+    /// Requests the model runs at once, see `dispatch_slots`.
+    pub dispatch_slots: usize,
+    /// Whether the worker dispatching the buffer runs, which it does from the first request.
+    pub worker_started: bool,
+}
+
+/// Messages to the worker of a model, which owns the model's request buffer.
+enum QueueMessage {
+    /// Buffers a request, handing it back through `reply` if the buffer turns it away.
+    /// With `wait_until`, a request that does not fit waits for room until then.
+    Push {
+        pending: Box<PendingInferenceRequest>,
+        overflow: OverflowPolicy,
+        sizing: BufferSizing,
+        wait_until: Option<Instant>,
+        reply: oneshot::Sender<Result<(), PendingInferenceRequest>>,
+    },
+    ServiceTime(Duration, BufferSizing),
+    StarvationLimit(Duration),
+    /// Answers every buffered request with `error`, replying with their number.
+    Fail {
+        error: String,
+        reply: oneshot::Sender<usize>,
+    },
+    /// Answers every buffered request with `error` and stops the worker. Requests already
+    /// running complete.
+    Stop {
+        error: String,
+    },
+}
+
+/// A request waiting for room in a full buffer.
+struct BlockedPush {
+    pending: PendingInferenceRequest,
+    until: Instant,
+    reply: oneshot::Sender<Result<(), PendingInferenceRequest>>,
+}
+
+/// Buffer and running requests of a model, as last published by its worker.
+#[derive(Clone)]
+struct QueueState {
+    buffer: BufferStats,
+    in_flight: usize,
+}
+
+/// The request buffer of a model and the requests waiting for room in it, owned by the
+/// model's worker so that no lock is taken to buffer or dispatch a request.
+struct QueueWorker {
+    model: String,
+    buffer: RequestBuffer,
+    blocked: VecDeque<BlockedPush>,
+    in_flight: usize,
+    state: watch::Sender<QueueState>,
+}
+
+impl QueueWorker {
+    fn new(model_id: &ModelId, sizing: &BufferSizing) -> (Self, watch::Receiver<QueueState>) {
+        let mut buffer = RequestBuffer::new(sizing);
+        buffer.buffer_mut().set_max_wait(sizing.starvation_limit);
+        let (state, view) = watch::channel(QueueState {
+            buffer: buffer.stats(&model_id.0),
+            in_flight: 0,
+        });
+        let worker = Self {
+            model: model_id.0.clone(),
+            buffer,
+            blocked: VecDeque::new(),
+            in_flight: 0,
+            state,
+        };
+        (worker, view)
+    }
+
+    fn publish(&self) {
+        self.state.send_replace(QueueState {
+            buffer: self.buffer.stats(&self.model),
+            in_flight: self.in_flight,
+        });
+    }
+
+    /// Handles `message`, returning whether the worker keeps running.
+    fn handle(&mut self, message: QueueMessage) -> bool {
+        match message {
+            QueueMessage::Push {
+                pending,
+                overflow,
+                sizing,
+                wait_until,
+                reply,
+            } => {
+                // A request evicted to make room is dropped, closing its response channel.
+                let pushed = self
+                    .buffer
+                    .push(*pending, Instant::now(), &sizing, overflow)
+                    .map(|_| ());
+                match (pushed, wait_until) {
+                    (Err(pending), Some(until)) => self.blocked.push_back(BlockedPush {
+                        pending,
+                        until,
+                        reply,
+                    }),
+                    (pushed, _) => {
+                        self.publish();
+                        let _ = reply.send(pushed);
+                    }
+                }
+            }
+            QueueMessage::ServiceTime(service_time, sizing) => {
+                self.buffer.record_service_time(service_time, &sizing);
+                self.publish();
+            }
+            QueueMessage::StarvationLimit(limit) => self.buffer.buffer_mut().set_max_wait(limit),
+            QueueMessage::Fail { error, reply } => {
+                let dropped = self.fail_buffered(&error);
+                let _ = reply.send(dropped);
+            }
+            QueueMessage::Stop { error } => {
+                self.fail_buffered(&error);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Takes the next request to run, letting the oldest blocked request into the room it
+    /// leaves.
+    fn pop(&mut self) -> Option<PendingInferenceRequest> {
+        let next = self.buffer.buffer_mut().pop(Instant::now())?;
+        self.admit_blocked();
+        Some(next)
+    }
+
+    /// Buffers blocked requests, oldest first, for as long as they fit.
+    fn admit_blocked(&mut self) {
+        while let Some(blocked) = self.blocked.pop_front() {
+            // The caller gave up waiting.
+            if blocked.reply.is_closed() {
+                continue;
+            }
+            match self.buffer.buffer_mut().push_at(
+                blocked.pending,
+                Instant::now(),
+                OverflowPolicy::Reject,
+            ) {
+                Ok(_) => {
+                    let _ = blocked.reply.send(Ok(()));
+                }
+                Err(pending) => {
+                    self.blocked.push_front(BlockedPush { pending, ..blocked });
+                    break;
+                }
+            }
+        }
+    }
+
+    /// When the first blocked request stops waiting.
+    fn next_expiry(&self) -> Option<Instant> {
+        self.blocked.iter().map(|blocked| blocked.until).min()
+    }
+
+    /// Hands the blocked requests that waited until `now` back to their callers.
+    fn expire_blocked(&mut self, now: Instant) {
+        let (expired, blocked) = std::mem::take(&mut self.blocked)
+            .into_iter()
+            .partition(|blocked| blocked.until <= now);
+        self.blocked = blocked;
+        for BlockedPush { pending, reply, .. } in expired {
+            let _ = reply.send(Err(pending));
+        }
+    }
+
+    /// Answers every buffered and blocked request with `error`.
+    fn fail_buffered(&mut self, error: &str) -> usize {
+        let buffered: Vec<PendingInferenceRequest> =
+            std::iter::from_fn(|| self.buffer.buffer_mut().pop(Instant::now())).collect();
+        self.publish();
+        let dropped = buffered.len() + self.blocked.len();
+        let respond = |pending: PendingInferenceRequest| {
+            let _ = pending
+                .response_tx
+                .send(InferenceResponse::Error(InferenceError {
+                    error: error.to_string(),
+                }));
+        };
+        for pending in buffered {
+            respond(pending);
+        }
+        // Blocked requests are taken in, answered with the error.
+        for BlockedPush { pending, reply, .. } in self.blocked.drain(..) {
+            respond(pending);
+            let _ = reply.send(Ok(()));
+        }
+        dropped
+    }
+}
+
+/// Handle to the worker of a model, which runs the model's requests. The worker starts with
+/// the first request; until then, the messages it is sent are handled in place.
+struct ModelQueue {
+    messages: mpsc::UnboundedSender<QueueMessage>,
+    started: AtomicBool,
+    /// The worker and its mailbox, until the worker starts.
+    idle: Mutex<Option<(QueueWorker, mpsc::UnboundedReceiver<QueueMessage>)>>,
+    state: watch::Receiver<QueueState>,
+    /// When the model was registered.
+    registered: SystemTime,
+}
+
+impl ModelQueue {
+    fn new(model_id: &ModelId, sizing: &BufferSizing) -> Self {
+        let (worker, state) = QueueWorker::new(model_id, sizing);
+        let (messages, mailbox) = mpsc::unbounded_channel();
+        Self {
+            messages,
+            started: AtomicBool::new(false),
+            idle: Mutex::new(Some((worker, mailbox))),
+            state,
+            registered: SystemTime::now(),
+        }
+    }
+
+    fn send(&self, message: QueueMessage) {
+        if !self.started.load(AtomicOrdering::Acquire)
+            && let Some((worker, _)) = self.idle.lock().unwrap().as_mut()
+        {
+            worker.handle(message);
+            return;
+        }
+        // A worker that stopped drops the message, and with it any reply channel.
+        let _ = self.messages.send(message);
+    }
+
+    /// The worker and its mailbox, the first time it is asked for.
+    fn start(&self) -> Option<(QueueWorker, mpsc::UnboundedReceiver<QueueMessage>)> {
+        if self.started.load(AtomicOrdering::Acquire) {
+            return None;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        self.started.store(true, AtomicOrdering::Release);
+        idle.take()
+    }
+
+    fn state(&self) -> QueueState {
+        self.state.borrow().clone()
+    }
 }
 
 pub struct ModelDiscoveryService {
-    models: DashMap<ModelId, Mutex<CircularBuffer<InferenceRequest>>>,
-    models_buffer_capacity: usize,
+    models: DashMap<ModelId, Arc<ModelQueue>>,
+    buffer_sizing: RwLock<BufferSizing>,
+    runtimes: DashMap<ModelVersionId, Arc<dyn InferenceRuntime>>,
+    /// Instance pools of the versions loaded from artifacts, whose instances can be restarted.
+    pools: DashMap<ModelVersionId, Arc<InstancePool>>,
+    version_policies: DashMap<ModelId, VersionPolicy>,
+    default_version_policy: VersionPolicy,
+    model_paths: DashMap<ModelId, PathBuf>,
+    /// Directory the local models were loaded from, where `load_model` finds further ones.
+    models_dir: RwLock<Option<PathBuf>>,
+    model_configs: DashMap<ModelId, Arc<ModelConfig>>,
+    model_store: LocalModelStore,
+    schema_registry: Arc<SchemaRegistry>,
+    runtime_registry: Arc<RuntimeRegistry>,
+    shadow: ShadowTraffic,
+    batcher: DynamicBatcher,
+    /// While set, model versions are neither loaded, unloaded nor promoted.
+    read_only: AtomicBool,
+    labels: DashMap<ModelId, Labels>,
+    /// Rotates routing among the models matching a selector.
+    next_route: AtomicUsize,
+    error_budget: Arc<ErrorBudget>,
+    devices: Arc<DeviceScheduler>,
+    stats: Arc<StatsRegistry>,
+    metrics: Arc<MetricsRecorder>,
+    /// Execution hints of requests that are honored.
+    hint_policy: HintPolicy,
+    watermarking: Arc<Watermarking>,
+    readiness: Arc<Readiness>,
+    response_cache: Arc<ResponseCache>,
+    in_flight: InFlightRequests,
+    /// Budgets the memory of the versions loaded from artifacts, see `model::memory`.
+    memory: Arc<MemoryManager>,
+    events: Arc<EventBus>,
+    pauses: Pauses,
+    circuits: CircuitBreakers,
+}
+
+/// Where the artifacts of an MLflow model version are stored.
+enum ArtifactLocation {
+    Local(PathBuf),
+    Remote(Box<dyn ObjectStoreClient>, String),
+}
+
+impl ArtifactLocation {
+    /// Resolves an MLflow artifact URI: the artifact proxy (`mlflow-artifacts:/`), an object
+    /// store (`s3://`, `gs://`, `az://`) or a local path (`file://` or plain).
+    fn from_uri(client: &Arc<dyn MLFlowClientTrait>, uri: &str) -> Result<Self> {
+        if let Some(path) = uri.strip_prefix("mlflow-artifacts:") {
+            // `mlflow-artifacts://host/path` names the tracking server, which is `client` here.
+            let path = match path.strip_prefix("//") {
+                Some(authority_and_path) => authority_and_path
+                    .split_once('/')
+                    .map_or("", |(_, path)| path),
+                None => path,
+            };
+            return Ok(ArtifactLocation::Remote(
+                Box::new(MLFlowArtifactStore::new(client.clone())),
+                path.trim_start_matches('/').to_string(),
+            ));
+        }
+        if let Some(path) = uri.strip_prefix("file://") {
+            return Ok(ArtifactLocation::Local(PathBuf::from(path)));
+        }
+        // Schemes without an authority, such as `dbfs:/` or `runs:/`, are not downloadable here.
+        if !uri.contains("://")
+            && uri
+                .split_once(':')
+                .is_some_and(|(scheme, _)| !scheme.contains('/'))
+        {
+            return Err(anyhow!("Unsupported MLflow artifact location '{}'", uri));
+        }
+
+        match ModelSource::from_uri(uri)?.with_env_credentials() {
+            ModelSource::Path(path) => Ok(ArtifactLocation::Local(path)),
+            source => {
+                let (store, _, prefix) = source
+                    .object_store()
+                    .ok_or_else(|| anyhow!("Unsupported MLflow artifact location '{}'", uri))?;
+                Ok(ArtifactLocation::Remote(store, prefix))
+            }
+        }
+    }
 }
 
 impl ModelDiscoveryService {
     pub fn new(models_buffer_capacity: usize) -> Self {
+        let metrics = Arc::new(MetricsRecorder::default());
+        let events = Arc::new(EventBus::default());
+        events.subscribe(metrics.clone());
         Self {
             models: DashMap::new(),
-            models_buffer_capacity,
+            buffer_sizing: RwLock::new(BufferSizing::with_initial_capacity(models_buffer_capacity)),
+            runtimes: DashMap::new(),
+            pools: DashMap::new(),
+            version_policies: DashMap::new(),
+            default_version_policy: VersionPolicy::default(),
+            model_paths: DashMap::new(),
+            models_dir: RwLock::new(None),
+            model_configs: DashMap::new(),
+            model_store: LocalModelStore::new(std::env::temp_dir().join(DEFAULT_MODEL_STORE_DIR)),
+            runtime_registry: Arc::new(RuntimeRegistry::new()),
+            schema_registry: Arc::new(SchemaRegistry::new()),
+            shadow: ShadowTraffic::default(),
+            batcher: DynamicBatcher::new(IdScheme::default().provider())
+                .with_metrics(metrics.clone())
+                .with_events(events.clone()),
+            read_only: AtomicBool::new(false),
+            labels: DashMap::new(),
+            next_route: AtomicUsize::new(0),
+            error_budget: Arc::new(ErrorBudget::default()),
+            devices: Arc::new(DeviceScheduler::default()),
+            stats: Arc::new(StatsRegistry::default()),
+            metrics,
+            hint_policy: HintPolicy::default(),
+            watermarking: Arc::new(Watermarking::default()),
+            readiness: Arc::new(Readiness::default()),
+            response_cache: Arc::new(ResponseCache::default()),
+            in_flight: InFlightRequests::default(),
+            memory: Arc::new(MemoryManager::default()),
+            events,
+            pauses: Pauses::default(),
+            circuits: CircuitBreakers::default(),
+        }
+    }
+
+    /// Loads the versions of a device with a budget on demand within it, see `model::memory`.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory = Arc::new(MemoryManager::new(budget));
+        self
+    }
+
+    pub fn memory(&self) -> &Arc<MemoryManager> {
+        &self.memory
+    }
+
+    /// Sets the provider of batch ids.
+    pub fn with_id_provider(mut self, ids: Arc<dyn IdProvider>) -> Self {
+        self.batcher = DynamicBatcher::new(ids)
+            .with_metrics(self.metrics.clone())
+            .with_events(self.events.clone());
+        self
+    }
+
+    /// Bus the registry, the scheduler and the servers publish their events on, see
+    /// `crate::events`. It logs them and counts them in the metrics.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    /// Opens the circuit of models whose runtime keeps failing, see `model::circuit`.
+    pub fn with_circuit_policy(mut self, policy: Option<CircuitPolicy>) -> Self {
+        self.circuits = CircuitBreakers::new(policy);
+        self
+    }
+
+    /// The circuit of every model that ran requests.
+    pub fn circuits(&self) -> Vec<CircuitStats> {
+        self.circuits.stats()
+    }
+
+    /// Whether `model_id` serves `version` (its default version when None) and its circuit
+    /// is closed.
+    pub fn is_model_ready(&self, model_id: &ModelId, version: Option<&str>) -> bool {
+        matches!(self.resolve_version(model_id, version), Ok(Some(_)))
+            && self.circuits.is_closed(model_id)
+    }
+
+    /// Counts an outcome of `model_id` in its circuit, publishing the opening or closing it
+    /// causes.
+    fn record_circuit(&self, model_id: &ModelId, probe: Option<CircuitProbe>, failed: bool) {
+        let Some(transition) = self.circuits.record(model_id, probe, failed) else {
+            return;
+        };
+        self.events.publish(match transition.to {
+            CircuitState::Closed => ServerEvent::CircuitClosed {
+                model: transition.model,
+            },
+            CircuitState::Open | CircuitState::HalfOpen => ServerEvent::CircuitOpened {
+                model: transition.model,
+                requests: transition.requests,
+                errors: transition.errors,
+                error_rate: transition.error_rate,
+            },
+        });
+    }
+
+    /// Tracks the runtime failures of each tenant with `error_budget`.
+    pub fn with_error_budget(mut self, error_budget: Arc<ErrorBudget>) -> Self {
+        self.error_budget = error_budget;
+        self
+    }
+
+    pub fn error_budget(&self) -> &Arc<ErrorBudget> {
+        &self.error_budget
+    }
+
+    /// Shares the GPUs among the model versions pinned to them.
+    pub fn with_device_scheduler(mut self, devices: Arc<DeviceScheduler>) -> Self {
+        self.devices = devices;
+        self
+    }
+
+    pub fn devices(&self) -> &Arc<DeviceScheduler> {
+        &self.devices
+    }
+
+    /// Honors the execution hints `policy` allows, see `hints`.
+    pub fn with_hint_policy(mut self, policy: HintPolicy) -> Self {
+        self.hint_policy = policy;
+        self
+    }
+
+    pub fn hint_policy(&self) -> &HintPolicy {
+        &self.hint_policy
+    }
+
+    /// Watermarks the generated text of outputs with `watermarking`, see `watermark`.
+    pub fn with_watermarking(mut self, watermarking: Arc<Watermarking>) -> Self {
+        self.watermarking = watermarking;
+        self
+    }
+
+    pub fn watermarking(&self) -> &Arc<Watermarking> {
+        &self.watermarking
+    }
+
+    /// Keeps the responses of the models configuring `response_cache` in `cache`, such as
+    /// one backed by Redis, instead of a private in-memory one.
+    pub fn with_response_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = cache;
+        self
+    }
+
+    pub fn response_cache(&self) -> &Arc<ResponseCache> {
+        &self.response_cache
+    }
+
+    pub fn readiness(&self) -> &Arc<Readiness> {
+        &self.readiness
+    }
+
+    /// Why the server is not ready, as (condition, reason) pairs: the pending conditions
+    /// of `readiness` and the runtime backends named by model configs that are not
+    /// registered. Empty when ready.
+    pub fn readiness_failures(&self) -> Vec<(String, String)> {
+        let mut failures = self.readiness.pending();
+        let mut missing: Vec<(String, String)> = self
+            .model_configs
+            .iter()
+            .filter_map(|entry| {
+                let backend = entry.value().backend.as_deref()?;
+                self.runtime_registry.get(backend).is_none().then(|| {
+                    (
+                        "backend".to_string(),
+                        format!(
+                            "Runtime backend '{}' of model '{}' is not available",
+                            backend,
+                            entry.key()
+                        ),
+                    )
+                })
+            })
+            .collect();
+        missing.sort();
+        failures.extend(missing);
+        failures
+    }
+
+    /// Statistics of every route, shared by the servers and the scheduler.
+    pub fn with_stats(mut self, stats: Arc<StatsRegistry>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> &Arc<StatsRegistry> {
+        &self.stats
+    }
+
+    /// Request and batch histograms, recorded by the servers and the batcher.
+    pub fn metrics(&self) -> &Arc<MetricsRecorder> {
+        &self.metrics
+    }
+
+    /// Every metric of the models in the Prometheus text exposition format: the recorded
+    /// histograms, the depth, capacity and fill of each request buffer, and the route
+    /// statistics.
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        self.metrics.render(&mut out);
+        let buffers = self.buffer_stats();
+        // Name, help and value of each gauge.
+        type Gauge = (&'static str, &'static str, fn(&BufferStats) -> f64);
+        let gauges: [Gauge; 3] = [
+            (
+                "galemind_queue_depth",
+                "Requests waiting in the buffer of a model.",
+                |stats| stats.queued as f64,
+            ),
+            (
+                "galemind_buffer_capacity",
+                "Capacity of the request buffer of a model.",
+                |stats| stats.capacity as f64,
+            ),
+            (
+                "galemind_buffer_fill_percent",
+                "Share of the request buffer of a model in use, in percent.",
+                |stats| {
+                    if stats.capacity == 0 {
+                        0.0
+                    } else {
+                        stats.queued as f64 * 100.0 / stats.capacity as f64
+                    }
+                },
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+            for stats in &buffers {
+                let _ = writeln!(
+                    out,
+                    "{}{{model=\"{}\"}} {}",
+                    name,
+                    escape_label(&stats.model),
+                    value(stats)
+                );
+            }
+        }
+        self.devices.transfers().render(&mut out);
+        self.response_cache.render(&mut out);
+        self.in_flight.render(&mut out);
+        out.push_str(&self.stats.render_metrics());
+        out
+    }
+
+    /// Freezes or unfreezes the model registry. While read-only, versions are neither
+    /// deployed, retired nor promoted, but inference on the served ones continues.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, AtomicOrdering::Release);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(AtomicOrdering::Acquire)
+    }
+
+    fn ensure_writable(&self, action: &str) -> Result<()> {
+        if self.is_read_only() {
+            return Err(anyhow!(
+                "Model registry is read-only, refusing to {}",
+                action
+            ));
+        }
+        Ok(())
+    }
+
+    /// Limits the requests duplicated to each shadow version that may run at once.
+    pub fn with_shadow_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.shadow = ShadowTraffic::new(max_in_flight);
+        self
+    }
+
+    /// Sets the runtime backends used to load downloaded model artifacts.
+    pub fn with_runtime_registry(mut self, registry: Arc<RuntimeRegistry>) -> Self {
+        self.runtime_registry = registry;
+        self
+    }
+
+    pub fn runtime_registry(&self) -> &Arc<RuntimeRegistry> {
+        &self.runtime_registry
+    }
+
+    /// Sets the local directory caching artifacts pulled from object storage.
+    pub fn with_model_store<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.model_store = LocalModelStore::new(root);
+        self
+    }
+
+    /// Converters registered in code, used in addition to those declared in model configs.
+    pub fn schema_registry(&self) -> &Arc<SchemaRegistry> {
+        &self.schema_registry
+    }
+
+    /// Sets the bounds within which request buffers are sized from observed load.
+    pub fn with_buffer_sizing(self, sizing: BufferSizing) -> Self {
+        self.set_buffer_sizing(sizing);
+        self
+    }
+
+    pub fn buffer_sizing(&self) -> BufferSizing {
+        self.buffer_sizing.read().unwrap().clone()
+    }
+
+    /// Replaces the sizing bounds at runtime. Buffers are resized within the new bounds as
+    /// they observe load, and take the new starvation limit right away.
+    pub fn set_buffer_sizing(&self, sizing: BufferSizing) {
+        let starvation_limit = sizing.starvation_limit;
+        *self.buffer_sizing.write().unwrap() = sizing;
+        for queue in self.models.iter() {
+            queue.send(QueueMessage::StarvationLimit(starvation_limit));
         }
     }
 
+    pub fn model_store(&self) -> &LocalModelStore {
+        &self.model_store
+    }
+
+    /// Sets the version policy applied to models without a policy of their own.
+    pub fn with_version_policy(mut self, policy: VersionPolicy) -> Self {
+        self.default_version_policy = policy;
+        self
+    }
+
     pub async fn discover_models(
         &self,
         sources: Vec<ModelSource>,
@@ -70,9 +1133,7 @@ impl ModelDiscoveryService {
             match source {
                 ModelSource::Path(path) => {
                     if path.is_dir() {
-                        self.load_models_from_dir(&path)?;
-                        let models = self.discover_from_directory(&path)?;
-                        discovered_models.extend(models);
+                        discovered_models.extend(self.load_directory(&path)?);
                     } else if let Some(model_id) = ModelId::from_path(path) {
                         self.register_model(model_id.clone());
                         discovered_models.push(model_id);
@@ -94,9 +1155,15 @@ impl ModelDiscoveryService {
                     api_token,
                     model_name,
                 } => {
-                    let models = self
-                        .discover_from_mlflow(base_url, api_token, model_name)
-                        .await?;
+                    let client: Arc<dyn MLFlowClientTrait> =
+                        Arc::new(MLFlowClient::new(base_url, api_token));
+                    let models = self.discover_from_mlflow(client, model_name).await?;
+                    discovered_models.extend(models);
+                }
+                source @ (ModelSource::S3 { .. }
+                | ModelSource::Gcs { .. }
+                | ModelSource::Azure { .. }) => {
+                    let models = self.discover_from_object_store(&source).await?;
                     discovered_models.extend(models);
                 }
             }
@@ -105,182 +1172,2900 @@ impl ModelDiscoveryService {
         Ok(discovered_models)
     }
 
-    async fn discover_from_mlflow(
-        &self,
-        base_url: String,
-        api_token: Option<String>,
-        model_name: Option<String>,
-    ) -> Result<Vec<ModelId>, Box<dyn std::error::Error>> {
-        let client = MLFlowClient::new(base_url, api_token);
-        let mut discovered_models = Vec::new();
+    /// Downloads every model of an object store source into the local model store.
+    async fn discover_from_object_store(&self, source: &ModelSource) -> Result<Vec<ModelId>> {
+        let (client, source_key, prefix) = source
+            .object_store()
+            .ok_or_else(|| anyhow!("{:?} is not an object store source", source))?;
 
-        if let Some(specific_model) = model_name {
-            // Discover specific model
-            if let Some(model) = client.get_model(&specific_model).await? {
-                let model_id = ModelId::from_string(model.name);
-                self.register_model(model_id.clone());
-                discovered_models.push(model_id);
-            }
-        } else {
-            // Discover all models
-            let models = client.list_models().await?;
-            for model in models {
-                let model_id = ModelId::from_string(model.name);
-                self.register_model(model_id.clone());
-                discovered_models.push(model_id);
+        let mut discovered_models = Vec::new();
+        for (model_name, model_dir) in self
+            .model_store
+            .sync_source(client.as_ref(), &source_key, &prefix)
+            .await?
+        {
+            let model_id = ModelId::from_string(model_name);
+            match self.register_model_path(model_id.clone(), model_dir) {
+                Ok(()) => discovered_models.push(model_id),
+                Err(e) => eprintln!("Skipping model {}: {}", model_id, e),
             }
         }
 
         Ok(discovered_models)
     }
 
-    fn discover_from_directory(&self, models_dir: &Path) -> std::io::Result<Vec<ModelId>> {
-        let mut models = Vec::new();
-        let model_entries = fs::read_dir(models_dir)?;
+    /// Pulls a single model from an object store source on demand and registers it.
+    pub async fn fetch_model(&self, source: &ModelSource, model_name: &str) -> Result<ModelId> {
+        let (client, source_key, prefix) = source
+            .object_store()
+            .ok_or_else(|| anyhow!("{:?} is not an object store source", source))?;
 
-        for model_entry in model_entries {
-            let model_entry = model_entry?;
-            if model_entry.file_type()?.is_dir() {
-                if let Some(model_id) = ModelId::from_path(model_entry.path()) {
-                    models.push(model_id);
+        let model_dir = self
+            .model_store
+            .sync_model(client.as_ref(), &source_key, &prefix, model_name)
+            .await?;
+        let model_id = ModelId::from_string(model_name.to_string());
+        self.register_model_path(model_id.clone(), model_dir)?;
+        Ok(model_id)
+    }
+
+    /// Registers MLflow models and deploys the READY versions selected by their version policy.
+    /// Versions that fail to download or load are reported and skipped.
+    pub async fn discover_from_mlflow(
+        &self,
+        client: Arc<dyn MLFlowClientTrait>,
+        model_name: Option<String>,
+    ) -> Result<Vec<ModelId>> {
+        let models = match model_name {
+            Some(specific_model) => client
+                .get_model(&specific_model)
+                .await?
+                .into_iter()
+                .collect(),
+            None => client.list_models().await?,
+        };
+
+        let mut discovered_models = Vec::new();
+        for model in models {
+            let model_id = ModelId::from_string(model.name);
+            self.register_model(model_id.clone());
+            // Labels from the model config take precedence over registry tags.
+            self.add_labels(model_id.clone(), labels_from_tags(model.tags), false);
+
+            let mut ready_versions: Vec<String> = client
+                .get_model_versions(&model_id.0)
+                .await?
+                .into_iter()
+                .filter(|version| version.status.as_deref().is_none_or(|s| s == "READY"))
+                .map(|version| version.version)
+                .collect();
+            ready_versions.sort_by(|a, b| compare_versions(a, b));
+
+            for version in self.version_policy(&model_id).select(ready_versions) {
+                let version_id = ModelVersionId::new(model_id.0.clone(), version);
+                if let Err(e) = self.deploy_mlflow_version(&client, &version_id).await {
+                    eprintln!("Failed to deploy MLflow model {}: {}", version_id, e);
                 }
             }
+            discovered_models.push(model_id);
         }
 
-        Ok(models)
+        Ok(discovered_models)
     }
 
-    pub fn load_models_from_dir<P: AsRef<Path>>(&self, models_dir: P) -> std::io::Result<()> {
+    /// Downloads the artifacts of an MLflow model version and loads them with the
+    /// runtime backend matching their MLmodel flavors.
+    pub(crate) async fn deploy_mlflow_version(
+        &self,
+        client: &Arc<dyn MLFlowClientTrait>,
+        version_id: &ModelVersionId,
+    ) -> Result<()> {
+        self.ensure_writable(&format!("deploy {}", version_id))?;
+        let artifact_uri = client
+            .get_download_uri(&version_id.model.0, &version_id.version)
+            .await?;
+
+        let artifact_dir = match ArtifactLocation::from_uri(client, &artifact_uri)? {
+            ArtifactLocation::Local(path) => path,
+            ArtifactLocation::Remote(store, prefix) => {
+                let artifact_dir = self
+                    .model_store
+                    .model_dir(MLFLOW_STORE_KEY, &version_id.model.0)
+                    .join(&version_id.version);
+                self.model_store
+                    .sync_prefix(store.as_ref(), &prefix, &artifact_dir)
+                    .await?;
+                artifact_dir
+            }
+        };
+
+        let flavors = MLModel::from_dir(&artifact_dir)?.flavors();
+        self.load_version(version_id, &flavors, &artifact_dir)?;
+        self.self_test_deployed(version_id).await;
+        Ok(())
+    }
+
+    /// Reports the outcome of the golden cases of a freshly deployed version, if it has any.
+    async fn self_test_deployed(&self, version_id: &ModelVersionId) {
+        if self
+            .get_model_config(&version_id.model)
+            .is_none_or(|config| config.golden.is_empty())
+        {
+            return;
+        }
+        match self
+            .self_test(&version_id.model, Some(&version_id.version))
+            .await
+        {
+            Ok(report) if report.passed => log_info!(
+                "Self-test of {} passed {} golden cases",
+                version_id,
+                report.cases.len()
+            ),
+            Ok(report) => {
+                for case in report.failed_cases() {
+                    let differences: Vec<String> = case
+                        .differences
+                        .iter()
+                        .map(|d| format!("{}: expected {}, got {}", d.field, d.expected, d.actual))
+                        .collect();
+                    eprintln!(
+                        "Self-test of {} failed golden case '{}': {}",
+                        version_id,
+                        case.name,
+                        differences.join("; ")
+                    );
+                }
+            }
+            Err(e) => eprintln!("Failed to self-test {}: {}", version_id, e),
+        }
+    }
+
+    /// Registers every model directory of `models_dir`, in the native layout or in the
+    /// Triton or MLServer one (see `layout`).
+    pub fn load_models_from_dir<P: AsRef<Path>>(&self, models_dir: P) -> std::io::Result<()> {
+        *self.models_dir.write().unwrap() = Some(models_dir.as_ref().to_path_buf());
+        self.load_directory(models_dir.as_ref()).map(|_| ())
+    }
+
+    /// Loads the model directories of `models_dir`, returning the models registered.
+    fn load_directory(&self, models_dir: &Path) -> std::io::Result<Vec<ModelId>> {
+        let mut models = Vec::new();
         let model_entries = fs::read_dir(models_dir)?;
 
-        for model_entry in model_entries {
-            let model_entry = model_entry?;
-            if model_entry.file_type()?.is_dir() {
-                if let Some(model_id) = ModelId::from_path(model_entry.path()) {
-                    self.register_model(model_id);
+        for model_entry in model_entries {
+            let model_entry = model_entry?;
+            if !model_entry.file_type()?.is_dir() {
+                continue;
+            }
+            let path = model_entry.path();
+            match self.load_model_dir(&path) {
+                Some(Ok(model_id)) => models.push(model_id),
+                Some(Err(e)) => eprintln!("Skipping model directory {}: {:#}", path.display(), e),
+                None => continue,
+            }
+        }
+
+        Ok(models)
+    }
+
+    /// Registers the model directory at `path`, or returns `None` if it holds no model.
+    fn load_model_dir(&self, path: &Path) -> Option<Result<ModelId>> {
+        match LegacyModel::detect(path) {
+            Ok(Some(model)) => Some(self.load_legacy_model(model)),
+            Ok(None) => ModelId::from_path(path.to_path_buf()).map(|model_id| {
+                self.register_model_path(model_id.clone(), path.to_path_buf())
+                    .map(|()| model_id)
+            }),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Loads the model directory `name` of the models directory, registering the model or
+    /// reloading it if it is registered already.
+    pub fn load_model(&self, name: &str) -> Result<ModelId> {
+        self.ensure_writable(&format!("load model {}", name))?;
+        if name.is_empty() || Path::new(name).file_name() != Some(name.as_ref()) {
+            return Err(anyhow!("Invalid model directory name '{}'", name));
+        }
+        let models_dir = self
+            .models_dir
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("No models directory to load model {} from", name))?;
+        let path = models_dir.join(name);
+        if !path.is_dir() {
+            return Err(anyhow!("Model directory {} does not exist", path.display()));
+        }
+        let model_id = self
+            .load_model_dir(&path)
+            .ok_or_else(|| anyhow!("{} is not a model directory", path.display()))??;
+        self.events.publish(ServerEvent::ModelLoaded {
+            model: model_id.0.clone(),
+            path: path.display().to_string(),
+        });
+        Ok(model_id)
+    }
+
+    /// Stops serving a model: retires its versions, fails the requests waiting in its
+    /// buffer and forgets its configuration. Requests already running complete. Returns
+    /// the number of versions retired.
+    pub fn unload_model(&self, model_id: &ModelId) -> Result<usize> {
+        self.ensure_writable(&format!("unload model {}", model_id))?;
+        let Some((_, queue)) = self.models.remove(model_id) else {
+            return Err(anyhow!("Model {} is not registered", model_id));
+        };
+        queue.send(QueueMessage::Stop {
+            error: format!("Model {} was unloaded", model_id),
+        });
+
+        let versions: Vec<ModelVersionId> = self
+            .runtimes
+            .iter()
+            .filter(|entry| &entry.key().model == model_id)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for version_id in &versions {
+            self.unregister_model_version(version_id);
+        }
+        self.shadow.set_versions(model_id.clone(), Vec::new());
+        self.labels.remove(model_id);
+        self.circuits.remove(model_id);
+        self.model_paths.remove(model_id);
+        self.model_configs.remove(model_id);
+        self.events.publish(ServerEvent::ModelUnloaded {
+            model: model_id.0.clone(),
+            versions: versions.len(),
+        });
+        Ok(versions.len())
+    }
+
+    /// Registers a model directory in the layout of another server and loads the versions
+    /// selected by its version policy. Versions that fail to load are reported and skipped.
+    fn load_legacy_model(&self, model: LegacyModel) -> Result<ModelId> {
+        let model_id = ModelId::from_string(model.name.clone());
+        self.register_model_path(model_id.clone(), model.dir.clone())?;
+
+        let selected = self.version_policy(&model_id).select(
+            model
+                .versions
+                .iter()
+                .map(|version| version.version.clone())
+                .collect(),
+        );
+        for version in model
+            .versions
+            .iter()
+            .filter(|version| selected.contains(&version.version))
+        {
+            let version_id = ModelVersionId::new(model_id.0.clone(), version.version.clone());
+            if let Err(e) = self.load_version(&version_id, &version.backends, &version.dir) {
+                eprintln!(
+                    "Failed to load {} model {}: {}",
+                    model.layout, version_id, e
+                );
+            }
+        }
+        log_info!(
+            "Registered {} model directory {} as {} (versions: {})",
+            model.layout,
+            model.dir.display(),
+            model_id,
+            selected.join(", ")
+        );
+        Ok(model_id)
+    }
+
+    /// Loads `version_id` from `artifact_dir` with the first available of `backends`, with
+    /// the instances and onto the device of its model's configuration. Under a memory
+    /// budget for that device, the version is registered unloaded and loads on demand,
+    /// or now when its model is pinned.
+    fn load_version(
+        &self,
+        version_id: &ModelVersionId,
+        backends: &[String],
+        artifact_dir: &Path,
+    ) -> Result<()> {
+        let config = self.get_model_config(&version_id.model);
+        let instances = config
+            .as_ref()
+            .map_or(1, |config| config.instance_count as usize);
+        let device = config
+            .as_ref()
+            .and_then(|config| config.device)
+            .unwrap_or_default();
+        if !self.memory.manages(device) {
+            let pool = self.runtime_registry.load_instances(
+                backends,
+                version_id,
+                artifact_dir,
+                instances,
+                device,
+            )?;
+            self.register_instance_pool(version_id.clone(), pool);
+            return Ok(());
+        }
+
+        let (backend, loader) = self.runtime_registry.deferred_instances(
+            backends,
+            version_id,
+            artifact_dir,
+            instances,
+            device,
+        )?;
+        let bytes = config
+            .as_ref()
+            .and_then(|config| config.memory)
+            .unwrap_or_else(|| artifact_size(artifact_dir))
+            .saturating_mul(instances as u64);
+        let pinned = config.as_ref().is_some_and(|config| config.pinned);
+        let runtime = ManagedRuntime::new(
+            version_id.clone(),
+            device,
+            bytes,
+            pinned,
+            backend,
+            loader,
+            self.memory.clone(),
+        );
+        if pinned && let Err(e) = runtime.load_blocking() {
+            self.memory.remove(version_id);
+            return Err(e);
+        }
+        self.register_model_version(version_id.clone(), runtime);
+        Ok(())
+    }
+
+    pub fn register_model(&self, model_id: ModelId) {
+        let sizing = self.buffer_sizing();
+        self.models
+            .entry(model_id.clone())
+            .or_insert_with(|| Arc::new(ModelQueue::new(&model_id, &sizing)));
+    }
+
+    /// Registers a model whose artifacts live in the local directory `path`, together with
+    /// its `model.yaml` or `config.pbtxt` if present. Fails on an invalid configuration.
+    pub fn register_model_path(&self, model_id: ModelId, path: PathBuf) -> Result<()> {
+        match ModelConfig::load(&path)? {
+            Some(config) => self.set_model_config(model_id.clone(), config),
+            None => {
+                self.model_configs.remove(&model_id);
+            }
+        }
+        self.register_model(model_id.clone());
+        self.model_paths.insert(model_id, path);
+        Ok(())
+    }
+
+    /// Sets the configuration of a model, including its shadow versions and labels.
+    pub fn set_model_config(&self, model_id: ModelId, config: ModelConfig) {
+        self.shadow
+            .set_versions(model_id.clone(), config.shadow_versions.clone());
+        self.add_labels(model_id.clone(), config.labels.clone(), true);
+        self.model_configs.insert(model_id, Arc::new(config));
+    }
+
+    /// Reads the configurations of the models loaded from local directories again, applying
+    /// those that changed to the following requests: batching, overflow, shadow versions and
+    /// labels. The device and instances of a version change when it is next loaded. Returns
+    /// the models whose configuration changed; none does when a configuration is invalid.
+    pub fn reload_model_configs(&self) -> Result<Vec<ModelId>> {
+        let paths: Vec<(ModelId, PathBuf)> = self
+            .model_paths
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        let mut changed = Vec::new();
+        for (model_id, path) in paths {
+            let config = ModelConfig::load(&path)?;
+            if config.as_ref() != self.get_model_config(&model_id).as_deref() {
+                changed.push((model_id, config));
+            }
+        }
+        changed.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        Ok(changed
+            .into_iter()
+            .map(|(model_id, config)| {
+                match config {
+                    Some(config) => self.set_model_config(model_id.clone(), config),
+                    None => {
+                        self.model_configs.remove(&model_id);
+                    }
+                }
+                model_id
+            })
+            .collect())
+    }
+
+    pub fn get_model_config(&self, model_id: &ModelId) -> Option<Arc<ModelConfig>> {
+        self.model_configs
+            .get(model_id)
+            .map(|config| config.clone())
+    }
+
+    /// Local directory holding the artifacts of a model, if known.
+    pub fn get_model_path(&self, model_id: &ModelId) -> Option<PathBuf> {
+        self.model_paths.get(model_id).map(|path| path.clone())
+    }
+
+    /// Registers `runtime` as the artifact serving `version_id`, replacing any previous one.
+    /// Versions of a model pinned to a GPU share that device's slots with the other models
+    /// placed on it.
+    pub fn register_model_version(
+        &self,
+        version_id: ModelVersionId,
+        runtime: Arc<dyn InferenceRuntime>,
+    ) {
+        self.register_model(version_id.model.clone());
+        let device = self
+            .get_model_config(&version_id.model)
+            .and_then(|config| config.device)
+            .filter(Device::is_gpu);
+        let runtime: Arc<dyn InferenceRuntime> = match device {
+            Some(device) => {
+                self.devices.place(&version_id, device);
+                Arc::new(PlacedRuntime::new(runtime, device, self.devices.clone()))
+            }
+            None => {
+                self.devices.remove(&version_id);
+                runtime
+            }
+        };
+        self.pools.remove(&version_id);
+        self.events.publish(ServerEvent::VersionLoaded {
+            model: version_id.model.0.clone(),
+            version: version_id.version.clone(),
+        });
+        self.runtimes.insert(version_id, runtime);
+    }
+
+    /// Registers `pool` as the runtime serving `version_id`, like `register_model_version`,
+    /// keeping it at hand to restart its instances.
+    pub fn register_instance_pool(&self, version_id: ModelVersionId, pool: Arc<InstancePool>) {
+        self.register_model_version(version_id.clone(), pool.clone());
+        self.pools.insert(version_id, pool);
+    }
+
+    /// State of every instance of `version_id`, if it was loaded into an instance pool.
+    pub fn instances(&self, version_id: &ModelVersionId) -> Option<Vec<InstanceStatus>> {
+        self.pools.get(version_id).map(|pool| pool.status())
+    }
+
+    /// Restarts instance `index` of `version_id` without touching its other instances nor
+    /// other models, see `InstancePool::restart`. Refused while the registry is read-only.
+    pub async fn restart_instance(
+        &self,
+        version_id: &ModelVersionId,
+        index: usize,
+        drain_timeout: Duration,
+    ) -> Result<InstanceRestart> {
+        self.ensure_writable(&format!("restart instance {} of {}", index, version_id))?;
+        let pool = self
+            .pools
+            .get(version_id)
+            .map(|pool| pool.clone())
+            .ok_or_else(|| anyhow!("Model {} has no restartable instances", version_id))?;
+        let restart = pool.restart(index, drain_timeout).await;
+        match &restart {
+            Ok(restart) => log_info!(
+                "Restarted instance {} of {} in {:.0} ms{}",
+                index,
+                version_id,
+                restart.duration_ms,
+                if restart.drained {
+                    ""
+                } else {
+                    " (calls still running were left on the previous runtime)"
+                }
+            ),
+            Err(e) => eprintln!(
+                "Failed to restart instance {} of {}: {}",
+                index, version_id, e
+            ),
+        }
+        restart
+    }
+
+    /// Stops serving `version_id`, returning whether it was removed. Versions are kept
+    /// while the registry is read-only.
+    pub fn unregister_model_version(&self, version_id: &ModelVersionId) -> bool {
+        if let Err(e) = self.ensure_writable(&format!("retire {}", version_id)) {
+            eprintln!("{}", e);
+            return false;
+        }
+        self.batcher.remove(version_id);
+        self.devices.remove(version_id);
+        self.pools.remove(version_id);
+        self.memory.remove(version_id);
+        self.stats
+            .remove_version(&version_id.model.0, &version_id.version);
+        self.metrics
+            .remove_version(&version_id.model.0, &version_id.version);
+        let removed = self.runtimes.remove(version_id).is_some();
+        if removed {
+            self.events.publish(ServerEvent::VersionUnloaded {
+                model: version_id.model.0.clone(),
+                version: version_id.version.clone(),
+            });
+        }
+        removed
+    }
+
+    /// Stops serving every version and drops its runtime, whether or not the registry is
+    /// read-only. Used at shutdown; returns the number of versions unloaded.
+    pub fn unload_all(&self) -> usize {
+        let versions: Vec<ModelVersionId> = self
+            .runtimes
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for version_id in &versions {
+            self.batcher.remove(version_id);
+            self.devices.remove(version_id);
+            self.pools.remove(version_id);
+            self.memory.remove(version_id);
+            self.runtimes.remove(version_id);
+        }
+        versions.len()
+    }
+
+    /// Marks `versions` of a model as shadow: they receive a copy of every request for
+    /// the model in the background but never answer clients unless requested explicitly.
+    pub fn set_shadow_versions(&self, model_id: ModelId, versions: Vec<String>) -> Result<()> {
+        self.ensure_writable(&format!("change the shadow versions of {}", model_id))?;
+        self.shadow.set_versions(model_id, versions);
+        Ok(())
+    }
+
+    pub fn shadow_versions(&self, model_id: &ModelId) -> Vec<String> {
+        self.shadow.versions(model_id)
+    }
+
+    /// Outcome and latency of the traffic duplicated to every shadow version.
+    pub fn shadow_stats(&self) -> Vec<ShadowStats> {
+        self.shadow.stats()
+    }
+
+    /// Adds `labels` to a model. Labels it already has are kept unless `replace` is set.
+    pub fn add_labels(&self, model_id: ModelId, labels: Labels, replace: bool) {
+        let mut current = self.labels.entry(model_id).or_default();
+        for (key, value) in labels {
+            if replace || !current.contains_key(&key) {
+                current.insert(key, value);
+            }
+        }
+    }
+
+    pub fn labels(&self, model_id: &ModelId) -> Labels {
+        self.labels
+            .get(model_id)
+            .map(|labels| labels.clone())
+            .unwrap_or_default()
+    }
+
+    /// Registered models whose labels match `selector`, sorted by name.
+    pub fn select_models(&self, selector: &LabelSelector) -> Vec<ModelId> {
+        let mut models: Vec<ModelId> = self
+            .get_models()
+            .into_iter()
+            .filter(|model_id| selector.matches(&self.labels(model_id)))
+            .collect();
+        models.sort();
+        models
+    }
+
+    /// Routes a request to one of the models matching `selector` that serve a version,
+    /// rotating among them.
+    pub fn route(&self, selector: &LabelSelector) -> Option<ModelId> {
+        let candidates: Vec<ModelId> = self
+            .select_models(selector)
+            .into_iter()
+            .filter(|model_id| !self.served_versions(model_id).is_empty())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let next = self.next_route.fetch_add(1, AtomicOrdering::Relaxed);
+        Some(candidates[next % candidates.len()].clone())
+    }
+
+    pub fn set_version_policy(&self, model_id: ModelId, policy: VersionPolicy) {
+        self.version_policies.insert(model_id, policy);
+    }
+
+    pub fn version_policy(&self, model_id: &ModelId) -> VersionPolicy {
+        self.version_policies
+            .get(model_id)
+            .map(|policy| policy.clone())
+            .unwrap_or_else(|| self.default_version_policy.clone())
+    }
+
+    /// All registered versions of a model, oldest first.
+    pub fn get_model_versions(&self, model_id: &ModelId) -> Vec<String> {
+        let mut versions: Vec<String> = self
+            .runtimes
+            .iter()
+            .filter(|entry| &entry.key().model == model_id)
+            .map(|entry| entry.key().version.clone())
+            .collect();
+        versions.sort_by(|a, b| compare_versions(a, b));
+        versions
+    }
+
+    /// Registered versions of a model that are served, oldest first: the versions selected
+    /// by its version policy among the primary ones, plus its shadow versions.
+    pub fn served_versions(&self, model_id: &ModelId) -> Vec<String> {
+        let (shadow, primary): (Vec<String>, Vec<String>) = self
+            .get_model_versions(model_id)
+            .into_iter()
+            .partition(|version| self.shadow.is_shadow(model_id, version));
+        let mut served = self.version_policy(model_id).select(primary);
+        served.extend(shadow);
+        served.sort_by(|a, b| compare_versions(a, b));
+        served
+    }
+
+    /// Resolves which version serves a request for `model_id`.
+    ///
+    /// Without a requested version the newest served version that is not a shadow
+    /// is chosen. Models registered without any versions resolve to `None` and are
+    /// served unversioned.
+    pub fn resolve_version(
+        &self,
+        model_id: &ModelId,
+        requested: Option<&str>,
+    ) -> Result<Option<ModelVersionId>> {
+        if !self.models.contains_key(model_id) {
+            return Err(anyhow!("Model '{}' not found", model_id));
+        }
+
+        let registered = self.get_model_versions(model_id);
+        if registered.is_empty() {
+            return match requested {
+                None => Ok(None),
+                Some(version) => Err(anyhow!(
+                    "Version '{}' of model '{}' not found",
+                    version,
+                    model_id
+                )),
+            };
+        }
+
+        let served = self.served_versions(model_id);
+        let version = match requested {
+            Some(version) => served
+                .into_iter()
+                .find(|served| served == version)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Version '{}' of model '{}' is not served",
+                        version,
+                        model_id
+                    )
+                })?,
+            None => served
+                .into_iter()
+                .rfind(|version| !self.shadow.is_shadow(model_id, version))
+                .ok_or_else(|| anyhow!("Model '{}' has no servable versions", model_id))?,
+        };
+
+        Ok(Some(ModelVersionId {
+            model: model_id.clone(),
+            version,
+        }))
+    }
+
+    /// Negotiates the request schema version of a model. Models without declared
+    /// schema versions ignore the requested version and convert nothing.
+    pub fn negotiate_schema(
+        &self,
+        model_id: &ModelId,
+        requested: Option<&str>,
+    ) -> Result<SchemaPlan> {
+        match self
+            .get_model_config(model_id)
+            .and_then(|config| config.schema.clone())
+        {
+            Some(versions) => self.schema_registry.plan(&model_id.0, &versions, requested),
+            None => Ok(SchemaPlan::default()),
+        }
+    }
+
+    /// Metadata of the version serving `requested` (or the newest served version).
+    ///
+    /// Tensors declared in the model configuration take precedence over the
+    /// signature reported by the runtime.
+    pub fn model_metadata(
+        &self,
+        model_id: &ModelId,
+        requested: Option<&str>,
+    ) -> Result<ModelMetadata> {
+        let runtime = self
+            .resolve_version(model_id, requested)?
+            .and_then(|version_id| self.get_runtime(&version_id));
+        let config = self.get_model_config(model_id);
+
+        let signature = Self::signature(config.as_deref(), runtime.as_deref());
+        let platform = Self::backend(config.as_deref(), runtime.as_deref()).unwrap_or_default();
+
+        Ok(ModelMetadata {
+            name: model_id.0.clone(),
+            versions: self.served_versions(model_id),
+            platform,
+            inputs: signature.inputs,
+            outputs: signature.outputs,
+            capabilities: config.and_then(|config| config.capabilities.clone()),
+        })
+    }
+
+    /// Backend declared in the model configuration, else the platform of the runtime.
+    fn backend(
+        config: Option<&ModelConfig>,
+        runtime: Option<&dyn InferenceRuntime>,
+    ) -> Option<String> {
+        config
+            .and_then(|config| config.backend.clone())
+            .or_else(|| runtime.and_then(|runtime| runtime.platform().map(String::from)))
+    }
+
+    /// The model as listings show it, None when it is not registered.
+    pub fn model_summary(&self, model_id: &ModelId) -> Option<ModelSummary> {
+        let registered = self.models.get(model_id)?.registered;
+        let runtime = self
+            .resolve_version(model_id, None)
+            .ok()
+            .flatten()
+            .and_then(|version_id| self.get_runtime(&version_id));
+        let config = self.get_model_config(model_id);
+        Some(ModelSummary {
+            name: model_id.0.clone(),
+            versions: self.served_versions(model_id),
+            backend: Self::backend(config.as_deref(), runtime.as_deref()),
+            ready: runtime.is_some(),
+            labels: self.labels(model_id),
+            registered,
+        })
+    }
+
+    /// Tensors declared in the model configuration, else those reported by the runtime.
+    fn signature(
+        config: Option<&ModelConfig>,
+        runtime: Option<&dyn InferenceRuntime>,
+    ) -> ModelSignature {
+        config
+            .map(ModelSignature::from_config)
+            .filter(|signature| !signature.is_empty())
+            .or_else(|| runtime.and_then(|runtime| runtime.signature()))
+            .unwrap_or_default()
+    }
+
+    /// Refuses a request to `model_name` whose input tensors do not match the signature of
+    /// the version serving `requested`, see `api::validation`.
+    pub fn check_inputs(
+        &self,
+        model_name: &str,
+        requested: Option<&str>,
+        inputs: &[TensorMetadata],
+    ) -> Result<(), TensorRefusal> {
+        let model_id = ModelId(model_name.to_string());
+        let runtime = self
+            .resolve_version(&model_id, requested)
+            .ok()
+            .flatten()
+            .and_then(|version_id| self.get_runtime(&version_id));
+        let config = self.get_model_config(&model_id);
+        Self::signature(config.as_deref(), runtime.as_deref()).check_inputs(model_name, inputs)
+    }
+
+    /// Refuses a request whose prompt and `max_tokens` do not fit the context window of its
+    /// model, see `model::context_window`. Models without a window accept every request.
+    pub fn check_context(&self, request: &InferenceRequest) -> Result<(), ContextRefusal> {
+        match self
+            .get_model_config(&ModelId(request.model_name.clone()))
+            .and_then(|config| config.context_window.clone())
+        {
+            Some(window) => window.check(request.parameters.as_ref()),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuses a request to `model_name` relying on capabilities the model does not declare:
+    /// those it `requested` and those its `parameters` imply, see `model::capabilities`.
+    /// Models without declared capabilities accept every request.
+    pub fn check_capabilities(
+        &self,
+        model_name: &str,
+        parameters: Option<&HashMap<String, InferParameter>>,
+        requested: Capabilities,
+    ) -> Result<(), CapabilityRefusal> {
+        match self
+            .get_model_config(&ModelId(model_name.to_string()))
+            .and_then(|config| config.capabilities.clone())
+        {
+            Some(capabilities) => {
+                capabilities.check(model_name, &requested.with_parameters(parameters))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Identifies an image sent to `model_name` and checks it against the model's limits,
+    /// the default ones when it sets none, see `model::images`.
+    pub fn check_image(&self, model_name: &str, bytes: &[u8]) -> Result<ImageInfo, ImageRefusal> {
+        self.get_model_config(&ModelId(model_name.to_string()))
+            .and_then(|config| config.images.clone())
+            .unwrap_or_default()
+            .check(bytes)
+    }
+
+    /// Tokenizer of the context window of `model_name`, `words` without one.
+    pub fn tokenizer(&self, model_name: &str) -> Tokenizer {
+        self.get_model_config(&ModelId(model_name.to_string()))
+            .and_then(|config| {
+                config
+                    .context_window
+                    .as_ref()
+                    .map(|window| window.tokenizer.clone())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Tokens a request to `model_name` with `parameters` is charged against quotas,
+    /// counted with the tokenizer of the model.
+    pub fn request_tokens(
+        &self,
+        model_name: &str,
+        parameters: Option<&HashMap<String, InferParameter>>,
+    ) -> u64 {
+        self.tokenizer(model_name).request_tokens(parameters)
+    }
+
+    /// Runs the golden cases of a model on the live runtime of `requested`, any registered
+    /// version, or of the newest served version. See `model::selftest`.
+    pub async fn self_test(
+        &self,
+        model_id: &ModelId,
+        requested: Option<&str>,
+    ) -> Result<SelfTestReport> {
+        let version_id = match requested {
+            Some(version) if self.has_model(model_id) => {
+                ModelVersionId::new(model_id.0.clone(), version)
+            }
+            _ => self
+                .resolve_version(model_id, requested)?
+                .ok_or_else(|| anyhow!("Model '{}' has no loaded versions", model_id))?,
+        };
+        let runtime = self.get_runtime(&version_id).ok_or_else(|| {
+            anyhow!(
+                "Version '{}' of model '{}' not found",
+                version_id.version,
+                model_id
+            )
+        })?;
+        let cases = self
+            .get_model_config(model_id)
+            .map(|config| config.golden.clone())
+            .unwrap_or_default();
+        if cases.is_empty() {
+            return Err(anyhow!("Model '{}' has no golden cases", model_id));
+        }
+        Ok(run_self_test(&version_id, runtime.as_ref(), &cases).await)
+    }
+
+    pub fn get_runtime(&self, version_id: &ModelVersionId) -> Option<Arc<dyn InferenceRuntime>> {
+        self.runtimes
+            .get(version_id)
+            .map(|runtime| runtime.value().clone())
+    }
+
+    /// Runs a request on the version serving it and duplicates it to the model's shadow
+    /// versions. Models configured with dynamic batching run it as part of a batch.
+    pub async fn infer(&self, request: InferenceRequest) -> Result<InferenceResponse> {
+        self.infer_probing(request, None).await
+    }
+
+    /// `infer`, for a request its model's circuit admitted as `circuit_probe`.
+    async fn infer_probing(
+        &self,
+        mut request: InferenceRequest,
+        circuit_probe: Option<CircuitProbe>,
+    ) -> Result<InferenceResponse> {
+        let started = Instant::now();
+        let deadline = request.deadline;
+        if is_expired(deadline, started) {
+            return Ok(deadline_exceeded(&request.id, "queued"));
+        }
+        let model_id = ModelId(request.model_name.clone());
+        let version_id = self
+            .resolve_version(&model_id, request.model_version.as_deref())?
+            .ok_or_else(|| anyhow!("Model '{}' has no loaded versions", model_id))?;
+        let runtime = self
+            .get_runtime(&version_id)
+            .ok_or_else(|| anyhow!("Model {} is not loaded", version_id))?;
+        request.model_version = Some(version_id.version.clone());
+        let timeline = request.timeline.clone();
+        if let Some(timeline) = &timeline {
+            timeline.span("resolve_version", started, Some(version_id.to_string()));
+        }
+        self.mirror_to_shadows(&model_id, &request);
+
+        let hints = self.hint_plan(&version_id, &request);
+        let policy = self
+            .get_model_config(&model_id)
+            .and_then(|config| BatchPolicy::from_config(&config))
+            .filter(|_| !hints.unbatched);
+        let id = request.id.clone();
+        let tenant = request.tenant.clone();
+        let execution = async {
+            match policy {
+                Some(policy) => {
+                    self.batcher
+                        .submit(&version_id, runtime, &policy, request)
+                        .await
+                }
+                None => {
+                    let runtime_started = Instant::now();
+                    let call = async {
+                        match hints.instance {
+                            Some(index) => {
+                                self.process_on_instance(&version_id, runtime, index, request)
+                                    .await
+                            }
+                            None => runtime.process_single(request).await,
+                        }
+                    };
+                    let response =
+                        AssertUnwindSafe(call)
+                            .catch_unwind()
+                            .await
+                            .unwrap_or_else(|panic| {
+                                InferenceResponse::Error(InferenceError {
+                                    error: format!(
+                                        "Runtime of {} panicked: {}",
+                                        version_id,
+                                        panic_message(panic.as_ref())
+                                    ),
+                                })
+                            });
+                    if let Some(timeline) = &timeline {
+                        timeline.span("runtime.process_single", runtime_started, None);
+                    }
+                    Ok(response)
+                }
+            }
+        };
+        // Past the deadline the runtime call is dropped, cancelling asynchronous runtimes.
+        let response = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline.into(), execution).await {
+                Ok(response) => response?,
+                Err(_) => {
+                    self.record_stats(&version_id, started.elapsed(), false);
+                    self.record_circuit(&model_id, circuit_probe, true);
+                    return Ok(deadline_exceeded(&id, "running"));
+                }
+            },
+            None => execution.await?,
+        };
+        let response = self.watermark(&model_id, tenant.as_deref(), response);
+        self.record_service_time(&model_id, started.elapsed());
+        self.record_stats(
+            &version_id,
+            started.elapsed(),
+            matches!(response, InferenceResponse::Ok(_)),
+        );
+        self.error_budget.record(
+            tenant.as_deref(),
+            matches!(response, InferenceResponse::Error(_)),
+        );
+        self.record_circuit(
+            &model_id,
+            circuit_probe,
+            matches!(response, InferenceResponse::Error(_)),
+        );
+        Ok(response)
+    }
+
+    /// Watermarks the output of `model_id` if the model or `tenant` asks for it. Outputs
+    /// that cannot be watermarked are replaced with an error.
+    fn watermark(
+        &self,
+        model_id: &ModelId,
+        tenant: Option<&str>,
+        response: InferenceResponse,
+    ) -> InferenceResponse {
+        let InferenceResponse::Ok(mut output) = response else {
+            return response;
+        };
+        let config = self.get_model_config(model_id);
+        let Some(settings) = self.watermarking.settings(
+            config.as_ref().and_then(|config| config.watermark.as_ref()),
+            tenant,
+        ) else {
+            return InferenceResponse::Ok(output);
+        };
+        match self.watermarking.apply(&settings, &mut output) {
+            Ok(()) => InferenceResponse::Ok(output),
+            Err(e) => InferenceResponse::Error(InferenceError {
+                error: format!("Output of model '{}': {}", model_id, e),
+            }),
+        }
+    }
+
+    /// Plans the execution hints of `request` for `version_id`.
+    fn hint_plan(&self, version_id: &ModelVersionId, request: &InferenceRequest) -> HintPlan {
+        let config = self.get_model_config(&version_id.model);
+        let target = ExecutionTarget {
+            batched: config
+                .as_ref()
+                .and_then(|config| BatchPolicy::from_config(config))
+                .is_some(),
+            instances: self.pools.get(version_id).map_or(1, |pool| pool.len()),
+            device: config.and_then(|config| config.device).unwrap_or_default(),
+        };
+        HintPlan::new(request.parameters.as_ref(), &self.hint_policy, target)
+    }
+
+    /// Execution hints of `request` that `infer` does not honor, with the reason, for the
+    /// servers to report in their response.
+    pub fn ignored_hints(&self, request: &InferenceRequest) -> Vec<IgnoredHint> {
+        match self.resolve_version(
+            &ModelId(request.model_name.clone()),
+            request.model_version.as_deref(),
+        ) {
+            Ok(Some(version_id)) => self.hint_plan(&version_id, request).ignored,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Runs `request` alone on instance `index` of `version_id`, in a slot of its GPU if it
+    /// is pinned to one. Versions not loaded into a pool have a single instance.
+    async fn process_on_instance(
+        &self,
+        version_id: &ModelVersionId,
+        runtime: Arc<dyn InferenceRuntime>,
+        index: usize,
+        request: InferenceRequest,
+    ) -> InferenceResponse {
+        let Some(pool) = self.pools.get(version_id).map(|pool| pool.clone()) else {
+            return runtime.process_single(request).await;
+        };
+        let device = self
+            .get_model_config(&version_id.model)
+            .and_then(|config| config.device)
+            .filter(Device::is_gpu);
+        let _lease = match device {
+            Some(device) => Some(self.devices.acquire(device).await),
+            None => None,
+        };
+        pool.process_on(index, request).await
+    }
+
+    fn record_stats(&self, version_id: &ModelVersionId, latency: Duration, ok: bool) {
+        self.stats.record(
+            &version_id.model.0,
+            &version_id.version,
+            SCHEDULER_ROUTE,
+            latency,
+            ok,
+        );
+    }
+
+    /// Enqueues a request in its model's buffer, to be run by `infer` in priority order. The
+    /// returned channel receives the response, or closes if the request is evicted from a
+    /// full buffer or dropped by the model's `OverflowPolicy`. Fails with `BufferRefusal` for
+    /// unknown or unloaded models and when the policy turns the request away, with
+    /// `ModelPaused` while the model is paused and with `CircuitOpen` while its circuit is.
+    /// Must be called within a Tokio runtime.
+    pub async fn add_request(
+        self: &Arc<Self>,
+        model_id: ModelId,
+        req: InferenceRequest,
+    ) -> Result<oneshot::Receiver<InferenceResponse>> {
+        if !self.models.contains_key(&model_id) {
+            return Err(BufferRefusal::NotFound { model: model_id.0 }.into());
+        }
+        self.pauses.check(&model_id)?;
+        let circuit_probe = self.circuits.admit(&model_id)?;
+        if let Some(timeline) = &req.timeline {
+            timeline.mark(
+                "queue.enter",
+                Some(format!("request buffer of {}", model_id)),
+            );
+        }
+
+        let config = self.get_model_config(&model_id);
+        let key = config
+            .as_ref()
+            .filter(|config| config.response_cache.is_some() || config.deduplicate)
+            .and_then(|config| self.request_key(&model_id, config, &req));
+        let cache_key = key
+            .clone()
+            .filter(|_| config.as_ref().is_some_and(|c| c.response_cache.is_some()));
+        if let Some(key) = &cache_key
+            && let Some(output) = self.response_cache.get(&model_id, key).await
+        {
+            if let Some(timeline) = &req.timeline {
+                timeline.mark("cache.hit", None);
+            }
+            let (response_tx, response_rx) = oneshot::channel();
+            let _ = response_tx.send(InferenceResponse::Ok(output));
+            return Ok(response_rx);
+        }
+
+        let sizing = self.buffer_sizing();
+        let queue = self
+            .models
+            .entry(model_id.clone())
+            .or_insert_with(|| Arc::new(ModelQueue::new(&model_id, &sizing)))
+            .clone();
+        let overflow = config
+            .as_ref()
+            .map(|config| config.overflow)
+            .unwrap_or_default();
+        let (response_tx, response_rx) = oneshot::channel();
+        let dedup_key = key.filter(|_| config.is_some_and(|config| config.deduplicate));
+        let response_tx = match dedup_key {
+            Some(key) => match self.in_flight.coalesce(&model_id, key, response_tx) {
+                Some(response_tx) => response_tx,
+                None => {
+                    if let Some(timeline) = &req.timeline {
+                        timeline.mark("dedup.joined", None);
+                    }
+                    return Ok(response_rx);
+                }
+            },
+            None => response_tx,
+        };
+        let pending = PendingInferenceRequest {
+            request: req,
+            response_tx,
+            cache_key,
+            circuit_probe,
+        };
+        if let Some((worker, mailbox)) = queue.start() {
+            tokio::spawn(Self::run_queue(
+                Arc::downgrade(self),
+                model_id.clone(),
+                worker,
+                mailbox,
+            ));
+        }
+        let wait_until = match overflow {
+            OverflowPolicy::BlockWithTimeout { timeout_ms } => {
+                let waited = Instant::now() + Duration::from_millis(timeout_ms);
+                Some(
+                    pending
+                        .request
+                        .deadline
+                        .map_or(waited, |deadline| deadline.min(waited)),
+                )
+            }
+            _ => None,
+        };
+        let (reply, pushed) = oneshot::channel();
+        queue.send(QueueMessage::Push {
+            pending: Box::new(pending),
+            overflow,
+            sizing,
+            wait_until,
+            reply,
+        });
+        let Ok(pushed) = pushed.await else {
+            return Err(BufferRefusal::Unloaded { model: model_id.0 }.into());
+        };
+        if let Err(pending) = pushed {
+            let rejected = |waited: Option<Duration>| {
+                let refusal = BufferRefusal::Full {
+                    model: model_id.0.clone(),
+                    waited,
+                };
+                self.events.publish(ServerEvent::RequestRejected {
+                    model: model_id.0.clone(),
+                    reason: refusal.to_string(),
+                });
+                anyhow::Error::from(refusal)
+            };
+            match overflow {
+                OverflowPolicy::Reject => return Err(rejected(None)),
+                OverflowPolicy::BlockWithTimeout { timeout_ms } => {
+                    if !is_expired(pending.request.deadline, Instant::now()) {
+                        return Err(rejected(Some(Duration::from_millis(timeout_ms))));
+                    }
+                    let id = pending.request.id.clone();
+                    let _ = pending.response_tx.send(deadline_exceeded(&id, "queued"));
+                }
+                // Dropping the request closes its response channel.
+                OverflowPolicy::DropNewest | OverflowPolicy::DropOldest => {}
+            }
+        }
+        Ok(response_rx)
+    }
+
+    /// Requests of `model_id` run at once: one per instance, or one batch per instance.
+    /// Further requests wait in the model's buffer.
+    fn dispatch_slots(&self, model_id: &ModelId) -> usize {
+        self.model_configs.get(model_id).map_or(1, |config| {
+            config.instance_count.max(1) as usize * config.max_batch_size.max(1) as usize
+        })
+    }
+
+    /// Worker of a model: buffers the requests it is sent and runs them, highest priority
+    /// first and no more than the model's dispatch slots at once, until the model is
+    /// unloaded or the service dropped. This worker is the only place buffered requests
+    /// run, each exactly once; models with dynamic batching hand them to the batcher, which
+    /// flushes a partial batch after `max_queue_delay`.
+    async fn run_queue(
+        service: Weak<Self>,
+        model_id: ModelId,
+        mut worker: QueueWorker,
+        mut mailbox: mpsc::UnboundedReceiver<QueueMessage>,
+    ) {
+        let mut running = JoinSet::new();
+        loop {
+            let Some(service) = service.upgrade() else {
+                break;
+            };
+            let slots = service.dispatch_slots(&model_id);
+            while running.len() < slots {
+                let Some(PendingInferenceRequest {
+                    request,
+                    response_tx,
+                    cache_key,
+                    circuit_probe,
+                }) = worker.pop()
+                else {
+                    break;
+                };
+                // Callers that gave up, or whose deadline passed, do not get their request run.
+                if response_tx.is_closed() {
+                    continue;
+                }
+                if is_expired(request.deadline, Instant::now()) {
+                    let _ = response_tx.send(deadline_exceeded(&request.id, "queued"));
+                    continue;
+                }
+                if let Some(timeline) = &request.timeline {
+                    timeline.mark("queue.dispatch", Some(request.priority.to_string()));
+                }
+                let service = service.clone();
+                let model_id = model_id.clone();
+                running.spawn(async move {
+                    let response = service
+                        .infer_probing(request, circuit_probe)
+                        .await
+                        .unwrap_or_else(|e| {
+                            InferenceResponse::Error(InferenceError {
+                                error: e.to_string(),
+                            })
+                        });
+                    // Cached before it is sent, so a request repeated right away finds it.
+                    if let (Some(key), InferenceResponse::Ok(output)) = (&cache_key, &response) {
+                        service.cache_response(&model_id, key, output.clone()).await;
+                    }
+                    // The caller may have given up waiting.
+                    let _ = response_tx.send(response);
+                });
+            }
+            // Not held while waiting, so that the service can be dropped.
+            drop(service);
+            worker.in_flight = running.len();
+            worker.publish();
+
+            let expiry = worker.next_expiry();
+            tokio::select! {
+                message = mailbox.recv() => match message {
+                    Some(message) => {
+                        if !worker.handle(message) {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+                Some(_) = running.join_next(), if !running.is_empty() => {}
+                _ = tokio::time::sleep_until(expiry.unwrap_or_else(Instant::now).into()),
+                    if expiry.is_some() => worker.expire_blocked(Instant::now()),
+            }
+        }
+        // Requests already running complete.
+        running.detach_all();
+    }
+
+    /// Key identifying `request` for the response cache and deduplication. Responses
+    /// watermarked for the tenant of the request are particular to it.
+    fn request_key(
+        &self,
+        model_id: &ModelId,
+        config: &ModelConfig,
+        request: &InferenceRequest,
+    ) -> Option<String> {
+        let version_id = self
+            .resolve_version(model_id, request.model_version.as_deref())
+            .ok()??;
+        let tenant = request.tenant.as_deref();
+        let watermarked = self
+            .watermarking
+            .settings(config.watermark.as_ref(), tenant)
+            .is_some();
+        Some(cache_key(
+            &version_id,
+            request,
+            tenant.filter(|_| watermarked),
+        ))
+    }
+
+    /// Caches `output` as the response of `key`, if `model_id` still caches its responses.
+    async fn cache_response(&self, model_id: &ModelId, key: &str, output: InferenceOutput) {
+        let Some(config) = self
+            .get_model_config(model_id)
+            .and_then(|config| config.response_cache.clone())
+        else {
+            return;
+        };
+        self.response_cache
+            .put(model_id, &config, key, output)
+            .await;
+    }
+
+    fn mirror_to_shadows(&self, model_id: &ModelId, req: &InferenceRequest) {
+        // Requests addressed to a shadow version explicitly are not duplicated.
+        if req
+            .model_version
+            .as_deref()
+            .is_some_and(|version| self.shadow.is_shadow(model_id, version))
+        {
+            return;
+        }
+        for version in self.shadow.versions(model_id) {
+            let version_id = ModelVersionId {
+                model: model_id.clone(),
+                version,
+            };
+            if let Some(runtime) = self.get_runtime(&version_id) {
+                self.shadow.mirror(version_id, runtime, req.clone());
+            }
+        }
+    }
+
+    /// Reports how long serving a request of `model_id` took, feeding buffer sizing.
+    pub fn record_service_time(&self, model_id: &ModelId, service_time: Duration) {
+        if let Some(queue) = self.models.get(model_id) {
+            queue.send(QueueMessage::ServiceTime(
+                service_time,
+                self.buffer_sizing(),
+            ));
+        }
+    }
+
+    /// Requests waiting in the model buffers or running.
+    pub fn pending_requests(&self) -> usize {
+        self.models
+            .iter()
+            .map(|queue| {
+                let state = queue.state();
+                state.buffer.queued + state.in_flight
+            })
+            .sum()
+    }
+
+    /// Waits until no request is buffered or running, or until `deadline`. Returns whether
+    /// every request finished.
+    pub async fn drain(&self, deadline: Instant) -> bool {
+        loop {
+            if self.pending_requests() == 0 {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    /// Refuses new requests for `model_id` until it is resumed, telling clients to retry
+    /// after `retry_after`. Requests already buffered or running still run.
+    pub fn pause_model(&self, model_id: &ModelId, retry_after: Duration) -> Result<ModelPause> {
+        if !self.has_model(model_id) {
+            return Err(anyhow!("Model {} is not registered", model_id));
+        }
+        let pause = self.pauses.pause(model_id, retry_after);
+        self.events.publish(ServerEvent::ModelPaused {
+            model: model_id.0.clone(),
+            retry_after_secs: pause.retry_after_secs,
+        });
+        Ok(pause)
+    }
+
+    /// Admits requests for `model_id` again, returning its pause if it was paused.
+    pub fn resume_model(&self, model_id: &ModelId) -> Option<ModelPause> {
+        let pause = self.pauses.resume(model_id)?;
+        self.events.publish(ServerEvent::ModelResumed {
+            model: model_id.0.clone(),
+        });
+        Some(pause)
+    }
+
+    /// Refuses requests for `model_id` while it is paused.
+    pub fn check_paused(&self, model_id: &ModelId) -> Result<(), ModelPaused> {
+        self.pauses.check(model_id)
+    }
+
+    pub fn paused_models(&self) -> Vec<ModelPause> {
+        self.pauses.list()
+    }
+
+    /// Requests buffered and running for `model_id`.
+    pub fn model_pending_requests(&self, model_id: &ModelId) -> usize {
+        self.models.get(model_id).map_or(0, |queue| {
+            let state = queue.state();
+            state.buffer.queued + state.in_flight
+        })
+    }
+
+    /// Waits until no request of `model_id` is buffered or running, or until `deadline`.
+    /// Returns whether every request finished. Pause the model first, or new requests may
+    /// keep it busy.
+    pub async fn drain_model(&self, model_id: &ModelId, deadline: Instant) -> bool {
+        loop {
+            if self.model_pending_requests(model_id) == 0 {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    /// Answers every request buffered for `model_id`, or for every model, with an error
+    /// instead of running it. Requests already running complete. Returns the number of
+    /// requests dropped.
+    pub async fn drain_buffers(&self, model_id: Option<&ModelId>) -> usize {
+        let drained: Vec<oneshot::Receiver<usize>> = self
+            .models
+            .iter()
+            .filter(|entry| model_id.is_none_or(|model_id| entry.key() == model_id))
+            .map(|entry| {
+                let (reply, dropped) = oneshot::channel();
+                entry.value().send(QueueMessage::Fail {
+                    error: format!("Request buffer of model '{}' was drained", entry.key()),
+                    reply,
+                });
+                dropped
+            })
+            .collect();
+        let mut dropped = 0;
+        for drained in drained {
+            dropped += drained.await.unwrap_or(0);
+        }
+        dropped
+    }
+
+    /// Requests buffered and running for every model, with the number it may run at once,
+    /// sorted by model.
+    pub fn scheduling(&self) -> Vec<SchedulingStats> {
+        let mut stats: Vec<SchedulingStats> = self
+            .models
+            .iter()
+            .map(|entry| {
+                let queue = entry.value();
+                let state = queue.state();
+                SchedulingStats {
+                    model: entry.key().0.clone(),
+                    queued: state.buffer.queued,
+                    in_flight: state.in_flight,
+                    dispatch_slots: self.dispatch_slots(entry.key()),
+                    worker_started: queue.started.load(AtomicOrdering::Acquire),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.model.cmp(&b.model));
+        stats
+    }
+
+    /// Current buffer capacity and load observations of every model, sorted by model.
+    pub fn buffer_stats(&self) -> Vec<BufferStats> {
+        let mut stats: Vec<BufferStats> = self
+            .models
+            .iter()
+            .map(|entry| entry.value().state().buffer)
+            .collect();
+        stats.sort_by(|a, b| a.model.cmp(&b.model));
+        stats
+    }
+
+    pub fn has_model(&self, model_id: &ModelId) -> bool {
+        self.models.contains_key(model_id)
+    }
+
+    pub fn get_models(&self) -> Vec<ModelId> {
+        self.models
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+/// Registry tags usable as labels; tags a selector could not match are skipped.
+pub(crate) fn labels_from_tags(tags: Option<HashMap<String, String>>) -> Labels {
+    tags.unwrap_or_default()
+        .into_iter()
+        .filter(|(key, value)| check_label(key, false).is_ok() && check_label(value, true).is_ok())
+        .collect()
+}
+
+// Type alias for backward compatibility
+pub type ModelManager = ModelDiscoveryService;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::{FakeInferenceProcessor, FakeRuntimeFactory};
+    use crate::api::inference::{InferenceOutput, InferenceProcessor};
+    use crate::api::inference_runtime::ProcessorRuntime;
+    use crate::api::mlflow_client::{MLFlowArtifact, MLFlowModel, MLFlowModelVersion};
+    use crate::api::runtime_registry::RuntimeFactory;
+    use crate::api::tensor::{Data, DataType};
+    use crate::model::memory::MemoryState;
+    use crate::tenants::{ErrorBudgetPolicy, Refusal};
+    use crate::timeline::Timeline;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// A request of `model` with nothing but its id set.
+    fn request(model: &str, id: impl ToString) -> InferenceRequest {
+        InferenceRequest {
+            model_name: model.to_string(),
+            model_version: None,
+            id: id.to_string(),
+            parameters: None,
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_from_path_with_valid_file_extension() {
+        let path = PathBuf::from("/models/my_model.py");
+        let model_id = ModelId::from_path(path).unwrap();
+        assert_eq!(model_id.0, "my_model.py");
+    }
+
+    #[test]
+    fn test_from_path_with_subpath_and_filename() {
+        let path = PathBuf::from("/models/my_model/my_model.py");
+        let model_id = ModelId::from_path(path).unwrap();
+        assert_eq!(model_id.0, "my_model.py");
+    }
+
+    #[test]
+    fn test_from_path_with_no_filename() {
+        let path = PathBuf::from("/models/");
+        let model_id = ModelId::from_path(path);
+        assert!(model_id.is_none());
+    }
+
+    #[test]
+    fn test_from_path_with_subpath_and_no_filename() {
+        let path = PathBuf::from("/models/my_model");
+        let model_id = ModelId::from_path(path);
+        assert!(model_id.is_none());
+    }
+
+    #[test]
+    fn test_from_path_with_empty_path() {
+        let path = PathBuf::new();
+        let model_id = ModelId::from_path(path);
+        assert!(model_id.is_none());
+    }
+
+    #[test]
+    fn test_from_url_with_valid_url() {
+        let url = "https://example.com/models/my_model";
+        let model_id = ModelId::from_url(url).unwrap();
+        assert_eq!(model_id.0, "my_model");
+    }
+
+    #[test]
+    fn test_from_url_with_trailing_slash() {
+        let url = "https://example.com/models/my_model/";
+        let model_id = ModelId::from_url(url);
+        assert!(model_id.is_none());
+    }
+
+    #[test]
+    fn test_from_string() {
+        let id = "my_custom_model".to_string();
+        let model_id = ModelId::from_string(id);
+        assert_eq!(model_id.0, "my_custom_model");
+    }
+
+    #[test]
+    fn test_model_source_from_uri() {
+        assert!(matches!(
+            ModelSource::from_uri("s3://bucket/models/prod").unwrap(),
+            ModelSource::S3 { bucket, prefix, .. } if bucket == "bucket" && prefix == "models/prod"
+        ));
+        assert!(matches!(
+            ModelSource::from_uri("gs://bucket").unwrap(),
+            ModelSource::Gcs { bucket, prefix, .. } if bucket == "bucket" && prefix.is_empty()
+        ));
+        assert!(matches!(
+            ModelSource::from_uri("az://account/container/models").unwrap(),
+            ModelSource::Azure { account, container, prefix, .. }
+                if account == "account" && container == "container" && prefix == "models"
+        ));
+        assert!(matches!(
+            ModelSource::from_uri("/srv/models").unwrap(),
+            ModelSource::Path(_)
+        ));
+        assert!(ModelSource::from_uri("az://account").is_err());
+        assert!(ModelSource::from_uri("ftp://host/models").is_err());
+    }
+
+    #[test]
+    fn test_load_models_from_dir_records_paths() {
+        let dir = std::env::temp_dir().join(format!("galemind-discovery-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("resnet.onnx")).unwrap();
+
+        let service = ModelDiscoveryService::new(10);
+        service.load_models_from_dir(&dir).unwrap();
+
+        let model_id = ModelId::from_string("resnet.onnx".to_string());
+        assert_eq!(
+            service.get_model_path(&model_id),
+            Some(dir.join("resnet.onnx"))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_models_from_dir_reads_configs() {
+        let dir = std::env::temp_dir().join(format!("galemind-configs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("good.onnx")).unwrap();
+        std::fs::create_dir_all(dir.join("bad.onnx")).unwrap();
+        std::fs::write(
+            dir.join("good.onnx/model.yaml"),
+            "backend: onnx\ninputs: [{ name: x, datatype: FP32, shape: [4] }]\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("bad.onnx/config.pbtxt"), "input { name: ").unwrap();
+
+        let service = ModelDiscoveryService::new(10);
+        service.load_models_from_dir(&dir).unwrap();
+
+        let good = ModelId::from_string("good.onnx".to_string());
+        let config = service.get_model_config(&good).unwrap();
+        assert_eq!(config.inputs[0].name, "x");
+        // Models with an invalid configuration are not registered.
+        assert_eq!(service.get_models(), vec![good]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_models_are_loaded_and_unloaded_by_name() {
+        let dir = std::env::temp_dir().join(format!("galemind-load-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("resnet.onnx")).unwrap();
+        let service = ModelDiscoveryService::new(10);
+        service.load_models_from_dir(&dir).unwrap();
+
+        std::fs::create_dir_all(dir.join("ranker.onnx")).unwrap();
+        let ranker = service.load_model("ranker.onnx").unwrap();
+        assert_eq!(ranker.0, "ranker.onnx");
+        assert!(service.has_model(&ranker));
+        assert!(service.load_model("../resnet.onnx").is_err());
+        assert!(service.load_model("missing.onnx").is_err());
+
+        service.set_read_only(true);
+        assert!(service.unload_model(&ranker).is_err());
+        service.set_read_only(false);
+        assert_eq!(service.unload_model(&ranker).unwrap(), 0);
+        assert!(!service.has_model(&ranker));
+        assert_eq!(service.get_model_path(&ranker), None);
+        assert!(service.unload_model(&ranker).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_model_configs_applies_changed_files() {
+        let dir = std::env::temp_dir().join(format!("galemind-reload-{}", std::process::id()));
+        let config_path = dir.join("ranker.onnx/model.yaml");
+        std::fs::create_dir_all(dir.join("ranker.onnx")).unwrap();
+        std::fs::write(&config_path, "backend: onnx\n").unwrap();
+        let service = ModelDiscoveryService::new(10);
+        service.load_models_from_dir(&dir).unwrap();
+        let ranker = ModelId::from_string("ranker.onnx".to_string());
+        assert!(service.reload_model_configs().unwrap().is_empty());
+
+        std::fs::write(
+            &config_path,
+            "backend: onnx\nmax_batch_size: 8\ndynamic_batching:\n  max_queue_delay_ms: 5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            service.reload_model_configs().unwrap(),
+            vec![ranker.clone()]
+        );
+        let batching = service
+            .get_model_config(&ranker)
+            .unwrap()
+            .dynamic_batching
+            .clone();
+        assert_eq!(batching.unwrap().max_queue_delay_ms, 5.0);
+
+        // An invalid file leaves every configuration as it was.
+        std::fs::write(&config_path, "max_batch_size: [").unwrap();
+        assert!(service.reload_model_configs().is_err());
+        assert!(
+            service
+                .get_model_config(&ranker)
+                .unwrap()
+                .dynamic_batching
+                .is_some()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_models_from_dir_maps_triton_versions() {
+        let dir = std::env::temp_dir().join(format!("galemind-triton-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("densenet/1")).unwrap();
+        std::fs::create_dir_all(dir.join("densenet/3")).unwrap();
+        std::fs::write(dir.join("densenet/config.pbtxt"), "backend: \"fake\"\n").unwrap();
+
+        let service = ModelDiscoveryService::new(10);
+        service
+            .runtime_registry()
+            .register(Arc::new(crate::api::fake::FakeRuntimeFactory));
+        service.load_models_from_dir(&dir).unwrap();
+
+        // The default policy serves the latest version only.
+        let model_id = ModelId::from_string("densenet".to_string());
+        assert_eq!(service.get_model_versions(&model_id), ["3"]);
+        assert_eq!(
+            service.get_model_path(&model_id),
+            Some(dir.join("densenet"))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget_loads_versions_on_demand() {
+        let dir = std::env::temp_dir().join(format!("galemind-budget-{}", std::process::id()));
+        for (model, config) in [
+            ("a", "memory: 40\npinned: true\n"),
+            ("b", "memory: 40\n"),
+            ("c", "memory: 40\n"),
+        ] {
+            std::fs::create_dir_all(dir.join(model).join("1")).unwrap();
+            // The layout takes the backend from config.pbtxt, the model its model.yaml.
+            std::fs::write(dir.join(model).join("config.pbtxt"), "backend: \"fake\"\n").unwrap();
+            std::fs::write(dir.join(model).join("model.yaml"), config).unwrap();
+        }
+        let service = ModelDiscoveryService::new(10).with_memory_budget(MemoryBudget {
+            gpu: None,
+            cpu: Some(100),
+        });
+        service
+            .runtime_registry()
+            .register(Arc::new(crate::api::fake::FakeRuntimeFactory));
+        service.load_models_from_dir(&dir).unwrap();
+        let loaded = |service: &ModelDiscoveryService| -> Vec<String> {
+            service.memory().usage().devices[0]
+                .versions
+                .iter()
+                .filter(|version| version.state == MemoryState::Loaded)
+                .map(|version| version.version.clone())
+                .collect()
+        };
+        assert_eq!(loaded(&service), ["a:1"]);
+
+        for model in ["b", "c"] {
+            let response = service
+                .infer(InferenceRequest {
+                    parameters: Some(HashMap::new()),
+                    ..request(model, "r")
+                })
+                .await
+                .unwrap();
+            assert!(matches!(response, InferenceResponse::Ok(_)));
+        }
+        assert_eq!(loaded(&service), ["a:1", "c:1"]);
+        assert_eq!(service.memory().usage().evictions, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_model_metadata_prefers_config_over_runtime() {
+        let service = ModelDiscoveryService::new(10);
+        let version_id = ModelVersionId::new("ranker", "1");
+        service.register_model_version(
+            version_id.clone(),
+            FakeRuntimeFactory
+                .load(&version_id, Path::new("/unused"))
+                .unwrap(),
+        );
+
+        let metadata = service.model_metadata(&version_id.model, None).unwrap();
+        assert_eq!(metadata.versions, vec!["1"]);
+        assert_eq!(metadata.platform, "fake");
+        assert_eq!(metadata.outputs[0].name, "output_1");
+
+        service.set_model_config(
+            version_id.model.clone(),
+            ModelConfig::from_yaml(
+                "backend: onnx\nmax_batch_size: 4\ninputs: [{ name: x, datatype: FP32, shape: [4] }]",
+            )
+            .unwrap(),
+        );
+        let metadata = service
+            .model_metadata(&version_id.model, Some("1"))
+            .unwrap();
+        assert_eq!(metadata.platform, "onnx");
+        assert_eq!(metadata.inputs[0].shape, vec![-1, 4]);
+        assert!(metadata.outputs.is_empty());
+
+        let summary = service.model_summary(&version_id.model).unwrap();
+        assert_eq!(summary.versions, vec!["1"]);
+        assert_eq!(summary.backend.as_deref(), Some("onnx"));
+        assert!(summary.ready);
+        service.register_model(ModelId::from_string("pending".to_string()));
+        let summary = service
+            .model_summary(&ModelId::from_string("pending".to_string()))
+            .unwrap();
+        assert!(!summary.ready && summary.versions.is_empty() && summary.backend.is_none());
+        assert!(
+            service
+                .model_summary(&ModelId::from_string("missing".to_string()))
+                .is_none()
+        );
+
+        assert!(
+            service
+                .model_metadata(&version_id.model, Some("2"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_model_discovery_service_register_model() {
+        let service = ModelDiscoveryService::new(10);
+        let model_id = ModelId::from_string("test_model".to_string());
+
+        service.register_model(model_id.clone());
+        let models = service.get_models();
+        assert!(models.contains(&model_id));
+    }
+
+    #[tokio::test]
+    async fn test_discover_models_with_mixed_sources() {
+        let service = ModelDiscoveryService::new(10);
+        let sources = vec![
+            ModelSource::Id("model1".to_string()),
+            ModelSource::Url("https://example.com/model2".to_string()),
+        ];
+
+        let discovered = service.discover_models(sources).await.unwrap();
+        assert_eq!(discovered.len(), 2);
+        assert_eq!(discovered[0].0, "model1");
+        assert_eq!(discovered[1].0, "model2");
+    }
+
+    fn runtime(model: &str) -> Arc<dyn InferenceRuntime> {
+        Arc::new(ProcessorRuntime::new(model, FakeInferenceProcessor))
+    }
+
+    fn service_with_versions(versions: &[&str]) -> ModelDiscoveryService {
+        let service = ModelDiscoveryService::new(10);
+        for version in versions {
+            service.register_model_version(ModelVersionId::new("m", *version), runtime("m"));
+        }
+        service
+    }
+
+    #[test]
+    fn test_compare_versions_orders_numerically() {
+        assert_eq!(compare_versions("2", "10"), Ordering::Less);
+        assert_eq!(compare_versions("b", "a"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_version_policy_from_str() {
+        assert_eq!(
+            "latest".parse::<VersionPolicy>().unwrap(),
+            VersionPolicy::Latest
+        );
+        assert_eq!("all".parse::<VersionPolicy>().unwrap(), VersionPolicy::All);
+        assert_eq!(
+            "specific:1, 3".parse::<VersionPolicy>().unwrap(),
+            VersionPolicy::Specific(vec!["1".to_string(), "3".to_string()])
+        );
+        assert!("specific:".parse::<VersionPolicy>().is_err());
+        assert!("newest".parse::<VersionPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_get_model_versions_sorted() {
+        let service = service_with_versions(&["10", "2", "1"]);
+        assert_eq!(
+            service.get_model_versions(&ModelId::from_string("m".to_string())),
+            vec!["1", "2", "10"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_version_latest_policy() {
+        let service = service_with_versions(&["1", "2"]);
+        let model = ModelId::from_string("m".to_string());
+
+        let resolved = service.resolve_version(&model, None).unwrap().unwrap();
+        assert_eq!(resolved.version, "2");
+        assert!(service.resolve_version(&model, Some("1")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_version_all_policy() {
+        let service = service_with_versions(&["1", "2"]).with_version_policy(VersionPolicy::All);
+        let model = ModelId::from_string("m".to_string());
+
+        let resolved = service.resolve_version(&model, Some("1")).unwrap().unwrap();
+        assert_eq!(resolved, ModelVersionId::new("m", "1"));
+        assert!(service.get_runtime(&resolved).is_some());
+    }
+
+    #[test]
+    fn test_resolve_version_specific_policy_per_model() {
+        let service = service_with_versions(&["1", "2", "3"]);
+        let model = ModelId::from_string("m".to_string());
+        service.set_version_policy(
+            model.clone(),
+            VersionPolicy::Specific(vec!["1".to_string(), "2".to_string()]),
+        );
+
+        assert_eq!(service.served_versions(&model), vec!["1", "2"]);
+        let resolved = service.resolve_version(&model, None).unwrap().unwrap();
+        assert_eq!(resolved.version, "2");
+        assert!(service.resolve_version(&model, Some("3")).is_err());
+    }
+
+    #[test]
+    fn test_models_are_selected_and_routed_by_labels() {
+        let service = ModelDiscoveryService::new(10);
+        for (model, lang) in [
+            ("sentiment-en", "en"),
+            ("sentiment-en-2", "en"),
+            ("sentiment-de", "de"),
+        ] {
+            service.register_model_version(ModelVersionId::new(model, "1"), runtime(model));
+            service.set_model_config(
+                ModelId::from_string(model.to_string()),
+                ModelConfig::from_yaml(&format!("labels: {{ task: sentiment, lang: {} }}", lang))
+                    .unwrap(),
+            );
+        }
+        // Registry tags never override labels from the config.
+        service.add_labels(
+            ModelId::from_string("sentiment-de".to_string()),
+            Labels::from([("lang".to_string(), "en".to_string())]),
+            false,
+        );
+        // Models without served versions are listed but not routed to.
+        service.register_model(ModelId::from_string("sentiment-en-3".to_string()));
+        service.add_labels(
+            ModelId::from_string("sentiment-en-3".to_string()),
+            Labels::from([("lang".to_string(), "en".to_string())]),
+            false,
+        );
+
+        let selector: LabelSelector = "lang=en".parse().unwrap();
+        let names: Vec<String> = service
+            .select_models(&selector)
+            .into_iter()
+            .map(|model| model.0)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["sentiment-en", "sentiment-en-2", "sentiment-en-3"]
+        );
+
+        let selector: LabelSelector = "task=sentiment,lang=en".parse().unwrap();
+        let mut routed: Vec<String> = (0..4)
+            .map(|_| service.route(&selector).unwrap().0)
+            .collect();
+        routed.sort();
+        assert_eq!(
+            routed,
+            vec![
+                "sentiment-en",
+                "sentiment-en",
+                "sentiment-en-2",
+                "sentiment-en-2"
+            ]
+        );
+        assert!(service.route(&"lang=fr".parse().unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_only_registry_keeps_serving_versions() {
+        let service = Arc::new(service_with_versions(&["1", "2"]));
+        let model = ModelId::from_string("m".to_string());
+        service.set_read_only(true);
+
+        assert!(!service.unregister_model_version(&ModelVersionId::new("m", "2")));
+        assert!(
+            service
+                .set_shadow_versions(model.clone(), vec!["2".to_string()])
+                .is_err()
+        );
+        assert_eq!(service.get_model_versions(&model), vec!["1", "2"]);
+        assert!(service.shadow_versions(&model).is_empty());
+
+        // Inference continues.
+        let request = InferenceRequest {
+            parameters: Some(HashMap::new()),
+            ..request("m", "r")
+        };
+        assert!(matches!(
+            service.add_request(model, request).await.unwrap().await,
+            Ok(InferenceResponse::Ok(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_self_test_runs_golden_cases_on_a_version() {
+        let service = service_with_versions(&["1", "2"]);
+        let model = ModelId::from_string("m".to_string());
+        assert!(service.self_test(&model, None).await.is_err());
+
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml("golden: [{ name: fake, expected: { data: [0.1, 0.5, 0.4] } }]")
+                .unwrap(),
+        );
+        let report = service.self_test(&model, None).await.unwrap();
+        assert_eq!(report.version, "2");
+        assert!(report.passed);
+        let report = service.self_test(&model, Some("1")).await.unwrap();
+        assert_eq!((report.version.as_str(), report.cases.len()), ("1", 1));
+        assert!(service.self_test(&model, Some("3")).await.is_err());
+        let unknown = ModelId::from_string("unknown".to_string());
+        assert!(service.self_test(&unknown, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_versions_pinned_to_a_gpu_run_in_its_slots() {
+        let service = ModelDiscoveryService::new(10);
+        let model = ModelId::from_string("m".to_string());
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml("device: cuda:1").unwrap(),
+        );
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", FakeInferenceProcessor)),
+        );
+        service.register_model_version(
+            ModelVersionId::new("cpu", "1"),
+            Arc::new(ProcessorRuntime::new("cpu", FakeInferenceProcessor)),
+        );
+
+        let loads = service.devices().loads();
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0].device, Device::Cuda(1));
+        assert_eq!(loads[0].versions, vec!["m:1"]);
+        let request = InferenceRequest {
+            parameters: Some(HashMap::new()),
+            ..request("m", "r")
+        };
+        assert!(matches!(
+            service.infer(request).await.unwrap(),
+            InferenceResponse::Ok(_)
+        ));
+        assert_eq!(service.devices().loads()[0].in_flight, 0);
+
+        assert!(service.unregister_model_version(&ModelVersionId::new("m", "1")));
+        assert!(service.devices().loads()[0].versions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shadow_version_receives_copies_without_serving() {
+        let service = Arc::new(service_with_versions(&["1", "2", "3"]));
+        let model = ModelId::from_string("m".to_string());
+        service
+            .set_shadow_versions(model.clone(), vec!["3".to_string()])
+            .unwrap();
+
+        // The latest policy applies to the primary versions only.
+        assert_eq!(service.served_versions(&model), vec!["2", "3"]);
+        let resolved = service.resolve_version(&model, None).unwrap().unwrap();
+        assert_eq!(resolved.version, "2");
+        assert!(service.resolve_version(&model, Some("3")).is_ok());
+
+        let request = |version: &str| InferenceRequest {
+            model_version: Some(version.to_string()),
+            ..request("m", "r")
+        };
+        // Both are answered (with an error, as the fake runtime needs parameters).
+        let primary = service
+            .add_request(model.clone(), request("2"))
+            .await
+            .unwrap();
+        let shadow = service.add_request(model, request("3")).await.unwrap();
+        assert!(matches!(primary.await, Ok(InferenceResponse::Error(_))));
+        assert!(matches!(shadow.await, Ok(InferenceResponse::Error(_))));
+
+        let stats = service.shadow_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].version.as_str(), stats[0].mirrored), ("3", 1));
+    }
+
+    /// Answers with an output named after the request id.
+    struct EchoIdProcessor;
+
+    impl InferenceProcessor for EchoIdProcessor {
+        fn process(&self, request: InferenceRequest) -> InferenceResponse {
+            InferenceResponse::Ok(InferenceOutput {
+                name: request.id,
+                shape: vec![1],
+                datatype: DataType::VFLOAT,
+                parameters: None,
+                data: Data::VFLOAT(vec![0.0]),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffered_requests_are_answered_on_their_own_channel() {
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", EchoIdProcessor)),
+        );
+        let model = ModelId::from_string("m".to_string());
+
+        let mut receivers = Vec::new();
+        for id in 0..5 {
+            receivers.push(
+                service
+                    .add_request(model.clone(), request("m", id))
+                    .await
+                    .unwrap(),
+            );
+        }
+        for (id, receiver) in receivers.into_iter().enumerate() {
+            match receiver.await.unwrap() {
+                InferenceResponse::Ok(output) => assert_eq!(output.name, id.to_string()),
+                InferenceResponse::Error(e) | InferenceResponse::DeadlineExceeded(e) => {
+                    panic!("request {} failed: {}", id, e.error)
                 }
             }
         }
 
-        Ok(())
+        // Requests for a model that is not registered do not reach a buffer.
+        let unknown = request("other", "r");
+        let refusal = service
+            .add_request(ModelId::from_string("other".to_string()), unknown)
+            .await
+            .map(drop)
+            .unwrap_err();
+        assert_eq!(
+            refusal.downcast_ref::<BufferRefusal>(),
+            Some(&BufferRefusal::NotFound {
+                model: "other".to_string()
+            })
+        );
+        assert!(
+            service
+                .resolve_version(&ModelId::from_string("other".to_string()), None)
+                .is_err()
+        );
     }
 
-    pub fn register_model(&self, model_id: ModelId) {
-        self.models
-            .entry(model_id)
-            .or_insert_with(|| Mutex::new(CircularBuffer::new(self.models_buffer_capacity)));
+    #[tokio::test]
+    async fn test_drained_buffers_answer_their_requests_with_an_error() {
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(ModelVersionId::new("m", "1"), Arc::new(SlowRuntime));
+        let model = ModelId::from_string("m".to_string());
+        // The first request takes the single dispatch slot, the second waits in the buffer.
+        let _running = service
+            .add_request(model.clone(), request("m", "1"))
+            .await
+            .unwrap();
+        let buffered = service
+            .add_request(model.clone(), request("m", "2"))
+            .await
+            .unwrap();
+        let stats = service.scheduling();
+        assert_eq!(
+            (stats[0].queued, stats[0].in_flight, stats[0].dispatch_slots),
+            (1, 1, 1)
+        );
+
+        let other = ModelId::from_string("other".to_string());
+        assert_eq!(service.drain_buffers(Some(&other)).await, 0);
+        assert_eq!(service.drain_buffers(None).await, 1);
+        assert!(matches!(
+            buffered.await,
+            Ok(InferenceResponse::Error(e)) if e.error.contains("drained")
+        ));
+        let stats = service.scheduling();
+        assert_eq!((stats[0].queued, stats[0].in_flight), (0, 1));
     }
 
-    pub fn add_request(&self, model_id: ModelId, req: InferenceRequest) {
-        let buffer = self
-            .models
-            .entry(model_id)
-            .or_insert_with(|| Mutex::new(CircularBuffer::new(self.models_buffer_capacity)));
+    #[tokio::test]
+    async fn test_paused_models_refuse_new_requests_and_finish_queued_ones() {
+        struct BriefRuntime;
+
+        #[async_trait::async_trait]
+        impl InferenceRuntime for BriefRuntime {
+            fn model_id(&self) -> &str {
+                "m"
+            }
 
-        let mut buffer = buffer.lock().unwrap();
-        buffer.push(req);
+            async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                EchoIdProcessor.process(request)
+            }
+        }
+
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(ModelVersionId::new("m", "1"), Arc::new(BriefRuntime));
+        let model = ModelId::from_string("m".to_string());
+        let running = service
+            .add_request(model.clone(), request("m", "1"))
+            .await
+            .unwrap();
+        let buffered = service
+            .add_request(model.clone(), request("m", "2"))
+            .await
+            .unwrap();
+
+        let unknown = ModelId::from_string("other".to_string());
+        assert!(
+            service
+                .pause_model(&unknown, Duration::from_secs(1))
+                .is_err()
+        );
+        service
+            .pause_model(&model, Duration::from_secs(10))
+            .unwrap();
+        let Err(refused) = service.add_request(model.clone(), request("m", "3")).await else {
+            panic!("A paused model accepted a request");
+        };
+        assert_eq!(
+            refused.downcast_ref::<ModelPaused>().map(|p| p.retry_after),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(service.model_pending_requests(&model), 2);
+
+        assert!(
+            service
+                .drain_model(&model, Instant::now() + Duration::from_secs(5))
+                .await
+        );
+        assert!(matches!(running.await, Ok(InferenceResponse::Ok(_))));
+        assert!(matches!(buffered.await, Ok(InferenceResponse::Ok(_))));
+
+        assert!(service.resume_model(&model).is_some());
+        assert!(service.paused_models().is_empty());
+        assert!(service.add_request(model, request("m", "4")).await.is_ok());
     }
 
-    pub fn get_models(&self) -> Vec<ModelId> {
-        self.models
-            .iter()
-            .map(|entry| entry.key().clone())
-            .collect()
+    #[tokio::test]
+    async fn test_repeated_requests_are_answered_from_the_response_cache() {
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", EchoIdProcessor)),
+        );
+        let model = ModelId::from_string("m".to_string());
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml("response_cache: { max_entries: 8 }").unwrap(),
+        );
+        let request = |id: &str, scale: i64| InferenceRequest {
+            parameters: Some(HashMap::from([(
+                "scale".to_string(),
+                InferParameter::Int64(scale),
+            )])),
+            ..request("m", id)
+        };
+        let answer = |receiver: oneshot::Receiver<InferenceResponse>| async {
+            match receiver.await.unwrap() {
+                InferenceResponse::Ok(output) => output.name,
+                _ => panic!("request failed"),
+            }
+        };
+
+        let first = service.add_request(model.clone(), request("a", 1)).await;
+        assert_eq!(answer(first.unwrap()).await, "a");
+        // The echoed id shows the cached response was returned instead of a new one.
+        let repeated = service.add_request(model.clone(), request("b", 1)).await;
+        assert_eq!(answer(repeated.unwrap()).await, "a");
+        let other = service.add_request(model.clone(), request("c", 2)).await;
+        assert_eq!(answer(other.unwrap()).await, "c");
+        assert!(
+            service
+                .render_metrics()
+                .contains("galemind_response_cache_hits_total{model=\"m\"} 1")
+        );
     }
-}
 
-// Type alias for backward compatibility
-pub type ModelManager = ModelDiscoveryService;
+    /// Records the ids of the requests it processes, in order.
+    struct RecordingProcessor(Arc<Mutex<Vec<String>>>);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::PathBuf;
+    impl InferenceProcessor for RecordingProcessor {
+        fn process(&self, request: InferenceRequest) -> InferenceResponse {
+            self.0.lock().unwrap().push(request.id.clone());
+            EchoIdProcessor.process(request)
+        }
+    }
 
-    #[test]
-    fn test_from_path_with_valid_file_extension() {
-        let path = PathBuf::from("/models/my_model.py");
-        let model_id = ModelId::from_path(path).unwrap();
-        assert_eq!(model_id.0, "my_model.py");
+    #[tokio::test]
+    async fn test_buffered_requests_are_dispatched_by_priority() {
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(GatedRuntime {
+                gate: gate.clone(),
+                processor: RecordingProcessor(processed.clone()),
+            }),
+        );
+        let model = ModelId::from_string("m".to_string());
+
+        // A model without configuration runs one request at a time: the first one holds the
+        // slot while the others are buffered.
+        let mut receivers = Vec::new();
+        for (id, priority) in [
+            ("first", Priority::Low),
+            ("low", Priority::Low),
+            ("normal", Priority::Normal),
+            ("high", Priority::High),
+            ("high-2", Priority::High),
+        ] {
+            let request = InferenceRequest {
+                priority,
+                ..request("m", id)
+            };
+            receivers.push(service.add_request(model.clone(), request).await.unwrap());
+        }
+        gate.add_permits(receivers.len());
+        for receiver in receivers {
+            assert!(matches!(receiver.await, Ok(InferenceResponse::Ok(_))));
+        }
+
+        assert_eq!(
+            *processed.lock().unwrap(),
+            vec!["first", "high", "high-2", "normal", "low"]
+        );
     }
 
-    #[test]
-    fn test_from_path_with_subpath_and_filename() {
-        let path = PathBuf::from("/models/my_model/my_model.py");
-        let model_id = ModelId::from_path(path).unwrap();
-        assert_eq!(model_id.0, "my_model.py");
+    /// Runs a request once the gate lets it through.
+    struct GatedRuntime {
+        gate: Arc<tokio::sync::Semaphore>,
+        processor: RecordingProcessor,
     }
 
-    #[test]
-    fn test_from_path_with_no_filename() {
-        let path = PathBuf::from("/models/");
-        let model_id = ModelId::from_path(path);
-        assert!(model_id.is_none());
+    #[async_trait::async_trait]
+    impl InferenceRuntime for GatedRuntime {
+        fn model_id(&self) -> &str {
+            "m"
+        }
+
+        async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+            let _permit = self.gate.acquire().await.unwrap();
+            self.processor.process(request)
+        }
     }
 
-    #[test]
-    fn test_from_path_with_subpath_and_no_filename() {
-        let path = PathBuf::from("/models/my_model");
-        let model_id = ModelId::from_path(path);
-        assert!(model_id.is_none());
+    /// Takes far longer than any deadline of the tests.
+    struct SlowRuntime;
+
+    #[async_trait::async_trait]
+    impl InferenceRuntime for SlowRuntime {
+        fn model_id(&self) -> &str {
+            "m"
+        }
+
+        async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            EchoIdProcessor.process(request)
+        }
     }
 
-    #[test]
-    fn test_from_path_with_empty_path() {
-        let path = PathBuf::new();
-        let model_id = ModelId::from_path(path);
-        assert!(model_id.is_none());
+    #[tokio::test]
+    async fn test_requests_past_their_deadline_are_not_run() {
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new(
+                "m",
+                RecordingProcessor(processed.clone()),
+            )),
+        );
+        service.register_model_version(ModelVersionId::new("slow", "1"), Arc::new(SlowRuntime));
+        let request = |model: &str, deadline: Instant| InferenceRequest {
+            deadline: Some(deadline),
+            ..request(model, "r")
+        };
+
+        // Expired while queued: answered without running.
+        let expired = service
+            .add_request(
+                ModelId::from_string("m".to_string()),
+                request("m", Instant::now()),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            expired.await,
+            Ok(InferenceResponse::DeadlineExceeded(_))
+        ));
+        assert!(processed.lock().unwrap().is_empty());
+
+        // Expired while running: the runtime call is abandoned.
+        let running = service
+            .add_request(
+                ModelId::from_string("slow".to_string()),
+                request("slow", Instant::now() + Duration::from_millis(20)),
+            )
+            .await
+            .unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            Ok(InferenceResponse::DeadlineExceeded(_))
+        ));
     }
 
-    #[test]
-    fn test_from_url_with_valid_url() {
-        let url = "https://example.com/models/my_model";
-        let model_id = ModelId::from_url(url).unwrap();
-        assert_eq!(model_id.0, "my_model");
+    struct PanickingRuntime;
+
+    #[async_trait::async_trait]
+    impl InferenceRuntime for PanickingRuntime {
+        fn model_id(&self) -> &str {
+            "m"
+        }
+
+        async fn process_single(&self, _request: InferenceRequest) -> InferenceResponse {
+            panic!("corrupted weights")
+        }
     }
 
-    #[test]
-    fn test_from_url_with_trailing_slash() {
-        let url = "https://example.com/models/my_model/";
-        let model_id = ModelId::from_url(url);
-        assert!(model_id.is_none());
+    #[tokio::test]
+    async fn test_runtime_panics_count_against_the_tenant() {
+        let policy = ErrorBudgetPolicy {
+            min_requests: 3,
+            ..ErrorBudgetPolicy::default()
+        };
+        let service =
+            ModelDiscoveryService::new(10).with_error_budget(Arc::new(ErrorBudget::new(policy)));
+        service.register_model_version(ModelVersionId::new("m", "1"), Arc::new(PanickingRuntime));
+
+        for _ in 0..3 {
+            let response = service
+                .infer(InferenceRequest {
+                    tenant: Some("acme".to_string()),
+                    ..request("m", "r")
+                })
+                .await
+                .unwrap();
+            match response {
+                InferenceResponse::Error(error) => {
+                    assert_eq!(error.error, "Runtime of m:1 panicked: corrupted weights")
+                }
+                _ => panic!("expected an error response"),
+            }
+        }
+        assert!(matches!(
+            service.error_budget().admit(Some("acme")),
+            Err(Refusal::Quarantined(_))
+        ));
+        assert!(service.error_budget().admit(Some("other")).is_ok());
     }
 
-    #[test]
-    fn test_from_string() {
-        let id = "my_custom_model".to_string();
-        let model_id = ModelId::from_string(id);
-        assert_eq!(model_id.0, "my_custom_model");
+    #[tokio::test]
+    async fn test_full_buffers_follow_the_overflow_policy() {
+        let service = Arc::new(ModelDiscoveryService::new(1));
+        service.register_model_version(ModelVersionId::new("slow", "1"), Arc::new(SlowRuntime));
+        let model = ModelId::from_string("slow".to_string());
+        let set_overflow = |overflow: &str| {
+            let config = ModelConfig::from_yaml(&format!("overflow: {}", overflow)).unwrap();
+            service.set_model_config(model.clone(), config);
+        };
+
+        // The first request takes the single dispatch slot, the second the single slot of
+        // the buffer.
+        set_overflow("{ policy: reject }");
+        let _running = service
+            .add_request(model.clone(), request("slow", "1"))
+            .await
+            .unwrap();
+        let _buffered = service
+            .add_request(model.clone(), request("slow", "2"))
+            .await
+            .unwrap();
+        let refusal = service
+            .add_request(model.clone(), request("slow", "3"))
+            .await
+            .map(drop)
+            .unwrap_err();
+        assert_eq!(
+            refusal.downcast_ref::<BufferRefusal>(),
+            Some(&BufferRefusal::Full {
+                model: "slow".to_string(),
+                waited: None
+            })
+        );
+
+        set_overflow("{ policy: drop_newest }");
+        let dropped = service
+            .add_request(model.clone(), request("slow", "4"))
+            .await
+            .unwrap();
+        assert!(dropped.await.is_err());
+
+        // The running request holds on to its slot for longer than the next request is
+        // willing to wait for room.
+        set_overflow("{ policy: block_with_timeout, timeout_ms: 20 }");
+        let refusal = service
+            .add_request(model, request("slow", "5"))
+            .await
+            .map(drop)
+            .unwrap_err();
+        assert_eq!(
+            refusal.downcast_ref::<BufferRefusal>(),
+            Some(&BufferRefusal::Full {
+                model: "slow".to_string(),
+                waited: Some(Duration::from_millis(20))
+            })
+        );
     }
 
-    #[test]
-    fn test_model_discovery_service_register_model() {
-        let service = ModelDiscoveryService::new(10);
-        let model_id = ModelId::from_string("test_model".to_string());
+    #[tokio::test]
+    async fn test_blocked_requests_enter_the_buffer_as_room_is_made() {
+        let service = Arc::new(ModelDiscoveryService::new(1));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", EchoIdProcessor)),
+        );
+        let model = ModelId::from_string("m".to_string());
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml("overflow: { policy: block_with_timeout, timeout_ms: 5000 }")
+                .unwrap(),
+        );
+        let mut receivers = Vec::new();
+        for id in 0..4 {
+            receivers.push(
+                service
+                    .add_request(model.clone(), request("m", id))
+                    .await
+                    .unwrap(),
+            );
+        }
+        for (id, receiver) in receivers.into_iter().enumerate() {
+            match receiver.await.unwrap() {
+                InferenceResponse::Ok(output) => assert_eq!(output.name, id.to_string()),
+                InferenceResponse::Error(e) | InferenceResponse::DeadlineExceeded(e) => {
+                    panic!("request {} failed: {}", id, e.error)
+                }
+            }
+        }
+    }
 
-        service.register_model(model_id.clone());
-        let models = service.get_models();
-        assert!(models.contains(&model_id));
+    #[tokio::test]
+    async fn test_infer_batches_models_with_dynamic_batching() {
+        let service = service_with_versions(&["1"]);
+        let model = ModelId::from_string("m".to_string());
+        let request = || InferenceRequest {
+            parameters: Some(HashMap::new()),
+            ..request("m", "r")
+        };
+        assert!(matches!(
+            service.infer(request()).await.unwrap(),
+            InferenceResponse::Ok(_)
+        ));
+
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml(
+                "max_batch_size: 4\ndynamic_batching: { max_queue_delay_ms: 1 }",
+            )
+            .unwrap(),
+        );
+        let timeline = Arc::new(Timeline::new());
+        let traced = InferenceRequest {
+            timeline: Some(timeline.clone()),
+            ..request()
+        };
+        assert!(matches!(
+            service.infer(traced).await.unwrap(),
+            InferenceResponse::Ok(_)
+        ));
+        assert!(service.buffer_stats()[0].service_time_ms.is_some());
+
+        let events: Vec<String> = timeline.events().into_iter().map(|e| e.name).collect();
+        assert_eq!(
+            events,
+            vec![
+                "resolve_version",
+                "queue.enter",
+                "queue",
+                "runtime.process_batch"
+            ]
+        );
+
+        let metrics = service.render_metrics();
+        assert!(metrics.contains("galemind_batch_size_count{model=\"m\",version=\"1\"} 1\n"));
+        assert!(metrics.contains("galemind_buffer_fill_percent{model=\"m\"} 0\n"));
+        assert!(metrics.contains(
+            "galemind_requests_total{model=\"m\",version=\"1\",route=\"scheduler\"} 2\n"
+        ));
     }
 
     #[tokio::test]
-    async fn test_discover_models_with_mixed_sources() {
+    async fn test_infer_runs_requests_opting_out_of_batching_alone() {
+        let service = service_with_versions(&["1"]);
+        service.set_model_config(
+            ModelId::from_string("m".to_string()),
+            ModelConfig::from_yaml(
+                "max_batch_size: 4\ndynamic_batching: { max_queue_delay_ms: 1 }",
+            )
+            .unwrap(),
+        );
+        let timeline = Arc::new(Timeline::new());
+        let request = InferenceRequest {
+            parameters: Some(
+                [
+                    (
+                        crate::NO_BATCHING_PARAMETER.to_string(),
+                        crate::api::inference::InferParameter::Bool(true),
+                    ),
+                    (
+                        crate::INSTANCE_PARAMETER.to_string(),
+                        crate::api::inference::InferParameter::Int64(0),
+                    ),
+                ]
+                .into(),
+            ),
+            timeline: Some(timeline.clone()),
+            ..request("m", "r")
+        };
+        let ignored = service.ignored_hints(&request);
+        assert_eq!(
+            IgnoredHint::describe(&ignored),
+            "instance: not allowed by the server"
+        );
+        assert!(matches!(
+            service.infer(request).await.unwrap(),
+            InferenceResponse::Ok(_)
+        ));
+        let events: Vec<String> = timeline.events().into_iter().map(|e| e.name).collect();
+        assert_eq!(events, ["resolve_version", "runtime.process_single"]);
+    }
+
+    /// Records the requests it runs, and the size of every batch.
+    struct CountingRuntime {
+        runs: Arc<Mutex<Vec<String>>>,
+        batches: Arc<Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl InferenceRuntime for CountingRuntime {
+        fn model_id(&self) -> &str {
+            "m"
+        }
+
+        async fn process_single(&self, request: InferenceRequest) -> InferenceResponse {
+            self.runs.lock().unwrap().push(request.id.clone());
+            EchoIdProcessor.process(request)
+        }
+
+        async fn process_batch(&self, requests: Vec<InferenceRequest>) -> Vec<InferenceResponse> {
+            self.batches.lock().unwrap().push(requests.len());
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(self.process_single(request).await);
+            }
+            responses
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffered_requests_are_run_exactly_once() {
+        let model = ModelId::from_string("m".to_string());
+        for batching in [
+            None,
+            Some("max_batch_size: 4\ndynamic_batching: { max_queue_delay_ms: 20 }"),
+        ] {
+            let runs = Arc::new(Mutex::new(Vec::new()));
+            let batches = Arc::new(Mutex::new(Vec::new()));
+            let service = Arc::new(ModelDiscoveryService::new(100));
+            service.register_model_version(
+                ModelVersionId::new("m", "1"),
+                Arc::new(CountingRuntime {
+                    runs: runs.clone(),
+                    batches: batches.clone(),
+                }),
+            );
+            if let Some(config) = batching {
+                service.set_model_config(model.clone(), ModelConfig::from_yaml(config).unwrap());
+            }
+
+            let mut receivers = Vec::new();
+            for id in 0..8 {
+                receivers.push(
+                    service
+                        .add_request(model.clone(), request("m", id))
+                        .await
+                        .unwrap(),
+                );
+            }
+            for (id, receiver) in receivers.into_iter().enumerate() {
+                match receiver.await.unwrap() {
+                    InferenceResponse::Ok(output) => assert_eq!(output.name, id.to_string()),
+                    InferenceResponse::Error(e) | InferenceResponse::DeadlineExceeded(e) => {
+                        panic!("request {} failed: {}", id, e.error)
+                    }
+                }
+            }
+            let mut runs = runs.lock().unwrap().clone();
+            runs.sort_by_key(|id| id.parse::<usize>().unwrap());
+            let expected: Vec<String> = (0..8).map(|id| id.to_string()).collect();
+            assert_eq!(runs, expected);
+
+            let batches = batches.lock().unwrap().clone();
+            match batching {
+                None => assert!(batches.is_empty()),
+                Some(_) => {
+                    assert_eq!(batches.iter().sum::<usize>(), 8);
+                    assert!(batches.iter().all(|&size| size <= 4));
+                    assert!(batches.len() < 8);
+                }
+            }
+            assert_eq!(service.pending_requests(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lone_batched_request_is_flushed_after_the_queue_delay() {
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(CountingRuntime {
+                runs: runs.clone(),
+                batches: batches.clone(),
+            }),
+        );
+        let model = ModelId::from_string("m".to_string());
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml(
+                "max_batch_size: 8\ndynamic_batching: { max_queue_delay_ms: 20 }",
+            )
+            .unwrap(),
+        );
+        let started = Instant::now();
+        let response = service
+            .add_request(model, request("m", "alone"))
+            .await
+            .unwrap();
+        assert!(matches!(response.await, Ok(InferenceResponse::Ok(_))));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(*runs.lock().unwrap(), ["alone"]);
+        assert_eq!(*batches.lock().unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn test_full_batches_are_dispatched_without_waiting_for_the_queue_delay() {
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        service.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(CountingRuntime {
+                runs: runs.clone(),
+                batches: batches.clone(),
+            }),
+        );
+        let model = ModelId::from_string("m".to_string());
+        service.set_model_config(
+            model.clone(),
+            ModelConfig::from_yaml(
+                "max_batch_size: 4\ndynamic_batching: { max_queue_delay_ms: 10000 }",
+            )
+            .unwrap(),
+        );
+
+        let mut receivers = Vec::new();
+        for id in 0..8 {
+            receivers.push(
+                service
+                    .add_request(model.clone(), request("m", id))
+                    .await
+                    .unwrap(),
+            );
+        }
+        let responses = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut responses = Vec::new();
+            for receiver in receivers {
+                responses.push(receiver.await.unwrap());
+            }
+            responses
+        })
+        .await
+        .expect("full batches waited for the queue delay");
+        assert!(
+            responses
+                .iter()
+                .all(|response| matches!(response, InferenceResponse::Ok(_)))
+        );
+        assert_eq!(*batches.lock().unwrap(), [4, 4]);
+        assert_eq!(runs.lock().unwrap().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_model_is_served_by_the_worker_of_its_new_queue() {
+        let service = Arc::new(ModelDiscoveryService::new(10));
+        let model = ModelId::from_string("m".to_string());
+        for round in 0..2 {
+            service.register_model(model.clone());
+            service.register_model_version(
+                ModelVersionId::new("m", "1"),
+                Arc::new(ProcessorRuntime::new("m", EchoIdProcessor)),
+            );
+            assert!(!service.scheduling()[0].worker_started, "round {}", round);
+            let response = service
+                .add_request(model.clone(), request("m", "r"))
+                .await
+                .unwrap();
+            assert!(matches!(response.await, Ok(InferenceResponse::Ok(_))));
+            assert!(service.scheduling()[0].worker_started);
+            assert_eq!(service.unload_model(&model).unwrap(), 1);
+            assert!(service.scheduling().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_resolve_version_unknown_model() {
+        let service = service_with_versions(&["1"]);
+        let model = ModelId::from_string("unknown".to_string());
+        assert!(service.resolve_version(&model, None).is_err());
+    }
+
+    #[test]
+    fn test_resolve_version_unversioned_model() {
         let service = ModelDiscoveryService::new(10);
-        let sources = vec![
-            ModelSource::Id("model1".to_string()),
-            ModelSource::Url("https://example.com/model2".to_string()),
-        ];
+        let model = ModelId::from_string("plain".to_string());
+        service.register_model(model.clone());
 
-        let discovered = service.discover_models(sources).await.unwrap();
-        assert_eq!(discovered.len(), 2);
-        assert_eq!(discovered[0].0, "model1");
-        assert_eq!(discovered[1].0, "model2");
+        assert!(service.resolve_version(&model, None).unwrap().is_none());
+        assert!(service.resolve_version(&model, Some("1")).is_err());
     }
 
     #[tokio::test]
     async fn test_discover_models_with_mlflow_source() {
-        let service = ModelDiscoveryService::new(10);
-        let sources = vec![ModelSource::MLFlow {
+        let _service = ModelDiscoveryService::new(10);
+        let sources = [ModelSource::MLFlow {
             base_url: "http://localhost:5000".to_string(),
             api_token: None,
             model_name: Some("test_model".to_string()),
@@ -294,8 +4079,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_discover_all_models_from_mlflow() {
-        let service = ModelDiscoveryService::new(10);
-        let sources = vec![ModelSource::MLFlow {
+        let _service = ModelDiscoveryService::new(10);
+        let sources = [ModelSource::MLFlow {
             base_url: "http://localhost:5000".to_string(),
             api_token: Some("token123".to_string()),
             model_name: None, // Discover all models
@@ -307,4 +4092,135 @@ mod tests {
             assert!(model_name.is_none());
         }
     }
+
+    struct LocalMLFlowClient {
+        artifact_root: PathBuf,
+    }
+
+    #[async_trait::async_trait]
+    impl MLFlowClientTrait for LocalMLFlowClient {
+        async fn list_models(&self) -> Result<Vec<MLFlowModel>> {
+            Ok(vec![MLFlowModel {
+                name: "classifier".to_string(),
+                version: None,
+                creation_timestamp: None,
+                last_updated_timestamp: None,
+                description: None,
+                tags: None,
+            }])
+        }
+
+        async fn get_model_versions(&self, model_name: &str) -> Result<Vec<MLFlowModelVersion>> {
+            let version = |version: &str, status: &str| MLFlowModelVersion {
+                name: model_name.to_string(),
+                version: version.to_string(),
+                creation_timestamp: None,
+                last_updated_timestamp: None,
+                description: None,
+                user_id: None,
+                current_stage: None,
+                source: None,
+                run_id: None,
+                status: Some(status.to_string()),
+                tags: None,
+            };
+            Ok(vec![
+                version("1", "READY"),
+                version("2", "READY"),
+                version("3", "PENDING_REGISTRATION"),
+            ])
+        }
+
+        async fn get_model(&self, name: &str) -> Result<Option<MLFlowModel>> {
+            Ok(self
+                .list_models()
+                .await?
+                .into_iter()
+                .find(|model| model.name == name))
+        }
+
+        async fn get_download_uri(&self, _model_name: &str, version: &str) -> Result<String> {
+            Ok(format!(
+                "file://{}",
+                self.artifact_root.join(version).display()
+            ))
+        }
+
+        async fn list_artifacts(&self, _path: &str) -> Result<Vec<MLFlowArtifact>> {
+            Ok(Vec::new())
+        }
+
+        async fn download_artifact(&self, path: &str) -> Result<Vec<u8>> {
+            Err(anyhow!("missing artifact {}", path))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_discover_from_mlflow_deploys_ready_versions() {
+        let artifact_root =
+            std::env::temp_dir().join(format!("galemind-mlflow-artifacts-{}", std::process::id()));
+        for version in ["1", "2", "3"] {
+            let dir = artifact_root.join(version);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("MLmodel"), "flavors:\n  fake: {}\n").unwrap();
+        }
+
+        let registry = Arc::new(RuntimeRegistry::new());
+        registry.register(Arc::new(FakeRuntimeFactory));
+        let service = ModelDiscoveryService::new(10)
+            .with_version_policy(VersionPolicy::All)
+            .with_runtime_registry(registry);
+        let client: Arc<dyn MLFlowClientTrait> = Arc::new(LocalMLFlowClient {
+            artifact_root: artifact_root.clone(),
+        });
+
+        let discovered = service.discover_from_mlflow(client, None).await.unwrap();
+        assert_eq!(
+            discovered,
+            vec![ModelId::from_string("classifier".to_string())]
+        );
+        assert_eq!(service.get_model_versions(&discovered[0]), vec!["1", "2"]);
+
+        fs::remove_dir_all(artifact_root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_discover_from_mlflow_skips_unloadable_versions() {
+        let service = ModelDiscoveryService::new(10);
+        let client: Arc<dyn MLFlowClientTrait> = Arc::new(LocalMLFlowClient {
+            artifact_root: PathBuf::from("/nonexistent"),
+        });
+
+        let discovered = service
+            .discover_from_mlflow(client, Some("classifier".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(discovered.len(), 1);
+        assert!(service.get_model_versions(&discovered[0]).is_empty());
+    }
+
+    #[test]
+    fn test_artifact_location_from_uri() {
+        let client: Arc<dyn MLFlowClientTrait> = Arc::new(LocalMLFlowClient {
+            artifact_root: PathBuf::new(),
+        });
+
+        assert!(matches!(
+            ArtifactLocation::from_uri(&client, "file:///mlruns/1/abc/artifacts/model"),
+            Ok(ArtifactLocation::Local(path)) if path == Path::new("/mlruns/1/abc/artifacts/model")
+        ));
+        assert!(matches!(
+            ArtifactLocation::from_uri(&client, "mlflow-artifacts:/1/abc/artifacts/model"),
+            Ok(ArtifactLocation::Remote(_, prefix)) if prefix == "1/abc/artifacts/model"
+        ));
+        assert!(matches!(
+            ArtifactLocation::from_uri(&client, "mlflow-artifacts://tracking:5000/1/abc/model"),
+            Ok(ArtifactLocation::Remote(_, prefix)) if prefix == "1/abc/model"
+        ));
+        assert!(matches!(
+            ArtifactLocation::from_uri(&client, "s3://bucket/1/abc/artifacts/model"),
+            Ok(ArtifactLocation::Remote(_, prefix)) if prefix == "1/abc/artifacts/model"
+        ));
+        assert!(ArtifactLocation::from_uri(&client, "dbfs:/databricks/model").is_err());
+    }
 }
@@ -40,12 +40,12 @@ impl ModelManager {
 
         for model_entry in model_entries {
             let model_entry = model_entry?;
-            if model_entry.file_type()?.is_dir() {
-                if let Some(model_id) = ModelId::from_path(model_entry.path()) {
-                    self.models.entry(model_id).or_insert_with(|| {
-                        Mutex::new(CircularBuffer::new(self.models_buffer_capacity))
-                    });
-                }
+            if model_entry.file_type()?.is_dir()
+                && let Some(model_id) = ModelId::from_path(model_entry.path())
+            {
+                self.models.entry(model_id).or_insert_with(|| {
+                    Mutex::new(CircularBuffer::new(self.models_buffer_capacity))
+                });
             }
         }
 
@@ -0,0 +1,298 @@
+/* Local cache of model artifacts pulled from object storage.
+
+Artifacts of a remote source are mirrored to `<root>/<source key>/<model>/...`,
+keeping the object layout below the model prefix. Files already present with
+the expected size are not downloaded again, so restarts and on-demand fetches
+only transfer what changed.
+*/
+
+use anyhow::{Result, anyhow};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+use crate::model::object_store::{ObjectEntry, ObjectStoreClient};
+
+#[derive(Debug, Clone)]
+pub struct LocalModelStore {
+    root: PathBuf,
+}
+
+impl LocalModelStore {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Local directory holding `model_name` from the source identified by `source_key`.
+    pub fn model_dir(&self, source_key: &str, model_name: &str) -> PathBuf {
+        self.root.join(source_key).join(model_name)
+    }
+
+    /// Names of the models stored under `prefix`, i.e. the first path segment below it.
+    pub fn model_names(entries: &[ObjectEntry], prefix: &str) -> Vec<String> {
+        let prefix = normalize_prefix(prefix);
+        entries
+            .iter()
+            .filter_map(|entry| entry.key.strip_prefix(&prefix))
+            .filter_map(|relative| relative.split_once('/'))
+            .map(|(model, _)| model.to_string())
+            .filter(|model| !model.is_empty())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Mirrors every model found under `prefix`, returning each model name with its local directory.
+    pub async fn sync_source(
+        &self,
+        client: &dyn ObjectStoreClient,
+        source_key: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, PathBuf)>> {
+        let entries = client.list_objects(&normalize_prefix(prefix)).await?;
+        let mut models = Vec::new();
+
+        for model_name in Self::model_names(&entries, prefix) {
+            let model_prefix = format!("{}{}/", normalize_prefix(prefix), model_name);
+            let model_dir = self.model_dir(source_key, &model_name);
+            self.download_missing(client, &entries, &model_prefix, &model_dir)
+                .await?;
+            models.push((model_name, model_dir));
+        }
+
+        Ok(models)
+    }
+
+    /// Mirrors a single model stored at `<prefix>/<model_name>/`.
+    pub async fn sync_model(
+        &self,
+        client: &dyn ObjectStoreClient,
+        source_key: &str,
+        prefix: &str,
+        model_name: &str,
+    ) -> Result<PathBuf> {
+        let model_prefix = format!("{}{}/", normalize_prefix(prefix), model_name);
+        let model_dir = self.model_dir(source_key, model_name);
+        self.sync_prefix(client, &model_prefix, &model_dir)
+            .await
+            .map_err(|e| anyhow!("Model '{}' in {}: {}", model_name, source_key, e))?;
+        Ok(model_dir)
+    }
+
+    /// Mirrors every object below `prefix` into `destination`, keeping the layout below the prefix.
+    pub async fn sync_prefix(
+        &self,
+        client: &dyn ObjectStoreClient,
+        prefix: &str,
+        destination: &Path,
+    ) -> Result<()> {
+        let prefix = normalize_prefix(prefix);
+        let entries = client.list_objects(&prefix).await?;
+        if entries.is_empty() {
+            return Err(anyhow!("No artifacts found under '{}'", prefix));
+        }
+
+        self.download_missing(client, &entries, &prefix, destination)
+            .await
+    }
+
+    async fn download_missing(
+        &self,
+        client: &dyn ObjectStoreClient,
+        entries: &[ObjectEntry],
+        model_prefix: &str,
+        model_dir: &Path,
+    ) -> Result<()> {
+        for entry in entries {
+            let Some(relative) = entry.key.strip_prefix(model_prefix) else {
+                continue;
+            };
+            // Directory placeholder objects carry no data.
+            if relative.is_empty() || relative.ends_with('/') {
+                continue;
+            }
+            let relative = safe_relative_path(relative)?;
+            let destination = model_dir.join(&relative);
+
+            if let Ok(metadata) = tokio::fs::metadata(&destination).await
+                && metadata.len() == entry.size
+            {
+                continue;
+            }
+
+            if let Some(parent) = destination.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let contents = client.get_object(&entry.key).await?;
+            // Write next to the destination and rename so readers never see partial files.
+            let partial = destination.with_extension("part");
+            tokio::fs::write(&partial, contents).await?;
+            tokio::fs::rename(&partial, &destination).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Ensures a non-empty prefix ends with a single `/`.
+fn normalize_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", prefix)
+    }
+}
+
+/// Rejects object keys that would escape the model directory.
+fn safe_relative_path(relative: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(relative);
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(path)
+    } else {
+        Err(anyhow!(
+            "Refusing to store object with unsafe key '{}'",
+            relative
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    struct MemoryObjectStore {
+        objects: HashMap<String, Vec<u8>>,
+        downloads: Mutex<Vec<String>>,
+    }
+
+    impl MemoryObjectStore {
+        fn new(objects: &[(&str, &str)]) -> Self {
+            Self {
+                objects: objects
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.as_bytes().to_vec()))
+                    .collect(),
+                downloads: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStoreClient for MemoryObjectStore {
+        async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectEntry>> {
+            let mut entries: Vec<ObjectEntry> = self
+                .objects
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| ObjectEntry {
+                    key: key.clone(),
+                    size: value.len() as u64,
+                })
+                .collect();
+            entries.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(entries)
+        }
+
+        async fn get_object(&self, key: &str) -> Result<Vec<u8>> {
+            self.downloads.lock().unwrap().push(key.to_string());
+            self.objects
+                .get(key)
+                .cloned()
+                .ok_or_else(|| anyhow!("missing {}", key))
+        }
+    }
+
+    fn temp_store(name: &str) -> LocalModelStore {
+        let root = std::env::temp_dir().join(format!(
+            "galemind-model-store-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        LocalModelStore::new(root)
+    }
+
+    fn entry(key: &str) -> ObjectEntry {
+        ObjectEntry {
+            key: key.to_string(),
+            size: 1,
+        }
+    }
+
+    #[test]
+    fn test_model_names() {
+        let entries = vec![
+            entry("repo/a/1/model.onnx"),
+            entry("repo/a/config.yaml"),
+            entry("repo/b/model.pt"),
+            entry("repo/readme.md"),
+            entry("other/c/model.pt"),
+        ];
+        assert_eq!(
+            LocalModelStore::model_names(&entries, "repo"),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            LocalModelStore::model_names(&entries, "/repo/"),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_safe_relative_path() {
+        assert!(safe_relative_path("1/model.onnx").is_ok());
+        assert!(safe_relative_path("../escape").is_err());
+        assert!(safe_relative_path("/etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_source_downloads_and_caches() {
+        let store = temp_store("source");
+        let client = MemoryObjectStore::new(&[
+            ("repo/a/1/model.onnx", "onnx"),
+            ("repo/b/model.pt", "torch"),
+        ]);
+
+        let models = store
+            .sync_source(&client, "s3/bucket", "repo")
+            .await
+            .unwrap();
+        assert_eq!(models.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(models[0].1.join("1/model.onnx")).unwrap(),
+            "onnx"
+        );
+
+        // A second sync finds everything cached and downloads nothing.
+        store
+            .sync_source(&client, "s3/bucket", "repo")
+            .await
+            .unwrap();
+        assert_eq!(client.downloads.lock().unwrap().len(), 2);
+
+        std::fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_model_unknown_model() {
+        let store = temp_store("unknown");
+        let client = MemoryObjectStore::new(&[("repo/a/model.onnx", "onnx")]);
+
+        assert!(
+            store
+                .sync_model(&client, "s3/bucket", "repo", "missing")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,138 @@
+/* Paused models.
+
+An operator pauses a model to swap its artifacts or to debug a stuck backend.
+While paused, new requests for the model are refused as unavailable, telling
+clients when to retry, and the requests already buffered or running finish:
+`ModelDiscoveryService::drain_model` waits for them. Resuming the model admits
+requests again. Pauses are kept in memory, and a restart resumes every model.
+*/
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::model_discovery_service::ModelId;
+
+/// Delay paused models tell clients to retry after, unless the pause sets another.
+pub const DEFAULT_PAUSE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// A model refusing new requests.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelPause {
+    pub model: String,
+    /// When the model was paused, in milliseconds since the Unix epoch.
+    pub since_ms: u128,
+    /// Seconds clients are told to wait before retrying.
+    pub retry_after_secs: u64,
+}
+
+/// A request refused because its model is paused.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelPaused {
+    pub model: String,
+    pub retry_after: Duration,
+}
+
+impl ModelPaused {
+    pub fn code(&self) -> &'static str {
+        "model_paused"
+    }
+}
+
+impl fmt::Display for ModelPaused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Model '{}' is paused, retry in {} s",
+            self.model,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for ModelPaused {}
+
+/// The paused models.
+#[derive(Debug, Default)]
+pub struct Pauses {
+    paused: DashMap<ModelId, ModelPause>,
+}
+
+impl Pauses {
+    /// Pauses `model_id`, or updates the retry delay of its pause.
+    pub fn pause(&self, model_id: &ModelId, retry_after: Duration) -> ModelPause {
+        let retry_after_secs = retry_after.as_secs().max(1);
+        let mut pause = self
+            .paused
+            .entry(model_id.clone())
+            .or_insert_with(|| ModelPause {
+                model: model_id.0.clone(),
+                since_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis(),
+                retry_after_secs,
+            });
+        pause.retry_after_secs = retry_after_secs;
+        pause.clone()
+    }
+
+    /// Resumes `model_id`, returning its pause if it was paused.
+    pub fn resume(&self, model_id: &ModelId) -> Option<ModelPause> {
+        self.paused.remove(model_id).map(|(_, pause)| pause)
+    }
+
+    /// Refuses requests for `model_id` while it is paused.
+    pub fn check(&self, model_id: &ModelId) -> Result<(), ModelPaused> {
+        match self.paused.get(model_id) {
+            Some(pause) => Err(ModelPaused {
+                model: model_id.0.clone(),
+                retry_after: Duration::from_secs(pause.retry_after_secs),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Every paused model, sorted by name.
+    pub fn list(&self) -> Vec<ModelPause> {
+        let mut paused: Vec<ModelPause> = self
+            .paused
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        paused.sort_by(|a, b| a.model.cmp(&b.model));
+        paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paused_models_are_refused_until_resumed() {
+        let pauses = Pauses::default();
+        let model = ModelId::from_string("m".to_string());
+        assert_eq!(pauses.check(&model), Ok(()));
+
+        let pause = pauses.pause(&model, Duration::from_secs(30));
+        assert_eq!(pause.retry_after_secs, 30);
+        let refusal = pauses.check(&model).unwrap_err();
+        assert_eq!(refusal.retry_after, Duration::from_secs(30));
+        assert_eq!(refusal.to_string(), "Model 'm' is paused, retry in 30 s");
+
+        // Pausing again keeps the start of the pause.
+        let again = pauses.pause(&model, Duration::ZERO);
+        assert_eq!(
+            (again.since_ms, again.retry_after_secs),
+            (pause.since_ms, 1)
+        );
+        assert_eq!(pauses.list(), vec![again]);
+
+        assert!(pauses.resume(&model).is_some());
+        assert!(pauses.resume(&model).is_none());
+        assert_eq!(pauses.check(&model), Ok(()));
+        assert!(pauses.list().is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+/* Minimal parser for the protobuf text format used by `config.pbtxt` files.
+
+The text is parsed without a schema into a JSON value: messages become
+objects, scalars become strings, numbers or booleans, and enum values are kept
+as strings. A field that appears more than once becomes an array; since a
+single occurrence of a repeated field cannot be told apart from a singular
+one, readers should accept both (see `as_list`).
+*/
+
+use anyhow::{Result, anyhow};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Punct(char),
+}
+
+/// Parses a text-format message into a JSON object.
+pub fn parse(input: &str) -> Result<Value> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let message = parser.message(None)?;
+    Ok(message)
+}
+
+/// Values of a possibly repeated field: arrays as-is, a single value as a one-element list.
+pub fn as_list(value: Option<&Value>) -> Vec<&Value> {
+    match value {
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => while chars.next().is_some_and(|c| c != '\n') {},
+            '{' | '}' | '[' | ']' | ':' | ',' | ';' | '<' | '>' => {
+                chars.next();
+                tokens.push(Token::Punct(c));
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(escaped) => value.push(escaped),
+                            None => return Err(anyhow!("Unterminated string in pbtxt")),
+                        },
+                        Some(ch) if ch == c => break,
+                        Some(ch) => value.push(ch),
+                        None => return Err(anyhow!("Unterminated string in pbtxt")),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut value = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '+' | '.') {
+                        value.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Num(value));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut value = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_ascii_alphanumeric() || matches!(ch, '_' | '.') {
+                        value.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(value));
+            }
+            other => return Err(anyhow!("Unexpected character '{}' in pbtxt", other)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parses fields until `end` (or the end of input for the top-level message).
+    fn message(&mut self, end: Option<char>) -> Result<Value> {
+        let mut fields: Vec<(String, Vec<Value>)> = Vec::new();
+
+        loop {
+            match (self.peek(), end) {
+                (None, None) => break,
+                (None, Some(end)) => return Err(anyhow!("Expected '{}' in pbtxt", end)),
+                (Some(Token::Punct(c)), Some(end)) if *c == end => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => {}
+            }
+
+            let name = match self.next() {
+                Some(Token::Ident(name)) => name,
+                other => return Err(anyhow!("Expected a field name in pbtxt, found {:?}", other)),
+            };
+            let has_colon = self.eat(':');
+            let value = match self.peek() {
+                Some(Token::Punct('{')) | Some(Token::Punct('<')) => self.nested()?,
+                Some(Token::Punct('[')) => {
+                    self.pos += 1;
+                    self.list()?
+                }
+                _ if has_colon => self.scalar()?,
+                other => return Err(anyhow!("Expected ':' after '{}', found {:?}", name, other)),
+            };
+            // Separators between fields are optional.
+            if !self.eat(',') {
+                self.eat(';');
+            }
+
+            match fields.iter_mut().find(|(field, _)| *field == name) {
+                Some((_, values)) => values.push(value),
+                None => fields.push((name, vec![value])),
+            }
+        }
+
+        let mut object = Map::new();
+        for (name, mut values) in fields {
+            let value = if values.len() == 1 {
+                values.remove(0)
+            } else {
+                // A repeated field written as several lists is still one list.
+                Value::Array(
+                    values
+                        .into_iter()
+                        .flat_map(|value| match value {
+                            Value::Array(items) => items,
+                            item => vec![item],
+                        })
+                        .collect(),
+                )
+            };
+            object.insert(name, value);
+        }
+        Ok(Value::Object(object))
+    }
+
+    fn nested(&mut self) -> Result<Value> {
+        match self.next() {
+            Some(Token::Punct('{')) => self.message(Some('}')),
+            Some(Token::Punct('<')) => self.message(Some('>')),
+            other => Err(anyhow!("Expected a message in pbtxt, found {:?}", other)),
+        }
+    }
+
+    fn list(&mut self) -> Result<Value> {
+        let mut items = Vec::new();
+        while !self.eat(']') {
+            let item = match self.peek() {
+                Some(Token::Punct('{')) | Some(Token::Punct('<')) => self.nested()?,
+                Some(_) => self.scalar()?,
+                None => return Err(anyhow!("Expected ']' in pbtxt")),
+            };
+            items.push(item);
+            self.eat(',');
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn scalar(&mut self) -> Result<Value> {
+        match self.next() {
+            Some(Token::Str(value)) => {
+                // Adjacent string literals are concatenated.
+                let mut value = value;
+                while let Some(Token::Str(next)) = self.peek() {
+                    value.push_str(next);
+                    self.pos += 1;
+                }
+                Ok(Value::String(value))
+            }
+            Some(Token::Num(value)) => parse_number(&value),
+            Some(Token::Ident(value)) => Ok(match value.as_str() {
+                "true" | "True" | "t" => Value::Bool(true),
+                "false" | "False" | "f" => Value::Bool(false),
+                _ => Value::String(value),
+            }),
+            other => Err(anyhow!("Expected a value in pbtxt, found {:?}", other)),
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<Value> {
+    if let Ok(integer) = value.parse::<i64>() {
+        return Ok(Value::from(integer));
+    }
+    value
+        .trim_end_matches(['f', 'F'])
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .ok_or_else(|| anyhow!("Invalid number '{}' in pbtxt", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_triton_config() {
+        let value = parse(
+            r#"
+name: "resnet"  # trailing comment
+platform: "onnxruntime_onnx"
+max_batch_size: 8
+input [
+  {
+    name: "input"
+    data_type: TYPE_FP32
+    dims: [ 3, 224, 224 ]
+  }
+]
+output {
+  name: "probabilities"
+  data_type: TYPE_FP32
+  dims: [ 1000 ]
+}
+output {
+  name: "label"
+  data_type: TYPE_STRING
+  dims: [ 1 ]
+}
+instance_group [ { count: 2, kind: KIND_GPU } ]
+dynamic_batching { }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(value["name"], "resnet");
+        assert_eq!(value["max_batch_size"], 8);
+        assert_eq!(value["input"][0]["dims"], json!([3, 224, 224]));
+        assert_eq!(value["output"][1]["data_type"], "TYPE_STRING");
+        assert_eq!(value["instance_group"][0]["kind"], "KIND_GPU");
+        assert_eq!(value["dynamic_batching"], json!({}));
+    }
+
+    #[test]
+    fn test_as_list_accepts_single_and_repeated() {
+        let value = parse("dims: 4 input { name: \"a\" } input { name: \"b\" }").unwrap();
+        assert_eq!(as_list(value.get("dims")), vec![&json!(4)]);
+        assert_eq!(as_list(value.get("input")).len(), 2);
+        assert!(as_list(value.get("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("name: \"unterminated").is_err());
+        assert!(parse("input { name: \"a\"").is_err());
+        assert!(parse("name \"missing colon\"").is_err());
+    }
+}
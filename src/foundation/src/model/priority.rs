@@ -0,0 +1,286 @@
+/* Priority classes of inference requests.
+
+Clients mark a request as `high` (latency-sensitive, e.g. interactive),
+`normal` (the default) or `low` (bulk or batch jobs) with the
+`x-galemind-priority` header or gRPC metadata entry. The request buffer of a
+model keeps one queue per class and hands out the oldest request of the
+highest non-empty class first.
+
+Starvation protection: a request that has waited longer than `max_wait` is
+handed out before any request that has not, whatever its class, so bulk
+traffic still progresses under a steady stream of latency-sensitive requests.
+
+When the buffer is full, a new request evicts the newest request of the
+lowest class below its own. Failing that, the model's overflow policy
+decides: `drop_oldest` evicts the oldest request of its own class, as the
+plain circular buffer does, and every other policy turns the new request
+away.
+*/
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::model::buffer_tuning::RequestBuffer;
+use crate::model::circular_buffer::OverflowPolicy;
+
+/// Header (or gRPC metadata entry) selecting the priority class of a request.
+pub const PRIORITY_HEADER: &str = "x-galemind-priority";
+
+/// Default wait after which a request is handed out before fresher ones of higher classes.
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    /// Highest class first.
+    const DESCENDING: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromStr for Priority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "high" | "interactive" => Ok(Self::High),
+            "normal" | "default" => Ok(Self::Normal),
+            "low" | "bulk" | "batch" => Ok(Self::Low),
+            other => Err(anyhow!(
+                "Unknown priority '{}', expected high, normal or low",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::High => write!(f, "high"),
+            Self::Normal => write!(f, "normal"),
+            Self::Low => write!(f, "low"),
+        }
+    }
+}
+
+/// Items queued in a `PriorityBuffer`.
+pub trait Prioritized {
+    fn priority(&self) -> Priority;
+}
+
+/// Bounded per-class queues, see the module documentation.
+#[derive(Debug)]
+pub struct PriorityBuffer<T> {
+    queues: [VecDeque<(T, Instant)>; 3],
+    capacity: usize,
+    max_wait: Duration,
+}
+
+impl<T: Prioritized> PriorityBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queues: Default::default(),
+            capacity,
+            max_wait: DEFAULT_MAX_WAIT,
+        }
+    }
+
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    pub fn set_max_wait(&mut self, max_wait: Duration) {
+        self.max_wait = max_wait;
+    }
+
+    /// Queues `item`, returning the request evicted to make room for it, if any. When
+    /// no request can be evicted, `item` is handed back instead.
+    pub fn push_at(
+        &mut self,
+        item: T,
+        now: Instant,
+        overflow: OverflowPolicy,
+    ) -> Result<Option<T>, T> {
+        let priority = item.priority();
+        let mut evicted = None;
+        if self.len() >= self.capacity {
+            evicted = self.evict_for(priority, overflow);
+            if evicted.is_none() {
+                return Err(item);
+            }
+        }
+        self.queues[priority.index()].push_back((item, now));
+        Ok(evicted)
+    }
+
+    /// Takes the next request to dispatch at `now`.
+    pub fn pop(&mut self, now: Instant) -> Option<T> {
+        // The longest-waiting request past `max_wait`, across classes.
+        let starving = Priority::DESCENDING
+            .iter()
+            .filter_map(|priority| {
+                let (_, queued) = self.queues[priority.index()].front()?;
+                (now.saturating_duration_since(*queued) > self.max_wait)
+                    .then_some((*queued, *priority))
+            })
+            .min_by_key(|(queued, _)| *queued)
+            .map(|(_, priority)| priority);
+
+        let priority = starving.or_else(|| {
+            Priority::DESCENDING
+                .into_iter()
+                .find(|priority| !self.queues[priority.index()].is_empty())
+        })?;
+        self.queues[priority.index()]
+            .pop_front()
+            .map(|(item, _)| item)
+    }
+
+    /// Queued requests per class, highest first.
+    pub fn queued(&self) -> [(Priority, usize); 3] {
+        Priority::DESCENDING.map(|priority| (priority, self.queues[priority.index()].len()))
+    }
+
+    fn evict_for(&mut self, priority: Priority, overflow: OverflowPolicy) -> Option<T> {
+        let lower = Priority::DESCENDING
+            .into_iter()
+            .rev()
+            .take_while(|lower| *lower < priority)
+            .find(|lower| !self.queues[lower.index()].is_empty());
+        match lower {
+            Some(lower) => self.queues[lower.index()].pop_back(),
+            None if overflow.evicts() => self.queues[priority.index()].pop_front(),
+            None => None,
+        }
+        .map(|(item, _)| item)
+    }
+}
+
+impl<T: Prioritized> RequestBuffer<T> for PriorityBuffer<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self::new(capacity)
+    }
+
+    fn push(&mut self, item: T, overflow: OverflowPolicy) -> Result<Option<T>, T> {
+        self.push_at(item, Instant::now(), overflow)
+    }
+
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity; when shrinking, requests are evicted lowest class first.
+    fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.len() > capacity {
+            let lowest = Priority::DESCENDING
+                .into_iter()
+                .rev()
+                .find(|priority| !self.queues[priority.index()].is_empty());
+            if let Some(lowest) = lowest {
+                self.queues[lowest.index()].pop_back();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Prioritized for (Priority, u32) {
+        fn priority(&self) -> Priority {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_higher_classes_first_with_aging() {
+        let start = Instant::now();
+        let mut buffer = PriorityBuffer::new(8).with_max_wait(Duration::from_millis(100));
+        let arrived = start + Duration::from_millis(100);
+        let overflow = OverflowPolicy::DropOldest;
+        for (item, at) in [
+            ((Priority::Low, 1), start),
+            ((Priority::Normal, 2), arrived),
+            ((Priority::High, 3), arrived),
+            ((Priority::High, 4), arrived),
+        ] {
+            assert_eq!(buffer.push_at(item, at, overflow), Ok(None));
+        }
+
+        assert_eq!(buffer.pop(arrived), Some((Priority::High, 3)));
+        // The low request has waited too long and goes before the remaining high one.
+        let later = start + Duration::from_millis(150);
+        assert_eq!(buffer.pop(later), Some((Priority::Low, 1)));
+        assert_eq!(buffer.pop(later), Some((Priority::High, 4)));
+        assert_eq!(buffer.pop(later), Some((Priority::Normal, 2)));
+        assert_eq!(buffer.pop(later), None);
+    }
+
+    #[test]
+    fn test_full_buffer_sheds_lowest_class() {
+        let now = Instant::now();
+        let drop_oldest = OverflowPolicy::DropOldest;
+        let mut buffer = PriorityBuffer::new(2);
+        assert_eq!(
+            buffer.push_at((Priority::Low, 1), now, drop_oldest),
+            Ok(None)
+        );
+        assert_eq!(
+            buffer.push_at((Priority::Low, 2), now, drop_oldest),
+            Ok(None)
+        );
+        // The newest low request makes room for a normal one, whatever the policy.
+        assert_eq!(
+            buffer.push_at((Priority::Normal, 3), now, OverflowPolicy::Reject),
+            Ok(Some((Priority::Low, 2)))
+        );
+        // A low request does not displace a normal one, only an older low one.
+        assert_eq!(
+            buffer.push_at((Priority::Low, 4), now, drop_oldest),
+            Ok(Some((Priority::Low, 1)))
+        );
+        assert_eq!(
+            buffer.push_at((Priority::Low, 5), now, OverflowPolicy::DropNewest),
+            Err((Priority::Low, 5))
+        );
+        assert_eq!(
+            buffer.push_at((Priority::Normal, 6), now, drop_oldest),
+            Ok(Some((Priority::Low, 4)))
+        );
+        assert_eq!(
+            buffer.push_at((Priority::Low, 7), now, drop_oldest),
+            Err((Priority::Low, 7))
+        );
+        assert_eq!(
+            buffer.push_at((Priority::Normal, 8), now, OverflowPolicy::Reject),
+            Err((Priority::Normal, 8))
+        );
+
+        buffer.resize(1);
+        assert_eq!(buffer.queued()[1], (Priority::Normal, 1));
+        assert_eq!(buffer.pop(now), Some((Priority::Normal, 3)));
+    }
+}
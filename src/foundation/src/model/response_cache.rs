@@ -0,0 +1,409 @@
+/* Cache of the responses of deterministic models.
+
+A model whose outputs depend only on its inputs can have its responses
+cached, so repeated requests are answered without running it:
+
+```yaml
+response_cache:
+  max_entries: 1024   # responses kept in memory, least recently used evicted first
+  ttl_secs: 300       # how long a response is served from the cache
+```
+
+Requests are keyed on the model, the version serving them, their input
+tensors and their parameters, in a canonical form: the order of inputs and
+parameters does not matter. Their id, priority and deadline are not part of
+the key. Neither is their tenant, unless the model's outputs are watermarked
+for it. Only successful responses are cached. A cached response is returned
+before the request is buffered, so it takes no dispatch slot.
+
+With `with_redis`, the responses are kept in a Redis server instead, shared
+by every server using it. Entries then expire in Redis, and `max_entries` is
+left to its eviction policy. A Redis server that fails or is slow to answer
+is treated as a miss, so the request runs as if the response were not cached.
+
+Hits and misses are counted per model, as
+`galemind_response_cache_hits_total` and `galemind_response_cache_misses_total`.
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::model_discovery_service::{ModelId, ModelVersionId};
+use crate::api::inference::{InferenceOutput, InferenceRequest};
+use crate::redis::{RedisClient, Reply};
+use crate::stats::escape_label;
+
+/// Longest a Redis command may take before the cache is bypassed.
+const REDIS_TIMEOUT: Duration = Duration::from_millis(250);
+const REDIS_KEY_PREFIX: &str = "galemind:response";
+
+fn default_max_entries() -> usize {
+    1024
+}
+
+fn default_ttl_secs() -> u64 {
+    300
+}
+
+/// Response caching of a model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: default_max_entries(),
+            ttl_secs: default_ttl_secs(),
+        }
+    }
+}
+
+impl ResponseCacheConfig {
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+}
+
+/// Key of the response to `request` run by `version_id`, the hex SHA-256 of its canonical
+/// form. `tenant` is part of it when the response is particular to the tenant.
+pub fn cache_key(
+    version_id: &ModelVersionId,
+    request: &InferenceRequest,
+    tenant: Option<&str>,
+) -> String {
+    let mut inputs: Vec<&InferenceOutput> = request.outputs.iter().flatten().collect();
+    inputs.sort_by(|a, b| a.name.cmp(&b.name));
+    let parameters: BTreeMap<_, _> = request.parameters.iter().flatten().collect();
+    // Objects of JSON values are sorted by key, including the parameters of the inputs.
+    let canonical = serde_json::to_value((
+        &version_id.model.0,
+        &version_id.version,
+        tenant,
+        inputs,
+        parameters,
+    ))
+    .map(|value| value.to_string())
+    .unwrap_or_default();
+    hex::encode(Sha256::digest(canonical.as_bytes()))
+}
+
+struct CachedResponse {
+    output: InferenceOutput,
+    expires: Instant,
+    /// Tick of the last use, its position in `Lru::order`.
+    used: u64,
+}
+
+/// Responses of a model, evicted least recently used first.
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, CachedResponse>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str, now: Instant) -> Option<InferenceOutput> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires <= now {
+            self.order.remove(&entry.used);
+            self.entries.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        self.order.remove(&entry.used);
+        entry.used = self.tick;
+        self.order.insert(self.tick, key.to_string());
+        Some(entry.output.clone())
+    }
+
+    fn put(&mut self, key: String, output: InferenceOutput, expires: Instant, capacity: usize) {
+        self.tick += 1;
+        if let Some(previous) = self.entries.insert(
+            key.clone(),
+            CachedResponse {
+                output,
+                expires,
+                used: self.tick,
+            },
+        ) {
+            self.order.remove(&previous.used);
+        }
+        self.order.insert(self.tick, key);
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Cached responses of the models configuring `response_cache`, in memory or in Redis.
+#[derive(Default)]
+pub struct ResponseCache {
+    memory: DashMap<ModelId, Mutex<Lru>>,
+    redis: Option<RedisClient>,
+    counters: DashMap<ModelId, CacheCounters>,
+}
+
+impl ResponseCache {
+    /// Keeps the responses in the Redis server at `url`,
+    /// `redis://[[user]:password@]host[:port][/db]`, instead of memory.
+    pub fn with_redis(mut self, url: &str) -> Result<Self> {
+        self.redis = Some(RedisClient::from_uri(url)?);
+        Ok(self)
+    }
+
+    /// Runs a command on the Redis server, giving up after `REDIS_TIMEOUT`.
+    async fn command(redis: &RedisClient, args: &[&[u8]]) -> Result<Reply> {
+        tokio::time::timeout(REDIS_TIMEOUT, redis.command(args))
+            .await
+            .map_err(|_| anyhow!("Redis did not answer within {:?}", REDIS_TIMEOUT))?
+    }
+
+    fn redis_key(model_id: &ModelId, key: &str) -> String {
+        format!("{}:{}:{}", REDIS_KEY_PREFIX, model_id, key)
+    }
+
+    /// The cached response of `key` for `model_id`, counting a hit or a miss.
+    pub async fn get(&self, model_id: &ModelId, key: &str) -> Option<InferenceOutput> {
+        let output = match &self.redis {
+            Some(redis) => {
+                match Self::command(redis, &[b"GET", Self::redis_key(model_id, key).as_bytes()])
+                    .await
+                {
+                    Ok(Reply::Bulk(Some(value))) => serde_json::from_slice(&value).ok(),
+                    Ok(_) => None,
+                    Err(e) => {
+                        eprintln!("Response cache of model {}: {}", model_id, e);
+                        None
+                    }
+                }
+            }
+            None => self
+                .memory
+                .get(model_id)
+                .and_then(|lru| lru.lock().unwrap().get(key, Instant::now())),
+        };
+        let counters = self.counters.entry(model_id.clone()).or_default();
+        let counter = if output.is_some() {
+            &counters.hits
+        } else {
+            &counters.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        output
+    }
+
+    /// Caches `output` as the response of `key` for `model_id`.
+    pub async fn put(
+        &self,
+        model_id: &ModelId,
+        config: &ResponseCacheConfig,
+        key: &str,
+        output: InferenceOutput,
+    ) {
+        if config.ttl_secs == 0 || config.max_entries == 0 {
+            return;
+        }
+        match &self.redis {
+            Some(redis) => {
+                let Ok(value) = serde_json::to_vec(&output) else {
+                    return;
+                };
+                let result = Self::command(
+                    redis,
+                    &[
+                        b"SET",
+                        Self::redis_key(model_id, key).as_bytes(),
+                        &value,
+                        b"EX",
+                        config.ttl_secs.to_string().as_bytes(),
+                    ],
+                )
+                .await;
+                if let Err(e) = result {
+                    eprintln!("Response cache of model {}: {}", model_id, e);
+                }
+            }
+            None => self
+                .memory
+                .entry(model_id.clone())
+                .or_default()
+                .lock()
+                .unwrap()
+                .put(
+                    key.to_string(),
+                    output,
+                    Instant::now() + config.ttl(),
+                    config.max_entries,
+                ),
+        }
+    }
+
+    /// Hits and misses of the cache of `model_id`.
+    pub fn hits_and_misses(&self, model_id: &ModelId) -> (u64, u64) {
+        self.counters.get(model_id).map_or((0, 0), |counters| {
+            (
+                counters.hits.load(Ordering::Relaxed),
+                counters.misses.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// Hits and misses of every model in the Prometheus text exposition format.
+    pub fn render(&self, out: &mut String) {
+        let mut models: Vec<ModelId> = self.counters.iter().map(|c| c.key().clone()).collect();
+        models.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, help, hits) in [
+            (
+                "galemind_response_cache_hits_total",
+                "Requests answered from the response cache of a model.",
+                true,
+            ),
+            (
+                "galemind_response_cache_misses_total",
+                "Requests of a model caching its responses that were not cached.",
+                false,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for model_id in &models {
+                let (hit_count, miss_count) = self.hits_and_misses(model_id);
+                let _ = writeln!(
+                    out,
+                    "{}{{model=\"{}\"}} {}",
+                    name,
+                    escape_label(&model_id.0),
+                    if hits { hit_count } else { miss_count }
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::inference::InferParameter;
+    use crate::api::tensor::{Data, DataType};
+
+    fn output(value: f64) -> InferenceOutput {
+        InferenceOutput {
+            name: "y".to_string(),
+            shape: vec![1],
+            datatype: DataType::VFLOAT,
+            parameters: None,
+            data: Data::VFLOAT(vec![value]),
+        }
+    }
+
+    fn request(parameters: &[(&str, i64)]) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: None,
+            id: "1".to_string(),
+            parameters: Some(
+                parameters
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), InferParameter::Int64(*value)))
+                    .collect(),
+            ),
+            outputs: Some(vec![output(1.0)]),
+            timeline: None,
+            priority: Default::default(),
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_keys_are_canonical() {
+        let version = ModelVersionId::new("m", "1");
+        let key = cache_key(&version, &request(&[("a", 1), ("b", 2)]), None);
+        let mut other = request(&[("b", 2), ("a", 1)]);
+        other.id = "2".to_string();
+        other.tenant = Some("acme".to_string());
+        assert_eq!(key, cache_key(&version, &other, None));
+        assert_ne!(key, cache_key(&version, &other, Some("acme")));
+        assert_ne!(key, cache_key(&version, &request(&[("a", 1)]), None));
+        assert_ne!(
+            key,
+            cache_key(
+                &ModelVersionId::new("m", "2"),
+                &request(&[("a", 1), ("b", 2)]),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_least_recently_used_responses_are_evicted() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+        let mut lru = Lru::default();
+        lru.put("a".to_string(), output(1.0), later, 2);
+        lru.put("b".to_string(), output(2.0), later, 2);
+        assert!(lru.get("a", now).is_some());
+        lru.put("c".to_string(), output(3.0), later, 2);
+        assert!(lru.get("b", now).is_none());
+        assert!(lru.get("a", now).is_some());
+        assert!(lru.get("c", now).is_some());
+        // Expired responses are dropped.
+        assert!(lru.get("a", later).is_none());
+        assert_eq!(lru.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hits_and_misses_are_counted() {
+        let cache = ResponseCache::default();
+        let model = ModelId("m".to_string());
+        let config = ResponseCacheConfig::default();
+        assert!(cache.get(&model, "k").await.is_none());
+        cache.put(&model, &config, "k", output(4.0)).await;
+        let cached = cache.get(&model, "k").await.unwrap();
+        assert!(matches!(cached.data, Data::VFLOAT(values) if values == vec![4.0]));
+        assert_eq!(cache.hits_and_misses(&model), (1, 1));
+
+        let mut metrics = String::new();
+        cache.render(&mut metrics);
+        assert!(metrics.contains("galemind_response_cache_hits_total{model=\"m\"} 1"));
+        assert!(metrics.contains("galemind_response_cache_misses_total{model=\"m\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_shared_through_redis() {
+        let addr = crate::redis::tests::fake_redis().await;
+        let url = format!("redis://:secret@{}", addr);
+        let cache = ResponseCache::default().with_redis(&url).unwrap();
+        let model = ModelId("m".to_string());
+        assert!(cache.get(&model, "k").await.is_none());
+        cache
+            .put(&model, &ResponseCacheConfig::default(), "k", output(4.0))
+            .await;
+        let cached = cache.get(&model, "k").await.unwrap();
+        assert!(matches!(cached.data, Data::VFLOAT(values) if values == vec![4.0]));
+        assert_eq!(cache.hits_and_misses(&model), (1, 1));
+        assert!(ResponseCache::default().with_redis("http://cache").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+/* Shared storage of asynchronous inference results.
+
+`ResultStore` keeps results in the memory of the server that ran them, so they
+are lost when it restarts and unknown to its replicas. A `ResultBackend` keeps
+them where every replica reads them, with the same time-to-live: results are
+written to it when an inference is accepted and once it completes, and read
+from it when the server asking has no such result in memory.
+
+`RedisResultBackend` stores them in Redis (`--result-store redis://...`), as
+`galemind:result:<id>` keys expiring with the result, through a `RedisClient`.
+*/
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+
+use crate::redis::{RedisClient, Reply};
+
+const KEY_PREFIX: &str = "galemind:result:";
+
+#[async_trait]
+pub trait ResultBackend: Send + Sync + fmt::Debug {
+    /// Stores the encoded `record` of result `id`, replacing any previous one, until `ttl`
+    /// passes.
+    async fn put(&self, id: &str, record: &[u8], ttl: Duration) -> Result<()>;
+    /// The record of result `id`, None when it is unknown or expired.
+    async fn get(&self, id: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// Results stored in Redis.
+#[derive(Debug)]
+pub struct RedisResultBackend {
+    client: RedisClient,
+}
+
+impl RedisResultBackend {
+    /// Parses `redis://[[user]:password@]host[:port][/db]`.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        Ok(Self {
+            client: RedisClient::from_uri(uri)?,
+        })
+    }
+}
+
+#[async_trait]
+impl ResultBackend for RedisResultBackend {
+    async fn put(&self, id: &str, record: &[u8], ttl: Duration) -> Result<()> {
+        let key = format!("{}{}", KEY_PREFIX, id);
+        let ttl = ttl.as_millis().max(1).to_string();
+        match self
+            .client
+            .command(&[b"SET", key.as_bytes(), record, b"PX", ttl.as_bytes()])
+            .await?
+        {
+            Reply::Simple(_) => Ok(()),
+            reply => Err(anyhow!("Unexpected Redis reply to SET: {:?}", reply)),
+        }
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let key = format!("{}{}", KEY_PREFIX, id);
+        match self.client.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(record) => Ok(record),
+            reply => Err(anyhow!("Unexpected Redis reply to GET: {:?}", reply)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::tests::fake_redis;
+
+    #[tokio::test]
+    async fn test_redis_backend_stores_results() {
+        let addr = fake_redis().await;
+        let backend = RedisResultBackend::from_uri(&format!("redis://:secret@{}", addr)).unwrap();
+        assert_eq!(backend.get("r1").await.unwrap(), None);
+        backend
+            .put("r1", b"{\"status\":\"pending\"}", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.get("r1").await.unwrap().as_deref(),
+            Some(&b"{\"status\":\"pending\"}"[..])
+        );
+        assert!(RedisResultBackend::from_uri("http://cache").is_err());
+    }
+}
@@ -0,0 +1,478 @@
+/* In-memory store for results of asynchronously executed inferences.
+
+Each entry is created as pending when a request is accepted and completed
+once its result is available. Readers can either peek at the current state
+or wait (long-poll) for completion with a timeout. Completed entries are kept
+for a fixed time-to-live and then purged lazily on subsequent inserts.
+
+`StreamStore` keeps the chunks of streamed responses the same way, so a
+client whose stream dropped can resume it with its token: the chunks after the
+last one it received are replayed, then it follows the stream live. Finished
+streams stay resumable for their own, shorter time-to-live.
+
+Streams are paced by their readers (`StreamPacing`). The producer of a stream
+reserves a slot before producing each chunk, and a slot is freed when a reader
+delivers a chunk to its client. At most `max_buffered` chunks are thus produced
+and not yet delivered, so a slow client slows the producer down instead of
+chunks piling up in memory. A producer that finds no free slot for
+`stall_timeout`, because the client stopped reading or went away without
+resuming, gives up.
+*/
+
+use dashmap::DashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::ids::{IdProvider, IdScheme};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultState<T> {
+    Pending,
+    Completed(T),
+}
+
+struct ResultEntry<T> {
+    sender: watch::Sender<Option<T>>,
+    completed_at: Option<Instant>,
+}
+
+pub struct ResultStore<T> {
+    entries: DashMap<String, ResultEntry<T>>,
+    ttl: Duration,
+    ids: Arc<dyn IdProvider>,
+}
+
+impl<T: Clone> ResultStore<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl,
+            ids: IdScheme::default().provider(),
+        }
+    }
+
+    /// Generates entry ids with `ids` instead of the default UUIDv7 provider.
+    pub fn with_id_provider(mut self, ids: Arc<dyn IdProvider>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Creates a pending entry and returns its generated id.
+    pub fn insert_pending(&self) -> String {
+        self.purge_expired();
+
+        let id = self.ids.next_id();
+        let (sender, _) = watch::channel(None);
+        self.entries.insert(
+            id.clone(),
+            ResultEntry {
+                sender,
+                completed_at: None,
+            },
+        );
+        id
+    }
+
+    /// Stores the result for `id`, waking up any waiters. Returns false for unknown ids.
+    pub fn complete(&self, id: &str, result: T) -> bool {
+        match self.entries.get_mut(id) {
+            Some(mut entry) => {
+                entry.sender.send_replace(Some(result));
+                entry.completed_at = Some(Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<ResultState<T>> {
+        self.entries
+            .get(id)
+            .map(|entry| match entry.sender.borrow().as_ref() {
+                Some(result) => ResultState::Completed(result.clone()),
+                None => ResultState::Pending,
+            })
+    }
+
+    /// Waits up to `timeout` for `id` to complete, returning its state at that point.
+    pub async fn wait(&self, id: &str, timeout: Duration) -> Option<ResultState<T>> {
+        let mut receiver = self.entries.get(id)?.sender.subscribe();
+
+        let completed = tokio::time::timeout(timeout, receiver.wait_for(Option::is_some)).await;
+        match completed {
+            Ok(Ok(result)) => result.clone().map(ResultState::Completed),
+            _ => self.get(id),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn purge_expired(&self) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, entry| {
+            entry
+                .completed_at
+                .is_none_or(|completed_at| completed_at.elapsed() < ttl)
+        });
+    }
+}
+
+/// Chunks of a stream after the last one a reader has seen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamChunks<T> {
+    /// Chunks with their ids, which count from 0 in the order they were appended.
+    pub chunks: Vec<(u64, T)>,
+    /// No chunks will be appended anymore.
+    pub finished: bool,
+}
+
+/// Flow control of streams, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamPacing {
+    /// Chunks produced, or being produced, and not yet delivered to a client.
+    pub max_buffered: usize,
+    /// How long a producer waits for a free slot before giving up.
+    pub stall_timeout: Duration,
+}
+
+impl Default for StreamPacing {
+    fn default() -> Self {
+        Self {
+            max_buffered: 16,
+            stall_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Why a producer could not reserve a slot for its next chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStall {
+    /// No chunk was delivered for the stall timeout while the buffer was full.
+    Stalled(Duration),
+    /// The stream is unknown or was purged.
+    Gone,
+}
+
+impl fmt::Display for StreamStall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stalled(timeout) => write!(
+                f,
+                "The client read nothing for {}s, the rest of the stream was not produced",
+                timeout.as_secs()
+            ),
+            Self::Gone => f.write_str("The stream is gone"),
+        }
+    }
+}
+
+struct StreamEntry<T> {
+    chunks: Vec<T>,
+    finished_at: Option<Instant>,
+    /// Signalled on every append and when the stream finishes.
+    updates: watch::Sender<()>,
+    /// Slots reserved by the producer for chunks it has yet to append.
+    reserved: usize,
+    /// Chunks delivered to a client, counted from the first.
+    delivered: watch::Sender<u64>,
+}
+
+impl<T> StreamEntry<T> {
+    fn buffered(&self) -> usize {
+        let delivered = usize::try_from(*self.delivered.borrow()).unwrap_or(usize::MAX);
+        self.chunks.len().saturating_sub(delivered) + self.reserved
+    }
+}
+
+pub struct StreamStore<T> {
+    entries: DashMap<String, StreamEntry<T>>,
+    ttl: Duration,
+    ids: Arc<dyn IdProvider>,
+    pacing: StreamPacing,
+}
+
+impl<T: Clone> StreamStore<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl,
+            ids: IdScheme::default().provider(),
+            pacing: StreamPacing::default(),
+        }
+    }
+
+    pub fn with_pacing(mut self, pacing: StreamPacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    pub fn pacing(&self) -> StreamPacing {
+        self.pacing
+    }
+
+    /// Generates stream tokens with `ids` instead of the default UUIDv7 provider.
+    pub fn with_id_provider(mut self, ids: Arc<dyn IdProvider>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Starts an empty stream and returns its token.
+    pub fn open(&self) -> String {
+        self.purge_expired();
+
+        let token = self.ids.next_id();
+        let (updates, _) = watch::channel(());
+        self.entries.insert(
+            token.clone(),
+            StreamEntry {
+                chunks: Vec::new(),
+                finished_at: None,
+                updates,
+                reserved: 0,
+                delivered: watch::channel(0).0,
+            },
+        );
+        token
+    }
+
+    /// Waits for a free slot for the next chunk of the stream and reserves it, see the
+    /// module documentation. `append` uses up the reservation.
+    pub async fn reserve(&self, token: &str) -> Result<(), StreamStall> {
+        let mut delivered = self
+            .entries
+            .get(token)
+            .ok_or(StreamStall::Gone)?
+            .delivered
+            .subscribe();
+        loop {
+            delivered.borrow_and_update();
+            {
+                let mut entry = self.entries.get_mut(token).ok_or(StreamStall::Gone)?;
+                if entry.buffered() < self.pacing.max_buffered.max(1) {
+                    entry.reserved += 1;
+                    return Ok(());
+                }
+            }
+            match tokio::time::timeout(self.pacing.stall_timeout, delivered.changed()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => return Err(StreamStall::Gone),
+                Err(_) => return Err(StreamStall::Stalled(self.pacing.stall_timeout)),
+            }
+        }
+    }
+
+    /// Records that a reader delivered the chunk with id `id` to its client, freeing its
+    /// slot. True the first time the chunk is delivered, false when it is delivered again to
+    /// a resuming client.
+    pub fn acknowledge(&self, token: &str, id: u64) -> bool {
+        self.entries.get(token).is_some_and(|entry| {
+            entry.delivered.send_if_modified(|delivered| {
+                let modified = id >= *delivered;
+                *delivered = (*delivered).max(id.saturating_add(1));
+                modified
+            })
+        })
+    }
+
+    /// Appends `chunk` to the stream, returning its id. None for unknown or finished streams.
+    pub fn append(&self, token: &str, chunk: T) -> Option<u64> {
+        let mut entry = self.entries.get_mut(token)?;
+        if entry.finished_at.is_some() {
+            return None;
+        }
+        entry.reserved = entry.reserved.saturating_sub(1);
+        entry.chunks.push(chunk);
+        entry.updates.send_replace(());
+        Some(entry.chunks.len() as u64 - 1)
+    }
+
+    /// Marks the stream as complete, waking up its readers.
+    pub fn finish(&self, token: &str) {
+        if let Some(mut entry) = self.entries.get_mut(token) {
+            entry.finished_at.get_or_insert_with(Instant::now);
+            entry.updates.send_replace(());
+        }
+    }
+
+    /// Chunks appended after the chunk with id `after`, or all of them when `after` is None.
+    pub fn read_after(&self, token: &str, after: Option<u64>) -> Option<StreamChunks<T>> {
+        let entry = self.entries.get(token)?;
+        let start = after.map_or(0, |after| after.saturating_add(1));
+        let chunks = entry
+            .chunks
+            .iter()
+            .enumerate()
+            .skip(usize::try_from(start).unwrap_or(usize::MAX))
+            .map(|(id, chunk)| (id as u64, chunk.clone()))
+            .collect();
+        Some(StreamChunks {
+            chunks,
+            finished: entry.finished_at.is_some(),
+        })
+    }
+
+    /// Receiver signalled whenever the stream changes; it closes once the stream is purged.
+    pub fn subscribe(&self, token: &str) -> Option<watch::Receiver<()>> {
+        self.entries
+            .get(token)
+            .map(|entry| entry.updates.subscribe())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn purge_expired(&self) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, entry| {
+            entry
+                .finished_at
+                .is_none_or(|finished_at| finished_at.elapsed() < ttl)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_pending_then_completed() {
+        let store = ResultStore::new(Duration::from_secs(60));
+        let id = store.insert_pending();
+
+        assert_eq!(store.get(&id), Some(ResultState::Pending));
+        assert!(store.complete(&id, 42));
+        assert_eq!(store.get(&id), Some(ResultState::Completed(42)));
+    }
+
+    #[test]
+    fn test_unknown_id() {
+        let store: ResultStore<i32> = ResultStore::new(Duration::from_secs(60));
+        assert_eq!(store.get("missing"), None);
+        assert!(!store.complete("missing", 1));
+    }
+
+    #[test]
+    fn test_ids_are_unique() {
+        let store: ResultStore<i32> = ResultStore::new(Duration::from_secs(60));
+        let first = store.insert_pending();
+        let second = store.insert_pending();
+        assert_ne!(first, second);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_completed_entries_expire() {
+        let store = ResultStore::new(Duration::ZERO);
+        let id = store.insert_pending();
+        store.complete(&id, 1);
+
+        let pending = store.insert_pending();
+        assert_eq!(store.get(&id), None);
+        assert_eq!(store.get(&pending), Some(ResultState::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_wait_times_out_while_pending() {
+        let store: ResultStore<i32> = ResultStore::new(Duration::from_secs(60));
+        let id = store.insert_pending();
+
+        let state = store.wait(&id, Duration::from_millis(10)).await;
+        assert_eq!(state, Some(ResultState::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_wait_returns_once_completed() {
+        let store = Arc::new(ResultStore::new(Duration::from_secs(60)));
+        let id = store.insert_pending();
+
+        let completer = store.clone();
+        let completed_id = id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            completer.complete(&completed_id, 7);
+        });
+
+        let state = store.wait(&id, Duration::from_secs(5)).await;
+        assert_eq!(state, Some(ResultState::Completed(7)));
+    }
+
+    #[test]
+    fn test_streams_replay_chunks_after_the_last_seen() {
+        let streams = StreamStore::new(Duration::from_secs(60));
+        let token = streams.open();
+        let mut updates = streams.subscribe(&token).unwrap();
+        updates.borrow_and_update();
+
+        assert_eq!(streams.append(&token, "a"), Some(0));
+        assert_eq!(streams.append(&token, "b"), Some(1));
+        assert!(updates.has_changed().unwrap());
+        let all = streams.read_after(&token, None).unwrap();
+        assert_eq!(all.chunks, vec![(0, "a"), (1, "b")]);
+        assert!(!all.finished);
+
+        streams.finish(&token);
+        assert_eq!(streams.append(&token, "c"), None);
+        let missed = streams.read_after(&token, Some(0)).unwrap();
+        assert_eq!(missed.chunks, vec![(1, "b")]);
+        assert!(missed.finished);
+        assert!(
+            streams
+                .read_after(&token, Some(1))
+                .unwrap()
+                .chunks
+                .is_empty()
+        );
+        assert_eq!(streams.read_after("missing", None), None);
+    }
+
+    #[tokio::test]
+    async fn test_producers_wait_for_readers_to_deliver_chunks() {
+        let streams = Arc::new(StreamStore::new(Duration::from_secs(60)).with_pacing(
+            StreamPacing {
+                max_buffered: 2,
+                stall_timeout: Duration::from_millis(50),
+            },
+        ));
+        let token = streams.open();
+
+        streams.reserve(&token).await.unwrap();
+        streams.reserve(&token).await.unwrap();
+        assert_eq!(streams.append(&token, 0), Some(0));
+        // Both slots are taken until a chunk is delivered.
+        assert_eq!(
+            streams.reserve(&token).await,
+            Err(StreamStall::Stalled(Duration::from_millis(50)))
+        );
+
+        let reader = streams.clone();
+        let read_token = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            reader.acknowledge(&read_token, 0);
+        });
+        streams.reserve(&token).await.unwrap();
+        assert_eq!(streams.append(&token, 1), Some(1));
+        assert_eq!(streams.append(&token, 2), Some(2));
+        assert!(streams.acknowledge(&token, 2));
+        // A resuming client is sent chunks again.
+        assert!(!streams.acknowledge(&token, 1));
+        assert!(!streams.acknowledge(&token, 2));
+        streams.reserve(&token).await.unwrap();
+        assert_eq!(streams.reserve("missing").await, Err(StreamStall::Gone));
+    }
+}
@@ -0,0 +1,349 @@
+/* Model self-tests against operator-provided golden cases.
+
+A model config may list golden cases: request parameters together with the
+output the model is expected to answer them with. A self-test runs every case
+through the live runtime of a version, bypassing the request buffers, the
+batcher and the statistics, and compares each answer with its expected output
+within the case's tolerance. The report lists, per case, every difference
+found (output name, shape, values or an error answer).
+
+```yaml
+golden:
+  - name: three_classes
+    parameters: { text: "a fine day" }
+    expected:
+      name: output_1
+      shape: [1, 3]
+      data: [0.1, 0.5, 0.4]
+    tolerance: 0.001
+```
+
+Self-tests run after every MLflow version is deployed, whether at startup or on
+a stage promotion, and on demand from the admin API.
+*/
+
+use anyhow::{Result, anyhow};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::time::Instant;
+
+use crate::api::inference::{
+    InferParameter, InferenceError, InferenceOutput, InferenceRequest, InferenceResponse,
+};
+use crate::api::inference_runtime::{InferenceRuntime, panic_message};
+use crate::model::model_discovery_service::ModelVersionId;
+use crate::model::priority::Priority;
+
+/// Values of a case that differ by more than this from the expected ones fail it, unless the
+/// case sets its own tolerance.
+pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+/// Value differences reported per case; the rest are only counted.
+const MAX_VALUE_DIFFERENCES: usize = 10;
+
+/// Output a golden case expects. Name and shape are only compared when given.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoldenOutput {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub shape: Option<Vec<usize>>,
+    pub data: Vec<f64>,
+}
+
+/// A request and the output the model must answer it with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoldenCase {
+    pub name: String,
+    /// Request parameters, as a client would send them.
+    #[serde(default)]
+    pub parameters: HashMap<String, Value>,
+    pub expected: GoldenOutput,
+    /// Largest absolute difference allowed between an expected and an actual value.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+fn default_tolerance() -> f64 {
+    DEFAULT_TOLERANCE
+}
+
+impl GoldenCase {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(anyhow!("Golden case names must not be empty"));
+        }
+        if !self.tolerance.is_finite() || self.tolerance < 0.0 {
+            return Err(anyhow!(
+                "Golden case '{}' must have a non-negative tolerance",
+                self.name
+            ));
+        }
+        if let Some(shape) = &self.expected.shape
+            && shape.iter().product::<usize>() != self.expected.data.len()
+        {
+            return Err(anyhow!(
+                "Golden case '{}' expects shape {:?} but lists {} values",
+                self.name,
+                shape,
+                self.expected.data.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn request(&self, version_id: &ModelVersionId) -> InferenceRequest {
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|(key, value)| (key.clone(), parameter(value)))
+            .collect();
+        InferenceRequest {
+            model_name: version_id.model.0.clone(),
+            model_version: Some(version_id.version.clone()),
+            id: format!("selftest-{}", self.name),
+            parameters: Some(parameters),
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    /// Differences between the expected output and `response`; none when the case passes.
+    pub fn compare(&self, response: &InferenceResponse) -> Vec<Difference> {
+        let output = match response {
+            InferenceResponse::Ok(output) => output,
+            InferenceResponse::Error(e) | InferenceResponse::DeadlineExceeded(e) => {
+                return vec![Difference::new(
+                    "status",
+                    "ok",
+                    format!("error: {}", e.error),
+                )];
+            }
+        };
+        let mut differences = Vec::new();
+        if let Some(name) = &self.expected.name
+            && *name != output.name
+        {
+            differences.push(Difference::new("name", name, &output.name));
+        }
+        if let Some(shape) = &self.expected.shape
+            && *shape != output.shape
+        {
+            differences.push(Difference::new(
+                "shape",
+                format!("{:?}", shape),
+                format!("{:?}", output.shape),
+            ));
+        }
+        differences.extend(self.compare_values(output));
+        differences
+    }
+
+    fn compare_values(&self, output: &InferenceOutput) -> Vec<Difference> {
+        let Some(actual) = output.data.float_values() else {
+            // Golden outputs are numbers.
+            return vec![Difference::new(
+                "data.datatype",
+                "FP64",
+                crate::api::tensor::BYTES_DATATYPE,
+            )];
+        };
+        let expected = &self.expected.data;
+        if actual.len() != expected.len() {
+            return vec![Difference::new(
+                "data.len",
+                expected.len().to_string(),
+                actual.len().to_string(),
+            )];
+        }
+        let mismatches: Vec<usize> = expected
+            .iter()
+            .zip(actual.iter())
+            .enumerate()
+            .filter(|(_, (expected, actual))| !within(**expected, **actual, self.tolerance))
+            .map(|(index, _)| index)
+            .collect();
+        let mut differences: Vec<Difference> = mismatches
+            .iter()
+            .take(MAX_VALUE_DIFFERENCES)
+            .map(|&index| {
+                Difference::new(
+                    format!("data[{}]", index),
+                    expected[index].to_string(),
+                    actual[index].to_string(),
+                )
+            })
+            .collect();
+        if mismatches.len() > MAX_VALUE_DIFFERENCES {
+            differences.push(Difference::new(
+                "data",
+                "",
+                format!(
+                    "{} more values out of tolerance",
+                    mismatches.len() - MAX_VALUE_DIFFERENCES
+                ),
+            ));
+        }
+        differences
+    }
+}
+
+fn within(expected: f64, actual: f64, tolerance: f64) -> bool {
+    (expected.is_nan() && actual.is_nan()) || (expected - actual).abs() <= tolerance
+}
+
+fn parameter(value: &Value) -> InferParameter {
+    match value {
+        Value::Bool(b) => InferParameter::Bool(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => InferParameter::Int64(i),
+            None => InferParameter::Double(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => InferParameter::String(s.clone()),
+        other => InferParameter::String(other.to_string()),
+    }
+}
+
+/// A field of an answer that is not the expected one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Difference {
+    /// `status`, `name`, `shape`, `data.len` or `data[<index>]`.
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Difference {
+    fn new(
+        field: impl Into<String>,
+        expected: impl Into<String>,
+        actual: impl Into<String>,
+    ) -> Self {
+        Self {
+            field: field.into(),
+            expected: expected.into(),
+            actual: actual.into(),
+        }
+    }
+}
+
+/// Outcome of one golden case.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    pub passed: bool,
+    pub latency_ms: f64,
+    pub differences: Vec<Difference>,
+}
+
+/// Outcome of the golden cases of a model version.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    pub model: String,
+    pub version: String,
+    pub passed: bool,
+    pub cases: Vec<CaseResult>,
+}
+
+impl SelfTestReport {
+    pub fn failed_cases(&self) -> impl Iterator<Item = &CaseResult> {
+        self.cases.iter().filter(|case| !case.passed)
+    }
+}
+
+/// Runs `cases` one after the other on `runtime`, the runtime serving `version_id`.
+pub async fn run_self_test(
+    version_id: &ModelVersionId,
+    runtime: &dyn InferenceRuntime,
+    cases: &[GoldenCase],
+) -> SelfTestReport {
+    let mut results = Vec::with_capacity(cases.len());
+    for case in cases {
+        let started = Instant::now();
+        let response = AssertUnwindSafe(runtime.process_single(case.request(version_id)))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|panic| {
+                InferenceResponse::Error(InferenceError {
+                    error: format!("Runtime panicked: {}", panic_message(panic.as_ref())),
+                })
+            });
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        let differences = case.compare(&response);
+        results.push(CaseResult {
+            name: case.name.clone(),
+            passed: differences.is_empty(),
+            latency_ms,
+            differences,
+        });
+    }
+    SelfTestReport {
+        model: version_id.model.0.clone(),
+        version: version_id.version.clone(),
+        passed: results.iter().all(|case| case.passed),
+        cases: results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeInferenceProcessor;
+    use crate::api::inference_runtime::ProcessorRuntime;
+
+    fn case(yaml: &str) -> GoldenCase {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_golden_cases_report_their_differences() {
+        let version_id = ModelVersionId::new("m", "1");
+        let runtime = ProcessorRuntime::new("m", FakeInferenceProcessor);
+        let cases = [
+            case(
+                "{ name: exact, expected: { name: output_1, shape: [1, 3], data: [0.1, 0.5, 0.4] } }",
+            ),
+            case("{ name: close, expected: { data: [0.1, 0.52, 0.4] }, tolerance: 0.05 }"),
+            case("{ name: off, expected: { name: label, shape: [3], data: [0.1, 0.9, 0.4] } }"),
+            case("{ name: short, expected: { data: [0.1] } }"),
+        ];
+
+        let report = run_self_test(&version_id, &runtime, &cases).await;
+        assert!(!report.passed);
+        assert!(report.cases[0].passed && report.cases[1].passed);
+        let fields: Vec<&str> = report.cases[2]
+            .differences
+            .iter()
+            .map(|difference| difference.field.as_str())
+            .collect();
+        assert_eq!(fields, ["name", "shape", "data[1]"]);
+        assert_eq!(report.cases[2].differences[1].actual, "[1, 3]");
+        assert_eq!(report.cases[3].differences[0].field, "data.len");
+        assert_eq!(report.failed_cases().count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_golden_cases_are_rejected() {
+        assert!(
+            case("{ name: c, expected: { data: [1] }, tolerance: -1 }")
+                .validate()
+                .is_err()
+        );
+        assert!(
+            case("{ name: c, expected: { shape: [2], data: [1] } }")
+                .validate()
+                .is_err()
+        );
+        assert!(
+            case("{ name: c, expected: { shape: [1], data: [1] } }")
+                .validate()
+                .is_ok()
+        );
+    }
+}
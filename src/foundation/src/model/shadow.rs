@@ -0,0 +1,251 @@
+/* Shadow deployment of model versions.
+
+A model version marked as shadow is never chosen to answer clients on its
+own. Instead every request enqueued for the model is duplicated to its shadow
+versions in the background: the shadow response is discarded, but its latency
+and outcome are recorded separately from the primary traffic, so a new
+version can be measured on production traffic before it is promoted.
+
+Duplication is fire-and-forget. At most `max_in_flight` duplicated requests
+run per shadow version; requests beyond that are dropped (and counted) rather
+than queued, so a slow shadow never holds back the primary version.
+*/
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::api::inference::{InferenceRequest, InferenceResponse};
+use crate::api::inference_runtime::InferenceRuntime;
+use crate::model::model_discovery_service::{ModelId, ModelVersionId, compare_versions};
+
+pub const DEFAULT_SHADOW_MAX_IN_FLIGHT: usize = 64;
+
+#[derive(Debug, Default)]
+struct ShadowCounters {
+    in_flight: AtomicUsize,
+    mirrored: AtomicU64,
+    completed: AtomicU64,
+    errors: AtomicU64,
+    dropped: AtomicU64,
+    total_latency_us: AtomicU64,
+    max_latency_us: AtomicU64,
+}
+
+/// Traffic duplicated to a shadow version, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShadowStats {
+    pub model: String,
+    pub version: String,
+    /// Requests duplicated to the version.
+    pub mirrored: u64,
+    pub completed: u64,
+    /// Completed requests the version answered with an error.
+    pub errors: u64,
+    /// Requests not duplicated because too many were still running.
+    pub dropped: u64,
+    pub in_flight: usize,
+    pub mean_latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+}
+
+pub struct ShadowTraffic {
+    versions: DashMap<ModelId, Vec<String>>,
+    counters: DashMap<ModelVersionId, Arc<ShadowCounters>>,
+    max_in_flight: usize,
+}
+
+impl Default for ShadowTraffic {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHADOW_MAX_IN_FLIGHT)
+    }
+}
+
+impl ShadowTraffic {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            versions: DashMap::new(),
+            counters: DashMap::new(),
+            max_in_flight,
+        }
+    }
+
+    /// Marks `versions` of a model as shadow, replacing the previous ones.
+    pub fn set_versions(&self, model_id: ModelId, mut versions: Vec<String>) {
+        if versions.is_empty() {
+            self.versions.remove(&model_id);
+            return;
+        }
+        versions.sort_by(|a, b| compare_versions(a, b));
+        versions.dedup();
+        self.versions.insert(model_id, versions);
+    }
+
+    pub fn versions(&self, model_id: &ModelId) -> Vec<String> {
+        self.versions
+            .get(model_id)
+            .map(|versions| versions.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn is_shadow(&self, model_id: &ModelId, version: &str) -> bool {
+        self.versions
+            .get(model_id)
+            .is_some_and(|versions| versions.iter().any(|v| v == version))
+    }
+
+    /// Duplicates `request` to `runtime` in the background. Returns false if the
+    /// request was dropped, because too many are in flight or there is no async runtime.
+    pub fn mirror(
+        &self,
+        version_id: ModelVersionId,
+        runtime: Arc<dyn InferenceRuntime>,
+        mut request: InferenceRequest,
+    ) -> bool {
+        let counters = self.counters.entry(version_id.clone()).or_default().clone();
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        };
+        let admitted = counters
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < self.max_in_flight).then_some(in_flight + 1)
+            })
+            .is_ok();
+        if !admitted {
+            counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        counters.mirrored.fetch_add(1, Ordering::Relaxed);
+
+        request.model_version = Some(version_id.version.clone());
+        // Shadow work is not part of the client's request.
+        request.timeline = None;
+        handle.spawn(async move {
+            let started = Instant::now();
+            let request_id = request.id.clone();
+            let response = runtime.process_single(request).await;
+            let latency_us = started.elapsed().as_micros() as u64;
+
+            if let InferenceResponse::Error(e) = &response {
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+                eprintln!(
+                    "Shadow {} failed request {}: {}",
+                    version_id, request_id, e.error
+                );
+            }
+            counters
+                .total_latency_us
+                .fetch_add(latency_us, Ordering::Relaxed);
+            counters
+                .max_latency_us
+                .fetch_max(latency_us, Ordering::Relaxed);
+            counters.completed.fetch_add(1, Ordering::Relaxed);
+            counters.in_flight.fetch_sub(1, Ordering::AcqRel);
+        });
+        true
+    }
+
+    /// Counters of every version that received shadow traffic, sorted by model and version.
+    pub fn stats(&self) -> Vec<ShadowStats> {
+        let mut stats: Vec<ShadowStats> = self
+            .counters
+            .iter()
+            .map(|entry| {
+                let counters = entry.value();
+                let completed = counters.completed.load(Ordering::Relaxed);
+                let total_latency_us = counters.total_latency_us.load(Ordering::Relaxed);
+                ShadowStats {
+                    model: entry.key().model.0.clone(),
+                    version: entry.key().version.clone(),
+                    mirrored: counters.mirrored.load(Ordering::Relaxed),
+                    completed,
+                    errors: counters.errors.load(Ordering::Relaxed),
+                    dropped: counters.dropped.load(Ordering::Relaxed),
+                    in_flight: counters.in_flight.load(Ordering::Relaxed),
+                    mean_latency_ms: (completed > 0)
+                        .then(|| total_latency_us as f64 / completed as f64 / 1000.0),
+                    max_latency_ms: (completed > 0)
+                        .then(|| counters.max_latency_us.load(Ordering::Relaxed) as f64 / 1000.0),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            a.model
+                .cmp(&b.model)
+                .then_with(|| compare_versions(&a.version, &b.version))
+        });
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::fake::FakeInferenceProcessor;
+    use crate::api::inference::InferParameter;
+    use crate::api::inference_runtime::ProcessorRuntime;
+    use crate::model::priority::Priority;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn request(with_parameters: bool) -> InferenceRequest {
+        InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: Some("1".to_string()),
+            id: "r".to_string(),
+            parameters: with_parameters
+                .then(|| HashMap::from([("k".to_string(), InferParameter::Bool(true))])),
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirrored_requests_are_measured() {
+        let shadow = ShadowTraffic::new(8);
+        let runtime: Arc<dyn InferenceRuntime> =
+            Arc::new(ProcessorRuntime::new("m", FakeInferenceProcessor));
+        let version_id = ModelVersionId::new("m", "2");
+
+        assert!(shadow.mirror(version_id.clone(), runtime.clone(), request(true)));
+        assert!(shadow.mirror(version_id, runtime, request(false)));
+
+        let stats = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let stats = shadow.stats();
+                if stats[0].completed == 2 {
+                    return stats;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].mirrored, stats[0].errors), (2, 1));
+        assert_eq!(stats[0].in_flight, 0);
+        assert!(stats[0].mean_latency_ms.is_some());
+    }
+
+    #[test]
+    fn test_requests_are_dropped_without_capacity_or_runtime() {
+        let runtime: Arc<dyn InferenceRuntime> =
+            Arc::new(ProcessorRuntime::new("m", FakeInferenceProcessor));
+        let shadow = ShadowTraffic::new(0);
+        // Not inside a tokio runtime either.
+        assert!(!shadow.mirror(ModelVersionId::new("m", "2"), runtime, request(true)));
+        assert_eq!(shadow.stats()[0].dropped, 1);
+
+        shadow.set_versions(ModelId("m".to_string()), vec!["3".into(), "2".into()]);
+        assert!(shadow.is_shadow(&ModelId("m".to_string()), "2"));
+        assert_eq!(shadow.versions(&ModelId("m".to_string())), vec!["2", "3"]);
+    }
+}
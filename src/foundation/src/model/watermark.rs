@@ -0,0 +1,389 @@
+/* Watermarks of generated text.
+
+A model config may ask for the text its outputs carry to be watermarked, so
+the server can later tell whether a piece of text was generated by it:
+
+```yaml
+watermark:
+  scheme: zero_width     # the default
+  parameters: [text]     # output parameters holding generated text, the default
+```
+
+Watermarking is also set per tenant: the server configuration forces it on for
+the outputs of some tenants, with the default settings above for models that
+do not declare any, or off for others.
+
+Schemes implement `Watermarker` and are registered by name in `Watermarking`.
+The built-in `zero_width` scheme embeds a keyed tag into the text with
+zero-width characters: a marker proving the text was generated by a server
+holding the key, and a digest of the visible text proving it was not edited.
+The tag is inserted after the first word and every `MARK_SPACING` words
+after it, so excerpts keep the marker. Schemes biasing the sampling of tokens
+need the sampler of the runtime and are registered the same way by runtimes
+generating text.
+
+Models watermarking with a scheme that is not registered, for instance
+because no key is configured, answer with an error rather than unmarked text.
+`POST /{version}/watermark/detect` checks a piece of text for a watermark.
+*/
+
+use anyhow::{Context, Result, anyhow};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::api::inference::{InferParameter, InferenceOutput};
+
+pub const ZERO_WIDTH_SCHEME: &str = "zero_width";
+/// Output parameter naming the scheme the text of an output was watermarked with.
+pub const WATERMARK_PARAMETER: &str = "watermark";
+/// Words between two tags of the `zero_width` scheme.
+pub const MARK_SPACING: usize = 64;
+
+/// Zero-width characters carrying two bits each.
+const ALPHABET: [char; 4] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}'];
+const MARKER_LEN: usize = 4;
+const DIGEST_LEN: usize = 4;
+const TAG_CHARS: usize = (MARKER_LEN + DIGEST_LEN) * 4;
+
+/// Watermark section of a model config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WatermarkConfig {
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+    #[serde(default = "default_parameters")]
+    pub parameters: Vec<String>,
+}
+
+fn default_scheme() -> String {
+    ZERO_WIDTH_SCHEME.to_string()
+}
+
+fn default_parameters() -> Vec<String> {
+    vec!["text".to_string()]
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            scheme: default_scheme(),
+            parameters: default_parameters(),
+        }
+    }
+}
+
+/// Whether a text carries a watermark of the scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatermarkStatus {
+    /// The text is watermarked and unchanged since.
+    Verified,
+    /// The text carries a watermark but was edited, or is an excerpt.
+    Modified,
+    Absent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Detection {
+    pub scheme: String,
+    pub status: WatermarkStatus,
+    /// Watermarks found in the text.
+    pub marks: usize,
+}
+
+/// Watermarks generated text and detects the watermark in text.
+pub trait Watermarker: Send + Sync {
+    /// Scheme name, matched against the `scheme` of model configs.
+    fn scheme(&self) -> &str;
+
+    fn apply(&self, text: &str) -> String;
+
+    fn detect(&self, text: &str) -> Detection;
+}
+
+/// Reads a watermark key described by `spec`: `env:<VARIABLE>` or the path of a key file.
+pub fn read_key(spec: &str) -> Result<Vec<u8>> {
+    let key = match spec.strip_prefix("env:") {
+        Some(variable) => std::env::var(variable)
+            .with_context(|| format!("Environment variable {} is not set", variable))?,
+        None => std::fs::read_to_string(Path::new(spec))
+            .with_context(|| format!("Failed to read watermark key {}", spec))?,
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(anyhow!("Watermark key {} is empty", spec));
+    }
+    Ok(key.as_bytes().to_vec())
+}
+
+/// The `zero_width` scheme, see the module documentation.
+pub struct ZeroWidthWatermark {
+    key: Vec<u8>,
+}
+
+impl ZeroWidthWatermark {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes().into()
+    }
+
+    fn marker(&self) -> [u8; MARKER_LEN] {
+        let mac = self.mac(&[b"galemind-watermark"]);
+        [mac[0], mac[1], mac[2], mac[3]]
+    }
+
+    fn digest(&self, visible: &str) -> [u8; DIGEST_LEN] {
+        let mac = self.mac(&[b"galemind-text:", visible.as_bytes()]);
+        [mac[0], mac[1], mac[2], mac[3]]
+    }
+
+    fn encode(tag: &[u8]) -> String {
+        tag.iter()
+            .flat_map(|byte| (0..4).rev().map(move |pair| (byte >> (pair * 2)) & 0b11))
+            .map(|bits| ALPHABET[bits as usize])
+            .collect()
+    }
+
+    fn decode(chars: &[char]) -> Vec<u8> {
+        chars
+            .chunks(4)
+            .map(|pairs| {
+                pairs.iter().fold(0u8, |byte, c| {
+                    let bits = ALPHABET.iter().position(|a| a == c).unwrap_or(0) as u8;
+                    (byte << 2) | bits
+                })
+            })
+            .collect()
+    }
+}
+
+/// `text` without the characters watermarks are written with.
+fn visible(text: &str) -> String {
+    text.chars().filter(|c| !ALPHABET.contains(c)).collect()
+}
+
+impl Watermarker for ZeroWidthWatermark {
+    fn scheme(&self) -> &str {
+        ZERO_WIDTH_SCHEME
+    }
+
+    fn apply(&self, text: &str) -> String {
+        let visible = visible(text);
+        if visible.trim().is_empty() {
+            return visible;
+        }
+        let mut tag = self.marker().to_vec();
+        tag.extend(self.digest(&visible));
+        let tag = Self::encode(&tag);
+
+        let mut marked = String::with_capacity(visible.len() + tag.len() * 2);
+        let mut words = 0;
+        let mut tags = 0;
+        let mut previous = ' ';
+        for c in visible.chars() {
+            // A word ends where whitespace starts.
+            if c.is_whitespace() && !previous.is_whitespace() {
+                if words % MARK_SPACING == 0 {
+                    marked.push_str(&tag);
+                    tags += 1;
+                }
+                words += 1;
+            }
+            marked.push(c);
+            previous = c;
+        }
+        if tags == 0 {
+            marked.push_str(&tag);
+        }
+        marked
+    }
+
+    fn detect(&self, text: &str) -> Detection {
+        let marker = self.marker();
+        let digest = self.digest(&visible(text));
+        let chars: Vec<char> = text.chars().collect();
+        let (mut marks, mut intact) = (0, false);
+        for run in chars.split(|c| !ALPHABET.contains(c)) {
+            if run.len() != TAG_CHARS {
+                continue;
+            }
+            let tag = Self::decode(run);
+            if tag[..MARKER_LEN] == marker {
+                marks += 1;
+                intact |= tag[MARKER_LEN..] == digest;
+            }
+        }
+        Detection {
+            scheme: ZERO_WIDTH_SCHEME.to_string(),
+            status: match (marks, intact) {
+                (0, _) => WatermarkStatus::Absent,
+                (_, true) => WatermarkStatus::Verified,
+                (_, false) => WatermarkStatus::Modified,
+            },
+            marks,
+        }
+    }
+}
+
+/// Registered watermark schemes and the tenants watermarking is set for.
+#[derive(Default)]
+pub struct Watermarking {
+    schemes: HashMap<String, Arc<dyn Watermarker>>,
+    /// Tenants whose outputs are always (`true`) or never (`false`) watermarked.
+    tenants: HashMap<String, bool>,
+}
+
+impl Watermarking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the built-in schemes with `key`.
+    pub fn with_key(self, key: &[u8]) -> Self {
+        self.with_scheme(Arc::new(ZeroWidthWatermark::new(key)))
+    }
+
+    pub fn with_scheme(mut self, watermarker: Arc<dyn Watermarker>) -> Self {
+        self.schemes
+            .insert(watermarker.scheme().to_string(), watermarker);
+        self
+    }
+
+    /// Always watermarks the outputs of `tenant`, or never does.
+    pub fn with_tenant(mut self, tenant: impl Into<String>, enabled: bool) -> Self {
+        self.tenants.insert(tenant.into(), enabled);
+        self
+    }
+
+    pub fn schemes(&self) -> Vec<String> {
+        let mut schemes: Vec<String> = self.schemes.keys().cloned().collect();
+        schemes.sort();
+        schemes
+    }
+
+    /// Settings watermarking the output of a model configured with `config` for `tenant`,
+    /// if it is watermarked.
+    pub fn settings(
+        &self,
+        config: Option<&WatermarkConfig>,
+        tenant: Option<&str>,
+    ) -> Option<WatermarkConfig> {
+        match tenant.and_then(|tenant| self.tenants.get(tenant)) {
+            Some(false) => None,
+            Some(true) => Some(config.cloned().unwrap_or_default()),
+            None => config.cloned(),
+        }
+    }
+
+    /// Watermarks the text parameters of `output` named in `settings`.
+    pub fn apply(&self, settings: &WatermarkConfig, output: &mut InferenceOutput) -> Result<()> {
+        let watermarker = self.schemes.get(&settings.scheme).ok_or_else(|| {
+            anyhow!(
+                "Watermark scheme '{}' is not available, outputs are not returned unmarked",
+                settings.scheme
+            )
+        })?;
+        let Some(parameters) = output.parameters.as_mut() else {
+            return Ok(());
+        };
+        let mut marked = false;
+        for name in &settings.parameters {
+            if let Some(InferParameter::String(text)) = parameters.get_mut(name) {
+                *text = watermarker.apply(text);
+                marked = true;
+            }
+        }
+        if marked {
+            parameters.insert(
+                WATERMARK_PARAMETER.to_string(),
+                InferParameter::String(settings.scheme.clone()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks `text` for a watermark of `scheme`, the built-in one by default.
+    pub fn detect(&self, scheme: Option<&str>, text: &str) -> Result<Detection> {
+        let scheme = scheme.unwrap_or(ZERO_WIDTH_SCHEME);
+        self.schemes
+            .get(scheme)
+            .map(|watermarker| watermarker.detect(text))
+            .ok_or_else(|| anyhow!("Watermark scheme '{}' is not available", scheme))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::tensor::{Data, DataType};
+
+    #[test]
+    fn test_watermarked_text_is_verified_until_edited() {
+        let watermarking = Watermarking::new()
+            .with_key(b"secret")
+            .with_tenant("exempt", false)
+            .with_tenant("audited", true);
+        let config = WatermarkConfig::default();
+        assert_eq!(watermarking.settings(Some(&config), Some("exempt")), None);
+        assert_eq!(watermarking.settings(None, Some("other")), None);
+        assert_eq!(
+            watermarking.settings(None, Some("audited")),
+            Some(config.clone())
+        );
+
+        let mut output = InferenceOutput {
+            name: "generation".to_string(),
+            shape: vec![1],
+            datatype: DataType::VFLOAT,
+            parameters: Some(HashMap::from([(
+                "text".to_string(),
+                InferParameter::String("The quick brown fox jumps over the lazy dog".to_string()),
+            )])),
+            data: Data::VFLOAT(vec![0.0]),
+        };
+        watermarking.apply(&config, &mut output).unwrap();
+        let parameters = output.parameters.unwrap();
+        let Some(InferParameter::String(text)) = parameters.get("text") else {
+            panic!("text parameter is gone");
+        };
+        assert_eq!(visible(text), "The quick brown fox jumps over the lazy dog");
+        assert!(matches!(
+            parameters.get(WATERMARK_PARAMETER),
+            Some(InferParameter::String(scheme)) if scheme == ZERO_WIDTH_SCHEME
+        ));
+
+        let detection = watermarking.detect(None, text).unwrap();
+        assert_eq!(detection.status, WatermarkStatus::Verified);
+        assert_eq!(detection.marks, 1);
+        let edited = text.replace("lazy", "sleepy");
+        assert_eq!(
+            watermarking.detect(None, &edited).unwrap().status,
+            WatermarkStatus::Modified
+        );
+        assert_eq!(
+            watermarking
+                .detect(None, "The quick brown fox")
+                .unwrap()
+                .status,
+            WatermarkStatus::Absent
+        );
+        // Another key does not recognize the watermark.
+        let other = Watermarking::new().with_key(b"other");
+        assert_eq!(
+            other.detect(None, text).unwrap().status,
+            WatermarkStatus::Absent
+        );
+        assert!(Watermarking::new().detect(None, text).is_err());
+    }
+}
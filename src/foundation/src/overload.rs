@@ -0,0 +1,273 @@
+/* Graceful degradation under overload.
+
+Saturation is the number of in-flight requests relative to the configured
+capacity, shared by every server. Once it reaches `degrade_at`, expensive
+optional features are switched off (log probabilities, explanations, shadow
+traffic), generation lengths are capped and cached responses are preferred.
+Full service is restored when saturation falls back to `restore_at`; the gap
+between both thresholds keeps the mode from flapping around a single value.
+*/
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::api::inference::{InferParameter, InferenceRequest};
+
+/// Request parameters dropped while degraded, grouped by the feature they enable.
+const LOGPROBS_PARAMETERS: &[&str] = &["logprobs", "top_logprobs"];
+const EXPLANATION_PARAMETERS: &[&str] = &["explain", "explanations"];
+const SHADOW_PARAMETERS: &[&str] = &["shadow"];
+pub const MAX_TOKENS_PARAMETER: &str = "max_tokens";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverloadPolicy {
+    /// In-flight requests corresponding to full saturation.
+    pub capacity: usize,
+    /// Saturation (0.0 - 1.0) at which degraded mode is entered.
+    pub degrade_at: f64,
+    /// Saturation at which degraded mode is left again.
+    pub restore_at: f64,
+    /// Upper bound applied to `max_tokens` while degraded.
+    pub max_tokens_cap: u64,
+}
+
+impl Default for OverloadPolicy {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            degrade_at: 0.9,
+            restore_at: 0.7,
+            max_tokens_cap: 256,
+        }
+    }
+}
+
+/// Optional features available for a request under the current load.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureGates {
+    pub logprobs: bool,
+    pub explanations: bool,
+    pub shadow_traffic: bool,
+    pub prefer_cached: bool,
+    pub max_tokens_cap: Option<u64>,
+}
+
+impl FeatureGates {
+    pub fn full() -> Self {
+        Self {
+            logprobs: true,
+            explanations: true,
+            shadow_traffic: true,
+            prefer_cached: false,
+            max_tokens_cap: None,
+        }
+    }
+
+    pub fn degraded(policy: &OverloadPolicy) -> Self {
+        Self {
+            logprobs: false,
+            explanations: false,
+            shadow_traffic: false,
+            prefer_cached: true,
+            max_tokens_cap: Some(policy.max_tokens_cap),
+        }
+    }
+
+    /// Whether a request parameter may be passed on to the model.
+    pub fn allows_parameter(&self, name: &str) -> bool {
+        (self.logprobs || !LOGPROBS_PARAMETERS.contains(&name))
+            && (self.explanations || !EXPLANATION_PARAMETERS.contains(&name))
+            && (self.shadow_traffic || !SHADOW_PARAMETERS.contains(&name))
+    }
+
+    pub fn cap_max_tokens(&self, requested: u64) -> u64 {
+        self.max_tokens_cap
+            .map_or(requested, |cap| requested.min(cap))
+    }
+
+    /// Removes disabled features from inference parameters and caps `max_tokens`.
+    pub fn apply(&self, parameters: &mut HashMap<String, InferParameter>) {
+        parameters.retain(|name, _| self.allows_parameter(name));
+        if let Some(InferParameter::Int64(max_tokens)) = parameters.get_mut(MAX_TOKENS_PARAMETER) {
+            *max_tokens = self.cap_max_tokens((*max_tokens).max(0) as u64) as i64;
+        }
+    }
+}
+
+/// Tracks global saturation and switches between full and degraded service.
+#[derive(Debug)]
+pub struct OverloadController {
+    policy: OverloadPolicy,
+    in_flight: AtomicUsize,
+    degraded: AtomicBool,
+}
+
+impl Default for OverloadController {
+    fn default() -> Self {
+        Self::new(OverloadPolicy::default())
+    }
+}
+
+impl OverloadController {
+    pub fn new(policy: OverloadPolicy) -> Self {
+        Self {
+            policy,
+            in_flight: AtomicUsize::new(0),
+            degraded: AtomicBool::new(false),
+        }
+    }
+
+    pub fn policy(&self) -> &OverloadPolicy {
+        &self.policy
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub fn begin(self: &Arc<Self>) -> LoadGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.evaluate();
+        LoadGuard {
+            controller: self.clone(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn saturation(&self) -> f64 {
+        self.in_flight() as f64 / self.policy.capacity.max(1) as f64
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::SeqCst)
+    }
+
+    pub fn features(&self) -> FeatureGates {
+        if self.is_degraded() {
+            FeatureGates::degraded(&self.policy)
+        } else {
+            FeatureGates::full()
+        }
+    }
+
+    /// Strips features unavailable under the current load from `request`.
+    pub fn apply(&self, request: &mut InferenceRequest) {
+        if let Some(parameters) = request.parameters.as_mut() {
+            self.features().apply(parameters);
+        }
+    }
+
+    fn evaluate(&self) {
+        let saturation = self.saturation();
+        if saturation >= self.policy.degrade_at {
+            if !self.degraded.swap(true, Ordering::SeqCst) {
+                eprintln!(
+                    "Overload: saturation {:.0}%, entering degraded mode",
+                    saturation * 100.0
+                );
+            }
+        } else if saturation <= self.policy.restore_at
+            && self.degraded.swap(false, Ordering::SeqCst)
+        {
+            eprintln!(
+                "Overload: saturation {:.0}%, restoring full service",
+                saturation * 100.0
+            );
+        }
+    }
+}
+
+/// Marks one in-flight request; releasing it may restore full service.
+pub struct LoadGuard {
+    controller: Arc<OverloadController>,
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.controller.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.controller.evaluate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::priority::Priority;
+
+    fn controller(capacity: usize) -> Arc<OverloadController> {
+        Arc::new(OverloadController::new(OverloadPolicy {
+            capacity,
+            degrade_at: 0.75,
+            restore_at: 0.25,
+            max_tokens_cap: 16,
+        }))
+    }
+
+    #[test]
+    fn test_degrades_and_restores_with_hysteresis() {
+        let controller = controller(4);
+        let mut guards: Vec<LoadGuard> = (0..2).map(|_| controller.begin()).collect();
+        assert!(!controller.is_degraded());
+
+        guards.push(controller.begin());
+        assert!(controller.is_degraded());
+        assert_eq!(
+            controller.features(),
+            FeatureGates::degraded(controller.policy())
+        );
+
+        // Between both thresholds the mode is kept.
+        guards.pop();
+        assert!(controller.is_degraded());
+
+        guards.pop();
+        assert!(!controller.is_degraded());
+        assert_eq!(controller.features(), FeatureGates::full());
+    }
+
+    #[test]
+    fn test_apply_strips_expensive_parameters() {
+        let controller = controller(1);
+        let _guard = controller.begin();
+
+        let mut request = InferenceRequest {
+            model_name: "m".to_string(),
+            model_version: None,
+            id: "1".to_string(),
+            parameters: Some(HashMap::from([
+                ("logprobs".to_string(), InferParameter::Bool(true)),
+                ("explain".to_string(), InferParameter::Bool(true)),
+                (
+                    "shadow".to_string(),
+                    InferParameter::String("m2".to_string()),
+                ),
+                ("max_tokens".to_string(), InferParameter::Int64(1024)),
+                ("temperature".to_string(), InferParameter::Double(0.2)),
+            ])),
+            outputs: None,
+            timeline: None,
+            priority: Priority::Normal,
+            deadline: None,
+            tenant: None,
+            sampling: Default::default(),
+        };
+        controller.apply(&mut request);
+
+        let parameters = request.parameters.unwrap();
+        let mut names: Vec<&String> = parameters.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["max_tokens", "temperature"]);
+        assert!(matches!(
+            parameters["max_tokens"],
+            InferParameter::Int64(16)
+        ));
+    }
+
+    #[test]
+    fn test_full_service_keeps_parameters() {
+        let gates = FeatureGates::full();
+        assert!(gates.allows_parameter("logprobs"));
+        assert_eq!(gates.cap_max_tokens(1024), 1024);
+    }
+}
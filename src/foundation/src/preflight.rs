@@ -0,0 +1,785 @@
+/* Startup preflight checks.
+
+`Preflight` verifies the environment a server is about to start in: GPU
+drivers, availability of the REST and gRPC ports, connectivity to every model
+source (MLflow, S3, GCS, Azure), free disk space for the model store cache and
+the consistency of the server configuration. Checks never abort early; every
+problem is collected into a `PreflightReport` together with a hint on how to
+fix it, so a single run surfaces all issues before `start` is attempted.
+
+`Preflight::validate` checks what is deployed rather than where: the server
+configuration, the model directories with their configs, and the artifacts of
+every version `start` would load, loaded once with the runtime backends and
+dropped. It needs neither the ports nor the model sources, which suits CI.
+*/
+
+use std::fmt;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::InferenceServerConfig;
+use crate::api::devices::Device;
+use crate::api::mlflow_client::{MLFlowClient, MLFlowClientTrait};
+use crate::api::runtime_registry::RuntimeRegistry;
+use crate::model::layout::LegacyModel;
+use crate::model::model_config::ModelConfig;
+use crate::model::model_discovery_service::{ModelId, ModelSource, ModelVersionId, VersionPolicy};
+
+/// NVIDIA kernel driver information, present when the driver is loaded.
+const NVIDIA_DRIVER_VERSION_FILE: &str = "/proc/driver/nvidia/version";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PreflightReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl PreflightReport {
+    /// True when no check failed; warnings do not prevent a start.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fail)
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Pass => " OK ",
+                CheckStatus::Warn => "WARN",
+                CheckStatus::Fail => "FAIL",
+            };
+            writeln!(f, "[{}] {}: {}", status, check.name, check.detail)?;
+            if let Some(hint) = &check.hint {
+                writeln!(f, "       -> {}", hint)?;
+            }
+        }
+        let failures = self.failures().count();
+        if failures == 0 {
+            write!(f, "All preflight checks passed.")
+        } else {
+            write!(f, "{} preflight check(s) failed.", failures)
+        }
+    }
+}
+
+pub struct Preflight {
+    config: InferenceServerConfig,
+    models_dir: Option<PathBuf>,
+    sources: Vec<ModelSource>,
+    model_store_dir: Option<PathBuf>,
+    min_free_space: u64,
+    require_gpu: bool,
+    source_timeout: Duration,
+    runtime_registry: Arc<RuntimeRegistry>,
+    version_policy: VersionPolicy,
+}
+
+impl Preflight {
+    pub fn new(config: InferenceServerConfig) -> Self {
+        Self {
+            config,
+            models_dir: None,
+            sources: Vec::new(),
+            model_store_dir: None,
+            min_free_space: 1024 * 1024 * 1024,
+            require_gpu: false,
+            source_timeout: Duration::from_secs(10),
+            runtime_registry: Arc::new(RuntimeRegistry::new()),
+            version_policy: VersionPolicy::default(),
+        }
+    }
+
+    /// Local model repository whose model directories and configs are validated.
+    pub fn with_models_dir(mut self, models_dir: Option<PathBuf>) -> Self {
+        self.models_dir = models_dir;
+        self
+    }
+
+    pub fn with_sources(mut self, sources: Vec<ModelSource>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Cache directory for downloaded models and the free space (in bytes) it needs.
+    pub fn with_model_store(mut self, dir: impl Into<PathBuf>, min_free_space: u64) -> Self {
+        self.model_store_dir = Some(dir.into());
+        self.min_free_space = min_free_space;
+        self
+    }
+
+    /// Turns a missing GPU driver from a warning into a failure.
+    pub fn with_require_gpu(mut self, require_gpu: bool) -> Self {
+        self.require_gpu = require_gpu;
+        self
+    }
+
+    pub fn with_source_timeout(mut self, timeout: Duration) -> Self {
+        self.source_timeout = timeout;
+        self
+    }
+
+    /// Runtime backends `validate` loads the artifacts with, and the versions it loads.
+    pub fn with_artifact_loading(
+        mut self,
+        registry: Arc<RuntimeRegistry>,
+        version_policy: VersionPolicy,
+    ) -> Self {
+        self.runtime_registry = registry;
+        self.version_policy = version_policy;
+        self
+    }
+
+    /// Checks the configuration, the models directory and the artifacts of its models,
+    /// without looking at the environment.
+    pub fn validate(&self) -> PreflightReport {
+        let mut checks = self.check_config();
+        if let Some(models_dir) = &self.models_dir {
+            checks.extend(check_models_dir(models_dir));
+            checks.extend(self.check_artifacts(models_dir));
+        }
+        PreflightReport { checks }
+    }
+
+    pub async fn run(&self) -> PreflightReport {
+        let mut checks = vec![self.check_gpu()];
+        checks.extend(self.check_config());
+        match &self.config.shared_port {
+            Some(shared) => checks.push(check_port("port", shared.host(), shared.port(), "--port")),
+            None => {
+                checks.push(check_port(
+                    "rest port",
+                    &self.config.rest_hostname,
+                    self.config.rest_port,
+                    "--rest-port",
+                ));
+                checks.push(check_port(
+                    "grpc port",
+                    &self.config.grpc_hostname,
+                    self.config.grpc_port,
+                    "--grpc-port",
+                ));
+            }
+        }
+        if let Some(port) = self.config.admin_port {
+            checks.push(check_port(
+                "admin port",
+                &self.config.admin_hostname,
+                port,
+                "--admin-port",
+            ));
+        }
+        if let Some(models_dir) = &self.models_dir {
+            checks.extend(check_models_dir(models_dir));
+        }
+        for source in &self.sources {
+            checks.push(self.check_source(source).await);
+        }
+        if let Some(dir) = &self.model_store_dir {
+            checks.push(check_disk_space(dir, self.min_free_space));
+        }
+        PreflightReport { checks }
+    }
+
+    fn check_gpu(&self) -> CheckResult {
+        if let Ok(version) = std::fs::read_to_string(NVIDIA_DRIVER_VERSION_FILE) {
+            let driver = version.lines().next().unwrap_or_default().trim();
+            return CheckResult::pass("gpu", format!("NVIDIA driver loaded ({})", driver));
+        }
+        if let Ok(output) = Command::new("nvidia-smi")
+            .args(["--query-gpu=name,driver_version", "--format=csv,noheader"])
+            .output()
+            && output.status.success()
+        {
+            let gpus = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join("; ");
+            return CheckResult::pass("gpu", gpus);
+        }
+
+        let detail = "No NVIDIA driver found, models will run on CPU";
+        let hint = "Install the NVIDIA driver and make /dev/nvidia* available to the server";
+        if self.require_gpu {
+            CheckResult::fail("gpu", detail, hint)
+        } else {
+            CheckResult::warn("gpu", detail, hint)
+        }
+    }
+
+    fn check_config(&self) -> Vec<CheckResult> {
+        let mut checks = Vec::new();
+        let limits = &self.config.limits;
+
+        if self.config.shared_port.is_none() && self.config.rest_port == self.config.grpc_port {
+            checks.push(CheckResult::fail(
+                "config",
+                format!(
+                    "REST and gRPC servers both use port {}",
+                    self.config.rest_port
+                ),
+                "Pass different values for --rest-port and --grpc-port",
+            ));
+        }
+        let public_ports = match &self.config.shared_port {
+            Some(shared) => vec![shared.port()],
+            None => vec![self.config.rest_port, self.config.grpc_port],
+        };
+        if let Some(port) = self
+            .config
+            .admin_port
+            .filter(|port| public_ports.contains(port))
+        {
+            checks.push(CheckResult::fail(
+                "config",
+                format!("The admin API and a public server both use port {}", port),
+                "Pass a port of its own to --admin-port",
+            ));
+        }
+        if limits.max_concurrent_streams == 0 {
+            checks.push(CheckResult::fail(
+                "config",
+                "max_concurrent_streams is 0, no request could be served",
+                "Set --max-concurrent-streams to at least 1",
+            ));
+        }
+        if limits.header_read_timeout.is_zero() || limits.idle_timeout.is_zero() {
+            checks.push(CheckResult::fail(
+                "config",
+                "Connection timeouts must be greater than zero",
+                "Set --header-read-timeout and --idle-timeout to at least 1 second",
+            ));
+        } else if limits.header_read_timeout > limits.idle_timeout {
+            checks.push(CheckResult::warn(
+                "config",
+                format!(
+                    "Header read timeout ({}s) exceeds the idle timeout ({}s)",
+                    limits.header_read_timeout.as_secs(),
+                    limits.idle_timeout.as_secs()
+                ),
+                "Lower --header-read-timeout or raise --idle-timeout",
+            ));
+        }
+        if self.config.overload.policy().capacity == 0 {
+            checks.push(CheckResult::fail(
+                "config",
+                "Overload capacity is 0, every request would run degraded",
+                "Set --overload-capacity to at least 1",
+            ));
+        }
+        if let Some(tls) = &self.config.tls
+            && let Err(e) = tls.server_config()
+        {
+            checks.push(CheckResult::fail(
+                "config",
+                format!("Invalid TLS configuration: {:#}", e),
+                "Check the files given with --tls-cert, --tls-key and --tls-client-ca",
+            ));
+        }
+        if let Some(cors) = &self.config.rest_cors
+            && let Err(e) = cors.check()
+        {
+            checks.push(CheckResult::fail(
+                "config",
+                format!("Invalid REST CORS configuration: {}", e),
+                "Check the --rest-cors-* flags",
+            ));
+        }
+
+        if checks.is_empty() {
+            checks.push(CheckResult::pass(
+                "config",
+                "Server configuration is consistent",
+            ));
+        }
+        checks
+    }
+
+    /// Loads the artifact of every version `start` would load from `models_dir`, on the CPU.
+    /// Models that `check_models_dir` refuses are skipped.
+    fn check_artifacts(&self, models_dir: &Path) -> Vec<CheckResult> {
+        let name = "artifacts";
+        let Ok(entries) = std::fs::read_dir(models_dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        paths.sort();
+
+        let mut checks = Vec::new();
+        for path in paths {
+            let model = match LegacyModel::detect(&path) {
+                Ok(Some(model)) => model,
+                Ok(None) if ModelId::from_path(path.clone()).is_some() => {
+                    // Native model directories are served as they are, their files are the artifact.
+                    let empty = std::fs::read_dir(&path)
+                        .map(|mut files| files.next().is_none())
+                        .unwrap_or(true);
+                    if empty {
+                        checks.push(CheckResult::fail(
+                            name,
+                            format!("{} holds no artifact", path.display()),
+                            "Copy the model artifact into its directory",
+                        ));
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let selected = self.version_policy.select(
+                model
+                    .versions
+                    .iter()
+                    .map(|version| version.version.clone())
+                    .collect(),
+            );
+            for version in model
+                .versions
+                .iter()
+                .filter(|version| selected.contains(&version.version))
+            {
+                let version_id = ModelVersionId::new(model.name.clone(), version.version.clone());
+                if !version
+                    .backends
+                    .iter()
+                    .any(|backend| self.runtime_registry.get(backend).is_some())
+                {
+                    checks.push(CheckResult::warn(
+                        name,
+                        format!(
+                            "{} was not loaded, no runtime backend for {:?} (available: {:?})",
+                            version_id,
+                            version.backends,
+                            self.runtime_registry.backends()
+                        ),
+                        "Build the server with a runtime backend for this artifact",
+                    ));
+                    continue;
+                }
+                match self.runtime_registry.load_on(
+                    &version.backends,
+                    &version_id,
+                    &version.dir,
+                    Device::Cpu,
+                ) {
+                    Ok(runtime) => checks.push(CheckResult::pass(
+                        name,
+                        format!(
+                            "{} loads ({})",
+                            version_id,
+                            runtime.platform().unwrap_or("unknown backend")
+                        ),
+                    )),
+                    Err(e) => checks.push(CheckResult::fail(
+                        name,
+                        format!("{}: {:#}", version_id, e),
+                        format!("Fix or replace the artifact in {}", version.dir.display()),
+                    )),
+                }
+            }
+        }
+        checks
+    }
+
+    async fn check_source(&self, source: &ModelSource) -> CheckResult {
+        let name = "model source";
+        match source {
+            ModelSource::Path(path) => {
+                if path.exists() {
+                    CheckResult::pass(name, format!("{} exists", path.display()))
+                } else {
+                    CheckResult::fail(
+                        name,
+                        format!("{} does not exist", path.display()),
+                        "Create the directory or fix the --model-source path",
+                    )
+                }
+            }
+            ModelSource::MLFlow {
+                base_url,
+                api_token,
+                model_name,
+            } => {
+                let client = MLFlowClient::new(base_url.clone(), api_token.clone());
+                let result = match model_name {
+                    Some(model_name) => {
+                        tokio::time::timeout(self.source_timeout, client.get_model(model_name))
+                            .await
+                            .map(|result| {
+                                result.and_then(|model| {
+                                    model.map(|_| 1).ok_or_else(|| {
+                                        anyhow::anyhow!("model '{}' is not registered", model_name)
+                                    })
+                                })
+                            })
+                    }
+                    None => tokio::time::timeout(self.source_timeout, client.list_models())
+                        .await
+                        .map(|result| result.map(|models| models.len())),
+                };
+                match result {
+                    Ok(Ok(count)) => CheckResult::pass(
+                        name,
+                        format!(
+                            "MLflow at {} reachable, {} registered model(s)",
+                            base_url, count
+                        ),
+                    ),
+                    Ok(Err(e)) => CheckResult::fail(
+                        name,
+                        format!("MLflow at {}: {}", base_url, e),
+                        "Check --mlflow-uri, --mlflow-model and MLFLOW_TRACKING_TOKEN",
+                    ),
+                    Err(_) => CheckResult::fail(
+                        name,
+                        format!("MLflow at {} did not answer in time", base_url),
+                        "Check that the tracking server is running and reachable",
+                    ),
+                }
+            }
+            ModelSource::Url(_) | ModelSource::Id(_) => {
+                CheckResult::pass(name, format!("{:?} needs no connectivity check", source))
+            }
+            source => {
+                let Some((client, source_key, prefix)) = source.object_store() else {
+                    return CheckResult::pass(name, format!("{:?}", source));
+                };
+                match tokio::time::timeout(self.source_timeout, client.list_objects(&prefix)).await
+                {
+                    Ok(Ok(objects)) if objects.is_empty() => CheckResult::warn(
+                        name,
+                        format!("{} has no objects under '{}'", source_key, prefix),
+                        "Upload models as <prefix>/<model>/... or fix the source prefix",
+                    ),
+                    Ok(Ok(objects)) => CheckResult::pass(
+                        name,
+                        format!(
+                            "{} reachable, {} object(s) under '{}'",
+                            source_key,
+                            objects.len(),
+                            prefix
+                        ),
+                    ),
+                    Ok(Err(e)) => CheckResult::fail(
+                        name,
+                        format!("{}: {}", source_key, e),
+                        "Check the bucket name and the AWS_*, GCS_ACCESS_TOKEN or \
+                         AZURE_STORAGE_SAS_TOKEN credentials",
+                    ),
+                    Err(_) => CheckResult::fail(
+                        name,
+                        format!("{} did not answer in time", source_key),
+                        "Check network access to the object store endpoint",
+                    ),
+                }
+            }
+        }
+    }
+}
+
+fn check_port(name: &str, host: &str, port: u16, flag: &str) -> CheckResult {
+    match TcpListener::bind((host, port)) {
+        Ok(_) => CheckResult::pass(name, format!("{}:{} is available", host, port)),
+        Err(e) => CheckResult::fail(
+            name,
+            format!("Cannot bind {}:{}: {}", host, port, e),
+            format!(
+                "Stop the process using the port or choose another one with {}",
+                flag
+            ),
+        ),
+    }
+}
+
+/// Validates the model directories and their configuration files.
+fn check_models_dir(models_dir: &Path) -> Vec<CheckResult> {
+    let name = "models dir";
+    let entries = match std::fs::read_dir(models_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return vec![CheckResult::fail(
+                name,
+                format!("Cannot read {}: {}", models_dir.display(), e),
+                "Point MODELS_DIR to an existing model repository",
+            )];
+        }
+    };
+
+    let mut checks = Vec::new();
+    let mut models = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        match LegacyModel::detect(&path) {
+            Ok(Some(_)) => {}
+            Ok(None) if ModelId::from_path(path.clone()).is_some() => {}
+            Ok(None) => {
+                checks.push(CheckResult::warn(
+                    name,
+                    format!("{} is not recognized as a model", path.display()),
+                    "Name model directories <model>.<format>, e.g. resnet.onnx, or use a Triton or MLServer layout",
+                ));
+                continue;
+            }
+            Err(e) => {
+                checks.push(CheckResult::fail(
+                    name,
+                    format!("{}: {:#}", path.display(), e),
+                    "Fix the config.pbtxt or model-settings.json of this model",
+                ));
+                continue;
+            }
+        }
+        match ModelConfig::load(&path) {
+            Ok(_) => models += 1,
+            Err(e) => checks.push(CheckResult::fail(
+                name,
+                e.to_string(),
+                "Fix the model.yaml or config.pbtxt of this model",
+            )),
+        }
+    }
+
+    if models == 0 && checks.is_empty() {
+        checks.push(CheckResult::warn(
+            name,
+            format!("{} contains no models", models_dir.display()),
+            "Add model directories or configure a --model-source",
+        ));
+    } else {
+        checks.insert(
+            0,
+            CheckResult::pass(
+                name,
+                format!("{} model(s) in {}", models, models_dir.display()),
+            ),
+        );
+    }
+    checks
+}
+
+fn check_disk_space(dir: &Path, min_free_space: u64) -> CheckResult {
+    let name = "disk space";
+    // The cache directory is created on first use, so check the closest existing ancestor.
+    let Some(existing) = dir.ancestors().find(|path| path.exists()) else {
+        return CheckResult::fail(
+            name,
+            format!("No existing parent of {}", dir.display()),
+            "Choose another --model-store-dir",
+        );
+    };
+    match free_space(existing) {
+        Some(free) if free >= min_free_space => CheckResult::pass(
+            name,
+            format!("{} MiB free for {}", free / (1024 * 1024), dir.display()),
+        ),
+        Some(free) => CheckResult::fail(
+            name,
+            format!(
+                "Only {} MiB free for {}, {} MiB required",
+                free / (1024 * 1024),
+                dir.display(),
+                min_free_space / (1024 * 1024)
+            ),
+            "Free up space or point --model-store-dir to a larger volume",
+        ),
+        None => CheckResult::warn(
+            name,
+            format!("Could not determine free space for {}", dir.display()),
+            "Make sure the volume holding the model store has enough space",
+        ),
+    }
+}
+
+/// Free bytes on the file system holding `path`, as reported by POSIX `df`.
+fn free_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let available_kb: u64 = stdout
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(available_kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionLimits;
+    use crate::ids::IdScheme;
+    use crate::overload::OverloadController;
+    use std::sync::Arc;
+
+    fn config(rest_port: u16, grpc_port: u16) -> InferenceServerConfig {
+        InferenceServerConfig {
+            rest_hostname: "127.0.0.1".to_string(),
+            rest_port,
+            grpc_hostname: "127.0.0.1".to_string(),
+            grpc_port,
+            shared_port: None,
+            analytics: None,
+            audit: None,
+            limits: ConnectionLimits::default(),
+            overload: Arc::new(OverloadController::default()),
+            ids: IdScheme::default().provider(),
+            cors_origins: Vec::new(),
+            rest_cors: None,
+            rate_limiter: Arc::new(crate::RateLimiter::default()),
+            concurrency: Arc::new(crate::ConcurrencyLimiter::default()),
+            traffic: Arc::new(crate::TrafficAccounting::default()),
+            quotas: Arc::new(crate::QuotaTracker::default()),
+            kafka: None,
+            batch_dir: None,
+            result_backend: None,
+            shared_memory: Arc::new(crate::SharedMemoryRegistry::default()),
+            stream_pacing: crate::StreamPacing::default(),
+            tls: None,
+            api_keys: None,
+            jwt: None,
+            config_reload: None,
+            admin_hostname: "127.0.0.1".to_string(),
+            admin_port: None,
+            trust_tenant_header: false,
+        }
+    }
+
+    fn statuses(report: &PreflightReport, name: &str) -> Vec<CheckStatus> {
+        report
+            .checks
+            .iter()
+            .filter(|check| check.name == name)
+            .map(|check| check.status)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_port_in_use_and_port_clash_fail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let report = Preflight::new(config(port, port)).run().await;
+        assert!(!report.passed());
+        assert_eq!(statuses(&report, "config"), vec![CheckStatus::Fail]);
+        assert_eq!(statuses(&report, "rest port"), vec![CheckStatus::Fail]);
+        assert!(report.to_string().contains("--rest-port"));
+    }
+
+    #[tokio::test]
+    async fn test_models_dir_and_sources() {
+        let root = std::env::temp_dir().join(format!("galemind-preflight-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("good.onnx")).unwrap();
+        std::fs::create_dir_all(root.join("bad.onnx")).unwrap();
+        std::fs::write(root.join("bad.onnx/model.yaml"), "instance_count: 0").unwrap();
+
+        let report = Preflight::new(config(0, 0))
+            .with_models_dir(Some(root.clone()))
+            .with_sources(vec![ModelSource::Path(root.join("missing"))])
+            .with_model_store(root.join("cache"), 0)
+            .run()
+            .await;
+
+        assert_eq!(
+            statuses(&report, "models dir"),
+            vec![CheckStatus::Pass, CheckStatus::Fail]
+        );
+        assert_eq!(statuses(&report, "model source"), vec![CheckStatus::Fail]);
+        assert_ne!(statuses(&report, "disk space"), vec![CheckStatus::Fail]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_validate_loads_the_served_versions() {
+        let root = std::env::temp_dir().join(format!("galemind-validate-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("densenet/1")).unwrap();
+        std::fs::create_dir_all(root.join("densenet/3")).unwrap();
+        std::fs::write(root.join("densenet/config.pbtxt"), "backend: \"fake\"\n").unwrap();
+        std::fs::create_dir_all(root.join("ranker/1")).unwrap();
+        std::fs::write(root.join("ranker/config.pbtxt"), "backend: \"onnx\"\n").unwrap();
+        std::fs::create_dir_all(root.join("empty.onnx")).unwrap();
+        let registry = Arc::new(RuntimeRegistry::new());
+        registry.register(Arc::new(crate::api::fake::FakeRuntimeFactory));
+
+        let report = Preflight::new(config(8080, 50051))
+            .with_models_dir(Some(root.clone()))
+            .with_artifact_loading(registry, VersionPolicy::default())
+            .validate();
+
+        // The latest version of densenet only, the empty model, then ranker which no backend
+        // of this build loads.
+        assert_eq!(
+            statuses(&report, "artifacts"),
+            vec![CheckStatus::Pass, CheckStatus::Fail, CheckStatus::Warn]
+        );
+        assert!(report.to_string().contains("densenet:3 loads (fake)"));
+        assert!(statuses(&report, "rest port").is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
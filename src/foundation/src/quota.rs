@@ -0,0 +1,422 @@
+/* Request and token quotas per account.
+
+Every inference request is charged to an account: the name of the API key or
+the subject of the token that authenticated it, or else the tenant a trusted
+proxy names (see `tenants::request_tenant`). Requests with neither are not
+counted.
+
+An account may send `requests` requests and use `tokens` tokens per window.
+Windows are fixed and aligned to the Unix epoch, so a daily window starts at
+midnight UTC and every account starts over at the same time. The tokens of a
+request are those of its prompt, counted with the model's tokenizer, plus the
+`max_tokens` it asks for; they are charged upfront, so a request that would
+exceed the quota is refused rather than cut short. The requests of a stream
+are charged together, all of them or none.
+
+`QuotaLimits::accounts` overrides the default quota for specific accounts.
+Both servers report the quota of the account in the `x-quota-*` headers of
+inference responses, and `GET /{version}/usage` reports it to the caller.
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const QUOTA_LIMIT_REQUESTS_HEADER: &str = "x-quota-limit-requests";
+pub const QUOTA_REMAINING_REQUESTS_HEADER: &str = "x-quota-remaining-requests";
+pub const QUOTA_LIMIT_TOKENS_HEADER: &str = "x-quota-limit-tokens";
+pub const QUOTA_REMAINING_TOKENS_HEADER: &str = "x-quota-remaining-tokens";
+/// Seconds until the window ends and the quota starts over.
+pub const QUOTA_RESET_HEADER: &str = "x-quota-reset";
+
+/// Parses a window length: a count followed by `s`, `m`, `h` or `d`, e.g. `1h`.
+pub fn parse_window(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let count: u64 = count
+        .parse()
+        .map_err(|_| anyhow!("Invalid quota window '{}'", s))?;
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => return Err(anyhow!("Unknown unit in quota window '{}'", s)),
+    };
+    if count == 0 {
+        return Err(anyhow!("Quota window '{}' is empty", s));
+    }
+    Ok(Duration::from_secs(count.saturating_mul(seconds)))
+}
+
+/// Requests and tokens an account may use per window; unlimited where unset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Quota {
+    pub requests: Option<u64>,
+    pub tokens: Option<u64>,
+}
+
+impl Quota {
+    fn is_unlimited(&self) -> bool {
+        self.requests.is_none() && self.tokens.is_none()
+    }
+}
+
+impl FromStr for Quota {
+    type Err = anyhow::Error;
+
+    /// `<requests>[:<tokens>]`, where `-` leaves either unlimited, e.g. `1000:50000`
+    /// or `-:50000`.
+    fn from_str(s: &str) -> Result<Self> {
+        let limit = |value: &str| match value.trim() {
+            "-" => Ok(None),
+            value => value
+                .parse()
+                .map(Some)
+                .map_err(|_| anyhow!("Invalid quota '{}', expected <requests>[:<tokens>]", s)),
+        };
+        let (requests, tokens) = s.split_once(':').unwrap_or((s, "-"));
+        Ok(Self {
+            requests: limit(requests)?,
+            tokens: limit(tokens)?,
+        })
+    }
+}
+
+/// Quotas from the server configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaLimits {
+    pub window: Duration,
+    /// Quota of accounts without one of their own.
+    pub default: Quota,
+    /// Quotas of specific accounts, replacing `default`.
+    pub accounts: HashMap<String, Quota>,
+}
+
+impl Default for QuotaLimits {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(86_400),
+            default: Quota::default(),
+            accounts: HashMap::new(),
+        }
+    }
+}
+
+impl QuotaLimits {
+    fn quota(&self, account: &str) -> Quota {
+        self.accounts.get(account).copied().unwrap_or(self.default)
+    }
+
+    /// Whether any account has a quota at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.default.is_unlimited() || self.accounts.values().any(|q| !q.is_unlimited())
+    }
+}
+
+/// Why a request was refused by the quota of its account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaRefusal {
+    Requests {
+        account: String,
+        limit: u64,
+    },
+    Tokens {
+        account: String,
+        limit: u64,
+        remaining: u64,
+        requested: u64,
+    },
+}
+
+impl fmt::Display for QuotaRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Requests { account, limit } => write!(
+                f,
+                "Account '{}' used its {} requests of the quota window",
+                account, limit
+            ),
+            Self::Tokens {
+                account,
+                limit,
+                remaining,
+                requested,
+            } => write!(
+                f,
+                "Request needs {} tokens but account '{}' has {} of its {} tokens left in the \
+                 quota window",
+                requested, account, remaining, limit
+            ),
+        }
+    }
+}
+
+/// Usage and quota of an account in the current window, as reported to the caller.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaUsage {
+    pub account: String,
+    pub requests: u64,
+    pub tokens: u64,
+    pub limits: Quota,
+    pub remaining: Quota,
+    /// Seconds until the window ends.
+    pub reset_seconds: u64,
+}
+
+impl QuotaUsage {
+    /// The `x-quota-*` headers of a response, for the limits the account has.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let (Some(limit), Some(remaining)) = (self.limits.requests, self.remaining.requests) {
+            headers.push((QUOTA_LIMIT_REQUESTS_HEADER, limit.to_string()));
+            headers.push((QUOTA_REMAINING_REQUESTS_HEADER, remaining.to_string()));
+        }
+        if let (Some(limit), Some(remaining)) = (self.limits.tokens, self.remaining.tokens) {
+            headers.push((QUOTA_LIMIT_TOKENS_HEADER, limit.to_string()));
+            headers.push((QUOTA_REMAINING_TOKENS_HEADER, remaining.to_string()));
+        }
+        if !headers.is_empty() {
+            headers.push((QUOTA_RESET_HEADER, self.reset_seconds.to_string()));
+        }
+        headers
+    }
+}
+
+#[derive(Debug, Default)]
+struct WindowUsage {
+    window: u64,
+    requests: u64,
+    tokens: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Counts the requests and tokens of each account and enforces the quotas, see the module
+/// documentation. Times are seconds since the Unix epoch.
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    limits: RwLock<Arc<QuotaLimits>>,
+    accounts: DashMap<String, WindowUsage>,
+}
+
+impl QuotaTracker {
+    pub fn new(limits: QuotaLimits) -> Self {
+        Self {
+            limits: RwLock::new(Arc::new(limits)),
+            accounts: DashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> Arc<QuotaLimits> {
+        self.limits.read().unwrap().clone()
+    }
+
+    /// Replaces the quotas, applying to the following requests. Usage counted so far in
+    /// the window is kept, so a changed window starts counting anew.
+    pub fn set_limits(&self, limits: QuotaLimits) {
+        *self.limits.write().unwrap() = Arc::new(limits);
+    }
+
+    fn window_length(&self) -> u64 {
+        self.limits().window.as_secs().max(1)
+    }
+
+    /// Charges a request using `tokens` tokens to `account`, unless it would exceed the
+    /// account's quota. Returns the usage after the charge.
+    pub fn admit(&self, account: &str, tokens: u64) -> Result<QuotaUsage, QuotaRefusal> {
+        self.admit_at(account, tokens, now())
+    }
+
+    pub fn admit_at(
+        &self,
+        account: &str,
+        tokens: u64,
+        now: u64,
+    ) -> Result<QuotaUsage, QuotaRefusal> {
+        self.admit_batch_at(account, &[tokens], now)
+    }
+
+    /// Charges requests using `tokens` tokens each to `account`, all of them or none if
+    /// together they would exceed the account's quota.
+    pub fn admit_batch(&self, account: &str, tokens: &[u64]) -> Result<QuotaUsage, QuotaRefusal> {
+        self.admit_batch_at(account, tokens, now())
+    }
+
+    pub fn admit_batch_at(
+        &self,
+        account: &str,
+        tokens: &[u64],
+        now: u64,
+    ) -> Result<QuotaUsage, QuotaRefusal> {
+        let requests = tokens.len() as u64;
+        let tokens = tokens
+            .iter()
+            .fold(0u64, |sum, &tokens| sum.saturating_add(tokens));
+        let window = now / self.window_length();
+        if !self.accounts.contains_key(account) {
+            // Accounts idle since an earlier window would start over anyway.
+            self.accounts.retain(|_, usage| usage.window >= window);
+        }
+        let quota = self.limits().quota(account);
+        {
+            let mut usage = self.accounts.entry(account.to_string()).or_default();
+            if usage.window != window {
+                *usage = WindowUsage {
+                    window,
+                    ..WindowUsage::default()
+                };
+            }
+            if let Some(limit) = quota.requests
+                && usage.requests.saturating_add(requests) > limit
+            {
+                return Err(QuotaRefusal::Requests {
+                    account: account.to_string(),
+                    limit,
+                });
+            }
+            if let Some(limit) = quota.tokens
+                && usage.tokens.saturating_add(tokens) > limit
+            {
+                return Err(QuotaRefusal::Tokens {
+                    account: account.to_string(),
+                    limit,
+                    remaining: limit.saturating_sub(usage.tokens),
+                    requested: tokens,
+                });
+            }
+            usage.requests += requests;
+            usage.tokens = usage.tokens.saturating_add(tokens);
+        }
+        Ok(self.usage_at(account, now))
+    }
+
+    /// Usage of `account` in the current window.
+    pub fn usage(&self, account: &str) -> QuotaUsage {
+        self.usage_at(account, now())
+    }
+
+    pub fn usage_at(&self, account: &str, now: u64) -> QuotaUsage {
+        let length = self.window_length();
+        let window = now / length;
+        let (requests, tokens) = self
+            .accounts
+            .get(account)
+            .filter(|usage| usage.window == window)
+            .map(|usage| (usage.requests, usage.tokens))
+            .unwrap_or_default();
+        let limits = self.limits().quota(account);
+        QuotaUsage {
+            account: account.to_string(),
+            requests,
+            tokens,
+            limits,
+            remaining: Quota {
+                requests: limits.requests.map(|limit| limit.saturating_sub(requests)),
+                tokens: limits.tokens.map(|limit| limit.saturating_sub(tokens)),
+            },
+            reset_seconds: (window + 1) * length - now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accounts_are_refused_past_their_quota_until_the_window_ends() {
+        assert_eq!(parse_window("1h").unwrap(), Duration::from_secs(3_600));
+        assert!(parse_window("0d").is_err());
+        assert!(parse_window("1w").is_err());
+        assert_eq!(
+            "1000:-".parse::<Quota>().unwrap(),
+            Quota {
+                requests: Some(1000),
+                tokens: None
+            }
+        );
+        assert_eq!("2".parse::<Quota>().unwrap().tokens, None);
+        assert!("many".parse::<Quota>().is_err());
+
+        let tracker = QuotaTracker::new(QuotaLimits {
+            window: Duration::from_secs(60),
+            default: Quota {
+                requests: Some(2),
+                tokens: Some(100),
+            },
+            accounts: HashMap::from([("big".to_string(), Quota::default())]),
+        });
+        assert!(tracker.limits().is_enabled());
+
+        let usage = tracker.admit_at("ci", 40, 130).unwrap();
+        assert_eq!((usage.requests, usage.tokens), (1, 40));
+        assert_eq!(usage.remaining.tokens, Some(60));
+        assert_eq!(usage.reset_seconds, 50);
+        assert_eq!(usage.headers().len(), 5);
+        assert_eq!(
+            tracker.admit_at("ci", 70, 131),
+            Err(QuotaRefusal::Tokens {
+                account: "ci".to_string(),
+                limit: 100,
+                remaining: 60,
+                requested: 70,
+            })
+        );
+        tracker.admit_at("ci", 60, 132).unwrap();
+        assert!(matches!(
+            tracker.admit_at("ci", 0, 133),
+            Err(QuotaRefusal::Requests { limit: 2, .. })
+        ));
+        // Unlimited accounts are counted all the same.
+        for _ in 0..5 {
+            tracker.admit_at("big", 1000, 133).unwrap();
+        }
+        assert_eq!(tracker.usage_at("big", 133).requests, 5);
+        assert!(tracker.usage_at("big", 133).headers().is_empty());
+
+        // The next window starts over.
+        assert_eq!(tracker.usage_at("ci", 180).requests, 0);
+        tracker.admit_at("ci", 100, 180).unwrap();
+    }
+
+    #[test]
+    fn test_batches_are_charged_whole_or_not_at_all() {
+        let tracker = QuotaTracker::new(QuotaLimits {
+            window: Duration::from_secs(60),
+            default: Quota {
+                requests: Some(3),
+                tokens: Some(100),
+            },
+            accounts: HashMap::new(),
+        });
+        let usage = tracker.admit_batch_at("ci", &[10, 20], 0).unwrap();
+        assert_eq!((usage.requests, usage.tokens), (2, 30));
+        assert!(matches!(
+            tracker.admit_batch_at("ci", &[1, 1], 1),
+            Err(QuotaRefusal::Requests { limit: 3, .. })
+        ));
+        assert_eq!(
+            tracker.admit_batch_at("ci", &[71], 1),
+            Err(QuotaRefusal::Tokens {
+                account: "ci".to_string(),
+                limit: 100,
+                remaining: 70,
+                requested: 71,
+            })
+        );
+        let usage = tracker.usage_at("ci", 1);
+        assert_eq!((usage.requests, usage.tokens), (2, 30));
+    }
+}
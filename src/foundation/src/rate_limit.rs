@@ -0,0 +1,356 @@
+/* Token-bucket rate limiting of inference requests.
+
+Each client and each model has a bucket holding up to `burst` tokens, refilled
+at `rate` tokens per second. An inference takes one token from the bucket of
+its client and one from the bucket of its model; when either is empty the
+request is refused, with the time until a token is available. Clients are
+identified by the account they authenticated as (see `auth::Authenticated`),
+and by their IP address otherwise: nothing the client sends unverified, such
+as a fresh key header on every request, gets it a new bucket. Limits come from the server configuration: one
+limit for every client, one for every model and optional per-model overrides.
+They can be replaced at runtime; buckets keep their tokens, capped by the new
+burst at their next refill.
+Buckets that have refilled completely are forgotten once many are tracked,
+since a full bucket is what a new client or model starts with anyway.
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::auth::Authenticated;
+
+/// Header (or gRPC metadata entry) some clients send their API key in, recorded by the
+/// audit log.
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Response headers describing the most constraining bucket of a request.
+pub const RATE_LIMIT_LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+pub const RATE_LIMIT_RESET_HEADER: &str = "x-ratelimit-reset";
+
+/// Buckets tracked per map before full ones are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Sustained rate and burst of a bucket, written `<count>/<s|m|h>[:<burst>]`, e.g.
+/// `100/s` or `6000/m:200`. The burst defaults to the count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added per second.
+    pub rate: f64,
+    pub burst: u32,
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid rate limit '{}', expected <count>/<s|m|h>[:<burst>]",
+                s
+            )
+        };
+        let (rate, burst) = match s.trim().split_once(':') {
+            Some((rate, burst)) => (rate, Some(burst.parse::<u32>().map_err(|_| invalid())?)),
+            None => (s.trim(), None),
+        };
+        let (count, unit) = rate.split_once('/').ok_or_else(invalid)?;
+        let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+        let seconds = match unit.trim() {
+            "s" | "sec" | "second" => 1.0,
+            "m" | "min" | "minute" => 60.0,
+            "h" | "hour" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        let burst = burst.unwrap_or(count);
+        if count == 0 || burst == 0 {
+            return Err(anyhow!(
+                "Rate limit '{}' must admit at least one request",
+                s
+            ));
+        }
+        Ok(RateLimit {
+            rate: count as f64 / seconds,
+            burst,
+        })
+    }
+}
+
+/// Limits from the server configuration; no limit applies where none is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    pub per_client: Option<RateLimit>,
+    pub per_model: Option<RateLimit>,
+    /// Limits of specific models, replacing `per_model`.
+    pub models: HashMap<String, RateLimit>,
+}
+
+impl RateLimits {
+    fn model_limit(&self, model: &str) -> Option<RateLimit> {
+        self.models.get(model).copied().or(self.per_model)
+    }
+}
+
+/// State of the most constraining bucket after a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateDecision {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+}
+
+/// A request refused because one of its buckets is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    /// `client` or `model '<name>'`.
+    pub scope: String,
+    pub decision: RateDecision,
+    /// Time until the bucket holds a token again.
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rate limit of the {} exceeded, retry in {:.1}s",
+            self.scope,
+            self.retry_after.as_secs_f64()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst as f64);
+        self.updated = now;
+    }
+
+    fn decision(&self, limit: RateLimit) -> RateDecision {
+        RateDecision {
+            limit: limit.burst,
+            remaining: self.tokens.floor() as u32,
+            reset: Duration::from_secs_f64((limit.burst as f64 - self.tokens) / limit.rate),
+        }
+    }
+}
+
+/// Client key of a request: the account it authenticated as, or else its IP address.
+pub fn client_key(account: Option<&Authenticated>, addr: Option<IpAddr>) -> Option<String> {
+    match (account, addr) {
+        (Some(Authenticated(account)), _) => Some(format!("account:{}", account)),
+        (None, Some(addr)) => Some(format!("ip:{}", addr)),
+        (None, None) => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RwLock<Arc<RateLimits>>,
+    clients: DashMap<String, Bucket>,
+    models: DashMap<String, Bucket>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimits::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: RwLock::new(Arc::new(limits)),
+            clients: DashMap::new(),
+            models: DashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> Arc<RateLimits> {
+        self.limits.read().unwrap().clone()
+    }
+
+    /// Replaces the limits, applying to the following requests.
+    pub fn set_limits(&self, limits: RateLimits) {
+        *self.limits.write().unwrap() = Arc::new(limits);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        let limits = self.limits();
+        limits.per_client.is_some() || limits.per_model.is_some() || !limits.models.is_empty()
+    }
+
+    /// Takes a token for `client` and one for `model`, or none when either bucket is
+    /// empty. Returns the most constraining bucket, if any limit applies.
+    pub fn check(
+        &self,
+        client: Option<&str>,
+        model: Option<&str>,
+    ) -> Result<Option<RateDecision>, RateLimited> {
+        self.check_at(client, model, Instant::now())
+    }
+
+    pub fn check_at(
+        &self,
+        client: Option<&str>,
+        model: Option<&str>,
+        now: Instant,
+    ) -> Result<Option<RateDecision>, RateLimited> {
+        let limits = self.limits();
+        let client = client.zip(limits.per_client);
+        let model = model.and_then(|model| Some((model, limits.model_limit(model)?)));
+        if let Some((key, _)) = client {
+            forget_full(&self.clients, key, now, |_| limits.per_client);
+        }
+        if let Some((key, _)) = model {
+            forget_full(&self.models, key, now, |model| limits.model_limit(model));
+        }
+
+        // Entries are always taken clients first, so concurrent checks cannot deadlock.
+        let mut client_bucket = client.map(|(key, limit)| {
+            (
+                "client".to_string(),
+                limit,
+                self.clients
+                    .entry(key.to_string())
+                    .or_insert_with(|| Bucket::full(limit, now)),
+            )
+        });
+        let mut model_bucket = model.map(|(key, limit)| {
+            (
+                format!("model '{}'", key),
+                limit,
+                self.models
+                    .entry(key.to_string())
+                    .or_insert_with(|| Bucket::full(limit, now)),
+            )
+        });
+        let mut buckets: Vec<_> = client_bucket
+            .iter_mut()
+            .chain(model_bucket.iter_mut())
+            .collect();
+        for (_, limit, bucket) in buckets.iter_mut() {
+            bucket.refill(*limit, now);
+        }
+        if let Some((scope, limit, bucket)) =
+            buckets.iter().find(|(_, _, bucket)| bucket.tokens < 1.0)
+        {
+            return Err(RateLimited {
+                scope: scope.clone(),
+                decision: bucket.decision(*limit),
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate),
+            });
+        }
+        for (_, _, bucket) in buckets.iter_mut() {
+            bucket.tokens -= 1.0;
+        }
+        Ok(buckets
+            .iter()
+            .map(|(_, limit, bucket)| bucket.decision(*limit))
+            .min_by_key(|decision| decision.remaining))
+    }
+}
+
+/// Forgets the full buckets of `buckets` before tracking a new `key` among too many.
+fn forget_full(
+    buckets: &DashMap<String, Bucket>,
+    key: &str,
+    now: Instant,
+    limit_of: impl Fn(&str) -> Option<RateLimit>,
+) {
+    if buckets.len() < MAX_BUCKETS || buckets.contains_key(key) {
+        return;
+    }
+    buckets.retain(|key, bucket| {
+        limit_of(key).is_some_and(|limit| {
+            let mut bucket = bucket.clone();
+            bucket.refill(limit, now);
+            bucket.tokens < limit.burst as f64
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_parsing() {
+        let limit: RateLimit = "6000/m:200".parse().unwrap();
+        assert_eq!(limit.rate, 100.0);
+        assert_eq!(limit.burst, 200);
+        assert_eq!("10/s".parse::<RateLimit>().unwrap().burst, 10);
+        assert!("10/d".parse::<RateLimit>().is_err());
+        assert!("0/s".parse::<RateLimit>().is_err());
+        assert!("ten/s".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn test_buckets_per_client_and_per_model() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_client: Some("2/s".parse().unwrap()),
+            per_model: Some("3/s".parse().unwrap()),
+            models: HashMap::from([("big".to_string(), "1/s".parse().unwrap())]),
+        });
+        let now = Instant::now();
+        let a = client_key(Some(&Authenticated("a".to_string())), None);
+        let b = client_key(None, Some("10.0.0.2".parse().unwrap()));
+
+        let decision = limiter.check_at(a.as_deref(), Some("m"), now).unwrap();
+        assert_eq!(decision.unwrap().remaining, 1);
+        assert!(limiter.check_at(a.as_deref(), Some("m"), now).is_ok());
+        // Client a is out of tokens, model m still has one.
+        let limited = limiter.check_at(a.as_deref(), Some("m"), now).unwrap_err();
+        assert_eq!(limited.scope, "client");
+        assert_eq!(limited.retry_after, Duration::from_millis(500));
+        assert!(limiter.check_at(b.as_deref(), Some("m"), now).is_ok());
+        let limited = limiter.check_at(b.as_deref(), Some("m"), now).unwrap_err();
+        assert_eq!(limited.scope, "model 'm'");
+        // The refused request took no token from client b.
+        assert!(limiter.check_at(b.as_deref(), Some("big"), now).is_ok());
+        assert!(limiter.check_at(b.as_deref(), Some("big"), now).is_err());
+
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check_at(a.as_deref(), Some("big"), later).is_ok());
+        assert_eq!(
+            limiter.check_at(None, None, later).unwrap(),
+            None,
+            "requests without a client or model are not limited"
+        );
+    }
+
+    #[test]
+    fn test_replaced_limits_apply_to_following_requests() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_client: Some("1/s".parse().unwrap()),
+            ..RateLimits::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.check_at(Some("key:a"), None, now).is_ok());
+        assert!(limiter.check_at(Some("key:a"), None, now).is_err());
+
+        limiter.set_limits(RateLimits::default());
+        assert!(!limiter.is_enabled());
+        assert_eq!(limiter.check_at(Some("key:a"), None, now).unwrap(), None);
+    }
+}
@@ -0,0 +1,81 @@
+/* Readiness of the server, for load balancers and Kubernetes probes.
+
+The server is live as soon as it listens, and ready once it can answer
+inference requests. Until then the conditions it waits for are pending, each
+with the reason reported to probes:
+
+- `MODELS_LOADING`: models are loaded from the model directory and sources
+  after the servers start listening, so liveness probes pass during a long
+  load.
+- `SHUTTING_DOWN`: a draining server stops being ready so no new traffic is
+  routed to it, but stays healthy.
+
+On top of these, a model whose config names a runtime backend that is not
+registered makes the server unready, see
+`ModelDiscoveryService::readiness_failures`.
+*/
+
+use dashmap::DashMap;
+
+pub const MODELS_LOADING: &str = "models_loading";
+pub const SHUTTING_DOWN: &str = "shutting_down";
+
+/// Conditions the server waits for before it is ready, with their reasons.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    pending: DashMap<String, String>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the server unready until `condition` is completed.
+    pub fn wait_for(&self, condition: &str, reason: impl Into<String>) {
+        self.pending.insert(condition.to_string(), reason.into());
+    }
+
+    pub fn complete(&self, condition: &str) {
+        self.pending.remove(condition);
+    }
+
+    /// Pending conditions and their reasons, sorted by condition.
+    pub fn pending(&self) -> Vec<(String, String)> {
+        let mut pending: Vec<(String, String)> = self
+            .pending
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        pending.sort();
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_is_ready_once_its_conditions_complete() {
+        let readiness = Readiness::new();
+        assert!(readiness.pending().is_empty());
+
+        readiness.wait_for(SHUTTING_DOWN, "Draining");
+        readiness.wait_for(MODELS_LOADING, "Loading models");
+        readiness.wait_for(MODELS_LOADING, "Loading models from MLflow");
+        assert_eq!(
+            readiness.pending(),
+            vec![
+                (
+                    MODELS_LOADING.to_string(),
+                    "Loading models from MLflow".to_string()
+                ),
+                (SHUTTING_DOWN.to_string(), "Draining".to_string()),
+            ]
+        );
+        readiness.complete(MODELS_LOADING);
+        readiness.complete(SHUTTING_DOWN);
+        assert!(readiness.pending().is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+/* A minimal Redis client.
+
+The result backend (see `model::result_backend`) and the response cache (see
+`model::response_cache`) keep their entries in Redis. They share this client
+rather than linking a Redis library: it speaks the Redis protocol (RESP) over
+one connection, opened on first use and again after a failure, and sends
+commands one at a time.
+
+Servers are given as `redis://[[user]:password@]host[:port][/db]`. The
+credentials are sent with `AUTH` and the database selected with `SELECT` when
+the connection is opened.
+*/
+
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+const DEFAULT_REDIS_PORT: u16 = 6379;
+/// Bound on connecting and on each command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A Redis reply. Error replies are returned as errors by `RedisClient::command`.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+}
+
+/// `args` as a RESP array of bulk strings.
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend_from_slice(arg);
+        command.extend_from_slice(b"\r\n");
+    }
+    command
+}
+
+async fn read_reply<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Reply> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(anyhow!("Redis closed the connection"));
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" => Ok(Reply::Simple(rest.to_string())),
+        "-" => Ok(Reply::Error(rest.to_string())),
+        ":" => Ok(Reply::Integer(rest.parse()?)),
+        "$" => {
+            let len: i64 = rest.parse()?;
+            if len < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            let mut data = vec![0; len as usize + 2];
+            reader.read_exact(&mut data).await?;
+            data.truncate(len as usize);
+            Ok(Reply::Bulk(Some(data)))
+        }
+        _ => Err(anyhow!("Unexpected Redis reply '{}'", line)),
+    }
+}
+
+/// `reply`, unless Redis answered with an error.
+fn succeeded(reply: Reply) -> Result<Reply> {
+    match reply {
+        Reply::Error(message) => Err(anyhow!("Redis answered: {}", message)),
+        reply => Ok(reply),
+    }
+}
+
+/// Sends `args` and reads the reply. Fails when the connection does.
+async fn exchange(stream: &mut BufStream<TcpStream>, args: &[&[u8]]) -> Result<Reply> {
+    tokio::time::timeout(COMMAND_TIMEOUT, async {
+        stream.write_all(&encode_command(args)).await?;
+        stream.flush().await?;
+        read_reply(stream).await
+    })
+    .await
+    .map_err(|_| anyhow!("Timed out waiting for Redis"))?
+}
+
+/// A Redis server, reached over one connection.
+pub struct RedisClient {
+    /// `host:port`.
+    addr: String,
+    /// Arguments of the `AUTH` command, when the URI has credentials.
+    auth: Option<Vec<String>>,
+    db: u32,
+    connection: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RedisClient {
+    /// Parses `redis://[[user]:password@]host[:port][/db]`.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let rest = uri
+            .strip_prefix("redis://")
+            .ok_or_else(|| anyhow!("Invalid Redis URI '{}', expected redis://", uri))?;
+        let (authority, db) = rest.split_once('/').unwrap_or((rest, ""));
+        let (auth, host) = match authority.rsplit_once('@') {
+            Some((credentials, host)) => {
+                let auth = match credentials.split_once(':') {
+                    Some(("", password)) => vec![password.to_string()],
+                    Some((user, password)) => vec![user.to_string(), password.to_string()],
+                    None => vec![credentials.to_string()],
+                };
+                (Some(auth), host)
+            }
+            None => (None, authority),
+        };
+        if host.is_empty() {
+            return Err(anyhow!("Redis URI '{}' is missing a host", uri));
+        }
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_REDIS_PORT)
+        };
+        let db = match db {
+            "" => 0,
+            db => db
+                .parse()
+                .map_err(|_| anyhow!("Invalid Redis database '{}' in '{}'", db, uri))?,
+        };
+        Ok(Self {
+            addr,
+            auth,
+            db,
+            connection: Mutex::new(None),
+        })
+    }
+
+    async fn connect(&self) -> Result<BufStream<TcpStream>> {
+        let stream = tokio::time::timeout(COMMAND_TIMEOUT, TcpStream::connect(&self.addr))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to Redis at {}", self.addr))??;
+        let mut stream = BufStream::new(stream);
+        if let Some(auth) = &self.auth {
+            let mut args: Vec<&[u8]> = vec![b"AUTH"];
+            args.extend(auth.iter().map(|arg| arg.as_bytes()));
+            succeeded(exchange(&mut stream, &args).await?)?;
+        }
+        if self.db != 0 {
+            succeeded(exchange(&mut stream, &[b"SELECT", self.db.to_string().as_bytes()]).await?)?;
+        }
+        Ok(stream)
+    }
+
+    /// Sends `args`, connecting first if need be. A failed connection is dropped, and the
+    /// command retried once on a new one. The connection is only kept once the reply is
+    /// read, so a command abandoned halfway (e.g. by a timeout) does not leave its reply to
+    /// the next one.
+    pub async fn command(&self, args: &[&[u8]]) -> Result<Reply> {
+        let mut connection = self.connection.lock().await;
+        let mut retried = false;
+        loop {
+            let mut stream = match connection.take() {
+                Some(stream) => stream,
+                None => self.connect().await?,
+            };
+            match exchange(&mut stream, args).await {
+                Ok(reply) => {
+                    *connection = Some(stream);
+                    return succeeded(reply);
+                }
+                Err(e) => {
+                    if retried {
+                        return Err(e);
+                    }
+                    retried = true;
+                }
+            }
+        }
+    }
+}
+
+/// Leaves the credentials out.
+impl fmt::Debug for RedisClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisClient")
+            .field("addr", &self.addr)
+            .field("db", &self.db)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    /// Answers AUTH, SET and GET as Redis would, for one connection at a time. Its address.
+    pub(crate) async fn fake_redis() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut values: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut stream = BufStream::new(stream);
+                loop {
+                    let mut header = String::new();
+                    if stream.read_line(&mut header).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let count: usize = header.trim_end()[1..].parse().unwrap();
+                    let mut args = Vec::new();
+                    for _ in 0..count {
+                        match read_reply(&mut stream).await.unwrap() {
+                            Reply::Bulk(Some(arg)) => args.push(arg),
+                            reply => panic!("unexpected argument {:?}", reply),
+                        }
+                    }
+                    let reply: Vec<u8> = match args[0].as_slice() {
+                        b"AUTH" if args[1] == b"secret" => b"+OK\r\n".to_vec(),
+                        b"AUTH" => b"-WRONGPASS invalid password\r\n".to_vec(),
+                        b"SET" => {
+                            values.insert(args[1].clone(), args[2].clone());
+                            b"+OK\r\n".to_vec()
+                        }
+                        b"GET" => match values.get(&args[1]) {
+                            Some(value) => {
+                                let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                                reply.extend_from_slice(value);
+                                reply.extend_from_slice(b"\r\n");
+                                reply
+                            }
+                            None => b"$-1\r\n".to_vec(),
+                        },
+                        _ => b"-ERR unknown command\r\n".to_vec(),
+                    };
+                    stream.write_all(&reply).await.unwrap();
+                    stream.flush().await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_from_uri() {
+        let client = RedisClient::from_uri("redis://cache").unwrap();
+        assert_eq!(
+            (client.addr.as_str(), client.auth, client.db),
+            ("cache:6379", None, 0)
+        );
+        let client = RedisClient::from_uri("redis://app:pw@cache:7000/2").unwrap();
+        assert_eq!(client.addr, "cache:7000");
+        assert_eq!(client.auth, Some(vec!["app".to_string(), "pw".to_string()]));
+        assert_eq!(client.db, 2);
+        let client = RedisClient::from_uri("redis://:pw@cache/").unwrap();
+        assert_eq!(client.auth, Some(vec!["pw".to_string()]));
+        assert_eq!(client.db, 0);
+        assert!(RedisClient::from_uri("http://cache").is_err());
+        assert!(RedisClient::from_uri("redis://cache/db").is_err());
+        assert!(RedisClient::from_uri("redis://:pw@").is_err());
+    }
+
+    #[test]
+    fn test_encode_command() {
+        assert_eq!(
+            encode_command(&[b"GET", b"key"]),
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_reply() {
+        let mut replies: &[u8] = b"+OK\r\n:42\r\n$5\r\na\r\nbc\r\n$-1\r\n-ERR nope\r\n";
+        assert_eq!(
+            read_reply(&mut replies).await.unwrap(),
+            Reply::Simple("OK".to_string())
+        );
+        assert_eq!(read_reply(&mut replies).await.unwrap(), Reply::Integer(42));
+        assert_eq!(
+            read_reply(&mut replies).await.unwrap(),
+            Reply::Bulk(Some(b"a\r\nbc".to_vec()))
+        );
+        assert_eq!(read_reply(&mut replies).await.unwrap(), Reply::Bulk(None));
+        assert_eq!(
+            read_reply(&mut replies).await.unwrap(),
+            Reply::Error("ERR nope".to_string())
+        );
+        assert!(read_reply(&mut replies).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_commands_authenticate_first() {
+        let addr = fake_redis().await;
+        let client = RedisClient::from_uri(&format!("redis://:secret@{}", addr)).unwrap();
+        assert_eq!(
+            client.command(&[b"SET", b"k", b"v"]).await.unwrap(),
+            Reply::Simple("OK".to_string())
+        );
+        assert_eq!(
+            client.command(&[b"GET", b"k"]).await.unwrap(),
+            Reply::Bulk(Some(b"v".to_vec()))
+        );
+        let error = client.command(&[b"DEL", b"k"]).await.unwrap_err();
+        assert_eq!(error.to_string(), "Redis answered: ERR unknown command");
+
+        // The fake serves one connection at a time.
+        drop(client);
+        let refused = RedisClient::from_uri(&format!("redis://:wrong@{}", addr)).unwrap();
+        assert!(refused.command(&[b"GET", b"k"]).await.is_err());
+    }
+}
@@ -0,0 +1,205 @@
+/* Runtime reload of the server configuration.
+
+Some options can change while the server runs: rate limits, concurrency limits,
+quotas, the sizing of the request buffers and the configurations of the models
+(their batching windows among others). A `ConfigReload` reads the configuration
+again and applies the options that changed, when the process receives SIGHUP,
+when the configuration file is modified, or on
+`POST /{version}/admin/config/reload`. Options that changed but only apply at
+startup (listeners, TLS files, authentication) keep their running values and
+are reported as requiring a restart. A configuration that is not valid is
+refused as a whole, and the running one stays. The options in effect are shown
+by `GET /{version}/admin/config`.
+*/
+
+use crate::log_info;
+use crate::settings::Settings;
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+
+/// Options and models affected by a reload.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    /// Options whose new values are applied.
+    pub applied: Vec<String>,
+    /// Options that changed but keep their running values until a restart.
+    pub restart_required: Vec<String>,
+    /// Models whose new configuration is applied.
+    pub models: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart_required.is_empty() && self.models.is_empty()
+    }
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no change");
+        }
+        let mut parts = Vec::new();
+        if !self.applied.is_empty() {
+            parts.push(format!("applied {}", self.applied.join(", ")));
+        }
+        if !self.models.is_empty() {
+            parts.push(format!("reconfigured models {}", self.models.join(", ")));
+        }
+        if !self.restart_required.is_empty() {
+            parts.push(format!(
+                "restart required for {}",
+                self.restart_required.join(", ")
+            ));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+type ReloadFn = dyn Fn() -> Result<ReloadReport> + Send + Sync;
+type ViewFn = dyn Fn() -> Settings + Send + Sync;
+
+/// Reloads the configuration with a function reading and applying it.
+pub struct ConfigReload {
+    reload: Box<ReloadFn>,
+    /// Options in effect, as the last successful reload left them.
+    view: Option<Box<ViewFn>>,
+    /// Reloads from signals, the file watcher and the admin API run one at a time.
+    running: Mutex<()>,
+}
+
+impl fmt::Debug for ConfigReload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigReload").finish_non_exhaustive()
+    }
+}
+
+impl ConfigReload {
+    pub fn new(reload: impl Fn() -> Result<ReloadReport> + Send + Sync + 'static) -> Self {
+        Self {
+            reload: Box::new(reload),
+            view: None,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Shows the options in effect with `view`, which should leave out secrets.
+    pub fn with_view(mut self, view: impl Fn() -> Settings + Send + Sync + 'static) -> Self {
+        self.view = Some(Box::new(view));
+        self
+    }
+
+    /// Options in effect, if they can be shown.
+    pub fn settings(&self) -> Option<Settings> {
+        self.view.as_ref().map(|view| view())
+    }
+
+    /// Reads the configuration again and applies what changed.
+    pub fn reload(&self) -> Result<ReloadReport> {
+        let _running = self.running.lock().unwrap();
+        (self.reload)()
+    }
+
+    fn reload_and_log(&self, cause: &str) {
+        match self.reload() {
+            Ok(report) => log_info!("Reloaded the configuration on {}: {}", cause, report),
+            Err(e) => eprintln!(
+                "Keeping the running configuration, reload on {} failed: {:#}",
+                cause, e
+            ),
+        }
+    }
+
+    /// Reloads the configuration whenever the process receives SIGHUP. Must be called from
+    /// within a tokio runtime.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(self: &Arc<Self>) -> Result<()> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let reload = self.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                reload.reload_and_log("SIGHUP");
+            }
+        });
+        Ok(())
+    }
+
+    /// Reloads the configuration whenever the modification time of the file at `path`
+    /// changes, checking it every `interval`. Must be called from within a tokio runtime.
+    pub fn watch(self: &Arc<Self>, path: PathBuf, interval: Duration) -> JoinHandle<()> {
+        let reload = self.clone();
+        tokio::spawn(async move {
+            let modified = |path: &PathBuf| -> Option<SystemTime> {
+                std::fs::metadata(path).and_then(|m| m.modified()).ok()
+            };
+            let mut last = modified(&path);
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let current = modified(&path);
+                // A file being replaced may be missing for a moment.
+                if current.is_some() && current != last {
+                    last = current;
+                    reload.reload_and_log(&format!("change of {}", path.display()));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_file_changes_trigger_reloads() {
+        let path =
+            std::env::temp_dir().join(format!("galemind-reload-{}.yaml", std::process::id()));
+        std::fs::write(&path, "rest-port: 8080\n").unwrap();
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counted = reloads.clone();
+        let reload = Arc::new(ConfigReload::new(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Ok(ReloadReport {
+                applied: vec!["client-rate-limit".to_string()],
+                ..ReloadReport::default()
+            })
+        }));
+        let watcher = reload.watch(path.clone(), Duration::from_millis(10));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reloads.load(Ordering::SeqCst), 0);
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+
+        watcher.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_report_summary() {
+        assert_eq!(ReloadReport::default().to_string(), "no change");
+        let report = ReloadReport {
+            applied: vec!["quota".to_string(), "max-in-flight".to_string()],
+            restart_required: vec!["rest-port".to_string()],
+            models: vec!["ranker".to_string()],
+        };
+        assert_eq!(
+            report.to_string(),
+            "applied quota, max-in-flight; reconfigured models ranker; restart required for \
+             rest-port"
+        );
+    }
+}
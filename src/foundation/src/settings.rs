@@ -0,0 +1,198 @@
+/* Server settings from a configuration file and the environment.
+
+Every option of `galemind start` can be set in a YAML file, in the
+environment, or on the command line, the latter overriding the former:
+
+```bash
+galemind start --config galemind.yaml          # or GALEMIND_CONFIG=galemind.yaml
+GALEMIND_REST_PORT=9090 galemind start --config galemind.yaml --max-request-bytes 16MiB
+```
+
+Keys are the names of the command line options. Nested sections stand for
+options starting with their name, so the file can group them:
+
+```yaml
+models-dir: /srv/models
+rest:
+  host: 0.0.0.0
+  port: 8080       # --rest-port
+tls:
+  cert: /etc/galemind/server.crt
+  key: /etc/galemind/server.key
+api-keys:          # repeatable options take a list
+  - secret-1
+  - secret-2
+read-only: true    # flags take a boolean
+```
+
+Underscores in keys are read as dashes. In the environment, an option is
+`GALEMIND_` followed by its name in upper case with underscores, e.g.
+`GALEMIND_MAX_REQUEST_BYTES`; repeatable options take a comma separated list.
+*/
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Prefix of the environment variables setting options.
+pub const ENV_PREFIX: &str = "GALEMIND_";
+/// Environment variable naming the configuration file.
+pub const CONFIG_ENV: &str = "GALEMIND_CONFIG";
+
+/// Values of options by name, as given in one layer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Settings {
+    values: BTreeMap<String, Vec<String>>,
+}
+
+fn option_name(key: &str) -> String {
+    key.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(flag) => Ok(flag.to_string()),
+        _ => bail!("Setting '{}' must be a string, number or boolean", key),
+    }
+}
+
+impl Settings {
+    /// Settings of the YAML file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid configuration file {}", path.display()))
+    }
+
+    /// Settings of a YAML document.
+    pub fn parse(yaml: &str) -> Result<Self> {
+        let mut settings = Self::default();
+        match serde_yaml::from_str::<Value>(yaml)? {
+            Value::Null => {}
+            Value::Mapping(mapping) => settings.read_section("", &Value::Mapping(mapping))?,
+            _ => bail!("A configuration file must be a mapping of options"),
+        }
+        Ok(settings)
+    }
+
+    fn read_section(&mut self, prefix: &str, section: &Value) -> Result<()> {
+        let Value::Mapping(mapping) = section else {
+            unreachable!("sections are mappings");
+        };
+        for (key, value) in mapping {
+            let key = key
+                .as_str()
+                .ok_or_else(|| anyhow!("Setting names must be strings"))?;
+            let name = match prefix {
+                "" => option_name(key),
+                prefix => format!("{}-{}", prefix, option_name(key)),
+            };
+            let values = match value {
+                Value::Mapping(_) => {
+                    self.read_section(&name, value)?;
+                    continue;
+                }
+                Value::Sequence(items) => items
+                    .iter()
+                    .map(|item| scalar(&name, item))
+                    .collect::<Result<_>>()?,
+                Value::Null => continue,
+                value => vec![scalar(&name, value)?],
+            };
+            if self.values.insert(name.clone(), values).is_some() {
+                bail!("Setting '{}' is given twice", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings of the `GALEMIND_*` variables of `vars`, but the configuration file's. Their
+    /// values are left whole: see `split_list` for repeatable options.
+    pub fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let values = vars
+            .into_iter()
+            .filter(|(name, _)| name != CONFIG_ENV)
+            .filter_map(|(name, value)| {
+                let option = name.strip_prefix(ENV_PREFIX)?;
+                Some((option_name(option), vec![value]))
+            })
+            .collect();
+        Self { values }
+    }
+
+    /// The items of a comma separated list.
+    pub fn split_list(values: &[String]) -> Vec<String> {
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    /// Values of the option `name`. Options set once have one value.
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.values.get(name).map(Vec::as_slice)
+    }
+
+    /// Sets the values of an option, replacing those it had.
+    pub fn insert(&mut self, name: &str, values: Vec<String>) {
+        self.values.insert(option_name(name), values);
+    }
+
+    /// Names of the options set.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sections_name_options() {
+        let settings = Settings::parse(
+            "models_dir: /srv/models\nrest:\n  port: 8080\n  cors:\n    origin: [https://a.example, https://b.example]\nread-only: true\naudit-log:\n",
+        )
+        .unwrap();
+        assert_eq!(settings.get("models-dir").unwrap(), ["/srv/models"]);
+        assert_eq!(settings.get("rest-port").unwrap(), ["8080"]);
+        assert_eq!(
+            settings.get("rest-cors-origin").unwrap(),
+            ["https://a.example", "https://b.example"]
+        );
+        assert_eq!(settings.get("read-only").unwrap(), ["true"]);
+        assert_eq!(settings.get("audit-log"), None);
+
+        assert!(Settings::parse("rest-port: 1\nrest:\n  port: 2\n").is_err());
+        assert!(Settings::parse("- a\n").is_err());
+        assert!(Settings::parse("api-keys: [[nested]]\n").is_err());
+    }
+
+    #[test]
+    fn test_environment_names_options() {
+        let settings = Settings::from_env([
+            ("GALEMIND_REST_PORT".to_string(), "9090".to_string()),
+            ("GALEMIND_API_KEYS".to_string(), "a, b".to_string()),
+            (CONFIG_ENV.to_string(), "galemind.yaml".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ]);
+        assert_eq!(settings.get("rest-port").unwrap(), ["9090"]);
+        assert_eq!(settings.get("api-keys").unwrap(), ["a, b"]);
+        assert_eq!(
+            Settings::split_list(settings.get("api-keys").unwrap()),
+            ["a", "b"]
+        );
+        assert_eq!(
+            settings.names().collect::<Vec<_>>(),
+            ["api-keys", "rest-port"]
+        );
+    }
+}
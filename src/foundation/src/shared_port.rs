@@ -0,0 +1,439 @@
+/* REST and gRPC on a single port.
+
+With `--port`, both servers are reached through one listening socket, for
+deployments that can expose a single port. Every accepted connection is
+peeked at, without consuming anything, until its protocol is known, then
+handed to the server speaking it:
+
+- plain TCP: connections opening with the HTTP/2 preface (prior knowledge,
+  as gRPC clients do) go to the gRPC server, HTTP/1.x ones to the REST server;
+- TLS: the ALPN protocols offered in the ClientHello decide. gRPC clients
+  offer `h2` alone, while browsers and HTTP clients also offer `http/1.1`,
+  so only the former go to the gRPC server. Each server then runs the TLS
+  handshake itself.
+
+REST clients speaking HTTP/2 with prior knowledge, and gRPC-Web calls over
+HTTP/1.1, therefore reach the wrong server; they need the dedicated ports.
+A connection whose protocol is not known after `header_read_timeout` is
+closed.
+
+With `--port-routing request`, nothing is sniffed: every connection goes to
+the REST server, which hands gRPC and gRPC-Web calls to the gRPC services by
+their content type. This serves those clients too, and lets one connection
+carry calls of both APIs.
+*/
+
+use anyhow::anyhow;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, mpsc};
+
+/// Client preface of HTTP/2 connections with prior knowledge.
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// Bytes of a connection peeked at before it is handed to the REST server anyway.
+const MAX_SNIFF_BYTES: usize = 16 * 1024 + 5;
+/// Pause between two peeks at a connection that has not sent enough yet.
+const SNIFF_INTERVAL: Duration = Duration::from_millis(5);
+/// Connections sniffed and waiting for their server to accept them.
+const ACCEPT_BACKLOG: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Rest,
+    Grpc,
+}
+
+/// How a shared port tells REST and gRPC apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PortRouting {
+    /// Each connection, by its opening bytes.
+    #[default]
+    Connection,
+    /// Each request, by its content type, in the REST server.
+    Request,
+}
+
+impl FromStr for PortRouting {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "connection" => Ok(Self::Connection),
+            "request" => Ok(Self::Request),
+            other => Err(anyhow!(
+                "Unknown port routing '{}', expected connection or request",
+                other
+            )),
+        }
+    }
+}
+
+/// Protocol of a connection opening with `prefix`, or None while more bytes are needed.
+pub fn sniff(prefix: &[u8]) -> Option<Protocol> {
+    if prefix.first() == Some(&0x16) {
+        return match client_hello_alpn(prefix) {
+            Err(Incomplete) => None,
+            Ok(Some(protocols))
+                if protocols.contains(&&b"h2"[..]) && !protocols.contains(&&b"http/1.1"[..]) =>
+            {
+                Some(Protocol::Grpc)
+            }
+            Ok(_) => Some(Protocol::Rest),
+        };
+    }
+    if prefix.starts_with(H2_PREFACE) {
+        Some(Protocol::Grpc)
+    } else if H2_PREFACE.starts_with(prefix) {
+        None
+    } else {
+        Some(Protocol::Rest)
+    }
+}
+
+/// The TLS record `prefix` opens with is not complete yet.
+#[derive(Debug, PartialEq)]
+struct Incomplete;
+
+/// Reads `len` bytes off the front of `bytes`.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let (taken, rest) = bytes.split_at_checked(len)?;
+    *bytes = rest;
+    Some(taken)
+}
+
+fn take_len(bytes: &mut &[u8], width: usize) -> Option<usize> {
+    Some(
+        take(bytes, width)?
+            .iter()
+            .fold(0, |len, byte| len << 8 | *byte as usize),
+    )
+}
+
+/// ALPN protocols offered by the ClientHello in the TLS record opening `prefix`; None when
+/// it offers none or is not a ClientHello.
+fn client_hello_alpn(prefix: &[u8]) -> Result<Option<Vec<&[u8]>>, Incomplete> {
+    let mut header = prefix;
+    let record_len = take(&mut header, 3)
+        .and_then(|_| take_len(&mut header, 2))
+        .ok_or(Incomplete)?;
+    let mut record = take(&mut header, record_len).ok_or(Incomplete)?;
+
+    let alpn = (|| {
+        let hello_type = take_len(&mut record, 1)?;
+        let hello_len = take_len(&mut record, 3)?;
+        if hello_type != 1 {
+            return None;
+        }
+        let mut hello = take(&mut record, hello_len)?;
+        // Version and random, then the session id, cipher suites and compression methods.
+        take(&mut hello, 34)?;
+        for width in [1, 2, 1] {
+            let len = take_len(&mut hello, width)?;
+            take(&mut hello, len)?;
+        }
+        let extensions_len = take_len(&mut hello, 2)?;
+        let mut extensions = take(&mut hello, extensions_len)?;
+        while !extensions.is_empty() {
+            let kind = take_len(&mut extensions, 2)?;
+            let len = take_len(&mut extensions, 2)?;
+            let mut data = take(&mut extensions, len)?;
+            if kind != 0x10 {
+                continue;
+            }
+            let list_len = take_len(&mut data, 2)?;
+            let mut list = take(&mut data, list_len)?;
+            let mut protocols = Vec::new();
+            while !list.is_empty() {
+                let len = take_len(&mut list, 1)?;
+                protocols.push(take(&mut list, len)?);
+            }
+            return Some(protocols);
+        }
+        None
+    })();
+    Ok(alpn)
+}
+
+/// Source of the connections of one server: its own socket, or its share of a `SharedPort`.
+pub enum Listener {
+    Tcp(TcpListener),
+    Shared {
+        connections: mpsc::Receiver<(TcpStream, SocketAddr)>,
+        local_addr: SocketAddr,
+    },
+}
+
+impl Listener {
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        TcpListener::bind(addr).await.map(Listener::Tcp)
+    }
+
+    /// The next connection. Once the shared port stopped accepting, this waits forever.
+    pub async fn accept(&mut self) -> io::Result<(TcpStream, SocketAddr)> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().await,
+            Listener::Shared { connections, .. } => match connections.recv().await {
+                Some(connection) => Ok(connection),
+                None => std::future::pending().await,
+            },
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr(),
+            Listener::Shared { local_addr, .. } => Ok(*local_addr),
+        }
+    }
+}
+
+struct Shares {
+    rest: Option<mpsc::Receiver<(TcpStream, SocketAddr)>>,
+    grpc: Option<mpsc::Receiver<(TcpStream, SocketAddr)>>,
+    local_addr: SocketAddr,
+}
+
+/// A port shared by the REST and gRPC servers, bound when the first of them starts.
+pub struct SharedPort {
+    host: String,
+    port: u16,
+    sniff_timeout: Duration,
+    routing: PortRouting,
+    shares: Mutex<Option<Shares>>,
+}
+
+impl std::fmt::Debug for SharedPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedPort")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("routing", &self.routing)
+            .finish()
+    }
+}
+
+impl SharedPort {
+    /// Closes connections whose protocol is not known after `sniff_timeout`.
+    pub fn new(host: impl Into<String>, port: u16, sniff_timeout: Duration) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            sniff_timeout,
+            routing: PortRouting::default(),
+            shares: Mutex::new(None),
+        }
+    }
+
+    pub fn with_routing(mut self, routing: PortRouting) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn routing(&self) -> PortRouting {
+        self.routing
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The connections speaking `protocol`. Fails when the port cannot be bound, or was
+    /// already taken for `protocol`. Routed by request, every connection is REST's.
+    pub async fn listener(&self, protocol: Protocol) -> io::Result<Listener> {
+        let mut shares = self.shares.lock().await;
+        if self.routing == PortRouting::Request {
+            if protocol == Protocol::Grpc {
+                return Err(io::Error::other(
+                    "gRPC calls are routed by request on the REST listener",
+                ));
+            }
+            if shares.is_some() {
+                return Err(io::Error::other("Rest listener already taken"));
+            }
+            let listener = TcpListener::bind((self.host.as_str(), self.port)).await?;
+            *shares = Some(Shares {
+                rest: None,
+                grpc: None,
+                local_addr: listener.local_addr()?,
+            });
+            return Ok(Listener::Tcp(listener));
+        }
+        if shares.is_none() {
+            let listener = TcpListener::bind((self.host.as_str(), self.port)).await?;
+            let local_addr = listener.local_addr()?;
+            let (rest, rest_connections) = mpsc::channel(ACCEPT_BACKLOG);
+            let (grpc, grpc_connections) = mpsc::channel(ACCEPT_BACKLOG);
+            tokio::spawn(dispatch(listener, rest, grpc, self.sniff_timeout));
+            *shares = Some(Shares {
+                rest: Some(rest_connections),
+                grpc: Some(grpc_connections),
+                local_addr,
+            });
+        }
+        let shares = shares.as_mut().expect("bound above");
+        let connections = match protocol {
+            Protocol::Rest => shares.rest.take(),
+            Protocol::Grpc => shares.grpc.take(),
+        };
+        connections
+            .map(|connections| Listener::Shared {
+                connections,
+                local_addr: shares.local_addr,
+            })
+            .ok_or_else(|| io::Error::other(format!("{:?} listener already taken", protocol)))
+    }
+}
+
+/// Hands every connection accepted by `listener` to the server of its protocol, until both
+/// servers stopped accepting.
+async fn dispatch(
+    listener: TcpListener,
+    rest: mpsc::Sender<(TcpStream, SocketAddr)>,
+    grpc: mpsc::Sender<(TcpStream, SocketAddr)>,
+    sniff_timeout: Duration,
+) {
+    while !(rest.is_closed() && grpc.is_closed()) {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Typically file descriptor exhaustion: back off instead of spinning.
+                eprintln!("Failed to accept connection on the shared port: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let (rest, grpc) = (rest.clone(), grpc.clone());
+        // Sniffed in their own tasks so that a slow client does not hold up the others.
+        tokio::spawn(async move {
+            let Ok(Some(protocol)) = tokio::time::timeout(sniff_timeout, peek(&stream)).await
+            else {
+                return;
+            };
+            let server = match protocol {
+                Protocol::Rest => rest,
+                Protocol::Grpc => grpc,
+            };
+            let _ = server.send((stream, peer)).await;
+        });
+    }
+}
+
+/// Peeks at `stream` until its protocol is known; None if it closes before.
+async fn peek(stream: &TcpStream) -> Option<Protocol> {
+    let mut buf = vec![0; MAX_SNIFF_BYTES];
+    loop {
+        let len = stream.peek(&mut buf).await.ok()?;
+        if len == 0 {
+            return None;
+        }
+        if let Some(protocol) = sniff(&buf[..len]) {
+            return Some(protocol);
+        }
+        if len == buf.len() {
+            return Some(Protocol::Rest);
+        }
+        tokio::time::sleep(SNIFF_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A TLS record holding a ClientHello offering `alpn`.
+    fn client_hello(alpn: &[&str]) -> Vec<u8> {
+        let mut list = Vec::new();
+        for protocol in alpn {
+            list.push(protocol.len() as u8);
+            list.extend_from_slice(protocol.as_bytes());
+        }
+        let mut extension = vec![0x00, 0x10];
+        extension.extend_from_slice(&((list.len() + 2) as u16).to_be_bytes());
+        extension.extend_from_slice(&(list.len() as u16).to_be_bytes());
+        extension.extend_from_slice(&list);
+        // Server name extension before ALPN, with an empty payload.
+        let mut extensions = vec![0x00, 0x00, 0x00, 0x00];
+        extensions.extend_from_slice(&extension);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![1];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn test_connections_are_sniffed_by_preface_and_alpn() {
+        assert_eq!(sniff(b"GET /livez HTTP/1.1\r\n"), Some(Protocol::Rest));
+        assert_eq!(sniff(H2_PREFACE), Some(Protocol::Grpc));
+        assert_eq!(sniff(&H2_PREFACE[..4]), None);
+        assert_eq!(sniff(b"P"), None);
+        assert_eq!(sniff(b"POST /v2"), Some(Protocol::Rest));
+
+        let grpc = client_hello(&["h2"]);
+        assert_eq!(sniff(&grpc), Some(Protocol::Grpc));
+        assert_eq!(sniff(&grpc[..grpc.len() - 1]), None);
+        assert_eq!(
+            sniff(&client_hello(&["h2", "http/1.1"])),
+            Some(Protocol::Rest)
+        );
+        assert_eq!(sniff(&client_hello(&[])), Some(Protocol::Rest));
+    }
+
+    #[tokio::test]
+    async fn test_shared_port_hands_connections_to_their_server() {
+        let port = SharedPort::new("127.0.0.1", 0, Duration::from_secs(1));
+        let mut rest = port.listener(Protocol::Rest).await.unwrap();
+        let mut grpc = port.listener(Protocol::Grpc).await.unwrap();
+        assert!(port.listener(Protocol::Grpc).await.is_err());
+        let addr = rest.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(H2_PREFACE).await.unwrap();
+        let (mut stream, _) = grpc.accept().await.unwrap();
+        // Nothing was consumed while sniffing.
+        let mut preface = vec![0; H2_PREFACE.len()];
+        stream.read_exact(&mut preface).await.unwrap();
+        assert_eq!(preface, H2_PREFACE);
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(rest.accept().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_port_routed_by_request_is_rest_alone() {
+        let port = SharedPort::new("127.0.0.1", 0, Duration::from_secs(1))
+            .with_routing(PortRouting::Request);
+        assert!(port.listener(Protocol::Grpc).await.is_err());
+        let mut rest = port.listener(Protocol::Rest).await.unwrap();
+        assert!(port.listener(Protocol::Rest).await.is_err());
+
+        let mut client = TcpStream::connect(rest.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(H2_PREFACE).await.unwrap();
+        assert!(rest.accept().await.is_ok());
+        assert_eq!(
+            "request".parse::<PortRouting>().unwrap(),
+            PortRouting::Request
+        );
+        assert!("ports".parse::<PortRouting>().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+/* Graceful shutdown.
+
+On SIGTERM or SIGINT the server stops accepting connections and drains: the
+requests in flight on open connections, and those waiting in the model
+buffers, get until the drain deadline to finish. Runtimes are then unloaded.
+
+`Shutdown` triggers the drain with its deadline; the servers wait on the
+`ShutdownSignal`s it hands out. A server stops accepting connections when the
+signal fires, asks its open connections to close once their requests are
+answered, and closes those still open at the deadline.
+*/
+
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Triggers the shutdown of the servers holding its signals.
+#[derive(Debug)]
+pub struct Shutdown {
+    deadline: watch::Sender<Option<Instant>>,
+}
+
+/// Fires when the server should stop accepting connections, with the deadline of the drain.
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal {
+    deadline: watch::Receiver<Option<Instant>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            deadline: watch::channel(None).0,
+        }
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            deadline: self.deadline.subscribe(),
+        }
+    }
+
+    /// Fires the signals, giving requests `drain_timeout` to finish. Returns the deadline.
+    pub fn trigger(&self, drain_timeout: Duration) -> Instant {
+        let deadline = Instant::now() + drain_timeout;
+        self.deadline.send_if_modified(|current| {
+            let first = current.is_none();
+            current.get_or_insert(deadline);
+            first
+        });
+        self.deadline.borrow().unwrap_or(deadline)
+    }
+}
+
+impl ShutdownSignal {
+    /// A signal that never fires, for servers started without one.
+    pub fn never() -> Self {
+        Shutdown::new().signal()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.deadline.borrow()
+    }
+
+    /// Waits for the shutdown and returns the drain deadline. Never returns if the
+    /// `Shutdown` is dropped without being triggered.
+    pub async fn triggered(mut self) -> Instant {
+        let deadline = match self.deadline.wait_for(Option::is_some).await {
+            Ok(deadline) => *deadline,
+            Err(_) => None,
+        };
+        match deadline {
+            Some(deadline) => deadline,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Waits until the drain deadline has passed.
+    pub async fn deadline_passed(self) {
+        let deadline = self.triggered().await;
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+/// Waits for SIGTERM or SIGINT (Ctrl-C) and returns its name.
+#[cfg(unix)]
+pub async fn termination() -> io::Result<&'static str> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    })
+}
+
+/// Waits for Ctrl-C.
+#[cfg(not(unix))]
+pub async fn termination() -> io::Result<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("Ctrl-C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signals_fire_once_with_the_first_deadline() {
+        let shutdown = Shutdown::new();
+        let signal = shutdown.signal();
+        assert_eq!(signal.deadline(), None);
+
+        let waiter = tokio::spawn(signal.clone().triggered());
+        let deadline = shutdown.trigger(Duration::from_secs(30));
+        assert_eq!(waiter.await.unwrap(), deadline);
+        // Triggering again keeps the first deadline.
+        assert_eq!(shutdown.trigger(Duration::ZERO), deadline);
+        assert_eq!(signal.deadline(), Some(deadline));
+
+        let never = ShutdownSignal::never();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), never.triggered())
+                .await
+                .is_err()
+        );
+    }
+}
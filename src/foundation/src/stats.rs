@@ -0,0 +1,365 @@
+/* Statistics registry.
+
+Every inference is counted once per place it passes through: the route that
+received it (`rest.infer`, `grpc.ModelInfer`, ...) and the scheduler that ran
+it. Each (model, version, route) series holds request and error counters and a
+latency histogram. Counters are sharded over cache lines and histograms over
+per-shard HDR histograms, so the threads of both servers rarely contend on the
+same memory; a snapshot adds the shards up. The Prometheus exposition, the
+admin API and the gRPC `ModelStatistics` RPC all read the same registry.
+Series of a version are dropped when the version is unregistered. The registry
+also feeds the scheduler's requests to the per-model history of `timeseries`.
+*/
+
+use dashmap::DashMap;
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::timeseries::TimeSeriesRegistry;
+
+/// Route of the requests run by the model scheduler, whichever server received them.
+pub const SCHEDULER_ROUTE: &str = "scheduler";
+
+const SHARDS: usize = 16;
+/// Latencies are recorded in microseconds, from 1µs up to this bound (saturating).
+const MAX_LATENCY_US: u64 = 3_600_000_000;
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Keeps each shard on its own cache line.
+#[repr(align(64))]
+#[derive(Debug, Default)]
+struct Padded<T>(T);
+
+/// Shard of the current thread; threads are spread over the shards as they first record.
+fn shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+    SHARD.with(|shard| *shard)
+}
+
+#[derive(Debug)]
+pub struct ShardedCounter {
+    shards: [Padded<AtomicU64>; SHARDS],
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Padded::default()),
+        }
+    }
+}
+
+impl ShardedCounter {
+    pub fn add(&self, n: u64) {
+        self.shards[shard()].0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn value(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.0.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).expect("valid histogram bounds")
+}
+
+/// Latency histogram with one HDR histogram per shard.
+pub struct ShardedHistogram {
+    shards: [Padded<Mutex<Histogram<u64>>>; SHARDS],
+}
+
+impl Default for ShardedHistogram {
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Padded(Mutex::new(new_histogram()))),
+        }
+    }
+}
+
+impl ShardedHistogram {
+    pub fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.shards[shard()]
+            .0
+            .lock()
+            .unwrap()
+            .saturating_record(micros.max(1));
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let mut merged = new_histogram();
+        for shard in &self.shards {
+            // Both histograms share their bounds, so adding cannot fail.
+            let _ = merged.add(&*shard.0.lock().unwrap());
+        }
+        LatencySummary::of(&merged)
+    }
+}
+
+/// Latency distribution of a series, in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    fn of(histogram: &Histogram<u64>) -> Self {
+        if histogram.is_empty() {
+            return Self::default();
+        }
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        Self {
+            count: histogram.len(),
+            mean_ms: histogram.mean() / 1000.0,
+            p50_ms: ms(histogram.value_at_quantile(QUANTILES[0])),
+            p90_ms: ms(histogram.value_at_quantile(QUANTILES[1])),
+            p99_ms: ms(histogram.value_at_quantile(QUANTILES[2])),
+            max_ms: ms(histogram.max()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct SeriesKey {
+    model: String,
+    version: String,
+    route: String,
+}
+
+#[derive(Default)]
+struct Series {
+    requests: ShardedCounter,
+    errors: ShardedCounter,
+    latency: ShardedHistogram,
+    latency_sum_us: ShardedCounter,
+}
+
+/// Statistics of one (model, version, route) series.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteStats {
+    pub model: String,
+    pub version: String,
+    pub route: String,
+    pub requests: u64,
+    pub errors: u64,
+    /// Sum of the latencies of the requests, in seconds.
+    pub latency_sum_s: f64,
+    pub latency: LatencySummary,
+}
+
+/// The registry of every series, shared by both servers and the model scheduler.
+#[derive(Default)]
+pub struct StatsRegistry {
+    series: DashMap<SeriesKey, Series>,
+    timeseries: TimeSeriesRegistry,
+}
+
+impl StatsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Latency and throughput history of every model, from the requests of the scheduler.
+    pub fn timeseries(&self) -> &TimeSeriesRegistry {
+        &self.timeseries
+    }
+
+    /// Counts a request of `model` `version` on `route` that took `latency`.
+    pub fn record(&self, model: &str, version: &str, route: &str, latency: Duration, ok: bool) {
+        if route == SCHEDULER_ROUTE {
+            self.timeseries.record(model, latency, ok);
+        }
+        let key = SeriesKey {
+            model: model.to_string(),
+            version: version.to_string(),
+            route: route.to_string(),
+        };
+        // Existing series only take a shared lock of their map shard.
+        let series = match self.series.get(&key) {
+            Some(series) => series,
+            None => self.series.entry(key).or_default().downgrade(),
+        };
+        series.requests.add(1);
+        if !ok {
+            series.errors.add(1);
+        }
+        series.latency.record(latency);
+        series
+            .latency_sum_us
+            .add(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+    }
+
+    /// Drops the series of a retired version.
+    pub fn remove_version(&self, model: &str, version: &str) {
+        self.series
+            .retain(|key, _| key.model != model || key.version != version);
+    }
+
+    /// Series matching `model` and `version` (every one when None), sorted by model,
+    /// version and route.
+    pub fn snapshot(&self, model: Option<&str>, version: Option<&str>) -> Vec<RouteStats> {
+        let mut stats: Vec<RouteStats> = self
+            .series
+            .iter()
+            .filter(|entry| {
+                model.is_none_or(|model| entry.key().model == model)
+                    && version.is_none_or(|version| entry.key().version == version)
+            })
+            .map(|entry| RouteStats {
+                model: entry.key().model.clone(),
+                version: entry.key().version.clone(),
+                route: entry.key().route.clone(),
+                requests: entry.requests.value(),
+                errors: entry.errors.value(),
+                latency_sum_s: entry.latency_sum_us.value() as f64 / 1_000_000.0,
+                latency: entry.latency.summary(),
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            (&a.model, &a.version, &a.route).cmp(&(&b.model, &b.version, &b.route))
+        });
+        stats
+    }
+
+    /// Every series in the Prometheus text exposition format.
+    pub fn render_metrics(&self) -> String {
+        let stats = self.snapshot(None, None);
+        let labels = |stats: &RouteStats| {
+            format!(
+                "model=\"{}\",version=\"{}\",route=\"{}\"",
+                escape_label(&stats.model),
+                escape_label(&stats.version),
+                escape_label(&stats.route)
+            )
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP galemind_requests_total Inference requests per model version and route.\n\
+             # TYPE galemind_requests_total counter"
+        );
+        for stats in &stats {
+            let _ = writeln!(
+                out,
+                "galemind_requests_total{{{}}} {}",
+                labels(stats),
+                stats.requests
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP galemind_request_errors_total Failed inference requests per model version and route.\n\
+             # TYPE galemind_request_errors_total counter"
+        );
+        for stats in &stats {
+            let _ = writeln!(
+                out,
+                "galemind_request_errors_total{{{}}} {}",
+                labels(stats),
+                stats.errors
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP galemind_request_duration_seconds Latency of inference requests.\n\
+             # TYPE galemind_request_duration_seconds summary"
+        );
+        for stats in &stats {
+            let labels = labels(stats);
+            let latency = &stats.latency;
+            for (quantile, ms) in
+                QUANTILES
+                    .iter()
+                    .zip([latency.p50_ms, latency.p90_ms, latency.p99_ms])
+            {
+                let _ = writeln!(
+                    out,
+                    "galemind_request_duration_seconds{{{},quantile=\"{}\"}} {}",
+                    labels,
+                    quantile,
+                    ms / 1000.0
+                );
+            }
+            let _ = writeln!(
+                out,
+                "galemind_request_duration_seconds_sum{{{}}} {}\n\
+                 galemind_request_duration_seconds_count{{{}}} {}",
+                labels, stats.latency_sum_s, labels, latency.count
+            );
+        }
+        out
+    }
+}
+
+/// Escapes a Prometheus label value.
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_series_add_up_across_threads() {
+        let registry = Arc::new(StatsRegistry::new());
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    for i in 0..250u64 {
+                        registry.record(
+                            "m",
+                            "1",
+                            "rest.infer",
+                            Duration::from_millis(1 + i % 10),
+                            !(thread == 0 && i < 10),
+                        );
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        registry.record("m", "2", SCHEDULER_ROUTE, Duration::from_millis(5), true);
+
+        let stats = registry.snapshot(Some("m"), Some("1"));
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].requests, stats[0].errors), (1000, 10));
+        assert_eq!(stats[0].latency.count, 1000);
+        assert!((stats[0].latency.max_ms - 10.0).abs() < 0.1);
+        assert!((stats[0].latency.p50_ms - 5.0).abs() < 0.1);
+        assert_eq!(registry.snapshot(Some("m"), None).len(), 2);
+
+        let metrics = registry.render_metrics();
+        assert!(metrics.contains(
+            "galemind_requests_total{model=\"m\",version=\"1\",route=\"rest.infer\"} 1000\n"
+        ));
+        assert!(metrics.contains(
+            "galemind_request_duration_seconds_count{model=\"m\",version=\"2\",route=\"scheduler\"} 1\n"
+        ));
+
+        registry.remove_version("m", "1");
+        assert_eq!(registry.snapshot(None, None)[0].version, "2");
+    }
+}
@@ -0,0 +1,497 @@
+/* Per-tenant error budgets.
+
+The tenant of a request is the account it authenticated as (see
+`auth::Authenticated`). Behind a proxy that authenticates the callers itself,
+`--trust-tenant-header` takes it from the `x-galemind-tenant` header (or gRPC
+metadata entry) the proxy sets instead; the header is ignored otherwise, so a
+tenant cannot leave its budget by changing or dropping it. Requests without a
+tenant are counted under `DEFAULT_TENANT`. The outcome of every request a tenant gets run by a model
+runtime is counted over a sliding `window`: runtime errors and panics count
+against the tenant's budget, anything else (success, deadlines, full buffers,
+invalid requests refused before running) does not. Once a tenant has had at
+least `min_requests` requests run in the window:
+
+- an error rate of `throttle_at` or more throttles the tenant to
+  `throttled_rate` requests per second, until the rate falls below
+  `restore_at`;
+- an error rate of `quarantine_at` or more quarantines the tenant: every
+  request is refused until an operator releases it.
+
+Every change of state is logged and, when a webhook is configured, posted to it
+as JSON.
+*/
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::auth::Authenticated;
+
+/// Header (or gRPC metadata entry) naming the tenant a request is sent for. Once a request
+/// is authenticated, the servers keep it only when it is trusted, see `request_tenant`.
+pub const TENANT_HEADER: &str = "x-galemind-tenant";
+/// Tenant the requests without one are counted under.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// The tenant of a request: its tenant header when a trusted proxy sets it, else the
+/// account it authenticated as. `None` for anonymous requests, counted as `DEFAULT_TENANT`.
+pub fn request_tenant(
+    header: Option<&str>,
+    account: Option<&Authenticated>,
+    trust_header: bool,
+) -> Option<String> {
+    header
+        .filter(|tenant| trust_header && !tenant.is_empty())
+        .map(str::to_string)
+        .or_else(|| account.map(|Authenticated(account)| account.clone()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorBudgetPolicy {
+    /// Span of the recent requests the error rate is computed over.
+    pub window: Duration,
+    /// Requests in the window before the error rate is acted upon.
+    pub min_requests: u64,
+    /// Error rate (0.0 - 1.0) at which a tenant is throttled.
+    pub throttle_at: f64,
+    /// Error rate below which a throttled tenant is restored.
+    pub restore_at: f64,
+    /// Error rate at which a tenant is quarantined.
+    pub quarantine_at: f64,
+    /// Requests per second admitted from a throttled tenant.
+    pub throttled_rate: u32,
+}
+
+impl Default for ErrorBudgetPolicy {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            min_requests: 20,
+            throttle_at: 0.2,
+            restore_at: 0.1,
+            quarantine_at: 0.5,
+            throttled_rate: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TenantState {
+    #[default]
+    Healthy,
+    Throttled,
+    Quarantined,
+}
+
+impl fmt::Display for TenantState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Healthy => write!(f, "healthy"),
+            Self::Throttled => write!(f, "throttled"),
+            Self::Quarantined => write!(f, "quarantined"),
+        }
+    }
+}
+
+/// Why a request of a tenant was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    Throttled(String),
+    Quarantined(String),
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Throttled(tenant) => write!(
+                f,
+                "Tenant '{}' is throttled after repeated backend failures",
+                tenant
+            ),
+            Self::Quarantined(tenant) => write!(
+                f,
+                "Tenant '{}' is quarantined after repeated backend failures",
+                tenant
+            ),
+        }
+    }
+}
+
+/// Error budget of a tenant, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantStats {
+    pub tenant: String,
+    pub state: TenantState,
+    /// Requests run in the window.
+    pub requests: u64,
+    /// Runtime errors and panics in the window.
+    pub errors: u64,
+    pub error_rate: f64,
+}
+
+/// A tenant changing state, as posted to the webhook.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantTransition {
+    pub tenant: String,
+    pub from: TenantState,
+    pub to: TenantState,
+    pub requests: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+}
+
+#[derive(Debug, Default)]
+struct TenantRecord {
+    /// Requests and errors per second of the window, oldest first.
+    buckets: VecDeque<(u64, u64, u64)>,
+    state: TenantState,
+    /// Second and count of the requests admitted while throttled.
+    admitted: (u64, u32),
+}
+
+impl TenantRecord {
+    fn totals(&self) -> (u64, u64) {
+        self.buckets
+            .iter()
+            .fold((0, 0), |(requests, errors), (_, r, e)| {
+                (requests + r, errors + e)
+            })
+    }
+}
+
+fn error_rate(requests: u64, errors: u64) -> f64 {
+    if requests == 0 {
+        0.0
+    } else {
+        errors as f64 / requests as f64
+    }
+}
+
+/// Tracks the error budget of every tenant, see the module documentation.
+pub struct ErrorBudget {
+    policy: ErrorBudgetPolicy,
+    tenants: DashMap<String, TenantRecord>,
+    /// Origin of the per-second buckets.
+    epoch: Instant,
+    webhook: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Default for ErrorBudget {
+    fn default() -> Self {
+        Self::new(ErrorBudgetPolicy::default())
+    }
+}
+
+impl ErrorBudget {
+    pub fn new(policy: ErrorBudgetPolicy) -> Self {
+        Self {
+            policy,
+            tenants: DashMap::new(),
+            epoch: Instant::now(),
+            webhook: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Posts every change of a tenant's state to `webhook`.
+    pub fn with_webhook(mut self, webhook: Option<String>) -> Self {
+        self.webhook = webhook;
+        self
+    }
+
+    pub fn policy(&self) -> &ErrorBudgetPolicy {
+        &self.policy
+    }
+
+    /// Whether a request of `tenant` may be run now.
+    pub fn admit(&self, tenant: Option<&str>) -> Result<(), Refusal> {
+        self.admit_at(tenant, Instant::now())
+    }
+
+    pub fn admit_at(&self, tenant: Option<&str>, now: Instant) -> Result<(), Refusal> {
+        let tenant = tenant.unwrap_or(DEFAULT_TENANT);
+        let Some(mut record) = self.tenants.get_mut(tenant) else {
+            return Ok(());
+        };
+        match record.state {
+            TenantState::Healthy => Ok(()),
+            TenantState::Quarantined => Err(Refusal::Quarantined(tenant.to_string())),
+            TenantState::Throttled => {
+                let second = self.second(now);
+                if record.admitted.0 != second {
+                    record.admitted = (second, 0);
+                }
+                if record.admitted.1 >= self.policy.throttled_rate {
+                    return Err(Refusal::Throttled(tenant.to_string()));
+                }
+                record.admitted.1 += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Counts a request of `tenant` that a runtime ran, `failed` if it errored or panicked.
+    pub fn record(&self, tenant: Option<&str>, failed: bool) {
+        if let Some(transition) = self.record_at(tenant, failed, Instant::now()) {
+            self.notify(transition);
+        }
+    }
+
+    /// Counts an outcome at `now`, returning the change of state it caused, if any.
+    pub fn record_at(
+        &self,
+        tenant: Option<&str>,
+        failed: bool,
+        now: Instant,
+    ) -> Option<TenantTransition> {
+        let tenant = tenant.unwrap_or(DEFAULT_TENANT);
+        if !self.tenants.contains_key(tenant) {
+            self.purge_idle(now);
+        }
+        let second = self.second(now);
+        let oldest = second.saturating_sub(self.policy.window.as_secs().max(1) - 1);
+
+        let mut record = self.tenants.entry(tenant.to_string()).or_default();
+        match record.buckets.back_mut() {
+            Some((bucket, requests, errors)) if *bucket == second => {
+                *requests += 1;
+                *errors += u64::from(failed);
+            }
+            _ => record.buckets.push_back((second, 1, u64::from(failed))),
+        }
+        while record
+            .buckets
+            .front()
+            .is_some_and(|(bucket, _, _)| *bucket < oldest)
+        {
+            record.buckets.pop_front();
+        }
+
+        let (requests, errors) = record.totals();
+        let from = record.state;
+        if from == TenantState::Quarantined || requests < self.policy.min_requests {
+            return None;
+        }
+        let rate = error_rate(requests, errors);
+        let to = if rate >= self.policy.quarantine_at {
+            TenantState::Quarantined
+        } else if rate >= self.policy.throttle_at
+            || (from == TenantState::Throttled && rate >= self.policy.restore_at)
+        {
+            TenantState::Throttled
+        } else {
+            TenantState::Healthy
+        };
+        if to == from {
+            return None;
+        }
+        record.state = to;
+        Some(TenantTransition {
+            tenant: tenant.to_string(),
+            from,
+            to,
+            requests,
+            errors,
+            error_rate: rate,
+        })
+    }
+
+    /// Restores a throttled or quarantined tenant with a fresh budget. Returns false for
+    /// tenants that are healthy or unknown.
+    pub fn release(&self, tenant: &str) -> bool {
+        let transition = {
+            let Some(mut record) = self.tenants.get_mut(tenant) else {
+                return false;
+            };
+            if record.state == TenantState::Healthy {
+                return false;
+            }
+            let (requests, errors) = record.totals();
+            let from = record.state;
+            *record = TenantRecord::default();
+            TenantTransition {
+                tenant: tenant.to_string(),
+                from,
+                to: TenantState::Healthy,
+                requests,
+                errors,
+                error_rate: error_rate(requests, errors),
+            }
+        };
+        self.notify(transition);
+        true
+    }
+
+    /// Error budget of every tracked tenant, sorted by tenant.
+    pub fn stats(&self) -> Vec<TenantStats> {
+        let mut stats: Vec<TenantStats> = self
+            .tenants
+            .iter()
+            .map(|entry| {
+                let (requests, errors) = entry.totals();
+                TenantStats {
+                    tenant: entry.key().clone(),
+                    state: entry.state,
+                    requests,
+                    errors,
+                    error_rate: error_rate(requests, errors),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        stats
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_secs()
+    }
+
+    /// Forgets healthy tenants without requests in the window.
+    fn purge_idle(&self, now: Instant) {
+        let oldest = self
+            .second(now)
+            .saturating_sub(self.policy.window.as_secs().max(1) - 1);
+        self.tenants.retain(|_, record| {
+            record.state != TenantState::Healthy
+                || record
+                    .buckets
+                    .back()
+                    .is_some_and(|(bucket, _, _)| *bucket >= oldest)
+        });
+    }
+
+    fn notify(&self, transition: TenantTransition) {
+        eprintln!(
+            "Tenant '{}' {} -> {} (error rate {:.2} over {} requests)",
+            transition.tenant,
+            transition.from,
+            transition.to,
+            transition.error_rate,
+            transition.requests
+        );
+        let Some(webhook) = self.webhook.clone() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let http = self.http.clone();
+        runtime.spawn(async move {
+            let result = http
+                .post(&webhook)
+                .json(&transition)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                eprintln!("Failed to notify tenant webhook: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget() -> ErrorBudget {
+        ErrorBudget::new(ErrorBudgetPolicy {
+            min_requests: 10,
+            throttled_rate: 2,
+            ..ErrorBudgetPolicy::default()
+        })
+    }
+
+    #[test]
+    fn test_failing_tenants_are_throttled_then_quarantined() {
+        let budget = budget();
+        let now = Instant::now();
+        let tenant = Some("acme");
+
+        for _ in 0..7 {
+            assert_eq!(budget.record_at(tenant, false, now), None);
+        }
+        for _ in 0..2 {
+            assert_eq!(budget.record_at(tenant, true, now), None);
+        }
+        // The tenth request brings the error rate to 0.3.
+        let throttled = budget.record_at(tenant, true, now).unwrap();
+        assert_eq!(throttled.to, TenantState::Throttled);
+        assert!(budget.admit_at(tenant, now).is_ok());
+        assert!(budget.admit_at(tenant, now).is_ok());
+        assert!(matches!(
+            budget.admit_at(tenant, now),
+            Err(Refusal::Throttled(_))
+        ));
+        assert!(
+            budget
+                .admit_at(tenant, now + Duration::from_secs(1))
+                .is_ok()
+        );
+        assert!(budget.admit_at(Some("other"), now).is_ok());
+        assert!(budget.admit_at(None, now).is_ok());
+        assert!(budget.record_at(None, false, now).is_none());
+        assert_eq!(
+            budget
+                .stats()
+                .into_iter()
+                .map(|stats| stats.tenant)
+                .collect::<Vec<_>>(),
+            vec!["acme", DEFAULT_TENANT]
+        );
+
+        let quarantined = (0..10)
+            .find_map(|_| budget.record_at(tenant, true, now))
+            .unwrap();
+        assert_eq!(quarantined.from, TenantState::Throttled);
+        assert_eq!(quarantined.to, TenantState::Quarantined);
+        assert!(matches!(
+            budget.admit_at(tenant, now + Duration::from_secs(600)),
+            Err(Refusal::Quarantined(_))
+        ));
+
+        assert!(budget.release("acme"));
+        assert!(!budget.release("acme"));
+        assert!(budget.admit_at(tenant, now).is_ok());
+        assert_eq!(budget.stats()[0].requests, 0);
+    }
+
+    #[test]
+    fn test_the_tenant_header_is_only_trusted_when_configured() {
+        let account = Authenticated("ci".to_string());
+        assert_eq!(
+            request_tenant(Some("acme"), Some(&account), false),
+            Some("ci".to_string())
+        );
+        assert_eq!(
+            request_tenant(Some("acme"), Some(&account), true),
+            Some("acme".to_string())
+        );
+        assert_eq!(request_tenant(Some("acme"), None, false), None);
+        assert_eq!(
+            request_tenant(Some(""), Some(&account), true),
+            Some("ci".to_string())
+        );
+    }
+
+    #[test]
+    fn test_throttled_tenants_recover_as_errors_leave_the_window() {
+        let budget = budget();
+        let start = Instant::now();
+        let tenant = Some("acme");
+        for _ in 0..3 {
+            budget.record_at(tenant, true, start);
+        }
+        let throttled = (0..7).find_map(|_| budget.record_at(tenant, false, start));
+        assert_eq!(throttled.unwrap().to, TenantState::Throttled);
+
+        let later = start + budget.policy().window;
+        let restored = (0..10).find_map(|_| budget.record_at(tenant, false, later));
+        assert_eq!(restored.unwrap().to, TenantState::Healthy);
+        assert_eq!(budget.stats()[0].errors, 0);
+    }
+}
@@ -0,0 +1,143 @@
+/* Per-request debug timelines.
+
+A client sending the `x-galemind-debug: timeline` header (or gRPC metadata
+entry) opts a single request into collecting a timeline: every stage the
+request passes through (parsing, schema negotiation, queueing, batch
+membership, runtime execution, serialization) records an event with its
+offset from the arrival of the request and, for spans, its duration. The
+events are returned in a `debug` section of the response.
+
+The timeline travels with the `InferenceRequest`, so runtimes can record
+their own phases too. Requests without the header carry no timeline and pay
+nothing for it.
+*/
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub const DEBUG_HEADER: &str = "x-galemind-debug";
+
+/// Whether the value of the debug header asks for a timeline.
+pub fn timeline_requested(value: Option<&str>) -> bool {
+    value.is_some_and(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|option| option.eq_ignore_ascii_case("timeline"))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelineEvent {
+    pub name: String,
+    /// Microseconds since the request arrived.
+    pub start_us: u64,
+    /// Duration in microseconds, for events spanning a stage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_us: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Timeline {
+    arrived: Instant,
+    events: Mutex<Vec<TimelineEvent>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::starting_at(Instant::now())
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn starting_at(arrived: Instant) -> Self {
+        Self {
+            arrived,
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a point in time, such as a request entering a queue.
+    pub fn mark(&self, name: &str, detail: Option<String>) {
+        self.push(name, Instant::now(), None, detail);
+    }
+
+    /// Records a stage that started at `started` and ends now.
+    pub fn span(&self, name: &str, started: Instant, detail: Option<String>) {
+        let now = Instant::now();
+        self.push(
+            name,
+            started,
+            Some(now.saturating_duration_since(started)),
+            detail,
+        );
+    }
+
+    /// Events recorded so far, ordered by start time.
+    pub fn events(&self) -> Vec<TimelineEvent> {
+        let mut events = self.events.lock().unwrap().clone();
+        events.sort_by_key(|event| event.start_us);
+        events
+    }
+
+    /// Microseconds since the request arrived.
+    pub fn elapsed_us(&self) -> u64 {
+        self.arrived.elapsed().as_micros() as u64
+    }
+
+    fn push(
+        &self,
+        name: &str,
+        start: Instant,
+        duration: Option<std::time::Duration>,
+        detail: Option<String>,
+    ) {
+        let event = TimelineEvent {
+            name: name.to_string(),
+            start_us: start.saturating_duration_since(self.arrived).as_micros() as u64,
+            duration_us: duration.map(|duration| duration.as_micros() as u64),
+            detail,
+        };
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_events_are_ordered_by_start() {
+        let arrived = Instant::now();
+        let timeline = Timeline::starting_at(arrived);
+        std::thread::sleep(Duration::from_millis(1));
+        timeline.mark("queue.enter", None);
+        timeline.span("parse", arrived, Some("schema version 1".to_string()));
+
+        let events = timeline.events();
+        assert_eq!(events[0].name, "parse");
+        assert_eq!(events[0].start_us, 0);
+        assert!(events[0].duration_us.is_some());
+        assert_eq!(events[1].duration_us, None);
+
+        // Stages that started before the request arrived are clamped to its arrival.
+        timeline.span("early", arrived - Duration::from_millis(1), None);
+        assert_eq!(timeline.events()[0].start_us, 0);
+    }
+
+    #[test]
+    fn test_timeline_requested() {
+        assert!(timeline_requested(Some("timeline")));
+        assert!(timeline_requested(Some("verbose, Timeline")));
+        assert!(!timeline_requested(Some("1")));
+        assert!(!timeline_requested(None));
+    }
+}
@@ -0,0 +1,319 @@
+/* In-memory latency and throughput history per model.
+
+Dashboards without a Prometheus stack can read the recent history of each model
+from the admin API. Every inference run by the model scheduler is added to the
+time slot (one minute by default) it completed in. A model keeps one slot per
+step of its retention (24 hours by default) in a ring: a slot is reset when the
+ring comes back to it, so memory stays bounded whatever the traffic.
+
+A slot counts requests and errors and buckets latencies over the bounds of the
+request histograms (`LATENCY_BUCKETS`). Slots add up, so a query can merge them
+into coarser steps; quantiles are interpolated within their bucket, as
+Prometheus' `histogram_quantile` does.
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::metrics::LATENCY_BUCKETS;
+
+/// Length of a slot by default.
+pub const DEFAULT_RESOLUTION: Duration = Duration::from_secs(60);
+/// History kept by default.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Debug, Clone, Default)]
+struct Slot {
+    /// Index of the slot since the Unix epoch, `unix seconds / resolution`.
+    index: u64,
+    requests: u64,
+    errors: u64,
+    latency_sum_s: f64,
+    max_latency_s: f64,
+    /// Requests per latency bucket, the last one above every bound.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl Slot {
+    fn record(&mut self, latency: Duration, ok: bool) {
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.requests += 1;
+        if !ok {
+            self.errors += 1;
+        }
+        self.latency_sum_s += seconds;
+        self.max_latency_s = self.max_latency_s.max(seconds);
+        self.buckets[bucket] += 1;
+    }
+
+    fn merge(&mut self, other: &Slot) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.latency_sum_s += other.latency_sum_s;
+        self.max_latency_s = self.max_latency_s.max(other.max_latency_s);
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    /// Latency below which `quantile` of the requests completed, in seconds.
+    fn quantile(&self, quantile: f64) -> f64 {
+        let rank = quantile * self.requests as f64;
+        let mut below = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            if *count > 0 && (below + count) as f64 >= rank {
+                let lower = if index == 0 {
+                    0.0
+                } else {
+                    LATENCY_BUCKETS[index - 1]
+                };
+                let Some(upper) = LATENCY_BUCKETS.get(index) else {
+                    return self.max_latency_s;
+                };
+                let within = (rank - below as f64) / *count as f64;
+                return (lower + (upper - lower) * within).min(self.max_latency_s);
+            }
+            below += count;
+        }
+        self.max_latency_s
+    }
+}
+
+/// Requests of a model during one step of a query.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimeSeriesPoint {
+    /// Start of the step, in Unix seconds.
+    pub timestamp: u64,
+    pub requests: u64,
+    pub errors: u64,
+    /// Requests per second over the step.
+    pub qps: f64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// History of a model from `TimeSeriesRegistry::query`, oldest point first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimeSeries {
+    pub model: String,
+    pub step_s: u64,
+    pub points: Vec<TimeSeriesPoint>,
+}
+
+/// Slot ring of every model.
+#[derive(Debug)]
+pub struct TimeSeriesRegistry {
+    resolution_s: u64,
+    slots: usize,
+    models: DashMap<String, Mutex<Vec<Slot>>>,
+}
+
+impl Default for TimeSeriesRegistry {
+    fn default() -> Self {
+        Self::new(DEFAULT_RESOLUTION, DEFAULT_RETENTION)
+    }
+}
+
+impl TimeSeriesRegistry {
+    /// Keeps `retention` of history in slots of `resolution`, both rounded to whole seconds.
+    pub fn new(resolution: Duration, retention: Duration) -> Self {
+        let resolution_s = resolution.as_secs().max(1);
+        Self {
+            resolution_s,
+            slots: (retention.as_secs() / resolution_s).max(1) as usize,
+            models: DashMap::new(),
+        }
+    }
+
+    pub fn resolution(&self) -> Duration {
+        Duration::from_secs(self.resolution_s)
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.resolution_s * self.slots as u64)
+    }
+
+    fn now_s() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// Adds a request of `model` that completed now after `latency`.
+    pub fn record(&self, model: &str, latency: Duration, ok: bool) {
+        self.record_at(model, Self::now_s(), latency, ok);
+    }
+
+    /// Adds a request of `model` that completed at `at` Unix seconds.
+    pub fn record_at(&self, model: &str, at: u64, latency: Duration, ok: bool) {
+        let index = at / self.resolution_s;
+        let ring = match self.models.get(model) {
+            Some(ring) => ring,
+            None => self
+                .models
+                .entry(model.to_string())
+                .or_insert_with(|| Mutex::new(vec![Slot::default(); self.slots]))
+                .downgrade(),
+        };
+        let mut ring = ring.lock().unwrap();
+        let slot = &mut ring[(index % self.slots as u64) as usize];
+        if slot.index != index {
+            *slot = Slot {
+                index,
+                ..Slot::default()
+            };
+        }
+        slot.record(latency, ok);
+    }
+
+    /// History of `model` over the last `window` in steps of `step` (whole multiples of the
+    /// resolution). Steps without requests are zero.
+    pub fn query(&self, model: &str, window: Duration, step: Duration) -> Result<TimeSeries> {
+        self.query_at(model, Self::now_s(), window, step)
+    }
+
+    /// History of `model` over the `window` ending at `now` Unix seconds.
+    pub fn query_at(
+        &self,
+        model: &str,
+        now: u64,
+        window: Duration,
+        step: Duration,
+    ) -> Result<TimeSeries> {
+        if step.as_secs() == 0 || !step.as_secs().is_multiple_of(self.resolution_s) {
+            return Err(anyhow!(
+                "The step must be a multiple of the resolution of {}s",
+                self.resolution_s
+            ));
+        }
+        if window > self.retention() {
+            return Err(anyhow!(
+                "Only the last {}s are retained",
+                self.retention().as_secs()
+            ));
+        }
+        let ring = self.models.get(model);
+        let ring = ring.as_ref().map(|ring| ring.lock().unwrap());
+        let slots_per_step = step.as_secs() / self.resolution_s;
+        let steps = window.as_secs().div_ceil(step.as_secs()).max(1);
+        // The last step ends with the current slot.
+        let last = now / self.resolution_s;
+        let first = (last + 1).saturating_sub(steps * slots_per_step);
+        let points = (0..steps)
+            .map(|step_index| {
+                let start = first + step_index * slots_per_step;
+                let mut merged = Slot::default();
+                for index in start..start + slots_per_step {
+                    let slot = ring
+                        .as_ref()
+                        .map(|ring| &ring[(index % self.slots as u64) as usize])
+                        .filter(|slot| slot.index == index);
+                    if let Some(slot) = slot {
+                        merged.merge(slot);
+                    }
+                }
+                Self::point(start * self.resolution_s, step.as_secs(), &merged)
+            })
+            .collect();
+        Ok(TimeSeries {
+            model: model.to_string(),
+            step_s: step.as_secs(),
+            points,
+        })
+    }
+
+    fn point(timestamp: u64, step_s: u64, slot: &Slot) -> TimeSeriesPoint {
+        let ms = |seconds: f64| seconds * 1000.0;
+        let (mean_ms, p50_ms, p90_ms, p99_ms) = if slot.requests == 0 {
+            (0.0, 0.0, 0.0, 0.0)
+        } else {
+            (
+                ms(slot.latency_sum_s / slot.requests as f64),
+                ms(slot.quantile(0.5)),
+                ms(slot.quantile(0.9)),
+                ms(slot.quantile(0.99)),
+            )
+        };
+        TimeSeriesPoint {
+            timestamp,
+            requests: slot.requests,
+            errors: slot.errors,
+            qps: slot.requests as f64 / step_s as f64,
+            mean_ms,
+            p50_ms,
+            p90_ms,
+            p99_ms,
+            max_ms: ms(slot.max_latency_s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_merge_into_steps_and_expire() {
+        let registry =
+            TimeSeriesRegistry::new(Duration::from_secs(60), Duration::from_secs(10 * 60));
+        let now = 6_000_030;
+        // Ten minutes ago shares the slot of the current minute, and is overwritten.
+        registry.record_at("m", now - 600, Duration::from_secs(20), true);
+        for i in 0..120 {
+            registry.record_at("m", now - 30, Duration::from_millis(2 + i % 4), i != 0);
+        }
+        registry.record_at("m", now - 90, Duration::from_millis(40), true);
+        registry.record_at("m", now, Duration::from_millis(3), true);
+
+        let series = registry
+            .query_at("m", now, Duration::from_secs(180), Duration::from_secs(60))
+            .unwrap();
+        let requests: Vec<u64> = series.points.iter().map(|p| p.requests).collect();
+        assert_eq!(requests, [0, 1, 121]);
+        let current = &series.points[2];
+        assert_eq!(current.timestamp, now / 60 * 60);
+        assert_eq!(current.errors, 1);
+        assert!((current.qps - 121.0 / 60.0).abs() < 1e-9);
+        assert!(current.p50_ms > 2.5 && current.p50_ms <= 5.0);
+        assert_eq!(current.max_ms, 5.0);
+
+        let series = registry
+            .query_at("m", now, Duration::from_secs(600), Duration::from_secs(300))
+            .unwrap();
+        assert_eq!(series.points.len(), 2);
+        assert_eq!(series.points[1].requests, 122);
+        assert_eq!(series.points[1].max_ms, 40.0);
+
+        let idle = registry
+            .query_at(
+                "other",
+                now,
+                Duration::from_secs(120),
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(idle.points.len(), 2);
+        assert_eq!((idle.points[1].requests, idle.points[1].p99_ms), (0, 0.0));
+        assert!(
+            registry
+                .query_at("m", now, Duration::from_secs(60), Duration::from_secs(90))
+                .is_err()
+        );
+        assert!(
+            registry
+                .query_at("m", now, Duration::from_secs(3600), Duration::from_secs(60))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,294 @@
+/* Request size accounting and limits per tenant.
+
+The transport layers count the bytes of every inference request received and
+every response sent, per tenant (see `tenants::request_tenant`) and per UTC
+day. Two
+limits protect the server's memory and share its bandwidth:
+
+- `max_request_bytes` bounds the body of any single request. It is enforced as
+  the bytes arrive, before a request is decoded and its tensors assembled.
+- `daily_bytes` bounds the bytes a tenant exchanges (received plus sent) per
+  UTC day. Once used up, the tenant's requests are refused until midnight UTC.
+  `tenants` overrides it for specific tenants.
+
+Requests without a tenant, anonymous ones, are counted under `DEFAULT_TENANT`
+and share its daily limit.
+*/
+
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::tenants::DEFAULT_TENANT;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Parses a byte size: a count optionally followed by a decimal (`KB`, `MB`, `GB`) or
+/// binary (`KiB`, `MiB`, `GiB`) unit, e.g. `512KiB` or `10 MB`.
+pub fn parse_byte_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let count: u64 = count
+        .parse()
+        .map_err(|_| anyhow!("Invalid byte size '{}'", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(anyhow!("Unknown unit in byte size '{}'", s)),
+    };
+    count
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("Byte size '{}' is too large", s))
+}
+
+/// Limits from the server configuration; no limit applies where none is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrafficLimits {
+    /// Bytes of a single request body.
+    pub max_request_bytes: Option<u64>,
+    /// Bytes received and sent per tenant and UTC day.
+    pub daily_bytes: Option<u64>,
+    /// Daily limits of specific tenants, replacing `daily_bytes`.
+    pub tenants: HashMap<String, u64>,
+}
+
+impl TrafficLimits {
+    fn daily_limit(&self, tenant: &str) -> Option<u64> {
+        self.tenants.get(tenant).copied().or(self.daily_bytes)
+    }
+}
+
+/// Why a request was refused by the traffic limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrafficRefusal {
+    /// The request body exceeds `max_request_bytes`.
+    RequestTooLarge { limit: u64 },
+    /// The tenant used up its bytes of the day.
+    DailyLimit { tenant: String, limit: u64 },
+}
+
+impl fmt::Display for TrafficRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RequestTooLarge { limit } => {
+                write!(f, "Request body exceeds the limit of {} bytes", limit)
+            }
+            Self::DailyLimit { tenant, limit } => write!(
+                f,
+                "Tenant '{}' used its {} bytes of the day, retry after midnight UTC",
+                tenant, limit
+            ),
+        }
+    }
+}
+
+/// Traffic of a tenant today, as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantTraffic {
+    pub tenant: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub daily_limit: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct DailyTraffic {
+    day: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+impl DailyTraffic {
+    /// Starts over on a new day.
+    fn on(&mut self, day: u64) -> &mut Self {
+        if self.day != day {
+            *self = DailyTraffic {
+                day,
+                ..DailyTraffic::default()
+            };
+        }
+        self
+    }
+
+    fn total(&self) -> u64 {
+        self.bytes_in.saturating_add(self.bytes_out)
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECONDS_PER_DAY
+}
+
+/// Counts the bytes of each tenant and enforces the limits, see the module documentation.
+#[derive(Debug, Default)]
+pub struct TrafficAccounting {
+    limits: TrafficLimits,
+    tenants: DashMap<String, DailyTraffic>,
+}
+
+impl TrafficAccounting {
+    pub fn new(limits: TrafficLimits) -> Self {
+        Self {
+            limits,
+            tenants: DashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &TrafficLimits {
+        &self.limits
+    }
+
+    /// Whether a request declaring `size` bytes (when known upfront) may be received for
+    /// `tenant`. Bodies without a declared size are checked with `check_size` as they arrive.
+    pub fn admit(&self, tenant: Option<&str>, size: Option<u64>) -> Result<(), TrafficRefusal> {
+        self.admit_on(tenant, size, today())
+    }
+
+    pub fn admit_on(
+        &self,
+        tenant: Option<&str>,
+        size: Option<u64>,
+        day: u64,
+    ) -> Result<(), TrafficRefusal> {
+        if let Some(size) = size {
+            self.check_size(size)?;
+        }
+        let tenant = tenant.unwrap_or(DEFAULT_TENANT);
+        let Some(limit) = self.limits.daily_limit(tenant) else {
+            return Ok(());
+        };
+        let used = self
+            .tenants
+            .get(tenant)
+            .filter(|traffic| traffic.day == day)
+            .map(|traffic| traffic.total())
+            .unwrap_or_default();
+        if used >= limit {
+            return Err(TrafficRefusal::DailyLimit {
+                tenant: tenant.to_string(),
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// Refuses a request body of `size` bytes above `max_request_bytes`.
+    pub fn check_size(&self, size: u64) -> Result<(), TrafficRefusal> {
+        match self.limits.max_request_bytes {
+            Some(limit) if size > limit => Err(TrafficRefusal::RequestTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts `bytes` received from `tenant`.
+    pub fn record_in(&self, tenant: Option<&str>, bytes: u64) {
+        self.record_on(tenant, bytes, 0, today());
+    }
+
+    /// Counts `bytes` sent to `tenant`.
+    pub fn record_out(&self, tenant: Option<&str>, bytes: u64) {
+        self.record_on(tenant, 0, bytes, today());
+    }
+
+    pub fn record_on(&self, tenant: Option<&str>, bytes_in: u64, bytes_out: u64, day: u64) {
+        let tenant = tenant.unwrap_or(DEFAULT_TENANT);
+        if !self.tenants.contains_key(tenant) {
+            // Tenants idle since an earlier day would start over anyway.
+            self.tenants.retain(|_, traffic| traffic.day >= day);
+        }
+        let mut traffic = self.tenants.entry(tenant.to_string()).or_default();
+        let traffic = traffic.on(day);
+        traffic.bytes_in = traffic.bytes_in.saturating_add(bytes_in);
+        traffic.bytes_out = traffic.bytes_out.saturating_add(bytes_out);
+    }
+
+    /// Traffic of every tenant seen today, sorted by tenant.
+    pub fn usage(&self) -> Vec<TenantTraffic> {
+        self.usage_on(today())
+    }
+
+    pub fn usage_on(&self, day: u64) -> Vec<TenantTraffic> {
+        let mut usage: Vec<TenantTraffic> = self
+            .tenants
+            .iter()
+            .filter(|entry| entry.day == day)
+            .map(|entry| TenantTraffic {
+                tenant: entry.key().clone(),
+                bytes_in: entry.bytes_in,
+                bytes_out: entry.bytes_out,
+                daily_limit: self.limits.daily_limit(entry.key()),
+            })
+            .collect();
+        usage.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_size_parsing() {
+        assert_eq!(parse_byte_size("1234").unwrap(), 1234);
+        assert_eq!(parse_byte_size("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_byte_size("512 KiB").unwrap(), 512 * 1024);
+        assert_eq!(parse_byte_size("2gib").unwrap(), 2 << 30);
+        assert!(parse_byte_size("10TB").is_err());
+        assert!(parse_byte_size("MB").is_err());
+    }
+
+    #[test]
+    fn test_tenants_are_refused_once_their_daily_bytes_are_used() {
+        let traffic = TrafficAccounting::new(TrafficLimits {
+            max_request_bytes: Some(100),
+            daily_bytes: Some(300),
+            tenants: HashMap::from([("big".to_string(), 1000)]),
+        });
+        let day = 20_000;
+        let acme = Some("acme");
+
+        assert_eq!(
+            traffic.admit_on(None, Some(101), day),
+            Err(TrafficRefusal::RequestTooLarge { limit: 100 })
+        );
+        assert!(traffic.admit_on(acme, Some(100), day).is_ok());
+        traffic.record_on(acme, 100, 150, day);
+        assert!(traffic.admit_on(acme, None, day).is_ok());
+        traffic.record_on(acme, 50, 0, day);
+        assert!(matches!(
+            traffic.admit_on(acme, None, day),
+            Err(TrafficRefusal::DailyLimit { limit: 300, .. })
+        ));
+        traffic.record_on(Some("big"), 100, 400, day);
+        assert!(traffic.admit_on(Some("big"), None, day).is_ok());
+        // Requests without a tenant share the default tenant and its limit.
+        traffic.record_on(None, 100, 200, day);
+        assert!(matches!(
+            traffic.admit_on(None, None, day),
+            Err(TrafficRefusal::DailyLimit { limit: 300, .. })
+        ));
+
+        let usage = traffic.usage_on(day);
+        assert_eq!(usage.len(), 3);
+        assert_eq!((usage[0].bytes_in, usage[0].bytes_out), (150, 150));
+        assert_eq!(usage[1].daily_limit, Some(1000));
+        assert_eq!(usage[2].tenant, DEFAULT_TENANT);
+
+        assert!(traffic.admit_on(acme, None, day + 1).is_ok());
+        assert!(traffic.usage_on(day + 1).is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+/* `galemind bench`: load generator for a running server.
+
+```bash
+galemind bench --model resnet --concurrency 32 --duration 60s [--grpc] [--input request.json]
+```
+
+`concurrency` clients send inference requests to the model back to back for
+`duration`, over REST or gRPC. Inputs are read from a V2 inference request, or
+generated from the metadata of the model: zeros, with every variable dimension
+set to 1. The report gives the throughput of the successful requests, their
+latency percentiles, and the error rate with the errors met.
+*/
+
+use crate::client::{ServerClient, Tensor};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Parses a duration such as `60s`, `500ms`, `5m` or `1h`; a bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let count: u64 = count
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration '{}'", s))?;
+    let duration = match unit.trim() {
+        "ms" => Duration::from_millis(count),
+        "" | "s" => Duration::from_secs(count),
+        "m" => Duration::from_secs(count.saturating_mul(60)),
+        "h" => Duration::from_secs(count.saturating_mul(3_600)),
+        _ => return Err(anyhow!("Unknown unit in duration '{}'", s)),
+    };
+    if duration.is_zero() {
+        return Err(anyhow!("Duration '{}' is empty", s));
+    }
+    Ok(duration)
+}
+
+/// What to send, and for how long.
+pub struct BenchPlan {
+    pub model: String,
+    pub version: Option<String>,
+    pub inputs: Vec<Tensor>,
+    pub concurrency: usize,
+    pub duration: Duration,
+}
+
+/// Latencies of the successful requests, in milliseconds.
+#[derive(Debug, Serialize)]
+pub struct Latency {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Latency {
+    /// Latency of `samples`, none when there is no sample.
+    fn of(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let ms = |duration: Duration| duration.as_secs_f64() * 1_000.0;
+        // Nearest-rank percentile.
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * samples.len() as f64).ceil() as usize;
+            ms(samples[rank.clamp(1, samples.len()) - 1])
+        };
+        let total: Duration = samples.iter().sum();
+        Some(Self {
+            mean: ms(total) / samples.len() as f64,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: ms(samples[samples.len() - 1]),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub model: String,
+    pub concurrency: usize,
+    /// Seconds the requests were sent for.
+    pub elapsed: f64,
+    pub requests: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Share of the requests that failed, between 0 and 1.
+    pub error_rate: f64,
+    /// Successful requests per second.
+    pub throughput: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<Latency>,
+    /// Failed requests by error message.
+    pub errors: BTreeMap<String, u64>,
+}
+
+/// What one client saw.
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Sends the requests of `plan` with `client` and reports how the server answered.
+pub async fn run(client: ServerClient, plan: BenchPlan) -> Result<BenchReport> {
+    let plan = Arc::new(plan);
+    let started = Instant::now();
+    let deadline = started + plan.duration;
+    let workers: Vec<_> = (0..plan.concurrency.max(1))
+        .map(|_| {
+            let client = client.clone();
+            let plan = plan.clone();
+            tokio::spawn(async move {
+                let mut samples = Samples::default();
+                while Instant::now() < deadline {
+                    let sent = Instant::now();
+                    match client
+                        .infer(&plan.model, plan.version.as_deref(), &plan.inputs)
+                        .await
+                    {
+                        Ok(_) => samples.latencies.push(sent.elapsed()),
+                        Err(e) => *samples.errors.entry(format!("{:#}", e)).or_default() += 1,
+                    }
+                }
+                samples
+            })
+        })
+        .collect();
+
+    let mut samples = Samples::default();
+    for worker in workers {
+        let worker = worker.await?;
+        samples.latencies.extend(worker.latencies);
+        for (error, count) in worker.errors {
+            *samples.errors.entry(error).or_default() += count;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let succeeded = samples.latencies.len() as u64;
+    let failed: u64 = samples.errors.values().sum();
+    let requests = succeeded + failed;
+    Ok(BenchReport {
+        model: plan.model.clone(),
+        concurrency: plan.concurrency.max(1),
+        elapsed,
+        requests,
+        succeeded,
+        failed,
+        error_rate: if requests == 0 {
+            0.0
+        } else {
+            failed as f64 / requests as f64
+        },
+        throughput: succeeded as f64 / elapsed,
+        latency: Latency::of(samples.latencies),
+        errors: samples.errors,
+    })
+}
+
+/// The report as text.
+pub fn format_report(report: &BenchReport) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Model:        {} ({} clients, {:.1}s)",
+        report.model, report.concurrency, report.elapsed
+    );
+    let _ = writeln!(
+        out,
+        "Requests:     {} ({} succeeded, {} failed)",
+        report.requests, report.succeeded, report.failed
+    );
+    let _ = writeln!(out, "Throughput:   {:.1} req/s", report.throughput);
+    let _ = writeln!(out, "Error rate:   {:.2}%", report.error_rate * 100.0);
+    if let Some(latency) = &report.latency {
+        let _ = writeln!(
+            out,
+            "Latency (ms): mean {:.2}, p50 {:.2}, p90 {:.2}, p95 {:.2}, p99 {:.2}, max {:.2}",
+            latency.mean, latency.p50, latency.p90, latency.p95, latency.p99, latency.max
+        );
+    }
+    if !report.errors.is_empty() {
+        let _ = writeln!(out, "Errors:");
+        let mut errors: Vec<_> = report.errors.iter().collect();
+        errors.sort_by(|a, b| b.1.cmp(a.1));
+        for (error, count) in errors {
+            let _ = writeln!(out, "  {:>8}  {}", count, error);
+        }
+    }
+    out
+}
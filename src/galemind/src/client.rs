@@ -0,0 +1,461 @@
+/* Client of a running server, for the commands operating it from the terminal.
+
+The server is reached over REST (`http://localhost:8080` by default) or over
+gRPC (`http://localhost:50051` by default) with `--grpc`. Both give the same
+answers: the catalog of models, the metadata of a model and inference. The API
+key or token of `--api-key`, or of `GALEMIND_API_KEY`, is sent as a Bearer
+credential.
+*/
+
+use anyhow::{Context, Result, anyhow};
+use foundation::{AUTHORIZATION_HEADER, Capabilities};
+use grpc_server::grpc_server::{
+    InferTensorContents, ModelIndexRequest, ModelInferRequest, ModelMetadataRequest,
+    model_infer_request::InferInputTensor, model_metadata_response,
+    prediction_service_client::PredictionServiceClient,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tonic::Request;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Channel;
+
+/// Server reached without `--server`, over REST.
+pub const DEFAULT_REST_URL: &str = "http://localhost:8080";
+/// Server reached without `--server`, over gRPC.
+pub const DEFAULT_GRPC_URL: &str = "http://localhost:50051";
+/// Environment variable holding the API key or token sent to the server.
+pub const API_KEY_ENV: &str = "GALEMIND_API_KEY";
+
+/// A model of the catalog.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelEntry {
+    pub name: String,
+    /// Served versions, oldest first.
+    pub versions: Vec<String>,
+    /// Backend serving the model, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Whether the version answering unversioned requests is loaded.
+    pub ready: bool,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// An input or output of a model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TensorInfo {
+    pub name: String,
+    pub datatype: String,
+    /// -1 marks a variable dimension.
+    pub shape: Vec<i64>,
+}
+
+/// A tensor sent to or returned by a model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tensor {
+    pub name: String,
+    pub datatype: String,
+    pub shape: Vec<i64>,
+    /// Elements flattened in row-major order, strings for BYTES tensors.
+    #[serde(default)]
+    pub data: Vec<Value>,
+}
+
+impl Tensor {
+    /// A tensor of `info` filled with zeros, empty strings or `false`, its variable
+    /// dimensions set to 1.
+    pub fn zeros(info: &TensorInfo) -> Self {
+        let shape: Vec<i64> = info.shape.iter().map(|&dim| dim.max(1)).collect();
+        let element = match info.datatype.as_str() {
+            "BOOL" => Value::Bool(false),
+            "BYTES" => Value::String(String::new()),
+            _ => Value::from(0),
+        };
+        Self {
+            name: info.name.clone(),
+            datatype: info.datatype.clone(),
+            data: vec![element; shape.iter().product::<i64>() as usize],
+            shape,
+        }
+    }
+}
+
+/// The inputs of the V2 inference request in the JSON file at `path`.
+pub fn read_inputs(path: &Path) -> Result<Vec<Tensor>> {
+    #[derive(Deserialize)]
+    struct RequestFile {
+        inputs: Vec<Tensor>,
+    }
+    let body = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let request: RequestFile = serde_json::from_str(&body)
+        .with_context(|| format!("{} is not a V2 inference request", path.display()))?;
+    Ok(request.inputs)
+}
+
+/// A model of the catalog with its metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelDescription {
+    #[serde(flatten)]
+    pub entry: ModelEntry,
+    pub platform: String,
+    pub inputs: Vec<TensorInfo>,
+    pub outputs: Vec<TensorInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+}
+
+/// Metadata of a model, as `GET /v2/models/{model_name}` returns it.
+#[derive(Debug, Deserialize)]
+struct RestMetadata {
+    platform: String,
+    inputs: Vec<TensorInfo>,
+    outputs: Vec<TensorInfo>,
+    capabilities: Option<Capabilities>,
+}
+
+/// Outputs of an inference, as `POST /v2/models/{model_name}/infer` returns them.
+#[derive(Debug, Deserialize)]
+struct RestInference {
+    #[serde(default)]
+    outputs: Option<Vec<Tensor>>,
+}
+
+#[derive(Clone)]
+enum Transport {
+    Rest {
+        base_url: String,
+        http: reqwest::Client,
+    },
+    Grpc(PredictionServiceClient<Channel>),
+}
+
+/// A connection to a running server.
+#[derive(Clone)]
+pub struct ServerClient {
+    transport: Transport,
+    api_key: Option<String>,
+}
+
+/// `url` with a scheme, `http://` when it has none.
+fn with_scheme(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    }
+}
+
+impl ServerClient {
+    /// Connects to the server at `url`, or at the default URL of the protocol.
+    pub async fn connect(url: Option<&str>, grpc: bool, api_key: Option<String>) -> Result<Self> {
+        let transport = if grpc {
+            let url = with_scheme(url.unwrap_or(DEFAULT_GRPC_URL));
+            let channel = Channel::from_shared(url.clone())?
+                .connect()
+                .await
+                .with_context(|| format!("Failed to connect to {}", url))?;
+            Transport::Grpc(PredictionServiceClient::new(channel))
+        } else {
+            Transport::Rest {
+                base_url: with_scheme(url.unwrap_or(DEFAULT_REST_URL)),
+                http: reqwest::Client::new(),
+            }
+        };
+        Ok(Self { transport, api_key })
+    }
+
+    /// `message` with the credential of the client.
+    fn grpc_request<T>(&self, message: T) -> Result<Request<T>> {
+        let mut request = Request::new(message);
+        if let Some(key) = &self.api_key {
+            let value = MetadataValue::try_from(format!("Bearer {}", key))
+                .map_err(|_| anyhow!("The API key is not a valid header value"))?;
+            request.metadata_mut().insert(AUTHORIZATION_HEADER, value);
+        }
+        Ok(request)
+    }
+
+    /// The REST resource at `path`, decoded from JSON. Errors carry the server's message.
+    async fn rest_get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.rest_call(path, None).await
+    }
+
+    /// The answer to a GET of `path`, or to a POST of `body` when given, decoded from JSON.
+    async fn rest_call<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<T> {
+        let Transport::Rest { base_url, http } = &self.transport else {
+            unreachable!("REST request on a gRPC client");
+        };
+        let url = format!("{}{}", base_url, path);
+        let mut request = match body {
+            Some(body) => http.post(&url).json(body),
+            None => http.get(&url),
+        };
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        rest_json(response).await
+    }
+
+    /// Models of the catalog matching the label `selector`, sorted by name.
+    pub async fn models(&self, selector: Option<&str>) -> Result<Vec<ModelEntry>> {
+        match &self.transport {
+            Transport::Rest { .. } => {
+                let path = match selector {
+                    Some(selector) => {
+                        format!("/v2/models?selector={}", urlencoding::encode(selector))
+                    }
+                    None => "/v2/models".to_string(),
+                };
+                self.rest_get(&path).await
+            }
+            Transport::Grpc(client) => {
+                let request = self.grpc_request(ModelIndexRequest {
+                    selector: selector.unwrap_or_default().to_string(),
+                })?;
+                let response = client
+                    .clone()
+                    .model_index(request)
+                    .await
+                    .map_err(status_error)?
+                    .into_inner();
+                Ok(response
+                    .models
+                    .into_iter()
+                    .map(|entry| ModelEntry {
+                        name: entry.name,
+                        versions: entry.versions,
+                        backend: Some(entry.backend).filter(|backend| !backend.is_empty()),
+                        ready: entry.ready,
+                        labels: entry.labels.into_iter().collect(),
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// A model of the catalog with the metadata of `version`, or of its default version.
+    pub async fn describe(&self, name: &str, version: Option<&str>) -> Result<ModelDescription> {
+        let entry = self
+            .models(None)
+            .await?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| anyhow!("Model {} is not registered", name))?;
+        let metadata = match &self.transport {
+            Transport::Rest { .. } => {
+                let path = match version {
+                    Some(version) => format!(
+                        "/v2/models/{}/versions/{}",
+                        urlencoding::encode(name),
+                        urlencoding::encode(version)
+                    ),
+                    None => format!("/v2/models/{}", urlencoding::encode(name)),
+                };
+                self.rest_get::<RestMetadata>(&path).await?
+            }
+            Transport::Grpc(client) => {
+                let request = self.grpc_request(ModelMetadataRequest {
+                    name: name.to_string(),
+                    version: version.unwrap_or_default().to_string(),
+                })?;
+                let response = client
+                    .clone()
+                    .model_metadata(request)
+                    .await
+                    .map_err(status_error)?
+                    .into_inner();
+                let tensors = |tensors: Vec<model_metadata_response::TensorMetadata>| {
+                    tensors
+                        .into_iter()
+                        .map(|tensor| TensorInfo {
+                            name: tensor.name,
+                            datatype: tensor.datatype,
+                            shape: tensor.shape,
+                        })
+                        .collect()
+                };
+                RestMetadata {
+                    platform: response.platform,
+                    inputs: tensors(response.inputs),
+                    outputs: tensors(response.outputs),
+                    capabilities: response.capabilities.map(|capabilities| Capabilities {
+                        streaming: capabilities.streaming,
+                        logprobs: capabilities.logprobs,
+                        tools: capabilities.tools,
+                        modalities: capabilities.modalities,
+                    }),
+                }
+            }
+        };
+        Ok(ModelDescription {
+            entry,
+            platform: metadata.platform,
+            inputs: metadata.inputs,
+            outputs: metadata.outputs,
+            capabilities: metadata.capabilities,
+        })
+    }
+
+    /// Outputs of `version` of the model, or of its default version, for `inputs`.
+    pub async fn infer(
+        &self,
+        name: &str,
+        version: Option<&str>,
+        inputs: &[Tensor],
+    ) -> Result<Vec<Tensor>> {
+        match &self.transport {
+            Transport::Rest { .. } => {
+                let path = match version {
+                    Some(version) => format!(
+                        "/v2/models/{}/versions/{}/infer",
+                        urlencoding::encode(name),
+                        urlencoding::encode(version)
+                    ),
+                    None => format!("/v2/models/{}/infer", urlencoding::encode(name)),
+                };
+                let body = serde_json::json!({ "inputs": inputs });
+                let response: RestInference = self.rest_call(&path, Some(&body)).await?;
+                Ok(response.outputs.unwrap_or_default())
+            }
+            Transport::Grpc(client) => {
+                let inputs = inputs
+                    .iter()
+                    .map(|tensor| {
+                        Ok(InferInputTensor {
+                            name: tensor.name.clone(),
+                            datatype: tensor.datatype.clone(),
+                            shape: tensor.shape.clone(),
+                            parameters: Default::default(),
+                            contents: Some(typed_contents(tensor)?),
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                let request = self.grpc_request(ModelInferRequest {
+                    model_name: name.to_string(),
+                    model_version: version.unwrap_or_default().to_string(),
+                    inputs,
+                    ..Default::default()
+                })?;
+                let response = client
+                    .clone()
+                    .model_infer(request)
+                    .await
+                    .map_err(status_error)?
+                    .into_inner();
+                Ok(response
+                    .outputs
+                    .into_iter()
+                    .map(|output| Tensor {
+                        name: output.name,
+                        datatype: output.datatype,
+                        shape: output.shape,
+                        data: output.contents.map(elements).unwrap_or_default(),
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+/// The elements of `tensor` in the typed contents field of its datatype.
+fn typed_contents(tensor: &Tensor) -> Result<InferTensorContents> {
+    fn convert<T>(
+        tensor: &Tensor,
+        kind: &str,
+        element: impl Fn(&Value) -> Option<T>,
+    ) -> Result<Vec<T>> {
+        tensor
+            .data
+            .iter()
+            .map(|value| {
+                element(value)
+                    .ok_or_else(|| anyhow!("Input '{}' holds {}, not {}", tensor.name, value, kind))
+            })
+            .collect()
+    }
+    let integer = |value: &Value| value.as_i64().and_then(|v| i32::try_from(v).ok());
+    let unsigned = |value: &Value| value.as_u64().and_then(|v| u32::try_from(v).ok());
+    let mut contents = InferTensorContents::default();
+    match tensor.datatype.as_str() {
+        "BOOL" => contents.bool_contents = convert(tensor, "a boolean", Value::as_bool)?,
+        "INT8" | "INT16" | "INT32" => {
+            contents.int_contents = convert(tensor, "a 32-bit integer", integer)?
+        }
+        "INT64" => contents.int64_contents = convert(tensor, "an integer", Value::as_i64)?,
+        "UINT8" | "UINT16" | "UINT32" => {
+            contents.uint_contents = convert(tensor, "a 32-bit unsigned integer", unsigned)?
+        }
+        "UINT64" => {
+            contents.uint64_contents = convert(tensor, "an unsigned integer", Value::as_u64)?
+        }
+        "FP32" => {
+            contents.fp32_contents =
+                convert(tensor, "a number", |value| value.as_f64().map(|v| v as f32))?
+        }
+        "FP64" => contents.fp64_contents = convert(tensor, "a number", Value::as_f64)?,
+        "BYTES" => {
+            contents.bytes_contents = convert(tensor, "a string", |value| {
+                value.as_str().map(|v| v.as_bytes().to_vec())
+            })?
+        }
+        datatype => {
+            return Err(anyhow!(
+                "Input '{}' is {}, which has no typed contents over gRPC",
+                tensor.name,
+                datatype
+            ));
+        }
+    }
+    Ok(contents)
+}
+
+/// The elements of typed `contents`. BYTES elements that are not UTF-8 are replaced lossily.
+fn elements(contents: InferTensorContents) -> Vec<Value> {
+    let mut values: Vec<Value> = Vec::new();
+    values.extend(contents.bool_contents.into_iter().map(Value::from));
+    values.extend(contents.int_contents.into_iter().map(Value::from));
+    values.extend(contents.int64_contents.into_iter().map(Value::from));
+    values.extend(contents.uint_contents.into_iter().map(Value::from));
+    values.extend(contents.uint64_contents.into_iter().map(Value::from));
+    values.extend(contents.fp32_contents.into_iter().map(Value::from));
+    values.extend(contents.fp64_contents.into_iter().map(Value::from));
+    values.extend(
+        contents
+            .bytes_contents
+            .iter()
+            .map(|bytes| Value::from(String::from_utf8_lossy(bytes))),
+    );
+    values
+}
+
+/// A gRPC error with its code and the server's message.
+fn status_error(status: tonic::Status) -> anyhow::Error {
+    anyhow!("{:?}: {}", status.code(), status.message())
+}
+
+/// The JSON body of a successful `response`, else an error with the server's message.
+async fn rest_json<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().await?);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|error| error.get("error")?.as_str().map(str::to_string))
+        .unwrap_or(body);
+    Err(anyhow!("{}: {}", status, message.trim()))
+}
@@ -0,0 +1,90 @@
+/* `galemind infer`: a single inference request to a running server.
+
+```bash
+galemind infer --model resnet --input request.json [--version 2] [--grpc] [--json]
+```
+
+The inputs of the V2 inference request in the file are sent to the model, and
+the outputs printed with their datatype and shape, their elements nested along
+the shape. Long dimensions are elided past their first elements; `--json`
+prints every element.
+*/
+
+use crate::client::Tensor;
+use serde_json::Value;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Elements shown per dimension before the rest is elided.
+const SHOWN: usize = 8;
+
+/// `data` nested along `shape`, each dimension elided past `SHOWN` elements. Rows of a
+/// tensor of two or more dimensions go on their own lines, indented by `indent`.
+fn nested(data: &[Value], shape: &[usize], indent: &str) -> String {
+    let elided = |len: usize| {
+        if len > SHOWN {
+            format!(", ... ({} more)", len - SHOWN)
+        } else {
+            String::new()
+        }
+    };
+    match shape {
+        [] => data.first().map(Value::to_string).unwrap_or_default(),
+        [len] => {
+            let shown: Vec<String> = data.iter().take(SHOWN).map(Value::to_string).collect();
+            format!("[{}{}]", shown.join(", "), elided(*len))
+        }
+        [len, rest @ ..] => {
+            let size = rest.iter().product::<usize>().max(1);
+            let rows: Vec<String> = data
+                .chunks(size)
+                .take(SHOWN)
+                .map(|row| nested(row, rest, &format!("{} ", indent)))
+                .collect();
+            let separator = if shape.len() == 2 {
+                format!(",\n{} ", indent)
+            } else {
+                ", ".to_string()
+            };
+            format!("[{}{}]", rows.join(&separator), elided(*len))
+        }
+    }
+}
+
+/// The elements of `tensor`, nested along its shape when they fill it.
+fn elements(tensor: &Tensor) -> String {
+    let shape: Option<Vec<usize>> = tensor
+        .shape
+        .iter()
+        .map(|&dim| usize::try_from(dim).ok())
+        .collect();
+    match shape {
+        Some(shape) if shape.iter().product::<usize>() == tensor.data.len() => {
+            nested(&tensor.data, &shape, "    ")
+        }
+        _ => nested(&tensor.data, &[tensor.data.len()], "    "),
+    }
+}
+
+/// The outputs of an inference that took `latency`, as text.
+pub fn format_outputs(model: &str, outputs: &[Tensor], latency: Duration) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Model:    {}", model);
+    let _ = writeln!(out, "Latency:  {:.2} ms", latency.as_secs_f64() * 1_000.0);
+    let _ = writeln!(out, "Outputs:");
+    if outputs.is_empty() {
+        let _ = writeln!(out, "  (none)");
+    }
+    for output in outputs {
+        let shape: Vec<String> = output.shape.iter().map(i64::to_string).collect();
+        let _ = writeln!(
+            out,
+            "  {}  {}  [{}]",
+            output.name,
+            output.datatype,
+            shape.join(", ")
+        );
+        let _ = writeln!(out, "    {}", elements(output));
+    }
+    out
+}
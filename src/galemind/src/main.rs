@@ -1,85 +1,386 @@
-use clap::{Arg, Command};
-use foundation::{InferenceServerBuilder, InferenceServerConfig, ModelDiscoveryService};
+mod bench;
+mod client;
+mod infer;
+mod models;
+
+use anyhow::anyhow;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use client::{API_KEY_ENV, ServerClient, Tensor};
+use foundation::{
+    AUDIT_QUEUE_CAPACITY, AnalyticsTee, AuditLogger, AuditSampling, BufferSizing, CONFIG_ENV,
+    CircuitPolicy, ConcurrencyLimiter, ConcurrencyLimits, ConfigReload, ConnectionLimits,
+    CorsConfig, DeviceScheduler, EVENT_QUEUE_CAPACITY, ErrorBudget, HintPolicy, IdScheme,
+    InferenceServerBuilder, InferenceServerConfig, JwtConfig, JwtValidator, KafkaConfig, KeyStore,
+    LogLevel, MLFlowClient, MLFlowStageWatcher, MODELS_LOADING, MemoryBudget, MessageFormat,
+    ModelDiscoveryService, ModelSource, OverloadController, OverloadPolicy, PidFile, PortRouting,
+    Preflight, QuotaLimits, QuotaTracker, RateLimiter, RateLimits, RedisResultBackend,
+    ReloadReport, ResponseCache, ResultBackend, Role, SHUTTING_DOWN, ServerEvent, Settings,
+    SharedMemoryRegistry, SharedPort, Shutdown, StreamPacing, TlsConfig, TrafficAccounting,
+    TrafficLimits, VersionPolicy, Watermarking, WebhookObserver, parse_byte_size, parse_window,
+    sd_notify, set_log_level, termination,
+};
 use grpc_server::GrpcServerBuilder;
 use rest_server::RestServerBuilder;
-use std::{env, error::Error, sync::Arc};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::{env, error::Error, path::PathBuf, sync::Arc, time::Duration};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let matches = Command::new("galemind")
+/// The command line of the server and of the commands talking to a running one.
+fn cli() -> Command {
+    Command::new("galemind")
         .version("0.1")
         .author("Zenforcode Team <team@zenforcode.com>")
         .about("GaleMind ML Inference Server v0.1")
         .subcommand(
             Command::new("start")
                 .about("Start the server")
+                .args(server_args())
+                .arg(
+                    Arg::new("read-only")
+                        .long("read-only")
+                        .action(ArgAction::SetTrue)
+                        .help("Freeze the model registry once the initial models are loaded"),
+                )
+                .arg(
+                    Arg::new("drain-timeout")
+                        .long("drain-timeout")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("30")
+                        .help("Seconds in-flight and queued requests get to finish on SIGTERM or SIGINT before runtimes are unloaded"),
+                )
+                .arg(
+                    Arg::new("pid-file")
+                        .long("pid-file")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Write the process id to this file while the server runs"),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check the environment before starting the server")
+                .args(server_args())
+                .arg(
+                    Arg::new("require-gpu")
+                        .long("require-gpu")
+                        .action(ArgAction::SetTrue)
+                        .help("Fail instead of warn when no GPU driver is found"),
+                )
+                .arg(
+                    Arg::new("min-free-space")
+                        .long("min-free-space")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1024")
+                        .help("Free space in MiB required for the model store cache"),
+                ),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check the configuration, the model directories and their artifacts before a deployment")
+                .args(server_args()),
+        )
+        .subcommand(
+            Command::new("infer")
+                .about("Send one inference request to a running server and print the outputs")
+                .args(client_args())
+                .arg(
+                    Arg::new("model")
+                        .long("model")
+                        .required(true)
+                        .help("Model to send the request to"),
+                )
+                .arg(
+                    Arg::new("version")
+                        .long("version")
+                        .help("Version of the model [default: the version the server picks]"),
+                )
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("V2 inference request (JSON) whose inputs are sent"),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Send inference requests to a running server and report throughput, latency and errors")
+                .args(client_args())
                 .arg(
-                    Arg::new("rest-host")
-                        .long("rest-host")
-                        .default_value("0.0.0.0")
-                        .help("REST server host"),
+                    Arg::new("model")
+                        .long("model")
+                        .required(true)
+                        .help("Model to send the requests to"),
                 )
                 .arg(
-                    Arg::new("rest-port")
-                        .long("rest-port")
-                        .default_value("8080")
-                        .help("REST server port"),
+                    Arg::new("version")
+                        .long("version")
+                        .help("Version of the model [default: the version the server picks]"),
                 )
                 .arg(
-                    Arg::new("grpc-host")
-                        .long("grpc-host")
-                        .default_value("0.0.0.0")
-                        .help("gRPC server host"),
+                    Arg::new("concurrency")
+                        .long("concurrency")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("8")
+                        .help("Requests in flight at any time"),
                 )
                 .arg(
-                    Arg::new("grpc-port")
-                        .long("grpc-port")
-                        .default_value("50051")
-                        .help("gRPC server port"),
+                    Arg::new("duration")
+                        .long("duration")
+                        .value_parser(|duration: &str| bench::parse_duration(duration))
+                        .default_value("30s")
+                        .help("How long to send requests for, e.g. 500ms, 60s or 5m"),
+                )
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("V2 inference request (JSON) whose inputs are sent [default: zeros shaped after the model's metadata]"),
+                ),
+        )
+        .subcommand(
+            Command::new("models")
+                .about("Inspect the models of a running server")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("List the models with their versions, backend, readiness and labels")
+                        .args(client_args())
+                        .arg(
+                            Arg::new("selector")
+                                .long("selector")
+                                .help("Only list the models matching this label selector, e.g. team=search,tier!=canary"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("describe")
+                        .about("Show the metadata of a model")
+                        .args(client_args())
+                        .arg(Arg::new("name").required(true).help("Model to describe"))
+                        .arg(
+                            Arg::new("version")
+                                .long("version")
+                                .help("Version to describe, the one answering unversioned requests by default"),
+                        ),
                 ),
         )
-        .get_matches();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let command = cli();
+    let args: Vec<OsString> = env::args_os().collect();
+    let matches = command
+        .clone()
+        .get_matches_from(layered_args(&command, args.clone())?);
 
     match matches.subcommand() {
         Some(("start", sub_matches)) => {
+            set_log_level(*sub_matches.get_one::<LogLevel>("log-level").unwrap());
+            let _pid_file = match sub_matches.get_one::<PathBuf>("pid-file") {
+                Some(path) => Some(PidFile::create(path)?),
+                None => None,
+            };
             println!("Starting servers...");
 
-            let context = InferenceServerConfig {
-                rest_hostname: sub_matches
-                    .get_one::<String>("rest-host")
-                    .unwrap()
-                    .to_string(),
-                rest_port: sub_matches
-                    .get_one::<String>("rest-port")
-                    .unwrap()
-                    .parse()?,
-                grpc_hostname: sub_matches
-                    .get_one::<String>("grpc-host")
-                    .unwrap()
-                    .to_string(),
-                grpc_port: sub_matches
-                    .get_one::<String>("grpc-port")
-                    .unwrap()
-                    .parse()?,
+            let analytics = match sub_matches.get_one::<String>("analytics-log") {
+                Some(spec) => Some(AnalyticsTee::spawn(
+                    foundation::analytics::open_sink(spec)?,
+                    1024,
+                )),
+                None => None,
             };
+
+            let audit = audit_logger(sub_matches)?;
+            let mut context = server_config(sub_matches, analytics, audit)?;
+            let routes_requests = context
+                .shared_port
+                .as_ref()
+                .is_some_and(|port| port.routing() == PortRouting::Request);
             let grpc_context = context.clone();
+            if let Some(jwt) = &context.jwt {
+                let keys = jwt.refresh().await?;
+                println!(
+                    "Loaded {} JWT signing keys from {}",
+                    keys,
+                    jwt.config().jwks_url
+                );
+                jwt.spawn_refresh(Duration::from_secs(
+                    *sub_matches.get_one::<u64>("jwks-refresh-interval").unwrap(),
+                ));
+            }
+
+            let version_policy: VersionPolicy = sub_matches
+                .get_one::<String>("version-policy")
+                .unwrap()
+                .parse()?;
+            // Request buffers start at 32 entries and are resized from observed load.
+            let mut model_manager = ModelDiscoveryService::new(32)
+                .with_buffer_sizing(buffer_sizing(sub_matches))
+                .with_circuit_policy(circuit_policy(sub_matches)?)
+                .with_version_policy(version_policy)
+                .with_id_provider(context.ids.clone())
+                .with_error_budget(Arc::new(
+                    ErrorBudget::default()
+                        .with_webhook(sub_matches.get_one::<String>("tenant-webhook").cloned()),
+                ))
+                .with_device_scheduler(Arc::new(DeviceScheduler::new(
+                    *sub_matches.get_one::<usize>("gpu-slots").unwrap(),
+                )))
+                .with_hint_policy(
+                    sub_matches
+                        .get_one::<String>("execution-hints")
+                        .unwrap()
+                        .parse::<HintPolicy>()?,
+                )
+                .with_watermarking(Arc::new(watermarking(sub_matches)?))
+                .with_memory_budget(MemoryBudget {
+                    gpu: sub_matches.get_one::<u64>("gpu-memory-budget").copied(),
+                    cpu: sub_matches.get_one::<u64>("cpu-memory-budget").copied(),
+                });
+            if let Some(url) = sub_matches.get_one::<String>("response-cache-redis") {
+                model_manager = model_manager
+                    .with_response_cache(Arc::new(ResponseCache::default().with_redis(url)?));
+            }
+            if let Some(dir) = sub_matches.get_one::<String>("model-store-dir") {
+                model_manager = model_manager.with_model_store(dir);
+            }
+            let model_manager = Arc::new(model_manager);
+            if let Some(webhook) = event_webhook(sub_matches)? {
+                model_manager.events().subscribe(Arc::new(webhook));
+            }
+            let idle_unload = sub_matches.get_one::<u64>("model-idle-unload").map(|secs| {
+                model_manager
+                    .memory()
+                    .spawn_idle_unload(Duration::from_secs(*secs))
+            });
+            let config_reload = Arc::new(config_reload(
+                command,
+                args,
+                sub_matches.clone(),
+                &context,
+                model_manager.clone(),
+            ));
+            config_reload.reload_on_sighup()?;
+            let config_watcher = config_path(sub_matches)
+                .map(|path| config_reload.watch(path, CONFIG_WATCH_INTERVAL));
+            context.config_reload = Some(config_reload);
+            let models_dir = models_dir(sub_matches)
+                .ok_or("Set the models directory with --models-dir, models-dir in --config, GALEMIND_MODELS_DIR or MODELS_DIR")?;
+            // The servers listen while models load, unready until they are.
+            let readiness = model_manager.readiness().clone();
+            readiness.wait_for(MODELS_LOADING, "Models are loading");
 
-            // Instantiate Model Manager with CircularBuffer capacity of 32 for each model ID
-            // TODO: Calculate optimal value or pass dynamically models_buffer_capacity !
-            let model_manager = Arc::new(ModelDiscoveryService::new(32));
-            model_manager.load_models_from_dir(
-                env::var("MODELS_DIR").expect("MODELS_DIR environment variable must be set!"),
-            )?;
+            let mut sources = model_sources(sub_matches)?;
+            let mlflow_model = sub_matches.get_one::<String>("mlflow-model").cloned();
+            let mut mlflow_watcher = None;
+            if let Some(base_url) = sub_matches.get_one::<String>("mlflow-uri") {
+                let api_token = env::var("MLFLOW_TRACKING_TOKEN").ok();
+                match sub_matches.get_one::<String>("mlflow-stage") {
+                    Some(stage) => {
+                        let watcher = MLFlowStageWatcher::new(
+                            Arc::new(MLFlowClient::new(base_url.to_string(), api_token)),
+                            model_manager.clone(),
+                            stage,
+                        )
+                        .with_model_name(mlflow_model)
+                        .with_poll_interval(Duration::from_secs(
+                            *sub_matches.get_one::<u64>("mlflow-poll-interval").unwrap(),
+                        ))
+                        .with_webhook(sub_matches.get_one::<String>("mlflow-webhook").cloned());
+                        mlflow_watcher = Some(watcher.spawn());
+                    }
+                    None => sources.push(ModelSource::MLFlow {
+                        base_url: base_url.to_string(),
+                        api_token,
+                        model_name: mlflow_model,
+                    }),
+                }
+            }
 
             // Load contexts for REST and gRPC servers
-            let rest_server = RestServerBuilder::configure(context, model_manager.clone());
+            let mut rest_server = RestServerBuilder::configure(context, model_manager.clone());
             let grpc_server = GrpcServerBuilder::configure(grpc_context, model_manager.clone());
 
             // Start REST and gRPC servers
-            let rest_handler = tokio::spawn(async move { rest_server.start().await });
-            let grpc_handler = tokio::spawn(async move { grpc_server.start().await });
+            let shutdown = Shutdown::new();
+            let rest_signal = shutdown.signal();
+            let grpc_signal = shutdown.signal();
+            // On a port routed by request, the REST server serves the gRPC services itself.
+            let grpc_server = if routes_requests {
+                let grpc = grpc_server
+                    .into_router(grpc_signal.clone())
+                    .map_err(|e| e as Box<dyn Error>)?;
+                rest_server = rest_server.with_grpc(grpc);
+                None
+            } else {
+                Some(grpc_server)
+            };
+            let rest_handler = tokio::spawn(async move { rest_server.start(rest_signal).await });
+            let grpc_handler = tokio::spawn(async move {
+                match grpc_server {
+                    Some(grpc_server) => grpc_server.start(grpc_signal).await,
+                    None => Ok(()),
+                }
+            });
+            let servers = async { tokio::join!(rest_handler, grpc_handler) };
+            tokio::pin!(servers);
+
+            model_manager.load_models_from_dir(models_dir)?;
+            if !sources.is_empty() {
+                let models = model_manager.discover_models(sources).await?;
+                println!("Discovered {} models from model sources", models.len());
+            }
+            if sub_matches.get_flag("read-only") {
+                model_manager.set_read_only(true);
+                println!("Model registry is read-only");
+            }
+            readiness.complete(MODELS_LOADING);
+            for (_, reason) in model_manager.readiness_failures() {
+                eprintln!("Not ready: {}", reason);
+            }
+            notify_systemd(&format!(
+                "READY=1\nSTATUS=Serving {} models",
+                model_manager.get_models().len()
+            ));
 
-            let (rest_result, grpc_result) = tokio::join!(rest_handler, grpc_handler);
+            let drain_timeout =
+                Duration::from_secs(*sub_matches.get_one::<u64>("drain-timeout").unwrap());
+            let mut deadline = None;
+            let (rest_result, grpc_result) = tokio::select! {
+                results = &mut servers => results,
+                signal = termination() => {
+                    println!(
+                        "Received {}, draining requests for up to {} s",
+                        signal?,
+                        drain_timeout.as_secs()
+                    );
+                    readiness.wait_for(SHUTTING_DOWN, "Server is shutting down");
+                    notify_systemd("STOPPING=1\nSTATUS=Draining requests");
+                    deadline = Some(shutdown.trigger(drain_timeout));
+                    servers.await
+                }
+            };
+            if let Some(watcher) = mlflow_watcher {
+                watcher.abort();
+            }
+            if let Some(watcher) = config_watcher {
+                watcher.abort();
+            }
+            if let Some(idle_unload) = idle_unload {
+                idle_unload.abort();
+            }
+            // Background inferences (asynchronous and streamed) outlive their connections.
+            if let Some(deadline) = deadline
+                && !model_manager.drain(deadline).await
+            {
+                eprintln!(
+                    "{} requests were still queued or running at the drain deadline",
+                    model_manager.pending_requests()
+                );
+            }
+            println!("Unloaded {} model versions", model_manager.unload_all());
 
             // Check REST server result
             match rest_result {
@@ -95,9 +396,1285 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 Err(e) => eprintln!("gRPC task panicked: {}", e),
             }
         }
+        Some(("doctor", sub_matches)) => {
+            let mut sources = model_sources(sub_matches)?;
+            if let Some(base_url) = sub_matches.get_one::<String>("mlflow-uri") {
+                sources.push(ModelSource::MLFlow {
+                    base_url: base_url.to_string(),
+                    api_token: env::var("MLFLOW_TRACKING_TOKEN").ok(),
+                    model_name: sub_matches.get_one::<String>("mlflow-model").cloned(),
+                });
+            }
+            let model_store_dir = match sub_matches.get_one::<String>("model-store-dir") {
+                Some(dir) => PathBuf::from(dir),
+                None => ModelDiscoveryService::new(0)
+                    .model_store()
+                    .root()
+                    .to_path_buf(),
+            };
+
+            let report = Preflight::new(server_config(sub_matches, None, None)?)
+                .with_models_dir(models_dir(sub_matches))
+                .with_sources(sources)
+                .with_model_store(
+                    model_store_dir,
+                    sub_matches.get_one::<u64>("min-free-space").unwrap() * 1024 * 1024,
+                )
+                .with_require_gpu(sub_matches.get_flag("require-gpu"))
+                .run()
+                .await;
+            println!("{}", report);
+            if !report.passed() {
+                return Err("Preflight checks failed".into());
+            }
+        }
+        Some(("validate", sub_matches)) => {
+            let models_dir = models_dir(sub_matches)
+                .ok_or("Set the models directory with --models-dir, models-dir in --config, GALEMIND_MODELS_DIR or MODELS_DIR")?;
+            let version_policy: VersionPolicy = sub_matches
+                .get_one::<String>("version-policy")
+                .unwrap()
+                .parse()?;
+            let report = Preflight::new(server_config(sub_matches, None, None)?)
+                .with_models_dir(Some(models_dir))
+                .with_artifact_loading(
+                    ModelDiscoveryService::new(0).runtime_registry().clone(),
+                    version_policy,
+                )
+                .validate();
+            println!("{}", report);
+            if !report.passed() {
+                return Err("Validation failed".into());
+            }
+        }
+        Some(("models", sub_matches)) => match sub_matches.subcommand() {
+            Some(("list", list_matches)) => {
+                let models = server_client(list_matches)
+                    .await?
+                    .models(
+                        list_matches
+                            .get_one::<String>("selector")
+                            .map(String::as_str),
+                    )
+                    .await
+                    .map_err(|e| format!("{:#}", e))?;
+                if list_matches.get_flag("json") {
+                    println!("{}", serde_json::to_string_pretty(&models)?);
+                } else {
+                    print!("{}", models::format_list(&models));
+                }
+            }
+            Some(("describe", describe_matches)) => {
+                let model = server_client(describe_matches)
+                    .await?
+                    .describe(
+                        describe_matches.get_one::<String>("name").unwrap(),
+                        describe_matches
+                            .get_one::<String>("version")
+                            .map(String::as_str),
+                    )
+                    .await
+                    .map_err(|e| format!("{:#}", e))?;
+                if describe_matches.get_flag("json") {
+                    println!("{}", serde_json::to_string_pretty(&model)?);
+                } else {
+                    print!("{}", models::format_description(&model));
+                }
+            }
+            _ => unreachable!("a models subcommand is required"),
+        },
+        Some(("infer", sub_matches)) => {
+            let client = server_client(sub_matches).await?;
+            let model = sub_matches.get_one::<String>("model").unwrap();
+            let inputs = client::read_inputs(sub_matches.get_one::<PathBuf>("input").unwrap())
+                .map_err(|e| format!("{:#}", e))?;
+            let sent = std::time::Instant::now();
+            let outputs = client
+                .infer(
+                    model,
+                    sub_matches.get_one::<String>("version").map(String::as_str),
+                    &inputs,
+                )
+                .await
+                .map_err(|e| format!("{:#}", e))?;
+            if sub_matches.get_flag("json") {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({ "outputs": outputs }))?
+                );
+            } else {
+                print!("{}", infer::format_outputs(model, &outputs, sent.elapsed()));
+            }
+        }
+        Some(("bench", sub_matches)) => {
+            let client = server_client(sub_matches).await?;
+            let model = sub_matches.get_one::<String>("model").unwrap().clone();
+            let version = sub_matches.get_one::<String>("version").cloned();
+            let inputs = match sub_matches.get_one::<PathBuf>("input") {
+                Some(path) => client::read_inputs(path),
+                None => client
+                    .describe(&model, version.as_deref())
+                    .await
+                    .map(|model| model.inputs.iter().map(Tensor::zeros).collect()),
+            }
+            .map_err(|e| format!("{:#}", e))?;
+            let plan = bench::BenchPlan {
+                model,
+                version,
+                inputs,
+                concurrency: *sub_matches.get_one::<u32>("concurrency").unwrap() as usize,
+                duration: *sub_matches.get_one::<Duration>("duration").unwrap(),
+            };
+            eprintln!(
+                "Benchmarking {} with {} clients for {:?}",
+                plan.model, plan.concurrency, plan.duration
+            );
+            let report = bench::run(client, plan).await?;
+            if sub_matches.get_flag("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", bench::format_report(&report));
+            }
+        }
         _ => {
             println!("Use --help for usage.");
         }
     }
     Ok(())
 }
+
+/// Sends `state` to systemd when running under a `Type=notify` unit.
+fn notify_systemd(state: &str) {
+    if let Err(e) = sd_notify(state) {
+        eprintln!("{:#}", e);
+    }
+}
+
+/// Options of the commands talking to a running server.
+fn client_args() -> Vec<Arg> {
+    vec![
+        Arg::new("server")
+            .long("server")
+            .help("URL of the server [default: http://localhost:8080, or http://localhost:50051 with --grpc]"),
+        Arg::new("grpc")
+            .long("grpc")
+            .action(ArgAction::SetTrue)
+            .help("Talk to the gRPC server instead of the REST one"),
+        Arg::new("api-key")
+            .long("api-key")
+            .help("API key or token sent to the server [env: GALEMIND_API_KEY]"),
+        Arg::new("json")
+            .long("json")
+            .action(ArgAction::SetTrue)
+            .help("Print JSON instead of text"),
+    ]
+}
+
+/// A client of the server given by the `client_args` of `matches`.
+async fn server_client(matches: &ArgMatches) -> Result<ServerClient, Box<dyn Error>> {
+    let api_key = matches
+        .get_one::<String>("api-key")
+        .cloned()
+        .or_else(|| env::var(API_KEY_ENV).ok());
+    Ok(ServerClient::connect(
+        matches.get_one::<String>("server").map(String::as_str),
+        matches.get_flag("grpc"),
+        api_key,
+    )
+    .await
+    .map_err(|e| format!("{:#}", e))?)
+}
+
+/// The command line `args`, followed by the options it does not give taken from the
+/// environment (`GALEMIND_<OPTION>`), then from the configuration file (`--config` or
+/// `GALEMIND_CONFIG`). Layered options are passed as if given on the command line, so they
+/// are checked as such when the arguments are matched.
+fn layered_args(
+    command: &Command,
+    mut args: Vec<OsString>,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let matches = command.clone().get_matches_from(&args);
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(args);
+    };
+    let subcommand = command
+        .find_subcommand(name)
+        .expect("the subcommand was matched");
+    // Commands talking to a running server take no configuration file.
+    if !subcommand
+        .get_arguments()
+        .any(|arg| arg.get_id() == "config")
+    {
+        return Ok(args);
+    }
+    let file = match config_path(sub_matches) {
+        Some(path) => Settings::load(path).map_err(|e| format!("{:#}", e))?,
+        None => Settings::default(),
+    };
+    let environment = Settings::from_env(env::vars());
+    // One file serves every subcommand.
+    let known: HashSet<&str> = command
+        .get_subcommands()
+        .flat_map(Command::get_arguments)
+        .map(|arg| arg.get_id().as_str())
+        .collect();
+    if let Some(unknown) = file.names().find(|option| !known.contains(option)) {
+        return Err(format!("Unknown option '{}' in the configuration file", unknown).into());
+    }
+    for arg in subcommand.get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == "config" || sub_matches.value_source(id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let repeatable = matches!(arg.get_action(), ArgAction::Append);
+        let values = match (environment.get(id), file.get(id)) {
+            (Some(values), _) if repeatable => Settings::split_list(values),
+            (Some(values), _) | (None, Some(values)) => values.to_vec(),
+            (None, None) => continue,
+        };
+        match (arg.get_action(), values.as_slice()) {
+            (ArgAction::SetTrue, [flag]) if flag == "true" => args.push(format!("--{}", id).into()),
+            (ArgAction::SetTrue, [flag]) if flag == "false" => {}
+            (ArgAction::SetTrue, _) => {
+                return Err(format!("Option '{}' takes true or false", id).into());
+            }
+            (_, [_]) => args.push(format!("--{}={}", id, values[0]).into()),
+            _ if repeatable => args.extend(
+                values
+                    .iter()
+                    .map(|value| format!("--{}={}", id, value).into()),
+            ),
+            _ => return Err(format!("Option '{}' takes a single value", id).into()),
+        }
+    }
+    Ok(args)
+}
+
+/// The configuration file given with `--config` or `GALEMIND_CONFIG`, if any.
+fn config_path(matches: &ArgMatches) -> Option<PathBuf> {
+    matches
+        .get_one::<String>("config")
+        .cloned()
+        .or_else(|| env::var(CONFIG_ENV).ok())
+        .map(PathBuf::from)
+}
+
+/// Options of `start` applied again when the configuration is reloaded; the others keep
+/// their values until a restart.
+const RELOADABLE: &[&str] = &[
+    "client-rate-limit",
+    "model-rate-limit",
+    "model-rate-limit-for",
+    "max-in-flight",
+    "model-max-in-flight",
+    "model-max-in-flight-for",
+    "quota-window",
+    "quota",
+    "quota-for",
+    "buffer-min-capacity",
+    "buffer-max-capacity",
+    "starvation-limit",
+    "log-level",
+];
+
+/// Options whose values the admin API does not show.
+const SECRET: &[&str] = &["api-keys", "watermark-key", "response-cache-redis"];
+
+/// How often the configuration file is checked for changes.
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reloads the `RELOADABLE` options of `start` from its command line `args`, the environment
+/// and the configuration file, together with the configurations of the models. Other options
+/// that differ from those the server `started` with are reported as requiring a restart.
+fn config_reload(
+    command: Command,
+    args: Vec<OsString>,
+    started: ArgMatches,
+    context: &InferenceServerConfig,
+    model_manager: Arc<ModelDiscoveryService>,
+) -> ConfigReload {
+    let rate_limiter = context.rate_limiter.clone();
+    let concurrency = context.concurrency.clone();
+    let quotas = context.quotas.clone();
+    let running = Arc::new(Mutex::new(started.clone()));
+    let shown = running.clone();
+    let options: Vec<String> = command
+        .find_subcommand("start")
+        .expect("start is a subcommand")
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    let invalid = |e: Box<dyn Error>| anyhow!("{:#}", e);
+    ConfigReload::new(move || {
+        let matches = command
+            .clone()
+            .try_get_matches_from(layered_args(&command, args.clone()).map_err(invalid)?)?;
+        let (_, matches) = matches.subcommand().expect("the server was started");
+        // Every value is checked before any is applied.
+        let rate_limits = rate_limits(matches).map_err(invalid)?;
+        let concurrency_limits = concurrency_limits(matches).map_err(invalid)?;
+        let quota_limits = quota_limits(matches).map_err(invalid)?;
+        let models = model_manager.reload_model_configs()?;
+
+        let mut running = running.lock().unwrap();
+        let values = |matches: &ArgMatches, id: &str| -> Option<Vec<OsString>> {
+            matches
+                .get_raw(id)
+                .map(|values| values.map(OsString::from).collect())
+        };
+        let mut report = ReloadReport {
+            models: models.into_iter().map(|model_id| model_id.0).collect(),
+            ..ReloadReport::default()
+        };
+        let start = command
+            .find_subcommand("start")
+            .expect("start is a subcommand");
+        for id in start.get_arguments().map(|arg| arg.get_id().as_str()) {
+            if RELOADABLE.contains(&id) {
+                if values(&running, id) != values(matches, id) {
+                    report.applied.push(id.to_string());
+                }
+            } else if values(&started, id) != values(matches, id) {
+                report.restart_required.push(id.to_string());
+            }
+        }
+        rate_limiter.set_limits(rate_limits);
+        concurrency.set_limits(concurrency_limits);
+        quotas.set_limits(quota_limits);
+        model_manager.set_buffer_sizing(buffer_sizing(matches));
+        set_log_level(*matches.get_one::<LogLevel>("log-level").unwrap());
+        *running = matches.clone();
+        Ok(report)
+    })
+    .with_view(move || {
+        let running = shown.lock().unwrap();
+        let mut settings = Settings::default();
+        for id in &options {
+            let Some(values) = running.get_raw(id) else {
+                continue;
+            };
+            let values = values
+                .map(|value| {
+                    if SECRET.contains(&id.as_str()) {
+                        "<redacted>".to_string()
+                    } else {
+                        value.to_string_lossy().into_owned()
+                    }
+                })
+                .collect();
+            settings.insert(id, values);
+        }
+        settings
+    })
+}
+
+/// The directory of the models to load, given as an option or, as it used to be, as the
+/// `MODELS_DIR` environment variable.
+fn models_dir(matches: &ArgMatches) -> Option<PathBuf> {
+    matches
+        .get_one::<String>("models-dir")
+        .cloned()
+        .or_else(|| env::var("MODELS_DIR").ok())
+        .map(PathBuf::from)
+}
+
+/// Server options shared by `start` and `doctor`.
+fn server_args() -> Vec<Arg> {
+    vec![
+            Arg::new("config")
+                .long("config")
+                .help("YAML file of options, overridden by GALEMIND_<OPTION> variables and the command line; reloaded when it changes [env: GALEMIND_CONFIG]"),
+            Arg::new("models-dir")
+                .long("models-dir")
+                .help("Directory of the models to load [env: MODELS_DIR]"),
+            Arg::new("rest-host")
+                .long("rest-host")
+                .default_value("0.0.0.0")
+                .help("REST server host"),
+            Arg::new("rest-port")
+                .long("rest-port")
+                .default_value("8080")
+                .help("REST server port"),
+            Arg::new("grpc-host")
+                .long("grpc-host")
+                .default_value("0.0.0.0")
+                .help("gRPC server host"),
+            Arg::new("grpc-port")
+                .long("grpc-port")
+                .default_value("50051")
+                .help("gRPC server port"),
+            Arg::new("port")
+                .long("port")
+                .value_parser(clap::value_parser!(u16))
+                .conflicts_with_all(["rest-port", "grpc-host", "grpc-port"])
+                .help("Serve REST and gRPC on this single port of --rest-host instead of their own"),
+            Arg::new("port-routing")
+                .long("port-routing")
+                .value_parser(|routing: &str| routing.parse::<PortRouting>())
+                .requires("port")
+                .help("How --port tells REST and gRPC apart: connection, by the protocol each connection opens with, or request, by the content type of each request [default: connection]"),
+            Arg::new("admin-port")
+                .long("admin-port")
+                .value_parser(clap::value_parser!(u16))
+                .help("Serve the admin API on this port of --admin-host only, instead of the REST port"),
+            Arg::new("admin-host")
+                .long("admin-host")
+                .default_value("127.0.0.1")
+                .help("Host of the admin API listener, when it has its own --admin-port"),
+            Arg::new("log-level")
+                .long("log-level")
+                .value_parser(|level: &str| level.parse::<LogLevel>())
+                .default_value("info")
+                .help("Messages written: warn, info (adds server events) or debug (adds each request); changed at runtime by the admin API"),
+            Arg::new("version-policy")
+                .long("version-policy")
+                .default_value("latest")
+                .help("Model versions to serve: latest, all or specific:<v1>,<v2>"),
+            Arg::new("buffer-min-capacity")
+                .long("buffer-min-capacity")
+                .value_parser(clap::value_parser!(usize))
+                .help("Smallest request buffer per model when sizing from observed load [default: 8]"),
+            Arg::new("buffer-max-capacity")
+                .long("buffer-max-capacity")
+                .value_parser(clap::value_parser!(usize))
+                .help("Largest request buffer per model when sizing from observed load [default: 4096]"),
+            Arg::new("starvation-limit")
+                .long("starvation-limit")
+                .value_parser(clap::value_parser!(u64))
+                .help("Milliseconds a buffered request waits before it is dispatched ahead of higher priorities [default: 1000]"),
+            Arg::new("id-scheme")
+                .long("id-scheme")
+                .default_value("uuidv7")
+                .help("Ids generated for requests and jobs: uuidv7, ulid or snowflake[:node]"),
+            Arg::new("max-concurrent-streams")
+                .long("max-concurrent-streams")
+                .value_parser(clap::value_parser!(u32))
+                .help("Maximum concurrent HTTP/2 streams per connection [default: 128]"),
+            Arg::new("header-read-timeout")
+                .long("header-read-timeout")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds a client has to send its request headers [default: 10]"),
+            Arg::new("idle-timeout")
+                .long("idle-timeout")
+                .value_parser(clap::value_parser!(u64))
+                .help(
+                    "Seconds without traffic before a connection is closed [default: 300]",
+                ),
+            Arg::new("overload-capacity")
+                .long("overload-capacity")
+                .value_parser(clap::value_parser!(usize))
+                .help("In-flight requests considered full saturation; degraded mode starts at 90% [default: 1024]"),
+            Arg::new("cors-origin")
+                .long("cors-origin")
+                .action(ArgAction::Append)
+                .help("Browser origin allowed to make gRPC-Web calls; repeat for several [default: any]"),
+            Arg::new("rest-cors-origin")
+                .long("rest-cors-origin")
+                .action(ArgAction::Append)
+                .help("Browser origin allowed to call the REST API, or * for any; repeat for several [default: none]"),
+            Arg::new("rest-cors-method")
+                .long("rest-cors-method")
+                .action(ArgAction::Append)
+                .help("Method browsers may use on the REST API; repeat for several [default: GET, POST, PUT, DELETE]"),
+            Arg::new("rest-cors-header")
+                .long("rest-cors-header")
+                .action(ArgAction::Append)
+                .help("Request header browsers may send to the REST API besides its own; repeat for several"),
+            Arg::new("rest-cors-credentials")
+                .long("rest-cors-credentials")
+                .action(ArgAction::SetTrue)
+                .help("Let browsers send credentials to the REST API; needs listed origins"),
+            Arg::new("rest-cors-max-age")
+                .long("rest-cors-max-age")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds browsers may cache a REST preflight response [default: 3600]"),
+            Arg::new("client-rate-limit")
+                .long("client-rate-limit")
+                .help("Inference rate per API key, or per IP without one, e.g. 100/s or 6000/m:200"),
+            Arg::new("model-rate-limit")
+                .long("model-rate-limit")
+                .help("Inference rate per model, e.g. 500/s"),
+            Arg::new("model-rate-limit-for")
+                .long("model-rate-limit-for")
+                .action(ArgAction::Append)
+                .help("Inference rate of one model, as <model>=<limit>; repeat for several"),
+            Arg::new("max-in-flight")
+                .long("max-in-flight")
+                .value_parser(clap::value_parser!(usize))
+                .help("Inferences served at once across all models; excess requests are shed"),
+            Arg::new("model-max-in-flight")
+                .long("model-max-in-flight")
+                .value_parser(clap::value_parser!(usize))
+                .help("Inferences served at once per model; excess requests are shed"),
+            Arg::new("model-max-in-flight-for")
+                .long("model-max-in-flight-for")
+                .action(ArgAction::Append)
+                .help("Inferences served at once by one model, as <model>=<count>; repeat for several"),
+            Arg::new("max-request-bytes")
+                .long("max-request-bytes")
+                .help("Largest request body after decompression, e.g. 16MiB (default 2MiB); larger requests are refused with 413 before being parsed"),
+            Arg::new("tenant-daily-bytes")
+                .long("tenant-daily-bytes")
+                .help("Bytes a tenant may send and receive per UTC day, e.g. 10GB"),
+            Arg::new("tenant-daily-bytes-for")
+                .long("tenant-daily-bytes-for")
+                .action(ArgAction::Append)
+                .help("Daily bytes of one tenant, as <tenant>=<size>; repeat for several"),
+            Arg::new("stream-buffer")
+                .long("stream-buffer")
+                .default_value("16")
+                .value_parser(clap::value_parser!(usize))
+                .help("Responses of a stream produced ahead of what its client has read; a slower client slows the stream down"),
+            Arg::new("stream-stall-timeout")
+                .long("stream-stall-timeout")
+                .default_value("60")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds a stream waits for a client that reads nothing before ending"),
+            Arg::new("quota-window")
+                .long("quota-window")
+                .default_value("1d")
+                .help("Window over which quotas are counted, e.g. 1h or 1d; windows start at multiples of it since the Unix epoch"),
+            Arg::new("quota")
+                .long("quota")
+                .help("Requests and tokens an API key, token subject or tenant may use per window, as <requests>[:<tokens>] with - for no limit, e.g. 1000:500000"),
+            Arg::new("quota-for")
+                .long("quota-for")
+                .action(ArgAction::Append)
+                .help("Quota of one account, as <account>=<requests>[:<tokens>]; repeat for several"),
+            Arg::new("analytics-log")
+                .long("analytics-log")
+                .help("Copy of streamed inference responses: a JSONL file path or kafka://<rest-proxy-host:port>/<topic>"),
+            Arg::new("audit-log")
+                .long("audit-log")
+                .help("Audit log of inference requests: a JSONL file path, stdout or kafka://<rest-proxy-host:port>/<topic>"),
+            Arg::new("kafka-proxy")
+                .long("kafka-proxy")
+                .requires_all(["kafka-input-topic", "kafka-output-topic"])
+                .help("Kafka REST proxy URL, e.g. http://localhost:8082: also consume inference requests from Kafka"),
+            Arg::new("kafka-input-topic")
+                .long("kafka-input-topic")
+                .help("Kafka topic inference requests are consumed from"),
+            Arg::new("kafka-output-topic")
+                .long("kafka-output-topic")
+                .help("Kafka topic results are produced to, keyed as their requests"),
+            Arg::new("kafka-group")
+                .long("kafka-group")
+                .default_value("galemind")
+                .help("Kafka consumer group of the servers sharing the input topic"),
+            Arg::new("kafka-format")
+                .long("kafka-format")
+                .default_value("v2")
+                .help("Encoding of Kafka messages: v2 (KServe V2 JSON naming its model), json or postcard"),
+            Arg::new("result-store")
+                .long("result-store")
+                .default_value("memory")
+                .help("Where results of asynchronous inferences are kept: memory, or redis://[[user]:password@]host[:port][/db] to share them between replicas"),
+            Arg::new("batch-dir")
+                .long("batch-dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Directory batch prediction jobs are checkpointed in; enables the batch job API"),
+            Arg::new("audit-rotate-size")
+                .long("audit-rotate-size")
+                .default_value("100MiB")
+                .help("Size at which the audit log file is rotated"),
+            Arg::new("audit-keep-files")
+                .long("audit-keep-files")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5")
+                .help("Rotated audit log files kept"),
+            Arg::new("audit-sample-rate")
+                .long("audit-sample-rate")
+                .default_value("1")
+                .help("Share of inference requests recorded in the audit log, between 0 and 1"),
+            Arg::new("audit-sample-rate-for")
+                .long("audit-sample-rate-for")
+                .action(ArgAction::Append)
+                .help("Audit sampling rate of one model, as <model>=<rate>; repeat for several"),
+            Arg::new("tls-cert")
+                .long("tls-cert")
+                .requires("tls-key")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("PEM certificate chain; both servers only accept TLS connections when set, and reload it on SIGHUP"),
+            Arg::new("tls-key")
+                .long("tls-key")
+                .requires("tls-cert")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("PEM private key of --tls-cert"),
+            Arg::new("tls-client-ca")
+                .long("tls-client-ca")
+                .requires("tls-cert")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("PEM CA certificates; clients must present a certificate signed by one of them"),
+            Arg::new("api-keys")
+                .long("api-keys")
+                .help("API keys required by both servers: a YAML key file, or env:<VARIABLE> holding <name>=<key>[:<models>] entries separated by ';'"),
+            Arg::new("jwks-url")
+                .long("jwks-url")
+                .help("JWKS endpoint of an OIDC provider; both servers then accept JWTs signed by its keys and enforce their roles"),
+            Arg::new("jwks-refresh-interval")
+                .long("jwks-refresh-interval")
+                .requires("jwks-url")
+                .default_value("300")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds between two fetches of the JWKS"),
+            Arg::new("jwt-issuer")
+                .long("jwt-issuer")
+                .requires("jwks-url")
+                .help("Issuer (iss claim) JWTs must have"),
+            Arg::new("jwt-audience")
+                .long("jwt-audience")
+                .requires("jwks-url")
+                .help("Audience (aud claim) JWTs must have"),
+            Arg::new("jwt-roles-claim")
+                .long("jwt-roles-claim")
+                .requires("jwks-url")
+                .default_value("roles")
+                .help("Claim listing the roles of a JWT, as a dotted path such as realm_access.roles"),
+            Arg::new("jwt-role")
+                .long("jwt-role")
+                .requires("jwks-url")
+                .action(ArgAction::Append)
+                .help("Maps a value of the roles claim to a role, as <value>=<admin|infer|read-only>; repeat for several"),
+            Arg::new("model-source")
+                .long("model-source")
+                .action(ArgAction::Append)
+                .help("Additional model repository: s3://, gs://, az:// URI or local path"),
+            Arg::new("mlflow-uri")
+                .long("mlflow-uri")
+                .help("MLflow tracking server whose registered models are served"),
+            Arg::new("mlflow-model")
+                .long("mlflow-model")
+                .requires("mlflow-uri")
+                .help("Serve only this registered MLflow model"),
+            Arg::new("mlflow-stage")
+                .long("mlflow-stage")
+                .requires("mlflow-uri")
+                .help("Keep serving the versions in this stage (e.g. Production), polling for changes"),
+            Arg::new("mlflow-poll-interval")
+                .long("mlflow-poll-interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("60")
+                .help("Seconds between MLflow registry polls in stage mode"),
+            Arg::new("mlflow-webhook")
+                .long("mlflow-webhook")
+                .requires("mlflow-stage")
+                .help("URL notified with a JSON POST on every stage deployment or retirement"),
+            Arg::new("model-store-dir")
+                .long("model-store-dir")
+                .help("Local cache for models pulled from object storage"),
+            Arg::new("gpu-slots")
+                .long("gpu-slots")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("4")
+                .help("Calls in flight at once on each GPU, shared by the models pinned to it"),
+            Arg::new("gpu-memory-budget")
+                .long("gpu-memory-budget")
+                .value_parser(|size: &str| parse_byte_size(size))
+                .help("Memory the model versions loaded onto each GPU may take, e.g. 20GiB; they then load on their first request, unloading the least recently used ones to make room"),
+            Arg::new("cpu-memory-budget")
+                .long("cpu-memory-budget")
+                .value_parser(|size: &str| parse_byte_size(size))
+                .help("Memory the model versions loaded onto the CPU may take, e.g. 64GiB, managed like --gpu-memory-budget"),
+            Arg::new("circuit-error-rate")
+                .long("circuit-error-rate")
+                .help("Error rate (0-1] of a model's runtime at which its circuit opens: the model is reported not ready and its requests fail fast until a probe succeeds"),
+            Arg::new("circuit-window")
+                .long("circuit-window")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .help("Seconds of requests the error rate of a circuit is computed over"),
+            Arg::new("circuit-min-requests")
+                .long("circuit-min-requests")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .help("Requests a model runs in the window before its circuit may open"),
+            Arg::new("circuit-probe-interval")
+                .long("circuit-probe-interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .help("Seconds between two requests let through an open circuit to probe the model"),
+            Arg::new("model-idle-unload")
+                .long("model-idle-unload")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds without requests after which a version loaded within a memory budget is unloaded"),
+            Arg::new("execution-hints")
+                .long("execution-hints")
+                .default_value("no_batching,device")
+                .help("Execution hints of requests that are honored: no_batching, device and instance, comma-separated, or none"),
+            Arg::new("trust-tenant-header")
+                .long("trust-tenant-header")
+                .action(ArgAction::SetTrue)
+                .help("Take the tenant of requests from the x-galemind-tenant header, set by a trusted proxy, instead of their authenticated account"),
+            Arg::new("tenant-webhook")
+                .long("tenant-webhook")
+                .help("URL notified with a JSON POST when a tenant is throttled, quarantined or restored"),
+            Arg::new("event-webhook")
+                .long("event-webhook")
+                .help("URL every server event of the kinds of --event-webhook-events is POSTed to as JSON"),
+            Arg::new("event-webhook-events")
+                .long("event-webhook-events")
+                .default_value("model_loaded,model_unloaded,model_paused,model_resumed,circuit_opened,circuit_closed,version_loaded,version_unloaded,request_rejected,auth_failure")
+                .help("Server events posted to --event-webhook, comma-separated, among those and batch_executed"),
+            Arg::new("watermark-key")
+                .long("watermark-key")
+                .help("Key of the watermarks of generated text: env:<VARIABLE> or the path of a key file"),
+            Arg::new("watermark-for")
+                .long("watermark-for")
+                .action(ArgAction::Append)
+                .help("Watermark the outputs of a tenant, as <tenant>=on|off; repeat for several"),
+            Arg::new("response-cache-redis")
+                .long("response-cache-redis")
+                .help("Keep the cached responses of models in this Redis server, redis://[[user]:password@]host[:port][/db], instead of memory"),
+    ]
+}
+
+/// The observer posting server events to `--event-webhook`, if any. Must be called from
+/// within the tokio runtime.
+fn event_webhook(matches: &ArgMatches) -> Result<Option<WebhookObserver>, Box<dyn Error>> {
+    let Some(url) = matches.get_one::<String>("event-webhook") else {
+        return Ok(None);
+    };
+    let kinds =
+        ServerEvent::parse_kinds(matches.get_one::<String>("event-webhook-events").unwrap())?;
+    Ok(Some(WebhookObserver::spawn(
+        url,
+        kinds,
+        EVENT_QUEUE_CAPACITY,
+    )?))
+}
+
+/// The audit logger configured with `--audit-log`, if any. Must be called from within the
+/// tokio runtime.
+fn audit_logger(matches: &ArgMatches) -> Result<Option<AuditLogger>, Box<dyn Error>> {
+    let Some(spec) = matches.get_one::<String>("audit-log") else {
+        return Ok(None);
+    };
+    let mut sampling = AuditSampling {
+        rate: AuditSampling::parse_rate(matches.get_one::<String>("audit-sample-rate").unwrap())?,
+        ..AuditSampling::default()
+    };
+    for entry in matches
+        .get_many::<String>("audit-sample-rate-for")
+        .unwrap_or_default()
+    {
+        let (model, rate) = entry.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid audit sampling rate '{}', expected <model>=<rate>",
+                entry
+            )
+        })?;
+        sampling
+            .models
+            .insert(model.to_string(), AuditSampling::parse_rate(rate)?);
+    }
+    let sink = foundation::audit::open_sink(
+        spec,
+        parse_byte_size(matches.get_one::<String>("audit-rotate-size").unwrap())?,
+        *matches.get_one::<usize>("audit-keep-files").unwrap(),
+    )?;
+    Ok(Some(AuditLogger::spawn(
+        sink,
+        sampling,
+        AUDIT_QUEUE_CAPACITY,
+    )))
+}
+
+fn server_config(
+    matches: &ArgMatches,
+    analytics: Option<AnalyticsTee>,
+    audit: Option<AuditLogger>,
+) -> Result<InferenceServerConfig, Box<dyn Error>> {
+    let mut limits = ConnectionLimits::default();
+    if let Some(streams) = matches.get_one::<u32>("max-concurrent-streams") {
+        limits.max_concurrent_streams = *streams;
+    }
+    if let Some(secs) = matches.get_one::<u64>("header-read-timeout") {
+        limits.header_read_timeout = Duration::from_secs(*secs);
+    }
+    if let Some(secs) = matches.get_one::<u64>("idle-timeout") {
+        limits.idle_timeout = Duration::from_secs(*secs);
+    }
+
+    let mut overload_policy = OverloadPolicy::default();
+    if let Some(capacity) = matches.get_one::<usize>("overload-capacity") {
+        overload_policy.capacity = *capacity;
+    }
+
+    let mut traffic_limits = TrafficLimits::default();
+    if let Some(size) = matches.get_one::<String>("max-request-bytes") {
+        traffic_limits.max_request_bytes = Some(parse_byte_size(size)?);
+    }
+    if let Some(size) = matches.get_one::<String>("tenant-daily-bytes") {
+        traffic_limits.daily_bytes = Some(parse_byte_size(size)?);
+    }
+    for entry in matches
+        .get_many::<String>("tenant-daily-bytes-for")
+        .unwrap_or_default()
+    {
+        let (tenant, size) = entry.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid tenant byte limit '{}', expected <tenant>=<size>",
+                entry
+            )
+        })?;
+        traffic_limits
+            .tenants
+            .insert(tenant.to_string(), parse_byte_size(size)?);
+    }
+
+    Ok(InferenceServerConfig {
+        rest_hostname: matches.get_one::<String>("rest-host").unwrap().to_string(),
+        rest_port: matches.get_one::<String>("rest-port").unwrap().parse()?,
+        grpc_hostname: matches.get_one::<String>("grpc-host").unwrap().to_string(),
+        grpc_port: matches.get_one::<String>("grpc-port").unwrap().parse()?,
+        shared_port: matches.get_one::<u16>("port").map(|port| {
+            Arc::new(
+                SharedPort::new(
+                    matches.get_one::<String>("rest-host").unwrap(),
+                    *port,
+                    limits.header_read_timeout,
+                )
+                .with_routing(
+                    matches
+                        .get_one::<PortRouting>("port-routing")
+                        .copied()
+                        .unwrap_or_default(),
+                ),
+            )
+        }),
+        analytics,
+        audit,
+        limits,
+        overload: Arc::new(OverloadController::new(overload_policy)),
+        ids: matches
+            .get_one::<String>("id-scheme")
+            .unwrap()
+            .parse::<IdScheme>()?
+            .provider(),
+        cors_origins: matches
+            .get_many::<String>("cors-origin")
+            .map(|origins| origins.cloned().collect())
+            .unwrap_or_default(),
+        rest_cors: rest_cors(matches)?,
+        rate_limiter: Arc::new(RateLimiter::new(rate_limits(matches)?)),
+        concurrency: Arc::new(ConcurrencyLimiter::new(concurrency_limits(matches)?)),
+        traffic: Arc::new(TrafficAccounting::new(traffic_limits)),
+        quotas: Arc::new(QuotaTracker::new(quota_limits(matches)?)),
+        kafka: kafka_config(matches)?,
+        batch_dir: matches.get_one::<PathBuf>("batch-dir").cloned(),
+        result_backend: result_backend(matches)?,
+        shared_memory: Arc::new(SharedMemoryRegistry::default()),
+        stream_pacing: StreamPacing {
+            max_buffered: *matches.get_one::<usize>("stream-buffer").unwrap(),
+            stall_timeout: Duration::from_secs(
+                *matches.get_one::<u64>("stream-stall-timeout").unwrap(),
+            ),
+        },
+        tls: tls_config(matches),
+        api_keys: matches
+            .get_one::<String>("api-keys")
+            .map(|spec| KeyStore::open(spec).map(Arc::new))
+            .transpose()?,
+        jwt: jwt_validator(matches)?.map(Arc::new),
+        config_reload: None,
+        admin_hostname: matches.get_one::<String>("admin-host").unwrap().to_string(),
+        admin_port: matches.get_one::<u16>("admin-port").copied(),
+        trust_tenant_header: matches.get_flag("trust-tenant-header"),
+    })
+}
+
+/// The rate limits given with `--client-rate-limit` and the `--model-rate-limit*` flags.
+fn rate_limits(matches: &ArgMatches) -> Result<RateLimits, Box<dyn Error>> {
+    let mut rate_limits = RateLimits::default();
+    if let Some(limit) = matches.get_one::<String>("client-rate-limit") {
+        rate_limits.per_client = Some(limit.parse()?);
+    }
+    if let Some(limit) = matches.get_one::<String>("model-rate-limit") {
+        rate_limits.per_model = Some(limit.parse()?);
+    }
+    for entry in matches
+        .get_many::<String>("model-rate-limit-for")
+        .unwrap_or_default()
+    {
+        let (model, limit) = entry.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid model rate limit '{}', expected <model>=<limit>",
+                entry
+            )
+        })?;
+        rate_limits.models.insert(model.to_string(), limit.parse()?);
+    }
+    Ok(rate_limits)
+}
+
+/// The concurrency limits given with `--max-in-flight` and the `--model-max-in-flight*` flags.
+fn concurrency_limits(matches: &ArgMatches) -> Result<ConcurrencyLimits, Box<dyn Error>> {
+    let mut concurrency_limits = ConcurrencyLimits {
+        global: matches.get_one::<usize>("max-in-flight").copied(),
+        per_model: matches.get_one::<usize>("model-max-in-flight").copied(),
+        ..ConcurrencyLimits::default()
+    };
+    for entry in matches
+        .get_many::<String>("model-max-in-flight-for")
+        .unwrap_or_default()
+    {
+        let (model, count) = entry.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid model concurrency limit '{}', expected <model>=<count>",
+                entry
+            )
+        })?;
+        concurrency_limits
+            .models
+            .insert(model.to_string(), count.trim().parse()?);
+    }
+    let limits_set = concurrency_limits
+        .global
+        .iter()
+        .chain(concurrency_limits.per_model.iter());
+    if limits_set
+        .chain(concurrency_limits.models.values())
+        .any(|limit| *limit == 0)
+    {
+        return Err("Concurrency limits must admit at least one request".into());
+    }
+    Ok(concurrency_limits)
+}
+
+/// The quotas given with `--quota-window`, `--quota` and `--quota-for`.
+fn quota_limits(matches: &ArgMatches) -> Result<QuotaLimits, Box<dyn Error>> {
+    let mut quota_limits = QuotaLimits {
+        window: parse_window(matches.get_one::<String>("quota-window").unwrap())?,
+        ..QuotaLimits::default()
+    };
+    if let Some(quota) = matches.get_one::<String>("quota") {
+        quota_limits.default = quota.parse()?;
+    }
+    for entry in matches.get_many::<String>("quota-for").unwrap_or_default() {
+        let (account, quota) = entry.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid account quota '{}', expected <account>=<requests>[:<tokens>]",
+                entry
+            )
+        })?;
+        quota_limits
+            .accounts
+            .insert(account.to_string(), quota.parse()?);
+    }
+    Ok(quota_limits)
+}
+
+/// The REST CORS given with `--rest-cors-origin` and the `--rest-cors-*` flags, if any.
+fn rest_cors(matches: &ArgMatches) -> Result<Option<CorsConfig>, Box<dyn Error>> {
+    let Some(origins) = matches.get_many::<String>("rest-cors-origin") else {
+        return Ok(None);
+    };
+    let strings = |name: &str| -> Option<Vec<String>> {
+        matches
+            .get_many::<String>(name)
+            .map(|values| values.cloned().collect())
+    };
+    let mut cors = CorsConfig::new(origins.cloned().collect())
+        .with_headers(strings("rest-cors-header").unwrap_or_default())
+        .with_credentials(matches.get_flag("rest-cors-credentials"));
+    if let Some(methods) = strings("rest-cors-method") {
+        cors = cors.with_methods(methods);
+    }
+    if let Some(secs) = matches.get_one::<u64>("rest-cors-max-age") {
+        cors = cors.with_max_age(Duration::from_secs(*secs));
+    }
+    cors.check()?;
+    Ok(Some(cors))
+}
+
+/// The JWT validation given with `--jwks-url` and the `--jwt-*` flags, if any.
+fn jwt_validator(matches: &ArgMatches) -> Result<Option<JwtValidator>, Box<dyn Error>> {
+    let Some(jwks_url) = matches.get_one::<String>("jwks-url") else {
+        return Ok(None);
+    };
+    let mut config = JwtConfig::new(jwks_url)
+        .with_roles_claim(matches.get_one::<String>("jwt-roles-claim").unwrap());
+    if let Some(issuer) = matches.get_one::<String>("jwt-issuer") {
+        config = config.with_issuer(issuer);
+    }
+    if let Some(audience) = matches.get_one::<String>("jwt-audience") {
+        config = config.with_audience(audience);
+    }
+    for entry in matches.get_many::<String>("jwt-role").unwrap_or_default() {
+        let (value, role) = entry
+            .split_once('=')
+            .and_then(|(value, role)| Some((value, Role::parse(role.trim())?)))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid JWT role mapping '{}', expected <value>=<admin|infer|read-only>",
+                    entry
+                )
+            })?;
+        config = config.with_role(value, role);
+    }
+    Ok(Some(JwtValidator::new(config)))
+}
+
+/// The TLS files given with `--tls-cert` and `--tls-key`, if any.
+fn tls_config(matches: &ArgMatches) -> Option<TlsConfig> {
+    let cert = matches.get_one::<PathBuf>("tls-cert")?;
+    let key = matches.get_one::<PathBuf>("tls-key")?;
+    let config = TlsConfig::new(cert, key);
+    Some(match matches.get_one::<PathBuf>("tls-client-ca") {
+        Some(client_ca) => config.with_client_ca(client_ca),
+        None => config,
+    })
+}
+
+/// The shared store of asynchronous inference results given with `--result-store`, if any.
+fn result_backend(matches: &ArgMatches) -> Result<Option<Arc<dyn ResultBackend>>, Box<dyn Error>> {
+    match matches
+        .get_one::<String>("result-store")
+        .map(String::as_str)
+    {
+        None | Some("memory") => Ok(None),
+        Some(uri) => Ok(Some(Arc::new(RedisResultBackend::from_uri(uri)?))),
+    }
+}
+
+/// The consumer mode given with `--kafka-proxy` and the other `--kafka-*` flags.
+fn kafka_config(matches: &ArgMatches) -> Result<Option<KafkaConfig>, Box<dyn Error>> {
+    let Some(proxy_url) = matches.get_one::<String>("kafka-proxy") else {
+        return Ok(None);
+    };
+    let topic = |name: &str| matches.get_one::<String>(name).unwrap().to_string();
+    Ok(Some(KafkaConfig {
+        proxy_url: proxy_url.to_string(),
+        group: topic("kafka-group"),
+        input_topic: topic("kafka-input-topic"),
+        output_topic: topic("kafka-output-topic"),
+        format: matches
+            .get_one::<String>("kafka-format")
+            .unwrap()
+            .parse::<MessageFormat>()?,
+    }))
+}
+
+fn model_sources(matches: &ArgMatches) -> Result<Vec<ModelSource>, Box<dyn Error>> {
+    Ok(matches
+        .get_many::<String>("model-source")
+        .unwrap_or_default()
+        .map(|uri| ModelSource::from_uri(uri).map(ModelSource::with_env_credentials))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Watermark schemes keyed with `--watermark-key` and the tenants of `--watermark-for`.
+fn watermarking(matches: &ArgMatches) -> Result<Watermarking, Box<dyn Error>> {
+    let mut watermarking = Watermarking::new();
+    if let Some(spec) = matches.get_one::<String>("watermark-key") {
+        watermarking = watermarking.with_key(&foundation::model::watermark::read_key(spec)?);
+    }
+    for entry in matches
+        .get_many::<String>("watermark-for")
+        .unwrap_or_default()
+    {
+        let enabled = match entry.split_once('=') {
+            Some((tenant, "on")) => Some((tenant, true)),
+            Some((tenant, "off")) => Some((tenant, false)),
+            _ => None,
+        };
+        let (tenant, enabled) = enabled.ok_or_else(|| {
+            format!(
+                "Invalid tenant watermarking '{}', expected <tenant>=on|off",
+                entry
+            )
+        })?;
+        watermarking = watermarking.with_tenant(tenant, enabled);
+    }
+    if watermarking.schemes().is_empty() && matches.contains_id("watermark-for") {
+        eprintln!("No --watermark-key set, outputs of the tenants watermarked are refused");
+    }
+    Ok(watermarking)
+}
+
+/// The circuit breaker policy of `--circuit-error-rate`, if set.
+fn circuit_policy(matches: &ArgMatches) -> Result<Option<CircuitPolicy>, Box<dyn Error>> {
+    let Some(rate) = matches.get_one::<String>("circuit-error-rate") else {
+        return Ok(None);
+    };
+    let open_at: f64 = rate
+        .parse()
+        .ok()
+        .filter(|rate| *rate > 0.0 && *rate <= 1.0)
+        .ok_or_else(|| format!("Invalid circuit error rate '{}', expected (0, 1]", rate))?;
+    Ok(Some(CircuitPolicy {
+        window: Duration::from_secs(*matches.get_one::<u64>("circuit-window").unwrap()),
+        min_requests: *matches.get_one::<u64>("circuit-min-requests").unwrap(),
+        open_at,
+        probe_interval: Duration::from_secs(
+            *matches.get_one::<u64>("circuit-probe-interval").unwrap(),
+        ),
+    }))
+}
+
+fn buffer_sizing(matches: &ArgMatches) -> BufferSizing {
+    let mut sizing = BufferSizing::default();
+    if let Some(min) = matches.get_one::<usize>("buffer-min-capacity") {
+        sizing.min_capacity = *min;
+    }
+    if let Some(max) = matches.get_one::<usize>("buffer-max-capacity") {
+        sizing.max_capacity = *max;
+    }
+    if let Some(millis) = matches.get_one::<u64>("starvation-limit") {
+        sizing.starvation_limit = Duration::from_millis(*millis);
+    }
+    sizing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    fn parse(args: &[&str]) -> Result<ArgMatches, clap::Error> {
+        cli().try_get_matches_from(std::iter::once("galemind").chain(args.iter().copied()))
+    }
+
+    fn refusal(args: &[&str]) -> ErrorKind {
+        parse(args).unwrap_err().kind()
+    }
+
+    /// The matches of the subcommand the arguments name.
+    fn subcommand(args: &[&str]) -> ArgMatches {
+        let matches = parse(args).unwrap();
+        matches.subcommand().unwrap().1.clone()
+    }
+
+    #[test]
+    fn test_shared_port_excludes_the_ports_of_each_server() {
+        let matches = subcommand(&["start", "--port", "9000", "--port-routing", "request"]);
+        assert_eq!(matches.get_one::<u16>("port"), Some(&9000));
+        assert_eq!(
+            matches.get_one::<PortRouting>("port-routing"),
+            Some(&PortRouting::Request)
+        );
+        for separate in ["--rest-port", "--grpc-port"] {
+            assert_eq!(
+                refusal(&["start", "--port", "9000", separate, "9001"]),
+                ErrorKind::ArgumentConflict
+            );
+        }
+        assert_eq!(
+            refusal(&["doctor", "--port", "9000", "--grpc-host", "::"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            refusal(&["start", "--port-routing", "request"]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_models_commands_parse_their_options() {
+        let matches = subcommand(&["models", "list", "--selector", "team=search", "--json"]);
+        let (_, list) = matches.subcommand().unwrap();
+        assert_eq!(
+            list.get_one::<String>("selector").map(String::as_str),
+            Some("team=search")
+        );
+        assert!(list.get_flag("json"));
+        assert!(!list.get_flag("grpc"));
+
+        let matches = subcommand(&["models", "describe", "resnet", "--version", "2", "--grpc"]);
+        let (_, describe) = matches.subcommand().unwrap();
+        assert_eq!(
+            describe.get_one::<String>("name").map(String::as_str),
+            Some("resnet")
+        );
+        assert_eq!(
+            describe.get_one::<String>("version").map(String::as_str),
+            Some("2")
+        );
+        assert!(describe.get_flag("grpc"));
+
+        assert_eq!(
+            refusal(&["models", "describe"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(refusal(&["models"]), ErrorKind::MissingSubcommand);
+        // The client commands take no server options.
+        assert_eq!(
+            refusal(&["models", "list", "--port", "9000"]),
+            ErrorKind::UnknownArgument
+        );
+    }
+
+    #[test]
+    fn test_bench_parses_its_load() {
+        let matches = subcommand(&["bench", "--model", "resnet"]);
+        assert_eq!(matches.get_one::<u32>("concurrency"), Some(&8));
+        assert_eq!(
+            matches.get_one::<Duration>("duration"),
+            Some(&Duration::from_secs(30))
+        );
+        assert_eq!(matches.get_one::<PathBuf>("input"), None);
+
+        let matches = subcommand(&[
+            "bench",
+            "--model",
+            "resnet",
+            "--concurrency",
+            "32",
+            "--duration",
+            "500ms",
+            "--grpc",
+        ]);
+        assert_eq!(matches.get_one::<u32>("concurrency"), Some(&32));
+        assert_eq!(
+            matches.get_one::<Duration>("duration"),
+            Some(&Duration::from_millis(500))
+        );
+        assert!(matches.get_flag("grpc"));
+
+        assert_eq!(refusal(&["bench"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(
+            refusal(&["bench", "--model", "resnet", "--concurrency", "0"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            refusal(&["bench", "--model", "resnet", "--duration", "soon"]),
+            ErrorKind::ValueValidation
+        );
+    }
+
+    #[test]
+    fn test_infer_needs_a_model_and_an_input() {
+        let matches = subcommand(&[
+            "infer",
+            "--model",
+            "resnet",
+            "--input",
+            "request.json",
+            "--server",
+            "http://inference:8080",
+        ]);
+        assert_eq!(
+            matches.get_one::<String>("model").map(String::as_str),
+            Some("resnet")
+        );
+        assert_eq!(
+            matches.get_one::<PathBuf>("input"),
+            Some(&PathBuf::from("request.json"))
+        );
+        assert_eq!(
+            matches.get_one::<String>("server").map(String::as_str),
+            Some("http://inference:8080")
+        );
+        assert_eq!(matches.get_one::<String>("version"), None);
+        assert!(!matches.get_flag("grpc"));
+
+        assert_eq!(
+            refusal(&["infer", "--model", "resnet"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(
+            refusal(&["infer", "--input", "request.json"]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+}
@@ -0,0 +1,127 @@
+/* `galemind models`: the catalog of a running server.
+
+```bash
+galemind models list [--selector team=search] [--json]
+galemind models describe resnet [--version 2] [--json]
+```
+
+`list` prints a table of the models with their versions, backend, readiness and
+labels; `describe` adds the platform, inputs, outputs and capabilities of a
+model.
+*/
+
+use crate::client::{ModelDescription, ModelEntry, TensorInfo};
+use std::fmt::Write;
+
+fn labels(entry: &ModelEntry) -> String {
+    entry
+        .labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn ready(entry: &ModelEntry) -> &'static str {
+    if entry.ready { "yes" } else { "no" }
+}
+
+/// Rows of `rows` with their columns padded to the widest cell.
+fn table(rows: &[Vec<String>]) -> String {
+    let columns = rows.first().map_or(0, Vec::len);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let _ = writeln!(out, "{}", line.trim_end());
+    }
+    out
+}
+
+/// The models as a table, one per line under a header.
+pub fn format_list(models: &[ModelEntry]) -> String {
+    if models.is_empty() {
+        return "No models\n".to_string();
+    }
+    let mut rows = vec![
+        ["NAME", "VERSIONS", "BACKEND", "READY", "LABELS"]
+            .map(str::to_string)
+            .to_vec(),
+    ];
+    rows.extend(models.iter().map(|entry| {
+        vec![
+            entry.name.clone(),
+            entry.versions.join(","),
+            entry.backend.clone().unwrap_or_else(|| "-".to_string()),
+            ready(entry).to_string(),
+            labels(entry),
+        ]
+    }));
+    table(&rows)
+}
+
+fn tensors(tensors: &[TensorInfo]) -> String {
+    if tensors.is_empty() {
+        return "  (none declared)\n".to_string();
+    }
+    let rows: Vec<Vec<String>> = tensors
+        .iter()
+        .map(|tensor| {
+            let shape = tensor
+                .shape
+                .iter()
+                .map(i64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            vec![
+                format!("  {}", tensor.name),
+                tensor.datatype.clone(),
+                format!("[{}]", shape),
+            ]
+        })
+        .collect();
+    table(&rows)
+}
+
+/// The model with its metadata, one field per line.
+pub fn format_description(model: &ModelDescription) -> String {
+    let entry = &model.entry;
+    let mut out = String::new();
+    let _ = writeln!(out, "Name:      {}", entry.name);
+    let _ = writeln!(out, "Versions:  {}", entry.versions.join(", "));
+    let _ = writeln!(
+        out,
+        "Backend:   {}",
+        entry.backend.as_deref().unwrap_or("-")
+    );
+    let _ = writeln!(out, "Platform:  {}", model.platform);
+    let _ = writeln!(out, "Ready:     {}", ready(entry));
+    let _ = writeln!(out, "Labels:    {}", labels(entry));
+    if let Some(capabilities) = &model.capabilities {
+        let mut supported: Vec<String> = [
+            ("streaming", capabilities.streaming),
+            ("logprobs", capabilities.logprobs),
+            ("tools", capabilities.tools),
+        ]
+        .into_iter()
+        .filter(|(_, supported)| *supported)
+        .map(|(name, _)| name.to_string())
+        .collect();
+        if !capabilities.modalities.is_empty() {
+            supported.push(format!("modalities {}", capabilities.modalities.join(", ")));
+        }
+        let _ = writeln!(out, "Supports:  {}", supported.join("; "));
+    }
+    let _ = writeln!(out, "Inputs:");
+    out.push_str(&tensors(&model.inputs));
+    let _ = writeln!(out, "Outputs:");
+    out.push_str(&tensors(&model.outputs));
+    out
+}
@@ -7,6 +7,25 @@ fn main() {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .protoc_arg("--experimental_allow_proto3_optional")
-        .compile_protos(&["proto/prediction/prediction.proto"], &["proto"])?;
+        // Raw tensor contents are kept in the received buffer, see `translator`.
+        .bytes([
+            ".grpc_server.ModelInferRequest.raw_input_contents",
+            ".grpc_server.ModelInferResponse.raw_output_contents",
+            ".inference.ModelInferRequest.raw_input_contents",
+            ".inference.ModelInferResponse.raw_output_contents",
+            // Record batches are decoded in place, see `flight`.
+            ".arrow.flight.protocol.FlightData",
+        ])
+        .compile_protos(
+            &[
+                "proto/prediction/prediction.proto",
+                "proto/inference/grpc_service.proto",
+                "proto/grpc/health/v1/health.proto",
+                "proto/google/rpc/status.proto",
+                "proto/google/rpc/error_details.proto",
+                "proto/arrow/flight/Flight.proto",
+            ],
+            &["proto"],
+        )?;
     Ok(())
 }
@@ -0,0 +1,57 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use foundation::{API_KEY_HEADER, AuditLogger, AuditRecord, TENANT_HEADER, caller_identity};
+use prost::Message;
+use tonic::Status;
+use tonic::metadata::MetadataMap;
+
+use crate::correlation;
+use crate::grpc_server::{ModelInferRequest, ModelInferResponse};
+
+/// Identity of the caller of a call, from its tenant or API key metadata entries or its
+/// peer address.
+pub fn caller(metadata: &MetadataMap, addr: Option<SocketAddr>) -> Option<String> {
+    let entry = |name: &str| metadata.get(name).and_then(|value| value.to_str().ok());
+    caller_identity(
+        entry(TENANT_HEADER),
+        entry(API_KEY_HEADER),
+        addr.map(|addr| addr.ip()),
+    )
+}
+
+/// Audit record of `req`, before it runs. Requests without an id take the caller's
+/// correlation id until their response gives them one.
+pub fn begin(
+    req: &ModelInferRequest,
+    metadata: &MetadataMap,
+    caller: Option<String>,
+) -> AuditRecord {
+    let id = Some(req.id.clone())
+        .filter(|id| !id.is_empty())
+        .or_else(|| correlation::external_id(metadata))
+        .unwrap_or_default();
+    AuditRecord::new("grpc", req.model_name.clone(), id)
+        .with_version(Some(req.model_version.clone()))
+        .with_caller(caller)
+        .with_bytes(req.encoded_len() as u64, None)
+}
+
+/// Completes `record` with the outcome of its request and logs it.
+pub fn finish(
+    audit: &AuditLogger,
+    mut record: AuditRecord,
+    result: Result<&ModelInferResponse, &Status>,
+    started: Instant,
+) {
+    let code = match result {
+        Ok(response) => {
+            record.request_id = response.id.clone();
+            record = record.with_version(Some(response.model_version.clone()));
+            record.output_bytes = Some(response.encoded_len() as u64);
+            tonic::Code::Ok
+        }
+        Err(status) => status.code(),
+    };
+    audit.log(record.with_outcome(format!("{:?}", code), started.elapsed()));
+}
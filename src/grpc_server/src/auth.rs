@@ -0,0 +1,77 @@
+use foundation::{
+    AUTHORIZATION_HEADER, AuthError, Authenticated, Authenticator, Role, TENANT_HEADER, Target,
+    request_tenant,
+};
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Status};
+
+fn refused(error: AuthError) -> Status {
+    if error.is_unauthenticated() {
+        Status::unauthenticated(error.to_string())
+    } else {
+        Status::permission_denied(error.to_string())
+    }
+}
+
+fn authorization(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get(AUTHORIZATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Interceptor refusing calls without a valid API key or token, before any message is
+/// decoded. Every call needs at least the read-only role. Authorized calls carry their
+/// account as an `Authenticated` extension.
+pub fn authenticate(
+    authenticator: Option<&Authenticator>,
+    mut request: Request<()>,
+) -> Result<Request<()>, Status> {
+    let account = authorize(
+        authenticator,
+        request.metadata(),
+        Role::ReadOnly,
+        Target::Server,
+    )?;
+    if let Some(account) = account {
+        request.extensions_mut().insert(Authenticated(account));
+    }
+    Ok(request)
+}
+
+/// Interceptor replacing the tenant metadata entry of a call with its tenant, see
+/// `foundation::request_tenant`: past it, the entry names the tenant the call is accounted
+/// to, or is absent for anonymous calls. Runs after `authenticate`.
+pub fn identify_tenant(trust_header: bool, mut request: Request<()>) -> Request<()> {
+    let header = request
+        .metadata()
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok());
+    let tenant = request_tenant(
+        header,
+        request.extensions().get::<Authenticated>(),
+        trust_header,
+    )
+    .and_then(|tenant| MetadataValue::try_from(tenant).ok());
+    match tenant {
+        Some(tenant) => request.metadata_mut().insert(TENANT_HEADER, tenant),
+        None => request.metadata_mut().remove(TENANT_HEADER),
+    };
+    request
+}
+
+/// Refuses calls without `role`, or whose key is not scoped to `target`. Returns the
+/// account of the caller when authentication is on.
+pub fn authorize(
+    authenticator: Option<&Authenticator>,
+    metadata: &MetadataMap,
+    role: Role,
+    target: Target<'_>,
+) -> Result<Option<String>, Status> {
+    match authenticator {
+        Some(authenticator) => authenticator
+            .authorize(authorization(metadata), role, target)
+            .map(Some)
+            .map_err(refused),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,143 @@
+use foundation::{ConnectionLimits, IdleTimeout, Listener, ReloadableTls};
+use futures::stream::{BoxStream, StreamExt};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+/// Accepted TCP connection subject to the idle timeouts of `ConnectionLimits`.
+pub enum GrpcConnection {
+    Plain(IdleTimeout<TcpStream>),
+    Tls(Box<TlsStream<IdleTimeout<TcpStream>>>),
+}
+
+/// Handshakes waiting for the server to pick them up.
+const HANDSHAKE_BACKLOG: usize = 128;
+
+/// Wraps every connection accepted by `listener` in an idle timeout, and terminates TLS
+/// when `tls` is set.
+pub fn incoming(
+    mut listener: Listener,
+    limits: ConnectionLimits,
+    tls: Option<Arc<ReloadableTls>>,
+) -> BoxStream<'static, io::Result<GrpcConnection>> {
+    let Some(tls) = tls else {
+        return futures::stream::unfold(listener, |mut listener| async move {
+            let accepted = listener.accept().await;
+            Some((accepted, listener))
+        })
+        .map(move |accepted| {
+            let (stream, _) = accepted?;
+            stream.set_nodelay(true)?;
+            Ok(GrpcConnection::Plain(IdleTimeout::new(stream, &limits)))
+        })
+        .boxed();
+    };
+
+    // Handshakes run in their own tasks so that a slow client does not hold up the others.
+    let (sender, receiver) = mpsc::channel(HANDSHAKE_BACKLOG);
+    let handshake_timeout = limits.header_read_timeout;
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // Typically file descriptor exhaustion: back off instead of spinning.
+                    eprintln!("Failed to accept gRPC connection: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            if stream.set_nodelay(true).is_err() {
+                continue;
+            }
+            let stream = IdleTimeout::new(stream, &limits);
+            let acceptor = tls.acceptor();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let handshake = acceptor.accept(stream);
+                // Failed handshakes only concern their client.
+                if let Ok(Ok(stream)) = tokio::time::timeout(handshake_timeout, handshake).await {
+                    let _ = sender.send(Ok(GrpcConnection::Tls(Box::new(stream)))).await;
+                }
+            });
+        }
+    });
+    ReceiverStream::new(receiver).boxed()
+}
+
+impl Connected for GrpcConnection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        match self {
+            GrpcConnection::Plain(stream) => stream.get_ref().connect_info(),
+            GrpcConnection::Tls(stream) => stream.get_ref().0.get_ref().connect_info(),
+        }
+    }
+}
+
+impl AsyncRead for GrpcConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            GrpcConnection::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            GrpcConnection::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for GrpcConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            GrpcConnection::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            GrpcConnection::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            GrpcConnection::Plain(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            GrpcConnection::Tls(stream) => Pin::new(stream.as_mut()).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            GrpcConnection::Plain(stream) => stream.is_write_vectored(),
+            GrpcConnection::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            GrpcConnection::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            GrpcConnection::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            GrpcConnection::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            GrpcConnection::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
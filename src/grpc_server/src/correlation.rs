@@ -0,0 +1,32 @@
+use foundation::{CORRELATION_ID_HEADER, IdProvider, correlation_id};
+use tonic::Response;
+use tonic::metadata::MetadataMap;
+
+/// Correlation id supplied by the caller in the request metadata.
+pub fn external_id(metadata: &MetadataMap) -> Option<String> {
+    correlation_id(|name| metadata.get(name).and_then(|v| v.to_str().ok()))
+}
+
+/// Gives a request without an id the caller's correlation id or a generated one,
+/// and returns the correlation id to echo back.
+pub fn assign_request_id(
+    ids: &dyn IdProvider,
+    metadata: &MetadataMap,
+    request_id: &mut String,
+) -> String {
+    let external = external_id(metadata);
+    if request_id.is_empty() {
+        *request_id = external.clone().unwrap_or_else(|| ids.next_id());
+    }
+    external.unwrap_or_else(|| request_id.clone())
+}
+
+pub fn with_correlation_id<T>(
+    correlation_id: Option<String>,
+    mut response: Response<T>,
+) -> Response<T> {
+    if let Some(value) = correlation_id.and_then(|id| id.parse().ok()) {
+        response.metadata_mut().insert(CORRELATION_ID_HEADER, value);
+    }
+    response
+}
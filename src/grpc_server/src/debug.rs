@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use foundation::{DEBUG_HEADER, Timeline, timeline_requested};
+use serde_json::json;
+use tonic::metadata::MetadataMap;
+
+use crate::grpc_server::{InferParameter, ModelInferResponse, infer_parameter::ParameterChoice};
+
+/// Response parameter carrying the debug section as a JSON string.
+const DEBUG_PARAMETER: &str = "debug";
+
+/// Timeline of a request that opted in with the debug metadata entry, starting at `arrived`.
+pub fn request_timeline(metadata: &MetadataMap, arrived: Instant) -> Option<Arc<Timeline>> {
+    let value = metadata.get(DEBUG_HEADER).and_then(|v| v.to_str().ok());
+    timeline_requested(value).then(|| Arc::new(Timeline::starting_at(arrived)))
+}
+
+/// Adds the collected timeline to the `debug` parameter of `response`.
+/// Protobuf encoding happens afterwards and is not part of the timeline.
+pub fn attach(timeline: Option<&Timeline>, response: &mut ModelInferResponse) {
+    let Some(timeline) = timeline else {
+        return;
+    };
+    let debug = json!({
+        "elapsed_us": timeline.elapsed_us(),
+        "timeline": timeline.events(),
+    });
+    response.parameters.insert(
+        DEBUG_PARAMETER.to_string(),
+        InferParameter {
+            parameter_choice: Some(ParameterChoice::StringParam(debug.to_string())),
+        },
+    );
+}
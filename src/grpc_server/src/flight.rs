@@ -0,0 +1,863 @@
+/* Arrow Flight service, `arrow.flight.protocol.FlightService`, for bulk scoring.
+
+Offline scoring jobs send columnar data with `DoExchange`: the first message
+names the model with a PATH descriptor, `[model]` or `[model, version]`, then
+come an Arrow schema and record batches. Every batch is one inference, whose
+inputs are the columns of the batch, by name:
+
+- a numeric or boolean column is a tensor of shape `[rows]`,
+- a fixed size list column of width `n` is a tensor of shape `[rows, n]`,
+- a string or binary column is a BYTES tensor.
+
+Each batch is answered with a batch of predictions, one column per output of
+the model, a fixed size list when the output has more than one element per
+row. Its `app_metadata` is echoed back, so clients can match them. The schema
+of the predictions is sent before the first of them, and again when it
+changes. Columns must not hold nulls, and dictionary encoded columns are
+refused.
+
+Batches run as `ModelInfer` calls do, on the `PredictionService` handler, so
+authentication, limits, statistics and audit are the same. A failed batch ends
+the exchange with its error. Numeric columns reach runtimes as views of the
+received message, without being copied.
+
+`GetSchema` and `GetFlightInfo` describe the inputs a model expects, as a
+schema, and `ListFlights` lists the models matching the label selector given
+as criteria. The other calls are not implemented.
+*/
+
+use arrow_array::cast::AsArray;
+use arrow_array::{
+    ArrayRef, BinaryArray, BooleanArray, FixedSizeListArray, RecordBatch, StringArray,
+};
+use arrow_buffer::Buffer;
+use arrow_data::ArrayDataBuilder;
+use arrow_ipc::MessageHeader;
+use arrow_ipc::convert::fb_to_schema;
+use arrow_ipc::reader::read_record_batch;
+use arrow_ipc::writer::{DictionaryTracker, IpcDataGenerator, IpcWriteOptions, write_message};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use bytes::Bytes;
+use foundation::api::tensor::{BYTES_DATATYPE, Data, decode_byte_elements, encode_byte_elements};
+use foundation::{LabelSelector, ModelId, OutputDatatype, Priority, Role, Target};
+use futures::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::grpc_server::ModelInferRequest;
+use crate::grpc_server::model_infer_request::InferInputTensor;
+use crate::grpc_server::model_infer_response::InferOutputTensor;
+use crate::{
+    PredictionServiceImpl, audit, auth, correlation, infer_message, request_deadline,
+    request_priority, send_paced, translator,
+};
+
+pub mod proto {
+    tonic::include_proto!("arrow.flight.protocol");
+}
+
+use proto::flight_descriptor::DescriptorType;
+use proto::flight_service_server::{FlightService, FlightServiceServer};
+use proto::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+
+type FlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+pub struct FlightScoringService {
+    prediction: PredictionServiceImpl,
+}
+
+impl FlightScoringService {
+    pub fn server(prediction: PredictionServiceImpl) -> FlightServiceServer<Self> {
+        FlightServiceServer::new(Self { prediction })
+    }
+
+    /// The descriptor of `model_name` and the schema of its inputs.
+    fn flight_info(&self, model_name: &str, version: Option<&str>) -> Result<FlightInfo, Status> {
+        let schema = self.input_schema(model_name, version)?;
+        Ok(FlightInfo {
+            schema: ipc_schema(&schema),
+            flight_descriptor: Some(FlightDescriptor {
+                r#type: DescriptorType::Path as i32,
+                path: [model_name]
+                    .into_iter()
+                    .chain(version)
+                    .map(str::to_string)
+                    .collect(),
+                ..Default::default()
+            }),
+            // Unknown, as the protocol has it.
+            total_records: -1,
+            total_bytes: -1,
+            ..Default::default()
+        })
+    }
+
+    /// Schema of the batches `model_name` scores. The first dimension of its inputs is the
+    /// rows of the batch.
+    fn input_schema(&self, model_name: &str, version: Option<&str>) -> Result<Schema, Status> {
+        let metadata = self
+            .prediction
+            .model_manager
+            .model_metadata(&ModelId(model_name.to_string()), version)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let fields = metadata
+            .inputs
+            .iter()
+            .map(|input| {
+                let data_type = arrow_type(&input.datatype).ok_or_else(|| {
+                    Status::failed_precondition(format!(
+                        "Input '{}' of model '{}' has datatype {}, which has no Arrow type",
+                        input.name, model_name, input.datatype
+                    ))
+                })?;
+                // Variable dimensions leave the width unknown, and the column flat.
+                let width = input
+                    .shape
+                    .iter()
+                    .skip(1)
+                    .try_fold(1i64, |width, dim| match *dim {
+                        dim if dim < 0 => None,
+                        dim => width.checked_mul(dim),
+                    })
+                    .and_then(|width| i32::try_from(width).ok());
+                let data_type = match width {
+                    Some(width) if width > 1 => list_type(data_type, width),
+                    _ => data_type,
+                };
+                Ok(Field::new(input.name.clone(), data_type, false))
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        Ok(Schema::new(fields))
+    }
+}
+
+/// Model name and version of a PATH descriptor.
+fn described_model(descriptor: &FlightDescriptor) -> Result<(String, String), Status> {
+    match (descriptor.r#type(), descriptor.path.as_slice()) {
+        (DescriptorType::Path, [model_name]) => Ok((model_name.clone(), String::new())),
+        (DescriptorType::Path, [model_name, version]) => Ok((model_name.clone(), version.clone())),
+        _ => Err(Status::invalid_argument(
+            "Flights are described by the path [model] or [model, version]",
+        )),
+    }
+}
+
+/// KServe datatype of the elements of an Arrow column.
+fn tensor_datatype(data_type: &DataType) -> Option<&'static str> {
+    Some(match data_type {
+        DataType::Boolean => "BOOL",
+        DataType::Int8 => "INT8",
+        DataType::Int16 => "INT16",
+        DataType::Int32 => "INT32",
+        DataType::Int64 => "INT64",
+        DataType::UInt8 => "UINT8",
+        DataType::UInt16 => "UINT16",
+        DataType::UInt32 => "UINT32",
+        DataType::UInt64 => "UINT64",
+        DataType::Float16 => "FP16",
+        DataType::Float32 => "FP32",
+        DataType::Float64 => "FP64",
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Binary | DataType::LargeBinary => {
+            BYTES_DATATYPE
+        }
+        _ => return None,
+    })
+}
+
+/// Arrow type of the elements of a KServe datatype. BYTES are binary, though string
+/// columns are accepted too; BF16 has no Arrow type.
+fn arrow_type(datatype: &str) -> Option<DataType> {
+    Some(match datatype {
+        "BOOL" => DataType::Boolean,
+        "INT8" => DataType::Int8,
+        "INT16" => DataType::Int16,
+        "INT32" => DataType::Int32,
+        "INT64" => DataType::Int64,
+        "UINT8" => DataType::UInt8,
+        "UINT16" => DataType::UInt16,
+        "UINT32" => DataType::UInt32,
+        "UINT64" => DataType::UInt64,
+        "FP16" => DataType::Float16,
+        "FP32" => DataType::Float32,
+        "FP64" => DataType::Float64,
+        BYTES_DATATYPE => DataType::Binary,
+        _ => return None,
+    })
+}
+
+fn list_type(data_type: DataType, width: i32) -> DataType {
+    DataType::FixedSizeList(Arc::new(Field::new("item", data_type, false)), width)
+}
+
+/// `schema` in its IPC form, as `FlightInfo` and `SchemaResult` hold it.
+fn ipc_schema(schema: &Schema) -> Vec<u8> {
+    let options = IpcWriteOptions::default();
+    let encoded = IpcDataGenerator::default().schema_to_bytes_with_dictionary_tracker(
+        schema,
+        &mut DictionaryTracker::new(false),
+        &options,
+    );
+    let mut bytes = Vec::new();
+    // Writing to memory cannot fail.
+    let _ = write_message(&mut bytes, encoded, &options);
+    bytes
+}
+
+fn schema_message(schema: &Schema) -> FlightData {
+    let encoded = IpcDataGenerator::default().schema_to_bytes_with_dictionary_tracker(
+        schema,
+        &mut DictionaryTracker::new(false),
+        &IpcWriteOptions::default(),
+    );
+    FlightData {
+        data_header: encoded.ipc_message.into(),
+        ..Default::default()
+    }
+}
+
+fn batch_message(batch: &RecordBatch, app_metadata: Bytes) -> Result<FlightData, Status> {
+    let (_, encoded) = IpcDataGenerator::default()
+        .encoded_batch(
+            batch,
+            &mut DictionaryTracker::new(false),
+            &IpcWriteOptions::default(),
+        )
+        .map_err(|e| Status::internal(e.to_string()))?;
+    Ok(FlightData {
+        flight_descriptor: None,
+        data_header: encoded.ipc_message.into(),
+        app_metadata,
+        data_body: encoded.arrow_data.into(),
+    })
+}
+
+fn invalid_data(error: ArrowError) -> Status {
+    Status::invalid_argument(format!("Invalid Arrow data: {}", error))
+}
+
+/// `values` as a slice of the message `body` they were decoded from, copied if they are
+/// not in it (the decoder realigns misaligned buffers).
+fn body_slice(body: &Bytes, values: &[u8]) -> Bytes {
+    let range = body.as_ptr_range();
+    if !values.is_empty()
+        && range.start <= values.as_ptr()
+        && values.as_ptr_range().end <= range.end
+    {
+        body.slice_ref(values)
+    } else {
+        Bytes::copy_from_slice(values)
+    }
+}
+
+/// The input tensor of a column and its raw contents.
+fn column_tensor(
+    name: &str,
+    column: &ArrayRef,
+    body: &Bytes,
+) -> Result<(InferInputTensor, Bytes), Status> {
+    let invalid =
+        |message: String| Status::invalid_argument(format!("Column '{}': {}", name, message));
+    let rows = column.len() as i64;
+    let (values, shape) = match column.data_type() {
+        DataType::FixedSizeList(_, width) => (
+            column.as_fixed_size_list().values().clone(),
+            vec![rows, *width as i64],
+        ),
+        _ => (column.clone(), vec![rows]),
+    };
+    if column.null_count() > 0 || values.null_count() > 0 {
+        return Err(invalid("nulls cannot be scored".to_string()));
+    }
+    let datatype = tensor_datatype(values.data_type())
+        .ok_or_else(|| invalid(format!("{} has no tensor datatype", column.data_type())))?;
+    let contents = match values.data_type() {
+        DataType::Boolean => values.as_boolean().values().iter().map(u8::from).collect(),
+        DataType::Utf8 => byte_contents(values.as_string::<i32>().iter().map(str_bytes)),
+        DataType::LargeUtf8 => byte_contents(values.as_string::<i64>().iter().map(str_bytes)),
+        DataType::Binary => byte_contents(
+            values
+                .as_binary::<i32>()
+                .iter()
+                .map(Option::unwrap_or_default),
+        ),
+        DataType::LargeBinary => byte_contents(
+            values
+                .as_binary::<i64>()
+                .iter()
+                .map(Option::unwrap_or_default),
+        ),
+        data_type => {
+            // Every other type with a tensor datatype has a fixed width.
+            let width = data_type.primitive_width().unwrap_or(1);
+            let data = values.to_data();
+            let start = data.offset() * width;
+            body_slice(body, &data.buffers()[0][start..start + data.len() * width])
+        }
+    };
+    let tensor = InferInputTensor {
+        name: name.to_string(),
+        datatype: datatype.to_string(),
+        shape,
+        ..Default::default()
+    };
+    Ok((tensor, contents))
+}
+
+fn str_bytes(value: Option<&str>) -> &[u8] {
+    value.unwrap_or_default().as_bytes()
+}
+
+fn byte_contents<'a>(elements: impl Iterator<Item = &'a [u8]>) -> Bytes {
+    encode_byte_elements(elements).into()
+}
+
+/// The inference of `batch` by `model_name`, with its columns as raw inputs.
+fn batch_request(
+    model_name: &str,
+    model_version: &str,
+    batch: &RecordBatch,
+    body: &Bytes,
+) -> Result<ModelInferRequest, Status> {
+    let mut request = ModelInferRequest {
+        model_name: model_name.to_string(),
+        model_version: model_version.to_string(),
+        ..Default::default()
+    };
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        let (tensor, contents) = column_tensor(field.name(), column, body)?;
+        request.inputs.push(tensor);
+        request.raw_input_contents.push(contents);
+    }
+    Ok(request)
+}
+
+/// A column of `rows` rows holding `output`, whose values are `raw` or else its typed
+/// contents.
+fn output_column(
+    output: InferOutputTensor,
+    raw: Option<Bytes>,
+    rows: usize,
+) -> Result<(Field, ArrayRef), Status> {
+    let invalid =
+        |message: String| Status::internal(format!("Output '{}': {}", output.name, message));
+    let contents = output.contents.unwrap_or_default();
+    let values: ArrayRef = if output.datatype == BYTES_DATATYPE {
+        let elements = match raw {
+            Some(bytes) => decode_byte_elements(&bytes).map_err(|e| invalid(e.to_string()))?,
+            None => contents.bytes_contents,
+        };
+        match Data::from_byte_elements(elements) {
+            Data::VSTRING(values) => Arc::new(StringArray::from(values)),
+            data => Arc::new(BinaryArray::from_iter_values(
+                data.byte_elements().unwrap_or_default(),
+            )),
+        }
+    } else {
+        let datatype = output
+            .datatype
+            .parse::<OutputDatatype>()
+            .map_err(|e| invalid(e.to_string()))?;
+        let bytes = match raw {
+            Some(bytes) => bytes,
+            None => translator::typed_values(contents, datatype)
+                .map_err(invalid)?
+                .raw_bytes()
+                .into(),
+        };
+        if datatype == OutputDatatype::Bool {
+            Arc::new(BooleanArray::from_iter(bytes.iter().map(|b| Some(*b != 0))))
+        } else {
+            let data_type = arrow_type(&output.datatype)
+                .ok_or_else(|| invalid(format!("{} has no Arrow type", datatype)))?;
+            let data = ArrayDataBuilder::new(data_type)
+                .len(bytes.len() / datatype.element_size())
+                .add_buffer(Buffer::from(bytes))
+                .align_buffers(true)
+                .build()
+                .map_err(|e| invalid(e.to_string()))?;
+            arrow_array::make_array(data)
+        }
+    };
+
+    let width = values.len().checked_div(rows).unwrap_or(1);
+    if rows * width != values.len() {
+        return Err(invalid(format!(
+            "its {} elements are not a whole number per row of the {} rows",
+            values.len(),
+            rows
+        )));
+    }
+    let column: ArrayRef = if width == 1 {
+        values
+    } else {
+        let item = Arc::new(Field::new("item", values.data_type().clone(), false));
+        Arc::new(
+            FixedSizeListArray::try_new(item, width as i32, values, None)
+                .map_err(|e| invalid(e.to_string()))?,
+        )
+    };
+    Ok((
+        Field::new(output.name, column.data_type().clone(), false),
+        column,
+    ))
+}
+
+/// State of a `DoExchange` call.
+#[derive(Default)]
+struct Exchange {
+    /// Model name and version, from the descriptor of the first message.
+    model: Option<(String, String)>,
+    input_schema: Option<SchemaRef>,
+    output_schema: Option<SchemaRef>,
+}
+
+impl Exchange {
+    /// Handles one message of the client, returning the messages answering it.
+    #[allow(clippy::too_many_arguments)]
+    async fn receive(
+        &mut self,
+        service: &PredictionServiceImpl,
+        metadata: &MetadataMap,
+        caller: &Option<String>,
+        priority: Priority,
+        call_started: Instant,
+        data: FlightData,
+    ) -> Result<Vec<FlightData>, Status> {
+        if self.model.is_none() {
+            let descriptor = data.flight_descriptor.as_ref().ok_or_else(|| {
+                Status::invalid_argument("The first message must describe the model to score with")
+            })?;
+            self.model = Some(described_model(descriptor)?);
+        }
+        if data.data_header.is_empty() {
+            return Ok(Vec::new());
+        }
+        let message = arrow_ipc::root_as_message(&data.data_header)
+            .map_err(|e| Status::invalid_argument(format!("Invalid Arrow message: {}", e)))?;
+        match message.header_type() {
+            MessageHeader::Schema => {
+                let schema = message
+                    .header_as_schema()
+                    .ok_or_else(|| Status::invalid_argument("Schema message without a schema"))?;
+                self.input_schema = Some(Arc::new(fb_to_schema(schema)));
+                Ok(Vec::new())
+            }
+            MessageHeader::RecordBatch => {
+                let (Some(schema), Some(header)) =
+                    (&self.input_schema, message.header_as_record_batch())
+                else {
+                    return Err(Status::invalid_argument(
+                        "Record batches must follow their schema",
+                    ));
+                };
+                let batch = read_record_batch(
+                    &Buffer::from(data.data_body.clone()),
+                    header,
+                    schema.clone(),
+                    &HashMap::new(),
+                    None,
+                    &message.version(),
+                )
+                .map_err(invalid_data)?;
+                let (model_name, model_version) = self.model.as_ref().expect("set above");
+                let request = batch_request(model_name, model_version, &batch, &data.data_body)?;
+                let _load = service.overload.begin();
+                let response = infer_message(
+                    service,
+                    "flight.DoExchange",
+                    metadata,
+                    caller,
+                    priority,
+                    call_started,
+                    request,
+                )
+                .await?;
+
+                let mut raw = response.raw_output_contents.into_iter();
+                let (fields, columns): (Vec<Field>, Vec<ArrayRef>) = response
+                    .outputs
+                    .into_iter()
+                    .map(|output| {
+                        let raw = raw.next();
+                        output_column(output, raw, batch.num_rows())
+                    })
+                    .collect::<Result<Vec<_>, Status>>()?
+                    .into_iter()
+                    .unzip();
+                let schema = Arc::new(Schema::new(fields));
+                let predictions = RecordBatch::try_new(schema.clone(), columns)
+                    .map_err(|e| Status::internal(e.to_string()))?;
+
+                let mut replies = Vec::new();
+                if self.output_schema.as_ref() != Some(&schema) {
+                    replies.push(schema_message(&schema));
+                    self.output_schema = Some(schema);
+                }
+                replies.push(batch_message(&predictions, data.app_metadata)?);
+                Ok(replies)
+            }
+            MessageHeader::DictionaryBatch => Err(Status::unimplemented(
+                "Dictionary encoded columns are not supported",
+            )),
+            header => Err(Status::invalid_argument(format!(
+                "Unexpected Arrow message {:?}",
+                header
+            ))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for FlightScoringService {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoExchangeStream = FlightStream<FlightData>;
+    type DoActionStream = FlightStream<proto::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+
+    /// Calls are authenticated with the same API keys and tokens as the other services.
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented(
+            "Send an API key or token in the metadata of each call instead",
+        ))
+    }
+
+    async fn list_flights(
+        &self,
+        request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        auth::authorize(
+            self.prediction.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            Target::Server,
+        )?;
+        let expression = String::from_utf8_lossy(&request.get_ref().expression).into_owned();
+        let selector = match expression.as_str() {
+            "" => LabelSelector::default(),
+            selector => selector
+                .parse::<LabelSelector>()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+        };
+        // Models whose inputs have no Arrow schema are left out.
+        let flights: Vec<Result<FlightInfo, Status>> = self
+            .prediction
+            .model_manager
+            .select_models(&selector)
+            .iter()
+            .filter_map(|model_id| self.flight_info(&model_id.0, None).ok())
+            .map(Ok)
+            .collect();
+        Ok(Response::new(
+            Box::pin(futures::stream::iter(flights)) as Self::ListFlightsStream
+        ))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let (model_name, version) = described_model(request.get_ref())?;
+        auth::authorize(
+            self.prediction.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            Target::Model(&model_name),
+        )?;
+        let version = (!version.is_empty()).then_some(version.as_str());
+        Ok(Response::new(self.flight_info(&model_name, version)?))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("Flights are not queries to poll"))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let (model_name, version) = described_model(request.get_ref())?;
+        auth::authorize(
+            self.prediction.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            Target::Model(&model_name),
+        )?;
+        let version = (!version.is_empty()).then_some(version.as_str());
+        let schema = self.input_schema(&model_name, version)?;
+        Ok(Response::new(SchemaResult {
+            schema: ipc_schema(&schema),
+        }))
+    }
+
+    async fn do_get(
+        &self,
+        _request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        Err(Status::unimplemented("Score batches with DoExchange"))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("Score batches with DoExchange"))
+    }
+
+    async fn do_exchange(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        // As on ModelInferAsync, call metadata applies to every batch.
+        let metadata = request.metadata().clone();
+        let caller = audit::caller(&metadata, request.remote_addr());
+        let correlation_id = correlation::external_id(&metadata);
+        let priority = request_priority(&metadata)?;
+        let call_started = Instant::now();
+        request_deadline(&metadata, call_started, call_started)?;
+        let mut stream = request.into_inner();
+        // The next batch is read once the predictions of the last one are queued.
+        let pacing = self.prediction.stream_pacing;
+        let (tx, rx) = mpsc::channel(pacing.max_buffered.max(1));
+
+        let service = self.prediction.clone();
+        tokio::spawn(async move {
+            let mut exchange = Exchange::default();
+            while let Some(message) = stream.message().await.transpose() {
+                let data = match message {
+                    Ok(data) => data,
+                    Err(e) => {
+                        eprintln!("Error reading stream: {:?}", e);
+                        break;
+                    }
+                };
+                let replies = exchange
+                    .receive(&service, &metadata, &caller, priority, call_started, data)
+                    .await;
+                match replies {
+                    Ok(replies) => {
+                        for reply in replies {
+                            if !send_paced(&tx, Ok(reply), pacing).await {
+                                return;
+                            }
+                        }
+                    }
+                    Err(status) => {
+                        send_paced(&tx, Err(status), pacing).await;
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(correlation::with_correlation_id(
+            correlation_id,
+            Response::new(Box::pin(ReceiverStream::new(rx)) as Self::DoExchangeStream),
+        ))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("No actions are offered"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(
+            Box::pin(futures::stream::empty()) as Self::ListActionsStream
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Date32Array, Float32Array, Int64Array};
+    use foundation::{
+        FakeInferenceProcessor, ModelDiscoveryService, ModelSignature, ModelVersionId,
+        ProcessorRuntime, TensorMetadata,
+    };
+
+    /// A service scoring model `m` with the fake processor, whose inputs are `inputs`.
+    fn service(inputs: Vec<TensorMetadata>) -> PredictionServiceImpl {
+        let model_manager = ModelDiscoveryService::new(10);
+        let signature = ModelSignature {
+            inputs,
+            outputs: Vec::new(),
+        };
+        let runtime = ProcessorRuntime::new("m", FakeInferenceProcessor).with_signature(signature);
+        model_manager.register_model_version(ModelVersionId::new("m", "1"), Arc::new(runtime));
+        PredictionServiceImpl::new(Arc::new(model_manager))
+    }
+
+    fn input(datatype: &str, shape: Vec<i64>) -> TensorMetadata {
+        TensorMetadata {
+            name: "x".to_string(),
+            datatype: datatype.to_string(),
+            shape,
+        }
+    }
+
+    /// A batch of the single column `x`.
+    fn batch(column: ArrayRef) -> RecordBatch {
+        RecordBatch::try_from_iter([("x", column)]).unwrap()
+    }
+
+    /// The messages a client sends to score `batch` with model `m`.
+    fn messages(batch: &RecordBatch) -> Vec<FlightData> {
+        let descriptor = FlightData {
+            flight_descriptor: Some(FlightDescriptor {
+                r#type: DescriptorType::Path as i32,
+                path: vec!["m".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        vec![
+            descriptor,
+            schema_message(&batch.schema()),
+            batch_message(batch, Bytes::from_static(b"batch-0")).unwrap(),
+        ]
+    }
+
+    /// Replies of the service to `messages`, or the error ending the exchange.
+    async fn exchange(
+        service: &PredictionServiceImpl,
+        messages: Vec<FlightData>,
+    ) -> Result<Vec<FlightData>, Status> {
+        let mut exchange = Exchange::default();
+        let mut replies = Vec::new();
+        for message in messages {
+            let received = exchange
+                .receive(
+                    service,
+                    &MetadataMap::new(),
+                    &None,
+                    Priority::default(),
+                    Instant::now(),
+                    message,
+                )
+                .await?;
+            replies.extend(received);
+        }
+        Ok(replies)
+    }
+
+    /// The batch of `message`, whose schema is that of `schema_message`.
+    fn decode(schema_message: &FlightData, message: &FlightData) -> RecordBatch {
+        let schema = arrow_ipc::root_as_message(&schema_message.data_header)
+            .unwrap()
+            .header_as_schema()
+            .map(fb_to_schema)
+            .unwrap();
+        let header = arrow_ipc::root_as_message(&message.data_header).unwrap();
+        read_record_batch(
+            &Buffer::from(message.data_body.clone()),
+            header.header_as_record_batch().unwrap(),
+            Arc::new(schema),
+            &HashMap::new(),
+            None,
+            &header.version(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_answered_with_their_predictions() {
+        let service = service(vec![input("FP32", vec![-1])]);
+        let rows: ArrayRef = Arc::new(Float32Array::from(vec![1.0, 2.0, 3.0]));
+        let replies = exchange(&service, messages(&batch(rows))).await.unwrap();
+        assert_eq!(replies.len(), 2, "the schema, then the predictions");
+        let predictions = decode(&replies[0], &replies[1]);
+        assert_eq!(replies[1].app_metadata, Bytes::from_static(b"batch-0"));
+        assert_eq!(predictions.num_rows(), 3);
+        let scores = predictions
+            .column_by_name("output_1")
+            .unwrap()
+            .as_primitive::<arrow_array::types::Float64Type>();
+        assert_eq!(scores.values().to_vec(), vec![0.1, 0.5, 0.4]);
+
+        // A single row takes every element of the output, as a list.
+        let row: ArrayRef = Arc::new(Float32Array::from(vec![1.0]));
+        let replies = exchange(&service, messages(&batch(row))).await.unwrap();
+        let predictions = decode(&replies[0], &replies[1]);
+        assert_eq!(
+            predictions.schema().field(0).data_type(),
+            &list_type(DataType::Float64, 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batches_unlike_a_tensor_are_refused() {
+        let service = service(vec![input("INT64", vec![-1])]);
+        let dates: ArrayRef = Arc::new(Date32Array::from(vec![1, 2]));
+        let status = exchange(&service, messages(&batch(dates)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("has no tensor datatype"));
+
+        let nulls: ArrayRef = Arc::new(Int64Array::from(vec![Some(1), None]));
+        let status = exchange(&service, messages(&batch(nulls)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("nulls cannot be scored"));
+
+        // A batch whose schema was never sent.
+        let rows: ArrayRef = Arc::new(Int64Array::from(vec![1, 2]));
+        let mut messages = messages(&batch(rows));
+        messages.remove(1);
+        let status = exchange(&service, messages).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("must follow their schema"));
+    }
+
+    #[test]
+    fn test_input_schemas_list_fixed_widths_only() {
+        let flight = FlightScoringService {
+            prediction: service(vec![
+                input("FP32", vec![-1, 2, 3]),
+                TensorMetadata {
+                    name: "variable".to_string(),
+                    ..input("FP32", vec![-1, -1, 4])
+                },
+                TensorMetadata {
+                    name: "huge".to_string(),
+                    ..input("FP32", vec![-1, i64::MAX, 2])
+                },
+            ]),
+        };
+        let schema = flight.input_schema("m", None).unwrap();
+        let types: Vec<&DataType> = schema.fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            types,
+            vec![
+                &list_type(DataType::Float32, 6),
+                &DataType::Float32,
+                &DataType::Float32
+            ]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+/* The standard gRPC health checking service, `grpc.health.v1.Health`.
+
+Kubernetes gRPC probes and service meshes check the server with it, without
+credentials, as the REST probes are. The service names are:
+
+- `""` and `grpc_server.PredictionService`: the server, serving when it is
+  ready (see `readiness`), not serving while models load or it drains.
+- `model.<name>`: a model, serving when the server is ready and the model has
+  a version to answer with, not serving otherwise.
+
+Other names are unknown: `Check` fails with NOT_FOUND, and `Watch` answers
+SERVICE_UNKNOWN, as the protocol asks. `Watch` sends the current status, then
+every change of it, until the client goes away or the server shuts down.
+*/
+
+use foundation::{ModelDiscoveryService, ModelId, ShutdownSignal};
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+use proto::health_check_response::ServingStatus;
+use proto::health_server::{Health, HealthServer};
+use proto::{HealthCheckRequest, HealthCheckResponse};
+
+/// Name of the prediction service, as health checked.
+const PREDICTION_SERVICE: &str = "grpc_server.PredictionService";
+/// Prefix of the health checked name of a model.
+const MODEL_PREFIX: &str = "model.";
+/// How often watched statuses are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct HealthService {
+    model_manager: Arc<ModelDiscoveryService>,
+    shutdown: ShutdownSignal,
+}
+
+impl HealthService {
+    pub fn server(
+        model_manager: Arc<ModelDiscoveryService>,
+        shutdown: ShutdownSignal,
+    ) -> HealthServer<Self> {
+        HealthServer::new(Self {
+            model_manager,
+            shutdown,
+        })
+    }
+}
+
+/// Status of `service`, or None for a name that is not known.
+fn status(model_manager: &ModelDiscoveryService, service: &str) -> Option<ServingStatus> {
+    let serving = |serving: bool| {
+        if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        }
+    };
+    let ready = model_manager.readiness_failures().is_empty();
+    if service.is_empty() || service == PREDICTION_SERVICE {
+        return Some(serving(ready));
+    }
+    let model_id = ModelId(service.strip_prefix(MODEL_PREFIX)?.to_string());
+    if !model_manager.has_model(&model_id) {
+        return None;
+    }
+    let servable = matches!(model_manager.resolve_version(&model_id, None), Ok(Some(_)));
+    Some(serving(ready && servable))
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        status(&self.model_manager, &service)
+            .map(|status| Response::new(response(status)))
+            .ok_or_else(|| Status::not_found(format!("Unknown service '{}'", service)))
+    }
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let model_manager = self.model_manager.clone();
+        let mut shutdown = Box::pin(self.shutdown.clone().triggered());
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut sent = None;
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = &mut shutdown => {
+                        // Lets the server drain instead of waiting for the watchers.
+                        let _ = tx.send(Ok(response(ServingStatus::NotServing))).await;
+                        return;
+                    }
+                }
+                let current =
+                    status(&model_manager, &service).unwrap_or(ServingStatus::ServiceUnknown);
+                if sent != Some(current) {
+                    if tx.send(Ok(response(current))).await.is_err() {
+                        return;
+                    }
+                    sent = Some(current);
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
@@ -0,0 +1,395 @@
+/* KServe / Triton V2 gRPC protocol, `inference.GRPCInferenceService`.
+
+Clients generated from the standard `grpc_service.proto` of KServe and Triton
+call the server unmodified. Every call is translated to the matching
+`PredictionService` call and served by the same handler, so authentication,
+limits, statistics and errors are the same on both services. The request
+metadata (API keys, tenants, deadlines...) and the response metadata are kept
+as they are.
+
+Both protocols share their messages but for `InferParameter`, whose `double`
+and `string` choices have swapped field numbers, and which has a `uint64`
+choice. Messages holding parameters are translated field by field, the others
+are re-decoded from their encoding.
+*/
+
+use prost::Message;
+use std::collections::HashMap;
+use tonic::{Request, Response, Status};
+
+use crate::PredictionServiceImpl;
+use crate::grpc_server;
+use crate::grpc_server::prediction_service_server::PredictionService;
+
+// Generated by prost: `InferParameter.parameter_choice` names every variant `*Param`.
+#[allow(clippy::enum_variant_names)]
+pub mod proto {
+    tonic::include_proto!("inference");
+}
+
+use proto::grpc_inference_service_server::{GrpcInferenceService, GrpcInferenceServiceServer};
+
+pub struct KServeService {
+    prediction: PredictionServiceImpl,
+}
+
+impl KServeService {
+    pub fn server(prediction: PredictionServiceImpl) -> GrpcInferenceServiceServer<Self> {
+        GrpcInferenceServiceServer::new(Self { prediction })
+    }
+}
+
+/// Re-decodes `message` as `T`, for messages with the same encoding in both protocols.
+fn transcode<T: Message + Default>(message: &impl Message) -> T {
+    T::decode(message.encode_to_vec().as_slice())
+        .expect("messages of both protocols share their encoding")
+}
+
+fn forward_request<A, B>(request: Request<A>, translate: impl FnOnce(A) -> B) -> Request<B> {
+    let (metadata, extensions, message) = request.into_parts();
+    Request::from_parts(metadata, extensions, translate(message))
+}
+
+fn forward_response<A, B>(
+    response: Result<Response<A>, Status>,
+    translate: impl FnOnce(A) -> B,
+) -> Result<Response<B>, Status> {
+    let (metadata, message, extensions) = response?.into_parts();
+    Ok(Response::from_parts(
+        metadata,
+        translate(message),
+        extensions,
+    ))
+}
+
+impl From<proto::InferParameter> for grpc_server::InferParameter {
+    fn from(parameter: proto::InferParameter) -> Self {
+        use grpc_server::infer_parameter::ParameterChoice;
+        use proto::infer_parameter::ParameterChoice as KServeChoice;
+
+        let choice = parameter.parameter_choice.map(|choice| match choice {
+            KServeChoice::BoolParam(value) => ParameterChoice::BoolParam(value),
+            KServeChoice::Int64Param(value) => ParameterChoice::Int64Param(value),
+            KServeChoice::StringParam(value) => ParameterChoice::StringParam(value),
+            KServeChoice::DoubleParam(value) => ParameterChoice::F64Param(value),
+            KServeChoice::Uint64Param(value) => i64::try_from(value)
+                .map_or(ParameterChoice::F64Param(value as f64), |value| {
+                    ParameterChoice::Int64Param(value)
+                }),
+        });
+        Self {
+            parameter_choice: choice,
+        }
+    }
+}
+
+impl From<grpc_server::InferParameter> for proto::InferParameter {
+    fn from(parameter: grpc_server::InferParameter) -> Self {
+        use grpc_server::infer_parameter::ParameterChoice;
+        use proto::infer_parameter::ParameterChoice as KServeChoice;
+
+        let choice = parameter.parameter_choice.map(|choice| match choice {
+            ParameterChoice::BoolParam(value) => KServeChoice::BoolParam(value),
+            ParameterChoice::Int64Param(value) => KServeChoice::Int64Param(value),
+            ParameterChoice::StringParam(value) => KServeChoice::StringParam(value),
+            ParameterChoice::F64Param(value) => KServeChoice::DoubleParam(value),
+        });
+        Self {
+            parameter_choice: choice,
+        }
+    }
+}
+
+fn parameters<A, B: From<A>>(parameters: HashMap<String, A>) -> HashMap<String, B> {
+    parameters
+        .into_iter()
+        .map(|(name, value)| (name, value.into()))
+        .collect()
+}
+
+impl From<proto::ModelInferRequest> for grpc_server::ModelInferRequest {
+    fn from(request: proto::ModelInferRequest) -> Self {
+        use grpc_server::model_infer_request::{InferInputTensor, InferRequestedOutputTensor};
+
+        Self {
+            model_name: request.model_name,
+            model_version: request.model_version,
+            id: request.id,
+            parameters: parameters(request.parameters),
+            inputs: request
+                .inputs
+                .into_iter()
+                .map(|input| InferInputTensor {
+                    name: input.name,
+                    datatype: input.datatype,
+                    shape: input.shape,
+                    parameters: parameters(input.parameters),
+                    contents: input.contents.as_ref().map(transcode),
+                })
+                .collect(),
+            outputs: request
+                .outputs
+                .into_iter()
+                .map(|output| InferRequestedOutputTensor {
+                    name: output.name,
+                    parameters: parameters(output.parameters),
+                })
+                .collect(),
+            raw_input_contents: request.raw_input_contents,
+            capabilities: None,
+        }
+    }
+}
+
+impl From<grpc_server::ModelInferResponse> for proto::ModelInferResponse {
+    fn from(response: grpc_server::ModelInferResponse) -> Self {
+        use proto::model_infer_response::InferOutputTensor;
+
+        Self {
+            model_name: response.model_name,
+            model_version: response.model_version,
+            id: response.id,
+            parameters: parameters(response.parameters),
+            outputs: response
+                .outputs
+                .into_iter()
+                .map(|output| InferOutputTensor {
+                    name: output.name,
+                    datatype: output.datatype,
+                    shape: output.shape,
+                    parameters: parameters(output.parameters),
+                    contents: output.contents.as_ref().map(transcode),
+                })
+                .collect(),
+            raw_output_contents: response.raw_output_contents,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcInferenceService for KServeService {
+    async fn server_live(
+        &self,
+        request: Request<proto::ServerLiveRequest>,
+    ) -> Result<Response<proto::ServerLiveResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(self.prediction.server_live(request).await, |message| {
+            transcode(&message)
+        })
+    }
+
+    async fn server_ready(
+        &self,
+        request: Request<proto::ServerReadyRequest>,
+    ) -> Result<Response<proto::ServerReadyResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(self.prediction.server_ready(request).await, |message| {
+            transcode(&message)
+        })
+    }
+
+    async fn model_ready(
+        &self,
+        request: Request<proto::ModelReadyRequest>,
+    ) -> Result<Response<proto::ModelReadyResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(self.prediction.model_ready(request).await, |message| {
+            transcode(&message)
+        })
+    }
+
+    async fn server_metadata(
+        &self,
+        request: Request<proto::ServerMetadataRequest>,
+    ) -> Result<Response<proto::ServerMetadataResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(self.prediction.server_metadata(request).await, |message| {
+            transcode(&message)
+        })
+    }
+
+    async fn model_metadata(
+        &self,
+        request: Request<proto::ModelMetadataRequest>,
+    ) -> Result<Response<proto::ModelMetadataResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        // The capabilities, unknown to the protocol, are dropped.
+        forward_response(self.prediction.model_metadata(request).await, |message| {
+            transcode(&message)
+        })
+    }
+
+    async fn model_infer(
+        &self,
+        request: Request<proto::ModelInferRequest>,
+    ) -> Result<Response<proto::ModelInferResponse>, Status> {
+        let request = forward_request(request, Into::into);
+        forward_response(self.prediction.model_infer(request).await, Into::into)
+    }
+
+    async fn system_shared_memory_status(
+        &self,
+        request: Request<proto::SystemSharedMemoryStatusRequest>,
+    ) -> Result<Response<proto::SystemSharedMemoryStatusResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(
+            self.prediction.system_shared_memory_status(request).await,
+            |message| transcode(&message),
+        )
+    }
+
+    async fn system_shared_memory_register(
+        &self,
+        request: Request<proto::SystemSharedMemoryRegisterRequest>,
+    ) -> Result<Response<proto::SystemSharedMemoryRegisterResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(
+            self.prediction.system_shared_memory_register(request).await,
+            |message| transcode(&message),
+        )
+    }
+
+    async fn system_shared_memory_unregister(
+        &self,
+        request: Request<proto::SystemSharedMemoryUnregisterRequest>,
+    ) -> Result<Response<proto::SystemSharedMemoryUnregisterResponse>, Status> {
+        let request = forward_request(request, |message| transcode(&message));
+        forward_response(
+            self.prediction
+                .system_shared_memory_unregister(request)
+                .await,
+            |message| transcode(&message),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundation::{
+        FakeInferenceProcessor, ModelDiscoveryService, ModelVersionId, ProcessorRuntime,
+    };
+    use proto::infer_parameter::ParameterChoice as KServeChoice;
+    use proto::model_infer_request::InferInputTensor;
+    use std::sync::Arc;
+
+    /// The KServe service of a server serving version 1 of model `m` with the fake processor.
+    fn service() -> KServeService {
+        let model_manager = ModelDiscoveryService::new(10);
+        model_manager.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", FakeInferenceProcessor)),
+        );
+        KServeService {
+            prediction: PredictionServiceImpl::new(Arc::new(model_manager)),
+        }
+    }
+
+    fn parameter(choice: KServeChoice) -> proto::InferParameter {
+        proto::InferParameter {
+            parameter_choice: Some(choice),
+        }
+    }
+
+    fn infer_request(model_name: &str) -> proto::ModelInferRequest {
+        proto::ModelInferRequest {
+            model_name: model_name.to_string(),
+            id: "1".to_string(),
+            parameters: HashMap::from([(
+                "temperature".to_string(),
+                parameter(KServeChoice::DoubleParam(0.5)),
+            )]),
+            inputs: vec![InferInputTensor {
+                name: "x".to_string(),
+                datatype: "FP32".to_string(),
+                shape: vec![2],
+                ..Default::default()
+            }],
+            raw_input_contents: vec![[1f32.to_le_bytes(), 2f32.to_le_bytes()].concat().into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parameters_are_translated_by_their_choice() {
+        use grpc_server::infer_parameter::ParameterChoice;
+
+        let translated = |choice| {
+            grpc_server::InferParameter::from(parameter(choice))
+                .parameter_choice
+                .unwrap()
+        };
+        assert_eq!(
+            translated(KServeChoice::DoubleParam(0.5)),
+            ParameterChoice::F64Param(0.5)
+        );
+        assert_eq!(
+            translated(KServeChoice::StringParam("a".to_string())),
+            ParameterChoice::StringParam("a".to_string())
+        );
+        assert_eq!(
+            translated(KServeChoice::Uint64Param(7)),
+            ParameterChoice::Int64Param(7)
+        );
+        assert_eq!(
+            translated(KServeChoice::Uint64Param(u64::MAX)),
+            ParameterChoice::F64Param(u64::MAX as f64)
+        );
+
+        let back = proto::InferParameter::from(grpc_server::InferParameter {
+            parameter_choice: Some(ParameterChoice::F64Param(0.5)),
+        });
+        assert_eq!(back.parameter_choice, Some(KServeChoice::DoubleParam(0.5)));
+    }
+
+    #[tokio::test]
+    async fn test_inferences_are_served_by_the_prediction_handler() {
+        let service = service();
+        let response = service
+            .model_infer(Request::new(infer_request("m")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.model_name, "m");
+        assert_eq!(response.model_version, "1");
+        assert_eq!(response.id, "1");
+        let output = &response.outputs[0];
+        assert_eq!(output.name, "output_1");
+        assert_eq!(
+            output.parameters["confidence"].parameter_choice,
+            Some(KServeChoice::DoubleParam(0.33))
+        );
+
+        let ready = service
+            .model_ready(Request::new(proto::ModelReadyRequest {
+                name: "m".to_string(),
+                version: String::new(),
+            }))
+            .await
+            .unwrap();
+        assert!(ready.into_inner().ready);
+        let live = service
+            .server_live(Request::new(proto::ServerLiveRequest {}))
+            .await
+            .unwrap();
+        assert!(live.into_inner().live);
+    }
+
+    #[tokio::test]
+    async fn test_refusals_keep_their_status() {
+        let service = service();
+        let status = service
+            .model_infer(Request::new(infer_request("unknown")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let mut request = infer_request("m");
+        request.raw_input_contents[0].truncate(7);
+        let status = service
+            .model_infer(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
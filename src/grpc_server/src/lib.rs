@@ -1,17 +1,49 @@
+// `tonic::Status` is large by design and is the natural error type of every handler helper.
+#![allow(clippy::result_large_err)]
+
+mod audit;
+mod auth;
+mod connection;
+mod correlation;
+mod debug;
+mod flight;
+mod health;
+mod kserve;
+mod quota;
+mod rate_limit;
+mod schema;
+mod status;
+mod traffic;
 mod translator;
+mod web;
 
 use async_trait::async_trait;
-use foundation::api::inference::InferParameter;
+use bytes::Bytes;
+use foundation::api::inference::{InferParameter, InferenceOutput, InferenceResponse};
 use foundation::{
-    InferenceRequest, InferenceServerBuilder, InferenceServerConfig, ModelDiscoveryService, ModelId,
+    AnalyticsRecord, AnalyticsTee, AuditLogger, Authenticator, BufferRefusal, CircuitOpen,
+    ConcurrencyLimiter, ConnectionLimits, GRPC_TIMEOUT_HEADER, HINTS_IGNORED_PARAMETER, IdProvider,
+    IdScheme, IgnoredHint, InFlightPermit, InferenceRequest, InferenceServerBuilder,
+    InferenceServerConfig, LabelSelector, Listener, MODEL_SELECTOR_HEADER, ModelDiscoveryService,
+    ModelId, ModelPaused, OverloadController, PRIORITY_HEADER, Priority, Protocol, QuotaTracker,
+    REQUEST_TIMEOUT_HEADER, RateLimiter, Refusal, ReloadableTls, Role, SamplingOptions,
+    SharedMemoryRegistry, SharedPort, ShutdownSignal, StreamPacing, TENANT_HEADER, Target,
+    TlsConfig, TrafficAccounting, log_debug, log_info, parse_grpc_timeout, parse_timeout_ms,
 };
-use futures::Stream;
+use futures::{FutureExt, Stream};
+use prost::Message;
 use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendTimeoutError;
 use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::{AxumRouter, Routes};
 use tonic::{Request, Response, Status, transport::Server};
+use tonic_web::GrpcWebLayer;
 
 // Include the generated protobuf code
 pub mod grpc_server {
@@ -19,21 +51,525 @@ pub mod grpc_server {
 }
 
 use grpc_server::{
+    LatencyStatistics, ModelIndexRequest, ModelIndexResponse, ModelInferBatchResponse,
     ModelInferRequest, ModelInferResponse, ModelMetadataRequest, ModelMetadataResponse,
-    ModelReadyRequest, ModelReadyResponse, ServerLiveRequest, ServerLiveResponse,
-    ServerMetadataRequest, ServerMetadataResponse, ServerReadyRequest, ServerReadyResponse,
-    model_metadata_response::TensorMetadata,
+    ModelReadyRequest, ModelReadyResponse, ModelStatisticsRequest, ModelStatisticsResponse,
+    RouteStatistics, ServerLiveRequest, ServerLiveResponse, ServerMetadataRequest,
+    ServerMetadataResponse, ServerReadyRequest, ServerReadyResponse,
+    SystemSharedMemoryRegisterRequest, SystemSharedMemoryRegisterResponse,
+    SystemSharedMemoryStatusRequest, SystemSharedMemoryStatusResponse,
+    SystemSharedMemoryUnregisterRequest, SystemSharedMemoryUnregisterResponse,
+    model_index_response,
+    model_infer_batch_response::{self, RequestError},
     prediction_service_server::{PredictionService, PredictionServiceServer},
+    system_shared_memory_status_response,
 };
 
+/// Requests accepted on a single `ModelInferBatch` stream.
+const MAX_BATCH_REQUESTS: usize = 10_000;
+
+#[derive(Clone)]
 pub struct PredictionServiceImpl {
     model_manager: Arc<ModelDiscoveryService>,
+    analytics: Option<AnalyticsTee>,
+    audit: Option<AuditLogger>,
+    overload: Arc<OverloadController>,
+    ids: Arc<dyn IdProvider>,
+    rate_limiter: Arc<RateLimiter>,
+    concurrency: Arc<ConcurrencyLimiter>,
+    traffic: Arc<TrafficAccounting>,
+    quotas: Arc<QuotaTracker>,
+    shared_memory: Arc<SharedMemoryRegistry>,
+    stream_pacing: StreamPacing,
+    authenticator: Option<Authenticator>,
+    trust_tenant_header: bool,
 }
 
 impl PredictionServiceImpl {
     pub fn new(model_manager: Arc<ModelDiscoveryService>) -> Self {
-        Self { model_manager }
+        Self {
+            model_manager,
+            analytics: None,
+            audit: None,
+            overload: Arc::new(OverloadController::default()),
+            ids: IdScheme::default().provider(),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            concurrency: Arc::new(ConcurrencyLimiter::default()),
+            traffic: Arc::new(TrafficAccounting::default()),
+            quotas: Arc::new(QuotaTracker::default()),
+            shared_memory: Arc::new(SharedMemoryRegistry::default()),
+            stream_pacing: StreamPacing::default(),
+            authenticator: None,
+            trust_tenant_header: false,
+        }
+    }
+
+    /// Applies per-model rate limits to every message, see `rate_limit`.
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Sheds messages beyond the concurrency limits, shared with the other servers.
+    pub fn with_concurrency(mut self, concurrency: Arc<ConcurrencyLimiter>) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Counts the bytes of each tenant and applies the traffic limits, see `traffic`.
+    pub fn with_traffic(mut self, traffic: Arc<TrafficAccounting>) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    /// Charges every inference to the account of its caller, see `quota`.
+    pub fn with_quotas(mut self, quotas: Arc<QuotaTracker>) -> Self {
+        self.quotas = quotas;
+        self
     }
+
+    /// Reads and writes the tensors in shared memory in the regions of `shared_memory`,
+    /// shared with the other servers.
+    pub fn with_shared_memory(mut self, shared_memory: Arc<SharedMemoryRegistry>) -> Self {
+        self.shared_memory = shared_memory;
+        self
+    }
+
+    /// Buffers at most `max_buffered` responses of a `ModelInferAsync` stream, and ends
+    /// streams whose client takes none for the stall timeout.
+    pub fn with_stream_pacing(mut self, pacing: StreamPacing) -> Self {
+        self.stream_pacing = pacing;
+        self
+    }
+
+    /// Generates ids for requests that arrive without one.
+    pub fn with_ids(mut self, ids: Arc<dyn IdProvider>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Shares load tracking (and degraded mode) with the other servers.
+    pub fn with_overload(mut self, overload: Arc<OverloadController>) -> Self {
+        self.overload = overload;
+        self
+    }
+
+    /// Tees every response sent on `ModelInferAsync` streams to `analytics`.
+    pub fn with_analytics(mut self, analytics: Option<AnalyticsTee>) -> Self {
+        self.analytics = analytics;
+        self
+    }
+
+    /// Requires an API key or token accepted by `authenticator` on every call, with the
+    /// role of the call and scoped to the models it uses.
+    pub fn with_authenticator(mut self, authenticator: Option<Authenticator>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
+    /// Takes the tenant of calls from the tenant metadata entry, set by a trusted proxy,
+    /// instead of their account. See `foundation::request_tenant`.
+    pub fn with_trusted_tenant_header(mut self, trust: bool) -> Self {
+        self.trust_tenant_header = trust;
+        self
+    }
+
+    /// Records every inference message in the audit log, see `audit`.
+    pub fn with_audit(mut self, audit: Option<AuditLogger>) -> Self {
+        self.audit = audit;
+        self
+    }
+}
+
+/// Counts one message of `model` as in flight until the permit is dropped, or sheds it
+/// when the model is paused or the server or the model is at its concurrency limit.
+fn admit_in_flight(
+    service: &PredictionServiceImpl,
+    model_name: &str,
+) -> Result<InFlightPermit, Status> {
+    let model_id = ModelId(model_name.to_string());
+    service
+        .model_manager
+        .check_paused(&model_id)
+        .map_err(status::paused_status)?;
+    // Unknown models only count globally, so made-up names are not tracked one by one.
+    let model = service
+        .model_manager
+        .has_model(&model_id)
+        .then_some(model_name);
+    service
+        .concurrency
+        .try_acquire(model)
+        .map_err(|shed| Status::unavailable(shed.to_string()))
+}
+
+/// Enqueues `request` in its model's buffer and waits for the outputs of the model,
+/// counting it in the statistics of `route`.
+async fn run_inference(
+    model_manager: &Arc<ModelDiscoveryService>,
+    route: &str,
+    request: InferenceRequest,
+) -> Result<Vec<InferenceOutput>, Status> {
+    let Some(version) = request.model_version.clone() else {
+        return Err(Status::unavailable(format!(
+            "Model '{}' has no loaded versions",
+            request.model_name
+        )));
+    };
+    let model_name = request.model_name.clone();
+    let started = Instant::now();
+    let result = infer_outputs(model_manager, request).await;
+    model_manager.stats().record(
+        &model_name,
+        &version,
+        route,
+        started.elapsed(),
+        result.is_ok(),
+    );
+    result
+}
+
+/// Refuses `request` if its model lacks one of the `capabilities` it relies on.
+fn check_capabilities(
+    model_manager: &ModelDiscoveryService,
+    request: &InferenceRequest,
+    capabilities: Option<grpc_server::Capabilities>,
+) -> Result<(), Status> {
+    model_manager
+        .check_capabilities(
+            &request.model_name,
+            request.parameters.as_ref(),
+            capabilities.map(Into::into).unwrap_or_default(),
+        )
+        .map_err(|refusal| status::api_status(refusal.into()))
+}
+
+/// Refuses a request whose input tensors do not match the signature of its model.
+fn check_inputs(
+    model_manager: &ModelDiscoveryService,
+    req: &ModelInferRequest,
+    model_version: Option<&str>,
+) -> Result<(), Status> {
+    model_manager
+        .check_inputs(
+            &req.model_name,
+            model_version,
+            &translator::input_metadata(&req.inputs),
+        )
+        .map_err(|refusal| status::api_status(refusal.into()))
+}
+
+async fn infer_outputs(
+    model_manager: &Arc<ModelDiscoveryService>,
+    mut request: InferenceRequest,
+) -> Result<Vec<InferenceOutput>, Status> {
+    model_manager
+        .check_context(&request)
+        .map_err(|refusal| status::api_status(refusal.into()))?;
+    request.sampling = SamplingOptions::from_parameters(request.parameters.as_ref())
+        .map_err(|refusal| status::api_status(refusal.into()))?;
+    let response = model_manager
+        .add_request(ModelId(request.model_name.clone()), request)
+        .await
+        // Paused models, open circuits and unloaded models are unavailable, unknown models
+        // not found and full buffers exhausted.
+        .map_err(|e| match e.downcast::<ModelPaused>() {
+            Ok(paused) => status::paused_status(paused),
+            Err(e) => match e.downcast::<CircuitOpen>() {
+                Ok(open) => status::api_status(open.into()),
+                Err(e) => match e.downcast::<BufferRefusal>() {
+                    Ok(refusal) => status::api_status(refusal.into()),
+                    Err(e) => Status::internal(e.to_string()),
+                },
+            },
+        })?;
+    match response.await {
+        Ok(InferenceResponse::Ok(output)) => Ok(vec![output]),
+        Ok(InferenceResponse::Error(e)) => Err(Status::internal(e.error)),
+        Ok(InferenceResponse::DeadlineExceeded(e)) => Err(Status::deadline_exceeded(e.error)),
+        Err(_) => Err(Status::unavailable(
+            "Request was dropped from the full request buffer of the model",
+        )),
+    }
+}
+
+/// Response parameters reporting the execution hints that were ignored, if any.
+fn hint_parameters(ignored: &[IgnoredHint]) -> HashMap<String, grpc_server::InferParameter> {
+    if ignored.is_empty() {
+        return HashMap::new();
+    }
+    HashMap::from([(
+        HINTS_IGNORED_PARAMETER.to_string(),
+        InferParameter::String(IgnoredHint::describe(ignored)).into(),
+    )])
+}
+
+/// Records an answered inference of `model_name` in the request histograms.
+fn record_inference<T>(
+    service: &PredictionServiceImpl,
+    model_name: &str,
+    result: &Result<T, Status>,
+    started: Instant,
+) {
+    let code = match result {
+        Ok(_) => tonic::Code::Ok,
+        Err(status) => status.code(),
+    };
+    // Unknown models share one series, so made-up names are not tracked one by one.
+    let model = service
+        .model_manager
+        .has_model(&ModelId(model_name.to_string()))
+        .then_some(model_name);
+    service.model_manager.metrics().record_request(
+        "grpc",
+        model,
+        &format!("{:?}", code),
+        started.elapsed(),
+    );
+}
+
+/// Runs one message of a request stream and records it in the request histograms and the
+/// audit log, under the identity of the call's `caller`.
+async fn infer_message(
+    service: &PredictionServiceImpl,
+    route: &str,
+    metadata: &MetadataMap,
+    caller: &Option<String>,
+    priority: Priority,
+    call_started: Instant,
+    req: ModelInferRequest,
+) -> Result<ModelInferResponse, Status> {
+    let started = Instant::now();
+    let model_name = req.model_name.clone();
+    let record = service
+        .audit
+        .as_ref()
+        .map(|_| audit::begin(&req, metadata, caller.clone()));
+    let result = run_message(service, route, metadata, priority, call_started, req).await;
+    record_inference(service, &model_name, &result, started);
+    if let (Some(logger), Some(record)) = (&service.audit, record) {
+        audit::finish(logger, record, result.as_ref(), started);
+    }
+    result
+}
+
+/// Runs one message of a request stream: routing, version resolution, schema
+/// negotiation and inference. Messages without an id get a generated one.
+async fn run_message(
+    service: &PredictionServiceImpl,
+    route: &str,
+    metadata: &MetadataMap,
+    priority: Priority,
+    call_started: Instant,
+    mut req: ModelInferRequest,
+) -> Result<ModelInferResponse, Status> {
+    let model_manager = &service.model_manager;
+    let started = Instant::now();
+    let deadline = request_deadline(metadata, call_started, started)?;
+    let tenant = admit_tenant(model_manager, metadata)?;
+    traffic::account_message(&service.traffic, tenant.as_deref(), req.encoded_len())?;
+    let timeline = debug::request_timeline(metadata, started);
+    route_by_selector(model_manager, metadata, &mut req)?;
+    let authenticated = auth::authorize(
+        service.authenticator.as_ref(),
+        metadata,
+        Role::Infer,
+        Target::Model(&req.model_name),
+    )?;
+    rate_limit::limit_model(&service.rate_limiter, &req.model_name)?;
+    let _in_flight = admit_in_flight(service, &req.model_name)?;
+    let model_version = resolve_model_version(model_manager, &req.model_name, &req.model_version)?;
+    let negotiated = Instant::now();
+    let plan = schema::negotiate_request(model_manager, metadata, &mut req)?;
+    if let Some(timeline) = &timeline {
+        timeline.span("parse", negotiated, plan.client_version.clone());
+    }
+    if req.id.is_empty() {
+        req.id = service.ids.next_id();
+    }
+    let casts = translator::output_casts(&req.outputs)?;
+    check_inputs(model_manager, &req, model_version.as_deref())?;
+    let locations = translator::output_locations(&req.outputs)?;
+    let inputs =
+        translator::input_tensors(req.inputs, req.raw_input_contents, &service.shared_memory)?;
+
+    let parameters = req
+        .parameters
+        .into_iter()
+        .map(|(k, v)| (k, InferParameter::from(v)))
+        .collect::<HashMap<_, _>>();
+
+    let mut inference_request = InferenceRequest {
+        model_name: req.model_name.clone(),
+        model_version: model_version.clone(),
+        id: req.id.clone(),
+        parameters: Some(parameters),
+        outputs: Some(inputs),
+        timeline: timeline.clone(),
+        priority,
+        deadline,
+        tenant: tenant.clone(),
+        sampling: Default::default(),
+    };
+    service.overload.apply(&mut inference_request);
+    check_capabilities(model_manager, &inference_request, req.capabilities.take())?;
+    quota::charge(
+        &service.quotas,
+        model_manager,
+        authenticated,
+        &inference_request,
+    )?;
+    let ignored_hints = model_manager.ignored_hints(&inference_request);
+
+    let outputs = run_inference(model_manager, route, inference_request).await?;
+    let (outputs, raw_output_contents) =
+        translator::output_tensors(outputs, &casts, &locations, &service.shared_memory)?;
+
+    let mut response = ModelInferResponse {
+        model_name: req.model_name,
+        model_version: model_version.unwrap_or_default(),
+        id: req.id,
+        parameters: hint_parameters(&ignored_hints),
+        outputs,
+        raw_output_contents,
+    };
+    let downgraded = Instant::now();
+    schema::downgrade_response(&plan, &mut response)?;
+    if let Some(timeline) = &timeline {
+        timeline.span("schema.downgrade", downgraded, None);
+    }
+    debug::attach(timeline.as_deref(), &mut response);
+    service
+        .traffic
+        .record_out(tenant.as_deref(), response.encoded_len() as u64);
+    Ok(response)
+}
+
+/// Sends `item` on a response stream. False when the client went away, or took nothing for
+/// the stall timeout of `pacing`, and the stream should end.
+async fn send_paced<T>(tx: &mpsc::Sender<T>, item: T, pacing: StreamPacing) -> bool {
+    match tx.send_timeout(item, pacing.stall_timeout).await {
+        Ok(()) => true,
+        Err(SendTimeoutError::Timeout(_)) => {
+            eprintln!(
+                "Ending response stream: the client read nothing for {}s",
+                pacing.stall_timeout.as_secs()
+            );
+            false
+        }
+        Err(SendTimeoutError::Closed(_)) => {
+            eprintln!("Error sending response: the client went away");
+            false
+        }
+    }
+}
+
+/// Summarizes a streamed response for the analytics sink.
+fn analytics_payload(response: &ModelInferResponse) -> serde_json::Value {
+    let outputs: Vec<serde_json::Value> = response
+        .outputs
+        .iter()
+        .map(|output| {
+            serde_json::json!({
+                "name": output.name,
+                "datatype": output.datatype,
+                "shape": output.shape,
+            })
+        })
+        .collect();
+    let raw_output_bytes: usize = response.raw_output_contents.iter().map(Bytes::len).sum();
+
+    serde_json::json!({
+        "outputs": outputs,
+        "raw_output_bytes": raw_output_bytes,
+    })
+}
+
+/// Resolves the version serving `model_name`, treating an empty `model_version` as unspecified.
+fn resolve_model_version(
+    model_manager: &ModelDiscoveryService,
+    model_name: &str,
+    model_version: &str,
+) -> Result<Option<String>, Status> {
+    let requested = (!model_version.is_empty()).then_some(model_version);
+    model_manager
+        .resolve_version(&ModelId(model_name.to_string()), requested)
+        .map(|resolved| resolved.map(|version_id| version_id.version))
+        .map_err(|e| Status::not_found(e.to_string()))
+}
+
+/// Replaces the model named by `req` with one chosen by the model selector metadata entry.
+fn route_by_selector(
+    model_manager: &ModelDiscoveryService,
+    metadata: &MetadataMap,
+    req: &mut ModelInferRequest,
+) -> Result<(), Status> {
+    let Some(selector) = metadata.get(MODEL_SELECTOR_HEADER) else {
+        return Ok(());
+    };
+    let selector = selector
+        .to_str()
+        .unwrap_or_default()
+        .parse::<LabelSelector>()
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    let model_id = model_manager.route(&selector).ok_or_else(|| {
+        Status::not_found(format!("No served model matches selector '{}'", selector))
+    })?;
+    req.model_name = model_id.0;
+    Ok(())
+}
+
+/// Priority class from the priority metadata entry, `normal` without one.
+fn request_priority(metadata: &MetadataMap) -> Result<Priority, Status> {
+    let Some(priority) = metadata.get(PRIORITY_HEADER) else {
+        return Ok(Priority::default());
+    };
+    priority
+        .to_str()
+        .unwrap_or_default()
+        .parse::<Priority>()
+        .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+/// Tenant of the call, as `auth::identify_tenant` left it in the tenant metadata entry,
+/// turned away while its error budget is exhausted.
+fn admit_tenant(
+    model_manager: &ModelDiscoveryService,
+    metadata: &MetadataMap,
+) -> Result<Option<String>, Status> {
+    let tenant = metadata
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok())
+        .map(str::to_string);
+    match model_manager.error_budget().admit(tenant.as_deref()) {
+        Ok(()) => Ok(tenant),
+        Err(refusal @ Refusal::Throttled(_)) => {
+            Err(Status::resource_exhausted(refusal.to_string()))
+        }
+        Err(refusal @ Refusal::Quarantined(_)) => {
+            Err(Status::permission_denied(refusal.to_string()))
+        }
+    }
+}
+
+/// Deadline of a request arriving at `arrived` on a call started at `call_started`: the
+/// gRPC deadline of the call, or else the request timeout counted from the arrival.
+fn request_deadline(
+    metadata: &MetadataMap,
+    call_started: Instant,
+    arrived: Instant,
+) -> Result<Option<Instant>, Status> {
+    let deadline = if let Some(timeout) = metadata.get(GRPC_TIMEOUT_HEADER) {
+        parse_grpc_timeout(timeout.to_str().unwrap_or_default())
+            .map(|timeout| call_started + timeout)
+    } else if let Some(timeout) = metadata.get(REQUEST_TIMEOUT_HEADER) {
+        parse_timeout_ms(timeout.to_str().unwrap_or_default()).map(|timeout| arrived + timeout)
+    } else {
+        return Ok(None);
+    };
+    deadline
+        .map(Some)
+        .map_err(|e| Status::invalid_argument(e.to_string()))
 }
 
 #[tonic::async_trait]
@@ -45,7 +581,7 @@ impl PredictionService for PredictionServiceImpl {
         &self,
         request: Request<ServerLiveRequest>,
     ) -> Result<Response<ServerLiveResponse>, Status> {
-        println!("Got a request: {:?}", request);
+        log_debug!("Got a request: {:?}", request);
 
         let reply = ServerLiveResponse { live: true };
 
@@ -56,9 +592,11 @@ impl PredictionService for PredictionServiceImpl {
         &self,
         request: Request<ServerReadyRequest>,
     ) -> Result<Response<ServerReadyResponse>, Status> {
-        println!("Got a request: {:?}", request);
+        log_debug!("Got a request: {:?}", request);
 
-        let reply = ServerReadyResponse { ready: true };
+        let reply = ServerReadyResponse {
+            ready: self.model_manager.readiness_failures().is_empty(),
+        };
 
         Ok(Response::new(reply))
     }
@@ -67,9 +605,20 @@ impl PredictionService for PredictionServiceImpl {
         &self,
         request: Request<ModelReadyRequest>,
     ) -> Result<Response<ModelReadyResponse>, Status> {
-        println!("Got a request: {:?}", request);
+        log_debug!("Got a request: {:?}", request);
 
-        let reply = ModelReadyResponse { ready: true };
+        auth::authorize(
+            self.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            Target::Model(&request.get_ref().name),
+        )?;
+        let req = request.into_inner();
+        let ready = self.model_manager.is_model_ready(
+            &ModelId(req.name),
+            Some(req.version.as_str()).filter(|version| !version.is_empty()),
+        );
+        let reply = ModelReadyResponse { ready };
 
         Ok(Response::new(reply))
     }
@@ -78,7 +627,7 @@ impl PredictionService for PredictionServiceImpl {
         &self,
         request: Request<ServerMetadataRequest>,
     ) -> Result<Response<ServerMetadataResponse>, Status> {
-        println!("Got a request: {:?}", request);
+        log_debug!("Got a request: {:?}", request);
 
         let reply = ServerMetadataResponse {
             name: "server_metadata".to_string(),
@@ -93,85 +642,93 @@ impl PredictionService for PredictionServiceImpl {
         &self,
         request: Request<ModelMetadataRequest>,
     ) -> Result<Response<ModelMetadataResponse>, Status> {
-        println!("Got a request: {:?}", request);
-
-        let reply = ModelMetadataResponse {
-            name: "model_metadata".to_string(),
-            versions: vec!["v1.0.0".to_string(), "v2.0.0".to_string()],
-            platform: "platform".to_string(),
-            inputs: vec![
-                TensorMetadata {
-                    name: "tensor_metadata_input1".to_string(),
-                    datatype: "float32".to_string(),
-                    shape: vec![1, 224, 224, 3],
-                },
-                TensorMetadata {
-                    name: "tensor_metadata_input2".to_string(),
-                    datatype: "int64".to_string(),
-                    shape: vec![1],
-                },
-            ],
-            outputs: vec![
-                TensorMetadata {
-                    name: "tensor_metadata_output1".to_string(),
-                    datatype: "float32".to_string(),
-                    shape: vec![1, 1000],
-                },
-                TensorMetadata {
-                    name: "tensor_metadata_output2".to_string(),
-                    datatype: "int64".to_string(),
-                    shape: vec![1],
-                },
-            ],
-        };
+        log_debug!("Got a request: {:?}", request);
 
-        Ok(Response::new(reply))
+        auth::authorize(
+            self.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            Target::Model(&request.get_ref().name),
+        )?;
+        let req = request.into_inner();
+        let requested = (!req.version.is_empty()).then_some(req.version.as_str());
+        let metadata = self
+            .model_manager
+            .model_metadata(&ModelId(req.name.clone()), requested)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+
+        Ok(Response::new(ModelMetadataResponse::from(metadata)))
     }
 
     async fn model_infer_async(
         &self,
         request: Request<tonic::Streaming<ModelInferRequest>>,
     ) -> Result<Response<Self::ModelInferAsyncStream>, Status> {
+        // Stream metadata selects the schema version of every message without a parameter.
+        let metadata = request.metadata().clone();
+        let caller = audit::caller(&metadata, request.remote_addr());
+        // Stream metadata identifies the whole stream; messages without an id get their own.
+        let correlation_id = correlation::external_id(&metadata);
+        // The priority metadata entry applies to every message of the stream.
+        let priority = request_priority(&metadata)?;
+        let call_started = Instant::now();
+        request_deadline(&metadata, call_started, call_started)?;
         let mut stream = request.into_inner();
-        let (tx, rx) = mpsc::channel(4);
+        // The next message is read once the response of the last one is queued, so a client
+        // that stops reading responses pauses the stream instead of responses piling up.
+        let pacing = self.stream_pacing;
+        let (tx, rx) = mpsc::channel(pacing.max_buffered.max(1));
 
-        let model_manager = self.model_manager.clone();
+        let service = self.clone();
 
         tokio::spawn(async move {
+            let PredictionServiceImpl {
+                analytics,
+                overload,
+                ..
+            } = service.clone();
+            let mut sequence = 0;
             while let Some(message) = stream.message().await.transpose() {
                 match message {
                     Ok(req) => {
-                        let model_id = ModelId(req.id.clone());
-
-                        let parameters = req
-                            .parameters
-                            .into_iter()
-                            .map(|(k, v)| (k, InferParameter::from(v)))
-                            .collect::<HashMap<_, _>>();
-
-                        let inference_request = InferenceRequest {
-                            model_name: req.model_name.clone(),
-                            model_version: Some(req.model_version.clone()),
-                            id: req.id.clone(),
-                            parameters: Some(parameters),
-                            outputs: None,
-                        };
-
-                        model_manager.add_request(model_id, inference_request);
-
-                        // ACK/dummy responses if needed
-                        let response = ModelInferResponse {
-                            model_name: req.model_name,
-                            model_version: req.model_version,
-                            id: req.id,
-                            parameters: HashMap::new(),
-                            outputs: vec![],
-                            raw_output_contents: vec![],
+                        let _load = overload.begin();
+                        let response = match infer_message(
+                            &service,
+                            "grpc.ModelInferAsync",
+                            &metadata,
+                            &caller,
+                            priority,
+                            call_started,
+                            req,
+                        )
+                        .await
+                        {
+                            Ok(response) => response,
+                            Err(status) => {
+                                if !send_paced(&tx, Err(status), pacing).await {
+                                    break;
+                                }
+                                continue;
+                            }
                         };
-                        if let Err(e) = tx.send(Ok(response)).await {
-                            eprintln!("Error sending response: {:?}", e);
+                        // Build the record before sending, but only tee what the client received.
+                        let record = analytics.as_ref().map(|_| {
+                            AnalyticsRecord::new(
+                                response.model_name.clone(),
+                                Some(response.model_version.clone())
+                                    .filter(|version| !version.is_empty()),
+                                response.id.clone(),
+                                sequence,
+                                analytics_payload(&response),
+                            )
+                        });
+                        if !send_paced(&tx, Ok(response), pacing).await {
                             break;
                         }
+                        if let (Some(analytics), Some(record)) = (&analytics, record) {
+                            analytics.tee(record);
+                        }
+                        sequence += 1;
                     }
                     Err(e) => {
                         eprintln!("Error reading stream: {:?}", e);
@@ -181,19 +738,286 @@ impl PredictionService for PredictionServiceImpl {
             }
         });
 
-        Ok(Response::new(
-            Box::pin(ReceiverStream::new(rx)) as Self::ModelInferAsyncStream
+        Ok(correlation::with_correlation_id(
+            correlation_id,
+            Response::new(Box::pin(ReceiverStream::new(rx)) as Self::ModelInferAsyncStream),
         ))
     }
 
+    async fn model_infer_batch(
+        &self,
+        request: Request<tonic::Streaming<ModelInferRequest>>,
+    ) -> Result<Response<ModelInferBatchResponse>, Status> {
+        // As on ModelInferAsync, stream metadata applies to every message.
+        let metadata = Arc::new(request.metadata().clone());
+        let caller = Arc::new(audit::caller(&metadata, request.remote_addr()));
+        let correlation_id = correlation::external_id(&metadata);
+        let priority = request_priority(&metadata)?;
+        let call_started = Instant::now();
+        request_deadline(&metadata, call_started, call_started)?;
+        let mut stream = request.into_inner();
+
+        // Requests run as they arrive; the response waits for the last of them.
+        let mut pending = Vec::new();
+        while let Some(req) = stream.message().await? {
+            if pending.len() == MAX_BATCH_REQUESTS {
+                return Err(Status::resource_exhausted(format!(
+                    "A batch holds at most {} requests",
+                    MAX_BATCH_REQUESTS
+                )));
+            }
+            let id = req.id.clone();
+            let load = self.overload.begin();
+            let service = self.clone();
+            let (metadata, caller) = (metadata.clone(), caller.clone());
+            let inference = tokio::spawn(async move {
+                let _load = load;
+                infer_message(
+                    &service,
+                    "grpc.ModelInferBatch",
+                    &metadata,
+                    &caller,
+                    priority,
+                    call_started,
+                    req,
+                )
+                .await
+            });
+            pending.push((id, inference));
+        }
+
+        let mut batch = ModelInferBatchResponse::default();
+        for (id, inference) in pending {
+            let result = inference
+                .await
+                .unwrap_or_else(|e| Err(Status::internal(e.to_string())));
+            let result = match result {
+                Ok(response) => {
+                    batch.succeeded += 1;
+                    model_infer_batch_response::result::Result::Response(response)
+                }
+                Err(status) => {
+                    batch.failed += 1;
+                    model_infer_batch_response::result::Result::Error(RequestError {
+                        id,
+                        code: status.code() as i32,
+                        message: status.message().to_string(),
+                    })
+                }
+            };
+            batch.results.push(model_infer_batch_response::Result {
+                result: Some(result),
+            });
+        }
+
+        Ok(correlation::with_correlation_id(
+            correlation_id,
+            Response::new(batch),
+        ))
+    }
+
+    async fn model_statistics(
+        &self,
+        request: Request<ModelStatisticsRequest>,
+    ) -> Result<Response<ModelStatisticsResponse>, Status> {
+        let target = match request.get_ref().name.as_str() {
+            "" => Target::AllModels,
+            name => Target::Model(name),
+        };
+        auth::authorize(
+            self.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            target,
+        )?;
+        let req = request.into_inner();
+        let statistics = self
+            .model_manager
+            .stats()
+            .snapshot(
+                Some(req.name.as_str()).filter(|name| !name.is_empty()),
+                Some(req.version.as_str()).filter(|version| !version.is_empty()),
+            )
+            .into_iter()
+            .map(|stats| RouteStatistics {
+                name: stats.model,
+                version: stats.version,
+                route: stats.route,
+                requests: stats.requests,
+                errors: stats.errors,
+                latency: Some(LatencyStatistics {
+                    count: stats.latency.count,
+                    mean_ms: stats.latency.mean_ms,
+                    p50_ms: stats.latency.p50_ms,
+                    p90_ms: stats.latency.p90_ms,
+                    p99_ms: stats.latency.p99_ms,
+                    max_ms: stats.latency.max_ms,
+                }),
+            })
+            .collect();
+        Ok(Response::new(ModelStatisticsResponse { statistics }))
+    }
+
+    async fn model_index(
+        &self,
+        request: Request<ModelIndexRequest>,
+    ) -> Result<Response<ModelIndexResponse>, Status> {
+        log_debug!("Got a request: {:?}", request);
+
+        auth::authorize(
+            self.authenticator.as_ref(),
+            request.metadata(),
+            Role::ReadOnly,
+            Target::Server,
+        )?;
+        let req = request.into_inner();
+        let selector = match req.selector.as_str() {
+            "" => LabelSelector::default(),
+            selector => selector
+                .parse::<LabelSelector>()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+        };
+        let models = self
+            .model_manager
+            .select_models(&selector)
+            .iter()
+            .filter_map(|model_id| self.model_manager.model_summary(model_id))
+            .map(|summary| model_index_response::Entry {
+                name: summary.name,
+                versions: summary.versions,
+                backend: summary.backend.unwrap_or_default(),
+                ready: summary.ready,
+                labels: summary.labels.into_iter().collect(),
+            })
+            .collect();
+        Ok(Response::new(ModelIndexResponse { models }))
+    }
+
+    async fn system_shared_memory_status(
+        &self,
+        request: Request<SystemSharedMemoryStatusRequest>,
+    ) -> Result<Response<SystemSharedMemoryStatusResponse>, Status> {
+        self.authorize_shared_memory(request.metadata())?;
+        let name = Some(request.get_ref().name.as_str()).filter(|name| !name.is_empty());
+        let regions = self
+            .shared_memory
+            .system_status(name)
+            .map_err(|e| Status::not_found(e.to_string()))?
+            .into_iter()
+            .map(|region| {
+                let status = system_shared_memory_status_response::RegionStatus {
+                    name: region.name,
+                    key: region.key,
+                    offset: region.offset,
+                    byte_size: region.byte_size,
+                };
+                (status.name.clone(), status)
+            })
+            .collect();
+        Ok(Response::new(SystemSharedMemoryStatusResponse { regions }))
+    }
+
+    async fn system_shared_memory_register(
+        &self,
+        request: Request<SystemSharedMemoryRegisterRequest>,
+    ) -> Result<Response<SystemSharedMemoryRegisterResponse>, Status> {
+        self.authorize_shared_memory(request.metadata())?;
+        let req = request.into_inner();
+        self.shared_memory
+            .register_system(&req.name, &req.key, req.offset, req.byte_size)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(SystemSharedMemoryRegisterResponse {}))
+    }
+
+    async fn system_shared_memory_unregister(
+        &self,
+        request: Request<SystemSharedMemoryUnregisterRequest>,
+    ) -> Result<Response<SystemSharedMemoryUnregisterResponse>, Status> {
+        self.authorize_shared_memory(request.metadata())?;
+        let name = Some(request.get_ref().name.as_str()).filter(|name| !name.is_empty());
+        self.shared_memory.unregister_system(name);
+        Ok(Response::new(SystemSharedMemoryUnregisterResponse {}))
+    }
+
     async fn model_infer(
         &self,
         request: Request<ModelInferRequest>,
     ) -> Result<Response<ModelInferResponse>, Status> {
-        println!("Got a request: {:?}", request);
+        let started = Instant::now();
+        let model_name = request.get_ref().model_name.clone();
+        let record = self.audit.as_ref().map(|_| {
+            let caller = audit::caller(request.metadata(), request.remote_addr());
+            audit::begin(request.get_ref(), request.metadata(), caller)
+        });
+        let result = self.infer_unary(request).await;
+        record_inference(self, &model_name, &result, started);
+        if let (Some(logger), Some(record)) = (&self.audit, record) {
+            audit::finish(
+                logger,
+                record,
+                result.as_ref().map(Response::get_ref),
+                started,
+            );
+        }
+        result
+    }
+}
 
-        let req = request.into_inner();
-        let model_id = ModelId(req.id.clone());
+impl PredictionServiceImpl {
+    /// Shared memory regions hold the tensors of inferences: managing them takes the infer
+    /// role.
+    fn authorize_shared_memory(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        auth::authorize(
+            self.authenticator.as_ref(),
+            metadata,
+            Role::Infer,
+            Target::Server,
+        )
+        .map(|_| ())
+    }
+
+    /// Runs a `ModelInfer` call.
+    async fn infer_unary(
+        &self,
+        request: Request<ModelInferRequest>,
+    ) -> Result<Response<ModelInferResponse>, Status> {
+        log_debug!("Got a request: {:?}", request);
+
+        let started = Instant::now();
+        let _load = self.overload.begin();
+        let metadata = request.metadata().clone();
+        let timeline = debug::request_timeline(&metadata, started);
+        let mut req = request.into_inner();
+        let priority = request_priority(&metadata)?;
+        let deadline = request_deadline(&metadata, started, started)?;
+        let tenant = admit_tenant(&self.model_manager, &metadata)?;
+        traffic::account_message(&self.traffic, tenant.as_deref(), req.encoded_len())?;
+        route_by_selector(&self.model_manager, &metadata, &mut req)?;
+        let authenticated = auth::authorize(
+            self.authenticator.as_ref(),
+            &metadata,
+            Role::Infer,
+            Target::Model(&req.model_name),
+        )?;
+        rate_limit::limit_model(&self.rate_limiter, &req.model_name)?;
+        let _in_flight = admit_in_flight(self, &req.model_name)?;
+        let model_version =
+            resolve_model_version(&self.model_manager, &req.model_name, &req.model_version)?;
+        if let Some(timeline) = &timeline {
+            timeline.span("resolve_version", started, model_version.clone());
+        }
+        let negotiated = Instant::now();
+        let plan = schema::negotiate_request(&self.model_manager, &metadata, &mut req)?;
+        if let Some(timeline) = &timeline {
+            timeline.span("parse", negotiated, plan.client_version.clone());
+        }
+        let correlation_id =
+            correlation::assign_request_id(self.ids.as_ref(), &metadata, &mut req.id);
+        let casts = translator::output_casts(&req.outputs)?;
+        check_inputs(&self.model_manager, &req, model_version.as_deref())?;
+        let locations = translator::output_locations(&req.outputs)?;
+        let inputs =
+            translator::input_tensors(req.inputs, req.raw_input_contents, &self.shared_memory)?;
 
         let domain_params = req
             .parameters
@@ -201,27 +1025,61 @@ impl PredictionService for PredictionServiceImpl {
             .map(|(k, v)| (k, InferParameter::from(v)))
             .collect::<HashMap<_, _>>();
 
-        let inference_request = InferenceRequest {
+        let mut inference_request = InferenceRequest {
             model_name: req.model_name.clone(),
-            model_version: Some(req.model_version.clone()),
+            model_version: model_version.clone(),
             id: req.id.clone(),
             parameters: Some(domain_params),
-            outputs: None, // or map req.outputs if needed
+            outputs: Some(inputs),
+            timeline: timeline.clone(),
+            priority,
+            deadline,
+            tenant: tenant.clone(),
+            sampling: Default::default(),
         };
+        self.overload.apply(&mut inference_request);
+        check_capabilities(
+            &self.model_manager,
+            &inference_request,
+            req.capabilities.take(),
+        )?;
+        let quota = quota::charge(
+            &self.quotas,
+            &self.model_manager,
+            authenticated,
+            &inference_request,
+        )?;
+        let ignored_hints = self.model_manager.ignored_hints(&inference_request);
 
-        // Enqueue into ModelManager
-        self.model_manager.add_request(model_id, inference_request);
+        let outputs =
+            run_inference(&self.model_manager, "grpc.ModelInfer", inference_request).await?;
+        let (outputs, raw_output_contents) =
+            translator::output_tensors(outputs, &casts, &locations, &self.shared_memory)?;
 
-        let reply = ModelInferResponse {
+        let mut reply = ModelInferResponse {
             model_name: req.model_name,
-            model_version: req.model_version,
+            model_version: model_version.unwrap_or_default(),
             id: req.id,
-            parameters: HashMap::new(),
-            outputs: vec![],
-            raw_output_contents: vec![],
+            parameters: hint_parameters(&ignored_hints),
+            outputs,
+            raw_output_contents,
         };
+        let downgraded = Instant::now();
+        schema::downgrade_response(&plan, &mut reply)?;
+        if let Some(timeline) = &timeline {
+            timeline.span("schema.downgrade", downgraded, None);
+        }
+        debug::attach(timeline.as_deref(), &mut reply);
+        self.traffic
+            .record_out(tenant.as_deref(), reply.encoded_len() as u64);
 
-        Ok(Response::new(reply))
+        Ok(correlation::with_correlation_id(
+            Some(correlation_id),
+            quota::with_quota(
+                quota,
+                schema::with_schema_version(&plan, Response::new(reply)),
+            ),
+        ))
     }
 }
 
@@ -229,7 +1087,60 @@ impl PredictionService for PredictionServiceImpl {
 pub struct GrpcServerBuilder {
     address: String,
     service_impl: PredictionServiceImpl,
+    limits: ConnectionLimits,
+    cors_origins: Vec<String>,
+    tls: Option<TlsConfig>,
+    shared_port: Option<Arc<SharedPort>>,
+}
+impl GrpcServerBuilder {
+    /// The services with their gRPC-Web and CORS layers, for a server serving them on its
+    /// own listener, as the REST server does on a shared port routed by request.
+    pub fn into_router(
+        self,
+        shutdown: ShutdownSignal,
+    ) -> Result<AxumRouter, Box<dyn std::error::Error + Send + Sync>> {
+        let cors = web::cors_layer(&self.cors_origins)?;
+        Ok(services(self.service_impl, &shutdown)
+            .prepare()
+            .into_axum_router()
+            .layer(GrpcWebLayer::new())
+            .layer(cors))
+    }
+}
+
+/// The health, inference and Arrow Flight services. Inference and Flight calls are
+/// authenticated, rate limited and accounted, and messages over the request size limit are
+/// refused before being decoded.
+fn services(service_impl: PredictionServiceImpl, shutdown: &ShutdownSignal) -> Routes {
+    let rate_limiter = service_impl.rate_limiter.clone();
+    let traffic = service_impl.traffic.clone();
+    let authenticator = service_impl.authenticator.clone();
+    let trust_tenant_header = service_impl.trust_tenant_header;
+    let health =
+        health::HealthService::server(service_impl.model_manager.clone(), shutdown.clone());
+    let mut kserve = kserve::KServeService::server(service_impl.clone());
+    let mut flight = flight::FlightScoringService::server(service_impl.clone());
+    let mut service = PredictionServiceServer::new(service_impl);
+    if let Some(limit) = traffic.limits().max_request_bytes {
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        service = service.max_decoding_message_size(limit);
+        kserve = kserve.max_decoding_message_size(limit);
+        flight = flight.max_decoding_message_size(limit);
+    }
+    // Applied to the calls of the inference and Flight services.
+    let intercept = move |request: Request<()>| {
+        let request = auth::authenticate(authenticator.as_ref(), request)?;
+        let request = auth::identify_tenant(trust_tenant_header, request);
+        let request = rate_limit::limit_client(&rate_limiter, request)?;
+        traffic::limit_tenant(&traffic, request)
+    };
+    // Probes need no credentials.
+    Routes::new(health)
+        .add_service(InterceptedService::new(service, intercept.clone()))
+        .add_service(InterceptedService::new(kserve, intercept.clone()))
+        .add_service(InterceptedService::new(flight, intercept))
 }
+
 /// async trait should applied also to the implementation.
 #[async_trait]
 impl InferenceServerBuilder for GrpcServerBuilder {
@@ -238,20 +1149,199 @@ impl InferenceServerBuilder for GrpcServerBuilder {
         model_manager: Arc<ModelDiscoveryService>,
     ) -> Self {
         let addr = format!("{}:{}", context.grpc_hostname, context.grpc_port);
+        let events = model_manager.events().clone();
         Self {
             address: addr,
-            service_impl: PredictionServiceImpl::new(model_manager),
+            service_impl: PredictionServiceImpl::new(model_manager)
+                .with_analytics(context.analytics)
+                .with_audit(context.audit)
+                .with_overload(context.overload)
+                .with_ids(context.ids)
+                .with_rate_limiter(context.rate_limiter)
+                .with_concurrency(context.concurrency)
+                .with_traffic(context.traffic)
+                .with_quotas(context.quotas)
+                .with_shared_memory(context.shared_memory)
+                .with_stream_pacing(context.stream_pacing)
+                .with_trusted_tenant_header(context.trust_tenant_header)
+                .with_authenticator(
+                    Authenticator::new(context.api_keys, context.jwt)
+                        .map(|authenticator| authenticator.with_events(events)),
+                ),
+            limits: context.limits,
+            cors_origins: context.cors_origins,
+            tls: context.tls,
+            shared_port: context.shared_port,
         }
     }
-    async fn start(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let addr = self.address.parse()?;
+    async fn start(
+        self,
+        shutdown: ShutdownSignal,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let listener = match &self.shared_port {
+            Some(shared) => shared.listener(Protocol::Grpc).await?,
+            None => Listener::bind(&self.address).await?,
+        };
+        let addr = listener.local_addr()?;
+
+        let cors = web::cors_layer(&self.cors_origins)?;
+        let routes = services(self.service_impl, &shutdown);
+
+        let tls = match self.tls {
+            Some(config) => {
+                let tls = Arc::new(ReloadableTls::load(config)?);
+                tls.reload_on_sighup("gRPC")?;
+                Some(tls)
+            }
+            None => None,
+        };
 
-        println!("gRPC PredictionService server listening on {}", addr);
+        log_info!(
+            "gRPC PredictionService, GRPCInferenceService and FlightService server listening on {}{}{}",
+            addr,
+            if tls.is_some() { " (TLS)" } else { "" },
+            if self.shared_port.is_some() {
+                " (shared with REST)"
+            } else {
+                ""
+            }
+        );
 
-        Server::builder()
-            .add_service(PredictionServiceServer::new(self.service_impl))
-            .serve(addr)
-            .await?;
+        // Once the signal fires, no connection is accepted and the open ones are asked to
+        // close after their calls are answered; those still open at the deadline are dropped.
+        let server = Server::builder()
+            .max_concurrent_streams(self.limits.max_concurrent_streams)
+            .concurrency_limit_per_connection(self.limits.max_concurrent_streams as usize)
+            // HTTP/1.1 for gRPC-Web calls from browsers.
+            .accept_http1(true)
+            .layer(cors)
+            .layer(GrpcWebLayer::new())
+            .add_routes(routes)
+            .serve_with_incoming_shutdown(
+                connection::incoming(listener, self.limits, tls),
+                shutdown.clone().triggered().map(|_| {
+                    log_info!("gRPC server stopped accepting connections, draining open calls")
+                }),
+            );
+        tokio::select! {
+            served = server => served?,
+            _ = shutdown.deadline_passed() => {
+                eprintln!("Closing gRPC connections still open at the drain deadline");
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundation::{FakeInferenceProcessor, ModelVersionId, ProcessorRuntime};
+    use grpc_server::infer_parameter::ParameterChoice;
+    use grpc_server::model_infer_request::InferInputTensor;
+    use grpc_server::prediction_service_client::PredictionServiceClient;
+    use model_infer_batch_response::result::Result as BatchResult;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    /// A client of a server serving version 1 of model `m` with the fake processor.
+    async fn client() -> PredictionServiceClient<tonic::transport::Channel> {
+        let model_manager = ModelDiscoveryService::new(10);
+        model_manager.register_model_version(
+            ModelVersionId::new("m", "1"),
+            Arc::new(ProcessorRuntime::new("m", FakeInferenceProcessor)),
+        );
+        let service = PredictionServiceImpl::new(Arc::new(model_manager));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(PredictionServiceServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        PredictionServiceClient::connect(format!("http://{address}"))
+            .await
+            .unwrap()
+    }
+
+    fn infer_request(model_name: &str, id: &str) -> ModelInferRequest {
+        ModelInferRequest {
+            model_name: model_name.to_string(),
+            id: id.to_string(),
+            parameters: HashMap::from([(
+                "temperature".to_string(),
+                grpc_server::InferParameter {
+                    parameter_choice: Some(ParameterChoice::F64Param(0.5)),
+                },
+            )]),
+            inputs: vec![InferInputTensor {
+                name: "x".to_string(),
+                datatype: "FP32".to_string(),
+                shape: vec![2],
+                ..Default::default()
+            }],
+            raw_input_contents: vec![[1f32.to_le_bytes(), 2f32.to_le_bytes()].concat().into()],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_answer_every_request_in_order() {
+        let mut client = client().await;
+        let requests = vec![
+            infer_request("m", "a"),
+            infer_request("absent", "b"),
+            infer_request("m", "c"),
+        ];
+        let batch = client
+            .model_infer_batch(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((batch.succeeded, batch.failed), (2, 1));
+        let results: Vec<_> = batch
+            .results
+            .into_iter()
+            .map(|result| result.result.unwrap())
+            .collect();
+        match &results[..] {
+            [
+                BatchResult::Response(first),
+                BatchResult::Error(error),
+                BatchResult::Response(last),
+            ] => {
+                assert_eq!((first.id.as_str(), last.id.as_str()), ("a", "c"));
+                assert_eq!(first.model_name, "m");
+                assert!(!first.outputs.is_empty());
+                assert_eq!(error.id, "b");
+                assert_eq!(error.code, tonic::Code::NotFound as i32);
+            }
+            results => panic!("unexpected results {:?}", results),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_batches_are_answered_empty() {
+        let mut client = client().await;
+        let batch = client
+            .model_infer_batch(tokio_stream::iter(Vec::new()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(batch.results.is_empty());
+        assert_eq!((batch.succeeded, batch.failed), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_batches_with_invalid_metadata_are_refused() {
+        let mut client = client().await;
+        for (name, value) in [
+            (REQUEST_TIMEOUT_HEADER, "soon"),
+            (PRIORITY_HEADER, "urgent"),
+        ] {
+            let mut request = Request::new(tokio_stream::iter(vec![infer_request("m", "a")]));
+            request.metadata_mut().insert(name, value.parse().unwrap());
+            let status = client.model_infer_batch(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument, "{}", name);
+        }
+    }
+}
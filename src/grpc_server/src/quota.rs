@@ -0,0 +1,31 @@
+use foundation::{InferenceRequest, ModelDiscoveryService, QuotaTracker, QuotaUsage};
+use tonic::metadata::AsciiMetadataValue;
+use tonic::{Response, Status};
+
+/// Charges `request` to the account of the caller: the key or token subject
+/// `authenticated` it, or else its tenant. Anonymous requests are not counted.
+pub fn charge(
+    quotas: &QuotaTracker,
+    model_manager: &ModelDiscoveryService,
+    authenticated: Option<String>,
+    request: &InferenceRequest,
+) -> Result<Option<QuotaUsage>, Status> {
+    let Some(account) = authenticated.or_else(|| request.tenant.clone()) else {
+        return Ok(None);
+    };
+    let tokens = model_manager.request_tokens(&request.model_name, request.parameters.as_ref());
+    quotas
+        .admit(&account, tokens)
+        .map(Some)
+        .map_err(|refusal| Status::resource_exhausted(refusal.to_string()))
+}
+
+/// `response` with the `x-quota-*` metadata of `usage`, if any.
+pub fn with_quota<T>(usage: Option<QuotaUsage>, mut response: Response<T>) -> Response<T> {
+    for (name, value) in usage.map(|usage| usage.headers()).unwrap_or_default() {
+        if let Ok(value) = value.parse::<AsciiMetadataValue>() {
+            response.metadata_mut().insert(name, value);
+        }
+    }
+    response
+}
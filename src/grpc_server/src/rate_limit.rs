@@ -0,0 +1,51 @@
+use foundation::{
+    ApiError, Authenticated, RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER,
+    RATE_LIMIT_RESET_HEADER, RateLimited, RateLimiter, client_key,
+};
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Status};
+
+use crate::status::api_status;
+
+/// `RESOURCE_EXHAUSTED` carrying the state of the exhausted bucket as metadata.
+fn limited_status(limited: RateLimited) -> Status {
+    let mut status =
+        api_status(ApiError::resource_exhausted(&limited).with_retry_delay(limited.retry_after));
+    let metadata = status.metadata_mut();
+    metadata.insert(RATE_LIMIT_LIMIT_HEADER, limited.decision.limit.into());
+    metadata.insert(
+        RATE_LIMIT_REMAINING_HEADER,
+        limited.decision.remaining.into(),
+    );
+    metadata.insert(
+        RATE_LIMIT_RESET_HEADER,
+        MetadataValue::from(limited.decision.reset.as_secs_f64().ceil() as u64),
+    );
+    metadata.insert(
+        "retry-after",
+        MetadataValue::from(limited.retry_after.as_secs_f64().ceil() as u64),
+    );
+    status
+}
+
+/// Interceptor applying the client rate limit once per call, keyed on the account the
+/// call authenticated as or the peer address.
+pub fn limit_client(limiter: &RateLimiter, request: Request<()>) -> Result<Request<()>, Status> {
+    if limiter.limits().per_client.is_none() {
+        return Ok(request);
+    }
+    let account = request.extensions().get::<Authenticated>();
+    let addr = request.remote_addr().map(|addr| addr.ip());
+    limiter
+        .check(client_key(account, addr).as_deref(), None)
+        .map_err(limited_status)?;
+    Ok(request)
+}
+
+/// Applies the rate limit of `model` to one inference message.
+pub fn limit_model(limiter: &RateLimiter, model: &str) -> Result<(), Status> {
+    limiter
+        .check(None, Some(model))
+        .map(|_| ())
+        .map_err(limited_status)
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use foundation::{
+    ModelDiscoveryService, ModelId, SCHEMA_VERSION_HEADER, SCHEMA_VERSION_PARAMETER, SchemaPlan,
+};
+use serde_json::{Value, json};
+use tonic::metadata::MetadataMap;
+use tonic::{Response, Status};
+
+use crate::grpc_server::{
+    InferParameter, ModelInferRequest, ModelInferResponse, infer_parameter::ParameterChoice,
+};
+
+fn parameter_to_json(parameter: &InferParameter) -> Value {
+    match &parameter.parameter_choice {
+        Some(ParameterChoice::BoolParam(b)) => json!(b),
+        Some(ParameterChoice::Int64Param(i)) => json!(i),
+        Some(ParameterChoice::F64Param(f)) => json!(f),
+        Some(ParameterChoice::StringParam(s)) => json!(s),
+        None => Value::Null,
+    }
+}
+
+fn json_to_parameter(value: Value) -> InferParameter {
+    let choice = match value {
+        Value::Bool(b) => ParameterChoice::BoolParam(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => ParameterChoice::Int64Param(i),
+            None => ParameterChoice::F64Param(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => ParameterChoice::StringParam(s),
+        other => ParameterChoice::StringParam(other.to_string()),
+    };
+    InferParameter {
+        parameter_choice: Some(choice),
+    }
+}
+
+fn parameters_to_json(parameters: &HashMap<String, InferParameter>) -> Value {
+    Value::Object(
+        parameters
+            .iter()
+            .map(|(name, parameter)| (name.clone(), parameter_to_json(parameter)))
+            .collect(),
+    )
+}
+
+fn json_to_parameters(value: Option<Value>) -> HashMap<String, InferParameter> {
+    match value {
+        Some(Value::Object(parameters)) => parameters
+            .into_iter()
+            .map(|(name, value)| (name, json_to_parameter(value)))
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+/// Name, datatype and shape of a tensor in a converted document.
+fn tensor_header(tensor: &Value) -> (Option<String>, Option<String>, Option<Vec<i64>>) {
+    let shape = tensor
+        .get("shape")
+        .and_then(Value::as_array)
+        .map(|shape| shape.iter().filter_map(Value::as_i64).collect());
+    (
+        tensor.get("name").and_then(Value::as_str).map(String::from),
+        tensor
+            .get("datatype")
+            .and_then(Value::as_str)
+            .map(String::from),
+        shape,
+    )
+}
+
+/// Takes the converted tensors of `field`, which must still match the original tensors.
+fn tensors(document: &mut Value, field: &str, expected: usize) -> Result<Vec<Value>, Status> {
+    let tensors = match document.get_mut(field).map(Value::take) {
+        Some(Value::Array(tensors)) => tensors,
+        _ => Vec::new(),
+    };
+    if tensors.len() != expected {
+        return Err(Status::internal(
+            "Schema converters may not add or remove tensors over gRPC",
+        ));
+    }
+    Ok(tensors)
+}
+
+/// Negotiates the schema version of a request and upgrades it to the model's current
+/// version. Only tensor names, datatypes and shapes and the parameters are converted;
+/// tensor contents are passed through unchanged.
+pub fn negotiate_request(
+    model_manager: &ModelDiscoveryService,
+    metadata: &MetadataMap,
+    request: &mut ModelInferRequest,
+) -> Result<SchemaPlan, Status> {
+    let parameter = request
+        .parameters
+        .remove(SCHEMA_VERSION_PARAMETER)
+        .map(|parameter| match parameter_to_json(&parameter) {
+            Value::String(version) => version,
+            other => other.to_string(),
+        });
+    let requested = metadata
+        .get(SCHEMA_VERSION_HEADER)
+        .and_then(|version| version.to_str().ok())
+        .map(String::from)
+        .or(parameter);
+
+    let plan = model_manager
+        .negotiate_schema(&ModelId(request.model_name.clone()), requested.as_deref())
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    if plan.is_identity() {
+        return Ok(plan);
+    }
+
+    let mut document = json!({
+        "id": request.id,
+        "parameters": parameters_to_json(&request.parameters),
+        "inputs": request.inputs.iter().map(|input| json!({
+            "name": input.name,
+            "datatype": input.datatype,
+            "shape": input.shape,
+            "parameters": parameters_to_json(&input.parameters),
+        })).collect::<Vec<_>>(),
+        "outputs": request.outputs.iter().map(|output| json!({
+            "name": output.name,
+            "parameters": parameters_to_json(&output.parameters),
+        })).collect::<Vec<_>>(),
+    });
+    plan.upgrade_request(&mut document)
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+    let inputs = tensors(&mut document, "inputs", request.inputs.len())?;
+    for (input, converted) in request.inputs.iter_mut().zip(&inputs) {
+        let (name, datatype, shape) = tensor_header(converted);
+        input.name = name.unwrap_or_default();
+        input.datatype = datatype.unwrap_or_default();
+        input.shape = shape.unwrap_or_default();
+        input.parameters = json_to_parameters(converted.get("parameters").cloned());
+    }
+    let outputs = tensors(&mut document, "outputs", request.outputs.len())?;
+    for (output, converted) in request.outputs.iter_mut().zip(&outputs) {
+        output.name = tensor_header(converted).0.unwrap_or_default();
+        output.parameters = json_to_parameters(converted.get("parameters").cloned());
+    }
+    request.parameters = json_to_parameters(document.get_mut("parameters").map(Value::take));
+
+    Ok(plan)
+}
+
+/// Converts a response back to the schema version spoken by the client.
+pub fn downgrade_response(
+    plan: &SchemaPlan,
+    response: &mut ModelInferResponse,
+) -> Result<(), Status> {
+    if plan.is_identity() {
+        return Ok(());
+    }
+
+    let mut document = json!({
+        "model_name": response.model_name,
+        "model_version": response.model_version,
+        "id": response.id,
+        "parameters": parameters_to_json(&response.parameters),
+        "outputs": response.outputs.iter().map(|output| json!({
+            "name": output.name,
+            "datatype": output.datatype,
+            "shape": output.shape,
+            "parameters": parameters_to_json(&output.parameters),
+        })).collect::<Vec<_>>(),
+    });
+    plan.downgrade_response(&mut document)
+        .map_err(|e| Status::internal(e.to_string()))?;
+
+    let outputs = tensors(&mut document, "outputs", response.outputs.len())?;
+    for (output, converted) in response.outputs.iter_mut().zip(&outputs) {
+        let (name, datatype, shape) = tensor_header(converted);
+        output.name = name.unwrap_or_default();
+        output.datatype = datatype.unwrap_or_default();
+        output.shape = shape.unwrap_or_default();
+        output.parameters = json_to_parameters(converted.get("parameters").cloned());
+    }
+    response.parameters = json_to_parameters(document.get_mut("parameters").map(Value::take));
+    Ok(())
+}
+
+/// Adds the negotiated schema version to a response, if the model is versioned.
+pub fn with_schema_version<T>(plan: &SchemaPlan, mut response: Response<T>) -> Response<T> {
+    if let Some(version) = &plan.client_version
+        && let Ok(value) = version.parse()
+    {
+        response.metadata_mut().insert(SCHEMA_VERSION_HEADER, value);
+    }
+    response
+}
@@ -0,0 +1,89 @@
+use foundation::{ApiError, ErrorDetail, ModelPaused};
+use prost::Message;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Status};
+
+pub mod proto {
+    tonic::include_proto!("google.rpc");
+}
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/google.rpc.";
+
+fn any(name: &str, message: impl Message) -> prost_types::Any {
+    prost_types::Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+        value: message.encode_to_vec(),
+    }
+}
+
+fn detail(detail: ErrorDetail) -> prost_types::Any {
+    match detail {
+        ErrorDetail::ErrorInfo {
+            reason,
+            domain,
+            metadata,
+        } => any(
+            "ErrorInfo",
+            proto::ErrorInfo {
+                reason,
+                domain,
+                metadata: metadata.into_iter().collect(),
+            },
+        ),
+        ErrorDetail::BadRequest { field_violations } => any(
+            "BadRequest",
+            proto::BadRequest {
+                field_violations: field_violations
+                    .into_iter()
+                    .map(|violation| proto::bad_request::FieldViolation {
+                        field: violation.field,
+                        description: violation.description,
+                    })
+                    .collect(),
+            },
+        ),
+        ErrorDetail::RetryInfo { retry_delay } => any(
+            "RetryInfo",
+            proto::RetryInfo {
+                retry_delay: prost_types::Duration::try_from(retry_delay).ok(),
+            },
+        ),
+    }
+}
+
+/// Status of `error` carrying its details, and its request id as `RequestInfo`, as the
+/// `google.rpc.Status` of the `grpc-status-details-bin` trailer. The message still leads
+/// with the reason, for clients matching on it.
+pub fn api_status(error: ApiError) -> Status {
+    let code = Code::from_i32(error.code.rpc_code());
+    let message = match error.reason() {
+        Some(reason) => format!("{}: {}", reason, error.message),
+        None => error.message.clone(),
+    };
+    let mut details: Vec<prost_types::Any> = error.details.into_iter().map(detail).collect();
+    if let Some(request_id) = error.request_id {
+        details.push(any(
+            "RequestInfo",
+            proto::RequestInfo {
+                request_id,
+                serving_data: String::new(),
+            },
+        ));
+    }
+    let status = proto::Status {
+        code: code as i32,
+        message: message.clone(),
+        details,
+    };
+    Status::with_details(code, message, status.encode_to_vec().into())
+}
+
+/// `UNAVAILABLE` for a request of a paused model, telling when to retry as metadata too.
+pub fn paused_status(paused: ModelPaused) -> Status {
+    let retry_after = paused.retry_after.as_secs();
+    let mut status = api_status(paused.into());
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(retry_after));
+    status
+}
@@ -0,0 +1,39 @@
+use foundation::{TENANT_HEADER, TrafficAccounting, TrafficRefusal};
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Status};
+
+fn refused(refusal: TrafficRefusal) -> Status {
+    match refusal {
+        TrafficRefusal::RequestTooLarge { .. } => Status::out_of_range(refusal.to_string()),
+        TrafficRefusal::DailyLimit { .. } => Status::resource_exhausted(refusal.to_string()),
+    }
+}
+
+fn tenant(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok())
+}
+
+/// Interceptor refusing calls of tenants that used their bytes of the day, before any
+/// message is decoded. The size of each message is bounded by the decoder itself.
+pub fn limit_tenant(
+    traffic: &TrafficAccounting,
+    request: Request<()>,
+) -> Result<Request<()>, Status> {
+    traffic
+        .admit(tenant(request.metadata()), None)
+        .map_err(refused)?;
+    Ok(request)
+}
+
+/// Applies the daily limit of `tenant` to one message of `size` bytes and counts it.
+pub fn account_message(
+    traffic: &TrafficAccounting,
+    tenant: Option<&str>,
+    size: usize,
+) -> Result<(), Status> {
+    traffic.admit(tenant, Some(size as u64)).map_err(refused)?;
+    traffic.record_in(tenant, size as u64);
+    Ok(())
+}
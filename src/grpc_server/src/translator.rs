@@ -1,5 +1,15 @@
 use crate::grpc_server;
-use foundation::api::inference::InferParameter; // the generated proto module
+use crate::grpc_server::model_infer_request::{InferInputTensor, InferRequestedOutputTensor};
+use crate::grpc_server::model_infer_response::InferOutputTensor;
+use bytes::Bytes;
+use foundation::api::inference::{InferParameter, InferenceOutput}; // the generated proto module
+use foundation::api::tensor::{BYTES_DATATYPE, Data, encode_byte_elements};
+use foundation::{
+    Capabilities, CastTensor, CastValues, DATATYPE_PARAMETER, ModelMetadata, OutputDatatype,
+    SHARED_MEMORY_REGION_PARAMETER, SharedMemoryRef, SharedMemoryRegistry, TensorMetadata,
+};
+use std::collections::HashMap;
+use tonic::Status;
 
 impl From<grpc_server::InferParameter> for InferParameter {
     fn from(p: grpc_server::InferParameter) -> Self {
@@ -21,10 +31,7 @@ impl From<InferParameter> for grpc_server::InferParameter {
             InferParameter::Bool(b) => Some(ParameterChoice::BoolParam(b)),
             InferParameter::Int64(i) => Some(ParameterChoice::Int64Param(i)),
             InferParameter::String(s) => Some(ParameterChoice::StringParam(s)),
-            InferParameter::Double(d) => {
-                // proto doesn’t support double? fallback, e.g., string encode
-                Some(ParameterChoice::StringParam(d.to_string()))
-            }
+            InferParameter::Double(d) => Some(ParameterChoice::F64Param(d)),
         };
 
         grpc_server::InferParameter {
@@ -32,3 +39,539 @@ impl From<InferParameter> for grpc_server::InferParameter {
         }
     }
 }
+
+impl From<grpc_server::Capabilities> for Capabilities {
+    fn from(capabilities: grpc_server::Capabilities) -> Self {
+        Self {
+            streaming: capabilities.streaming,
+            logprobs: capabilities.logprobs,
+            tools: capabilities.tools,
+            modalities: capabilities.modalities,
+        }
+    }
+}
+
+impl From<Capabilities> for grpc_server::Capabilities {
+    fn from(capabilities: Capabilities) -> Self {
+        Self {
+            streaming: capabilities.streaming,
+            logprobs: capabilities.logprobs,
+            tools: capabilities.tools,
+            modalities: capabilities.modalities,
+        }
+    }
+}
+
+impl From<TensorMetadata> for grpc_server::model_metadata_response::TensorMetadata {
+    fn from(tensor: TensorMetadata) -> Self {
+        Self {
+            name: tensor.name,
+            datatype: tensor.datatype,
+            shape: tensor.shape,
+        }
+    }
+}
+
+impl From<ModelMetadata> for grpc_server::ModelMetadataResponse {
+    fn from(metadata: ModelMetadata) -> Self {
+        Self {
+            name: metadata.name,
+            versions: metadata.versions,
+            platform: metadata.platform,
+            inputs: metadata.inputs.into_iter().map(Into::into).collect(),
+            outputs: metadata.outputs.into_iter().map(Into::into).collect(),
+            capabilities: metadata.capabilities.map(Into::into),
+        }
+    }
+}
+
+/// Datatypes the requested outputs ask to be cast to, by output name.
+pub fn output_casts(
+    outputs: &[InferRequestedOutputTensor],
+) -> Result<HashMap<String, OutputDatatype>, Status> {
+    let mut casts = HashMap::new();
+    for output in outputs {
+        let Some(datatype) = output.parameters.get(DATATYPE_PARAMETER) else {
+            continue;
+        };
+        let Some(grpc_server::infer_parameter::ParameterChoice::StringParam(datatype)) =
+            &datatype.parameter_choice
+        else {
+            return Err(Status::invalid_argument(format!(
+                "The datatype of output '{}' must be a string",
+                output.name
+            )));
+        };
+        let datatype = datatype
+            .parse::<OutputDatatype>()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        casts.insert(output.name.clone(), datatype);
+    }
+    Ok(casts)
+}
+
+/// Output tensors of the model, cast as requested, and their raw contents. As the protocol
+/// requires, either every output is sent as raw contents or none is: all of them are as
+/// soon as one is cast to a datatype without a typed contents field. Outputs are FP64 unless
+/// cast; views keep their own datatype and are sent without copying them when raw. Outputs
+/// with a location are written to shared memory instead, and sent without contents.
+pub fn output_tensors(
+    outputs: Vec<InferenceOutput>,
+    casts: &HashMap<String, OutputDatatype>,
+    locations: &HashMap<String, SharedMemoryRef>,
+    shared_memory: &SharedMemoryRegistry,
+) -> Result<(Vec<InferOutputTensor>, Vec<Bytes>), Status> {
+    let casts: Vec<OutputDatatype> = outputs
+        .iter()
+        .map(|output| {
+            casts
+                .get(&output.name)
+                .copied()
+                .unwrap_or(match &output.data {
+                    Data::VRAW(view) => view.datatype(),
+                    _ => OutputDatatype::Fp64,
+                })
+        })
+        .collect();
+    let raw = outputs
+        .iter()
+        .zip(&casts)
+        .any(|(output, datatype)| datatype.requires_raw() && !locations.contains_key(&output.name));
+
+    let mut raw_contents = Vec::new();
+    let mut tensors = Vec::new();
+    for (output, datatype) in outputs.into_iter().zip(casts) {
+        let mut parameters = output.parameters.unwrap_or_default();
+        let location = locations.get(&output.name);
+        let (datatype, contents) =
+            output_contents(&output.data, datatype, raw || location.is_some());
+        let contents = match (contents, location) {
+            (Contents::Raw(bytes), Some(location)) => {
+                shared_memory.write(location, &bytes).map_err(|e| {
+                    Status::invalid_argument(format!("Output '{}': {}", output.name, e))
+                })?;
+                parameters.extend(location.parameters());
+                None
+            }
+            (Contents::Raw(bytes), None) => {
+                raw_contents.push(bytes);
+                None
+            }
+            (Contents::Typed(contents), _) => Some(contents),
+        };
+        tensors.push(InferOutputTensor {
+            name: output.name,
+            datatype,
+            shape: output.shape.into_iter().map(|dim| dim as i64).collect(),
+            parameters: parameters.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            contents,
+        });
+    }
+    Ok((tensors, raw_contents))
+}
+
+/// Values of an output, in a typed contents field or raw.
+enum Contents {
+    Typed(grpc_server::InferTensorContents),
+    Raw(Bytes),
+}
+
+/// The datatype and contents of `data` cast to `datatype`, raw if `raw`. String and bytes
+/// outputs are BYTES tensors and are never cast.
+fn output_contents(data: &Data, datatype: OutputDatatype, raw: bool) -> (String, Contents) {
+    let cast = match data {
+        Data::VFLOAT(values) => CastTensor::new(values, datatype),
+        Data::VRAW(view) if raw && view.datatype() == datatype => {
+            return (datatype.to_string(), Contents::Raw(view.bytes().clone()));
+        }
+        Data::VRAW(view) => CastTensor::new(&view.to_f64(), datatype),
+        data => {
+            let elements = data.byte_elements().unwrap_or_default();
+            let contents = if raw {
+                Contents::Raw(encode_byte_elements(elements).into())
+            } else {
+                Contents::Typed(grpc_server::InferTensorContents {
+                    bytes_contents: elements.iter().map(|e| e.to_vec()).collect(),
+                    ..Default::default()
+                })
+            };
+            return (BYTES_DATATYPE.to_string(), contents);
+        }
+    };
+    let contents = if raw {
+        Contents::Raw(cast.raw_bytes().into())
+    } else {
+        Contents::Typed(typed_contents(cast.values))
+    };
+    (datatype.to_string(), contents)
+}
+
+/// Where the requested outputs are to be written in shared memory, by output name.
+pub fn output_locations(
+    outputs: &[InferRequestedOutputTensor],
+) -> Result<HashMap<String, SharedMemoryRef>, Status> {
+    let mut locations = HashMap::new();
+    for output in outputs {
+        let mut parameters: HashMap<String, InferParameter> = output
+            .parameters
+            .clone()
+            .into_iter()
+            .map(|(k, v)| (k, v.into()))
+            .collect();
+        let location = SharedMemoryRef::take(&mut parameters)
+            .map_err(|e| Status::invalid_argument(format!("Output '{}': {}", output.name, e)))?;
+        if let Some(location) = location {
+            locations.insert(output.name.clone(), location);
+        }
+    }
+    Ok(locations)
+}
+
+fn typed_contents(values: CastValues) -> grpc_server::InferTensorContents {
+    let mut contents = grpc_server::InferTensorContents::default();
+    match values {
+        CastValues::Fp64(values) => contents.fp64_contents = values,
+        CastValues::Fp32(values) => contents.fp32_contents = values,
+        CastValues::Int64(values) => contents.int64_contents = values,
+        CastValues::Int32(values) => contents.int_contents = values,
+        CastValues::Uint64(values) => contents.uint64_contents = values,
+        CastValues::Uint32(values) => contents.uint_contents = values,
+        CastValues::Bool(values) => contents.bool_contents = values,
+        // Half precision has no typed field; `output_tensors` sends it as raw contents.
+        CastValues::Half(_) => {}
+    }
+    contents
+}
+
+/// Names, datatypes and shapes of the input tensors of a request.
+pub fn input_metadata(inputs: &[InferInputTensor]) -> Vec<TensorMetadata> {
+    inputs
+        .iter()
+        .map(|input| TensorMetadata {
+            name: input.name.clone(),
+            datatype: input.datatype.clone(),
+            shape: input.shape.clone(),
+        })
+        .collect()
+}
+
+/// Input tensors of a request as runtimes take them, read from their typed contents, from
+/// `raw_input_contents` or from shared memory. As the protocol requires, either every input
+/// not in shared memory is sent as raw contents, one entry each in input order, or none is.
+/// Raw numeric inputs are views of the request buffer or of their region; BYTES inputs are
+/// strings when all their elements are UTF-8, bytes otherwise.
+pub fn input_tensors(
+    inputs: Vec<InferInputTensor>,
+    raw_input_contents: Vec<Bytes>,
+    shared_memory: &SharedMemoryRegistry,
+) -> Result<Vec<InferenceOutput>, Status> {
+    let in_shared_memory = inputs
+        .iter()
+        .filter(|input| {
+            input
+                .parameters
+                .contains_key(SHARED_MEMORY_REGION_PARAMETER)
+        })
+        .count();
+    let raw = !raw_input_contents.is_empty();
+    if raw && raw_input_contents.len() != inputs.len() - in_shared_memory {
+        return Err(Status::invalid_argument(format!(
+            "The request has {} inputs outside shared memory but {} raw_input_contents",
+            inputs.len() - in_shared_memory,
+            raw_input_contents.len()
+        )));
+    }
+    let mut raw_input_contents = raw_input_contents.into_iter();
+    inputs
+        .into_iter()
+        .map(|input| {
+            let invalid = |message: String| {
+                Status::invalid_argument(format!("Input '{}': {}", input.name, message))
+            };
+            let shape = input
+                .shape
+                .iter()
+                .map(|dim| usize::try_from(*dim))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| {
+                    invalid(format!("shape {:?} has a negative dimension", input.shape))
+                })?;
+            let mut parameters: HashMap<String, InferParameter> = input
+                .parameters
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect();
+            let raw_bytes =
+                match SharedMemoryRef::take(&mut parameters).map_err(|e| invalid(e.to_string()))? {
+                    Some(location) => Some(
+                        shared_memory
+                            .read(&location)
+                            .map_err(|e| invalid(e.to_string()))?,
+                    ),
+                    None => raw_input_contents.next(),
+                };
+            if raw_bytes.is_some() && input.contents.is_some() {
+                return Err(invalid(
+                    "contents must not be set with raw_input_contents or shared memory".to_string(),
+                ));
+            }
+            let data = match raw_bytes {
+                // Numbers are viewed in their buffer, not decoded.
+                Some(bytes) => {
+                    Data::from_raw(&input.datatype, bytes).map_err(|e| invalid(e.to_string()))?
+                }
+                None if input.datatype == BYTES_DATATYPE => Data::from_byte_elements(
+                    only_field(input.contents.unwrap_or_default(), BYTES_FIELD)
+                        .map(|contents| contents.bytes_contents)
+                        .map_err(invalid)?,
+                ),
+                None => {
+                    let datatype = input
+                        .datatype
+                        .parse::<OutputDatatype>()
+                        .map_err(|e| invalid(e.to_string()))?;
+                    let contents = input.contents.unwrap_or_default();
+                    Data::VFLOAT(typed_values(contents, datatype).map_err(invalid)?.to_f64())
+                }
+            };
+            let expected = shape
+                .iter()
+                .try_fold(1usize, |product, dim| product.checked_mul(*dim))
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "shape {:?} has more elements than can be counted",
+                        shape
+                    ))
+                })?;
+            if data.len() != expected {
+                return Err(invalid(format!(
+                    "shape {:?} has {} elements, its contents hold {}",
+                    shape,
+                    expected,
+                    data.len()
+                )));
+            }
+            Ok(InferenceOutput {
+                name: input.name,
+                shape,
+                datatype: data.datatype(),
+                parameters: Some(parameters),
+                data,
+            })
+        })
+        .collect()
+}
+
+/// Index of `bytes_contents` among the fields of `InferTensorContents`.
+const BYTES_FIELD: usize = 7;
+
+/// `contents`, provided only its field at index `field` holds values.
+fn only_field(
+    contents: grpc_server::InferTensorContents,
+    field: usize,
+) -> Result<grpc_server::InferTensorContents, String> {
+    let filled = [
+        !contents.bool_contents.is_empty(),
+        !contents.int_contents.is_empty(),
+        !contents.int64_contents.is_empty(),
+        !contents.uint_contents.is_empty(),
+        !contents.uint64_contents.is_empty(),
+        !contents.fp32_contents.is_empty(),
+        !contents.fp64_contents.is_empty(),
+        !contents.bytes_contents.is_empty(),
+    ];
+    if filled
+        .iter()
+        .enumerate()
+        .any(|(index, filled)| *filled && index != field)
+    {
+        return Err("values must be in the contents field of their datatype".to_string());
+    }
+    Ok(contents)
+}
+
+/// Values of the typed contents field of `datatype`, the other fields being empty.
+pub fn typed_values(
+    contents: grpc_server::InferTensorContents,
+    datatype: OutputDatatype,
+) -> Result<CastTensor, String> {
+    let field = match datatype {
+        OutputDatatype::Bool => 0,
+        OutputDatatype::Int32 | OutputDatatype::Int16 | OutputDatatype::Int8 => 1,
+        OutputDatatype::Int64 => 2,
+        OutputDatatype::Uint32 | OutputDatatype::Uint16 | OutputDatatype::Uint8 => 3,
+        OutputDatatype::Uint64 => 4,
+        OutputDatatype::Fp32 => 5,
+        OutputDatatype::Fp64 => 6,
+        OutputDatatype::Fp16 | OutputDatatype::Bf16 => {
+            return Err(format!(
+                "{} has no typed contents field and must be sent as raw_input_contents",
+                datatype
+            ));
+        }
+    };
+    let contents = only_field(contents, field)?;
+    let values = match field {
+        0 => CastValues::Bool(contents.bool_contents),
+        1 => CastValues::Int32(contents.int_contents),
+        2 => CastValues::Int64(contents.int64_contents),
+        3 => CastValues::Uint32(contents.uint_contents),
+        4 => CastValues::Uint64(contents.uint64_contents),
+        5 => CastValues::Fp32(contents.fp32_contents),
+        _ => CastValues::Fp64(contents.fp64_contents),
+    };
+    // Narrow integers travel in 32-bit fields. As over REST, values their datatype does not
+    // hold are refused rather than truncated.
+    let (min, max) = match datatype {
+        OutputDatatype::Int16 => (i16::MIN as i64, i16::MAX as i64),
+        OutputDatatype::Int8 => (i8::MIN as i64, i8::MAX as i64),
+        OutputDatatype::Uint16 => (0, u16::MAX as i64),
+        OutputDatatype::Uint8 => (0, u8::MAX as i64),
+        _ => (i64::MIN, i64::MAX),
+    };
+    let out_of_range = match &values {
+        CastValues::Int32(values) => values
+            .iter()
+            .map(|value| *value as i64)
+            .find(|value| !(min..=max).contains(value)),
+        CastValues::Uint32(values) => values
+            .iter()
+            .map(|value| *value as i64)
+            .find(|value| !(min..=max).contains(value)),
+        _ => None,
+    };
+    if let Some(value) = out_of_range {
+        return Err(format!("holds {}, out of the range of {}", value, datatype));
+    }
+    Ok(CastTensor { datatype, values })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(datatype: &str, shape: Vec<i64>) -> InferInputTensor {
+        InferInputTensor {
+            name: "x".to_string(),
+            datatype: datatype.to_string(),
+            shape,
+            ..Default::default()
+        }
+    }
+
+    fn typed(
+        datatype: &str,
+        shape: Vec<i64>,
+        contents: grpc_server::InferTensorContents,
+    ) -> InferInputTensor {
+        InferInputTensor {
+            contents: Some(contents),
+            ..input(datatype, shape)
+        }
+    }
+
+    /// The message of the refusal of `inputs`, None if they are accepted.
+    fn refusal(inputs: Vec<InferInputTensor>, raw: Vec<Bytes>) -> Option<String> {
+        let refused = input_tensors(inputs, raw, &SharedMemoryRegistry::default()).err()?;
+        assert_eq!(refused.code(), tonic::Code::InvalidArgument);
+        Some(refused.message().to_string())
+    }
+
+    #[test]
+    fn test_raw_and_typed_inputs_are_decoded() {
+        let raw = Bytes::from([1i32, -2, 3, 4].map(i32::to_le_bytes).concat());
+        let tensors = input_tensors(
+            vec![input("INT32", vec![2, 2])],
+            vec![raw],
+            &SharedMemoryRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(tensors[0].shape, vec![2, 2]);
+        assert_eq!(tensors[0].data.len(), 4);
+
+        let contents = grpc_server::InferTensorContents {
+            uint_contents: vec![0, 255],
+            ..Default::default()
+        };
+        let tensors = input_tensors(
+            vec![typed("UINT8", vec![2], contents)],
+            Vec::new(),
+            &SharedMemoryRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(tensors[0].data.len(), 2);
+    }
+
+    #[test]
+    fn test_shapes_unlike_the_contents_are_refused() {
+        let contents = grpc_server::InferTensorContents {
+            fp32_contents: vec![1.0, 2.0, 3.0],
+            ..Default::default()
+        };
+        let refused = refusal(vec![typed("FP32", vec![2, 2], contents)], Vec::new()).unwrap();
+        assert!(refused.contains("has 4 elements, its contents hold 3"));
+
+        let raw = Bytes::from(vec![0; 4]);
+        let refused = refusal(vec![input("FP32", vec![1 << 32, 1 << 32])], vec![raw]).unwrap();
+        assert!(refused.contains("more elements than can be counted"));
+
+        let refused = refusal(vec![input("FP32", vec![-1])], vec![Bytes::new()]).unwrap();
+        assert!(refused.contains("negative dimension"));
+    }
+
+    #[test]
+    fn test_raw_contents_of_a_partial_element_are_refused() {
+        let raw = Bytes::from(vec![0; 6]);
+        assert!(refusal(vec![input("INT32", vec![2])], vec![raw]).is_some());
+    }
+
+    #[test]
+    fn test_typed_values_out_of_their_datatype_are_refused() {
+        let ints = |values: Vec<i32>| grpc_server::InferTensorContents {
+            int_contents: values,
+            ..Default::default()
+        };
+        let uints = |values: Vec<u32>| grpc_server::InferTensorContents {
+            uint_contents: values,
+            ..Default::default()
+        };
+        let refused = refusal(
+            vec![typed("INT8", vec![2], ints(vec![-128, 128]))],
+            Vec::new(),
+        );
+        assert!(
+            refused
+                .unwrap()
+                .contains("holds 128, out of the range of INT8")
+        );
+        let refused = refusal(
+            vec![typed("INT16", vec![1], ints(vec![-32769]))],
+            Vec::new(),
+        );
+        assert!(refused.unwrap().contains("out of the range of INT16"));
+        let refused = refusal(vec![typed("UINT8", vec![1], uints(vec![256]))], Vec::new());
+        assert!(refused.unwrap().contains("out of the range of UINT8"));
+        let refused = refusal(
+            vec![typed("UINT16", vec![1], uints(vec![65536]))],
+            Vec::new(),
+        );
+        assert!(refused.unwrap().contains("out of the range of UINT16"));
+
+        assert!(
+            refusal(
+                vec![typed("INT8", vec![2], ints(vec![-128, 127]))],
+                Vec::new()
+            )
+            .is_none()
+        );
+        assert!(
+            refusal(
+                vec![typed("INT32", vec![1], ints(vec![i32::MIN]))],
+                Vec::new()
+            )
+            .is_none()
+        );
+        // Values in the field of another datatype.
+        assert!(refusal(vec![typed("INT8", vec![1], uints(vec![1]))], Vec::new()).is_some());
+    }
+}
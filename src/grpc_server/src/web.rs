@@ -0,0 +1,80 @@
+/* gRPC-Web for browsers.
+
+Browsers cannot speak gRPC over HTTP/2 directly. The gRPC-Web layer accepts
+their HTTP/1.1 (or HTTP/2) `application/grpc-web` requests and translates them
+for `PredictionService`, so no Envoy proxy is needed. Unary and
+server-streaming calls work; browsers cannot send client streams.
+
+Cross-origin calls are allowed from the configured origins, or from any origin
+when none is configured. The preflight response lets browsers send the gRPC-Web
+headers and the `x-galemind-*` / `x-request-timeout-ms` metadata, and read the
+gRPC status and the metadata the server sends back.
+*/
+
+use foundation::{
+    AUTHORIZATION_HEADER, CORRELATION_ID_HEADER, DEBUG_HEADER, GRPC_TIMEOUT_HEADER,
+    MODEL_SELECTOR_HEADER, PRIORITY_HEADER, REQUEST_TIMEOUT_HEADER, SCHEMA_VERSION_HEADER,
+    TENANT_HEADER,
+};
+use std::time::Duration;
+use tonic::codegen::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// How long browsers may cache a preflight response.
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Request headers browsers may send on a gRPC-Web call.
+const ALLOWED_HEADERS: &[&str] = &[
+    "content-type",
+    "x-grpc-web",
+    "x-user-agent",
+    GRPC_TIMEOUT_HEADER,
+    REQUEST_TIMEOUT_HEADER,
+    PRIORITY_HEADER,
+    MODEL_SELECTOR_HEADER,
+    SCHEMA_VERSION_HEADER,
+    CORRELATION_ID_HEADER,
+    DEBUG_HEADER,
+    TENANT_HEADER,
+    AUTHORIZATION_HEADER,
+];
+
+/// Response headers browsers may read.
+const EXPOSED_HEADERS: &[&str] = &[
+    "grpc-status",
+    "grpc-message",
+    "grpc-status-details-bin",
+    SCHEMA_VERSION_HEADER,
+    CORRELATION_ID_HEADER,
+];
+
+/// CORS for gRPC-Web calls from `origins`, or from any origin when empty.
+pub fn cors_layer(
+    origins: &[String],
+) -> Result<CorsLayer, Box<dyn std::error::Error + Send + Sync>> {
+    let allow_origin = if origins.is_empty() {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| format!("Invalid CORS origin '{}'", origin))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::POST])
+        .allow_headers(header_names(ALLOWED_HEADERS))
+        .expose_headers(header_names(EXPOSED_HEADERS))
+        .max_age(PREFLIGHT_MAX_AGE))
+}
+
+fn header_names(names: &[&'static str]) -> Vec<HeaderName> {
+    names
+        .iter()
+        .map(|name| HeaderName::from_static(name))
+        .collect()
+}
@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    routing::{get, post, put},
+};
+use foundation::{
+    BufferStats, CircuitStats, DEFAULT_PAUSE_RETRY_AFTER, DeviceLoad, InstanceRestart,
+    InstanceStatus, LogLevel, MemoryUsage, ModelDiscoveryService, ModelId, ModelPause,
+    ModelVersionId, ReloadReport, RouteStats, SchedulingStats, SelfTestReport, Settings,
+    ShadowStats, TenantStats, TenantTraffic, TimeSeries, log_info, log_level, set_log_level,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::data_model::ErrorInferenceResponse;
+use crate::error::status_error;
+use crate::state::AppState;
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+#[derive(Debug, Serialize)]
+struct BufferSizingResponse {
+    min_capacity: usize,
+    max_capacity: usize,
+    models: Vec<BufferStats>,
+}
+
+/// Request buffer capacities chosen from the observed load of each model.
+async fn buffers_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<BufferSizingResponse> {
+    let sizing = model_manager.buffer_sizing();
+    Json(BufferSizingResponse {
+        min_capacity: sizing.min_capacity,
+        max_capacity: sizing.max_capacity,
+        models: model_manager.buffer_stats(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct DrainQuery {
+    model: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DrainedBuffers {
+    drained: usize,
+}
+
+/// Answers the requests waiting in the buffer of a model, or of every model, with an error.
+/// Requests already running complete.
+async fn drain_buffers_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Query(query): Query<DrainQuery>,
+) -> Result<Json<DrainedBuffers>, InferenceError> {
+    let model_id = query.model.map(ModelId);
+    if let Some(model_id) = &model_id
+        && !model_manager.has_model(model_id)
+    {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("Model {} is not registered", model_id),
+        ));
+    }
+    let drained = model_manager.drain_buffers(model_id.as_ref()).await;
+    log_info!(
+        "Drained {} buffered requests of {}",
+        drained,
+        model_id.map_or_else(|| "every model".to_string(), |model_id| model_id.0)
+    );
+    Ok(Json(DrainedBuffers { drained }))
+}
+
+/// Memory budget, memory in use and model versions of every device, see `model::memory`.
+async fn memory_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<MemoryUsage> {
+    Json(model_manager.memory().usage())
+}
+
+/// Requests buffered and running for every model, with the number each may run at once.
+async fn scheduler_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<Vec<SchedulingStats>> {
+    Json(model_manager.scheduling())
+}
+
+/// Calls in flight and waiting on every GPU, with the model versions placed on it.
+async fn devices_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<Vec<DeviceLoad>> {
+    Json(model_manager.devices().loads())
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsQuery {
+    model: Option<String>,
+    version: Option<String>,
+}
+
+/// Requests, errors and latency of every (model, version, route) series, optionally of one
+/// model or version only.
+async fn stats_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Query(query): Query<StatsQuery>,
+) -> Json<Vec<RouteStats>> {
+    Json(
+        model_manager
+            .stats()
+            .snapshot(query.model.as_deref(), query.version.as_deref()),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct TimeSeriesQuery {
+    /// Seconds of history, the whole retention by default.
+    window: Option<u64>,
+    /// Seconds per point, the resolution of the history by default.
+    step: Option<u64>,
+}
+
+/// Requests, errors, QPS and latency of a model per step over its recent history, oldest
+/// first.
+async fn timeseries_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<TimeSeriesQuery>,
+) -> Result<Json<TimeSeries>, InferenceError> {
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    if !model_manager.has_model(&model_id) {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("Model {} is not registered", model_id),
+        ));
+    }
+    let history = model_manager.stats().timeseries();
+    let window = query
+        .window
+        .map_or_else(|| history.retention(), Duration::from_secs);
+    let step = query
+        .step
+        .map_or_else(|| history.resolution(), Duration::from_secs);
+    history
+        .query(&model_id.0, window, step)
+        .map(Json)
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))
+}
+
+#[derive(Debug, Deserialize)]
+struct SelfTestQuery {
+    version: Option<String>,
+}
+
+/// Runs the golden cases of a model through the live runtime of a version (the newest served
+/// one by default) and reports every case with its differences.
+async fn selftest_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<SelfTestQuery>,
+) -> Result<Json<SelfTestReport>, InferenceError> {
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    let report = model_manager
+        .self_test(&model_id, query.version.as_deref())
+        .await
+        .map_err(|e| status_error(StatusCode::NOT_FOUND, e))?;
+    if !report.passed {
+        eprintln!(
+            "Self-test of {} version {} failed {} of {} golden cases",
+            report.model,
+            report.version,
+            report.failed_cases().count(),
+            report.cases.len()
+        );
+    }
+    Ok(Json(report))
+}
+
+/// Calls in flight on a restart leave the instance for this long by default.
+const DEFAULT_DRAIN_TIMEOUT_MS: u64 = 30_000;
+
+fn version_id(params: &HashMap<String, String>) -> ModelVersionId {
+    ModelVersionId::new(
+        params.get("model_name").cloned().unwrap_or_default(),
+        params.get("model_version").cloned().unwrap_or_default(),
+    )
+}
+
+/// Reads the configuration again and applies the options that can change at runtime,
+/// reporting those that need a restart.
+async fn config_reload_handler(
+    State(state): State<AppState>,
+) -> Result<Json<ReloadReport>, InferenceError> {
+    let Some(config_reload) = state.config_reload else {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            "The configuration cannot be reloaded".to_string(),
+        ));
+    };
+    let report = tokio::task::spawn_blocking(move || config_reload.reload())
+        .await
+        .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map_err(|e| status_error(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    log_info!("Reloaded the configuration from the admin API: {}", report);
+    Ok(Json(report))
+}
+
+/// Options the server runs with, secrets left out.
+async fn config_handler(State(state): State<AppState>) -> Result<Json<Settings>, InferenceError> {
+    state
+        .config_reload
+        .and_then(|config_reload| config_reload.settings())
+        .map(Json)
+        .ok_or_else(|| {
+            status_error(
+                StatusCode::NOT_FOUND,
+                "The configuration cannot be shown".to_string(),
+            )
+        })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogLevelSetting {
+    level: LogLevel,
+}
+
+async fn log_level_handler() -> Json<LogLevelSetting> {
+    Json(LogLevelSetting { level: log_level() })
+}
+
+/// Changes the messages written until the next change or configuration reload.
+async fn set_log_level_handler(Json(body): Json<LogLevelSetting>) -> Json<LogLevelSetting> {
+    if body.level != log_level() {
+        eprintln!("Log level changed to {}", body.level);
+    }
+    set_log_level(body.level);
+    Json(LogLevelSetting { level: log_level() })
+}
+
+#[derive(Debug, Serialize)]
+struct LoadedModel {
+    model: String,
+    /// Versions served once loaded.
+    versions: Vec<String>,
+}
+
+/// Loads a model directory of the models directory, or reloads it if registered already.
+async fn load_model_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<LoadedModel>, InferenceError> {
+    if model_manager.is_read_only() {
+        return Err(status_error(
+            StatusCode::LOCKED,
+            "Model registry is read-only".to_string(),
+        ));
+    }
+    let name = params.get("model_name").cloned().unwrap_or_default();
+    let loading = model_manager.clone();
+    let model_id = tokio::task::spawn_blocking(move || loading.load_model(&name))
+        .await
+        .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map_err(|e| status_error(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    Ok(Json(LoadedModel {
+        versions: model_manager.served_versions(&model_id),
+        model: model_id.0,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct UnloadedModel {
+    model: String,
+    /// Versions no longer served.
+    versions_retired: usize,
+}
+
+/// Stops serving a model, answering the requests waiting in its buffer with an error.
+async fn unload_model_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<UnloadedModel>, InferenceError> {
+    if model_manager.is_read_only() {
+        return Err(status_error(
+            StatusCode::LOCKED,
+            "Model registry is read-only".to_string(),
+        ));
+    }
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    let versions_retired = model_manager
+        .unload_model(&model_id)
+        .map_err(|e| status_error(StatusCode::NOT_FOUND, e))?;
+    Ok(Json(UnloadedModel {
+        model: model_id.0,
+        versions_retired,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct PauseQuery {
+    /// Seconds clients are told to wait before retrying.
+    retry_after: Option<u64>,
+}
+
+/// Refuses new requests for a model with 503 and `Retry-After` until it is resumed.
+/// Requests already buffered or running still run.
+async fn pause_model_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<PauseQuery>,
+) -> Result<Json<ModelPause>, InferenceError> {
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    let retry_after = query
+        .retry_after
+        .map_or(DEFAULT_PAUSE_RETRY_AFTER, Duration::from_secs);
+    model_manager
+        .pause_model(&model_id, retry_after)
+        .map(Json)
+        .map_err(|e| status_error(StatusCode::NOT_FOUND, e))
+}
+
+/// Admits requests for a paused model again.
+async fn resume_model_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<ModelPause>, InferenceError> {
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    model_manager
+        .resume_model(&model_id)
+        .map(Json)
+        .ok_or_else(|| {
+            status_error(
+                StatusCode::NOT_FOUND,
+                format!("Model {} is not paused", model_id),
+            )
+        })
+}
+
+/// Models refusing new requests.
+async fn paused_models_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<Vec<ModelPause>> {
+    Json(model_manager.paused_models())
+}
+
+/// The circuit of every model that ran requests, see `foundation::CircuitPolicy`.
+async fn circuits_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<Vec<CircuitStats>> {
+    Json(model_manager.circuits())
+}
+
+/// Upper bound on how long a drain may hold the connection.
+const MAX_DRAIN_SECS: u64 = 300;
+const DEFAULT_DRAIN_SECS: u64 = 30;
+
+#[derive(Debug, Deserialize)]
+struct DrainModelQuery {
+    /// Seconds to wait for the requests of the model to finish.
+    timeout: Option<u64>,
+    /// Fail the buffered requests instead of running them.
+    #[serde(default)]
+    drop: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DrainedModel {
+    model: String,
+    /// True when no request of the model is left buffered or running.
+    drained: bool,
+    /// Requests still buffered or running.
+    pending: usize,
+    /// Buffered requests failed instead of run.
+    dropped: usize,
+}
+
+/// Pauses a model and waits until the requests buffered and running for it finish, so its
+/// artifacts can be swapped. The model stays paused until resumed.
+async fn drain_model_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<DrainModelQuery>,
+) -> Result<Json<DrainedModel>, InferenceError> {
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    if model_manager.check_paused(&model_id).is_ok() {
+        model_manager
+            .pause_model(&model_id, DEFAULT_PAUSE_RETRY_AFTER)
+            .map_err(|e| status_error(StatusCode::NOT_FOUND, e))?;
+    }
+    let dropped = if query.drop {
+        model_manager.drain_buffers(Some(&model_id)).await
+    } else {
+        0
+    };
+    let timeout = Duration::from_secs(
+        query
+            .timeout
+            .unwrap_or(DEFAULT_DRAIN_SECS)
+            .min(MAX_DRAIN_SECS),
+    );
+    let drained = model_manager
+        .drain_model(&model_id, std::time::Instant::now() + timeout)
+        .await;
+    log_info!(
+        "Drained model {}: {}",
+        model_id,
+        if drained { "done" } else { "timed out" }
+    );
+    Ok(Json(DrainedModel {
+        pending: model_manager.model_pending_requests(&model_id),
+        model: model_id.0,
+        drained,
+        dropped,
+    }))
+}
+
+/// Calls in flight and restart state of every instance of a model version.
+async fn instances_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<Vec<InstanceStatus>>, InferenceError> {
+    let version_id = version_id(&params);
+    model_manager
+        .instances(&version_id)
+        .map(Json)
+        .ok_or_else(|| {
+            status_error(
+                StatusCode::NOT_FOUND,
+                format!("Model {} has no restartable instances", version_id),
+            )
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct RestartQuery {
+    drain_timeout_ms: Option<u64>,
+}
+
+/// Restarts one instance of a model version: drains its calls, reloads its runtime and
+/// returns it to the pool. The other instances and models keep serving.
+async fn restart_instance_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<RestartQuery>,
+) -> Result<Json<InstanceRestart>, InferenceError> {
+    let version_id = version_id(&params);
+    let index: usize = params
+        .get("instance")
+        .and_then(|instance| instance.parse().ok())
+        .ok_or_else(|| {
+            status_error(
+                StatusCode::BAD_REQUEST,
+                "The instance must be a non-negative number".to_string(),
+            )
+        })?;
+    let Some(instances) = model_manager.instances(&version_id) else {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("Model {} has no restartable instances", version_id),
+        ));
+    };
+    match instances.get(index) {
+        None => {
+            return Err(status_error(
+                StatusCode::NOT_FOUND,
+                format!("Model {} has no instance {}", version_id, index),
+            ));
+        }
+        Some(instance) if instance.restarting => {
+            return Err(status_error(
+                StatusCode::CONFLICT,
+                format!("Instance {} of {} is already restarting", index, version_id),
+            ));
+        }
+        Some(_) => {}
+    }
+    if model_manager.is_read_only() {
+        return Err(status_error(
+            StatusCode::LOCKED,
+            "Model registry is read-only".to_string(),
+        ));
+    }
+    let drain_timeout =
+        Duration::from_millis(query.drain_timeout_ms.unwrap_or(DEFAULT_DRAIN_TIMEOUT_MS));
+    model_manager
+        .restart_instance(&version_id, index, drain_timeout)
+        .await
+        .map(Json)
+        .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShadowVersions {
+    versions: Vec<String>,
+}
+
+/// Outcome and latency of the requests duplicated to shadow versions.
+async fn shadow_stats_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<Vec<ShadowStats>> {
+    Json(model_manager.shadow_stats())
+}
+
+/// Replaces the shadow versions of a model; an empty list stops shadowing.
+async fn shadow_versions_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+    Json(body): Json<ShadowVersions>,
+) -> Result<Json<ShadowVersions>, InferenceError> {
+    let model_id = ModelId(params.get("model_name").cloned().unwrap_or_default());
+    if !model_manager.get_models().contains(&model_id) {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("Model '{}' not found", model_id),
+        ));
+    }
+    model_manager
+        .set_shadow_versions(model_id.clone(), body.versions)
+        .map_err(|e| status_error(StatusCode::LOCKED, e))?;
+    Ok(Json(ShadowVersions {
+        versions: model_manager.shadow_versions(&model_id),
+    }))
+}
+
+/// Error budget of every tenant seen in the current window.
+async fn tenants_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<Vec<TenantStats>> {
+    Json(model_manager.error_budget().stats())
+}
+
+/// Bytes received from and sent to every tenant today, with their daily limits.
+async fn traffic_handler(State(state): State<AppState>) -> Json<Vec<TenantTraffic>> {
+    Json(state.traffic.usage())
+}
+
+/// Lifts the throttling or quarantine of a tenant, forgetting its recorded failures.
+async fn release_tenant_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<StatusCode, InferenceError> {
+    let tenant = params.get("tenant").cloned().unwrap_or_default();
+    if !model_manager.error_budget().release(&tenant) {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("Tenant '{}' is neither throttled nor quarantined", tenant),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadOnlyMode {
+    read_only: bool,
+}
+
+async fn read_only_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+) -> Json<ReadOnlyMode> {
+    Json(ReadOnlyMode {
+        read_only: model_manager.is_read_only(),
+    })
+}
+
+/// Freezes the model registry during maintenance; inference keeps being served.
+async fn set_read_only_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Json(body): Json<ReadOnlyMode>,
+) -> Json<ReadOnlyMode> {
+    if body.read_only != model_manager.is_read_only() {
+        log_info!(
+            "Model registry {}",
+            if body.read_only {
+                "locked (read-only)"
+            } else {
+                "unlocked"
+            }
+        );
+    }
+    model_manager.set_read_only(body.read_only);
+    Json(ReadOnlyMode {
+        read_only: model_manager.is_read_only(),
+    })
+}
+
+pub fn new_admin_router(state: AppState) -> Router {
+    Router::new()
+        .route("/buffers", get(buffers_handler))
+        .route("/buffers/drain", post(drain_buffers_handler))
+        .route("/circuits", get(circuits_handler))
+        .route("/config", get(config_handler))
+        .route("/config/reload", post(config_reload_handler))
+        .route("/devices", get(devices_handler))
+        .route(
+            "/log-level",
+            get(log_level_handler).put(set_log_level_handler),
+        )
+        .route("/memory", get(memory_handler))
+        .route("/models/{model_name}/load", post(load_model_handler))
+        .route("/models/{model_name}/unload", post(unload_model_handler))
+        .route("/models/{model_name}/pause", post(pause_model_handler))
+        .route("/models/{model_name}/resume", post(resume_model_handler))
+        .route("/models/{model_name}/drain", post(drain_model_handler))
+        .route("/paused", get(paused_models_handler))
+        .route("/models/{model_name}/selftest", post(selftest_handler))
+        .route("/models/{model_name}/timeseries", get(timeseries_handler))
+        .route(
+            "/models/{model_name}/versions/{model_version}/instances",
+            get(instances_handler),
+        )
+        .route(
+            "/models/{model_name}/versions/{model_version}/instances/{instance}/restart",
+            post(restart_instance_handler),
+        )
+        .route("/scheduler", get(scheduler_handler))
+        .route("/shadow", get(shadow_stats_handler))
+        .route("/stats", get(stats_handler))
+        .route("/shadow/{model_name}", put(shadow_versions_handler))
+        .route("/tenants", get(tenants_handler))
+        .route("/tenants/{tenant}/release", post(release_tenant_handler))
+        .route("/traffic", get(traffic_handler))
+        .route(
+            "/read-only",
+            get(read_only_handler).put(set_read_only_handler),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use foundation::{
+    API_KEY_HEADER, AuditLogger, AuditRecord, CORRELATION_ID_HEADER, TENANT_HEADER,
+    caller_identity, correlation_id,
+};
+
+use crate::model::inference_model;
+use crate::traffic::counted;
+
+/// Version addressed by an inference path, `/{version}/models/{model}/versions/{v}/...`.
+fn path_version(path: &str) -> Option<String> {
+    let mut segments = path.trim_start_matches('/').split('/').skip(3);
+    (segments.next()? == "versions").then(|| segments.next().map(str::to_string))?
+}
+
+/// Records every inference request (POSTs to a model) in the audit log, refused ones
+/// included.
+pub async fn audit_inference(
+    State(audit): State<AuditLogger>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    let Some(model) = inference_model(path).map(str::to_string) else {
+        return next.run(request).await;
+    };
+    let version = path_version(path);
+    let headers = request.headers();
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let addr = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let caller = caller_identity(header(TENANT_HEADER), header(API_KEY_HEADER), addr);
+    let external_id = correlation_id(header);
+
+    let started = Instant::now();
+    let received = Arc::new(AtomicU64::new(0));
+    let (parts, body) = request.into_parts();
+    let body = {
+        let received = received.clone();
+        counted(body, move |bytes| {
+            received.fetch_add(bytes, Ordering::Relaxed);
+        })
+    };
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    // Handlers echo the id they gave the request, generated or the caller's own.
+    let request_id = response
+        .headers()
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string)
+        .or(external_id)
+        .unwrap_or_default();
+    audit.log(
+        AuditRecord::new("rest", model, request_id)
+            .with_version(version)
+            .with_caller(caller)
+            .with_bytes(
+                received.load(Ordering::Relaxed),
+                response.body().size_hint().exact(),
+            )
+            .with_outcome(response.status().as_str(), started.elapsed()),
+    );
+    response
+}
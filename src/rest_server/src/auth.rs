@@ -0,0 +1,136 @@
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use foundation::{
+    AuthError, Authenticated, Authenticator, Role, TENANT_HEADER, Target, request_tenant,
+};
+
+use crate::data_model::ErrorInferenceResponse;
+use crate::error::status_error;
+use crate::openapi::{DOCS_PATH, OPENAPI_PATH};
+
+/// 401 with a Bearer challenge for requests without a valid key, 403 otherwise.
+pub fn refused(error: AuthError) -> (StatusCode, HeaderMap, Json<ErrorInferenceResponse>) {
+    let mut headers = HeaderMap::new();
+    let status = if error.is_unauthenticated() {
+        headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::FORBIDDEN
+    };
+    let (status, body) = status_error(status, error);
+    (status, headers, body)
+}
+
+/// Path of the WebSocket inference stream, whose clients cannot set headers.
+const WEBSOCKET_PATH: &str = "/v1/stream";
+
+/// Moves the `access_token` query parameter of a request without an `Authorization`
+/// header to one, for browsers opening a WebSocket.
+fn token_from_query(request: &mut Request) {
+    if request.headers().contains_key(header::AUTHORIZATION) {
+        return;
+    }
+    let token = request.uri().query().and_then(|query| {
+        query.split('&').find_map(|pair| {
+            let value = pair.strip_prefix("access_token=")?;
+            urlencoding::decode(value)
+                .ok()
+                .map(|value| value.into_owned())
+        })
+    });
+    if let Some(value) =
+        token.and_then(|token| HeaderValue::from_str(&format!("Bearer {}", token)).ok())
+    {
+        request.headers_mut().insert(header::AUTHORIZATION, value);
+    }
+}
+
+/// Replaces the tenant header of a request with its tenant, see `foundation::request_tenant`:
+/// past this middleware, the header names the tenant the request is accounted to, or is
+/// absent for anonymous requests. Runs within `authenticate`.
+pub async fn identify_tenant(
+    State(trust_header): State<bool>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let header = request
+        .headers()
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok());
+    let tenant = request_tenant(
+        header,
+        request.extensions().get::<Authenticated>(),
+        trust_header,
+    )
+    .and_then(|tenant| HeaderValue::from_str(&tenant).ok());
+    match tenant {
+        Some(tenant) => request.headers_mut().insert(TENANT_HEADER, tenant),
+        None => request.headers_mut().remove(TENANT_HEADER),
+    };
+    next.run(request).await
+}
+
+pub fn authorization(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Requires a valid API key or token on every request but health probes, metric
+/// scrapes and the API description. Inference needs the infer role, the admin API the admin role and the rest
+/// the read-only one. Model endpoints also require keys to be scoped to the model in
+/// the path, and the admin API a key that is not scoped at all. Authorized requests carry
+/// their account as an `Authenticated` extension.
+pub async fn authenticate(
+    State(authenticator): State<Authenticator>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.uri().path() == WEBSOCKET_PATH {
+        token_from_query(&mut request);
+    }
+    let path = request.uri().path();
+    if matches!(
+        path,
+        "/metrics" | "/livez" | "/readyz" | "/healthz" | OPENAPI_PATH | DOCS_PATH
+    ) {
+        return next.run(request).await;
+    }
+    // `/{version}/{area}/...`
+    let mut segments = path.trim_start_matches('/').split('/').skip(1);
+    let (role, target) = match (segments.next(), segments.next()) {
+        (Some("health"), _) => return next.run(request).await,
+        (Some("models"), Some(model)) if !model.is_empty() => {
+            let infers = segments
+                .last()
+                .is_some_and(|action| action.starts_with("infer"));
+            let role = if infers { Role::Infer } else { Role::ReadOnly };
+            (role, Target::Model(model))
+        }
+        // The model of OpenAI requests and batch jobs is in their body, it is authorized by
+        // the handler.
+        (
+            Some(
+                "inference" | "operations" | "streams" | "stream" | "completions" | "chat"
+                | "audio" | "batch_jobs",
+            ),
+            _,
+        ) => (Role::Infer, Target::Server),
+        // Shared memory regions hold the tensors of inferences.
+        (Some("systemsharedmemory"), _) => (Role::Infer, Target::Server),
+        (Some("admin"), _) => (Role::Admin, Target::AllModels),
+        _ => (Role::ReadOnly, Target::Server),
+    };
+    match authenticator.authorize(authorization(request.headers()), role, target) {
+        Ok(account) => {
+            request.extensions_mut().insert(Authenticated(account));
+            next.run(request).await
+        }
+        Err(error) => refused(error).into_response(),
+    }
+}
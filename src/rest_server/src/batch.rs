@@ -0,0 +1,103 @@
+/* Batch prediction job API, on when the server has a `--batch-dir`.
+
+```text
+POST /v1/batch_jobs        {"model_name": "iris", "input": "s3://bucket/rows.csv", "batch_size": 64}
+GET  /v1/batch_jobs
+GET  /v1/batch_jobs/{id}
+```
+
+A submission is answered with 202, the status of the job and its location;
+its rows then run in the background, see `foundation::batch`. The status tells
+the progress of the job and the path of its output. Jobs need the infer role,
+on their model for submissions.
+*/
+
+use std::collections::HashMap;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use foundation::{BatchJobSpec, BatchJobStatus, BatchJobs, Role, Target};
+use std::sync::Arc;
+
+use crate::auth::{authorization, refused};
+use crate::data_model::ErrorInferenceResponse;
+use crate::error::status_error;
+use crate::model::resolve_model;
+use crate::state::AppState;
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+fn batch_jobs(state: &AppState) -> Result<&Arc<BatchJobs>, InferenceError> {
+    state.batch_jobs.as_ref().ok_or_else(|| {
+        status_error(
+            StatusCode::NOT_FOUND,
+            "Batch jobs are not enabled, see --batch-dir",
+        )
+    })
+}
+
+async fn submit_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut spec): Json<BatchJobSpec>,
+) -> Result<Response, Response> {
+    let jobs = batch_jobs(&state).map_err(IntoResponse::into_response)?;
+    if let Some(authenticator) = &state.authenticator {
+        authenticator
+            .authorize(
+                authorization(&headers),
+                Role::Infer,
+                Target::Model(&spec.model_name),
+            )
+            .map_err(|error| refused(error).into_response())?;
+    }
+    let params = HashMap::from([("model_name".to_string(), spec.model_name.clone())])
+        .into_iter()
+        .chain(
+            spec.model_version
+                .clone()
+                .map(|version| ("model_version".to_string(), version)),
+        )
+        .collect();
+    let (_, version) =
+        resolve_model(&state.model_manager, &params).map_err(IntoResponse::into_response)?;
+    spec.model_version = version;
+    let status = jobs
+        .submit(spec)
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e).into_response())?;
+    let location = format!("/v1/batch_jobs/{}", status.id);
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(status),
+    )
+        .into_response())
+}
+
+async fn list_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<BatchJobStatus>>, InferenceError> {
+    Ok(Json(batch_jobs(&state)?.list()))
+}
+
+async fn status_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<BatchJobStatus>, InferenceError> {
+    batch_jobs(&state)?
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| status_error(StatusCode::NOT_FOUND, format!("Batch job {} not found", id)))
+}
+
+pub fn new_batch_router(state: AppState) -> Router {
+    Router::new()
+        .route("/v1/batch_jobs", get(list_handler).post(submit_handler))
+        .route("/v1/batch_jobs/{id}", get(status_handler))
+        .with_state(state)
+}
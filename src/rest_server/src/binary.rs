@@ -0,0 +1,419 @@
+/* Binary tensor data extension of the KServe V2 REST protocol.
+
+Large tensors (images, embeddings) are sent as raw little-endian bytes after
+the JSON of the request instead of as JSON numbers. The
+`Inference-Header-Content-Length` header gives the length of the JSON; the
+bytes of the inputs follow it, in the order of the inputs. An input sent as
+binary data has no `data` and a `binary_data_size` parameter, its number of
+bytes. Numeric inputs are not decoded: the runtime receives a view of their
+bytes in the request body (see `foundation::TensorView`), so large tensors are
+never copied. The elements of BYTES tensors, each preceded by its length as a
+little-endian 32-bit integer, are read into `data` before the request is
+negotiated.
+
+Outputs are returned as binary data when the requested output has the
+`binary_data` parameter set to true, or when the request has the
+`binary_data_output` parameter set to true for all outputs. The response then
+has the same layout: its JSON, whose length is in the
+`Inference-Header-Content-Length` header, followed by the bytes of these
+outputs.
+*/
+
+use std::collections::HashSet;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Json,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use foundation::api::tensor::{BYTES_DATATYPE, decode_byte_elements, encode_byte_elements};
+use foundation::{CastTensor, TensorView, Timeline};
+use serde_json::Value;
+
+use crate::data_model::{
+    ErrorInferenceResponse, InferenceRequest, InferenceResponse, Parameters, TensorData,
+};
+use crate::debug::debug_section;
+use crate::error::status_error;
+
+/// Length of the JSON preceding the binary data of a request or response.
+pub const INFERENCE_HEADER_CONTENT_LENGTH: &str = "inference-header-content-length";
+/// Parameter of a tensor sent as binary data, its number of bytes.
+pub const BINARY_DATA_SIZE_PARAMETER: &str = "binary_data_size";
+/// Parameter of a requested output asking for it as binary data.
+pub const BINARY_DATA_PARAMETER: &str = "binary_data";
+/// Parameter of a request asking for all its outputs as binary data.
+pub const BINARY_DATA_OUTPUT_PARAMETER: &str = "binary_data_output";
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+/// The JSON of a request body and the raw contents of its numeric inputs sent as binary
+/// data, in input order. The raw contents are slices of `body`, not copies.
+pub fn request_body(
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<(Value, Vec<Bytes>), InferenceError> {
+    let Some(length) = headers.get(INFERENCE_HEADER_CONTENT_LENGTH) else {
+        let request =
+            serde_json::from_slice(&body).map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+        return Ok((request, Vec::new()));
+    };
+    let length = length
+        .to_str()
+        .ok()
+        .and_then(|length| length.trim().parse::<usize>().ok())
+        .filter(|length| *length <= body.len())
+        .ok_or_else(|| {
+            status_error(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "{} must be a number of bytes of at most the {} bytes of the body",
+                    INFERENCE_HEADER_CONTENT_LENGTH,
+                    body.len()
+                ),
+            )
+        })?;
+    let mut request: Value = serde_json::from_slice(&body[..length])
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+    let binary = read_binary_inputs(&mut request, body.slice(length..))
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+    Ok((request, binary))
+}
+
+/// Splits `binary` between the inputs with a `binary_data_size`. Numeric inputs keep the
+/// parameter and their contents are returned, in input order; BYTES inputs have their
+/// elements moved to their `data`.
+fn read_binary_inputs(request: &mut Value, mut binary: Bytes) -> Result<Vec<Bytes>, String> {
+    let mut raw_contents = Vec::new();
+    let inputs = request
+        .get_mut("inputs")
+        .and_then(Value::as_array_mut)
+        .map(|inputs| inputs.as_mut_slice())
+        .unwrap_or_default();
+    for input in inputs {
+        let Some(size) = input
+            .get("parameters")
+            .and_then(|parameters| parameters.get(BINARY_DATA_SIZE_PARAMETER))
+            .cloned()
+        else {
+            continue;
+        };
+        let name = input
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let size = size
+            .as_u64()
+            .and_then(|size| usize::try_from(size).ok())
+            .ok_or_else(|| {
+                format!(
+                    "The {} of input '{}' must be a number of bytes",
+                    BINARY_DATA_SIZE_PARAMETER, name
+                )
+            })?;
+        if size > binary.len() {
+            return Err(format!(
+                "Input '{}' has {} bytes of binary data, only {} are left in the body",
+                name,
+                size,
+                binary.len()
+            ));
+        }
+        let datatype = input
+            .get("datatype")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let bytes = binary.split_to(size);
+        let unreadable =
+            |e: anyhow::Error| format!("Input '{}' cannot be read from binary data: {}", name, e);
+        let elements = if datatype == BYTES_DATATYPE {
+            let data = read_byte_elements(&bytes).map_err(unreadable)?;
+            let elements = data.len();
+            if let Some(parameters) = input["parameters"].as_object_mut() {
+                parameters.remove(BINARY_DATA_SIZE_PARAMETER);
+            }
+            input["data"] =
+                serde_json::to_value(TensorData::String(data)).map_err(|e| e.to_string())?;
+            elements
+        } else {
+            let view = datatype
+                .parse()
+                .and_then(|datatype| TensorView::new(datatype, bytes.clone()))
+                .map_err(unreadable)?;
+            raw_contents.push(bytes);
+            view.len()
+        };
+        // Variable dimensions (-1) match any number of elements.
+        let shape: Option<Vec<u64>> = input
+            .get("shape")
+            .and_then(Value::as_array)
+            .and_then(|dims| dims.iter().map(Value::as_u64).collect());
+        if let Some(shape) = shape {
+            // A shape the client sent may have more elements than can be counted.
+            let expected = shape
+                .iter()
+                .try_fold(1u64, |product, dim| product.checked_mul(*dim));
+            if expected != Some(elements as u64) {
+                let expected =
+                    expected.map_or_else(|| "more than 2^64".to_string(), |n| n.to_string());
+                return Err(format!(
+                    "Input '{}' of shape {:?} has {} elements, its binary data holds {}",
+                    name, shape, expected, elements
+                ));
+            }
+        }
+    }
+    if !binary.is_empty() {
+        return Err(format!(
+            "{} bytes of binary data are not claimed by any input",
+            binary.len()
+        ));
+    }
+    Ok(raw_contents)
+}
+
+/// Elements of the raw contents of a BYTES tensor. JSON has no byte strings: they must be
+/// text.
+fn read_byte_elements(bytes: &[u8]) -> anyhow::Result<Vec<String>> {
+    Ok(decode_byte_elements(bytes)?
+        .into_iter()
+        .map(String::from_utf8)
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Outputs a request asked for as binary data.
+#[derive(Debug, Default)]
+pub struct BinaryOutputs {
+    all: bool,
+    names: HashSet<String>,
+}
+
+impl BinaryOutputs {
+    pub fn of(payload: &InferenceRequest) -> Self {
+        let enabled = |parameters: Option<&Parameters>, name: &str| {
+            parameters
+                .and_then(|parameters| parameters.get(name))
+                .and_then(Value::as_bool)
+                .unwrap_or(false)
+        };
+        Self {
+            all: enabled(payload.parameters.as_ref(), BINARY_DATA_OUTPUT_PARAMETER),
+            names: payload
+                .outputs
+                .iter()
+                .flatten()
+                .filter(|output| enabled(output.parameters.as_ref(), BINARY_DATA_PARAMETER))
+                .map(|output| output.name.clone())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.names.is_empty()
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.all || self.names.contains(name)
+    }
+}
+
+/// Raw contents of `data` in `datatype`. Integers are cast as integers, 64-bit values do
+/// not fit in FP64.
+pub fn raw_bytes(data: &TensorData, datatype: &str) -> anyhow::Result<Vec<u8>> {
+    let tensor = match data {
+        TensorData::String(values) => {
+            return Ok(encode_byte_elements(values.iter().map(String::as_bytes)));
+        }
+        TensorData::Int32(values) => {
+            let values: Vec<i64> = values.iter().map(|v| *v as i64).collect();
+            CastTensor::from_i64(&values, datatype.parse()?)
+        }
+        TensorData::Int64(values) => CastTensor::from_i64(values, datatype.parse()?),
+        TensorData::UInt64(values) => CastTensor::from_u64(values, datatype.parse()?),
+        TensorData::Bool(values) => {
+            let values: Vec<i64> = values.iter().map(|v| *v as i64).collect();
+            CastTensor::from_i64(&values, datatype.parse()?)
+        }
+        TensorData::Float32(values) => {
+            let values: Vec<f64> = values.iter().map(|v| *v as f64).collect();
+            CastTensor::new(&values, datatype.parse()?)
+        }
+        TensorData::Float64(values) => CastTensor::new(values, datatype.parse()?),
+    };
+    Ok(tensor.raw_bytes())
+}
+
+/// `response` with the outputs in `binary` sent as binary data after its JSON, adding a
+/// `debug` section when a timeline was collected.
+pub fn binary_response(
+    timeline: Option<&Timeline>,
+    mut response: InferenceResponse,
+    binary: &BinaryOutputs,
+) -> Result<Response, InferenceError> {
+    let mut bytes = Vec::new();
+    for output in response.outputs.iter_mut().flatten() {
+        if !binary.contains(&output.name) {
+            continue;
+        }
+        let Some(data) = output.data.take() else {
+            continue;
+        };
+        let raw = raw_bytes(&data, &output.datatype)
+            .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        output
+            .parameters
+            .get_or_insert_default()
+            .insert(BINARY_DATA_SIZE_PARAMETER.to_string(), raw.len().into());
+        bytes.extend(raw);
+    }
+    let mut value = serde_json::to_value(response)
+        .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if let (Some(timeline), Some(object)) = (timeline, value.as_object_mut()) {
+        object.insert("debug".to_string(), debug_section(timeline));
+    }
+    let mut body = serde_json::to_vec(&value).unwrap_or_default();
+    let length = body.len();
+    body.extend(bytes);
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            ),
+            (
+                header::HeaderName::from_static(INFERENCE_HEADER_CONTENT_LENGTH),
+                HeaderValue::from(length),
+            ),
+        ],
+        Body::from(body),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundation::OutputDatatype;
+    use serde_json::json;
+
+    /// A request body of `inputs` followed by `binary`, with its length header.
+    fn body(inputs: Value, binary: &[u8]) -> (HeaderMap, Bytes) {
+        let mut body = serde_json::to_vec(&json!({ "inputs": inputs })).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            INFERENCE_HEADER_CONTENT_LENGTH,
+            HeaderValue::from(body.len()),
+        );
+        body.extend(binary);
+        (headers, Bytes::from(body))
+    }
+
+    #[tokio::test]
+    async fn test_64_bit_outputs_round_trip_as_binary_data() {
+        let response: InferenceResponse = serde_json::from_value(json!({
+            "model_name": "m",
+            "outputs": [
+                {"name": "signed", "datatype": "INT64", "shape": [2],
+                 "data": [i64::MIN, i64::MAX]},
+                {"name": "unsigned", "datatype": "UINT64", "shape": [2],
+                 "data": [u64::MAX, (1u64 << 53) + 1]},
+            ],
+        }))
+        .unwrap();
+        let binary = BinaryOutputs {
+            all: true,
+            names: HashSet::new(),
+        };
+        let response = binary_response(None, response, &binary).unwrap();
+        let length: usize = response.headers()[INFERENCE_HEADER_CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: Value = serde_json::from_slice(&bytes[..length]).unwrap();
+        assert_eq!(json["outputs"][0]["parameters"]["binary_data_size"], 16);
+        assert!(json["outputs"][0].get("data").is_none());
+
+        // The outputs are sent back as inputs: their raw contents are unchanged.
+        let inputs = json!([
+            {"name": "signed", "datatype": "INT64", "shape": [2],
+             "parameters": {"binary_data_size": 16}},
+            {"name": "unsigned", "datatype": "UINT64", "shape": [2],
+             "parameters": {"binary_data_size": 16}},
+        ]);
+        let (headers, body) = body(inputs, &bytes[length..]);
+        let (_, raw) = request_body(&headers, body).unwrap();
+        assert_eq!(
+            CastTensor::from_raw_bytes(&raw[0], OutputDatatype::Int64).unwrap(),
+            CastTensor::from_i64(&[i64::MIN, i64::MAX], OutputDatatype::Int64)
+        );
+        assert_eq!(
+            CastTensor::from_raw_bytes(&raw[1], OutputDatatype::Uint64).unwrap(),
+            CastTensor::from_u64(&[u64::MAX, (1 << 53) + 1], OutputDatatype::Uint64)
+        );
+    }
+
+    #[test]
+    fn test_bytes_elements_are_read_into_data() {
+        let data = TensorData::String(vec!["".to_string(), "héllo".to_string()]);
+        let raw = raw_bytes(&data, BYTES_DATATYPE).unwrap();
+        assert_eq!(raw.len(), 4 + 4 + "héllo".len());
+        let inputs = json!([
+            {"name": "text", "datatype": "BYTES", "shape": [2],
+             "parameters": {"binary_data_size": raw.len()}},
+        ]);
+        let (headers, body) = body(inputs, &raw);
+        let (request, numeric) = request_body(&headers, body).unwrap();
+        assert!(numeric.is_empty());
+        assert_eq!(request["inputs"][0]["data"], json!(["", "héllo"]));
+        assert!(
+            request["inputs"][0]["parameters"]
+                .get(BINARY_DATA_SIZE_PARAMETER)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_bad_binary_data_sizes_are_refused() {
+        let refused = |size: Value, binary: &[u8]| {
+            let inputs = json!([
+                {"name": "x", "datatype": "INT32", "shape": [2],
+                 "parameters": {"binary_data_size": size}},
+            ]);
+            let (headers, body) = body(inputs, binary);
+            let (status, Json(error)) = request_body(&headers, body).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            error.error
+        };
+        // More bytes than the body holds.
+        assert!(refused(json!(16), &[0; 8]).contains("only 8 are left"));
+        // Not a number of bytes.
+        assert!(refused(json!("8"), &[0; 8]).contains("must be a number of bytes"));
+        assert!(refused(json!(-8), &[0; 8]).contains("must be a number of bytes"));
+        // Not a whole number of elements.
+        assert!(refused(json!(6), &[0; 6]).contains("cannot be read"));
+        // Fewer elements than the shape.
+        assert!(refused(json!(4), &[0; 4]).contains("has 2 elements"));
+        // Bytes no input claims.
+        assert!(refused(json!(8), &[0; 12]).contains("not claimed"));
+    }
+
+    #[test]
+    fn test_shapes_too_large_to_count_are_refused() {
+        // The element count wraps around to 0 without checks.
+        let inputs = json!([
+            {"name": "x", "datatype": "INT32", "shape": [1u64 << 32, 1u64 << 32],
+             "parameters": {"binary_data_size": 0}},
+        ]);
+        let (headers, body) = body(inputs, &[]);
+        let (status, Json(error)) = request_body(&headers, body).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.error.contains("has more than 2^64 elements"));
+    }
+}
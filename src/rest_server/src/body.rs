@@ -0,0 +1,185 @@
+/* Compression of responses and request bodies, and the limit on their size.
+
+Responses are compressed with gzip or brotli when the client accepts either
+(`Accept-Encoding`), except event streams, which must reach the client as each
+event is sent, and responses too small to gain from it. Request bodies sent
+with `Content-Encoding: gzip` or `br` are decompressed before anything reads
+them; other encodings are refused with 415.
+
+Bodies are limited to `--max-request-bytes`, 2 MiB by default, counted after
+decompression so a small compressed payload cannot expand past it. A larger
+body is refused with 413 and an error body naming the limit:
+
+```json
+{"error": "Request body exceeds the limit of 2097152 bytes", "status": "OUT_OF_RANGE", ...}
+```
+
+The other refusals of the extractors (a malformed JSON body, a missing
+content type) are answered in the error body of the API as well, instead of
+plain text.
+*/
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+
+use crate::error::status_error;
+
+/// Largest request body when no limit is configured, that of the extractors.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Compresses responses with gzip or brotli, as the client accepts.
+pub fn compression_layer() -> CompressionLayer {
+    CompressionLayer::new().gzip(true).br(true)
+}
+
+/// Decompresses gzip and brotli request bodies.
+pub fn decompression_layer() -> RequestDecompressionLayer {
+    RequestDecompressionLayer::new().gzip(true).br(true)
+}
+
+/// Answers the plain text refusals of the extractors and body layers in the error body of
+/// the API, 413 naming the body limit `limit`. Other responses are passed through.
+pub async fn describe_rejections(
+    State(limit): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|content_type| content_type.as_bytes().starts_with(b"text/plain"));
+    if !status.is_client_error() || !is_text {
+        return response;
+    }
+    if status == StatusCode::PAYLOAD_TOO_LARGE {
+        let message = format!("Request body exceeds the limit of {} bytes", limit);
+        return status_error(status, message).into_response();
+    }
+    let (parts, body) = response.into_parts();
+    // Rejections are short messages rendered in memory.
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let message = match String::from_utf8_lossy(&bytes).trim() {
+        "" => status
+            .canonical_reason()
+            .unwrap_or("Request refused")
+            .to_string(),
+        text => text.to_string(),
+    };
+    let mut response = status_error(status, message).into_response();
+    for (name, value) in &parts.headers {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().append(name, value.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::send;
+    use axum::{Json, Router, extract::DefaultBodyLimit, middleware, routing::post};
+    use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+    use serde_json::{Value, json};
+    use std::io::{Read, Write};
+
+    const LIMIT: usize = 4096;
+
+    /// A router echoing JSON bodies behind the body layers of the REST server.
+    fn echo() -> Router {
+        Router::new()
+            .route(
+                "/echo",
+                post(|Json(body): Json<Value>| async { Json(body) }),
+            )
+            .layer(DefaultBodyLimit::max(LIMIT))
+            .layer(decompression_layer())
+            .layer(middleware::from_fn_with_state(LIMIT, describe_rejections))
+            .layer(compression_layer())
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn post_echo(body: impl Into<Body>, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::post("/echo").header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(body.into()).unwrap()
+    }
+
+    /// A JSON body of about `size` bytes.
+    fn padded(size: usize) -> Value {
+        json!({ "padding": "a".repeat(size) })
+    }
+
+    #[tokio::test]
+    async fn test_compressed_bodies_are_read_and_answered_compressed() {
+        let body = padded(1000);
+        let request = post_echo(
+            gzip(body.to_string().as_bytes()),
+            &[("content-encoding", "gzip"), ("accept-encoding", "gzip")],
+        );
+        let (status, headers, answer) = send(echo(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+        let mut echoed = String::new();
+        GzDecoder::new(&answer[..])
+            .read_to_string(&mut echoed)
+            .unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&echoed).unwrap(), body);
+
+        // Too small to gain from compression.
+        let request = post_echo("{}", &[("accept-encoding", "gzip")]);
+        let (status, headers, answer) = send(echo(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(&answer[..], b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_bodies_past_the_limit_are_refused_once_decompressed() {
+        let body = padded(2 * LIMIT).to_string();
+        assert!(gzip(body.as_bytes()).len() < LIMIT);
+        for request in [
+            post_echo(body.clone(), &[]),
+            post_echo(gzip(body.as_bytes()), &[("content-encoding", "gzip")]),
+        ] {
+            let (status, _, answer) = send(echo(), request).await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+            let error: Value = serde_json::from_slice(&answer).unwrap();
+            assert_eq!(
+                error["error"],
+                format!("Request body exceeds the limit of {} bytes", LIMIT)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refusals_are_answered_in_the_error_body() {
+        let request = post_echo("{}", &[("content-encoding", "compress")]);
+        let (status, _, _) = send(echo(), request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let (status, headers, answer) = send(echo(), post_echo("{not json", &[])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+        let error: Value = serde_json::from_slice(&answer).unwrap();
+        assert!(error["error"].as_str().unwrap().contains("JSON"));
+    }
+}
@@ -0,0 +1,44 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use foundation::{ApiError, ModelId};
+use std::time::Duration;
+
+use crate::error::api_error;
+use crate::model::inference_model;
+use crate::state::AppState;
+
+/// Sheds inference requests (POSTs to a model) for paused models and beyond the global or
+/// per-model concurrency limit, before they reach the request buffers.
+pub async fn shed_excess(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(model) = inference_model(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    // Unknown models only count globally, so made-up names are not tracked one by one.
+    let model_id = ModelId(model.to_string());
+    let model = state.model_manager.has_model(&model_id).then_some(model);
+    if let Err(paused) = state.model_manager.check_paused(&model_id) {
+        return (
+            [(
+                header::RETRY_AFTER,
+                HeaderValue::from(paused.retry_after.as_secs()),
+            )],
+            api_error(paused.into()),
+        )
+            .into_response();
+    }
+    match state.concurrency.try_acquire(model) {
+        Ok(_permit) => next.run(request).await,
+        Err(shed) => (
+            [(header::RETRY_AFTER, HeaderValue::from_static("1"))],
+            api_error(ApiError::unavailable(shed).with_retry_delay(Duration::from_secs(1))),
+        )
+            .into_response(),
+    }
+}
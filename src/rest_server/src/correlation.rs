@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use axum::body::{Body, to_bytes};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use foundation::{CORRELATION_ID_HEADER, IdProvider, correlation_id};
+
+use crate::data_model::ErrorInferenceResponse;
+
+/// Gives a request without an id the caller's correlation id or a generated one,
+/// and returns the correlation id to echo back.
+pub fn assign_request_id(
+    ids: &dyn IdProvider,
+    headers: &HeaderMap,
+    request_id: &mut Option<String>,
+) -> String {
+    let external = correlation_id(|name| headers.get(name).and_then(|v| v.to_str().ok()));
+    match (request_id.as_ref(), external) {
+        (Some(_), Some(external)) => external,
+        (Some(id), None) => id.clone(),
+        (None, external) => {
+            let id = external.unwrap_or_else(|| ids.next_id());
+            *request_id = Some(id.clone());
+            id
+        }
+    }
+}
+
+pub fn with_correlation_id(correlation_id: String, response: impl IntoResponse) -> Response {
+    ([(CORRELATION_ID_HEADER, correlation_id)], response).into_response()
+}
+
+/// Gives the error bodies of the REST API the id of their request, the correlation id
+/// echoed back or, for requests refused before they were identified, the caller's or a
+/// generated one, echoed back too. Other responses are passed through.
+pub async fn identify_errors(
+    State(ids): State<Arc<dyn IdProvider>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let external = correlation_id(|name| request.headers().get(name).and_then(|v| v.to_str().ok()));
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !is_json || !(response.status().is_client_error() || response.status().is_server_error()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Error bodies are rendered in memory, read whole.
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let mut error = match serde_json::from_slice::<ErrorInferenceResponse>(&bytes) {
+        Ok(error) if error.request_id.is_none() => error,
+        // OpenAI errors have a layout of their own.
+        _ => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    let request_id = match parts
+        .headers
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+    {
+        Some(id) => id.to_string(),
+        None => {
+            let id = external.unwrap_or_else(|| ids.next_id());
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                parts.headers.insert(CORRELATION_ID_HEADER, value);
+            }
+            id
+        }
+    };
+    error.request_id = Some(request_id);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(
+        parts,
+        Body::from(serde_json::to_vec(&error).unwrap_or_default()),
+    )
+}
@@ -0,0 +1,92 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use foundation::{
+    API_KEY_HEADER, AUTHORIZATION_HEADER, CORRELATION_ID_HEADER, CorsConfig, DEBUG_HEADER,
+    MODEL_SELECTOR_HEADER, PRIORITY_HEADER, QUOTA_LIMIT_REQUESTS_HEADER, QUOTA_LIMIT_TOKENS_HEADER,
+    QUOTA_REMAINING_REQUESTS_HEADER, QUOTA_REMAINING_TOKENS_HEADER, QUOTA_RESET_HEADER,
+    RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER, RATE_LIMIT_RESET_HEADER,
+    REQUEST_TIMEOUT_HEADER, SCHEMA_VERSION_HEADER, TENANT_HEADER,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::binary::INFERENCE_HEADER_CONTENT_LENGTH;
+
+/// Request headers of the API, always allowed.
+const ALLOWED_HEADERS: &[&str] = &[
+    "content-type",
+    "last-event-id",
+    AUTHORIZATION_HEADER,
+    API_KEY_HEADER,
+    REQUEST_TIMEOUT_HEADER,
+    PRIORITY_HEADER,
+    MODEL_SELECTOR_HEADER,
+    SCHEMA_VERSION_HEADER,
+    CORRELATION_ID_HEADER,
+    DEBUG_HEADER,
+    TENANT_HEADER,
+    INFERENCE_HEADER_CONTENT_LENGTH,
+];
+
+/// Response headers scripts may read.
+const EXPOSED_HEADERS: &[&str] = &[
+    "retry-after",
+    CORRELATION_ID_HEADER,
+    SCHEMA_VERSION_HEADER,
+    INFERENCE_HEADER_CONTENT_LENGTH,
+    RATE_LIMIT_LIMIT_HEADER,
+    RATE_LIMIT_REMAINING_HEADER,
+    RATE_LIMIT_RESET_HEADER,
+    QUOTA_LIMIT_REQUESTS_HEADER,
+    QUOTA_REMAINING_REQUESTS_HEADER,
+    QUOTA_LIMIT_TOKENS_HEADER,
+    QUOTA_REMAINING_TOKENS_HEADER,
+    QUOTA_RESET_HEADER,
+];
+
+/// CORS of the REST API as configured by `config`.
+pub fn cors_layer(
+    config: &CorsConfig,
+) -> Result<CorsLayer, Box<dyn std::error::Error + Send + Sync>> {
+    config.check()?;
+    let allow_origin = if config.allows_any_origin() {
+        AllowOrigin::any()
+    } else {
+        let origins = config
+            .origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| format!("Invalid CORS origin '{}'", origin))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    let methods = config
+        .methods
+        .iter()
+        .map(|method| {
+            Method::from_bytes(method.as_bytes())
+                .map_err(|_| format!("Invalid CORS method '{}'", method))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut headers: Vec<HeaderName> = ALLOWED_HEADERS
+        .iter()
+        .map(|name| HeaderName::from_static(name))
+        .collect();
+    for name in &config.headers {
+        headers.push(
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid CORS header '{}'", name))?,
+        );
+    }
+    let exposed: Vec<HeaderName> = EXPOSED_HEADERS
+        .iter()
+        .map(|name| HeaderName::from_static(name))
+        .collect();
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(exposed)
+        .allow_credentials(config.allow_credentials)
+        .max_age(config.max_age))
+}
@@ -1,11 +1,11 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 pub type Parameters = HashMap<String, serde_json::Value>;
 
-/// The InferenceRequest struct, top-level request object
-
-#[derive(Serialize, Deserialize, Debug)]
+/// A V2 inference request.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct InferenceRequest {
     /// Optional identifier for this request
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -21,28 +21,69 @@ pub struct InferenceRequest {
     /// Optional requested outputs; if None, all model outputs are returned
     #[serde(skip_serializing_if = "Option::is_none")]
     pub outputs: Option<Vec<TensorRequestOutput>>,
+
+    /// Optional capabilities the request relies on, checked against the model's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<foundation::Capabilities>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A V2 inference response.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct InferenceResponse {
+    /// Name of the model that served the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_name: Option<String>,
+
+    /// Version of the model that served the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_version: Option<String>,
+
     /// Optional identifier for this request
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
 
+    /// Optional parameters, such as the execution hints that were ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Parameters>,
+
     /// Optional requested outputs; if None, all model outputs are returned
     #[serde(skip_serializing_if = "Option::is_none")]
     pub outputs: Option<Vec<MetadataTensor>>,
+
+    /// Debug information requested with the `x-galemind-debug` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<serde_json::Value>,
+}
+
+/// Status of an inference accepted for asynchronous execution
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AsyncInferenceStatus {
+    /// Identifier to poll the result with
+    pub id: String,
+
+    /// Either "pending" or "completed"
+    pub status: String,
+}
+
+/// First event of an inference stream, identifying it for resumption
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamToken {
+    /// Token of the stream
+    pub token: String,
+
+    /// Path resuming the stream, with the `Last-Event-ID` header
+    pub resume: String,
 }
 
 /// Represents an input tensor to the model
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MetadataTensor {
     /// Name of the input tensor
     pub name: String,
 
-    /// Shape of the input tensor (each dimension as u64)
-    pub shape: Vec<u64>,
+    /// Shape of the input tensor; -1 marks a variable dimension
+    pub shape: Vec<i64>,
 
     /// Data type of the tensor elements (e.g. "FP32", "INT64")
     pub datatype: String,
@@ -57,7 +98,7 @@ pub struct MetadataTensor {
 }
 
 /// Represents requested output tensor(s)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TensorRequestOutput {
     /// Name of the output tensor
@@ -68,31 +109,82 @@ pub struct TensorRequestOutput {
     pub parameters: Option<Parameters>,
 }
 
-/// Enum to hold tensor data (extendable based on supported tensor types)
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Elements of a tensor, flattened in row-major order
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum TensorData {
     // Numeric data as a flat array of numbers (integers or floats)
     Int32(Vec<i32>),
     Int64(Vec<i64>),
-    Float32(Vec<f32>),
+    /// Tried before the floats, which would take integers above `i64::MAX` with a loss
+    UInt64(Vec<u64>),
+    /// Tried before FP32, which would take JSON numbers out of its range as infinities
     Float64(Vec<f64>),
+    /// Serialized only, for outputs cast to FP32 or half precision
+    Float32(Vec<f32>),
     Bool(Vec<bool>),
+    /// Elements of BYTES tensors
+    String(Vec<String>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Metadata of a model
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MetadataModelResponse {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub versions: Option<Vec<String>>,
-    pub platform: Vec<String>,
+    pub platform: String,
     pub inputs: Vec<MetadataTensor>,
     pub outputs: Vec<MetadataTensor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<foundation::Capabilities>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A model of the catalog, as listed by `GET /v2/models`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ModelListEntry {
+    pub name: String,
+    pub versions: Vec<String>,
+    /// Backend serving the model, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Whether the version answering unversioned requests is loaded
+    pub ready: bool,
+    pub labels: foundation::Labels,
+}
+
+/// A refused or failed request
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct ErrorMetadataModelResponse {
+pub struct ErrorInferenceResponse {
     pub error: String,
+    /// Machine-readable reason, e.g. `context_length_exceeded`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Canonical code of the error, e.g. `INVALID_ARGUMENT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<foundation::ErrorCode>,
+    /// The `google.rpc` details of the error, e.g. the invalid fields of the request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<foundation::ErrorDetail>,
+    /// Id of the request, to quote when reporting the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Each input tensor mismatch of an `invalid_tensors` error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mismatches: Option<Vec<foundation::TensorMismatch>>,
+}
+
+impl From<foundation::ApiError> for ErrorInferenceResponse {
+    fn from(error: foundation::ApiError) -> Self {
+        Self {
+            code: error.reason().map(str::to_string),
+            status: Some(error.code),
+            error: error.message,
+            details: error.details,
+            request_id: error.request_id,
+            mismatches: None,
+        }
+    }
 }
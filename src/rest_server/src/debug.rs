@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    extract::Json,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use foundation::{DEBUG_HEADER, Timeline, timeline_requested};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+/// Timeline of a request that opted in with the debug header, starting at `arrived`.
+pub fn request_timeline(headers: &HeaderMap, arrived: Instant) -> Option<Arc<Timeline>> {
+    let value = headers.get(DEBUG_HEADER).and_then(|v| v.to_str().ok());
+    timeline_requested(value).then(|| Arc::new(Timeline::starting_at(arrived)))
+}
+
+/// The `debug` section returned with a response.
+pub fn debug_section(timeline: &Timeline) -> Value {
+    json!({
+        "elapsed_us": timeline.elapsed_us(),
+        "timeline": timeline.events(),
+    })
+}
+
+/// Serializes `response` as JSON, adding a `debug` section when a timeline was collected.
+pub fn json_with_debug<T: Serialize>(timeline: Option<&Timeline>, response: T) -> Response {
+    let Some(timeline) = timeline else {
+        return Json(response).into_response();
+    };
+    let started = Instant::now();
+    let mut value = serde_json::to_value(response).unwrap_or_default();
+    timeline.span("serialize", started, None);
+    if let Some(object) = value.as_object_mut() {
+        object.insert("debug".to_string(), debug_section(timeline));
+    }
+    Json(value).into_response()
+}
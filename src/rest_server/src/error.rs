@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use foundation::{ApiError, ErrorCode, ErrorDetail};
+
+use crate::data_model::ErrorInferenceResponse;
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+/// `error` answered with the HTTP status of its code.
+pub fn api_error(error: ApiError) -> InferenceError {
+    let status =
+        StatusCode::from_u16(error.code.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, Json(error.into()))
+}
+
+/// `message` answered with `status`, and the canonical code of that status.
+pub fn status_error(status: StatusCode, message: impl ToString) -> InferenceError {
+    let error = ApiError::new(ErrorCode::from_http_status(status.as_u16()), message);
+    (status, Json(error.into()))
+}
+
+/// Gives an error the id of the request it refuses.
+pub fn identified(request_id: String) -> impl Fn(InferenceError) -> InferenceError {
+    move |(status, Json(mut error))| {
+        error.request_id = Some(request_id.clone());
+        (status, Json(error))
+    }
+}
+
+/// How long the client of `error` is asked to wait before retrying, from its `RetryInfo`.
+pub fn retry_delay(error: &ErrorInferenceResponse) -> Option<Duration> {
+    error.details.iter().find_map(|detail| match detail {
+        ErrorDetail::RetryInfo { retry_delay } => Some(*retry_delay),
+        _ => None,
+    })
+}
+
+/// `Retry-After` value of `delay`, in whole seconds rounded up.
+pub fn retry_after_header(delay: Duration) -> HeaderValue {
+    HeaderValue::from(delay.as_secs_f64().ceil() as u64)
+}
+
+/// Gives the 429 and 503 error bodies with a retry delay, such as those of paused models or
+/// open circuits, the `Retry-After` header of that delay, whichever handler refused the
+/// request. Responses with the header already are passed through.
+pub async fn advertise_retry_delay(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let retried = matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    );
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !retried || !is_json || response.headers().contains_key(header::RETRY_AFTER) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Error bodies are rendered in memory, read whole.
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    if let Some(delay) = serde_json::from_slice::<ErrorInferenceResponse>(&bytes)
+        .ok()
+        .as_ref()
+        .and_then(retry_delay)
+    {
+        parts
+            .headers
+            .insert(header::RETRY_AFTER, retry_after_header(delay));
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware, response::IntoResponse, routing::get};
+    use tower::ServiceExt;
+
+    async fn retry_after(router: Router, path: &str) -> Option<HeaderValue> {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = router
+            .layer(middleware::from_fn(advertise_retry_delay))
+            .oneshot(request)
+            .await
+            .unwrap();
+        response.headers().get(header::RETRY_AFTER).cloned()
+    }
+
+    #[tokio::test]
+    async fn test_refusals_with_a_retry_delay_advertise_it() {
+        let router = Router::new()
+            .route(
+                "/paused",
+                get(|| async {
+                    api_error(
+                        ApiError::unavailable("paused")
+                            .with_retry_delay(Duration::from_millis(1500)),
+                    )
+                    .into_response()
+                }),
+            )
+            .route(
+                "/failed",
+                get(|| async {
+                    status_error(StatusCode::SERVICE_UNAVAILABLE, "failed").into_response()
+                }),
+            );
+        assert_eq!(
+            retry_after(router.clone(), "/paused").await,
+            Some(HeaderValue::from(2))
+        );
+        assert_eq!(retry_after(router, "/failed").await, None);
+    }
+}
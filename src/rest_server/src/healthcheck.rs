@@ -1,14 +1,94 @@
-use axum::{Router, extract::Path, response::IntoResponse, routing::get};
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use foundation::SHUTTING_DOWN;
+use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::state::AppState;
+
+#[derive(Serialize)]
+struct HealthReason {
+    condition: String,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct HealthStatus {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reasons: Vec<HealthReason>,
+}
+
+/// 200 when none of `failures` remain, else 503 with them.
+fn health_response(failures: Vec<(String, String)>, unhealthy: &'static str) -> Response {
+    let status = if failures.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = HealthStatus {
+        status: if failures.is_empty() { "ok" } else { unhealthy },
+        reasons: failures
+            .into_iter()
+            .map(|(condition, reason)| HealthReason { condition, reason })
+            .collect(),
+    };
+    (status, Json(body)).into_response()
+}
+
+/// The process is up and serving; never fails while it can answer.
+async fn livez_handler() -> Response {
+    health_response(Vec::new(), "unhealthy")
+}
+
+/// Whether traffic should be routed to the server, see `foundation::readiness`.
+async fn readyz_handler(State(state): State<AppState>) -> Response {
+    health_response(state.model_manager.readiness_failures(), "not ready")
+}
+
+/// Readiness without the shutdown condition: a draining server is still healthy.
+async fn healthz_handler(State(state): State<AppState>) -> Response {
+    let failures = state
+        .model_manager
+        .readiness_failures()
+        .into_iter()
+        .filter(|(condition, _)| condition != SHUTTING_DOWN)
+        .collect();
+    health_response(failures, "unhealthy")
+}
+
 async fn liveness_handler(Path(_): Path<HashMap<String, String>>) -> impl IntoResponse {
     "OK"
 }
-async fn readiness_handler(Path(_): Path<HashMap<String, String>>) -> impl IntoResponse {
-    "OK"
+
+async fn readiness_handler(
+    State(state): State<AppState>,
+    Path(_): Path<HashMap<String, String>>,
+) -> Response {
+    if state.model_manager.readiness_failures().is_empty() {
+        "OK".into_response()
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE.into_response()
+    }
 }
-pub fn new_health_check_router() -> Router {
+
+/// Kubernetes style probes, at the root of the server.
+pub fn new_probe_router(state: AppState) -> Router {
+    Router::new()
+        .route("/livez", get(livez_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/healthz", get(healthz_handler))
+        .with_state(state)
+}
+
+pub fn new_health_check_router(state: AppState) -> Router {
     Router::new()
         .route("/live", get(liveness_handler))
         .route("/ready", get(readiness_handler))
+        .with_state(state)
 }
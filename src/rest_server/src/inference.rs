@@ -0,0 +1,238 @@
+/* Results of asynchronous inferences (`infer_async`).
+
+```text
+GET /{version}/inference/{id}[?wait=<seconds>]
+GET /v1/operations/{id}[?wait=<seconds>]
+```
+
+Both answer 200 with the result once completed, 202 while pending and 500
+with the error of a failed inference. Results are kept in memory and, with a
+`--result-store`, in a shared store too (see `foundation::ResultBackend`), so
+that any replica answers for them, a restarted server included. An inference
+is then unknown to the other replicas until it is accepted by the store.
+
+An `x-callback-url` header on `infer_async` has the result POSTed to that
+http(s) URL once completed, `{"id", "status": "completed", "response"}` or
+`{"id", "status": "failed", "error"}`. Failed deliveries are retried a few
+times, then dropped; the result stays retrievable.
+*/
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Router,
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use foundation::{ResultBackend, ResultState};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::data_model::AsyncInferenceStatus;
+use crate::error::status_error;
+use crate::state::{ASYNC_RESULT_TTL, AppState, AsyncResult};
+use crate::tabular::{TabularFormat, tabular_response};
+
+/// Upper bound on how long a single long-poll may hold the connection.
+const MAX_WAIT_SECS: u64 = 60;
+/// How often a long-poll reads the shared store while the inference is pending.
+const SHARED_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// URL the result of an asynchronous inference is POSTed to once completed.
+pub const CALLBACK_URL_HEADER: &str = "x-callback-url";
+const CALLBACK_ATTEMPTS: u32 = 3;
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the second delivery of a callback, doubled for each one after.
+const CALLBACK_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+struct WaitQuery {
+    /// Seconds to wait for completion before answering with the pending status.
+    wait: Option<u64>,
+}
+
+/// A result as the shared store holds it.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum StoredResult {
+    Pending,
+    Completed { result: AsyncResult },
+}
+
+/// The callback URL of an `infer_async` request, if it has a valid one.
+pub fn callback_url(headers: &HeaderMap) -> Result<Option<Url>, String> {
+    let Some(value) = headers.get(CALLBACK_URL_HEADER) else {
+        return Ok(None);
+    };
+    let url = value
+        .to_str()
+        .ok()
+        .and_then(|value| Url::parse(value).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| format!("The {} header must be an http(s) URL", CALLBACK_URL_HEADER))?;
+    Ok(Some(url))
+}
+
+/// Writes the state of inference `id` to the shared store, if there is one. Failures are
+/// logged: the result stays in memory.
+pub async fn share(state: &AppState, id: &str, result: &ResultState<AsyncResult>) {
+    let Some(backend) = &state.result_backend else {
+        return;
+    };
+    let stored = match result {
+        ResultState::Pending => StoredResult::Pending,
+        ResultState::Completed(result) => StoredResult::Completed {
+            result: result.clone(),
+        },
+    };
+    let shared = match serde_json::to_vec(&stored) {
+        Ok(record) => backend.put(id, &record, ASYNC_RESULT_TTL).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = shared {
+        eprintln!("Failed to share the result of inference {}: {}", id, e);
+    }
+}
+
+/// Completes inference `id` with `result`: in memory, in the shared store and at the
+/// callback URL of the request.
+pub async fn complete(state: &AppState, id: &str, result: AsyncResult, callback: Option<Url>) {
+    state.async_results.complete(id, result.clone());
+    share(state, id, &ResultState::Completed(result.clone())).await;
+    if let Some(url) = callback {
+        deliver(url, id, &result).await;
+    }
+}
+
+async fn deliver(url: Url, id: &str, result: &AsyncResult) {
+    let body = match result {
+        Ok(response) => json!({ "id": id, "status": "completed", "response": response }),
+        Err(error) => json!({ "id": id, "status": "failed", "error": error }),
+    };
+    let client = reqwest::Client::new();
+    for attempt in 0..CALLBACK_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(CALLBACK_BACKOFF * 2u32.pow(attempt - 1)).await;
+        }
+        let failure = match client
+            .post(url.clone())
+            .timeout(CALLBACK_TIMEOUT)
+            .json(&body)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => format!("answered {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        eprintln!(
+            "Callback of inference {} to {} failed (attempt {} of {}): {}",
+            id,
+            url,
+            attempt + 1,
+            CALLBACK_ATTEMPTS,
+            failure
+        );
+    }
+}
+
+/// The state of inference `id` in the shared store.
+async fn shared_result(
+    backend: &Arc<dyn ResultBackend>,
+    id: &str,
+) -> Option<ResultState<AsyncResult>> {
+    let record = match backend.get(id).await {
+        Ok(record) => record?,
+        Err(e) => {
+            eprintln!("Failed to read the result of inference {}: {}", id, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&record) {
+        Ok(StoredResult::Pending) => Some(ResultState::Pending),
+        Ok(StoredResult::Completed { result }) => Some(ResultState::Completed(result)),
+        Err(e) => {
+            eprintln!("Invalid shared result of inference {}: {}", id, e);
+            None
+        }
+    }
+}
+
+/// The state of inference `id`, waiting up to `wait` for it to complete. Inferences this
+/// server does not know of are looked up in the shared store.
+async fn lookup(
+    state: &AppState,
+    id: &str,
+    wait: Option<Duration>,
+) -> Option<ResultState<AsyncResult>> {
+    let local = match wait {
+        Some(timeout) => state.async_results.wait(id, timeout).await,
+        None => state.async_results.get(id),
+    };
+    let Some(backend) = state.result_backend.as_ref().filter(|_| local.is_none()) else {
+        return local;
+    };
+    let deadline = Instant::now() + wait.unwrap_or_default();
+    loop {
+        let shared = shared_result(backend, id).await;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match shared {
+            Some(ResultState::Pending) if !remaining.is_zero() => {
+                tokio::time::sleep(SHARED_POLL_INTERVAL.min(remaining)).await
+            }
+            shared => return shared,
+        }
+    }
+}
+
+async fn inference_result_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<WaitQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let id = params.get("id").cloned().unwrap_or_default();
+    let wait = query
+        .wait
+        .filter(|wait| *wait > 0)
+        .map(|wait| Duration::from_secs(wait.min(MAX_WAIT_SECS)));
+
+    match lookup(&state, &id, wait).await {
+        Some(ResultState::Completed(Ok(response))) => match TabularFormat::from_accept(&headers) {
+            Some(format) => tabular_response(format, response).into_response(),
+            None => (StatusCode::OK, Json(response)).into_response(),
+        },
+        Some(ResultState::Completed(Err(error))) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response()
+        }
+        Some(ResultState::Pending) => (
+            StatusCode::ACCEPTED,
+            Json(AsyncInferenceStatus {
+                id,
+                status: "pending".to_string(),
+            }),
+        )
+            .into_response(),
+        None => status_error(
+            StatusCode::NOT_FOUND,
+            format!("Inference '{}' not found", id),
+        )
+        .into_response(),
+    }
+}
+
+pub fn new_inference_router(state: AppState) -> Router {
+    Router::new()
+        .route("/{id}", get(inference_result_handler))
+        .with_state(state)
+}
+
+pub fn new_operations_router(state: AppState) -> Router {
+    Router::new()
+        .route("/v1/operations/{id}", get(inference_result_handler))
+        .with_state(state)
+}
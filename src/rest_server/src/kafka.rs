@@ -0,0 +1,247 @@
+/* Kafka consumer mode.
+
+With `--kafka-proxy`, the REST server also consumes inference requests from a
+Kafka topic, through a Kafka REST proxy (see `foundation::kafka`), and
+produces their results to an output topic, keyed with the key of their
+request so pipelines can match them. Messages are in the format of
+`--kafka-format`:
+
+- `v2`: KServe V2 inference requests in JSON, as the REST API takes them, with
+  the `model_name` they are for and optionally a `model_version`. Results are
+  V2 inference responses, or the error of the request when it failed.
+- `json`, `postcard`: domain requests and responses encoded with that codec,
+  see `foundation::api::codec`. Failures are error responses.
+
+Requests run through the scheduler of their model as REST requests do, and
+are counted under the `kafka` route of the statistics. The messages of a poll
+run concurrently, so the model can batch them. They come from the operator's
+own topics and are not authenticated, rate limited or audited.
+
+Offsets are committed once the results of a poll are produced. When the proxy
+fails, the consumer subscribes again after a delay, and the group reads the
+messages without a result again. On shutdown, the messages being handled are
+answered before the consumer leaves the group.
+*/
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use foundation::api::inference::{InferenceError, InferenceResponse as DomainResponse};
+use foundation::{
+    Codec, InferenceRequest as DomainRequest, KafkaConfig, KafkaConsumer, KafkaProducer,
+    MessageFormat, ModelId, SamplingOptions, ShutdownSignal, log_info,
+};
+use serde::Deserialize;
+
+use crate::data_model::InferenceRequest;
+use crate::model::{checked_casts, infer, resolve_model};
+use crate::state::AppState;
+use crate::translator::RequestContext;
+
+/// Statistics route of the requests consumed from Kafka.
+const ROUTE: &str = "kafka";
+/// Delay before subscribing again after the proxy failed.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A V2 inference request, with the model it is for.
+#[derive(Deserialize)]
+struct AddressedRequest {
+    model_name: String,
+    #[serde(default)]
+    model_version: Option<String>,
+    #[serde(flatten)]
+    payload: InferenceRequest,
+}
+
+/// Consumes the requests of `kafka`, if set, until `shutdown` fires.
+pub async fn serve(
+    kafka: Option<(KafkaConfig, AppState)>,
+    shutdown: ShutdownSignal,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((config, state)) = kafka else {
+        return Ok(());
+    };
+    log_info!(
+        "Consuming {} inference requests from Kafka topic {}, producing results to {}",
+        config.format,
+        config.input_topic,
+        config.output_topic
+    );
+    let producer = KafkaProducer::new(&config.proxy_url, &config.output_topic);
+    while shutdown.deadline().is_none() {
+        let subscribed = tokio::select! {
+            _ = shutdown.clone().triggered() => break,
+            subscribed = KafkaConsumer::subscribe(&config.proxy_url, &config.group, &config.input_topic) => subscribed,
+        };
+        let failure = match subscribed {
+            Ok(consumer) => {
+                let consumed =
+                    consume(&state, &config.format, &consumer, &producer, &shutdown).await;
+                if let Err(e) = consumer.close().await {
+                    eprintln!(
+                        "Failed to leave Kafka consumer group {}: {}",
+                        config.group, e
+                    );
+                }
+                match consumed {
+                    Ok(()) => break,
+                    Err(e) => e,
+                }
+            }
+            Err(e) => e,
+        };
+        eprintln!(
+            "Kafka consumer of {} failed, subscribing again in {}s: {}",
+            config.input_topic,
+            RETRY_DELAY.as_secs(),
+            failure
+        );
+        tokio::select! {
+            _ = shutdown.clone().triggered() => break,
+            _ = tokio::time::sleep(RETRY_DELAY) => {}
+        }
+    }
+    log_info!("Kafka consumer of {} stopped", config.input_topic);
+    Ok(())
+}
+
+/// Answers the messages of `consumer` until `shutdown` fires or the proxy fails.
+async fn consume(
+    state: &AppState,
+    format: &MessageFormat,
+    consumer: &KafkaConsumer,
+    producer: &KafkaProducer,
+    shutdown: &ShutdownSignal,
+) -> anyhow::Result<()> {
+    loop {
+        let messages = tokio::select! {
+            _ = shutdown.clone().triggered() => return Ok(()),
+            polled = consumer.poll() => polled?,
+        };
+        if messages.is_empty() {
+            continue;
+        }
+        let answers: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                let (state, format, value) = (state.clone(), format.clone(), message.value.clone());
+                tokio::spawn(async move { answer(&state, &format, &value).await })
+            })
+            .collect();
+        let mut records = Vec::with_capacity(messages.len());
+        for (message, answer) in messages.iter().zip(answers) {
+            records.push((message.key.clone(), answer.await?));
+        }
+        producer.produce(&records).await?;
+        consumer.commit(&messages).await?;
+    }
+}
+
+/// The result of the request encoded in `value`, in `format`.
+async fn answer(state: &AppState, format: &MessageFormat, value: &[u8]) -> Vec<u8> {
+    match format {
+        MessageFormat::V2 => answer_v2(state, value).await,
+        MessageFormat::Codec(codec) => answer_encoded(state, codec.as_ref(), value).await,
+    }
+}
+
+async fn answer_v2(state: &AppState, value: &[u8]) -> Vec<u8> {
+    let result = async {
+        let AddressedRequest {
+            model_name,
+            model_version,
+            mut payload,
+        } = serde_json::from_slice(value).map_err(|e| {
+            crate::error::status_error(
+                axum::http::StatusCode::BAD_REQUEST,
+                format!("Invalid V2 inference request: {}", e),
+            )
+        })?;
+        payload.id.get_or_insert_with(|| state.ids.next_id());
+        let params = HashMap::from([("model_name".to_string(), model_name)])
+            .into_iter()
+            .chain(model_version.map(|version| ("model_version".to_string(), version)))
+            .collect();
+        let (model_name, model_version) = resolve_model(&state.model_manager, &params)?;
+        let id = payload.id.clone();
+        let context = RequestContext {
+            casts: checked_casts(&payload)?,
+            shared_memory: state.shared_memory.clone(),
+            ..Default::default()
+        };
+        infer(
+            &state.model_manager,
+            ROUTE,
+            model_name,
+            model_version,
+            payload,
+            context,
+            None,
+        )
+        .await
+        .map_err(|(status, Json(mut error))| {
+            error.request_id = error.request_id.or(id);
+            (status, Json(error))
+        })
+    }
+    .await;
+    let encoded = match result {
+        Ok(response) => serde_json::to_vec(&response),
+        Err((_, Json(error))) => serde_json::to_vec(&error),
+    };
+    encoded.unwrap_or_default()
+}
+
+async fn answer_encoded(state: &AppState, codec: &dyn Codec, value: &[u8]) -> Vec<u8> {
+    let response = match codec.decode_request(value) {
+        Ok(request) => run_domain(state, request).await,
+        Err(e) => DomainResponse::Error(InferenceError {
+            error: format!("Invalid {} inference request: {}", codec.name(), e),
+        }),
+    };
+    codec.encode_response(&response).unwrap_or_default()
+}
+
+/// Runs a decoded request on the version it names, or else on the one serving its model.
+async fn run_domain(state: &AppState, mut request: DomainRequest) -> DomainResponse {
+    let failed = |error: String| DomainResponse::Error(InferenceError { error });
+    let model_manager = &state.model_manager;
+    let model_name = request.model_name.clone();
+    let model_id = ModelId(model_name.clone());
+    let version = match model_manager.resolve_version(&model_id, request.model_version.as_deref()) {
+        Ok(Some(version_id)) => version_id.version,
+        Ok(None) => {
+            return failed(format!("Model '{}' has no loaded versions", model_name));
+        }
+        Err(e) => return failed(e.to_string()),
+    };
+    request.model_version = Some(version.clone());
+    if request.id.is_empty() {
+        request.id = state.ids.next_id();
+    }
+    if let Err(refusal) = model_manager.check_context(&request) {
+        return failed(refusal.to_string());
+    }
+    request.sampling = match SamplingOptions::from_parameters(request.parameters.as_ref()) {
+        Ok(sampling) => sampling,
+        Err(refusal) => return failed(refusal.to_string()),
+    };
+
+    let started = Instant::now();
+    let response = match model_manager.add_request(model_id, request).await {
+        Ok(receiver) => receiver.await.unwrap_or_else(|_| {
+            failed("Request was dropped from the full request buffer of the model".to_string())
+        }),
+        Err(e) => failed(e.to_string()),
+    };
+    model_manager.stats().record(
+        &model_name,
+        &version,
+        ROUTE,
+        started.elapsed(),
+        matches!(response, DomainResponse::Ok(_)),
+    );
+    response
+}
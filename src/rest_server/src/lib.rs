@@ -1,25 +1,116 @@
+// The error of the handlers carries the details of `foundation::ApiError` and is large by design.
+#![allow(clippy::result_large_err)]
+
+mod admin;
+mod audit;
+mod auth;
+mod batch;
+mod binary;
+mod body;
+mod concurrency;
+mod correlation;
+mod cors;
 mod data_model;
+mod debug;
+mod error;
 mod healthcheck;
+mod inference;
+mod kafka;
 mod metadata_model;
+mod metrics;
 mod model;
+mod openai;
+mod openapi;
+mod overload;
+mod quota;
+mod rate_limit;
+mod request_routing;
+mod schema;
 mod server;
+mod shared_memory;
+mod state;
+mod stream;
+mod tabular;
+#[cfg(test)]
+mod testing;
+mod traffic;
+mod transcriptions;
+mod translator;
+mod watermark;
+mod websocket;
 
-use crate::healthcheck::new_health_check_router;
+use crate::admin::new_admin_router;
+use crate::batch::new_batch_router;
+use crate::healthcheck::{new_health_check_router, new_probe_router};
+use crate::inference::{new_inference_router, new_operations_router};
 use crate::model::new_model_router;
+use crate::openai::new_openai_router;
+use crate::openapi::new_openapi_router;
+use crate::quota::new_usage_router;
 use crate::server::new_server_router;
+use crate::shared_memory::new_system_shared_memory_router;
+use crate::state::AppState;
+use crate::stream::new_stream_router;
+use crate::transcriptions::new_transcription_router;
+use crate::watermark::new_watermark_router;
+use crate::websocket::new_websocket_router;
 use anyhow::Result;
 use async_trait::async_trait;
-use axum::{Router, serve};
-use foundation::{InferenceServerBuilder, InferenceServerConfig, ModelDiscoveryService};
+use axum::{
+    Router,
+    extract::{ConnectInfo, DefaultBodyLimit},
+    middleware,
+    routing::get,
+};
+use foundation::{
+    Authenticator, BatchJobs, ConnectionLimits, CorsConfig, IdleTimeout, InferenceServerBuilder,
+    InferenceServerConfig, KafkaConfig, Listener, ModelDiscoveryService, Protocol, ReloadableTls,
+    SharedPort, ShutdownSignal, TlsConfig, log_info,
+};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
 use std::error::Error;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+use tower::util::option_layer;
 use tower_http::trace::TraceLayer;
 
 pub struct RestServerBuilder {
     addr: SocketAddr,
     app: Router,
+    limits: ConnectionLimits,
+    tls: Option<TlsConfig>,
+    shared_port: Option<Arc<SharedPort>>,
+    cors: Option<CorsConfig>,
+    grpc: Option<Router>,
+    /// The admin API, when it is served on its own listener rather than with the rest.
+    admin: Option<(SocketAddr, Router)>,
+    /// The Kafka consumer mode, with the state its requests run with.
+    kafka: Option<(KafkaConfig, AppState)>,
+    /// Batch jobs, resumed from their checkpoints when the server starts.
+    batch_jobs: Option<Arc<BatchJobs>>,
+}
+
+impl RestServerBuilder {
+    /// Lets browsers from the origins of `cors` call the API.
+    pub fn with_cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    /// Serves the gRPC services of `grpc` too, on a shared port routed by request: gRPC and
+    /// gRPC-Web calls go to them.
+    pub fn with_grpc(mut self, grpc: Router) -> Self {
+        self.grpc = Some(grpc);
+        self
+    }
 }
 
 #[async_trait]
@@ -31,26 +122,305 @@ impl InferenceServerBuilder for RestServerBuilder {
         let addr = format!("{}:{}", context.rest_hostname, context.rest_port)
             .parse()
             .expect("Invalid Host/Port");
+        let authenticator = Authenticator::new(context.api_keys, context.jwt)
+            .map(|authenticator| authenticator.with_events(model_manager.events().clone()));
+        let ids = context.ids.clone();
+        let batch_jobs = context.batch_dir.map(|dir| {
+            Arc::new(BatchJobs::new(dir, model_manager.clone()).with_id_provider(ids.clone()))
+        });
+        let state = AppState::new(model_manager, context.overload.clone(), context.ids)
+            .with_concurrency(context.concurrency)
+            .with_traffic(context.traffic.clone())
+            .with_quotas(context.quotas)
+            .with_shared_memory(context.shared_memory)
+            .with_stream_pacing(context.stream_pacing)
+            .with_authenticator(authenticator.clone())
+            .with_config_reload(context.config_reload)
+            .with_batch_jobs(batch_jobs.clone())
+            .with_result_backend(context.result_backend)
+            .with_analytics(context.analytics);
+        let kafka = context.kafka.map(|kafka| (kafka, state.clone()));
+        // The size limit replaces the extractors' default one.
+        let body_limit = context
+            .traffic
+            .limits()
+            .max_request_bytes
+            .map_or(body::DEFAULT_MAX_BODY_BYTES, |limit| {
+                usize::try_from(limit).unwrap_or(usize::MAX)
+            });
+        let admin = Router::new().nest("/{version}/admin", new_admin_router(state.clone()));
+        let (public, admin) = match context.admin_port {
+            Some(port) => {
+                let addr = format!("{}:{}", context.admin_hostname, port)
+                    .parse()
+                    .expect("Invalid admin Host/Port");
+                let admin = admin
+                    .layer(DefaultBodyLimit::max(body_limit))
+                    .layer(option_layer(authenticator.clone().map(|authenticator| {
+                        middleware::from_fn_with_state(authenticator, auth::authenticate)
+                    })))
+                    .layer(middleware::from_fn_with_state(
+                        body_limit,
+                        body::describe_rejections,
+                    ))
+                    .layer(middleware::from_fn_with_state(
+                        ids.clone(),
+                        correlation::identify_errors,
+                    ))
+                    .layer(body::compression_layer())
+                    .layer(TraceLayer::new_for_http());
+                (Router::new(), Some((addr, admin)))
+            }
+            None => (admin, None),
+        };
         let app = Router::new()
+            .route(
+                "/metrics",
+                get(metrics::metrics_handler).with_state(state.clone()),
+            )
+            .merge(new_probe_router(state.clone()))
+            .merge(new_openapi_router())
+            .merge(new_openai_router(state.clone()))
+            .merge(new_batch_router(state.clone()))
+            .merge(new_operations_router(state.clone()))
+            .merge(new_transcription_router(state.clone()))
+            .merge(new_websocket_router(state.clone()))
             .nest("/{version}", new_server_router())
-            .nest("/{version}/health", new_health_check_router())
-            .nest("/{version}/models", new_model_router(model_manager.clone()))
+            .nest("/{version}/health", new_health_check_router(state.clone()))
+            .nest("/{version}/models", new_model_router(state.clone()))
+            .nest("/{version}/inference", new_inference_router(state.clone()))
+            .nest("/{version}/streams", new_stream_router(state.clone()))
+            .nest("/{version}/usage", new_usage_router(state.clone()))
+            .nest(
+                "/{version}/systemsharedmemory",
+                new_system_shared_memory_router(state.clone()),
+            )
+            .nest("/{version}/watermark", new_watermark_router(state.clone()))
+            .merge(public)
+            .layer(DefaultBodyLimit::max(body_limit))
+            .layer(middleware::from_fn_with_state(
+                context.traffic,
+                traffic::account_traffic,
+            ))
+            // Outside the accounting, so limits and counts apply to decompressed bodies.
+            .layer(body::decompression_layer())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                concurrency::shed_excess,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state,
+                metrics::record_inference,
+            ))
+            .layer(middleware::from_fn_with_state(
+                context.rate_limiter,
+                rate_limit::limit_rate,
+            ))
+            .layer(middleware::from_fn_with_state(
+                context.overload,
+                overload::track_load,
+            ))
+            .layer(middleware::from_fn_with_state(
+                context.trust_tenant_header,
+                auth::identify_tenant,
+            ))
+            .layer(option_layer(authenticator.map(|authenticator| {
+                middleware::from_fn_with_state(authenticator, auth::authenticate)
+            })))
+            .layer(option_layer(context.audit.map(|audit| {
+                middleware::from_fn_with_state(audit, audit::audit_inference)
+            })))
+            .layer(middleware::from_fn_with_state(
+                body_limit,
+                body::describe_rejections,
+            ))
+            .layer(middleware::from_fn_with_state(
+                ids,
+                correlation::identify_errors,
+            ))
+            .layer(middleware::from_fn(error::advertise_retry_delay))
+            .layer(body::compression_layer())
             .layer(TraceLayer::new_for_http());
 
-        Self { addr, app }
+        Self {
+            addr,
+            app,
+            limits: context.limits,
+            tls: context.tls,
+            shared_port: context.shared_port,
+            cors: context.rest_cors,
+            grpc: None,
+            admin,
+            kafka,
+            batch_jobs,
+        }
     }
 
-    async fn start(self) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let listener = TcpListener::bind(self.addr)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?;
+    async fn start(self, shutdown: ShutdownSignal) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Outermost, so preflight requests are answered before authentication.
+        let app = match &self.cors {
+            Some(cors) => self.app.layer(cors::cors_layer(cors)?),
+            None => self.app,
+        };
+        // Outside the REST middleware, which does not apply to gRPC calls.
+        let app = match &self.grpc {
+            Some(grpc) => app.layer(middleware::from_fn_with_state(
+                grpc.clone(),
+                request_routing::route_grpc,
+            )),
+            None => app,
+        };
+        let listener = match &self.shared_port {
+            Some(shared) => shared.listener(Protocol::Rest).await?,
+            None => Listener::Tcp(TcpListener::bind(self.addr).await?),
+        };
 
-        let local_addr = listener.local_addr()?;
-        println!("Rest Server listening on {}", local_addr);
-        serve(listener, self.app)
-            .await
-            .map_err(|e| Box::<dyn Error + Send + Sync>::from(e.to_string()))?;
+        let tls = match self.tls {
+            Some(config) => {
+                let tls = Arc::new(ReloadableTls::load(config)?);
+                tls.reload_on_sighup("REST")?;
+                Some(tls)
+            }
+            None => None,
+        };
 
+        let local_addr = listener.local_addr()?;
+        log_info!(
+            "Rest Server listening on {}{}{}",
+            local_addr,
+            if tls.is_some() { " (TLS)" } else { "" },
+            if self.shared_port.is_some() {
+                " (shared with gRPC)"
+            } else {
+                ""
+            }
+        );
+        let rest = serve(
+            "REST",
+            listener,
+            app,
+            tls.clone(),
+            self.limits.clone(),
+            shutdown.clone(),
+        );
+        if let Some(batch_jobs) = &self.batch_jobs {
+            batch_jobs.resume()?;
+        }
+        let kafka = kafka::serve(self.kafka, shutdown.clone());
+        let Some((addr, admin)) = self.admin else {
+            tokio::try_join!(rest, kafka)?;
+            return Ok(());
+        };
+        let admin_listener = Listener::Tcp(TcpListener::bind(addr).await?);
+        log_info!(
+            "Admin API listening on {}{}",
+            admin_listener.local_addr()?,
+            if tls.is_some() { " (TLS)" } else { "" }
+        );
+        let admin = serve("admin", admin_listener, admin, tls, self.limits, shutdown);
+        tokio::try_join!(rest, admin, kafka)?;
         Ok(())
     }
 }
+
+/// Serves `app` on `listener` until `shutdown` fires, then drains the open connections until
+/// its deadline. `name` tells the listener apart in messages.
+async fn serve(
+    name: &'static str,
+    mut listener: Listener,
+    app: Router,
+    tls: Option<Arc<ReloadableTls>>,
+    limits: ConnectionLimits,
+    shutdown: ShutdownSignal,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut http = auto::Builder::new(TokioExecutor::new());
+    http.http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(limits.header_read_timeout);
+    http.http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(limits.max_concurrent_streams);
+
+    let mut connections = JoinSet::new();
+    let draining = shutdown.clone().triggered();
+    tokio::pin!(draining);
+    let deadline = loop {
+        let accepted = tokio::select! {
+            deadline = &mut draining => break deadline,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            accepted = listener.accept() => accepted,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Typically file descriptor exhaustion: back off instead of spinning.
+                eprintln!("Failed to accept {} connection: {}", name, e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let stream = IdleTimeout::new(stream, &limits);
+        // The peer address identifies clients without an API key to the rate limiter.
+        let service = TowerToHyperService::new(app.clone().map_request(
+            move |mut request: axum::http::Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                request
+            },
+        ));
+        let http = http.clone();
+        let acceptor = tls.as_ref().map(|tls| tls.acceptor());
+        let handshake_timeout = limits.header_read_timeout;
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let stream: Box<dyn Io> = match acceptor {
+                Some(acceptor) => {
+                    let Ok(Ok(stream)) =
+                        tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await
+                    else {
+                        return;
+                    };
+                    Box::new(stream)
+                }
+                None => Box::new(stream),
+            };
+            // Errors here (including reaped idle connections and failed handshakes) only
+            // concern this client.
+            let connection = http.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            tokio::select! {
+                _ = connection.as_mut() => {}
+                _ = shutdown.triggered() => {
+                    // Answers the requests in flight, then closes.
+                    connection.as_mut().graceful_shutdown();
+                    let _ = connection.await;
+                }
+            }
+        });
+    };
+
+    drop(listener);
+    log_info!(
+        "{} server stopped accepting connections, draining {} open connections",
+        name,
+        connections.len()
+    );
+    let drained = tokio::time::timeout_at(deadline.into(), async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await
+    .is_ok();
+    if !drained {
+        eprintln!(
+            "Closing {} {} connections still open at the drain deadline",
+            connections.len(),
+            name
+        );
+    }
+    Ok(())
+}
+
+/// A client connection, plain or TLS.
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
@@ -0,0 +1,55 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use foundation::ModelId;
+
+use crate::model::inference_model;
+use crate::state::AppState;
+
+/// Records every inference request (POSTs to a model) in the request histograms, with the
+/// status it was answered with.
+pub async fn record_inference(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(model) = inference_model(request.uri().path()).map(str::to_string) else {
+        return next.run(request).await;
+    };
+    let started = Instant::now();
+    let response = next.run(request).await;
+    // Unknown models share one series, so made-up names are not tracked one by one.
+    let model = state
+        .model_manager
+        .has_model(&ModelId(model.clone()))
+        .then_some(model.as_str());
+    state.model_manager.metrics().record_request(
+        "rest",
+        model,
+        response.status().as_str(),
+        started.elapsed(),
+    );
+    response
+}
+
+/// Request histograms, buffer gauges, route statistics, in-flight counts and shed
+/// requests in the Prometheus text format.
+pub async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let mut metrics = state.concurrency.render_metrics();
+    metrics.push_str(&state.model_manager.render_metrics());
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        metrics,
+    )
+}
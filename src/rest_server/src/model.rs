@@ -1,79 +1,1016 @@
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinSet;
 
 use axum::{
     Router,
-    extract::{Json, Path},
-    response::IntoResponse,
+    body::Bytes,
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::Response,
     routing::{get, post},
 };
-use foundation::ModelDiscoveryService;
+use foundation::{
+    ApiError, BufferRefusal, CircuitOpen, ErrorCode, HINTS_IGNORED_PARAMETER, IgnoredHint,
+    InferenceResponse as DomainResponse, LabelSelector, MODEL_SELECTOR_HEADER,
+    ModelDiscoveryService, ModelId, ModelPaused, OutputDatatype, PRIORITY_HEADER, Priority,
+    QuotaUsage, REQUEST_TIMEOUT_HEADER, Refusal, ResultState, Role, SamplingOptions, SchemaPlan,
+    TENANT_HEADER, Target, TensorMetadata, Timeline, parse_timeout_ms,
+};
+use serde::Deserialize;
+use serde_json::Value;
 
 //  TODO: later change this to galemind::api
+use crate::auth::{authorization, refused};
+use crate::binary::{BinaryOutputs, binary_response, request_body};
+use crate::correlation::{assign_request_id, with_correlation_id};
 use crate::data_model::{
-    ErrorMetadataModelResponse, InferenceRequest, InferenceResponse, MetadataModelResponse,
-    MetadataTensor,
+    AsyncInferenceStatus, ErrorInferenceResponse, InferenceRequest, InferenceResponse,
+    MetadataModelResponse, MetadataTensor, ModelListEntry, Parameters, StreamToken,
+};
+use crate::debug::{debug_section, json_with_debug, request_timeline};
+use crate::error::{api_error, identified, status_error};
+use crate::inference::{callback_url, complete, share};
+use crate::overload::degrade_parameters;
+use crate::quota::{self, with_quota};
+use crate::schema::{downgrade_response, negotiate_request, with_schema_version};
+use crate::shared_memory::{output_locations, place_output};
+use crate::state::AppState;
+use crate::stream::follow;
+use crate::tabular::{TabularFormat, tabular_response};
+use crate::translator::{
+    RequestContext, check_tensor_data, domain_parameter, domain_request, output_casts,
+    output_tensor,
 };
 
-async fn model_ready_handler(Path(model_name): Path<String>) -> impl IntoResponse {
-    format!("Model: {}, Ready!", model_name)
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+/// Most requests a single inference stream may carry.
+const MAX_STREAM_REQUESTS: usize = 1024;
+
+/// Ready while the model serves a version and its circuit is closed, see
+/// `foundation::CircuitPolicy`; 503 otherwise.
+async fn model_ready_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<String, InferenceError> {
+    let model_name = params.get("model_name").cloned().unwrap_or_default();
+    let model_version = params.get("model_version");
+    if !state.model_manager.is_model_ready(
+        &ModelId(model_name.clone()),
+        model_version.map(String::as_str),
+    ) {
+        let model = match model_version {
+            Some(version) => format!("Version {} of model '{}'", version, model_name),
+            None => format!("Model '{}'", model_name),
+        };
+        return Err(status_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{} is not ready", model),
+        ));
+    }
+    Ok(match model_version {
+        Some(version) => format!("Model: {}, Version: {}, Ready!", model_name, version),
+        None => format!("Model: {}, Ready!", model_name),
+    })
+}
+
+/// Model addressed by an inference path, `/{version}/models/{model_name}/...`.
+pub fn inference_model(path: &str) -> Option<&str> {
+    let mut segments = path.trim_start_matches('/').split('/').skip(1);
+    if segments.next()? != "models" {
+        return None;
+    }
+    let model = segments.next()?;
+    segments.next().map(|_| model)
+}
+
+/// The error of a request its model turned away: paused, with an open circuit, unknown,
+/// unloaded or with a full buffer.
+pub fn refused_request(error: anyhow::Error) -> InferenceError {
+    let error = match error.downcast::<ModelPaused>() {
+        Ok(paused) => return api_error(paused.into()),
+        Err(error) => error,
+    };
+    let error = match error.downcast::<CircuitOpen>() {
+        Ok(open) => return api_error(open.into()),
+        Err(error) => error,
+    };
+    match error.downcast::<BufferRefusal>() {
+        Ok(refusal) => api_error(refusal.into()),
+        Err(error) => status_error(StatusCode::INTERNAL_SERVER_ERROR, error),
+    }
+}
+
+/// Resolves the model name and served version addressed by the request path.
+pub fn resolve_model(
+    model_manager: &ModelDiscoveryService,
+    params: &HashMap<String, String>,
+) -> Result<(String, Option<String>), InferenceError> {
+    let model_name = params.get("model_name").cloned().unwrap_or_default();
+    let model_version = model_manager
+        .resolve_version(
+            &ModelId(model_name.clone()),
+            params.get("model_version").map(String::as_str),
+        )
+        .map_err(|e| status_error(StatusCode::NOT_FOUND, e))?
+        .map(|version_id| version_id.version);
+
+    Ok((model_name, model_version))
+}
+
+/// `value` as the name of a model or a version: trimmed, and for versions that are numbers,
+/// written as such so that `"01"` and `1` address the same version.
+fn addressed_name(name: &str, value: &str) -> String {
+    let value = value.trim();
+    match value.parse::<u64>() {
+        Ok(number) if name == "model_version" => number.to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Refuses a body naming another model, or version, than the one the request path or the
+/// model selector resolved to. The names are removed from the body, which addresses the model
+/// through its path alone.
+fn check_addressed_model(
+    model_name: &str,
+    model_version: Option<&str>,
+    body: &mut Value,
+) -> Result<(), InferenceError> {
+    let Some(body) = body.as_object_mut() else {
+        return Ok(());
+    };
+    for (name, addressed) in [
+        ("model_name", Some(model_name)),
+        ("model_version", model_version),
+    ] {
+        let Some(value) = body.remove(name) else {
+            continue;
+        };
+        let given = match &value {
+            Value::String(value) => Some(addressed_name(name, value)),
+            Value::Number(value) if name == "model_version" => {
+                Some(addressed_name(name, &value.to_string()))
+            }
+            _ => None,
+        };
+        if given.is_none() || given != addressed.map(|addressed| addressed_name(name, addressed)) {
+            let addressed = match addressed {
+                Some(addressed) => format!("'{}'", addressed),
+                None => "none".to_string(),
+            };
+            return Err(status_error(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "The {} of the body, {}, conflicts with the request path or model \
+                     selector, which names {}",
+                    name, value, addressed
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn parse_selector(selector: &str) -> Result<LabelSelector, InferenceError> {
+    selector
+        .parse()
+        .map_err(|e: anyhow::Error| status_error(StatusCode::BAD_REQUEST, e))
+}
+
+/// The model chosen by the model selector header, if the request has one.
+fn route_by_selector(
+    model_manager: &ModelDiscoveryService,
+    headers: &HeaderMap,
+) -> Result<Option<String>, InferenceError> {
+    let Some(selector) = headers.get(MODEL_SELECTOR_HEADER) else {
+        return Ok(None);
+    };
+    let selector = parse_selector(selector.to_str().unwrap_or_default())?;
+    match model_manager.route(&selector) {
+        Some(model_id) => Ok(Some(model_id.0)),
+        None => Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("No served model matches selector '{}'", selector),
+        )),
+    }
+}
+
+/// Priority class from the priority header, `normal` without one.
+fn request_priority(headers: &HeaderMap) -> Result<Priority, InferenceError> {
+    let Some(priority) = headers.get(PRIORITY_HEADER) else {
+        return Ok(Priority::default());
+    };
+    priority
+        .to_str()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e: anyhow::Error| status_error(StatusCode::BAD_REQUEST, e))
+}
+
+/// Deadline from the request timeout header, counted from now.
+fn request_deadline(headers: &HeaderMap) -> Result<Option<Instant>, InferenceError> {
+    let Some(timeout) = headers.get(REQUEST_TIMEOUT_HEADER) else {
+        return Ok(None);
+    };
+    let timeout = parse_timeout_ms(timeout.to_str().unwrap_or_default())
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+    Ok(Some(Instant::now() + timeout))
+}
+
+/// Tenant of the request, as `auth::identify_tenant` left it in the tenant header, turned
+/// away while its error budget is exhausted.
+fn admit_tenant(
+    model_manager: &ModelDiscoveryService,
+    headers: &HeaderMap,
+) -> Result<Option<String>, InferenceError> {
+    let tenant = headers
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok())
+        .map(str::to_string);
+    model_manager
+        .error_budget()
+        .admit(tenant.as_deref())
+        .map_err(|refusal| {
+            let status = match refusal {
+                Refusal::Throttled(_) => StatusCode::TOO_MANY_REQUESTS,
+                Refusal::Quarantined(_) => StatusCode::FORBIDDEN,
+            };
+            status_error(status, refusal)
+        })?;
+    Ok(tenant)
+}
+
+/// Datatypes the requested outputs are cast to, by output name.
+fn requested_casts(
+    payload: &InferenceRequest,
+) -> Result<HashMap<String, OutputDatatype>, InferenceError> {
+    output_casts(payload).map_err(|e| status_error(StatusCode::BAD_REQUEST, e))
+}
+
+/// Refuses input tensors whose data does not fit their datatype or shape with 422.
+fn valid_tensor_data(payload: &InferenceRequest) -> Result<(), InferenceError> {
+    check_tensor_data(&payload.inputs)
+        .map_err(|e| status_error(StatusCode::UNPROCESSABLE_ENTITY, e))
+}
+
+/// Datatypes the requested outputs are cast to, once the outputs and the input tensors of
+/// `payload` were checked. Requests are run with these casts, see `RequestContext::casts`.
+pub fn checked_casts(
+    payload: &InferenceRequest,
+) -> Result<HashMap<String, OutputDatatype>, InferenceError> {
+    let casts = requested_casts(payload)?;
+    valid_tensor_data(payload)?;
+    Ok(casts)
+}
+
+/// Account a request is charged to, and the tokens it uses.
+struct Charge {
+    account: String,
+    tokens: u64,
+}
+
+/// A request resolved to its model version and upgraded to the model's schema.
+pub struct PreparedRequest {
+    pub model_name: String,
+    pub model_version: Option<String>,
+    pub plan: SchemaPlan,
+    pub payload: InferenceRequest,
+    pub correlation_id: String,
+    pub context: RequestContext,
+    /// Quota of the account the request was charged to.
+    pub quota: Option<QuotaUsage>,
+}
+
+/// Resolves, negotiates, identifies and charges a request, recording these stages on
+/// `timeline`. `streaming` requests rely on the model streaming its answers.
+pub fn prepare_request(
+    state: &AppState,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+    body: Value,
+    streaming: bool,
+    timeline: Option<&Timeline>,
+) -> Result<PreparedRequest, InferenceError> {
+    let (mut request, charge) = check_request(state, params, headers, body, streaming, timeline)?;
+    if let Some(Charge { account, tokens }) = charge {
+        let usage = state.quotas.admit(&account, tokens).map_err(|refusal| {
+            identified(request.correlation_id.clone())(quota::refused(refusal))
+        })?;
+        request.quota = Some(usage);
+    }
+    Ok(request)
+}
+
+/// A request prepared as `prepare_request` does, with what it is to be charged rather than
+/// charged.
+fn check_request(
+    state: &AppState,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+    mut body: Value,
+    streaming: bool,
+    timeline: Option<&Timeline>,
+) -> Result<(PreparedRequest, Option<Charge>), InferenceError> {
+    let priority = request_priority(headers)?;
+    let deadline = request_deadline(headers)?;
+    let tenant = admit_tenant(&state.model_manager, headers)?;
+    let started = Instant::now();
+    let mut params = params.clone();
+    if let Some(model_name) = route_by_selector(&state.model_manager, headers)? {
+        params.insert("model_name".to_string(), model_name);
+    }
+    let (model_name, model_version) = resolve_model(&state.model_manager, &params)?;
+    check_addressed_model(&model_name, model_version.as_deref(), &mut body)?;
+    // The path model was authorized by the middleware, a selector may have chosen another.
+    let authenticated = match &state.authenticator {
+        Some(authenticator) => Some(
+            authenticator
+                .authorize(
+                    authorization(headers),
+                    Role::Infer,
+                    Target::Model(&model_name),
+                )
+                .map_err(|error| {
+                    let (status, _, body) = refused(error);
+                    (status, body)
+                })?,
+        ),
+        None => None,
+    };
+    if let Some(timeline) = timeline {
+        timeline.span("resolve_version", started, model_version.clone());
+    }
+
+    let started = Instant::now();
+    let (plan, mut payload) = negotiate_request(&state.model_manager, &model_name, headers, body)?;
+    if let Some(timeline) = timeline {
+        timeline.span("parse", started, plan.client_version.clone());
+    }
+    let correlation_id = assign_request_id(state.ids.as_ref(), headers, &mut payload.id);
+    if let Some(parameters) = payload.parameters.as_mut() {
+        degrade_parameters(&state.overload.features(), parameters);
+    }
+    let identified = identified(correlation_id.clone());
+    // Rejected now rather than once the model has run.
+    let casts = checked_casts(&payload).map_err(&identified)?;
+    let parameters = payload.parameters.clone().map(|parameters| {
+        parameters
+            .into_iter()
+            .map(|(name, value)| (name, domain_parameter(value)))
+            .collect()
+    });
+    let mut capabilities = payload.capabilities.take().unwrap_or_default();
+    capabilities.streaming |= streaming;
+    state
+        .model_manager
+        .check_capabilities(&model_name, parameters.as_ref(), capabilities)
+        .map_err(|refusal| identified(api_error(refusal.into())))?;
+    let inputs: Vec<TensorMetadata> = payload
+        .inputs
+        .iter()
+        .map(|input| TensorMetadata {
+            name: input.name.clone(),
+            datatype: input.datatype.clone(),
+            shape: input.shape.clone(),
+        })
+        .collect();
+    state
+        .model_manager
+        .check_inputs(&model_name, model_version.as_deref(), &inputs)
+        .map_err(|refusal| {
+            let mismatches = refusal.mismatches.clone();
+            let (status, Json(mut error)) = api_error(refusal.into());
+            error.mismatches = Some(mismatches);
+            identified((status, Json(error)))
+        })?;
+    let charge = quota::account(authenticated, headers).map(|account| Charge {
+        account,
+        tokens: state
+            .model_manager
+            .request_tokens(&model_name, parameters.as_ref()),
+    });
+    let request = PreparedRequest {
+        model_name,
+        model_version,
+        plan,
+        payload,
+        correlation_id,
+        context: RequestContext {
+            priority,
+            deadline,
+            tenant,
+            inputs: None,
+            binary: Vec::new(),
+            shared_memory: state.shared_memory.clone(),
+            casts,
+        },
+        quota: None,
+    };
+    Ok((request, charge))
+}
+
+/// Enqueues a request in its model's buffer and waits for the outputs of the model,
+/// counting it in the statistics of `route`.
+pub async fn infer(
+    model_manager: &Arc<ModelDiscoveryService>,
+    route: &str,
+    model_name: String,
+    model_version: Option<String>,
+    payload: InferenceRequest,
+    context: RequestContext,
+    timeline: Option<Arc<Timeline>>,
+) -> Result<InferenceResponse, InferenceError> {
+    let Some(version) = model_version.clone() else {
+        return Err(status_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Model '{}' has no loaded versions", model_name),
+        ));
+    };
+    let started = Instant::now();
+    let result = run_inference(
+        model_manager,
+        model_name.clone(),
+        model_version,
+        payload,
+        context,
+        timeline,
+    )
+    .await;
+    model_manager.stats().record(
+        &model_name,
+        &version,
+        route,
+        started.elapsed(),
+        result.is_ok(),
+    );
+    result
 }
 
-async fn model_version_ready_handler(
-    Path((model_name, model_version)): Path<(String, String)>,
-) -> impl IntoResponse {
-    format!("Model: {}, Version: {}, Ready!", model_name, model_version)
+async fn run_inference(
+    model_manager: &Arc<ModelDiscoveryService>,
+    model_name: String,
+    model_version: Option<String>,
+    payload: InferenceRequest,
+    mut context: RequestContext,
+    timeline: Option<Arc<Timeline>>,
+) -> Result<InferenceResponse, InferenceError> {
+    let casts = std::mem::take(&mut context.casts);
+    let locations = output_locations(payload.outputs.as_deref())?;
+    let shared_memory = context.shared_memory.clone();
+    let mut request = domain_request(
+        model_name.clone(),
+        model_version.clone(),
+        payload,
+        context,
+        timeline,
+    )
+    .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+    model_manager
+        .check_context(&request)
+        .map_err(|refusal| api_error(refusal.into()))?;
+    request.sampling = SamplingOptions::from_parameters(request.parameters.as_ref())
+        .map_err(|refusal| api_error(refusal.into()))?;
+    let id = request.id.clone();
+    let ignored_hints = model_manager.ignored_hints(&request);
+    let response = model_manager
+        .add_request(ModelId(model_name.clone()), request)
+        .await
+        .map_err(refused_request)?;
+    let output = match response.await {
+        Ok(DomainResponse::Ok(output)) => output,
+        Ok(DomainResponse::Error(e)) => {
+            return Err(status_error(StatusCode::INTERNAL_SERVER_ERROR, e.error));
+        }
+        Ok(DomainResponse::DeadlineExceeded(e)) => {
+            return Err(status_error(StatusCode::GATEWAY_TIMEOUT, e.error));
+        }
+        Err(_) => {
+            return Err(status_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "Request was dropped from the full request buffer of model '{}'",
+                    model_name
+                ),
+            ));
+        }
+    };
+
+    let datatype = casts.get(&output.name).copied();
+    let mut output = output_tensor(output, datatype);
+    if let Some(location) = locations.get(&output.name) {
+        place_output(&shared_memory, location, &mut output)?;
+    }
+
+    Ok(InferenceResponse {
+        model_name: Some(model_name),
+        model_version,
+        id: Some(id),
+        parameters: (!ignored_hints.is_empty()).then(|| {
+            Parameters::from([(
+                HINTS_IGNORED_PARAMETER.to_string(),
+                IgnoredHint::describe(&ignored_hints).into(),
+            )])
+        }),
+        outputs: Some(vec![output]),
+        debug: None,
+    })
 }
 
 async fn model_infer_handler(
-    Path(_params): Path<HashMap<String, String>>,
-    Json(_payload): Json<InferenceRequest>,
-) -> Json<InferenceResponse> {
-    Json(InferenceResponse {
-        id: None,
-        outputs: Some(vec![MetadataTensor {
-            name: "my_tensor".to_string(),
-            shape: vec![12, 21],
-            datatype: "magic".to_string(),
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, InferenceError> {
+    let timeline = request_timeline(&headers, Instant::now());
+    let (body, binary) = request_body(&headers, body)?;
+    let PreparedRequest {
+        model_name,
+        model_version,
+        plan,
+        payload,
+        correlation_id,
+        mut context,
+        quota,
+    } = prepare_request(&state, &params, &headers, body, false, timeline.as_deref())?;
+    context.binary = binary;
+    let binary = BinaryOutputs::of(&payload);
+
+    let started = Instant::now();
+    let response = infer(
+        &state.model_manager,
+        "rest.infer",
+        model_name,
+        model_version,
+        payload,
+        context,
+        timeline.clone(),
+    )
+    .await
+    .map_err(identified(correlation_id.clone()))?;
+    if let Some(timeline) = &timeline {
+        timeline.span("infer", started, None);
+    }
+    let started = Instant::now();
+    let response =
+        downgrade_response(&plan, response).map_err(identified(correlation_id.clone()))?;
+    if let Some(timeline) = &timeline {
+        timeline.span("schema.downgrade", started, None);
+    }
+    let body = match TabularFormat::from_accept(&headers) {
+        Some(format) => tabular_response(format, response)?,
+        None if !binary.is_empty() => binary_response(timeline.as_deref(), response, &binary)?,
+        None => json_with_debug(timeline.as_deref(), response),
+    };
+    Ok(with_correlation_id(
+        correlation_id,
+        with_quota(quota, with_schema_version(&plan, body)),
+    ))
+}
+
+/// Accepts an inference for background execution and answers immediately with its id.
+async fn model_infer_async_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, InferenceError> {
+    let timeline = request_timeline(&headers, Instant::now());
+    let (body, binary) = request_body(&headers, body)?;
+    let PreparedRequest {
+        model_name,
+        model_version,
+        plan,
+        payload,
+        correlation_id,
+        mut context,
+        quota,
+    } = prepare_request(&state, &params, &headers, body, false, timeline.as_deref())?;
+    context.binary = binary;
+
+    let callback = callback_url(&headers).map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+
+    let id = state.async_results.insert_pending();
+    share(&state, &id, &ResultState::Pending).await;
+    let task_state = state.clone();
+    let result_id = id.clone();
+    // Background inferences count towards saturation until they complete.
+    let load = state.overload.begin();
+    let result_plan = plan.clone();
+    tokio::spawn(async move {
+        let _load = load;
+        let state = task_state;
+        let started = Instant::now();
+        let response = infer(
+            &state.model_manager,
+            "rest.infer_async",
+            model_name,
+            model_version,
+            payload,
+            context,
+            timeline.clone(),
+        )
+        .await;
+        if let Some(timeline) = &timeline {
+            timeline.span("infer", started, None);
+        }
+        let response = match response {
+            Ok(response) => response,
+            Err((_, Json(e))) => {
+                complete(&state, &result_id, Err(e), callback).await;
+                return;
+            }
+        };
+        let started = Instant::now();
+        let mut response = match downgrade_response(&result_plan, response.clone()) {
+            Ok(downgraded) => downgraded,
+            Err((_, Json(e))) => {
+                eprintln!("Failed to downgrade inference {}: {}", result_id, e.error);
+                response
+            }
+        };
+        if let Some(timeline) = &timeline {
+            timeline.span("schema.downgrade", started, None);
+        }
+        // Serialization happens when the result is fetched, after the timeline is taken.
+        response.debug = timeline.as_deref().map(debug_section);
+        complete(&state, &result_id, Ok(response), callback).await;
+    });
+
+    let api_version = params.get("version").cloned().unwrap_or_default();
+    let location = format!("/{}/inference/{}", api_version, id);
+    Ok(with_correlation_id(
+        correlation_id,
+        with_quota(
+            quota,
+            with_schema_version(
+                &plan,
+                (
+                    StatusCode::ACCEPTED,
+                    [(header::LOCATION, location)],
+                    Json(AsyncInferenceStatus {
+                        id,
+                        status: "pending".to_string(),
+                    }),
+                ),
+            ),
+        ),
+    ))
+}
+
+/// Runs a JSON array of inference requests and streams their results as server-sent events,
+/// in the order they complete. The first event carries a token resuming the stream. Requests
+/// start as the client takes their results, see `StreamPacing`.
+async fn model_infer_stream_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    Json(bodies): Json<Vec<Value>>,
+) -> Result<Response, InferenceError> {
+    if bodies.len() > MAX_STREAM_REQUESTS {
+        return Err(status_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("A stream carries at most {} requests", MAX_STREAM_REQUESTS),
+        ));
+    }
+    let (requests, charges): (Vec<_>, Vec<_>) = bodies
+        .into_iter()
+        .map(|body| check_request(&state, &params, &headers, body, true, None))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .unzip();
+    // Charged together once all are valid, so a refused stream costs nothing. The requests
+    // share their headers and model, so the account they are charged to.
+    let charges: Vec<Charge> = charges.into_iter().flatten().collect();
+    let quota = match charges.first() {
+        Some(Charge { account, .. }) => {
+            let tokens: Vec<u64> = charges.iter().map(|charge| charge.tokens).collect();
+            Some(
+                state
+                    .quotas
+                    .admit_batch(account, &tokens)
+                    .map_err(quota::refused)?,
+            )
+        }
+        None => None,
+    };
+
+    let token = state.streams.open();
+    let streams = state.streams.clone();
+    let model_manager = state.model_manager.clone();
+    let stream_token = token.clone();
+    // Streamed inferences count towards saturation until they complete, connected or not.
+    let load = state.overload.begin();
+    tokio::spawn(async move {
+        let _load = load;
+        let mut running = JoinSet::new();
+        for PreparedRequest {
+            model_name,
+            model_version,
+            plan,
+            payload,
+            context,
+            ..
+        } in requests
+        {
+            // A request runs once the client has room for its result.
+            if let Err(stall) = streams.reserve(&stream_token).await {
+                eprintln!("Stopped producing stream {}: {}", stream_token, stall);
+                streams.append(
+                    &stream_token,
+                    Err(ApiError::new(ErrorCode::Aborted, stall).into()),
+                );
+                break;
+            }
+            let model_manager = model_manager.clone();
+            let (streams, stream_token) = (streams.clone(), stream_token.clone());
+            running.spawn(async move {
+                let id = payload.id.clone().unwrap_or_default();
+                let result = infer(
+                    &model_manager,
+                    "rest.infer_stream",
+                    model_name,
+                    model_version,
+                    payload,
+                    context,
+                    None,
+                )
+                .await
+                .map(
+                    |response| match downgrade_response(&plan, response.clone()) {
+                        Ok(downgraded) => downgraded,
+                        Err((_, Json(e))) => {
+                            eprintln!("Failed to downgrade inference {}: {}", id, e.error);
+                            response
+                        }
+                    },
+                )
+                .map_err(|(_, Json(e))| ErrorInferenceResponse {
+                    error: format!("Request '{}': {}", id, e.error),
+                    ..e
+                });
+                streams.append(&stream_token, result);
+            });
+        }
+        while let Some(result) = running.join_next().await {
+            if let Err(e) = result {
+                streams.append(&stream_token, Err(ApiError::internal(e).into()));
+            }
+        }
+        streams.finish(&stream_token);
+    });
+
+    let api_version = params.get("version").cloned().unwrap_or_default();
+    let announce = StreamToken {
+        resume: format!("/{}/streams/{}", api_version, token),
+        token: token.clone(),
+    };
+    Ok(with_quota(
+        quota,
+        follow(
+            state.streams.clone(),
+            state.analytics.clone(),
+            token,
+            None,
+            Some(announce),
+        ),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    /// Label selector the listed models must match.
+    selector: Option<String>,
+}
+
+/// Models of the catalog with their served versions, backend, readiness and labels, sorted
+/// by name.
+async fn list_models_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<ModelListEntry>>, InferenceError> {
+    let selector = match &query.selector {
+        Some(selector) => parse_selector(selector)?,
+        None => LabelSelector::default(),
+    };
+    let models = model_manager
+        .select_models(&selector)
+        .iter()
+        .filter_map(|model_id| model_manager.model_summary(model_id))
+        .map(|summary| ModelListEntry {
+            name: summary.name,
+            versions: summary.versions,
+            backend: summary.backend,
+            ready: summary.ready,
+            labels: summary.labels,
+        })
+        .collect();
+    Ok(Json(models))
+}
+
+fn metadata_tensors(tensors: Vec<foundation::TensorMetadata>) -> Vec<MetadataTensor> {
+    tensors
+        .into_iter()
+        .map(|tensor| MetadataTensor {
+            name: tensor.name,
+            shape: tensor.shape,
+            datatype: tensor.datatype,
             parameters: None,
             data: None,
-        }]),
-    })
+        })
+        .collect()
 }
 
-async fn model_version_handler(
-    Path(_): Path<HashMap<String, String>>,
-) -> Result<Json<MetadataModelResponse>, Json<ErrorMetadataModelResponse>> {
-    let tensor = MetadataTensor {
-        name: "my_tensor".to_string(),
-        shape: vec![12, 21],
-        datatype: "magic".to_string(),
-        parameters: None,
-        data: None,
-    };
+/// Model metadata as declared by its configuration or reported by its runtime.
+async fn model_metadata_handler(
+    State(model_manager): State<Arc<ModelDiscoveryService>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<MetadataModelResponse>, InferenceError> {
+    let model_name = params.get("model_name").cloned().unwrap_or_default();
+    let metadata = model_manager
+        .model_metadata(
+            &ModelId(model_name),
+            params.get("model_version").map(String::as_str),
+        )
+        .map_err(|e| status_error(StatusCode::NOT_FOUND, e))?;
+
     Ok(Json(MetadataModelResponse {
-        name: "something".to_string(),
-        versions: None,
-        platform: vec!["some_platform".to_string()],
-        inputs: vec![tensor.clone()],
-        outputs: vec![tensor.clone()],
+        name: metadata.name,
+        versions: Some(metadata.versions),
+        platform: metadata.platform,
+        inputs: metadata_tensors(metadata.inputs),
+        outputs: metadata_tensors(metadata.outputs),
+        capabilities: metadata.capabilities,
     }))
 }
 
-pub fn new_model_router(model_manager: Arc<ModelDiscoveryService>) -> Router {
+pub fn new_model_router(state: AppState) -> Router {
     Router::new()
+        .route("/", get(list_models_handler))
         .route("/{model_name}/ready", get(model_ready_handler))
         .route("/{model_name}/infer", post(model_infer_handler))
+        .route("/{model_name}/infer_async", post(model_infer_async_handler))
+        .route(
+            "/{model_name}/infer_stream",
+            post(model_infer_stream_handler),
+        )
+        .route("/{model_name}", get(model_metadata_handler))
         .route(
             "/{model_name}/versions/{model_version}",
-            post(model_version_handler),
+            get(model_metadata_handler).post(model_metadata_handler),
         )
         .route(
             "/{model_name}/versions/{model_version}/ready",
-            get(model_version_ready_handler),
+            get(model_ready_handler),
         )
         .route(
             "/{model_name}/versions/{model_version}/infer",
             post(model_infer_handler),
         )
-        .with_state(model_manager)
+        .route(
+            "/{model_name}/versions/{model_version}/infer_async",
+            post(model_infer_async_handler),
+        )
+        .route(
+            "/{model_name}/versions/{model_version}/infer_stream",
+            post(model_infer_stream_handler),
+        )
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{output, post_json, send_json, state_with};
+    use axum::body::Body;
+    use axum::http::Request;
+    use foundation::api::tensor::Data;
+    use foundation::{Quota, QuotaLimits, QuotaTracker};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_refused_requests_keep_their_status() {
+        let status = |error: anyhow::Error| refused_request(error).0;
+        let model = || "m".to_string();
+        assert_eq!(
+            status(BufferRefusal::NotFound { model: model() }.into()),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(BufferRefusal::Unloaded { model: model() }.into()),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(
+                BufferRefusal::Full {
+                    model: model(),
+                    waited: None
+                }
+                .into()
+            ),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(anyhow::anyhow!("unexpected")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_bodies_may_repeat_the_addressed_model() {
+        let mut body = json!({"model_name": "m", "model_version": 2, "inputs": []});
+        assert!(check_addressed_model("m", Some("2"), &mut body).is_ok());
+        assert_eq!(body, json!({"inputs": []}));
+
+        let mut body = json!({"model_name": " m ", "model_version": "02"});
+        assert!(check_addressed_model("m", Some("2"), &mut body).is_ok());
+    }
+
+    #[test]
+    fn test_bodies_naming_another_model_are_refused() {
+        let refused = |model_version: Option<&str>, mut body: Value| {
+            check_addressed_model("m", model_version, &mut body)
+                .unwrap_err()
+                .0
+        };
+        assert_eq!(
+            refused(None, json!({"model_name": "other"})),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            refused(Some("1"), json!({"model_version": 2})),
+            StatusCode::BAD_REQUEST
+        );
+        // An unversioned model has no version to name.
+        assert_eq!(
+            refused(None, json!({"model_version": "1"})),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            refused(None, json!({"model_name": 1})),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bodies_are_checked_against_the_resolved_version() {
+        let router = || {
+            new_model_router(state_with("m", |_| {
+                output("y", Data::VFLOAT(vec![1.0]), &[])
+            }))
+        };
+        let body = |model_version: &str| {
+            json!({
+                "model_version": model_version,
+                "inputs": [{ "name": "x", "shape": [1], "datatype": "FP64", "data": [1.0] }],
+            })
+        };
+        let (status, answer) = send_json(router(), post_json("/m/infer", &body("1"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(answer["model_version"], "1");
+
+        let (status, _) = send_json(router(), post_json("/m/infer", &body("2"))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_streams_are_charged_whole_once_valid() {
+        let quotas = Arc::new(QuotaTracker::new(QuotaLimits {
+            default: Quota {
+                requests: Some(2),
+                tokens: None,
+            },
+            ..QuotaLimits::default()
+        }));
+        let state = state_with("m", |_| output("y", Data::VFLOAT(vec![1.0]), &[]))
+            .with_quotas(quotas.clone());
+        let input =
+            json!({"inputs": [{ "name": "x", "shape": [1], "datatype": "FP64", "data": [1.0] }]});
+        let stream = |bodies: Vec<&Value>| {
+            let request = Request::post("/m/infer_stream")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(TENANT_HEADER, "ci")
+                .body(Body::from(json!(bodies).to_string()))
+                .unwrap();
+            new_model_router(state.clone()).oneshot(request)
+        };
+        let requests = || quotas.usage("ci").requests;
+
+        let malformed =
+            json!({"inputs": [{ "name": "x", "shape": [2], "datatype": "FP64", "data": [1.0] }]});
+        let response = stream(vec![&input, &malformed]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(requests(), 0);
+
+        let response = stream(vec![&input, &input, &input]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(requests(), 0);
+
+        let response = stream(vec![&input, &input]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests(), 2);
+    }
 }
@@ -0,0 +1,1434 @@
+/* OpenAI compatible API: `POST /v1/completions`, `POST /v1/chat/completions` and the
+model listing, `GET /v1/models[/{model}]`.
+
+SDKs and evaluation harnesses written against OpenAI call the language models
+served here unmodified. A completion request becomes an inference of the model
+named by `model`, with the prompt and the generation settings as parameters:
+`prompt` (or `messages`, the JSON of the conversation, for chats), `max_tokens`
+(16 when absent, as OpenAI does), `n`, `logprobs` and the sampling fields
+(`temperature`, `top_p`, penalties, `stop`, `seed`). It is admitted, checked
+and accounted as any other inference of the model: API keys, quotas,
+capabilities, context window and statistics apply.
+
+The model answers with a BYTES output holding the generated text of each
+choice. Its `finish_reason` parameter tells why generation stopped (`stop` or
+`length`, `stop` when absent). When the request asks for log probabilities,
+the model gets the `logprobs` parameter set to true and `top_logprobs`, the
+number of alternatives wanted at each position. Its `logprobs` output
+parameter then holds, for every choice, the list of its generated tokens:
+`{"token", "logprob", "top_logprobs": [{"token", "logprob"}...]}`. They are
+returned in the layout of each API, the alternatives cut to those asked for.
+
+A list of prompts runs one inference per prompt, and the choices are numbered
+across them. With `echo`, each text starts with its prompt. Usage counts tokens
+with the tokenizer of the model's context window. Streaming is not supported.
+Errors have the OpenAI layout.
+
+Chats may declare `tools` and a `tool_choice`, passed to the model as the JSON
+of these fields. Tool calls are read from the generated text: either
+`<tool_call>{"name": ..., "arguments": ...}</tool_call>` blocks, the text
+around them being the content, or a text that is only such a call or a list
+of them. Calls to undeclared tools are left as text. The results come back in
+`tool` messages, whose `tool_call_id` must name a call of an earlier assistant
+message.
+
+Message content may be a list of `text` and `image_url` parts. Images are
+`data:` URLs of base64 encoded files, checked against the image limits of the
+model (see `model::images`). They are passed to the model as the `images`
+BYTES input, one element per image, and each part is replaced in `messages` by
+`{"type": "image", "index": <position in images>}`. Requests with images rely
+on the `image` modality.
+
+The model listing shows the registered models with their served versions,
+backend and readiness besides the OpenAI fields, `created` being when the
+model was registered. These paths take precedence over the V2 model routes of
+version `v1`; the V2 listing and metadata stay on `/v2/models`.
+*/
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Router,
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::prelude::{BASE64_STANDARD, Engine};
+use foundation::api::inference::InferenceOutput;
+use foundation::api::tensor::{Data, DataType};
+use foundation::{INVALID_IMAGE, LabelSelector, ModelId, ModelSummary, QuotaUsage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::correlation::with_correlation_id;
+use crate::data_model::{ErrorInferenceResponse, Parameters, TensorData};
+use crate::error::{retry_after_header, retry_delay};
+use crate::model::{PreparedRequest, infer, prepare_request};
+use crate::quota::with_quota;
+use crate::state::AppState;
+
+/// Tokens generated when the request does not say, as OpenAI does.
+const DEFAULT_MAX_TOKENS: u64 = 16;
+/// Parameter of the model's output telling why generation stopped.
+const FINISH_REASON_PARAMETER: &str = "finish_reason";
+/// Parameter of the model's output holding the log probabilities of every choice.
+const LOGPROBS_PARAMETER: &str = "logprobs";
+/// Most alternatives a text completion may ask log probabilities for.
+const MAX_COMPLETION_LOGPROBS: u32 = 5;
+/// Most alternatives a chat completion may ask log probabilities for.
+const MAX_CHAT_TOP_LOGPROBS: u32 = 20;
+
+/// One stop sequence or up to four of them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Sampling fields shared by both APIs, passed to the model as parameters of the same name
+/// and checked as its `sampling` options.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Sampling {
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub presence_penalty: Option<f64>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f64>,
+    /// Up to four stop sequences.
+    #[serde(default)]
+    pub stop: Option<Stop>,
+    #[serde(default)]
+    pub seed: Option<i64>,
+}
+
+impl Sampling {
+    fn insert_into(&self, parameters: &mut Value) {
+        let numbers = [
+            ("temperature", self.temperature),
+            ("top_p", self.top_p),
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ];
+        for (name, value) in numbers {
+            if let Some(value) = value {
+                parameters[name] = value.into();
+            }
+        }
+        match &self.stop {
+            Some(Stop::One(stop)) => parameters["stop"] = stop.clone().into(),
+            Some(Stop::Many(stops)) => parameters["stop"] = json!(stops).to_string().into(),
+            None => {}
+        }
+        if let Some(seed) = self.seed {
+            parameters["seed"] = seed.into();
+        }
+    }
+}
+
+/// One prompt or a batch of them.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Prompt {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// An OpenAI text completion request.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CompletionRequest {
+    pub model: String,
+    pub prompt: Prompt,
+    /// 16 when absent.
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// Completions generated per prompt.
+    #[serde(default)]
+    pub n: Option<u32>,
+    /// Most likely tokens returned with their log probability at each position.
+    #[serde(default)]
+    pub logprobs: Option<u32>,
+    /// Whether each text starts with its prompt.
+    #[serde(default)]
+    pub echo: bool,
+    /// Not supported.
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(flatten)]
+    pub sampling: Sampling,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CompletionChoice {
+    pub text: String,
+    pub index: usize,
+    pub logprobs: Option<Value>,
+    pub finish_reason: String,
+}
+
+/// Tokens of the prompts and of the completions.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct CompletionUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+/// An OpenAI text completion.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CompletionResponse {
+    pub id: String,
+    /// `text_completion`.
+    pub object: &'static str,
+    /// Unix time in seconds.
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    pub usage: CompletionUsage,
+}
+
+/// An error in the layout OpenAI clients parse.
+pub struct OpenAiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub code: Option<String>,
+    /// Sent as `Retry-After`, for requests refused until later such as those of paused models.
+    pub retry_after: Option<Duration>,
+}
+
+impl OpenAiError {
+    pub fn invalid_request(message: impl ToString) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+            code: None,
+            retry_after: None,
+        }
+    }
+}
+
+impl From<(StatusCode, Json<ErrorInferenceResponse>)> for OpenAiError {
+    fn from((status, Json(error)): (StatusCode, Json<ErrorInferenceResponse>)) -> Self {
+        Self {
+            status,
+            retry_after: retry_delay(&error),
+            message: error.error,
+            code: error.code,
+        }
+    }
+}
+
+impl IntoResponse for OpenAiError {
+    fn into_response(self) -> Response {
+        let kind = if self.status.is_client_error() {
+            "invalid_request_error"
+        } else {
+            "server_error"
+        };
+        let retry_after = self
+            .retry_after
+            .map(|delay| [(header::RETRY_AFTER, retry_after_header(delay))]);
+        (
+            self.status,
+            retry_after,
+            Json(json!({
+                "error": {
+                    "message": self.message,
+                    "type": kind,
+                    "param": null,
+                    "code": self.code,
+                }
+            })),
+        )
+            .into_response()
+    }
+}
+
+/// An alternative token at a position of a generated text.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+/// A generated token with its log probability and the most likely alternatives.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// Log probabilities of a text completion: tokens, their log probability, the
+/// alternatives by token and the character offset of each token in the text.
+fn completion_logprobs(tokens: &[TokenLogprob], top: usize, offset: usize) -> Value {
+    let mut text_offset = Vec::new();
+    let mut position = offset;
+    for token in tokens {
+        text_offset.push(position);
+        position += token.token.chars().count();
+    }
+    json!({
+        "tokens": tokens.iter().map(|token| &token.token).collect::<Vec<_>>(),
+        "token_logprobs": tokens.iter().map(|token| token.logprob).collect::<Vec<_>>(),
+        "top_logprobs": tokens
+            .iter()
+            .map(|token| {
+                token
+                    .top_logprobs
+                    .iter()
+                    .take(top)
+                    .map(|top| (top.token.clone(), Value::from(top.logprob)))
+                    .collect::<serde_json::Map<_, _>>()
+            })
+            .collect::<Vec<_>>(),
+        "text_offset": text_offset,
+    })
+}
+
+/// Log probabilities of a chat completion: the tokens of the content with their bytes.
+fn chat_logprobs(tokens: &[TokenLogprob], top: usize) -> Value {
+    let content: Vec<Value> = tokens
+        .iter()
+        .map(|token| {
+            json!({
+                "token": token.token,
+                "logprob": token.logprob,
+                "bytes": token.token.as_bytes(),
+                "top_logprobs": token
+                    .top_logprobs
+                    .iter()
+                    .take(top)
+                    .map(|top| json!({
+                        "token": top.token,
+                        "logprob": top.logprob,
+                        "bytes": top.token.as_bytes(),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({ "content": content })
+}
+
+/// Files passed to the model as one BYTES input, one element per file.
+pub struct Media {
+    /// Name of the input.
+    pub input: &'static str,
+    /// Input modalities the request relies on, such as `image`.
+    pub modalities: &'static [&'static str],
+    pub files: Vec<Vec<u8>>,
+}
+
+/// Generated texts of a model's answer, with their finish reason and log probabilities.
+pub struct Generation {
+    pub texts: Vec<String>,
+    pub finish_reason: String,
+    /// Tokens of each choice, when the model reported them.
+    pub logprobs: Vec<Vec<TokenLogprob>>,
+    /// Parameters of the output, for those the API reads besides the above.
+    pub parameters: Parameters,
+    pub correlation_id: String,
+    pub quota: Option<QuotaUsage>,
+}
+
+/// Runs one inference of `model_name` with `parameters`, and `media` when given, as any other
+/// inference of the model.
+pub async fn generate(
+    state: &AppState,
+    headers: &HeaderMap,
+    model_name: &str,
+    route: &'static str,
+    parameters: Value,
+    media: Option<Media>,
+) -> Result<Generation, OpenAiError> {
+    let params = HashMap::from([("model_name".to_string(), model_name.to_string())]);
+    let mut body = json!({"inputs": [], "parameters": parameters});
+    if let Some(media) = &media {
+        body["capabilities"] = json!({ "modalities": media.modalities });
+    }
+    let PreparedRequest {
+        model_name,
+        model_version,
+        payload,
+        correlation_id,
+        mut context,
+        quota,
+        ..
+    } = prepare_request(state, &params, headers, body, false, None)?;
+    if let Some(media) = media {
+        context.inputs = Some(vec![InferenceOutput {
+            name: media.input.to_string(),
+            shape: vec![media.files.len()],
+            datatype: DataType::VBYTES,
+            parameters: None,
+            data: Data::VBYTES(media.files),
+        }]);
+    }
+    let response = infer(
+        &state.model_manager,
+        route,
+        model_name.clone(),
+        model_version,
+        payload,
+        context,
+        None,
+    )
+    .await?;
+
+    let output = response.outputs.into_iter().flatten().next();
+    let Some(output) = output else {
+        return Err(not_text(&model_name));
+    };
+    let Some(TensorData::String(texts)) = output.data else {
+        return Err(not_text(&model_name));
+    };
+    let parameters = output.parameters.unwrap_or_default();
+    let finish_reason = parameters
+        .get(FINISH_REASON_PARAMETER)
+        .and_then(Value::as_str)
+        .unwrap_or("stop")
+        .to_string();
+    let logprobs = match parameters.get(LOGPROBS_PARAMETER) {
+        Some(Value::String(logprobs)) => serde_json::from_str(logprobs).ok(),
+        Some(logprobs) => serde_json::from_value(logprobs.clone()).ok(),
+        None => Some(Vec::new()),
+    };
+    let Some(logprobs) = logprobs else {
+        return Err(OpenAiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!(
+                "Model '{}' returned malformed log probabilities",
+                model_name
+            ),
+            code: None,
+            retry_after: None,
+        });
+    };
+    Ok(Generation {
+        texts,
+        finish_reason,
+        logprobs,
+        parameters,
+        correlation_id,
+        quota,
+    })
+}
+
+fn not_text(model_name: &str) -> OpenAiError {
+    OpenAiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        message: format!("Model '{}' did not answer with generated text", model_name),
+        code: None,
+        retry_after: None,
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+async fn completions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CompletionRequest>,
+) -> Result<Response, OpenAiError> {
+    if request.stream {
+        return Err(OpenAiError::invalid_request(
+            "Streamed completions are not supported",
+        ));
+    }
+    let prompts = match request.prompt {
+        Prompt::One(prompt) => vec![prompt],
+        Prompt::Many(prompts) => prompts,
+    };
+    if prompts.is_empty() {
+        return Err(OpenAiError::invalid_request("prompt must not be empty"));
+    }
+    let n = request.n.unwrap_or(1);
+    if n == 0 {
+        return Err(OpenAiError::invalid_request("n must be at least 1"));
+    }
+    if request
+        .logprobs
+        .is_some_and(|logprobs| logprobs > MAX_COMPLETION_LOGPROBS)
+    {
+        return Err(OpenAiError::invalid_request(format!(
+            "logprobs must be at most {}",
+            MAX_COMPLETION_LOGPROBS
+        )));
+    }
+    let tokenizer = state.model_manager.tokenizer(&request.model);
+
+    let mut id = None;
+    let mut quota = None;
+    let mut choices = Vec::new();
+    let mut usage = CompletionUsage::default();
+    for prompt in prompts {
+        let mut parameters = json!({
+            "prompt": prompt,
+            "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "n": n,
+        });
+        if let Some(top_logprobs) = request.logprobs {
+            parameters["logprobs"] = true.into();
+            parameters["top_logprobs"] = top_logprobs.into();
+        }
+        request.sampling.insert_into(&mut parameters);
+        let generation = generate(
+            &state,
+            &headers,
+            &request.model,
+            "rest.completions",
+            parameters,
+            None,
+        )
+        .await?;
+        id.get_or_insert(generation.correlation_id);
+        quota = generation.quota.or(quota);
+
+        usage.prompt_tokens += tokenizer.count(&prompt);
+        for (index, text) in generation.texts.into_iter().enumerate() {
+            usage.completion_tokens += tokenizer.count(&text);
+            choices.push(CompletionChoice {
+                logprobs: request.logprobs.map(|top| {
+                    let tokens = generation
+                        .logprobs
+                        .get(index)
+                        .map_or(&[][..], Vec::as_slice);
+                    let offset = if request.echo {
+                        prompt.chars().count()
+                    } else {
+                        0
+                    };
+                    completion_logprobs(tokens, top as usize, offset)
+                }),
+                text: if request.echo {
+                    format!("{}{}", prompt, text)
+                } else {
+                    text
+                },
+                index: choices.len(),
+                finish_reason: generation.finish_reason.clone(),
+            });
+        }
+    }
+    usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+
+    let id = id.unwrap_or_default();
+    let response = CompletionResponse {
+        id: format!("cmpl-{}", id),
+        object: "text_completion",
+        created: unix_time(),
+        model: request.model,
+        choices,
+        usage,
+    };
+    Ok(with_correlation_id(id, with_quota(quota, Json(response))))
+}
+
+/// Roles of the messages of a chat.
+const ROLES: &[&str] = &["system", "developer", "user", "assistant", "tool"];
+/// Finish reason of a choice calling tools.
+const TOOL_CALLS_FINISH_REASON: &str = "tool_calls";
+const TOOL_CALL_OPEN: &str = "<tool_call>";
+const TOOL_CALL_CLOSE: &str = "</tool_call>";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON of the arguments.
+    pub arguments: String,
+}
+
+/// A call of a tool.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ToolCall {
+    pub id: String,
+    /// `function`.
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// An image given by URL. Only `data:` URLs of base64 encoded images are supported.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// A part of the content of a message.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    /// An image as the model sees it: its position in the `images` input.
+    Image {
+        index: usize,
+    },
+}
+
+/// Content of a message: text, or parts mixing text and images.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text of the content, its text parts one after the other.
+    fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// A message of a chat.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChatMessage {
+    /// `system`, `developer`, `user`, `assistant` or `tool`.
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Tools an assistant message called.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Call a tool message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// An OpenAI chat completion request.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    /// 16 when absent.
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    #[serde(default)]
+    pub n: Option<u32>,
+    #[serde(default)]
+    pub logprobs: bool,
+    #[serde(default)]
+    pub top_logprobs: Option<u32>,
+    /// Tools the model may call, `{"type": "function", "function": {"name", ...}}`.
+    #[serde(default)]
+    pub tools: Option<Vec<Value>>,
+    /// `none`, `auto`, `required` or the function to call.
+    #[serde(default)]
+    pub tool_choice: Option<Value>,
+    /// Not supported.
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(flatten)]
+    pub sampling: Sampling,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ChatChoice {
+    pub index: usize,
+    pub message: ChatMessage,
+    pub logprobs: Option<Value>,
+    pub finish_reason: String,
+}
+
+/// An OpenAI chat completion.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    /// `chat.completion`.
+    pub object: &'static str,
+    /// Unix time in seconds.
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    pub usage: CompletionUsage,
+}
+
+/// Names of the declared tools, checking their layout and that of the tool choice.
+fn tool_names(tools: &[Value], tool_choice: Option<&Value>) -> Result<Vec<String>, OpenAiError> {
+    let names = tools
+        .iter()
+        .map(|tool| {
+            tool.pointer("/function/name")
+                .and_then(Value::as_str)
+                .filter(|_| tool.get("type").and_then(Value::as_str) == Some("function"))
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    OpenAiError::invalid_request(
+                        "Tools must be {\"type\": \"function\", \"function\": {\"name\": ...}}",
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match tool_choice {
+        None => {}
+        Some(Value::String(choice)) if choice == "required" && names.is_empty() => {
+            return Err(OpenAiError::invalid_request(
+                "tool_choice 'required' needs tools",
+            ));
+        }
+        Some(Value::String(choice)) if ["none", "auto", "required"].contains(&choice.as_str()) => {}
+        Some(choice) => {
+            let Some(name) = choice.pointer("/function/name").and_then(Value::as_str) else {
+                return Err(OpenAiError::invalid_request(
+                    "tool_choice must be 'none', 'auto', 'required' or a function",
+                ));
+            };
+            if !names.iter().any(|declared| declared == name) {
+                return Err(OpenAiError::invalid_request(format!(
+                    "tool_choice names the undeclared tool '{}'",
+                    name
+                )));
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Checks the roles of the messages, and that tool messages answer earlier calls.
+fn check_messages(messages: &[ChatMessage]) -> Result<(), OpenAiError> {
+    if messages.is_empty() {
+        return Err(OpenAiError::invalid_request("messages must not be empty"));
+    }
+    let mut calls = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        if !ROLES.contains(&message.role.as_str()) {
+            return Err(OpenAiError::invalid_request(format!(
+                "messages[{}] has the unknown role '{}'",
+                index, message.role
+            )));
+        }
+        calls.extend(message.tool_calls.iter().flatten().map(|call| &call.id));
+        if message.role != "tool" {
+            continue;
+        }
+        match &message.tool_call_id {
+            Some(id) if calls.contains(&id) => {}
+            Some(id) => {
+                return Err(OpenAiError::invalid_request(format!(
+                    "messages[{}] answers the unknown tool call '{}'",
+                    index, id
+                )));
+            }
+            None => {
+                return Err(OpenAiError::invalid_request(format!(
+                    "messages[{}] is a tool message without tool_call_id",
+                    index
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the input holding the images of a chat.
+const IMAGES_INPUT: &str = "images";
+
+/// Bytes of the image of a `data:<mime type>;base64,<data>` URL, checked against the limits
+/// of `model_name`.
+fn image_bytes(state: &AppState, model_name: &str, url: &str) -> Result<Vec<u8>, OpenAiError> {
+    let invalid = |message: String| OpenAiError {
+        status: StatusCode::BAD_REQUEST,
+        message,
+        code: Some(INVALID_IMAGE.to_string()),
+        retry_after: None,
+    };
+    let Some((header, data)) = url
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(','))
+    else {
+        return Err(invalid(
+            "Images must be given as data: URLs of base64 encoded files".to_string(),
+        ));
+    };
+    let Some(declared) = header.strip_suffix(";base64") else {
+        return Err(invalid(
+            "Image data URLs must be base64 encoded".to_string(),
+        ));
+    };
+    let bytes = BASE64_STANDARD
+        .decode(data.trim())
+        .map_err(|e| invalid(format!("Image data is not valid base64: {}", e)))?;
+    let info = state
+        .model_manager
+        .check_image(model_name, &bytes)
+        .map_err(|refusal| OpenAiError {
+            status: StatusCode::BAD_REQUEST,
+            message: refusal.to_string(),
+            code: Some(refusal.code().to_string()),
+            retry_after: None,
+        })?;
+    if !declared.is_empty() && declared != info.mime_type {
+        return Err(invalid(format!(
+            "The image is declared {} but is {}",
+            declared, info.mime_type
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Moves the images of `messages` to the returned list, leaving in their place their
+/// position in it.
+fn take_images(
+    state: &AppState,
+    model_name: &str,
+    messages: &mut [ChatMessage],
+) -> Result<Vec<Vec<u8>>, OpenAiError> {
+    let mut images = Vec::new();
+    let parts = messages
+        .iter_mut()
+        .flat_map(|message| match &mut message.content {
+            Some(MessageContent::Parts(parts)) => parts.as_mut_slice(),
+            _ => &mut [],
+        });
+    for part in parts {
+        match part {
+            ContentPart::Text { .. } => {}
+            ContentPart::ImageUrl { image_url } => {
+                images.push(image_bytes(state, model_name, &image_url.url)?);
+                *part = ContentPart::Image {
+                    index: images.len() - 1,
+                };
+            }
+            ContentPart::Image { .. } => {
+                return Err(OpenAiError::invalid_request(
+                    "Content parts must be text or image_url",
+                ));
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// A call read from generated text, `{"name": ..., "arguments": ...}`.
+fn function_call(call: &Value) -> Option<FunctionCall> {
+    let name = call.get("name")?.as_str()?.to_string();
+    let arguments = match call.get("arguments").or_else(|| call.get("parameters")) {
+        None => "{}".to_string(),
+        Some(Value::String(arguments)) => arguments.clone(),
+        Some(arguments) => arguments.to_string(),
+    };
+    Some(FunctionCall { name, arguments })
+}
+
+/// The content and tool calls of a generated text, None when it calls no declared tool.
+fn tool_calls(text: &str, tools: &[String]) -> Option<(Option<String>, Vec<FunctionCall>)> {
+    let mut calls = Vec::new();
+    let mut content = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(TOOL_CALL_OPEN) {
+        content.push_str(&rest[..start]);
+        let after = &rest[start + TOOL_CALL_OPEN.len()..];
+        let end = after.find(TOOL_CALL_CLOSE).unwrap_or(after.len());
+        calls.push(function_call(
+            &serde_json::from_str(after[..end].trim()).ok()?,
+        )?);
+        rest = after[end..].trim_start_matches(TOOL_CALL_CLOSE);
+    }
+    content.push_str(rest);
+    if calls.is_empty() {
+        content.clear();
+        calls = match serde_json::from_str(text.trim()).ok()? {
+            Value::Array(values) => values.iter().map(function_call).collect::<Option<_>>()?,
+            value => vec![function_call(&value)?],
+        };
+    }
+    if calls.is_empty() || !calls.iter().all(|call| tools.contains(&call.name)) {
+        return None;
+    }
+    let content = Some(content.trim().to_string()).filter(|content| !content.is_empty());
+    Some((content, calls))
+}
+
+async fn chat_completions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Result<Response, OpenAiError> {
+    if request.stream {
+        return Err(OpenAiError::invalid_request(
+            "Streamed completions are not supported",
+        ));
+    }
+    let n = request.n.unwrap_or(1);
+    if n == 0 {
+        return Err(OpenAiError::invalid_request("n must be at least 1"));
+    }
+    if let Some(top_logprobs) = request.top_logprobs {
+        if !request.logprobs {
+            return Err(OpenAiError::invalid_request(
+                "top_logprobs needs logprobs to be true",
+            ));
+        }
+        if top_logprobs > MAX_CHAT_TOP_LOGPROBS {
+            return Err(OpenAiError::invalid_request(format!(
+                "top_logprobs must be at most {}",
+                MAX_CHAT_TOP_LOGPROBS
+            )));
+        }
+    }
+    check_messages(&request.messages)?;
+    let mut messages = request.messages;
+    let images = take_images(&state, &request.model, &mut messages)?;
+    let tools = request.tools.unwrap_or_default();
+    let tool_names = tool_names(&tools, request.tool_choice.as_ref())?;
+
+    let mut parameters = json!({
+        "messages": serde_json::to_string(&messages).unwrap_or_default(),
+        "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "n": n,
+    });
+    if request.logprobs {
+        parameters["logprobs"] = true.into();
+        parameters["top_logprobs"] = request.top_logprobs.unwrap_or(0).into();
+    }
+    request.sampling.insert_into(&mut parameters);
+    if !tools.is_empty() {
+        parameters["tools"] = Value::Array(tools).to_string().into();
+    }
+    if let Some(tool_choice) = &request.tool_choice {
+        parameters["tool_choice"] = match tool_choice {
+            Value::String(choice) => choice.clone(),
+            choice => choice.to_string(),
+        }
+        .into();
+    }
+    let calls_tools = !tool_names.is_empty()
+        && request.tool_choice.as_ref().and_then(Value::as_str) != Some("none");
+
+    let generation = generate(
+        &state,
+        &headers,
+        &request.model,
+        "rest.chat_completions",
+        parameters,
+        (!images.is_empty()).then_some(Media {
+            input: IMAGES_INPUT,
+            modalities: &["text", "image"],
+            files: images,
+        }),
+    )
+    .await?;
+    let id = generation.correlation_id;
+
+    let tokenizer = state.model_manager.tokenizer(&request.model);
+    let mut usage = CompletionUsage::default();
+    for message in &messages {
+        let text = message.content.as_ref().map(MessageContent::text);
+        usage.prompt_tokens += tokenizer.count(&text.unwrap_or_default());
+        for call in message.tool_calls.iter().flatten() {
+            usage.prompt_tokens += tokenizer.count(&call.function.arguments);
+        }
+    }
+    let mut choices = Vec::new();
+    for (index, text) in generation.texts.into_iter().enumerate() {
+        usage.completion_tokens += tokenizer.count(&text);
+        let calls = calls_tools
+            .then(|| tool_calls(&text, &tool_names))
+            .flatten();
+        let (content, tool_calls, finish_reason) = match calls {
+            Some((content, calls)) => {
+                let calls = calls
+                    .into_iter()
+                    .enumerate()
+                    .map(|(call, function)| ToolCall {
+                        id: format!("call_{}_{}_{}", id, index, call),
+                        kind: function_type(),
+                        function,
+                    })
+                    .collect();
+                (
+                    content.map(MessageContent::Text),
+                    Some(calls),
+                    TOOL_CALLS_FINISH_REASON.to_string(),
+                )
+            }
+            None => (
+                Some(MessageContent::Text(text)),
+                None,
+                generation.finish_reason.clone(),
+            ),
+        };
+        choices.push(ChatChoice {
+            index,
+            message: ChatMessage {
+                role: "assistant".to_string(),
+                content,
+                name: None,
+                tool_calls,
+                tool_call_id: None,
+            },
+            logprobs: request.logprobs.then(|| {
+                let tokens = generation
+                    .logprobs
+                    .get(index)
+                    .map_or(&[][..], Vec::as_slice);
+                chat_logprobs(tokens, request.top_logprobs.unwrap_or(0) as usize)
+            }),
+            finish_reason,
+        });
+    }
+    usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+
+    let response = ChatCompletionResponse {
+        id: format!("chatcmpl-{}", id),
+        object: "chat.completion",
+        created: unix_time(),
+        model: request.model,
+        choices,
+        usage,
+    };
+    Ok(with_correlation_id(
+        id,
+        with_quota(generation.quota, Json(response)),
+    ))
+}
+
+/// A served model, with the Galemind versions, backend and readiness alongside the OpenAI
+/// fields.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct OpenAiModel {
+    pub id: String,
+    /// `model`.
+    pub object: &'static str,
+    /// Unix time in seconds the model was registered.
+    pub created: u64,
+    pub owned_by: &'static str,
+    pub versions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    pub ready: bool,
+}
+
+impl From<ModelSummary> for OpenAiModel {
+    fn from(summary: ModelSummary) -> Self {
+        Self {
+            id: summary.name,
+            object: "model",
+            created: summary
+                .registered
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            owned_by: "galemind",
+            versions: summary.versions,
+            backend: summary.backend,
+            ready: summary.ready,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct OpenAiModelList {
+    /// `list`.
+    pub object: &'static str,
+    pub data: Vec<OpenAiModel>,
+}
+
+/// Registered models, sorted by name.
+async fn models_handler(State(state): State<AppState>) -> Json<OpenAiModelList> {
+    let model_manager = &state.model_manager;
+    let data = model_manager
+        .select_models(&LabelSelector::default())
+        .iter()
+        .filter_map(|model_id| model_manager.model_summary(model_id))
+        .map(OpenAiModel::from)
+        .collect();
+    Json(OpenAiModelList {
+        object: "list",
+        data,
+    })
+}
+
+async fn model_handler(
+    State(state): State<AppState>,
+    Path(model_name): Path<String>,
+) -> Result<Json<OpenAiModel>, OpenAiError> {
+    state
+        .model_manager
+        .model_summary(&ModelId(model_name.clone()))
+        .map(|summary| Json(summary.into()))
+        .ok_or_else(|| OpenAiError {
+            status: StatusCode::NOT_FOUND,
+            message: format!("The model '{}' does not exist", model_name),
+            code: Some("model_not_found".to_string()),
+            retry_after: None,
+        })
+}
+
+pub fn new_openai_router(state: AppState) -> Router {
+    Router::new()
+        .route("/v1/models", get(models_handler))
+        .route("/v1/models/{model_name}", get(model_handler))
+        .route("/v1/completions", post(completions_handler))
+        .route("/v1/chat/completions", post(chat_completions_handler))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{get, output, parameter, post_json, send_json, state_with};
+    use foundation::api::inference::InferParameter;
+
+    /// A router serving model `m`, which completes every prompt with ` world`.
+    fn completing() -> Router {
+        new_openai_router(state_with("m", |request| {
+            let prompt = parameter(&request, "prompt").unwrap_or_default();
+            output(
+                "text",
+                Data::VSTRING(vec![format!("{} world", prompt.len())]),
+                &[(
+                    FINISH_REASON_PARAMETER,
+                    InferParameter::String("length".to_string()),
+                )],
+            )
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_completions_are_generated_per_prompt() {
+        let (status, body) = send_json(
+            completing(),
+            post_json(
+                "/v1/completions",
+                &json!({ "model": "m", "prompt": ["a", "bc"], "echo": true }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["object"], "text_completion");
+        assert!(body["id"].as_str().unwrap().starts_with("cmpl-"));
+        assert_eq!(body["choices"][0]["text"], "a1 world");
+        assert_eq!(body["choices"][1]["text"], "bc2 world");
+        assert_eq!(body["choices"][1]["index"], 1);
+        assert_eq!(body["choices"][0]["finish_reason"], "length");
+        assert_eq!(body["choices"][0]["logprobs"], Value::Null);
+        let usage = &body["usage"];
+        assert_eq!(
+            usage["total_tokens"].as_u64(),
+            Some(
+                usage["prompt_tokens"].as_u64().unwrap()
+                    + usage["completion_tokens"].as_u64().unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_completion_requests_are_refused() {
+        for (request, status) in [
+            (json!({ "model": "m" }), StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                json!({ "model": "m", "prompt": [] }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "model": "m", "prompt": "a", "n": 0 }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "model": "m", "prompt": "a", "stream": true }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "model": "m", "prompt": "a", "logprobs": 6 }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "model": "unknown", "prompt": "a" }),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let (actual, body) =
+                send_json(completing(), post_json("/v1/completions", &request)).await;
+            assert_eq!(actual, status, "{request}: {body}");
+            if status != StatusCode::UNPROCESSABLE_ENTITY {
+                assert_eq!(body["error"]["type"], "invalid_request_error", "{body}");
+            }
+        }
+    }
+
+    /// A router serving model `m`, which answers every chat with `text`.
+    fn chatting(text: &'static str) -> Router {
+        new_openai_router(state_with("m", move |request| {
+            // The declared tools reach the model as JSON.
+            let tools = parameter(&request, "tools").unwrap_or("[]");
+            assert!(serde_json::from_str::<Vec<Value>>(tools).is_ok());
+            output("text", Data::VSTRING(vec![text.to_string()]), &[])
+        }))
+    }
+
+    fn chat(tools: Value, tool_choice: Value) -> Value {
+        json!({
+            "model": "m",
+            "messages": [{ "role": "user", "content": "weather?" }],
+            "tools": tools,
+            "tool_choice": tool_choice,
+        })
+    }
+
+    fn weather_tool() -> Value {
+        json!([{ "type": "function", "function": { "name": "weather" } }])
+    }
+
+    #[test]
+    fn test_tool_calls_are_read_from_generated_text() {
+        let tools = ["weather".to_string()];
+        let (content, calls) = tool_calls(
+            "Let me check. <tool_call>{\"name\": \"weather\", \"arguments\": {\"city\": \"Oslo\"}}</tool_call>",
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(content.as_deref(), Some("Let me check."));
+        assert_eq!(calls[0].name, "weather");
+        assert_eq!(calls[0].arguments, r#"{"city":"Oslo"}"#);
+
+        let (content, calls) = tool_calls(
+            r#"[{"name": "weather", "parameters": {}}, {"name": "weather"}]"#,
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(content, None);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].arguments, "{}");
+
+        assert!(tool_calls(r#"{"name": "search"}"#, &tools).is_none());
+        assert!(tool_calls("<tool_call>not json</tool_call>", &tools).is_none());
+        assert!(tool_calls("It is sunny.", &tools).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chats_call_the_declared_tools() {
+        let router = chatting(
+            r#"<tool_call>{"name": "weather", "arguments": {"city": "Oslo"}}</tool_call>"#,
+        );
+        let (status, body) = send_json(
+            router.clone(),
+            post_json("/v1/chat/completions", &chat(weather_tool(), json!("auto"))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let choice = &body["choices"][0];
+        assert_eq!(choice["finish_reason"], TOOL_CALLS_FINISH_REASON);
+        assert_eq!(choice["message"]["content"], Value::Null);
+        let call = &choice["message"]["tool_calls"][0];
+        assert_eq!(call["type"], "function");
+        assert_eq!(call["function"]["arguments"], r#"{"city":"Oslo"}"#);
+        assert!(call["id"].as_str().unwrap().starts_with("call_"));
+
+        // Told not to call tools, the text is the content.
+        let (_, body) = send_json(
+            router,
+            post_json("/v1/chat/completions", &chat(weather_tool(), json!("none"))),
+        )
+        .await;
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+        assert!(
+            body["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap()
+                .contains("<tool_call>")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_tools_are_refused() {
+        let undeclared = json!({ "type": "function", "function": { "name": "search" } });
+        let unanswered = json!({
+            "model": "m",
+            "messages": [{ "role": "tool", "content": "12C", "tool_call_id": "call_0" }],
+        });
+        for request in [
+            chat(json!([{ "type": "retrieval" }]), json!("auto")),
+            chat(json!([]), json!("required")),
+            chat(weather_tool(), undeclared),
+            chat(weather_tool(), json!("sometimes")),
+            unanswered,
+        ] {
+            let (status, body) =
+                send_json(chatting(""), post_json("/v1/chat/completions", &request)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{request}: {body}");
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+        }
+    }
+
+    /// A router serving model `m`, which answers `ab` with the log probabilities `logprobs`
+    /// when asked for them.
+    fn scoring(logprobs: &'static str) -> Router {
+        new_openai_router(state_with("m", move |request| {
+            let parameters = request.parameters.clone().unwrap_or_default();
+            let parameters = match parameters.get(LOGPROBS_PARAMETER) {
+                Some(InferParameter::Bool(true)) => {
+                    assert!(matches!(
+                        parameters.get("top_logprobs"),
+                        Some(InferParameter::Int64(_))
+                    ));
+                    vec![(
+                        LOGPROBS_PARAMETER,
+                        InferParameter::String(logprobs.to_string()),
+                    )]
+                }
+                _ => Vec::new(),
+            };
+            output("text", Data::VSTRING(vec!["ab".to_string()]), &parameters)
+        }))
+    }
+
+    const LOGPROBS: &str = r#"[[
+        {"token": "a", "logprob": -0.5,
+         "top_logprobs": [{"token": "a", "logprob": -0.5}, {"token": "b", "logprob": -1.5}]},
+        {"token": "b", "logprob": -0.25}
+    ]]"#;
+
+    #[tokio::test]
+    async fn test_log_probabilities_take_the_layout_of_each_api() {
+        let (status, body) = send_json(
+            scoring(LOGPROBS),
+            post_json(
+                "/v1/completions",
+                &json!({ "model": "m", "prompt": "xy", "logprobs": 1, "echo": true }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body["choices"][0]["logprobs"],
+            json!({
+                "tokens": ["a", "b"],
+                "token_logprobs": [-0.5, -0.25],
+                "top_logprobs": [{ "a": -0.5 }, {}],
+                "text_offset": [2, 3],
+            })
+        );
+
+        let (status, body) = send_json(
+            scoring(LOGPROBS),
+            post_json(
+                "/v1/chat/completions",
+                &json!({
+                    "model": "m",
+                    "messages": [{ "role": "user", "content": "xy" }],
+                    "logprobs": true,
+                    "top_logprobs": 2,
+                }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let content = &body["choices"][0]["logprobs"]["content"];
+        assert_eq!(content[0]["bytes"], json!([97]));
+        assert_eq!(content[0]["top_logprobs"][1]["token"], "b");
+        assert_eq!(content[1]["top_logprobs"], json!([]));
+
+        // Not asked for, none are returned.
+        let (_, body) = send_json(
+            scoring(LOGPROBS),
+            post_json("/v1/completions", &json!({ "model": "m", "prompt": "xy" })),
+        )
+        .await;
+        assert_eq!(body["choices"][0]["logprobs"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_log_probability_requests_fail() {
+        let chat = |fields: Value| {
+            let mut request = json!({
+                "model": "m",
+                "messages": [{ "role": "user", "content": "xy" }],
+            });
+            request
+                .as_object_mut()
+                .unwrap()
+                .extend(fields.as_object().unwrap().clone());
+            post_json("/v1/chat/completions", &request)
+        };
+        for request in [
+            chat(json!({ "top_logprobs": 2 })),
+            chat(json!({ "logprobs": true, "top_logprobs": 21 })),
+        ] {
+            let (status, _) = send_json(scoring(LOGPROBS), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        let (status, body) =
+            send_json(scoring("not json"), chat(json!({ "logprobs": true }))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("malformed log probabilities")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registered_models_are_listed() {
+        let (status, body) = send_json(completing(), get("/v1/models")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["object"], "list");
+        let model = &body["data"][0];
+        assert_eq!(model["id"], "m");
+        assert_eq!(model["object"], "model");
+        assert_eq!(model["owned_by"], "galemind");
+        assert_eq!(model["versions"], json!(["1"]));
+        assert_eq!(model["ready"], true);
+        assert!(model["created"].as_u64().unwrap() > 0);
+
+        let (status, body) = send_json(completing(), get("/v1/models/m")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], "m");
+
+        let (status, body) = send_json(completing(), get("/v1/models/unknown")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "model_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_models_answering_no_text_fail() {
+        let router = new_openai_router(state_with("m", |_| {
+            output("scores", Data::VFLOAT(vec![0.5]), &[])
+        }));
+        let (status, body) = send_json(
+            router,
+            post_json("/v1/completions", &json!({ "model": "m", "prompt": "a" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["type"], "server_error");
+    }
+}
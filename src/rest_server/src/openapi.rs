@@ -0,0 +1,1185 @@
+/* OpenAPI description of the REST API: `GET /openapi.json` and `GET /docs`.
+
+`/openapi.json` is an OpenAPI 3.1 document of every route of the server, from
+which client teams can generate SDKs:
+
+```bash
+curl -s http://localhost:8080/openapi.json -o galemind.json
+openapi-generator-cli generate -i galemind.json -g python -o galemind-client
+```
+
+`/docs` serves Swagger UI on it, to browse and try the API. The page loads
+Swagger UI from a CDN; browsers without internet access can still fetch the
+document itself. Neither route requires an API key.
+
+The schemas of the bodies are those of the types the handlers parse and
+answer with: every type derives `JsonSchema` next to its definition, so its
+schema follows its fields and serde attributes. A route is described by an
+`Operation` in `document`; adding a route means adding its operation.
+*/
+
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use axum::{
+    Json, Router,
+    response::{Html, IntoResponse},
+    routing::get,
+};
+use schemars::{JsonSchema, SchemaGenerator, generate::SchemaSettings};
+use serde_json::{Map, Value, json};
+
+use crate::data_model::{
+    AsyncInferenceStatus, ErrorInferenceResponse, InferenceRequest, InferenceResponse,
+    MetadataModelResponse, ModelListEntry,
+};
+use crate::metadata_model::ServerMetadataResponse;
+use crate::openai::{
+    ChatCompletionRequest, ChatCompletionResponse, CompletionRequest, CompletionResponse,
+    OpenAiModel, OpenAiModelList,
+};
+
+pub const OPENAPI_PATH: &str = "/openapi.json";
+pub const DOCS_PATH: &str = "/docs";
+
+/// The named schemas of the document, generated from the types by `schemars` as they
+/// are deserialized: a field the type may do without is optional.
+pub struct Components {
+    generator: SchemaGenerator,
+}
+
+impl Default for Components {
+    fn default() -> Self {
+        let settings = SchemaSettings::draft2020_12().with(|settings| {
+            settings.definitions_path = "/components/schemas".into();
+        });
+        Self {
+            generator: settings.into_generator(),
+        }
+    }
+}
+
+impl Components {
+    /// Reference to the schema of `T`, adding it and those it references when missing.
+    pub fn reference<T: JsonSchema>(&mut self) -> Value {
+        self.generator.subschema_for::<T>().to_value()
+    }
+
+    fn schemas(mut self) -> Map<String, Value> {
+        self.generator.take_definitions(true)
+    }
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer" })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+/// Any JSON value.
+fn any() -> Value {
+    json!({})
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// One of the strings of `values`.
+fn one_of_strings(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn one_of(schemas: Vec<Value>) -> Value {
+    json!({ "oneOf": schemas })
+}
+
+/// `schema` or null.
+fn nullable(schema: Value) -> Value {
+    one_of(vec![schema, json!({ "type": "null" })])
+}
+
+/// Schema of an object, built field by field.
+struct Object {
+    schema: Map<String, Value>,
+    properties: Map<String, Value>,
+    required: Vec<&'static str>,
+}
+
+fn object(description: &str) -> Object {
+    let mut schema = Map::new();
+    schema.insert("type".to_string(), "object".into());
+    if !description.is_empty() {
+        schema.insert("description".to_string(), description.into());
+    }
+    Object {
+        schema,
+        properties: Map::new(),
+        required: Vec::new(),
+    }
+}
+
+impl Object {
+    /// A field that is always present.
+    fn field(mut self, name: &'static str, schema: Value) -> Self {
+        self.required.push(name);
+        self.optional(name, schema)
+    }
+
+    /// A field that may be absent.
+    fn optional(mut self, name: &'static str, schema: Value) -> Self {
+        self.properties.insert(name.to_string(), schema);
+        self
+    }
+}
+
+impl From<Object> for Value {
+    fn from(object: Object) -> Self {
+        let mut schema = object.schema;
+        schema.insert("properties".to_string(), Value::Object(object.properties));
+        if !object.required.is_empty() {
+            schema.insert("required".to_string(), json!(object.required));
+        }
+        Value::Object(schema)
+    }
+}
+
+/// A route of the API.
+struct Operation {
+    tag: &'static str,
+    value: Map<String, Value>,
+    responses: Map<String, Value>,
+}
+
+fn operation(tag: &'static str, summary: &str) -> Operation {
+    let mut value = Map::new();
+    value.insert("summary".to_string(), summary.into());
+    Operation {
+        tag,
+        value,
+        responses: Map::new(),
+    }
+}
+
+fn content(media_type: &str, schema: Value) -> Value {
+    json!({ media_type: { "schema": schema } })
+}
+
+impl Operation {
+    fn description(mut self, description: &str) -> Self {
+        self.value
+            .insert("description".to_string(), description.into());
+        self
+    }
+
+    /// A query parameter.
+    fn query(mut self, name: &str, schema: Value, description: &str) -> Self {
+        let parameter = json!({
+            "name": name,
+            "in": "query",
+            "schema": schema,
+            "description": description,
+        });
+        self.parameters().push(parameter);
+        self
+    }
+
+    fn parameters(&mut self) -> &mut Vec<Value> {
+        let parameters = self
+            .value
+            .entry("parameters")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        match parameters {
+            Value::Array(parameters) => parameters,
+            _ => unreachable!("parameters are an array"),
+        }
+    }
+
+    /// A JSON request body.
+    fn body(self, schema: Value) -> Self {
+        self.body_as("application/json", schema)
+    }
+
+    fn body_as(mut self, media_type: &str, schema: Value) -> Self {
+        self.value.insert(
+            "requestBody".to_string(),
+            json!({ "required": true, "content": content(media_type, schema) }),
+        );
+        self
+    }
+
+    /// A JSON response.
+    fn response(self, status: u16, description: &str, schema: Value) -> Self {
+        self.response_as(status, description, "application/json", schema)
+    }
+
+    fn response_as(
+        mut self,
+        status: u16,
+        description: &str,
+        media_type: &str,
+        schema: Value,
+    ) -> Self {
+        self.responses.insert(
+            status.to_string(),
+            json!({ "description": description, "content": content(media_type, schema) }),
+        );
+        self
+    }
+
+    /// A response without a body.
+    fn empty(mut self, status: u16, description: &str) -> Self {
+        self.responses
+            .insert(status.to_string(), json!({ "description": description }));
+        self
+    }
+}
+
+/// The routes of the document by path and method.
+#[derive(Default)]
+struct Paths {
+    paths: BTreeMap<String, Map<String, Value>>,
+}
+
+impl Paths {
+    fn add(&mut self, method: &str, path: &str, mut operation: Operation) {
+        // Parameters of the path, e.g. `{model_name}`.
+        for name in path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        {
+            let parameter = json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": string(),
+            });
+            operation.parameters().push(parameter);
+        }
+        operation
+            .value
+            .insert("tags".to_string(), json!([operation.tag]));
+        operation
+            .value
+            .insert("responses".to_string(), Value::Object(operation.responses));
+        self.paths
+            .entry(path.to_string())
+            .or_default()
+            .insert(method.to_string(), Value::Object(operation.value));
+    }
+}
+
+/// The OpenAPI document of the REST API.
+pub fn document() -> Value {
+    let mut components = Components::default();
+    let mut paths = Paths::default();
+    let error = components.reference::<ErrorInferenceResponse>();
+    // The errors every V2 route may answer with.
+    let refusals = |operation: Operation| {
+        operation
+            .response(401, "Missing or invalid API key", error.clone())
+            .response(429, "Rate limited, or over quota", error.clone())
+            .response(503, "Overloaded, or shedding load", error.clone())
+    };
+    let openai_error: Value = object("An error in the layout OpenAI clients parse.")
+        .field(
+            "error",
+            object("")
+                .field("message", string())
+                .field(
+                    "type",
+                    one_of_strings(&["invalid_request_error", "server_error"]),
+                )
+                .field("param", nullable(string()))
+                .field("code", nullable(string()))
+                .into(),
+        )
+        .into();
+
+    paths.add(
+        "get",
+        "/v2",
+        operation("Server", "Server metadata").response(
+            200,
+            "Server metadata",
+            components.reference::<ServerMetadataResponse>(),
+        ),
+    );
+    paths.add(
+        "get",
+        "/v2/health/live",
+        operation("Health", "Whether the server is live").empty(200, "Live"),
+    );
+    for (path, summary) in [
+        (
+            "/v2/health/ready",
+            "Whether the server is ready to serve inferences",
+        ),
+        ("/livez", "Liveness probe"),
+        ("/readyz", "Readiness probe"),
+        ("/healthz", "Health of the server"),
+    ] {
+        paths.add(
+            "get",
+            path,
+            operation("Health", summary)
+                .empty(200, "Live or ready")
+                .empty(503, "Not live, not ready or unhealthy"),
+        );
+    }
+    paths.add(
+        "get",
+        "/metrics",
+        operation("Server", "Prometheus metrics").response_as(
+            200,
+            "Metrics in the Prometheus text exposition format",
+            "text/plain",
+            string(),
+        ),
+    );
+
+    paths.add(
+        "get",
+        "/v2/models",
+        refusals(operation("Models", "Models of the catalog"))
+            .query(
+                "selector",
+                string(),
+                "Label selector the models must match, e.g. team=search",
+            )
+            .response(
+                200,
+                "The models",
+                array(components.reference::<ModelListEntry>()),
+            ),
+    );
+    let metadata = components.reference::<MetadataModelResponse>();
+    for path in [
+        "/v2/models/{model_name}",
+        "/v2/models/{model_name}/versions/{model_version}",
+    ] {
+        paths.add(
+            "get",
+            path,
+            refusals(operation("Models", "Model metadata"))
+                .response(
+                    200,
+                    "Inputs, outputs and capabilities of the model",
+                    metadata.clone(),
+                )
+                .response(404, "Unknown model or version", error.clone()),
+        );
+    }
+    for path in [
+        "/v2/models/{model_name}/ready",
+        "/v2/models/{model_name}/versions/{model_version}/ready",
+    ] {
+        paths.add(
+            "get",
+            path,
+            refusals(operation("Models", "Whether the model is ready"))
+                .response_as(200, "Ready", "text/plain", string())
+                .response(503, "Not served, or its circuit is open", error.clone()),
+        );
+    }
+
+    let request = components.reference::<InferenceRequest>();
+    let response = components.reference::<InferenceResponse>();
+    let status = components.reference::<AsyncInferenceStatus>();
+    let inference_errors = |operation: Operation| {
+        refusals(operation)
+            .response(
+                400,
+                "Invalid request, e.g. tensors unlike the model's inputs",
+                error.clone(),
+            )
+            .response(404, "Unknown model or version", error.clone())
+            .response(413, "Request body over the size limit", error.clone())
+            .response(
+                422,
+                "Malformed body, or tensor data unlike its datatype or shape",
+                error.clone(),
+            )
+            .response(504, "Deadline exceeded", error.clone())
+    };
+    for prefix in [
+        "/v2/models/{model_name}",
+        "/v2/models/{model_name}/versions/{model_version}",
+    ] {
+        paths.add(
+            "post",
+            &format!("{}/infer", prefix),
+            inference_errors(operation("Inference", "Runs an inference"))
+                .description(
+                    "Also takes the binary tensor extension, with the \
+                     Inference-Header-Content-Length header, and answers in CSV \
+                     or NDJSON as the Accept header asks.",
+                )
+                .body(request.clone())
+                .response(200, "The outputs of the model", response.clone()),
+        );
+        paths.add(
+            "post",
+            &format!("{}/infer_async", prefix),
+            inference_errors(operation(
+                "Inference",
+                "Submits an inference to run asynchronously",
+            ))
+            .description(
+                "With an x-callback-url header, the result is also POSTed to that URL \
+                 once completed.",
+            )
+            .body(request.clone())
+            .response(
+                202,
+                "Accepted, to poll at the Location header",
+                status.clone(),
+            ),
+        );
+        paths.add(
+            "post",
+            &format!("{}/infer_stream", prefix),
+            inference_errors(operation(
+                "Inference",
+                "Runs inferences, streaming their results",
+            ))
+            .description(
+                "Server-sent events: `stream` with the token resuming the stream, \
+                     one `result` or `error` per request, then `end`.",
+            )
+            .body(array(request.clone()))
+            .response_as(200, "The event stream", "text/event-stream", string()),
+        );
+    }
+    for path in ["/v2/inference/{id}", "/v1/operations/{id}"] {
+        paths.add(
+            "get",
+            path,
+            refusals(operation(
+                "Inference",
+                "Result of an asynchronous inference",
+            ))
+            .query(
+                "wait",
+                integer(),
+                "Seconds to wait for completion, up to 60",
+            )
+            .response(200, "Completed", response.clone())
+            .response(202, "Still pending", status.clone())
+            .response(404, "Unknown or expired inference", error.clone())
+            .response(500, "The inference failed", error.clone()),
+        );
+    }
+    paths.add(
+        "get",
+        "/v2/streams/{token}",
+        refusals(operation("Inference", "Resumes an inference stream"))
+            .description(
+                "Replays the events after the Last-Event-ID header, then follows the stream.",
+            )
+            .response_as(200, "The event stream", "text/event-stream", string())
+            .response(404, "Unknown or expired stream", error.clone()),
+    );
+    paths.add(
+        "get",
+        "/v1/stream",
+        operation("Inference", "Bidirectional inference over a WebSocket")
+            .description(
+                "Each text frame is an inference request with its model_name and \
+                 model_version, answered by a frame holding the response or the error.",
+            )
+            .query("access_token", string(), "API key or token, for browsers")
+            .empty(101, "Switched to the WebSocket protocol"),
+    );
+
+    paths.add(
+        "get",
+        "/v2/usage",
+        refusals(operation("Quotas", "Usage of the caller's quotas")).response(
+            200,
+            "Usage and limits of each window",
+            any(),
+        ),
+    );
+    paths.add(
+        "post",
+        "/v2/watermark/detect",
+        refusals(operation(
+            "Watermark",
+            "Whether a text carries a watermark of this server",
+        ))
+        .body(
+            object("")
+                .field("text", string())
+                .optional("scheme", string())
+                .into(),
+        )
+        .response(200, "The detection", any()),
+    );
+    let batch_job: Value = object("A batch prediction job and its progress.")
+        .field("id", string())
+        .field("state", string())
+        .field("model_name", string())
+        .field("model_version", string())
+        .field("input", string())
+        .field("format", string())
+        .field("batch_size", integer())
+        .optional("rows_total", integer())
+        .field("rows_done", integer())
+        .field("rows_failed", integer())
+        .field("output", string())
+        .optional("error", string())
+        .into();
+    paths.add(
+        "post",
+        "/v1/batch_jobs",
+        refusals(operation(
+            "Batch jobs",
+            "Runs a model over the rows of a file in the background",
+        ))
+        .description(
+            "The input is a local path or an s3://, gs:// or az:// URI of a JSONL, CSV or \
+             Parquet file, whose columns are the inputs of the model. Results are written to the \
+             output of the job, one JSON line per row.",
+        )
+        .body(
+            object("")
+                .field("model_name", string())
+                .optional("model_version", string())
+                .field("input", string())
+                .optional("format", string())
+                .optional("batch_size", integer())
+                .into(),
+        )
+        .response(202, "The accepted job", batch_job.clone())
+        .response(400, "Invalid job", error.clone())
+        .response(
+            404,
+            "Unknown model, or batch jobs not enabled",
+            error.clone(),
+        ),
+    );
+    paths.add(
+        "get",
+        "/v1/batch_jobs",
+        refusals(operation("Batch jobs", "Submitted batch jobs")).response(
+            200,
+            "The jobs, oldest first",
+            array(batch_job.clone()),
+        ),
+    );
+    paths.add(
+        "get",
+        "/v1/batch_jobs/{id}",
+        refusals(operation("Batch jobs", "A batch job and its progress"))
+            .response(200, "The job", batch_job)
+            .response(404, "Unknown job", error.clone()),
+    );
+    let system_region: Value = object("A system shared memory region.")
+        .field("name", string())
+        .field("key", string())
+        .field("offset", integer())
+        .field("byte_size", integer())
+        .into();
+    for path in [
+        "/v2/systemsharedmemory/status",
+        "/v2/systemsharedmemory/region/{name}/status",
+    ] {
+        paths.add(
+            "get",
+            path,
+            refusals(operation(
+                "Shared memory",
+                "Registered system shared memory regions",
+            ))
+            .response(200, "The regions", array(system_region.clone()))
+            .response(404, "Unknown region", error.clone()),
+        );
+    }
+    paths.add(
+        "post",
+        "/v2/systemsharedmemory/region/{name}/register",
+        refusals(operation(
+            "Shared memory",
+            "Registers a region of a shared memory object",
+        ))
+        .description(
+            "Inputs and requested outputs then name the region in their \
+             shared_memory_region, shared_memory_byte_size and shared_memory_offset \
+             parameters instead of carrying data. CUDA shared memory is not served.",
+        )
+        .body(
+            object("")
+                .field("key", string())
+                .optional("offset", integer())
+                .field("byte_size", integer())
+                .into(),
+        )
+        .empty(200, "Registered")
+        .response(400, "Unknown object, or region out of it", error.clone()),
+    );
+    for path in [
+        "/v2/systemsharedmemory/unregister",
+        "/v2/systemsharedmemory/region/{name}/unregister",
+    ] {
+        paths.add(
+            "post",
+            path,
+            refusals(operation(
+                "Shared memory",
+                "Unregisters a system shared memory region, or all of them",
+            ))
+            .empty(200, "Unregistered"),
+        );
+    }
+    for (method, path, summary) in [
+        ("get", "/v2/admin/buffers", "Requests buffered per model"),
+        (
+            "post",
+            "/v2/admin/buffers/drain",
+            "Fails the requests buffered for a model or every model",
+        ),
+        (
+            "get",
+            "/v2/admin/circuits",
+            "Circuit breaker state of every model",
+        ),
+        ("get", "/v2/admin/config", "Options the server runs with"),
+        (
+            "post",
+            "/v2/admin/config/reload",
+            "Reloads the server configuration",
+        ),
+        (
+            "get",
+            "/v2/admin/devices",
+            "Devices and the models placed on them",
+        ),
+        (
+            "get",
+            "/v2/admin/log-level",
+            "Level of the server's messages",
+        ),
+        (
+            "put",
+            "/v2/admin/log-level",
+            "Changes the level of the server's messages",
+        ),
+        (
+            "get",
+            "/v2/admin/memory",
+            "Memory budget and loaded versions per device",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/load",
+            "Loads a model from the models directory",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/unload",
+            "Stops serving a model",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/pause",
+            "Refuses new requests for a model until resumed",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/resume",
+            "Admits requests for a paused model again",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/drain",
+            "Pauses a model and waits for its requests to finish",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/selftest",
+            "Runs the self-test of a model",
+        ),
+        (
+            "get",
+            "/v2/admin/models/{model_name}/timeseries",
+            "Recent statistics of a model",
+        ),
+        (
+            "get",
+            "/v2/admin/models/{model_name}/versions/{model_version}/instances",
+            "Instances of a model version",
+        ),
+        (
+            "post",
+            "/v2/admin/models/{model_name}/versions/{model_version}/instances/{instance}/restart",
+            "Restarts an instance",
+        ),
+        ("get", "/v2/admin/paused", "Models refusing new requests"),
+        (
+            "get",
+            "/v2/admin/scheduler",
+            "Requests queued and running per model",
+        ),
+        ("get", "/v2/admin/shadow", "Statistics of shadowed versions"),
+        (
+            "put",
+            "/v2/admin/shadow/{model_name}",
+            "Sets the versions shadowing a model",
+        ),
+        ("get", "/v2/admin/stats", "Statistics of every model"),
+        ("get", "/v2/admin/tenants", "Error budgets of the tenants"),
+        (
+            "post",
+            "/v2/admin/tenants/{tenant}/release",
+            "Releases a suspended tenant",
+        ),
+        ("get", "/v2/admin/traffic", "Bytes of each tenant today"),
+        (
+            "get",
+            "/v2/admin/read-only",
+            "Whether the catalog is read-only",
+        ),
+        (
+            "put",
+            "/v2/admin/read-only",
+            "Makes the catalog read-only or writable",
+        ),
+    ] {
+        paths.add(
+            method,
+            path,
+            refusals(operation("Admin", summary)).response(200, "Done", any()),
+        );
+    }
+
+    paths.add(
+        "get",
+        "/v1/models",
+        operation("OpenAI", "Models, as OpenAI lists them").response(
+            200,
+            "The models",
+            components.reference::<OpenAiModelList>(),
+        ),
+    );
+    paths.add(
+        "get",
+        "/v1/models/{model_name}",
+        operation("OpenAI", "A model, as OpenAI describes it")
+            .response(200, "The model", components.reference::<OpenAiModel>())
+            .response(404, "Unknown model", openai_error.clone()),
+    );
+    paths.add(
+        "post",
+        "/v1/completions",
+        operation("OpenAI", "Text completion")
+            .body(components.reference::<CompletionRequest>())
+            .response(
+                200,
+                "The completions",
+                components.reference::<CompletionResponse>(),
+            )
+            .response(400, "Invalid request", openai_error.clone()),
+    );
+    paths.add(
+        "post",
+        "/v1/chat/completions",
+        operation("OpenAI", "Chat completion")
+            .body(components.reference::<ChatCompletionRequest>())
+            .response(
+                200,
+                "The completions",
+                components.reference::<ChatCompletionResponse>(),
+            )
+            .response(400, "Invalid request", openai_error.clone()),
+    );
+    paths.add(
+        "post",
+        "/v1/audio/transcriptions",
+        operation("OpenAI", "Speech to text")
+            .body_as(
+                "multipart/form-data",
+                object("")
+                    .field(
+                        "file",
+                        json!({ "type": "string", "contentMediaType": "application/octet-stream" }),
+                    )
+                    .field("model", string())
+                    .optional("language", string())
+                    .optional("prompt", string())
+                    .optional("temperature", number())
+                    .optional(
+                        "response_format",
+                        one_of_strings(&["json", "text", "verbose_json"]),
+                    )
+                    .into(),
+            )
+            .response(200, "The transcript", any())
+            .response(400, "Invalid request", openai_error),
+    );
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Galemind",
+            "description": "KServe V2 inference, OpenAI compatible and administration API.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths.paths,
+        "components": {
+            "schemas": components.schemas(),
+            "securitySchemes": {
+                "apiKey": { "type": "apiKey", "in": "header", "name": "x-api-key" },
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+        "security": [{ "apiKey": [] }, { "bearer": [] }, {}],
+    })
+}
+
+/// Built once, on first request.
+static DOCUMENT: LazyLock<Value> = LazyLock::new(document);
+
+async fn openapi_handler() -> impl IntoResponse {
+    Json(DOCUMENT.clone())
+}
+
+async fn docs_handler() -> Html<String> {
+    Html(format!(
+        r##"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Galemind API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({{ url: "{}", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        OPENAPI_PATH
+    ))
+}
+
+pub fn new_openapi_router() -> Router {
+    Router::new()
+        .route(OPENAPI_PATH, get(openapi_handler))
+        .route(DOCS_PATH, get(docs_handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_model::{MetadataTensor, TensorData};
+    use crate::openai::{
+        ChatChoice, ChatMessage, CompletionChoice, CompletionUsage, FunctionCall, ToolCall,
+    };
+    use foundation::ApiError;
+    use serde::Serialize;
+    use std::time::Duration;
+
+    /// Why `value` does not conform to `schema`, resolving references in `document`.
+    ///
+    /// Stricter than JSON Schema on one point: a key an object schema does not declare is
+    /// refused, as a field renamed on the type but not in its schema would be.
+    fn violation(document: &Value, schema: &Value, value: &Value, path: &str) -> Option<String> {
+        let schema = match schema {
+            Value::Bool(true) => return None,
+            Value::Bool(false) => return Some(format!("{path}: no value is allowed")),
+            Value::Object(schema) => schema,
+            _ => return Some(format!("{path}: invalid schema {schema}")),
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let target = document
+                .pointer(reference.trim_start_matches('#'))
+                .unwrap_or_else(|| panic!("unresolved reference {reference}"));
+            return violation(document, target, value, path);
+        }
+        let types: Vec<&str> = match schema.get("type") {
+            Some(Value::String(kind)) => vec![kind],
+            Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let kind = match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(number) if number.is_f64() => "number",
+            Value::Number(_) => "integer",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        let typed = types.is_empty()
+            || types.contains(&kind)
+            || (kind == "integer" && types.contains(&"number"));
+        if !typed {
+            return Some(format!("{path}: {kind} is not one of {types:?}"));
+        }
+        if let Some(expected) = schema.get("const").filter(|expected| *expected != value) {
+            return Some(format!("{path}: {value} is not {expected}"));
+        }
+        if let Some(Value::Array(values)) = schema.get("enum")
+            && !values.contains(value)
+        {
+            return Some(format!("{path}: {value} is not one of {values:?}"));
+        }
+        for keyword in ["oneOf", "anyOf"] {
+            if let Some(Value::Array(schemas)) = schema.get(keyword) {
+                let conforming = schemas
+                    .iter()
+                    .filter(|schema| violation(document, schema, value, path).is_none())
+                    .count();
+                if conforming == 0 || (keyword == "oneOf" && conforming > 1) {
+                    return Some(format!(
+                        "{path}: {conforming} of the {keyword} schemas match"
+                    ));
+                }
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("allOf")
+            && let Some(violation) = schemas
+                .iter()
+                .find_map(|schema| violation(document, schema, value, path))
+        {
+            return Some(violation);
+        }
+        match value {
+            Value::Array(items) => {
+                let schema = schema.get("items")?;
+                items.iter().enumerate().find_map(|(index, item)| {
+                    violation(document, schema, item, &format!("{path}[{index}]"))
+                })
+            }
+            Value::Object(fields) => {
+                if let Some(Value::Array(required)) = schema.get("required")
+                    && let Some(missing) = required
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .find(|name| !fields.contains_key(*name))
+                {
+                    return Some(format!("{path}: missing {missing}"));
+                }
+                let properties = schema.get("properties").and_then(Value::as_object);
+                let additional = schema.get("additionalProperties");
+                fields.iter().find_map(|(name, field)| {
+                    let path = format!("{path}.{name}");
+                    match (
+                        properties.and_then(|properties| properties.get(name)),
+                        additional,
+                    ) {
+                        (Some(schema), _) | (None, Some(schema)) => {
+                            violation(document, schema, field, &path)
+                        }
+                        (None, None) if properties.is_some() => {
+                            Some(format!("{path}: not in the schema"))
+                        }
+                        (None, None) => None,
+                    }
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks that `value`, serialized, conforms to the published schema of `T`.
+    fn assert_conforms<T: JsonSchema>(document: &Value, value: &impl Serialize) {
+        let schema = Components::default().reference::<T>();
+        let value = serde_json::to_value(value).unwrap();
+        if let Some(violation) = violation(document, &schema, &value, "$") {
+            panic!("{value} does not conform to its schema: {violation}");
+        }
+    }
+
+    /// Checks that `value` parses as `T` and conforms to its published schema.
+    fn assert_accepted<T: JsonSchema + serde::de::DeserializeOwned>(
+        document: &Value,
+        value: Value,
+    ) {
+        serde_json::from_value::<T>(value.clone()).unwrap();
+        assert_conforms::<T>(document, &value);
+    }
+
+    fn tensor(name: &str, datatype: &str, data: TensorData) -> MetadataTensor {
+        MetadataTensor {
+            name: name.to_string(),
+            shape: vec![2],
+            datatype: datatype.to_string(),
+            parameters: None,
+            data: Some(data),
+        }
+    }
+
+    #[test]
+    fn test_v2_bodies_conform_to_their_schemas() {
+        let document = document();
+        let request: InferenceRequest = serde_json::from_value(json!({
+            "id": "1",
+            "parameters": { "temperature": 0.5 },
+            "inputs": [{
+                "name": "x", "shape": [2, -1], "datatype": "INT64", "data": [1, 2],
+                "parameters": { "binary_data": false },
+            }],
+            "outputs": [{ "name": "y" }],
+            "capabilities": { "streaming": true, "modalities": ["image"] },
+        }))
+        .unwrap();
+        assert_conforms::<InferenceRequest>(&document, &request);
+        let response = InferenceResponse {
+            model_name: Some("m".to_string()),
+            model_version: Some("1".to_string()),
+            id: Some("1".to_string()),
+            parameters: None,
+            outputs: Some(vec![
+                tensor("scores", "FP32", TensorData::Float32(vec![0.5, 1.5])),
+                tensor(
+                    "labels",
+                    "BYTES",
+                    TensorData::String(vec!["a".into(), "b".into()]),
+                ),
+            ]),
+            debug: Some(json!({ "queued_ms": 1 })),
+        };
+        assert_conforms::<InferenceResponse>(&document, &response);
+        let error = ErrorInferenceResponse::from(
+            ApiError::invalid_argument("bad")
+                .with_reason("invalid_tensors")
+                .with_field_violation("inputs[x].shape[0]", "expected 3")
+                .with_retry_delay(Duration::from_millis(1500))
+                .with_request_id("r"),
+        );
+        assert_conforms::<ErrorInferenceResponse>(&document, &error);
+        let metadata = MetadataModelResponse {
+            name: "m".to_string(),
+            versions: Some(vec!["1".to_string()]),
+            platform: "onnx".to_string(),
+            inputs: vec![tensor("x", "FP32", TensorData::Float64(vec![1.0]))],
+            outputs: Vec::new(),
+            capabilities: Some(foundation::Capabilities::default()),
+        };
+        assert_conforms::<MetadataModelResponse>(&document, &metadata);
+        let entry = ModelListEntry {
+            name: "m".to_string(),
+            versions: vec!["1".to_string()],
+            backend: None,
+            ready: true,
+            labels: [("team".to_string(), "search".to_string())].into(),
+        };
+        assert_conforms::<ModelListEntry>(&document, &entry);
+        let server = ServerMetadataResponse {
+            name: "galemind".to_string(),
+            version: "1".to_string(),
+            extensions: Vec::new(),
+        };
+        assert_conforms::<ServerMetadataResponse>(&document, &server);
+    }
+
+    #[test]
+    fn test_openai_bodies_conform_to_their_schemas() {
+        let document = document();
+        assert_accepted::<CompletionRequest>(
+            &document,
+            json!({ "model": "m", "prompt": ["a", "b"], "max_tokens": 4, "stop": "\n" }),
+        );
+        assert_accepted::<ChatCompletionRequest>(
+            &document,
+            json!({
+                "model": "m",
+                "messages": [
+                    { "role": "user", "content": [
+                        { "type": "text", "text": "what is it?" },
+                        { "type": "image_url", "image_url": { "url": "data:image/png;base64,AA" } },
+                    ]},
+                    { "role": "tool", "content": "42", "tool_call_id": "call_0" },
+                ],
+                "temperature": 0.2,
+                "stop": ["a", "b"],
+                "tools": [{ "type": "function", "function": { "name": "f" } }],
+            }),
+        );
+        let usage = CompletionUsage {
+            prompt_tokens: 1,
+            completion_tokens: 2,
+            total_tokens: 3,
+        };
+        let completion = CompletionResponse {
+            id: "cmpl-1".to_string(),
+            object: "text_completion",
+            created: 0,
+            model: "m".to_string(),
+            choices: vec![CompletionChoice {
+                text: "a".to_string(),
+                index: 0,
+                logprobs: None,
+                finish_reason: "length".to_string(),
+            }],
+            usage,
+        };
+        assert_conforms::<CompletionResponse>(&document, &completion);
+        let chat = ChatCompletionResponse {
+            id: "chatcmpl-1".to_string(),
+            object: "chat.completion",
+            created: 0,
+            model: "m".to_string(),
+            choices: vec![ChatChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: "assistant".to_string(),
+                    content: None,
+                    name: None,
+                    tool_calls: Some(vec![ToolCall {
+                        id: "call_0".to_string(),
+                        kind: "function".to_string(),
+                        function: FunctionCall {
+                            name: "f".to_string(),
+                            arguments: "{}".to_string(),
+                        },
+                    }]),
+                    tool_call_id: None,
+                },
+                logprobs: Some(json!({ "content": [] })),
+                finish_reason: "tool_calls".to_string(),
+            }],
+            usage: CompletionUsage::default(),
+        };
+        assert_conforms::<ChatCompletionResponse>(&document, &chat);
+        let models = OpenAiModelList {
+            object: "list",
+            data: vec![OpenAiModel {
+                id: "m".to_string(),
+                object: "model",
+                created: 0,
+                owned_by: "galemind",
+                versions: vec!["1".to_string()],
+                backend: Some("onnx".to_string()),
+                ready: true,
+            }],
+        };
+        assert_conforms::<OpenAiModelList>(&document, &models);
+    }
+
+    #[test]
+    fn test_schemas_refuse_bodies_unlike_the_types() {
+        let document = document();
+        let schema = Components::default().reference::<InferenceRequest>();
+        for body in [
+            json!({ "inputs": [{ "name": "x", "shape": ["2"], "datatype": "FP32" }] }),
+            json!({ "inputs": [{ "name": "x", "shape": [2], "dataType": "FP32" }] }),
+            json!({ "id": "1" }),
+            json!({ "inputs": [], "request_id": "1" }),
+        ] {
+            assert!(
+                violation(&document, &schema, &body, "$").is_some(),
+                "{body} conforms"
+            );
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use foundation::{FeatureGates, OverloadController};
+
+use crate::data_model::Parameters;
+
+/// Response header set while the server is degrading optional features.
+const DEGRADED_HEADER: &str = "x-galemind-degraded";
+
+/// Counts every request towards global saturation while it is being handled.
+pub async fn track_load(
+    State(overload): State<Arc<OverloadController>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let _load = overload.begin();
+    let mut response = next.run(request).await;
+    if overload.is_degraded() {
+        response
+            .headers_mut()
+            .insert(DEGRADED_HEADER, HeaderValue::from_static("true"));
+    }
+    response
+}
+
+/// Removes parameters of disabled features and caps `max_tokens`.
+pub fn degrade_parameters(gates: &FeatureGates, parameters: &mut Parameters) {
+    parameters.retain(|name, _| gates.allows_parameter(name));
+    if let Some(max_tokens) = parameters.get_mut("max_tokens")
+        && let Some(requested) = max_tokens.as_u64()
+    {
+        *max_tokens = gates.cap_max_tokens(requested).into();
+    }
+}
@@ -0,0 +1,70 @@
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use foundation::{QuotaRefusal, QuotaUsage, Role, TENANT_HEADER, Target};
+
+use crate::auth::{authorization, refused as unauthorized};
+use crate::data_model::ErrorInferenceResponse;
+use crate::error::status_error;
+use crate::state::AppState;
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+pub fn refused(refusal: QuotaRefusal) -> InferenceError {
+    status_error(StatusCode::TOO_MANY_REQUESTS, refusal)
+}
+
+/// `response` with the `x-quota-*` headers of `usage`, if any.
+pub fn with_quota(usage: Option<QuotaUsage>, response: impl IntoResponse) -> Response {
+    let mut response = response.into_response();
+    for (name, value) in usage.map(|usage| usage.headers()).unwrap_or_default() {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+/// Account a request is charged to: the key or token subject `authenticated` it, or else
+/// the tenant a trusted proxy named. `None` for anonymous requests, which are not counted.
+pub fn account(authenticated: Option<String>, headers: &HeaderMap) -> Option<String> {
+    authenticated.or_else(|| {
+        headers
+            .get(TENANT_HEADER)
+            .and_then(|tenant| tenant.to_str().ok())
+            .map(str::to_string)
+    })
+}
+
+/// Usage and quota of the caller's account in the current window.
+async fn usage_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<QuotaUsage>, Response> {
+    let authenticated = match &state.authenticator {
+        Some(authenticator) => Some(
+            authenticator
+                .authorize(authorization(&headers), Role::ReadOnly, Target::Server)
+                .map_err(|error| unauthorized(error).into_response())?,
+        ),
+        None => None,
+    };
+    let Some(account) = account(authenticated, &headers) else {
+        return Err(status_error(
+            StatusCode::BAD_REQUEST,
+            "Usage is tracked per API key, token or tenant, send an authorization header",
+        )
+        .into_response());
+    };
+    Ok(Json(state.quotas.usage(&account)))
+}
+
+pub fn new_usage_router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(usage_handler))
+        .with_state(state)
+}
@@ -0,0 +1,71 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use foundation::{
+    ApiError, Authenticated, RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER,
+    RATE_LIMIT_RESET_HEADER, RateDecision, RateLimiter, client_key,
+};
+
+use crate::error::api_error;
+use crate::model::inference_model;
+
+fn insert_decision(headers: &mut HeaderMap, decision: &RateDecision) {
+    headers.insert(RATE_LIMIT_LIMIT_HEADER, HeaderValue::from(decision.limit));
+    headers.insert(
+        RATE_LIMIT_REMAINING_HEADER,
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        RATE_LIMIT_RESET_HEADER,
+        HeaderValue::from(decision.reset.as_secs_f64().ceil() as u64),
+    );
+}
+
+/// Applies the client and model rate limits to inference requests (POSTs to a model).
+pub async fn limit_rate(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.is_enabled() || request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(model) = inference_model(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let account = request.extensions().get::<Authenticated>();
+    let addr = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = client_key(account, addr);
+
+    match limiter.check(client.as_deref(), Some(model)) {
+        Ok(decision) => {
+            let mut response = next.run(request).await;
+            if let Some(decision) = decision {
+                insert_decision(response.headers_mut(), &decision);
+            }
+            response
+        }
+        Err(limited) => {
+            let mut response = api_error(
+                ApiError::resource_exhausted(&limited).with_retry_delay(limited.retry_after),
+            )
+            .into_response();
+            let headers = response.headers_mut();
+            insert_decision(headers, &limited.decision);
+            headers.insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(limited.retry_after.as_secs_f64().ceil() as u64),
+            );
+            response
+        }
+    }
+}
@@ -0,0 +1,124 @@
+/* gRPC calls on the REST listener of a shared port: `--port-routing request`.
+
+When `--port` is routed by request rather than by connection, the REST server
+takes every connection of the port and routes each request by its content
+type: those of `application/grpc` (gRPC) and `application/grpc-web`
+(gRPC-Web) go to the gRPC services, with their authentication, limits and
+CORS, and every other one to the REST API. gRPC-Web preflight requests, which
+ask to send `x-grpc-web`, go to the gRPC services too.
+
+Unlike routing by connection, this serves REST over HTTP/2 with prior
+knowledge and gRPC-Web over HTTP/1.1 on the shared port, and lets one
+connection carry calls of both APIs.
+*/
+
+use axum::{
+    Router,
+    extract::{Request, State},
+    http::{Method, header},
+    middleware::Next,
+    response::Response,
+};
+use tower::ServiceExt;
+
+/// Whether `request` is a gRPC or gRPC-Web call, or the preflight of one.
+fn is_grpc(request: &Request) -> bool {
+    let headers = request.headers();
+    if request.method() == Method::OPTIONS {
+        return headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|names| names.to_str().ok())
+            .is_some_and(|names| {
+                names
+                    .split(',')
+                    .any(|name| name.trim().eq_ignore_ascii_case("x-grpc-web"))
+            });
+    }
+    headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/grpc"))
+}
+
+/// Hands gRPC calls to the `grpc` services, and the other requests to the REST API.
+pub async fn route_grpc(State(grpc): State<Router>, request: Request, next: Next) -> Response {
+    if !is_grpc(&request) {
+        return next.run(request).await;
+    }
+    grpc.oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::any};
+
+    fn request(method: Method, headers: &[(header::HeaderName, &str)]) -> Request {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/inference.GRPCInferenceService/ServerLive");
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_grpc_calls_are_told_by_content_type() {
+        let grpc = |content_type| request(Method::POST, &[(header::CONTENT_TYPE, content_type)]);
+        assert!(is_grpc(&grpc("application/grpc")));
+        assert!(is_grpc(&grpc("application/grpc+proto")));
+        assert!(is_grpc(&grpc("application/grpc-web")));
+        assert!(is_grpc(&grpc("application/grpc-web-text")));
+        assert!(!is_grpc(&grpc("application/json")));
+        assert!(!is_grpc(&request(Method::POST, &[])));
+
+        let preflight = |names| {
+            request(
+                Method::OPTIONS,
+                &[(header::ACCESS_CONTROL_REQUEST_HEADERS, names)],
+            )
+        };
+        assert!(is_grpc(&preflight("content-type, X-Grpc-Web")));
+        assert!(!is_grpc(&preflight("content-type, x-api-key")));
+    }
+
+    #[tokio::test]
+    async fn test_requests_reach_the_api_of_their_content_type() {
+        let grpc = Router::new().fallback(any(|| async { "grpc" }));
+        let app = Router::new()
+            .fallback(any(|| async { "rest" }))
+            .layer(middleware::from_fn_with_state(grpc, route_grpc));
+        for (request, api) in [
+            (
+                request(Method::POST, &[(header::CONTENT_TYPE, "application/grpc")]),
+                "grpc",
+            ),
+            (
+                request(
+                    Method::POST,
+                    &[(header::CONTENT_TYPE, "application/grpc-web")],
+                ),
+                "grpc",
+            ),
+            (
+                request(
+                    Method::OPTIONS,
+                    &[(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-grpc-web")],
+                ),
+                "grpc",
+            ),
+            (
+                request(Method::POST, &[(header::CONTENT_TYPE, "application/json")]),
+                "rest",
+            ),
+        ] {
+            let response = app.clone().oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, api);
+        }
+    }
+}
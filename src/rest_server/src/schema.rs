@@ -0,0 +1,69 @@
+use axum::{
+    extract::Json,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use foundation::{
+    ModelDiscoveryService, ModelId, SCHEMA_VERSION_HEADER, SCHEMA_VERSION_PARAMETER, SchemaPlan,
+};
+use serde_json::Value;
+
+use crate::data_model::{ErrorInferenceResponse, InferenceRequest, InferenceResponse};
+use crate::error::status_error;
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+/// Negotiates the schema version of a request body and upgrades it to the model's
+/// current version. The version comes from the header, else from the parameter.
+pub fn negotiate_request(
+    model_manager: &ModelDiscoveryService,
+    model_name: &str,
+    headers: &HeaderMap,
+    mut body: Value,
+) -> Result<(SchemaPlan, InferenceRequest), InferenceError> {
+    let parameter = body
+        .get_mut("parameters")
+        .and_then(Value::as_object_mut)
+        .and_then(|parameters| parameters.remove(SCHEMA_VERSION_PARAMETER))
+        .map(|version| match version {
+            Value::String(version) => version,
+            other => other.to_string(),
+        });
+    let requested = headers
+        .get(SCHEMA_VERSION_HEADER)
+        .and_then(|version| version.to_str().ok())
+        .map(String::from)
+        .or(parameter);
+
+    let plan = model_manager
+        .negotiate_schema(&ModelId(model_name.to_string()), requested.as_deref())
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+    plan.upgrade_request(&mut body)
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))?;
+    let request = serde_json::from_value(body)
+        .map_err(|e| status_error(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    Ok((plan, request))
+}
+
+/// Converts a response back to the schema version spoken by the client.
+pub fn downgrade_response(
+    plan: &SchemaPlan,
+    response: InferenceResponse,
+) -> Result<InferenceResponse, InferenceError> {
+    if plan.is_identity() {
+        return Ok(response);
+    }
+    let mut value = serde_json::to_value(response)
+        .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    plan.downgrade_response(&mut value)
+        .map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    serde_json::from_value(value).map_err(|e| status_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Adds the negotiated schema version to a response, if the model is versioned.
+pub fn with_schema_version(plan: &SchemaPlan, response: impl IntoResponse) -> Response {
+    match &plan.client_version {
+        Some(version) => ([(SCHEMA_VERSION_HEADER, version.clone())], response).into_response(),
+        None => response.into_response(),
+    }
+}
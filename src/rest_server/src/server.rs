@@ -9,7 +9,12 @@ async fn server_metadata(
 ) -> Result<Json<ServerMetadataResponse>, Json<ErrorServerMetadataResponse>> {
     let now = SystemTime::now();
 
-    if now.duration_since(UNIX_EPOCH).unwrap().as_secs() % 2 == 0 {
+    if now
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .is_multiple_of(2)
+    {
         Ok(Json(ServerMetadataResponse {
             name: "test".to_string(),
             version: "v2".to_string(),
@@ -0,0 +1,144 @@
+/* Shared memory extension of the KServe V2 REST protocol.
+
+```text
+GET  /v2/systemsharedmemory[/region/{name}]/status
+POST /v2/systemsharedmemory/region/{name}/register    {"key": "/input", "offset": 0, "byte_size": 64}
+POST /v2/systemsharedmemory[/region/{name}]/unregister
+```
+
+Registered regions are shared with the gRPC server. CUDA shared memory is not
+supported and `/v2/cudasharedmemory` is not served, see
+`foundation::api::shared_memory`.
+*/
+
+use std::collections::HashMap;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use foundation::{
+    SharedMemoryRef, SharedMemoryRegistry, SystemRegionStatus, api::inference::InferParameter,
+};
+use serde::Deserialize;
+
+use crate::binary::raw_bytes;
+use crate::data_model::{ErrorInferenceResponse, MetadataTensor, Parameters, TensorRequestOutput};
+use crate::error::status_error;
+use crate::state::AppState;
+use crate::translator::{domain_parameter, json_parameter};
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+#[derive(Deserialize)]
+struct SystemRegistration {
+    key: String,
+    #[serde(default)]
+    offset: u64,
+    byte_size: u64,
+}
+
+fn region(params: &HashMap<String, String>) -> Option<&str> {
+    params.get("name").map(String::as_str)
+}
+
+/// Where the requested `outputs` are to be written, by output name.
+pub fn output_locations(
+    outputs: Option<&[TensorRequestOutput]>,
+) -> Result<HashMap<String, SharedMemoryRef>, InferenceError> {
+    let mut locations = HashMap::new();
+    for output in outputs.into_iter().flatten() {
+        let mut parameters: HashMap<String, InferParameter> = output
+            .parameters
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, domain_parameter(v)))
+            .collect();
+        let location = SharedMemoryRef::take(&mut parameters).map_err(|e| {
+            status_error(
+                StatusCode::BAD_REQUEST,
+                format!("Output '{}': {}", output.name, e),
+            )
+        })?;
+        if let Some(location) = location {
+            locations.insert(output.name.clone(), location);
+        }
+    }
+    Ok(locations)
+}
+
+/// Writes the data of `output` to `location`, leaving it the shared memory parameters
+/// instead.
+pub fn place_output(
+    shared_memory: &SharedMemoryRegistry,
+    location: &SharedMemoryRef,
+    output: &mut MetadataTensor,
+) -> Result<(), InferenceError> {
+    let Some(data) = output.data.take() else {
+        return Ok(());
+    };
+    raw_bytes(&data, &output.datatype)
+        .and_then(|bytes| shared_memory.write(location, &bytes))
+        .map_err(|e| {
+            status_error(
+                StatusCode::BAD_REQUEST,
+                format!("Output '{}': {}", output.name, e),
+            )
+        })?;
+    output.parameters.get_or_insert_default().extend(
+        location
+            .parameters()
+            .into_iter()
+            .map(|(name, value)| (name, json_parameter(value))),
+    );
+    Ok(())
+}
+
+async fn system_status_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<Vec<SystemRegionStatus>>, InferenceError> {
+    state
+        .shared_memory
+        .system_status(region(&params))
+        .map(Json)
+        .map_err(|e| status_error(StatusCode::NOT_FOUND, e))
+}
+
+async fn system_register_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    Json(registration): Json<SystemRegistration>,
+) -> Result<Json<Parameters>, InferenceError> {
+    state
+        .shared_memory
+        .register_system(
+            region(&params).unwrap_or_default(),
+            &registration.key,
+            registration.offset,
+            registration.byte_size,
+        )
+        .map(|_| Json(Parameters::new()))
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e))
+}
+
+async fn system_unregister_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Json<Parameters> {
+    state.shared_memory.unregister_system(region(&params));
+    Json(Parameters::new())
+}
+
+pub fn new_system_shared_memory_router(state: AppState) -> Router {
+    Router::new()
+        .route("/status", get(system_status_handler))
+        .route("/region/{name}/status", get(system_status_handler))
+        .route("/region/{name}/register", post(system_register_handler))
+        .route("/unregister", post(system_unregister_handler))
+        .route("/region/{name}/unregister", post(system_unregister_handler))
+        .with_state(state)
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::FromRef;
+use foundation::{
+    AnalyticsTee, Authenticator, BatchJobs, ConcurrencyLimiter, ConfigReload, IdProvider,
+    ModelDiscoveryService, OverloadController, QuotaTracker, ResultBackend, ResultStore,
+    SharedMemoryRegistry, StreamPacing, StreamStore, TrafficAccounting,
+};
+
+use crate::data_model::{ErrorInferenceResponse, InferenceResponse};
+
+/// How long results of asynchronous inferences remain retrievable after completion.
+pub const ASYNC_RESULT_TTL: Duration = Duration::from_secs(600);
+
+/// How long finished inference streams remain resumable.
+const STREAM_TTL: Duration = Duration::from_secs(60);
+
+/// Outcome of an asynchronous inference, as returned when its result is fetched.
+pub type AsyncResult = Result<InferenceResponse, ErrorInferenceResponse>;
+
+/// State shared by the REST routers.
+#[derive(Clone)]
+pub struct AppState {
+    pub model_manager: Arc<ModelDiscoveryService>,
+    pub async_results: Arc<ResultStore<AsyncResult>>,
+    /// Where results of asynchronous inferences are shared with the other replicas, if set.
+    pub result_backend: Option<Arc<dyn ResultBackend>>,
+    /// Results of inference streams, one chunk per request, kept for resumption.
+    pub streams: Arc<StreamStore<AsyncResult>>,
+    pub overload: Arc<OverloadController>,
+    pub ids: Arc<dyn IdProvider>,
+    /// Sheds inferences beyond the concurrency limits.
+    pub concurrency: Arc<ConcurrencyLimiter>,
+    /// Bytes exchanged per tenant, reported by the admin API.
+    pub traffic: Arc<TrafficAccounting>,
+    /// Requests and tokens charged to each account.
+    pub quotas: Arc<QuotaTracker>,
+    /// Shared memory regions inputs are read from and outputs written to.
+    pub shared_memory: Arc<SharedMemoryRegistry>,
+    /// Checks the model an inference resolves to, when authentication is on.
+    pub authenticator: Option<Authenticator>,
+    /// Reloads the configuration on request of the admin API, when set.
+    pub config_reload: Option<Arc<ConfigReload>>,
+    /// Batch prediction jobs, when the server has a directory for them.
+    pub batch_jobs: Option<Arc<BatchJobs>>,
+    /// When set, results sent on inference streams and WebSockets are teed to it.
+    pub analytics: Option<AnalyticsTee>,
+}
+
+impl AppState {
+    pub fn new(
+        model_manager: Arc<ModelDiscoveryService>,
+        overload: Arc<OverloadController>,
+        ids: Arc<dyn IdProvider>,
+    ) -> Self {
+        Self {
+            model_manager,
+            async_results: Arc::new(
+                ResultStore::new(ASYNC_RESULT_TTL).with_id_provider(ids.clone()),
+            ),
+            result_backend: None,
+            streams: Arc::new(StreamStore::new(STREAM_TTL).with_id_provider(ids.clone())),
+            overload,
+            ids,
+            concurrency: Arc::new(ConcurrencyLimiter::default()),
+            traffic: Arc::new(TrafficAccounting::default()),
+            quotas: Arc::new(QuotaTracker::default()),
+            shared_memory: Arc::new(SharedMemoryRegistry::default()),
+            authenticator: None,
+            config_reload: None,
+            batch_jobs: None,
+            analytics: None,
+        }
+    }
+
+    pub fn with_concurrency(mut self, concurrency: Arc<ConcurrencyLimiter>) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    pub fn with_traffic(mut self, traffic: Arc<TrafficAccounting>) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    /// Paces inference streams by their readers, see `StreamPacing`.
+    pub fn with_stream_pacing(mut self, pacing: StreamPacing) -> Self {
+        self.streams = Arc::new(
+            StreamStore::new(STREAM_TTL)
+                .with_id_provider(self.ids.clone())
+                .with_pacing(pacing),
+        );
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: Arc<QuotaTracker>) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Shares the shared memory regions with the other servers.
+    pub fn with_shared_memory(mut self, shared_memory: Arc<SharedMemoryRegistry>) -> Self {
+        self.shared_memory = shared_memory;
+        self
+    }
+
+    pub fn with_authenticator(mut self, authenticator: Option<Authenticator>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
+    pub fn with_config_reload(mut self, config_reload: Option<Arc<ConfigReload>>) -> Self {
+        self.config_reload = config_reload;
+        self
+    }
+
+    pub fn with_result_backend(mut self, result_backend: Option<Arc<dyn ResultBackend>>) -> Self {
+        self.result_backend = result_backend;
+        self
+    }
+
+    pub fn with_batch_jobs(mut self, batch_jobs: Option<Arc<BatchJobs>>) -> Self {
+        self.batch_jobs = batch_jobs;
+        self
+    }
+
+    /// Tees every result sent on `infer_stream` streams and WebSockets to `analytics`.
+    pub fn with_analytics(mut self, analytics: Option<AnalyticsTee>) -> Self {
+        self.analytics = analytics;
+        self
+    }
+}
+
+impl FromRef<AppState> for Arc<ModelDiscoveryService> {
+    fn from_ref(state: &AppState) -> Self {
+        state.model_manager.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<ResultStore<AsyncResult>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.async_results.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<StreamStore<AsyncResult>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.streams.clone()
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::get,
+};
+use foundation::{AnalyticsRecord, AnalyticsTee, StreamStore};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::data_model::{ErrorInferenceResponse, InferenceResponse, StreamToken};
+use crate::error::status_error;
+use crate::state::AppState;
+
+/// Header with which browsers (and other SSE clients) resume after the last event they received.
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+type Streams = Arc<StreamStore<crate::state::AsyncResult>>;
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+/// Sends the chunks of stream `token` after the chunk `after` as server-sent events: a
+/// `stream` event with the token when `announce` is set, one `result` or `error` event per
+/// chunk with the chunk id as event id, and an `end` event once the stream is finished.
+/// Chunks are acknowledged once the response body takes them, which paces the producer
+/// of the stream by the client. Results are teed to `analytics` the first time they are
+/// sent, not again to a resuming client.
+pub fn follow(
+    streams: Streams,
+    analytics: Option<AnalyticsTee>,
+    token: String,
+    after: Option<u64>,
+    announce: Option<StreamToken>,
+) -> Response {
+    let (events, receiver) = mpsc::channel(16);
+    tokio::spawn(async move {
+        if let Some(announce) = announce
+            && events
+                .send(Ok(json_event("stream", &announce)))
+                .await
+                .is_err()
+        {
+            return;
+        }
+        let Some(mut updates) = streams.subscribe(&token) else {
+            return;
+        };
+        let mut after = after;
+        loop {
+            updates.borrow_and_update();
+            let Some(read) = streams.read_after(&token, after) else {
+                break;
+            };
+            for (id, chunk) in read.chunks {
+                let event = match &chunk {
+                    Ok(response) => json_event("result", response),
+                    Err(error) => json_event("error", error),
+                };
+                // The client went away; it may resume later.
+                if events.send(Ok(event.id(id.to_string()))).await.is_err() {
+                    return;
+                }
+                let first = streams.acknowledge(&token, id);
+                if let (Some(analytics), Ok(response), true) = (&analytics, &chunk, first) {
+                    analytics.tee(analytics_record(response, id));
+                }
+                after = Some(id);
+            }
+            if read.finished {
+                let _ = events
+                    .send(Ok(Event::default().event("end").data("")))
+                    .await;
+                break;
+            }
+            if updates.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+    let events: ReceiverStream<Result<Event, Infallible>> = ReceiverStream::new(receiver);
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Record of a streamed `response` for the analytics sink: its outputs, without their data.
+pub fn analytics_record(response: &InferenceResponse, sequence: u64) -> AnalyticsRecord {
+    let outputs: Vec<Value> = response
+        .outputs
+        .iter()
+        .flatten()
+        .map(|output| {
+            serde_json::json!({
+                "name": output.name,
+                "datatype": output.datatype,
+                "shape": output.shape,
+            })
+        })
+        .collect();
+    AnalyticsRecord::new(
+        response.model_name.clone().unwrap_or_default(),
+        response.model_version.clone(),
+        response.id.clone().unwrap_or_default(),
+        sequence,
+        serde_json::json!({ "outputs": outputs }),
+    )
+}
+
+fn json_event<T: Serialize>(name: &str, data: &T) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()))
+}
+
+/// Replays the chunks a client missed after the `Last-Event-ID` it sends, then follows the
+/// stream until it finishes.
+async fn resume_stream_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, InferenceError> {
+    let token = params.get("token").cloned().unwrap_or_default();
+    let after = match headers.get(LAST_EVENT_ID_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or_else(|| {
+                    status_error(
+                        StatusCode::BAD_REQUEST,
+                        "Invalid Last-Event-ID, expected a chunk id".to_string(),
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    if state.streams.read_after(&token, None).is_none() {
+        return Err(status_error(
+            StatusCode::NOT_FOUND,
+            format!("Stream '{}' not found or expired", token),
+        ));
+    }
+    Ok(follow(
+        state.streams.clone(),
+        state.analytics.clone(),
+        token,
+        after,
+        None,
+    ))
+}
+
+pub fn new_stream_router(state: AppState) -> Router {
+    Router::new()
+        .route("/{token}", get(resume_stream_handler))
+        .with_state(state)
+}
@@ -0,0 +1,306 @@
+use std::convert::Infallible;
+
+use axum::{
+    Json,
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde_json::{Map, Value};
+
+use crate::data_model::{ErrorInferenceResponse, InferenceResponse, MetadataTensor, TensorData};
+use crate::error::status_error;
+
+/// Row-oriented representations of inference outputs, negotiated with `Accept`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TabularFormat {
+    Csv,
+    Ndjson,
+}
+
+impl TabularFormat {
+    /// The tabular format the client prefers, or None when it takes JSON. Media ranges
+    /// are considered in the order listed; quality values are ignored.
+    pub fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+        accept
+            .split(',')
+            .map(|range| {
+                range
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .find_map(|range| match range.as_str() {
+                "text/csv" => Some(Some(TabularFormat::Csv)),
+                "application/x-ndjson" | "application/jsonl" => Some(Some(TabularFormat::Ndjson)),
+                "application/json" | "*/*" => Some(None),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            TabularFormat::Csv => "text/csv; charset=utf-8",
+            TabularFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// Outputs laid out as columns sharing their first dimension as rows. An output of shape
+/// `[rows]` is one column named after it; `[rows, n, ..]` gives `n × ..` columns named
+/// `<output>_<index>`. Scalars are a single row.
+struct Table {
+    columns: Vec<String>,
+    /// Per output: its data and number of columns.
+    outputs: Vec<(TensorData, usize)>,
+    rows: usize,
+}
+
+impl Table {
+    fn new(outputs: Vec<MetadataTensor>) -> Result<Self, String> {
+        let mut table = Table {
+            columns: Vec::new(),
+            outputs: Vec::new(),
+            rows: 0,
+        };
+        for (index, output) in outputs.into_iter().enumerate() {
+            // Variable (negative) dimensions never hold values.
+            let dims: Vec<usize> = output
+                .shape
+                .iter()
+                .map(|dim| usize::try_from(*dim).unwrap_or(0))
+                .collect();
+            let rows = dims.first().copied().unwrap_or(1);
+            let width: usize = dims.iter().skip(1).product();
+            let data = output.data.unwrap_or(TensorData::Float64(Vec::new()));
+            if index == 0 {
+                table.rows = rows;
+            } else if rows != table.rows {
+                return Err(format!(
+                    "Output '{}' has {} rows where the previous outputs have {}",
+                    output.name, rows, table.rows
+                ));
+            }
+            if len(&data) != rows * width {
+                return Err(format!(
+                    "Output '{}' holds {} values, not the {} of its shape {:?}",
+                    output.name,
+                    len(&data),
+                    rows * width,
+                    output.shape
+                ));
+            }
+            if output.shape.len() <= 1 {
+                table.columns.push(output.name);
+            } else {
+                table
+                    .columns
+                    .extend((0..width).map(|column| format!("{}_{}", output.name, column)));
+            }
+            table.outputs.push((data, width));
+        }
+        Ok(table)
+    }
+
+    fn row(&self, row: usize) -> impl Iterator<Item = Value> + '_ {
+        self.outputs.iter().flat_map(move |(data, width)| {
+            (row * width..(row + 1) * width).map(move |index| cell(data, index))
+        })
+    }
+
+    fn csv_line(&self, row: usize) -> String {
+        let mut line = self
+            .row(row)
+            .map(|value| match value {
+                Value::Null => String::new(),
+                Value::String(field) => csv_field(&field),
+                value => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        line.push('\n');
+        line
+    }
+
+    fn ndjson_line(&self, row: usize) -> String {
+        let object: Map<String, Value> = self.columns.iter().cloned().zip(self.row(row)).collect();
+        let mut line = Value::Object(object).to_string();
+        line.push('\n');
+        line
+    }
+}
+
+fn len(data: &TensorData) -> usize {
+    match data {
+        TensorData::Int32(values) => values.len(),
+        TensorData::Int64(values) => values.len(),
+        TensorData::Float32(values) => values.len(),
+        TensorData::Float64(values) => values.len(),
+        TensorData::Bool(values) => values.len(),
+        TensorData::UInt64(values) => values.len(),
+        TensorData::String(values) => values.len(),
+    }
+}
+
+/// JSON value of one element; non-finite floats have none and become null.
+fn cell(data: &TensorData, index: usize) -> Value {
+    match data {
+        TensorData::Int32(values) => values[index].into(),
+        TensorData::Int64(values) => values[index].into(),
+        TensorData::Float32(values) => values[index].into(),
+        TensorData::Float64(values) => values[index].into(),
+        TensorData::Bool(values) => values[index].into(),
+        TensorData::UInt64(values) => values[index].into(),
+        TensorData::String(values) => values[index].clone().into(),
+    }
+}
+
+/// Quotes a CSV field when it holds a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The outputs of `response` as CSV (with a header line) or NDJSON, one line per row. Lines
+/// are written to the body as it is sent rather than rendered upfront.
+pub fn tabular_response(
+    format: TabularFormat,
+    response: InferenceResponse,
+) -> Result<Response, (StatusCode, Json<ErrorInferenceResponse>)> {
+    let table = Table::new(response.outputs.unwrap_or_default()).map_err(|error| {
+        status_error(
+            StatusCode::NOT_ACCEPTABLE,
+            format!("Outputs cannot be returned as rows: {}", error),
+        )
+    })?;
+    let heading = match format {
+        TabularFormat::Csv => {
+            let mut heading = table
+                .columns
+                .iter()
+                .map(|column| csv_field(column))
+                .collect::<Vec<_>>()
+                .join(",");
+            heading.push('\n');
+            Some(heading)
+        }
+        TabularFormat::Ndjson => None,
+    };
+    let rows = table.rows;
+    let lines = heading
+        .into_iter()
+        .chain((0..rows).map(move |row| match format {
+            TabularFormat::Csv => table.csv_line(row),
+            TabularFormat::Ndjson => table.ndjson_line(row),
+        }))
+        .map(Ok::<_, Infallible>);
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        )],
+        Body::from_stream(tokio_stream::iter(lines)),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::new_model_router;
+    use crate::testing::{output, send, state_with};
+    use axum::http::Request;
+    use foundation::api::inference::{InferenceOutput, InferenceResponse as Answered};
+    use foundation::api::tensor::Data;
+    use serde_json::json;
+
+    fn accepting(accept: &str) -> HeaderMap {
+        HeaderMap::from_iter([(header::ACCEPT, HeaderValue::from_str(accept).unwrap())])
+    }
+
+    /// The answer of a model answering `answer` to an inference taking `accept`.
+    async fn infer(answer: Answered, accept: &str) -> (StatusCode, HeaderMap, String) {
+        let router = new_model_router(state_with("m", move |_| answer.clone()));
+        let body = json!({
+            "inputs": [{ "name": "x", "shape": [1], "datatype": "FP64", "data": [1.0] }],
+        });
+        let request = Request::post("/m/infer")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, accept)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, headers, body) = send(router, request).await;
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// An output of shape `shape` holding `data`.
+    fn shaped(shape: Vec<usize>, data: Data) -> Answered {
+        Answered::Ok(InferenceOutput {
+            name: "y".to_string(),
+            shape,
+            datatype: data.datatype(),
+            parameters: Some(Default::default()),
+            data,
+        })
+    }
+
+    #[test]
+    fn test_formats_are_negotiated_in_the_order_listed() {
+        let negotiated = |accept| TabularFormat::from_accept(&accepting(accept));
+        assert_eq!(negotiated("text/csv"), Some(TabularFormat::Csv));
+        assert_eq!(
+            negotiated("text/html, Application/JSONL;q=0.5"),
+            Some(TabularFormat::Ndjson)
+        );
+        assert_eq!(negotiated("application/json, text/csv"), None);
+        assert_eq!(negotiated("*/*"), None);
+        assert_eq!(negotiated("image/png"), None);
+        assert_eq!(TabularFormat::from_accept(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_outputs_are_sent_as_csv_rows() {
+        let (status, headers, body) = infer(
+            shaped(vec![2, 2], Data::VFLOAT(vec![0.5, 1.0, 1.5, 2.0])),
+            "text/csv",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(body, "y_0,y_1\n0.5,1.0\n1.5,2.0\n");
+
+        let labels = Data::VSTRING(vec!["a,b".to_string(), "say \"hi\"".to_string()]);
+        let (_, _, body) = infer(output("label", labels, &[]), "text/csv").await;
+        assert_eq!(body, "label\n\"a,b\"\n\"say \"\"hi\"\"\"\n");
+    }
+
+    #[tokio::test]
+    async fn test_outputs_are_sent_as_ndjson_rows() {
+        let (status, headers, body) = infer(
+            output("y", Data::VFLOAT(vec![0.5, 1.5]), &[]),
+            "application/x-ndjson",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/x-ndjson");
+        assert_eq!(body, "{\"y\":0.5}\n{\"y\":1.5}\n");
+    }
+
+    #[tokio::test]
+    async fn test_outputs_unlike_their_shape_are_not_acceptable() {
+        let (status, _, body) = infer(
+            shaped(vec![2, 2], Data::VFLOAT(vec![0.5, 1.0, 1.5])),
+            "text/csv",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert!(body.contains("cannot be returned as rows"));
+    }
+}
@@ -0,0 +1,101 @@
+/* Helpers of the handler tests.
+
+A state serving models whose answers the tests write, and requests sent
+through a router with their responses read back as JSON.
+*/
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{HeaderMap, Request, StatusCode, header},
+};
+use foundation::api::inference::{InferParameter, InferenceOutput, InferenceProcessor};
+use foundation::api::tensor::Data;
+use foundation::{
+    IdScheme, InferenceRequest, InferenceResponse, ModelDiscoveryService, ModelVersionId,
+    OverloadController, ProcessorRuntime,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::state::AppState;
+
+/// Answers each request with what its function makes of it.
+pub struct Answer<F>(pub F);
+
+impl<F: Fn(InferenceRequest) -> InferenceResponse> InferenceProcessor for Answer<F> {
+    fn process(&self, request: InferenceRequest) -> InferenceResponse {
+        (self.0)(request)
+    }
+}
+
+/// A state serving version 1 of `model`, answered by `answer`.
+pub fn state_with(
+    model: &str,
+    answer: impl Fn(InferenceRequest) -> InferenceResponse + Send + Sync + 'static,
+) -> AppState {
+    let model_manager = ModelDiscoveryService::new(10);
+    model_manager.register_model_version(
+        ModelVersionId::new(model, "1"),
+        Arc::new(ProcessorRuntime::new(model, Answer(answer))),
+    );
+    AppState::new(
+        Arc::new(model_manager),
+        Arc::new(OverloadController::default()),
+        IdScheme::default().provider(),
+    )
+}
+
+/// An output named `name` holding `data`, with `parameters`.
+pub fn output(name: &str, data: Data, parameters: &[(&str, InferParameter)]) -> InferenceResponse {
+    InferenceResponse::Ok(InferenceOutput {
+        name: name.to_string(),
+        shape: vec![data.len()],
+        datatype: data.datatype(),
+        parameters: Some(
+            parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect::<HashMap<_, _>>(),
+        ),
+        data,
+    })
+}
+
+/// String parameter `name` of `request`.
+pub fn parameter<'a>(request: &'a InferenceRequest, name: &str) -> Option<&'a str> {
+    match request.parameters.as_ref()?.get(name)? {
+        InferParameter::String(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Status, headers and body of the answer of `router` to `request`.
+pub async fn send(router: Router, request: Request<Body>) -> (StatusCode, HeaderMap, Bytes) {
+    let response = router.oneshot(request).await.unwrap();
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    (parts.status, parts.headers, body)
+}
+
+/// Status and JSON body of the answer of `router` to `request`; null when the body is not
+/// JSON.
+pub async fn send_json(router: Router, request: Request<Body>) -> (StatusCode, Value) {
+    let (status, _, body) = send(router, request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+pub fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+/// A POST of `body` as JSON.
+pub fn post_json(uri: &str, body: &Value) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use foundation::{TENANT_HEADER, TrafficAccounting, TrafficRefusal};
+use tokio_stream::StreamExt;
+
+use crate::error::status_error;
+use crate::model::inference_model;
+
+fn refused(refusal: TrafficRefusal) -> Response {
+    let status = match refusal {
+        TrafficRefusal::RequestTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        TrafficRefusal::DailyLimit { .. } => StatusCode::TOO_MANY_REQUESTS,
+    };
+    status_error(status, refusal).into_response()
+}
+
+/// `body`, calling `count` with the size of every chunk as it goes through.
+pub(crate) fn counted(body: Body, count: impl Fn(u64) + Send + Sync + 'static) -> Body {
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            count(chunk.len() as u64);
+        }
+        chunk
+    }))
+}
+
+/// Enforces the request size and daily byte limits on inference requests (POSTs to a
+/// model) and counts the bytes they exchange per tenant, anonymous requests under the
+/// default one. The tenant is the one `auth::identify_tenant` left in the header. Bodies are read up to the size
+/// limit before any handler parses them.
+pub async fn account_traffic(
+    State(traffic): State<Arc<TrafficAccounting>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST || inference_model(request.uri().path()).is_none() {
+        return next.run(request).await;
+    }
+    let tenant = request
+        .headers()
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok())
+        .map(str::to_string);
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    if let Err(refusal) = traffic.admit(tenant.as_deref(), declared) {
+        return refused(refusal);
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = match traffic.limits().max_request_bytes {
+        // Bodies without a length, or lying about it, stop being read past the limit.
+        Some(limit) => match to_bytes(body, usize::try_from(limit).unwrap_or(usize::MAX)).await {
+            Ok(bytes) => {
+                traffic.record_in(tenant.as_deref(), bytes.len() as u64);
+                Body::from(bytes)
+            }
+            Err(_) => return refused(TrafficRefusal::RequestTooLarge { limit }),
+        },
+        None => {
+            let (traffic, tenant) = (traffic.clone(), tenant.clone());
+            counted(body, move |bytes| {
+                traffic.record_in(tenant.as_deref(), bytes)
+            })
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let (parts, body) = response.into_parts();
+    let body = counted(body, move |bytes| {
+        traffic.record_out(tenant.as_deref(), bytes)
+    });
+    Response::from_parts(parts, body)
+}
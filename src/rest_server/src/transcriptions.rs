@@ -0,0 +1,362 @@
+/* OpenAI compatible speech to text: `POST /v1/audio/transcriptions`.
+
+The request is a multipart form, as OpenAI's: the audio `file`, the `model`,
+and optionally `language`, `prompt`, `temperature` and `response_format`. It
+becomes an inference of the model, admitted and accounted as any other (see
+`openai`). The file is passed as the `audio` BYTES input, a single element
+holding the file as uploaded, and relies on the `audio` modality. The model
+gets the `filename` parameter, from which it may tell the format, and the
+`language`, `prompt` and `temperature` fields given.
+
+A Whisper-style runtime answers with a BYTES output holding the transcript,
+and these output parameters:
+
+```yaml
+language: english   # detected or given language
+duration: 12.4      # seconds of audio
+segments: '[{"id": 0, "start": 0.0, "end": 4.2, "text": " Hello."}]'
+```
+
+`response_format` is `json` (`{"text"}`, the default), `text` (the transcript
+as plain text) or `verbose_json`, which adds the language, duration and
+segments. Segments are a JSON list, or a string holding one.
+
+Audio files are accepted up to 25 MiB, as OpenAI does, above the default body
+limit of the server; `max_request_bytes` still applies.
+*/
+
+use std::collections::HashMap;
+
+use axum::{
+    Router,
+    extract::{DefaultBodyLimit, Json, Multipart, State, multipart::MultipartRejection},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::correlation::with_correlation_id;
+use crate::openai::{Media, OpenAiError, generate};
+use crate::quota::with_quota;
+use crate::state::AppState;
+
+/// Largest audio file accepted, as OpenAI does.
+pub const MAX_AUDIO_BYTES: usize = 25 * 1024 * 1024;
+const AUDIO_INPUT: &str = "audio";
+const RESPONSE_FORMATS: &[&str] = &["json", "text", "verbose_json"];
+
+/// A stretch of the transcript with its time in the audio.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub id: u32,
+    /// Seconds from the start of the audio.
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_logprob: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_speech_prob: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerboseTranscription {
+    pub task: &'static str,
+    pub language: Option<String>,
+    pub duration: Option<f64>,
+    pub text: String,
+    pub segments: Vec<Segment>,
+}
+
+/// Fields of a transcription form.
+#[derive(Debug, Default)]
+struct TranscriptionForm {
+    file: Option<(String, Vec<u8>)>,
+    model: Option<String>,
+    fields: HashMap<String, String>,
+}
+
+async fn read_form(
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<TranscriptionForm, OpenAiError> {
+    let mut multipart = multipart.map_err(|e| OpenAiError::invalid_request(e.body_text()))?;
+    let mut form = TranscriptionForm::default();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| OpenAiError::invalid_request(e.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            let filename = field.file_name().unwrap_or("audio").to_string();
+            let bytes = field
+                .bytes()
+                .await
+                .map_err(|e| OpenAiError::invalid_request(e.body_text()))?;
+            form.file = Some((filename, bytes.to_vec()));
+            continue;
+        }
+        let value = field
+            .text()
+            .await
+            .map_err(|e| OpenAiError::invalid_request(e.body_text()))?;
+        if name == "model" {
+            form.model = Some(value);
+        } else {
+            form.fields.insert(name, value);
+        }
+    }
+    Ok(form)
+}
+
+/// Segments of the `segments` output parameter, a JSON list or its JSON string.
+fn segments(model_name: &str, value: Option<&Value>) -> Result<Vec<Segment>, OpenAiError> {
+    let segments = match value {
+        None => Some(Vec::new()),
+        Some(Value::String(segments)) => serde_json::from_str(segments).ok(),
+        Some(segments) => serde_json::from_value(segments.clone()).ok(),
+    };
+    segments.ok_or_else(|| OpenAiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        message: format!("Model '{}' returned malformed segments", model_name),
+        code: None,
+        retry_after: None,
+    })
+}
+
+async fn transcriptions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<Response, OpenAiError> {
+    let form = read_form(multipart).await?;
+    let Some(model) = form.model else {
+        return Err(OpenAiError::invalid_request("model is required"));
+    };
+    let Some((filename, audio)) = form.file else {
+        return Err(OpenAiError::invalid_request("file is required"));
+    };
+    if audio.is_empty() {
+        return Err(OpenAiError::invalid_request("file must not be empty"));
+    }
+    if audio.len() > MAX_AUDIO_BYTES {
+        return Err(OpenAiError {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            message: format!(
+                "The audio file has {} bytes, at most {} are accepted",
+                audio.len(),
+                MAX_AUDIO_BYTES
+            ),
+            code: None,
+            retry_after: None,
+        });
+    }
+    let format = form
+        .fields
+        .get("response_format")
+        .map(String::as_str)
+        .unwrap_or("json");
+    if !RESPONSE_FORMATS.contains(&format) {
+        return Err(OpenAiError::invalid_request(format!(
+            "response_format must be one of {}",
+            RESPONSE_FORMATS.join(", ")
+        )));
+    }
+
+    let mut parameters = json!({ "filename": filename });
+    for name in ["language", "prompt"] {
+        if let Some(value) = form.fields.get(name) {
+            parameters[name] = value.clone().into();
+        }
+    }
+    if let Some(temperature) = form.fields.get("temperature") {
+        let temperature: f64 = temperature
+            .parse()
+            .map_err(|_| OpenAiError::invalid_request("temperature must be a number"))?;
+        parameters["temperature"] = temperature.into();
+    }
+    let generation = generate(
+        &state,
+        &headers,
+        &model,
+        "rest.transcriptions",
+        parameters,
+        Some(Media {
+            input: AUDIO_INPUT,
+            modalities: &["audio"],
+            files: vec![audio],
+        }),
+    )
+    .await?;
+    let text = generation.texts.into_iter().next().unwrap_or_default();
+
+    let response = match format {
+        "text" => text.into_response(),
+        "json" => Json(json!({ "text": text })).into_response(),
+        _ => Json(VerboseTranscription {
+            task: "transcribe",
+            language: generation
+                .parameters
+                .get("language")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .or_else(|| form.fields.get("language").cloned()),
+            duration: generation
+                .parameters
+                .get("duration")
+                .and_then(Value::as_f64),
+            text,
+            segments: segments(&model, generation.parameters.get("segments"))?,
+        })
+        .into_response(),
+    };
+    Ok(with_correlation_id(
+        generation.correlation_id,
+        with_quota(generation.quota, response),
+    ))
+}
+
+/// Room for the other fields of the form besides the audio file.
+const FORM_OVERHEAD_BYTES: usize = 64 * 1024;
+
+pub fn new_transcription_router(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/v1/audio/transcriptions",
+            post(transcriptions_handler)
+                .layer(DefaultBodyLimit::max(MAX_AUDIO_BYTES + FORM_OVERHEAD_BYTES)),
+        )
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{output, parameter, send, send_json, state_with};
+    use axum::body::Body;
+    use axum::http::{Request, header};
+    use foundation::api::inference::InferParameter;
+    use foundation::api::tensor::Data;
+
+    const BOUNDARY: &str = "galemind-boundary";
+
+    /// A router serving model `whisper`, which transcribes the `audio` input it gets.
+    fn transcribing() -> Router {
+        new_transcription_router(state_with("whisper", |request| {
+            let audio = request
+                .outputs
+                .iter()
+                .flatten()
+                .find(|input| input.name == AUDIO_INPUT)
+                .map(|input| input.data.clone());
+            let Some(Data::VBYTES(files)) = audio else {
+                return output("text", Data::VFLOAT(Vec::new()), &[]);
+            };
+            let text = format!(
+                "{} bytes of {}",
+                files[0].len(),
+                parameter(&request, "filename").unwrap_or_default()
+            );
+            output(
+                "text",
+                Data::VSTRING(vec![text]),
+                &[
+                    ("language", InferParameter::String("english".to_string())),
+                    ("duration", InferParameter::Double(1.5)),
+                    (
+                        "segments",
+                        InferParameter::String(
+                            r#"[{"id": 0, "start": 0.0, "end": 1.5, "text": " Hello."}]"#
+                                .to_string(),
+                        ),
+                    ),
+                ],
+            )
+        }))
+    }
+
+    /// A transcription form of `fields`, with `file` as the audio file when given.
+    fn form(fields: &[(&str, &str)], file: Option<&[u8]>) -> Request<Body> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+                )
+                .bytes(),
+            );
+        }
+        if let Some(file) = file {
+            body.extend(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"hello.wav\"\r\nContent-Type: audio/wav\r\n\r\n"
+                )
+                .bytes(),
+            );
+            body.extend(file);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{BOUNDARY}--\r\n").bytes());
+        Request::post("/v1/audio/transcriptions")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_audio_files_are_transcribed_in_each_format() {
+        let (status, body) =
+            send_json(transcribing(), form(&[("model", "whisper")], Some(b"RIFF"))).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body, json!({ "text": "4 bytes of hello.wav" }));
+
+        let request = form(
+            &[("model", "whisper"), ("response_format", "text")],
+            Some(b"RIFF"),
+        );
+        let (status, _, body) = send(transcribing(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"4 bytes of hello.wav");
+
+        let request = form(
+            &[("model", "whisper"), ("response_format", "verbose_json")],
+            Some(b"RIFF"),
+        );
+        let (status, body) = send_json(transcribing(), request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["task"], "transcribe");
+        assert_eq!(body["language"], "english");
+        assert_eq!(body["duration"], 1.5);
+        assert_eq!(body["segments"][0]["text"], " Hello.");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transcription_forms_are_refused() {
+        for request in [
+            form(&[], Some(b"RIFF")),
+            form(&[("model", "whisper")], None),
+            form(&[("model", "whisper")], Some(b"")),
+            form(
+                &[("model", "whisper"), ("response_format", "srt")],
+                Some(b"RIFF"),
+            ),
+            form(
+                &[("model", "whisper"), ("temperature", "hot")],
+                Some(b"RIFF"),
+            ),
+            Request::post("/v1/audio/transcriptions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        ] {
+            let (status, body) = send_json(transcribing(), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::body::Bytes;
+use foundation::api::inference::{InferParameter, InferenceOutput};
+use foundation::api::tensor::{BYTES_DATATYPE, Data};
+use foundation::{
+    CastTensor, CastValues, DATATYPE_PARAMETER, InferenceRequest as DomainRequest, OutputDatatype,
+    Priority, SharedMemoryRef, SharedMemoryRegistry, TensorView, Timeline,
+};
+use serde_json::Value;
+
+use crate::binary::BINARY_DATA_SIZE_PARAMETER;
+use crate::data_model::{InferenceRequest, MetadataTensor, Parameters, TensorData};
+
+pub fn domain_parameter(value: Value) -> InferParameter {
+    match value {
+        Value::Bool(b) => InferParameter::Bool(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => InferParameter::Int64(i),
+            None => InferParameter::Double(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => InferParameter::String(s),
+        // Arrays, objects and null have no domain counterpart and are passed as JSON.
+        other => InferParameter::String(other.to_string()),
+    }
+}
+
+pub fn json_parameter(parameter: InferParameter) -> Value {
+    match parameter {
+        InferParameter::Bool(b) => b.into(),
+        InferParameter::Int64(i) => i.into(),
+        InferParameter::Double(d) => d.into(),
+        InferParameter::String(s) => s.into(),
+    }
+}
+
+/// Scheduling and accounting of a request, read from its headers.
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+    pub priority: Priority,
+    pub deadline: Option<Instant>,
+    pub tenant: Option<String>,
+    /// Input tensors the server attaches to the request, such as the images of a chat.
+    pub inputs: Option<Vec<InferenceOutput>>,
+    /// Raw contents of the numeric inputs sent as binary data, in input order.
+    pub binary: Vec<Bytes>,
+    /// Regions the inputs and outputs in shared memory are in.
+    pub shared_memory: Arc<SharedMemoryRegistry>,
+    /// Datatypes the requested outputs are cast to, by output name, as checked when the
+    /// request was prepared.
+    pub casts: HashMap<String, OutputDatatype>,
+}
+
+/// Input tensors of a payload as runtimes take them, followed by those the server attached.
+/// Inputs sent as binary data are views of the request body, in the order of `binary`, and
+/// inputs in shared memory views of their region; JSON numbers are FP64 values and JSON
+/// strings are strings. Inputs without data are left out.
+pub fn domain_inputs(
+    inputs: Vec<MetadataTensor>,
+    binary: Vec<Bytes>,
+    attached: Option<Vec<InferenceOutput>>,
+    shared_memory: &SharedMemoryRegistry,
+) -> anyhow::Result<Vec<InferenceOutput>> {
+    let mut binary = binary.into_iter();
+    let mut tensors = Vec::new();
+    for input in inputs {
+        let mut parameters: HashMap<String, InferParameter> = input
+            .parameters
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, domain_parameter(v)))
+            .collect();
+        let invalid = |e: anyhow::Error| anyhow::anyhow!("Input '{}': {}", input.name, e);
+        let location = SharedMemoryRef::take(&mut parameters).map_err(invalid)?;
+        let data = if parameters.remove(BINARY_DATA_SIZE_PARAMETER).is_some() {
+            let bytes = binary.next().ok_or_else(|| {
+                anyhow::anyhow!("Input '{}' has no binary data in the body", input.name)
+            })?;
+            Data::VRAW(TensorView::new(input.datatype.parse()?, bytes)?)
+        } else if let Some(location) = location {
+            let bytes = shared_memory.read(&location).map_err(invalid)?;
+            Data::from_raw(&input.datatype, bytes).map_err(invalid)?
+        } else {
+            match input.data {
+                Some(TensorData::String(values)) => Data::VSTRING(values),
+                Some(data) => Data::VFLOAT(json_values(&data)),
+                None => continue,
+            }
+        };
+        tensors.push(InferenceOutput {
+            name: input.name,
+            shape: input
+                .shape
+                .iter()
+                .map(|dim| (*dim).max(0) as usize)
+                .collect(),
+            datatype: data.datatype(),
+            parameters: Some(parameters),
+            data,
+        });
+    }
+    tensors.extend(attached.into_iter().flatten());
+    Ok(tensors)
+}
+
+/// Checks that the JSON data of each input fits its datatype and shape: strings for BYTES
+/// inputs, values of its kind and range for numeric ones (see `check_values`), and as many
+/// elements as the shape holds. Variable dimensions (-1) match any number of elements.
+/// Inputs without data are left to their binary data or shared memory region.
+pub fn check_tensor_data(inputs: &[MetadataTensor]) -> anyhow::Result<()> {
+    for input in inputs {
+        let Some(data) = &input.data else {
+            continue;
+        };
+        let elements = match data {
+            TensorData::Int32(values) => values.len(),
+            TensorData::Int64(values) => values.len(),
+            TensorData::Float32(values) => values.len(),
+            TensorData::Float64(values) => values.len(),
+            TensorData::Bool(values) => values.len(),
+            TensorData::UInt64(values) => values.len(),
+            TensorData::String(values) => values.len(),
+        };
+        let strings = matches!(data, TensorData::String(_));
+        if input.datatype == BYTES_DATATYPE {
+            if !strings && elements > 0 {
+                anyhow::bail!("Input '{}' of datatype BYTES must hold strings", input.name);
+            }
+        } else {
+            let datatype = input.datatype.parse::<OutputDatatype>().map_err(|_| {
+                anyhow::anyhow!(
+                    "Input '{}' has unknown datatype '{}'",
+                    input.name,
+                    input.datatype
+                )
+            })?;
+            if strings {
+                anyhow::bail!(
+                    "Input '{}' of datatype {} must hold numbers",
+                    input.name,
+                    input.datatype
+                );
+            }
+            if elements > 0 {
+                check_values(&input.name, data, datatype)?;
+            }
+        }
+        if let Some(dim) = input.shape.iter().find(|dim| **dim < -1) {
+            anyhow::bail!("Input '{}' has invalid dimension {}", input.name, dim);
+        }
+        if input.shape.contains(&-1) {
+            continue;
+        }
+        let expected = input
+            .shape
+            .iter()
+            .try_fold(1i64, |product, dim| product.checked_mul(*dim))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Input '{}' of shape {:?} has more elements than can be counted",
+                    input.name,
+                    input.shape
+                )
+            })?;
+        if expected != elements as i64 {
+            anyhow::bail!(
+                "Input '{}' of shape {:?} has {} elements, its data holds {}",
+                input.name,
+                input.shape,
+                expected,
+                elements
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks that JSON values are of the kind and in the range of `datatype`: booleans for
+/// BOOL, integers the datatype holds for integer datatypes, and numbers below the largest
+/// finite value of floating point ones.
+fn check_values(name: &str, data: &TensorData, datatype: OutputDatatype) -> anyhow::Result<()> {
+    let integers: Option<Vec<i128>> = match data {
+        TensorData::Int32(values) => Some(values.iter().map(|v| *v as i128).collect()),
+        TensorData::Int64(values) => Some(values.iter().map(|v| *v as i128).collect()),
+        TensorData::UInt64(values) => Some(values.iter().map(|v| *v as i128).collect()),
+        _ => None,
+    };
+    let (min, max) = match datatype {
+        OutputDatatype::Bool => {
+            if !matches!(data, TensorData::Bool(_)) {
+                anyhow::bail!("Input '{}' of datatype BOOL must hold booleans", name);
+            }
+            return Ok(());
+        }
+        OutputDatatype::Fp64
+        | OutputDatatype::Fp32
+        | OutputDatatype::Fp16
+        | OutputDatatype::Bf16 => {
+            if matches!(data, TensorData::Bool(_)) {
+                anyhow::bail!(
+                    "Input '{}' of datatype {} must hold numbers",
+                    name,
+                    datatype
+                );
+            }
+            let largest = match datatype {
+                OutputDatatype::Fp64 => f64::MAX,
+                // The largest finite half precision value.
+                OutputDatatype::Fp16 => 65504.0,
+                _ => f32::MAX as f64,
+            };
+            if let Some(value) = json_values(data).into_iter().find(|v| v.abs() > largest) {
+                anyhow::bail!(
+                    "Input '{}' holds {}, out of the range of {}",
+                    name,
+                    value,
+                    datatype
+                );
+            }
+            return Ok(());
+        }
+        OutputDatatype::Int64 => (i64::MIN as i128, i64::MAX as i128),
+        OutputDatatype::Int32 => (i32::MIN as i128, i32::MAX as i128),
+        OutputDatatype::Int16 => (i16::MIN as i128, i16::MAX as i128),
+        OutputDatatype::Int8 => (i8::MIN as i128, i8::MAX as i128),
+        OutputDatatype::Uint64 => (0, u64::MAX as i128),
+        OutputDatatype::Uint32 => (0, u32::MAX as i128),
+        OutputDatatype::Uint16 => (0, u16::MAX as i128),
+        OutputDatatype::Uint8 => (0, u8::MAX as i128),
+    };
+    let Some(integers) = integers else {
+        anyhow::bail!(
+            "Input '{}' of datatype {} must hold integers",
+            name,
+            datatype
+        );
+    };
+    if let Some(value) = integers.iter().find(|v| !(min..=max).contains(*v)) {
+        anyhow::bail!(
+            "Input '{}' holds {}, out of the range of {}",
+            name,
+            value,
+            datatype
+        );
+    }
+    Ok(())
+}
+
+/// Numbers of JSON data as FP64 values.
+fn json_values(data: &TensorData) -> Vec<f64> {
+    match data {
+        TensorData::Int32(values) => values.iter().map(|v| f64::from(*v)).collect(),
+        TensorData::Int64(values) => values.iter().map(|v| *v as f64).collect(),
+        TensorData::Float32(values) => values.iter().map(|v| f64::from(*v)).collect(),
+        TensorData::Float64(values) => values.clone(),
+        TensorData::Bool(values) => values.iter().map(|v| *v as u8 as f64).collect(),
+        TensorData::UInt64(values) => values.iter().map(|v| *v as f64).collect(),
+        TensorData::String(_) => Vec::new(),
+    }
+}
+
+/// Domain request for a prepared REST payload addressed to `model_name`.
+pub fn domain_request(
+    model_name: String,
+    model_version: Option<String>,
+    payload: InferenceRequest,
+    context: RequestContext,
+    timeline: Option<Arc<Timeline>>,
+) -> anyhow::Result<DomainRequest> {
+    let inputs = domain_inputs(
+        payload.inputs,
+        context.binary,
+        context.inputs,
+        &context.shared_memory,
+    )?;
+    let parameters: HashMap<String, InferParameter> = payload
+        .parameters
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(k, v)| (k, domain_parameter(v)))
+        .collect();
+
+    Ok(DomainRequest {
+        model_name,
+        model_version,
+        id: payload.id.unwrap_or_default(),
+        parameters: Some(parameters),
+        outputs: (!inputs.is_empty()).then_some(inputs),
+        timeline,
+        priority: context.priority,
+        deadline: context.deadline,
+        tenant: context.tenant,
+        sampling: Default::default(),
+    })
+}
+
+/// Datatypes the requested outputs of `payload` ask to be cast to, by output name.
+pub fn output_casts(payload: &InferenceRequest) -> anyhow::Result<HashMap<String, OutputDatatype>> {
+    let mut casts = HashMap::new();
+    for output in payload.outputs.iter().flatten() {
+        let Some(datatype) = output
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.get(DATATYPE_PARAMETER))
+        else {
+            continue;
+        };
+        let datatype = datatype.as_str().ok_or_else(|| {
+            anyhow::anyhow!("The datatype of output '{}' must be a string", output.name)
+        })?;
+        casts.insert(output.name.clone(), datatype.parse()?);
+    }
+    Ok(casts)
+}
+
+/// REST tensor of a model output, cast to `datatype` when the request asked for one. Views
+/// keep their own datatype unless cast, other numbers are FP64.
+/// String and bytes outputs are BYTES tensors of JSON strings and are never cast; bytes
+/// that are not UTF-8 have no JSON string and are replaced with U+FFFD.
+pub fn output_tensor(output: InferenceOutput, datatype: Option<OutputDatatype>) -> MetadataTensor {
+    let (datatype, data) = match output.data {
+        Data::VFLOAT(values) => {
+            let cast = CastTensor::new(&values, datatype.unwrap_or(OutputDatatype::Fp64));
+            (cast.datatype.to_string(), tensor_data(cast))
+        }
+        Data::VRAW(view) => {
+            let cast = CastTensor::new(&view.to_f64(), datatype.unwrap_or(view.datatype()));
+            (cast.datatype.to_string(), tensor_data(cast))
+        }
+        Data::VSTRING(values) => (BYTES_DATATYPE.to_string(), TensorData::String(values)),
+        Data::VBYTES(values) => (
+            BYTES_DATATYPE.to_string(),
+            TensorData::String(
+                values
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value).into_owned())
+                    .collect(),
+            ),
+        ),
+    };
+
+    MetadataTensor {
+        name: output.name,
+        shape: output.shape.into_iter().map(|dim| dim as i64).collect(),
+        datatype,
+        parameters: output.parameters.map(|parameters| {
+            parameters
+                .into_iter()
+                .map(|(k, v)| (k, json_parameter(v)))
+                .collect::<Parameters>()
+        }),
+        data: Some(data),
+    }
+}
+
+/// JSON data of cast values.
+pub fn tensor_data(cast: CastTensor) -> TensorData {
+    let widened = cast.half_as_f32();
+    match cast.values {
+        CastValues::Fp64(values) => TensorData::Float64(values),
+        CastValues::Fp32(values) => TensorData::Float32(values),
+        // JSON has no half precision numbers: the rounded values are sent as FP32 numbers.
+        CastValues::Half(_) => TensorData::Float32(widened.unwrap_or_default()),
+        CastValues::Int64(values) => TensorData::Int64(values),
+        CastValues::Int32(values) => TensorData::Int32(values),
+        CastValues::Uint64(values) => TensorData::UInt64(values),
+        CastValues::Uint32(values) => {
+            TensorData::Int64(values.into_iter().map(i64::from).collect())
+        }
+        CastValues::Bool(values) => TensorData::Bool(values),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The refusal of an input of `datatype` and `shape` holding `data`, if any.
+    fn refusal(datatype: &str, shape: Value, data: Value) -> Option<String> {
+        let input: MetadataTensor = serde_json::from_value(json!({
+            "name": "x", "datatype": datatype, "shape": shape, "data": data,
+        }))
+        .unwrap();
+        check_tensor_data(&[input]).err().map(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_data_of_the_datatype_and_shape_is_accepted() {
+        assert_eq!(refusal("FP32", json!([2]), json!([1, 2.5])), None);
+        assert_eq!(refusal("FP64", json!([1]), json!([1e300])), None);
+        assert_eq!(
+            refusal("INT64", json!([2]), json!([i64::MIN, i64::MAX])),
+            None
+        );
+        assert_eq!(refusal("UINT64", json!([1]), json!([u64::MAX])), None);
+        assert_eq!(refusal("UINT8", json!([2]), json!([0, 255])), None);
+        assert_eq!(refusal("BOOL", json!([2]), json!([true, false])), None);
+        assert_eq!(refusal("BYTES", json!([-1]), json!(["a", "b"])), None);
+        assert_eq!(refusal("INT8", json!([0]), json!([])), None);
+    }
+
+    #[test]
+    fn test_a_shape_too_large_to_count_is_refused() {
+        let refused = refusal("FP32", json!([i64::MAX, 2]), json!([1.0])).unwrap();
+        assert!(refused.contains("more elements than can be counted"));
+        let refused = refusal("FP32", json!([3, 2]), json!([1.0])).unwrap();
+        assert!(refused.contains("has 6 elements, its data holds 1"));
+        let refused = refusal("FP32", json!([-2]), json!([1.0])).unwrap();
+        assert!(refused.contains("invalid dimension -2"));
+    }
+
+    #[test]
+    fn test_data_of_another_kind_is_refused() {
+        let refused = refusal("BOOL", json!([2]), json!([0.5, 1.0])).unwrap();
+        assert!(refused.contains("must hold booleans"));
+        let refused = refusal("BOOL", json!([2]), json!([0, 1])).unwrap();
+        assert!(refused.contains("must hold booleans"));
+        let refused = refusal("INT32", json!([2]), json!([1.5, 2])).unwrap();
+        assert!(refused.contains("must hold integers"));
+        let refused = refusal("UINT8", json!([1]), json!([true])).unwrap();
+        assert!(refused.contains("must hold integers"));
+        let refused = refusal("FP32", json!([1]), json!([true])).unwrap();
+        assert!(refused.contains("must hold numbers"));
+        let refused = refusal("FP32", json!([1]), json!(["1.0"])).unwrap();
+        assert!(refused.contains("must hold numbers"));
+        let refused = refusal("BYTES", json!([1]), json!([1])).unwrap();
+        assert!(refused.contains("must hold strings"));
+        let refused = refusal("FP8", json!([1]), json!([1])).unwrap();
+        assert!(refused.contains("unknown datatype 'FP8'"));
+    }
+
+    #[test]
+    fn test_values_out_of_the_datatype_range_are_refused() {
+        let refused = refusal("UINT8", json!([1]), json!([-1])).unwrap();
+        assert!(refused.contains("holds -1, out of the range of UINT8"));
+        let refused = refusal("UINT8", json!([1]), json!([256])).unwrap();
+        assert!(refused.contains("holds 256, out of the range of UINT8"));
+        let refused = refusal("INT8", json!([1]), json!([128])).unwrap();
+        assert!(refused.contains("out of the range of INT8"));
+        let refused = refusal("INT64", json!([1]), json!([u64::MAX])).unwrap();
+        assert!(refused.contains("out of the range of INT64"));
+        let refused = refusal("UINT64", json!([1]), json!([-1])).unwrap();
+        assert!(refused.contains("out of the range of UINT64"));
+        let refused = refusal("FP16", json!([1]), json!([70000])).unwrap();
+        assert!(refused.contains("out of the range of FP16"));
+        let refused = refusal("FP32", json!([1]), json!([1e300])).unwrap();
+        assert!(refused.contains("out of the range of FP32"));
+    }
+}
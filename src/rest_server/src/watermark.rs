@@ -0,0 +1,46 @@
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use foundation::{Detection, Role, Target};
+use serde::Deserialize;
+
+use crate::auth::{authorization, refused as unauthorized};
+use crate::error::status_error;
+use crate::state::AppState;
+
+#[derive(Deserialize)]
+struct DetectRequest {
+    text: String,
+    /// Scheme to check for, the built-in one by default.
+    #[serde(default)]
+    scheme: Option<String>,
+}
+
+/// Whether a piece of text carries a watermark of this server.
+async fn detect_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<DetectRequest>,
+) -> Result<Json<Detection>, Response> {
+    if let Some(authenticator) = &state.authenticator {
+        authenticator
+            .authorize(authorization(&headers), Role::ReadOnly, Target::Server)
+            .map_err(|error| unauthorized(error).into_response())?;
+    }
+    state
+        .model_manager
+        .watermarking()
+        .detect(request.scheme.as_deref(), &request.text)
+        .map(Json)
+        .map_err(|e| status_error(StatusCode::BAD_REQUEST, e).into_response())
+}
+
+pub fn new_watermark_router(state: AppState) -> Router {
+    Router::new()
+        .route("/detect", post(detect_handler))
+        .with_state(state)
+}
@@ -0,0 +1,285 @@
+/* Bidirectional inference over WebSocket: `GET /v1/stream`.
+
+Browsers cannot call gRPC, so the connection upgraded on `/v1/stream` serves
+them as `ModelInferAsync` serves gRPC clients. Each text frame the client
+sends is an inference request: the JSON body of a V2 request with the model it
+is for,
+
+```json
+{"model_name": "resnet", "model_version": "1", "id": "42", "inputs": [...]}
+```
+
+and is answered with a text frame holding the V2 response, or the error body
+of the REST API with the `id` of the request and the `httpStatus` it would
+have been refused with. Frames are run one at a time and
+answered in order. A refused or failed frame does not end the stream. When the
+server has an analytics sink, the responses sent are teed to it, numbered
+from 0 on each connection.
+
+The headers of the upgrade request (API key, priority, deadline, schema
+version, selectors) apply to every frame, as the metadata of a gRPC stream.
+Browsers cannot set headers on a WebSocket, so they may pass their key or
+token as the `access_token` query parameter instead.
+*/
+
+use std::collections::HashMap;
+
+use axum::{
+    Router,
+    extract::{
+        Json, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::get,
+};
+use serde_json::Value;
+
+use crate::data_model::{ErrorInferenceResponse, InferenceResponse};
+use crate::error::status_error;
+use crate::model::{PreparedRequest, infer, prepare_request};
+use crate::schema::downgrade_response;
+use crate::state::AppState;
+use crate::stream::analytics_record;
+
+type InferenceError = (StatusCode, Json<ErrorInferenceResponse>);
+
+fn bad_request(message: impl ToString) -> InferenceError {
+    status_error(StatusCode::BAD_REQUEST, message)
+}
+
+/// Runs the inference request of a frame.
+async fn infer_frame(
+    state: &AppState,
+    headers: &HeaderMap,
+    mut body: Value,
+) -> Result<InferenceResponse, InferenceError> {
+    let Some(request) = body.as_object_mut() else {
+        return Err(bad_request("A frame must be a JSON object"));
+    };
+    let mut params = HashMap::new();
+    for name in ["model_name", "model_version"] {
+        match request.remove(name) {
+            Some(Value::String(value)) => {
+                params.insert(name.to_string(), value);
+            }
+            Some(_) => return Err(bad_request(format!("{} must be a string", name))),
+            None => {}
+        }
+    }
+    if !params.contains_key("model_name") {
+        return Err(bad_request("A frame must name its model_name"));
+    }
+    let PreparedRequest {
+        model_name,
+        model_version,
+        plan,
+        payload,
+        context,
+        ..
+    } = prepare_request(state, &params, headers, body, false, None)?;
+    let response = infer(
+        &state.model_manager,
+        "rest.stream",
+        model_name,
+        model_version,
+        payload,
+        context,
+        None,
+    )
+    .await?;
+    downgrade_response(&plan, response)
+}
+
+/// Reply to a text frame, and the response it holds.
+async fn reply(
+    state: &AppState,
+    headers: &HeaderMap,
+    text: &str,
+) -> (Value, Option<InferenceResponse>) {
+    let body: Value = match serde_json::from_str(text) {
+        Ok(body) => body,
+        Err(e) => return (error_frame(None, bad_request(e)), None),
+    };
+    let id = body.get("id").cloned();
+    let _load = state.overload.begin();
+    match infer_frame(state, headers, body).await {
+        Ok(response) => (
+            serde_json::to_value(&response).unwrap_or_default(),
+            Some(response),
+        ),
+        Err(error) => (error_frame(id, error), None),
+    }
+}
+
+fn error_frame(id: Option<Value>, (status, Json(error)): InferenceError) -> Value {
+    let mut frame = serde_json::to_value(error).unwrap_or_default();
+    frame["id"] = id.unwrap_or_default();
+    frame["httpStatus"] = status.as_u16().into();
+    frame
+}
+
+async fn serve(state: AppState, headers: HeaderMap, mut socket: WebSocket) {
+    let mut sequence = 0;
+    while let Some(Ok(message)) = socket.recv().await {
+        let (reply, response) = match message {
+            Message::Text(text) => reply(&state, &headers, text.as_str()).await,
+            Message::Binary(_) => (
+                error_frame(None, bad_request("Frames must be JSON text")),
+                None,
+            ),
+            Message::Close(_) => break,
+            // Pings are answered by the WebSocket itself.
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+        if socket
+            .send(Message::Text(reply.to_string().into()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+        // Only what the client received is teed.
+        if let (Some(analytics), Some(response)) = (&state.analytics, response) {
+            analytics.tee(analytics_record(&response, sequence));
+            sequence += 1;
+        }
+    }
+}
+
+async fn stream_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| serve(state, headers, socket))
+}
+
+pub fn new_websocket_router(state: AppState) -> Router {
+    Router::new()
+        .route("/v1/stream", get(stream_handler))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{output, send, state_with};
+    use axum::{body::Body, http::Request};
+    use foundation::api::tensor::Data;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite;
+
+    fn doubling() -> AppState {
+        state_with("m", |request| {
+            let inputs = request.outputs.unwrap_or_default();
+            let Data::VFLOAT(values) = &inputs[0].data else {
+                panic!("the frame sent floats");
+            };
+            output(
+                "y",
+                Data::VFLOAT(values.iter().map(|v| v * 2.0).collect()),
+                &[],
+            )
+        })
+    }
+
+    fn frame(extra: Value) -> String {
+        let mut frame = json!({
+            "model_name": "m",
+            "id": "7",
+            "inputs": [{ "name": "x", "shape": [2], "datatype": "FP64", "data": [1.0, 2.5] }],
+        });
+        for (name, value) in extra.as_object().unwrap() {
+            frame[name] = value.clone();
+        }
+        frame.to_string()
+    }
+
+    async fn status_of(state: &AppState, text: &str) -> Value {
+        let (answer, response) = reply(state, &HeaderMap::new(), text).await;
+        assert!(response.is_none());
+        answer["httpStatus"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_frames_are_answered_with_their_response() {
+        let state = doubling();
+        let (answer, response) = reply(&state, &HeaderMap::new(), &frame(json!({}))).await;
+        assert!(response.is_some());
+        assert_eq!(answer["model_name"], "m");
+        assert_eq!(answer["id"], "7");
+        assert_eq!(answer["outputs"][0]["name"], "y");
+        assert_eq!(answer["outputs"][0]["data"], json!([2.0, 5.0]));
+
+        let (answer, _) = reply(
+            &state,
+            &HeaderMap::new(),
+            &frame(json!({"model_version": "1"})),
+        )
+        .await;
+        assert_eq!(answer["model_version"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_refused_frames_keep_their_id_and_status() {
+        let state = doubling();
+        assert_eq!(status_of(&state, "{not json").await, 400);
+        assert_eq!(status_of(&state, "[1, 2]").await, 400);
+        assert_eq!(status_of(&state, r#"{"id": "7", "inputs": []}"#).await, 400);
+        assert_eq!(
+            status_of(&state, &frame(json!({"model_version": 1}))).await,
+            400
+        );
+        assert_eq!(
+            status_of(&state, &frame(json!({"model_name": "absent"}))).await,
+            404
+        );
+
+        let (answer, _) = reply(&state, &HeaderMap::new(), r#"{"id": "9"}"#).await;
+        assert_eq!(answer["id"], "9");
+        assert!(answer["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_upgrade_are_refused() {
+        let request = Request::get("/v1/stream").body(Body::empty()).unwrap();
+        let (status, _, _) = send(new_websocket_router(doubling()), request).await;
+        assert!(status.is_client_error());
+    }
+
+    #[tokio::test]
+    async fn test_streams_answer_frames_in_order_and_outlive_refusals() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, new_websocket_router(doubling())).into_future());
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{address}/v1/stream"))
+            .await
+            .unwrap();
+
+        for text in [
+            frame(json!({"id": "1"})),
+            "{".to_string(),
+            frame(json!({"id": "3"})),
+        ] {
+            socket.send(tungstenite::Message::text(text)).await.unwrap();
+        }
+        socket
+            .send(tungstenite::Message::binary(vec![1, 2]))
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        while replies.len() < 4 {
+            if let tungstenite::Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                replies.push(serde_json::from_str::<Value>(&text).unwrap());
+            }
+        }
+        assert_eq!(replies[0]["id"], "1");
+        assert_eq!(replies[0]["outputs"][0]["data"], json!([2.0, 5.0]));
+        assert_eq!(replies[1]["httpStatus"], 400);
+        assert_eq!(replies[2]["id"], "3");
+        assert_eq!(replies[3]["httpStatus"], 400);
+    }
+}